@@ -0,0 +1,211 @@
+//! Streaming base64 encoding and decoding (RFC 4648, standard alphabet).
+//!
+//! Both directions work on arbitrary input chunks so that large files can be
+//! converted without holding them in memory.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes input incrementally, wrapping output lines at a fixed width.
+pub struct Encoder {
+    /// Bytes left over from the previous chunk (fewer than 3).
+    pending: Vec<u8>,
+    /// Line width, or 0 for no wrapping.
+    wrap: usize,
+    column: usize,
+}
+
+impl Encoder {
+    pub fn new(wrap: usize) -> Self {
+        Self {
+            pending: Vec::new(),
+            wrap,
+            column: 0,
+        }
+    }
+
+    /// Encodes `input`, appending complete output characters to `out`.
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) {
+        self.pending.extend_from_slice(input);
+        let whole = self.pending.len() / 3 * 3;
+        let pending = std::mem::take(&mut self.pending);
+        for group in pending[..whole].chunks(3) {
+            self.emit_group(group, out);
+        }
+        self.pending = pending[whole..].to_vec();
+    }
+
+    /// Encodes any remaining bytes with padding and ends the last line.
+    pub fn finish(mut self, out: &mut Vec<u8>) {
+        let rest = std::mem::take(&mut self.pending);
+        if !rest.is_empty() {
+            self.emit_group(&rest, out);
+        }
+        if self.wrap > 0 && self.column > 0 {
+            out.push(b'\n');
+        }
+    }
+
+    fn emit_group(&mut self, group: &[u8], out: &mut Vec<u8>) {
+        let b = [
+            group[0],
+            group.get(1).copied().unwrap_or(0),
+            group.get(2).copied().unwrap_or(0),
+        ];
+        let indices = [
+            b[0] >> 2,
+            (b[0] & 0x03) << 4 | b[1] >> 4,
+            (b[1] & 0x0F) << 2 | b[2] >> 6,
+            b[2] & 0x3F,
+        ];
+        for (i, &index) in indices.iter().enumerate() {
+            let c = if i <= group.len() {
+                ALPHABET[index as usize]
+            } else {
+                b'='
+            };
+            self.push(c, out);
+        }
+    }
+
+    fn push(&mut self, c: u8, out: &mut Vec<u8>) {
+        out.push(c);
+        self.column += 1;
+        if self.wrap > 0 && self.column == self.wrap {
+            out.push(b'\n');
+            self.column = 0;
+        }
+    }
+}
+
+/// Decodes input incrementally. Line breaks and other whitespace are
+/// skipped; other characters outside the alphabet are errors unless
+/// garbage is ignored.
+pub struct Decoder {
+    quad: [u8; 4],
+    filled: usize,
+    ignore_garbage: bool,
+    /// Set once padding has been seen; only whitespace may follow.
+    ended: bool,
+}
+
+impl Decoder {
+    pub fn new(ignore_garbage: bool) -> Self {
+        Self {
+            quad: [0; 4],
+            filled: 0,
+            ignore_garbage,
+            ended: false,
+        }
+    }
+
+    /// Decodes `input`, appending the decoded bytes to `out`.
+    pub fn update(&mut self, input: &[u8], out: &mut Vec<u8>) -> Result<(), String> {
+        for &c in input {
+            if c.is_ascii_whitespace() {
+                continue;
+            }
+            if c == b'=' {
+                // Padding completes a group of 2 or 3 characters
+                if self.filled < 2 && !self.ended {
+                    return Err("invalid input".to_string());
+                }
+                if !self.ended {
+                    self.flush_partial(out);
+                    self.ended = true;
+                }
+                continue;
+            }
+            let Some(value) = ALPHABET.iter().position(|&a| a == c) else {
+                if self.ignore_garbage {
+                    continue;
+                }
+                return Err("invalid input".to_string());
+            };
+            if self.ended {
+                return Err("invalid input".to_string());
+            }
+            self.quad[self.filled] = value as u8;
+            self.filled += 1;
+            if self.filled == 4 {
+                let q = self.quad;
+                out.extend_from_slice(&[
+                    q[0] << 2 | q[1] >> 4,
+                    q[1] << 4 | q[2] >> 2,
+                    q[2] << 6 | q[3],
+                ]);
+                self.filled = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the input ended on a group boundary, decoding an
+    /// unpadded final group if needed.
+    pub fn finish(mut self, out: &mut Vec<u8>) -> Result<(), String> {
+        match self.filled {
+            0 => Ok(()),
+            1 => Err("invalid input".to_string()),
+            _ => {
+                self.flush_partial(out);
+                Ok(())
+            }
+        }
+    }
+
+    /// Decodes a final group of 2 or 3 characters.
+    fn flush_partial(&mut self, out: &mut Vec<u8>) {
+        let q = self.quad;
+        if self.filled >= 2 {
+            out.push(q[0] << 2 | q[1] >> 4);
+        }
+        if self.filled == 3 {
+            out.push(q[1] << 4 | q[2] >> 2);
+        }
+        self.filled = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(data: &[u8], wrap: usize, chunk: usize) -> String {
+        let mut encoder = Encoder::new(wrap);
+        let mut out = Vec::new();
+        for piece in data.chunks(chunk) {
+            encoder.update(piece, &mut out);
+        }
+        encoder.finish(&mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    fn decode(text: &str, ignore_garbage: bool) -> Result<Vec<u8>, String> {
+        let mut decoder = Decoder::new(ignore_garbage);
+        let mut out = Vec::new();
+        for piece in text.as_bytes().chunks(3) {
+            decoder.update(piece, &mut out)?;
+        }
+        decoder.finish(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(encode(b"", 76, 1), "");
+        assert_eq!(encode(b"f", 0, 1), "Zg==");
+        assert_eq!(encode(b"fo", 0, 1), "Zm8=");
+        assert_eq!(encode(b"foobar", 0, 4), "Zm9vYmFy");
+        assert_eq!(encode(b"foobar", 4, 5), "Zm9v\nYmFy\n");
+        assert_eq!(encode(b"hello", 76, 2), "aGVsbG8=\n");
+
+        assert_eq!(decode("aGVs\nbG8=\n", false).unwrap(), b"hello");
+        assert_eq!(decode("Zm8", false).unwrap(), b"fo");
+        assert_eq!(decode("Zm9v!YmFy", true).unwrap(), b"foobar");
+        assert!(decode("Zm9v!YmFy", false).is_err());
+        assert!(decode("Z", false).is_err());
+        assert!(decode("Zg==Zg==", false).is_err());
+
+        let data: Vec<u8> = (0..=255).collect();
+        assert_eq!(decode(&encode(&data, 76, 7), false).unwrap(), data);
+    }
+}
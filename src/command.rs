@@ -1,61 +1,466 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::env;
 use std::fs::{self, File};
 use std::io::{self, BufRead, BufReader, Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, Local};
 
+use crate::command_call::{CommandCall, tokenize};
+use crate::jobs::JobTable;
+use crate::ls_colors::FileKind;
+use crate::manual::Manual;
+use crate::random::ShellRng;
+use crate::spec::{Flag, ParsedArgs, Spec};
+
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 
 /// The result of a command execution, containing output and error streams.
+///
+/// Output is bytes, so binary data passes through commands unchanged; error
+/// messages are text.
 pub struct CommandResult {
-    pub stdout: String,
+    pub stdout: Vec<u8>,
     pub stderr: String,
+    /// The exit status: 0 for success, anything else for failure. See
+    /// [`CommandError::status`] for what the values mean.
+    pub status: i32,
     /// If true, the shell should terminate.
     pub should_exit: bool,
 }
 
+impl Default for CommandResult {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CommandResult {
     pub fn new() -> Self {
         Self {
-            stdout: String::new(),
+            stdout: Vec::new(),
             stderr: String::new(),
+            status: 0,
             should_exit: false,
         }
     }
 
-    pub fn with_stdout(stdout: String) -> Self {
+    pub fn with_stdout(stdout: impl Into<Vec<u8>>) -> Self {
         Self {
-            stdout,
-            stderr: String::new(),
-            should_exit: false,
+            stdout: stdout.into(),
+            ..Self::new()
         }
     }
 
+    /// A failed result with the error message `stderr`.
     pub fn with_stderr(stderr: String) -> Self {
         Self {
-            stdout: String::new(),
             stderr,
-            should_exit: false,
+            status: 1,
+            ..Self::new()
         }
     }
 
-    pub fn exit() -> Self {
+    /// A successful result with a message on stderr that is not an error,
+    /// such as a transfer report.
+    pub fn with_notice(stderr: String) -> Self {
         Self {
-            stdout: String::new(),
-            stderr: String::new(),
+            stderr,
+            ..Self::new()
+        }
+    }
+
+    /// A failed result reporting `error`.
+    pub fn error(error: CommandError) -> Self {
+        let mut result = Self::new();
+        result.push_error(error);
+        result
+    }
+
+    /// Exits the shell with `status`.
+    pub fn exit(status: i32) -> Self {
+        Self {
+            status,
             should_exit: true,
+            ..Self::new()
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+
+    /// The output as text, with any invalid UTF-8 replaced.
+    pub fn stdout_text(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// Appends an error message to stderr, separating it from earlier messages.
+    /// The command fails with status 1, unless an earlier error set another.
+    pub fn append_stderr(&mut self, msg: &str) {
+        self.append_notice(msg);
+        if self.status == 0 {
+            self.status = 1;
+        }
+    }
+
+    /// Reports `error`. The first error reported decides the status.
+    pub fn push_error(&mut self, error: CommandError) {
+        self.append_notice(&error.to_string());
+        if self.status == 0 {
+            self.status = error.status();
+        }
+    }
+
+    /// Appends a message to stderr that is not an error, such as a progress
+    /// report, leaving the status as it is.
+    pub fn append_notice(&mut self, msg: &str) {
+        if !self.stderr.is_empty() {
+            self.stderr.push('\n');
+        }
+        self.stderr.push_str(msg);
+    }
+}
+
+/// Why a command failed. Each message is complete, command name included,
+/// as in `cat: notes.txt: No such file or directory`.
+#[derive(Debug)]
+pub enum CommandError {
+    /// There is no command by the name given.
+    CommandNotFound(String),
+    /// The command was given options or operands it cannot use.
+    Usage(String),
+    /// A file the command was given does not exist.
+    NotFound(String),
+    /// The command was not allowed to read, write, or run something.
+    PermissionDenied(String),
+    /// Some other input or output error.
+    Io(String),
+    /// Any other failure.
+    Failed(String),
+}
+
+impl CommandError {
+    /// Describes the I/O error `e` after `context`, as in `cat: notes.txt`,
+    /// sorting it by its kind.
+    pub fn io(context: &str, e: io::Error) -> Self {
+        let message = format!("{}: {}", context, e);
+        match e.kind() {
+            io::ErrorKind::NotFound => Self::NotFound(message),
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied(message),
+            _ => Self::Io(message),
+        }
+    }
+
+    /// The exit status of a command failing this way: 127 for an unknown
+    /// command, 2 for a usage error, and 1 for anything else, as in Bash.
+    /// `timeout` adds 124 for a command that ran out of time, and any
+    /// command killed by signal N ends with 128 + N.
+    pub fn status(&self) -> i32 {
+        match self {
+            Self::CommandNotFound(_) => 127,
+            Self::Usage(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::CommandNotFound(msg)
+            | Self::Usage(msg)
+            | Self::NotFound(msg)
+            | Self::PermissionDenied(msg)
+            | Self::Io(msg)
+            | Self::Failed(msg) => f.write_str(msg),
+        }
+    }
+}
+
+/// The streams a command writes its output to as it produces it.
+///
+/// The shell hands its own stdout and stderr to commands run at the prompt,
+/// while commands run by other commands, such as `xargs` and `watch`, write
+/// into buffers that end up in the caller's [`CommandResult`].
+pub struct Output<'a> {
+    pub stdout: &'a mut dyn Write,
+    pub stderr: &'a mut dyn Write,
+}
+
+impl<'a> Output<'a> {
+    pub fn new(stdout: &'a mut dyn Write, stderr: &'a mut dyn Write) -> Self {
+        Self { stdout, stderr }
+    }
+
+    /// Writes an error message to stderr as a line of its own.
+    pub fn error(&mut self, msg: &str) -> io::Result<()> {
+        writeln!(self.stderr, "{}", msg)
+    }
+
+    /// Writes what a command left in its result, once it has finished.
+    pub fn write_result(&mut self, result: &CommandResult) -> io::Result<()> {
+        if !result.stdout.is_empty() {
+            self.stdout.write_all(&result.stdout)?;
+            self.stdout.flush()?;
+        }
+        if !result.stderr.is_empty() {
+            self.error(&result.stderr)?;
+            self.stderr.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// The shell's variables.
+///
+/// They start out as a copy of the environment the shell was started with,
+/// and every variable is exported. Changes stay here rather than in the
+/// process environment, which cannot safely be modified while commands run
+/// on other threads; programs the shell starts get them through
+/// [`Variables::apply`].
+#[derive(Debug, Clone)]
+pub struct Variables {
+    values: BTreeMap<String, String>,
+}
+
+impl Variables {
+    /// Copies the process environment, skipping names or values that are
+    /// not valid UTF-8.
+    pub fn from_env() -> Self {
+        let values = env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .collect();
+        Self { values }
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) {
+        self.values.insert(name.to_string(), value.to_string());
+    }
+
+    pub fn remove(&mut self, name: &str) {
+        self.values.remove(name);
+    }
+
+    /// Gives `command` exactly these variables as its environment.
+    pub fn apply<'a>(
+        &self,
+        command: &'a mut std::process::Command,
+    ) -> &'a mut std::process::Command {
+        command.env_clear().envs(&self.values)
+    }
+}
+
+impl Default for Variables {
+    fn default() -> Self {
+        Self::from_env()
+    }
+}
+
+/// The state of a shell session that outlives any one command.
+#[derive(Debug, Clone, Default)]
+pub struct ShellState {
+    pub vars: Variables,
+    /// Aliases defined with `alias`, by name.
+    pub aliases: BTreeMap<String, String>,
+    /// The exit status of the last command run at the prompt, for `$?`.
+    pub last_status: i32,
+    /// Jobs waiting to run, scheduled with `schedule`.
+    pub jobs: JobTable,
+    /// The database of directories `cd` and `j` record visits in, which `j`
+    /// jumps to. With `None`, the default, nothing is recorded.
+    pub dirs_file: Option<PathBuf>,
+    /// The generator of `random` and `$RANDOM`.
+    pub rng: ShellRng,
+}
+
+/// What a command can reach of the shell running it: the other commands,
+/// the session's state, and its standard streams.
+pub struct ShellContext<'a> {
+    pub commands: &'a CommandList,
+    pub state: &'a mut ShellState,
+    pub stdin: &'a mut dyn Read,
+    /// Where the command writes output it does not return in its result.
+    pub out: Output<'a>,
+}
+
+impl<'a> ShellContext<'a> {
+    pub fn new(
+        commands: &'a CommandList,
+        state: &'a mut ShellState,
+        stdin: &'a mut dyn Read,
+        out: Output<'a>,
+    ) -> Self {
+        Self {
+            commands,
+            state,
+            stdin,
+            out,
+        }
+    }
+
+    /// Runs `call` with its output collected in the result. Used by
+    /// commands that run other commands and use their output.
+    pub fn capture(&mut self, call: CommandCall) -> CommandResult {
+        let commands = self.commands;
+        capture(commands, self.state, self.stdin, |ctx| {
+            commands.run_call(ctx, call)
+        })
+    }
+}
+
+/// Runs `run` with a context whose output goes into buffers, which are then
+/// put in front of whatever it returned in its result.
+fn capture(
+    commands: &CommandList,
+    state: &mut ShellState,
+    stdin: &mut dyn Read,
+    run: impl FnOnce(&mut ShellContext) -> CommandResult,
+) -> CommandResult {
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let mut result = run(&mut ShellContext::new(
+        commands,
+        state,
+        stdin,
+        Output::new(&mut stdout, &mut stderr),
+    ));
+    if !stdout.is_empty() {
+        stdout.append(&mut result.stdout);
+        result.stdout = stdout;
+    }
+    // Messages in a result are separated by newlines, not ended by them
+    let streamed = String::from_utf8_lossy(&stderr);
+    let streamed = streamed.trim_end_matches('\n');
+    if !streamed.is_empty() {
+        result.stderr = if result.stderr.is_empty() {
+            streamed.to_string()
+        } else {
+            format!("{}\n{}", streamed, result.stderr)
+        };
+    }
+    result
+}
+
+/// A command's name and arguments, as the shell hands them to it.
+pub struct Invocation {
+    pub name: String,
+    /// The flags given, such as `-l` and `--all`. Commands that receive
+    /// their arguments unsplit, including those parsed by their spec, get
+    /// none.
+    pub flags: Vec<String>,
+    pub args: Vec<String>,
+}
+
+/// Something the shell can run by name.
+///
+/// Implementations may keep state between runs, such as a cache or their
+/// configuration. `xargs -P` runs commands on several threads at once, so
+/// state that changes must be kept behind a lock or in atomics.
+pub trait ShellCommand: Send + Sync {
+    /// Runs the command. Output may be written to `ctx.out` as it is
+    /// produced; what is left in the result is written once it returns.
+    fn run(&self, ctx: &mut ShellContext, inv: Invocation) -> CommandResult;
+}
+
+/// A command that returns all of its output at once, in its result.
+struct Buffered(fn(Vec<String>, Vec<String>) -> CommandResult);
+
+impl ShellCommand for Buffered {
+    fn run(&self, _ctx: &mut ShellContext, inv: Invocation) -> CommandResult {
+        (self.0)(inv.flags, inv.args)
+    }
+}
+
+/// A command that is given the shell's context. It may write its output as
+/// it goes, returning only what is left once it has finished, such as
+/// errors collected along the way.
+struct Contextual(fn(Vec<String>, Vec<String>, &mut ShellContext) -> CommandResult);
+
+impl ShellCommand for Contextual {
+    fn run(&self, ctx: &mut ShellContext, inv: Invocation) -> CommandResult {
+        (self.0)(inv.flags, inv.args, ctx)
+    }
+}
+
+/// A command whose arguments the shell parses by its spec.
+struct Parsed {
+    spec: &'static Spec,
+    require_args: bool,
+    callback: fn(ParsedArgs) -> CommandResult,
+}
+
+impl ShellCommand for Parsed {
+    fn run(&self, _ctx: &mut ShellContext, inv: Invocation) -> CommandResult {
+        match parse_invocation(self.spec, self.require_args, inv) {
+            Ok(args) => (self.callback)(args),
+            Err(result) => result,
+        }
+    }
+}
+
+/// Like [`Parsed`], for a command that is given the shell's context.
+struct ParsedContextual {
+    spec: &'static Spec,
+    require_args: bool,
+    callback: fn(ParsedArgs, &mut ShellContext) -> CommandResult,
+}
+
+impl ShellCommand for ParsedContextual {
+    fn run(&self, ctx: &mut ShellContext, inv: Invocation) -> CommandResult {
+        match parse_invocation(self.spec, self.require_args, inv) {
+            Ok(args) => (self.callback)(args, ctx),
+            Err(result) => result,
         }
     }
 }
 
-/// Represents a single command with its metadata and callback function
+/// Parses the arguments of `inv` by `spec`. Returns the result to give
+/// instead of running the command if they ask for its help or version, or
+/// break the spec, or if `require_args` and there are no operands.
+fn parse_invocation(
+    spec: &'static Spec,
+    require_args: bool,
+    inv: Invocation,
+) -> Result<ParsedArgs, CommandResult> {
+    let usage = |msg: String| {
+        CommandResult::error(CommandError::Usage(format!(
+            "{}: {}\nTry 'help' or '{} --help' for more information.",
+            inv.name, msg, inv.name
+        )))
+    };
+    // Callers that split the flags out have them put back first
+    let tokens: Vec<String> = inv.flags.iter().chain(&inv.args).cloned().collect();
+    let args = spec.parse(&tokens).map_err(usage)?;
+    if args.has("--help") {
+        return Err(CommandResult::with_stdout(spec.help_text()));
+    }
+    if args.has("--version") {
+        return Err(CommandResult::with_stdout(crate::spec::version(&inv.name)));
+    }
+    if require_args && args.operands.is_empty() {
+        return Err(usage("missing operand.".to_string()));
+    }
+    Ok(args)
+}
+
+/// Represents a single command with its metadata and implementation
 pub struct Command {
     help: String,
     pub require_args: bool,
-    callback: fn(Vec<String>, Vec<String>) -> CommandResult,
+    /// If true, the callback receives every token in its original order as
+    /// `args` (with no flags split out), so it can parse its own options.
+    pub raw_args: bool,
+    /// The command's arguments, from which its help is generated.
+    spec: Option<&'static Spec>,
+    /// The long help shown by `help NAME`, beyond the usage line.
+    manual: Option<&'static Manual>,
+    command: Box<dyn ShellCommand>,
 }
 
 impl Command {
@@ -63,16 +468,154 @@ impl Command {
         help: &str,
         require_args: bool,
         callback: fn(Vec<String>, Vec<String>) -> CommandResult,
+    ) -> Self {
+        Self::from_command(help, require_args, Buffered(callback))
+    }
+
+    /// Creates a command that is given the shell's context, for commands
+    /// that use the session's state or standard input, run other commands,
+    /// or write their output as they produce it.
+    pub fn new_contextual(
+        help: &str,
+        require_args: bool,
+        callback: fn(Vec<String>, Vec<String>, &mut ShellContext) -> CommandResult,
+    ) -> Self {
+        Self::from_command(help, require_args, Contextual(callback))
+    }
+
+    /// Creates a command run by `command`, which may keep state of its own.
+    pub fn from_command(
+        help: &str,
+        require_args: bool,
+        command: impl ShellCommand + 'static,
     ) -> Self {
         Self {
             help: help.to_string(),
             require_args,
+            raw_args: false,
+            spec: None,
+            manual: None,
+            command: Box::new(command),
+        }
+    }
+
+    /// Creates a command whose one-line help and `--help` output are
+    /// generated from `spec`.
+    pub fn from_spec(
+        spec: &'static Spec,
+        require_args: bool,
+        callback: fn(Vec<String>, Vec<String>) -> CommandResult,
+    ) -> Self {
+        Self {
+            spec: Some(spec),
+            ..Self::new(&spec.help_line(), require_args, callback)
+        }
+    }
+
+    /// Like [`Command::from_spec`], for a command that is given the
+    /// shell's context.
+    pub fn from_spec_contextual(
+        spec: &'static Spec,
+        require_args: bool,
+        callback: fn(Vec<String>, Vec<String>, &mut ShellContext) -> CommandResult,
+    ) -> Self {
+        Self {
+            spec: Some(spec),
+            ..Self::new_contextual(&spec.help_line(), require_args, callback)
+        }
+    }
+
+    /// Creates a command whose arguments are parsed by `spec`, which also
+    /// generates its help. Flags may take values, and anything `spec` does
+    /// not allow is a usage error. With `require_args`, the command must be
+    /// given an operand.
+    pub fn from_spec_parsed(
+        spec: &'static Spec,
+        require_args: bool,
+        callback: fn(ParsedArgs) -> CommandResult,
+    ) -> Self {
+        let parsed = Parsed {
+            spec,
+            require_args,
+            callback,
+        };
+        Self {
+            spec: Some(spec),
+            raw_args: true,
+            ..Self::from_command(&spec.help_line(), false, parsed)
+        }
+    }
+
+    /// Like [`Command::from_spec_parsed`], for a command that is given the
+    /// shell's context.
+    pub fn from_spec_parsed_contextual(
+        spec: &'static Spec,
+        require_args: bool,
+        callback: fn(ParsedArgs, &mut ShellContext) -> CommandResult,
+    ) -> Self {
+        let parsed = ParsedContextual {
+            spec,
+            require_args,
             callback,
+        };
+        Self {
+            spec: Some(spec),
+            raw_args: true,
+            ..Self::from_command(&spec.help_line(), false, parsed)
+        }
+    }
+
+    /// The output of `--help`.
+    pub fn usage(&self) -> String {
+        match self.spec {
+            Some(spec) => spec.help_text(),
+            None => format!("Usage: {}\n", self.help),
+        }
+    }
+
+    /// Gives the command a long help page.
+    pub fn with_manual(self, manual: &'static Manual) -> Self {
+        Self {
+            manual: Some(manual),
+            ..self
+        }
+    }
+
+    /// Whether `flag` asks for the command's usage. A spec may give `-h`
+    /// to an option of its own, as `free -h` and `ls -h` do.
+    fn asks_for_help(&self, flag: &str) -> bool {
+        match self.spec {
+            Some(spec) => spec.is_help_flag(flag),
+            None => flag == "--help" || flag == "-h",
+        }
+    }
+
+    /// Creates a command that receives its arguments unsplit.
+    /// Used by commands that run another command line, such as `xargs`, or
+    /// whose option values may look like flags, such as `truncate -s -5`.
+    pub fn new_raw(
+        help: &str,
+        require_args: bool,
+        callback: fn(Vec<String>, Vec<String>) -> CommandResult,
+    ) -> Self {
+        Self {
+            raw_args: true,
+            ..Self::new(help, require_args, callback)
+        }
+    }
+
+    /// Makes the command receive its arguments unsplit, as
+    /// [`Command::new_raw`] does.
+    pub fn with_raw_args(self) -> Self {
+        Self {
+            raw_args: true,
+            ..self
         }
     }
 }
 
 /// Collection of registered commands
+#[derive(Default)]
 pub struct CommandList {
     cmds: HashMap<String, Command>,
 }
@@ -88,18 +631,28 @@ impl CommandList {
         self.cmds.insert(name, cmd);
     }
 
-    pub fn execute(
-        &self,
-        cmd_name: String,
-        flags: Vec<String>,
-        args: Vec<String>,
-    ) -> CommandResult {
-        // 1. Global 'help' list
+    pub fn contains(&self, name: &str) -> bool {
+        self.cmds.contains_key(name)
+    }
+
+    /// Executes a command, letting it write to `out` as it goes. What is
+    /// left in the result is for the caller to write once it returns.
+    pub fn execute(&self, ctx: &mut ShellContext, inv: Invocation) -> CommandResult {
+        let Invocation {
+            name: cmd_name,
+            flags,
+            args,
+        } = inv;
+        // 1. Global 'help' list, or the pages of the commands named
         if cmd_name == "help" {
+            if !args.is_empty() {
+                return self.help_pages(&args, &ctx.state.vars);
+            }
             let mut help_text = String::from("Available commands:\n");
             for (name, cmd) in &self.cmds {
                 help_text.push_str(&format!("  {:10} - {}\n", name, cmd.help));
             }
+            help_text.push_str("Type 'help COMMAND' for more about a command.\n");
             return CommandResult::with_stdout(help_text);
         }
 
@@ -107,711 +660,12261 @@ impl CommandList {
         let cmd = match self.cmds.get(&cmd_name) {
             Some(c) => c,
             None => {
-                return CommandResult::with_stderr(format!(
+                return CommandResult::error(CommandError::CommandNotFound(format!(
                     "0-shell: {}: command not found",
                     cmd_name
-                ));
+                )));
             }
         };
 
-        // 3. Specific '--help' flag check
-        if flags.iter().any(|flag| flag == "--help" || flag == "-h") {
-            return CommandResult::with_stdout(format!("Usage: {}\n", cmd.help));
+        // 3. Specific '--help' flag check ('-h' too, unless the spec uses it)
+        if flags.iter().any(|flag| cmd.asks_for_help(flag)) {
+            return CommandResult::with_stdout(cmd.usage());
+        }
+        if flags.iter().any(|flag| flag == "--version") {
+            return CommandResult::with_stdout(crate::spec::version(&cmd_name));
         }
 
         // 4. Centralized Argument Validation
         if cmd.require_args && args.is_empty() {
-            return CommandResult::with_stderr(format!(
+            return CommandResult::error(CommandError::Usage(format!(
                 "{}: missing operand.\nTry 'help' or '{} --help' for more information.",
                 cmd_name, cmd_name
-            ));
+            )));
+        }
+
+        // 5. Run the command
+        cmd.command.run(
+            ctx,
+            Invocation {
+                name: cmd_name,
+                flags,
+                args,
+            },
+        )
+    }
+
+    /// The long help page of the command `name`, if there is such a command.
+    pub fn manual_page(&self, name: &str) -> Option<String> {
+        let cmd = self.cmds.get(name)?;
+        Some(crate::manual::render(name, &cmd.help, cmd.spec, cmd.manual))
+    }
+
+    /// Shows the pages of the commands in `names`, through the pager on a
+    /// terminal. Pages that fit on the screen are printed as they are.
+    fn help_pages(&self, names: &[String], vars: &Variables) -> CommandResult {
+        use std::io::IsTerminal;
+
+        let mut result = CommandResult::new();
+        let mut pages = Vec::new();
+        for name in names {
+            match self.manual_page(name) {
+                Some(page) => pages.push(page),
+                None => result.append_stderr(&format!("help: no help topics match '{}'", name)),
+            }
+        }
+        let text = pages.join("\n");
+        if text.is_empty() || !io::stdout().is_terminal() {
+            result.stdout = text.into_bytes();
+        } else if let Err(e) =
+            crate::pager::page(&text, &format!("help {}", names.join(" ")), true, vars)
+        {
+            result.append_stderr(&format!("help: {}", e));
+        }
+        result
+    }
+
+    /// Executes a parsed call, handing raw-argument commands their tokens
+    /// in the original order. Commands may write to `ctx.out` as they go;
+    /// what is left in the result is for the caller to write. To collect
+    /// all of the output instead, use [`ShellContext::capture`].
+    ///
+    /// A command name that is an alias is replaced by the alias's text,
+    /// once, so an alias may use the command it is named after.
+    ///
+    /// `NAME=value` assignments in the call are set for the duration of the
+    /// command and restored afterwards. Assignments with no command are
+    /// applied to the shell permanently.
+    pub fn run_call(&self, ctx: &mut ShellContext, mut call: CommandCall) -> CommandResult {
+        let mut env = std::mem::take(&mut call.env);
+        if let Some(text) = ctx.state.aliases.get(&call.name) {
+            let mut tokens = match tokenize(text, ctx.state) {
+                Ok(tokens) => tokens,
+                Err(e) => return CommandResult::error(e),
+            };
+            tokens.append(&mut call.raw_args);
+            match CommandCall::from_tokens(tokens) {
+                Some(mut expanded) => {
+                    env.append(&mut expanded.env);
+                    call = expanded;
+                }
+                None => return CommandResult::new(),
+            }
+        }
+
+        let vars = &mut ctx.state.vars;
+        if call.name.is_empty() {
+            for (name, value) in &env {
+                // As in other shells, assigning RANDOM seeds its generator
+                if name == "RANDOM" {
+                    ctx.state.rng.seed(crate::random::seed_from_text(value));
+                } else {
+                    vars.set(name, value);
+                }
+            }
+            return CommandResult::new();
+        }
+
+        let saved: Vec<(String, Option<String>)> = env
+            .iter()
+            .map(|(name, _)| (name.clone(), vars.get(name)))
+            .collect();
+        for (name, value) in &env {
+            vars.set(name, value);
+        }
+
+        let result = self.dispatch_call(ctx, call);
+
+        let vars = &mut ctx.state.vars;
+        for (name, previous) in saved.iter().rev() {
+            match previous {
+                Some(value) => vars.set(name, value),
+                None => vars.remove(name),
+            }
         }
+        result
+    }
+
+    /// Runs `call` with `state` and `stdin`, collecting its output in the
+    /// result.
+    pub fn capture_call(
+        &self,
+        state: &mut ShellState,
+        stdin: &mut dyn Read,
+        call: CommandCall,
+    ) -> CommandResult {
+        capture(self, state, stdin, |ctx| self.run_call(ctx, call))
+    }
+
+    /// Runs `call` on its own, with a copy of `state` and no input, collecting
+    /// its output in the result. Used to run commands on other threads,
+    /// which cannot share the shell's context.
+    pub fn run_detached(&self, state: &ShellState, call: CommandCall) -> CommandResult {
+        self.capture_call(&mut state.clone(), &mut io::empty(), call)
+    }
 
-        // 5. Trigger the callback
-        (cmd.callback)(flags, args)
+    fn dispatch_call(&self, ctx: &mut ShellContext, call: CommandCall) -> CommandResult {
+        let (flags, args) = match self.cmds.get(&call.name) {
+            Some(cmd) if cmd.raw_args => {
+                // Only a leading --help or --version belongs to the command itself
+                match call
+                    .raw_args
+                    .first()
+                    .filter(|a| *a == "--help" || *a == "--version")
+                {
+                    Some(flag) => (vec![flag.clone()], vec![]),
+                    None => (vec![], call.raw_args),
+                }
+            }
+            _ => (call.flags, call.args),
+        };
+        self.execute(
+            ctx,
+            Invocation {
+                name: call.name,
+                flags,
+                args,
+            },
+        )
     }
 }
 
-/// Creates and registers all available commands
+/// Creates and registers all available commands. Plugins are loaded
+/// separately, with [`crate::Shell::load_plugins`].
 pub fn command_list() -> CommandList {
     let mut cmds = CommandList::new();
 
     cmds.register(
         "exit".to_string(),
-        Command::new("exit - cause the shell to exit", false, exit_callback),
+        Command::from_spec_contextual(&EXIT_SPEC, false, exit_callback)
+            .with_manual(&crate::manual::EXIT),
     );
 
     cmds.register(
         "echo".to_string(),
-        Command::new(
-            "echo [-e] [text ...] - display a line of text",
-            false,
-            echo_callback,
-        ),
+        Command::from_spec(&ECHO_SPEC, false, echo_callback).with_manual(&crate::manual::ECHO),
     );
 
     cmds.register(
         "pwd".to_string(),
-        Command::new(
-            "pwd - print name of current/working directory",
-            false,
-            pwd_callback,
-        ),
+        Command::from_spec_contextual(&PWD_SPEC, false, pwd_callback)
+            .with_manual(&crate::manual::PWD),
     );
 
     cmds.register(
         "cd".to_string(),
-        Command::new(
-            "cd [DIRECTORY] - change the working directory",
-            false,
-            cd_callback,
-        ),
+        Command::from_spec_contextual(&CD_SPEC, false, cd_callback).with_manual(&crate::manual::CD),
     );
 
     cmds.register(
         "mkdir".to_string(),
-        Command::new(
-            "mkdir DIRECTORY... - create directories",
-            true,
-            mkdir_callback,
-        ),
+        Command::from_spec_parsed(&MKDIR_SPEC, true, mkdir_callback)
+            .with_manual(&crate::manual::MKDIR),
     );
 
     cmds.register(
         "cat".to_string(),
-        Command::new(
-            "cat [FILE...] - concatenate files and print on the standard output",
-            false,
-            cat_callback,
-        ),
+        Command::from_spec_contextual(&CAT_SPEC, false, cat_callback)
+            .with_manual(&crate::manual::CAT),
     );
 
     cmds.register(
         "cp".to_string(),
-        Command::new(
-            "cp SOURCE DEST or cp SOURCE... DIRECTORY - copy files and directories",
-            true,
-            cp_callback,
-        ),
+        Command::from_spec_contextual(&CP_SPEC, true, cp_callback).with_manual(&crate::manual::CP),
     );
 
     cmds.register(
         "mv".to_string(),
-        Command::new(
-            "mv SOURCE DEST or mv SOURCE... DIRECTORY - move (rename) files",
-            true,
-            mv_callback,
-        ),
+        Command::from_spec_contextual(&MV_SPEC, true, mv_callback).with_manual(&crate::manual::MV),
     );
 
     cmds.register(
         "rm".to_string(),
-        Command::new(
-            "rm [-r] FILE... - remove files or directories",
-            true,
-            rm_callback,
-        ),
+        Command::from_spec_contextual(&RM_SPEC, true, rm_callback).with_manual(&crate::manual::RM),
     );
 
     cmds.register(
         "ls".to_string(),
-        Command::new(
-            "ls [-a] [-l] [-F] [FILE...] - list directory contents",
+        Command::from_spec_contextual(&LS_SPEC, false, ls_callback).with_manual(&crate::manual::LS),
+    );
+
+    cmds.register(
+        "gzip".to_string(),
+        Command::new_contextual(
+            "gzip [-d] [-k] [-c] [-f] [-1..-9] [FILE...] - compress or expand files",
+            false,
+            gzip_callback,
+        ),
+    );
+
+    cmds.register(
+        "gunzip".to_string(),
+        Command::new_contextual(
+            "gunzip [-k] [-c] [-f] [FILE...] - expand gzip compressed files",
+            false,
+            gunzip_callback,
+        ),
+    );
+
+    cmds.register(
+        "less".to_string(),
+        Command::new_contextual(
+            "less [FILE...] - view text one screen at a time",
+            false,
+            less_callback,
+        ),
+    );
+
+    cmds.register(
+        "more".to_string(),
+        Command::new_contextual(
+            "more [FILE...] - view text one screen at a time, exiting at the end",
+            false,
+            more_callback,
+        ),
+    );
+
+    cmds.register(
+        "xargs".to_string(),
+        Command::new_contextual(
+            "xargs [-n MAX] [-I REPLACE] [-0] [-P PROCS] [COMMAND [ARG...]] - build and run commands from standard input",
+            false,
+            xargs_callback,
+        ).with_raw_args(),
+    );
+
+    cmds.register(
+        "watch".to_string(),
+        Command::new_contextual(
+            "watch [-n SECONDS] [-t] COMMAND [ARG...] - execute a command periodically",
+            true,
+            watch_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "uptime".to_string(),
+        Command::new(
+            "uptime [-p] [-s] - tell how long the system has been running",
+            false,
+            uptime_callback,
+        ),
+    );
+
+    cmds.register(
+        "free".to_string(),
+        Command::from_spec_parsed(&FREE_SPEC, false, free_callback),
+    );
+
+    cmds.register(
+        "dd".to_string(),
+        Command::new_contextual(
+            "dd [if=FILE] [of=FILE] [bs=BYTES] [count=N] [skip=N] [seek=N] [status=LEVEL] [conv=notrunc] - copy and convert a file",
+            false,
+            dd_callback,
+        ),
+    );
+
+    cmds.register(
+        "split".to_string(),
+        Command::from_spec_parsed_contextual(&SPLIT_SPEC, false, split_callback),
+    );
+
+    cmds.register(
+        "truncate".to_string(),
+        Command::new_raw(
+            "truncate [-c] -s SIZE | -r RFILE FILE... - shrink or extend the size of files",
+            true,
+            truncate_callback,
+        ),
+    );
+
+    cmds.register(
+        "shred".to_string(),
+        Command::from_spec_parsed_contextual(&SHRED_SPEC, true, shred_callback),
+    );
+
+    cmds.register(
+        "nohup".to_string(),
+        Command::new_contextual(
+            "nohup COMMAND [ARG...] - run a command immune to hangups",
+            true,
+            nohup_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "timeout".to_string(),
+        Command::new_contextual(
+            "timeout [-s SIGNAL] [-k DURATION] [-v] DURATION COMMAND [ARG...] - run a command with a time limit",
+            true,
+            timeout_callback,
+        ).with_raw_args(),
+    );
+
+    cmds.register(
+        "nice".to_string(),
+        Command::new_contextual(
+            "nice [-n ADJUSTMENT] [COMMAND [ARG...]] - run a command with adjusted priority",
+            false,
+            nice_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "renice".to_string(),
+        Command::new_raw(
+            "renice [-n] PRIORITY [-p|-g|-u] ID... - change the priority of running processes",
+            true,
+            renice_callback,
+        ),
+    );
+
+    cmds.register(
+        "pgrep".to_string(),
+        Command::new_raw(
+            "pgrep [-flaxivcno] [-u USER] [-d DELIM] PATTERN - find processes by name",
+            false,
+            pgrep_callback,
+        ),
+    );
+
+    cmds.register(
+        "pkill".to_string(),
+        Command::new_raw(
+            "pkill [-SIGNAL] [-fxivneo] [-u USER] PATTERN - signal processes by name",
+            false,
+            pkill_callback,
+        ),
+    );
+
+    cmds.register(
+        "mount".to_string(),
+        Command::from_spec_parsed(&MOUNT_SPEC, false, mount_callback),
+    );
+
+    cmds.register(
+        "rev".to_string(),
+        Command::new_contextual(
+            "rev [FILE...] - reverse the characters of each line",
+            false,
+            rev_callback,
+        ),
+    );
+
+    cmds.register(
+        "fold".to_string(),
+        Command::from_spec_parsed_contextual(&FOLD_SPEC, false, fold_callback),
+    );
+
+    cmds.register(
+        "expand".to_string(),
+        Command::from_spec_parsed_contextual(&EXPAND_SPEC, false, expand_callback),
+    );
+
+    cmds.register(
+        "unexpand".to_string(),
+        Command::from_spec_parsed_contextual(&UNEXPAND_SPEC, false, unexpand_callback),
+    );
+
+    cmds.register(
+        "comm".to_string(),
+        Command::new_contextual(
+            "comm [-123] FILE1 FILE2 - compare two sorted files line by line",
+            true,
+            comm_callback,
+        ),
+    );
+
+    cmds.register(
+        "join".to_string(),
+        Command::from_spec_parsed_contextual(&JOIN_SPEC, true, join_callback),
+    );
+
+    cmds.register(
+        "shuf".to_string(),
+        Command::from_spec_parsed_contextual(&SHUF_SPEC, false, shuf_callback),
+    );
+
+    cmds.register(
+        "tac".to_string(),
+        Command::new_contextual(
+            "tac [FILE...] - print files with lines in reverse order",
+            false,
+            tac_callback,
+        ),
+    );
+
+    cmds.register(
+        "strings".to_string(),
+        Command::from_spec_parsed_contextual(&STRINGS_SPEC, false, strings_callback),
+    );
+
+    cmds.register(
+        "base64".to_string(),
+        Command::from_spec_parsed_contextual(&BASE64_SPEC, false, base64_callback),
+    );
+
+    cmds.register(
+        "cksum".to_string(),
+        Command::new_contextual(
+            "cksum [FILE...] - print POSIX CRC checksums and byte counts",
+            false,
+            cksum_callback,
+        ),
+    );
+
+    cmds.register(
+        "crc32".to_string(),
+        Command::new_contextual(
+            "crc32 [FILE...] - print CRC-32 checksums as used by gzip and zip",
+            false,
+            crc32_callback,
+        ),
+    );
+
+    cmds.register(
+        "sync".to_string(),
+        Command::new(
+            "sync [-d] [-f] [FILE...] - flush cached writes to disk",
+            false,
+            sync_callback,
+        ),
+    );
+
+    cmds.register(
+        "mkfifo".to_string(),
+        Command::from_spec_parsed(&MKFIFO_SPEC, true, mkfifo_callback),
+    );
+
+    cmds.register(
+        "ulimit".to_string(),
+        Command::new(
+            "ulimit [-SHa] [-cdflmnstuv] [LIMIT] - show or set resource limits",
+            false,
+            ulimit_callback,
+        ),
+    );
+
+    cmds.register(
+        "cal".to_string(),
+        Command::new(
+            "cal [-3] [-y] [-m|-s] [[MONTH] YEAR] - print a calendar",
+            false,
+            cal_callback,
+        ),
+    );
+
+    cmds.register(
+        "calc".to_string(),
+        Command::new_contextual(
+            "calc EXPRESSION... - evaluate an arithmetic expression",
+            true,
+            calc_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "expr".to_string(),
+        Command::new_contextual(
+            "expr EXPRESSION... - evaluate an arithmetic expression",
+            true,
+            expr_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "uuidgen".to_string(),
+        Command::from_spec_parsed(&UUIDGEN_SPEC, false, uuidgen_callback),
+    );
+
+    cmds.register(
+        "random".to_string(),
+        Command::new_contextual(
+            "random [MIN MAX] - print a random number",
+            false,
+            random_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "json".to_string(),
+        Command::new_contextual(
+            "json [-c] [-r] [-C|-M] [QUERY] [FILE...] - pretty-print and query JSON",
+            false,
+            json_callback,
+        ),
+    );
+
+    cmds.register(
+        "csv".to_string(),
+        Command::from_spec_parsed_contextual(&CSV_SPEC, false, csv_callback),
+    );
+
+    cmds.register(
+        "fetch".to_string(),
+        Command::from_spec_parsed_contextual(&FETCH_SPEC, true, fetch_callback),
+    );
+
+    cmds.register(
+        "ping".to_string(),
+        Command::from_spec_parsed_contextual(&PING_SPEC, true, ping_callback),
+    );
+
+    cmds.register(
+        "nc".to_string(),
+        Command::from_spec_parsed_contextual(&NC_SPEC, true, nc_callback),
+    );
+
+    cmds.register(
+        "resolve".to_string(),
+        Command::new(
+            "resolve [-4|-6] [-x] NAME... - look up host addresses or names",
+            true,
+            resolve_callback,
+        ),
+    );
+
+    cmds.register(
+        "ifinfo".to_string(),
+        Command::new(
+            "ifinfo [-u] [INTERFACE...] - list network interfaces and their addresses",
             false,
-            ls_callback,
+            ifinfo_callback,
         ),
     );
 
-    cmds
-}
+    cmds.register(
+        "open".to_string(),
+        Command::new_contextual(
+            "open FILE|URL... - open files or URLs with their default applications",
+            true,
+            open_callback,
+        ),
+    );
+
+    cmds.register(
+        "clip".to_string(),
+        Command::new_contextual(
+            "clip [FILE...] - copy files or standard input to the clipboard",
+            false,
+            clip_callback,
+        ),
+    );
+
+    cmds.register(
+        "paste-clip".to_string(),
+        Command::new_contextual(
+            "paste-clip - print the clipboard contents",
+            false,
+            paste_clip_callback,
+        ),
+    );
+
+    cmds.register(
+        "zip".to_string(),
+        Command::from_spec_parsed(&ZIP_SPEC, true, zip_callback),
+    );
+
+    cmds.register(
+        "unzip".to_string(),
+        Command::from_spec_parsed_contextual(&UNZIP_SPEC, true, unzip_callback),
+    );
+
+    cmds.register(
+        "encode".to_string(),
+        Command::from_spec_parsed_contextual(&ENCODE_SPEC, false, encode_callback),
+    );
+
+    cmds.register(
+        "dos2unix".to_string(),
+        Command::new_contextual(
+            "dos2unix [-c] [-b] [-f] [--check] [FILE...] - convert CRLF line endings to LF",
+            false,
+            dos2unix_callback,
+        ),
+    );
+
+    cmds.register(
+        "unix2dos".to_string(),
+        Command::new_contextual(
+            "unix2dos [-c] [-m] [-f] [--check] [FILE...] - convert LF line endings to CRLF",
+            false,
+            unix2dos_callback,
+        ),
+    );
+
+    cmds.register(
+        "killall".to_string(),
+        Command::new_raw(
+            "killall [-SIGNAL] [-iqvw] [-u USER] NAME... - signal processes by exact name",
+            true,
+            killall_callback,
+        ),
+    );
+
+    cmds.register(
+        "top".to_string(),
+        Command::from_spec_parsed_contextual(&TOP_SPEC, false, top_callback),
+    );
+
+    cmds.register(
+        "schedule".to_string(),
+        Command::new_contextual(
+            "schedule WHEN COMMAND... | -l | -c ID... - run a command later in this session",
+            true,
+            schedule_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "at".to_string(),
+        Command::new_contextual(
+            "at WHEN COMMAND... | -l | -c ID... - same as schedule",
+            true,
+            schedule_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "alias".to_string(),
+        Command::new_contextual(
+            "alias [NAME[=VALUE]...] - define or show aliases",
+            false,
+            alias_callback,
+        )
+        .with_raw_args(),
+    );
+
+    cmds.register(
+        "unalias".to_string(),
+        Command::new_contextual(
+            "unalias -a | NAME... - remove aliases",
+            true,
+            unalias_callback,
+        ),
+    );
+
+    cmds.register(
+        "history".to_string(),
+        Command::from_spec_parsed_contextual(&HISTORY_SPEC, false, history_callback),
+    );
+
+    cmds.register(
+        "j".to_string(),
+        Command::new_contextual(
+            "j [-l] [PATTERN...] - jump to a frequently used directory",
+            false,
+            j_callback,
+        ),
+    );
+
+    cmds.register(
+        "trash-list".to_string(),
+        Command::new_contextual(
+            "trash-list - list the files in the trash",
+            false,
+            trash_list_callback,
+        ),
+    );
+
+    cmds.register(
+        "restore".to_string(),
+        Command::new_contextual(
+            "restore FILE... - move files in the trash back to where they were",
+            true,
+            restore_callback,
+        ),
+    );
+
+    cmds
+}
+
+// ============================================================================
+// Command Callback Functions
+// ============================================================================
+
+const EXIT_SPEC: Spec = Spec {
+    name: "exit",
+    summary: "cause the shell to exit",
+    forms: &["[N]"],
+    flags: &[],
+};
+
+/// Causes the shell to exit with status N, or with the status of the last
+/// command.
+///
+/// Returns a special `CommandResult` that indicates the shell should terminate.
+fn exit_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let status = match args.first() {
+        None => ctx.state.last_status,
+        // As in other shells, only the low 8 bits are kept
+        Some(n) => match n.parse::<i64>() {
+            Ok(n) => (n & 0xff) as i32,
+            Err(_) => {
+                return CommandResult::error(CommandError::Usage(format!(
+                    "exit: {}: numeric argument required",
+                    n
+                )));
+            }
+        },
+    };
+    CommandResult::exit(status)
+}
+
+const ECHO_SPEC: Spec = Spec {
+    name: "echo",
+    summary: "display a line of text",
+    forms: &["[text ...]"],
+    flags: &[Flag::new(
+        &["-e"],
+        "Interpret backslash escapes: \\a, \\b, \\e, \\f, \\n, \\r, \\t, \\v and \\\\, \\xHH and \\0NNN \
+         for bytes in hex and octal, \\uHHHH and \\UHHHHHHHH for Unicode characters, and \\c, \
+         which ends the output there, without a newline.",
+    )],
+};
+
+/// Displays a line of text.
+///
+/// Supports the `-e` flag to interpret backslash escape sequences, including
+/// `\xHH` and `\0NNN` bytes and `\uHHHH` and `\UHHHHHHHH` characters.
+fn echo_callback(flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    let mut interpret = false;
+    let mut result = CommandResult::new();
+
+    // Check for the -e flag in flags
+    if flags.iter().any(|f| f == "-e") {
+        interpret = true;
+    }
+
+    let input = args.join(" ");
+
+    if !interpret {
+        // Default Bash behavior: print literally
+        result.stdout = format!("{}\n", input).into_bytes();
+        return result;
+    }
+
+    // -e behavior: interpret backslash sequences. Built as bytes, since
+    // `\x` and `\0` escapes may give any byte, valid UTF-8 or not
+    let mut output = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut utf8 = [0; 4];
+
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('c') => {
+                    result.stdout = output;
+                    return result; // "Stop" signal: return immediately without \n
+                }
+                Some('x') => match take_escape_digits(&mut chars, 16, 2) {
+                    Some(byte) => output.push(byte as u8),
+                    None => output.extend_from_slice(b"\\x"),
+                },
+                // Values above 0o377 wrap, as in Bash
+                Some('0') => output.push(take_escape_digits(&mut chars, 8, 3).unwrap_or(0) as u8),
+                Some(next @ ('u' | 'U')) => {
+                    let max = if next == 'u' { 4 } else { 8 };
+                    match take_escape_digits(&mut chars, 16, max) {
+                        Some(code) => {
+                            let c = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                            output.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                        }
+                        None => {
+                            output.push(b'\\');
+                            output.extend_from_slice(next.encode_utf8(&mut utf8).as_bytes());
+                        }
+                    }
+                }
+                Some(next) => {
+                    if let Some(mapped) = map_echo_escape(next) {
+                        output.extend_from_slice(mapped.encode_utf8(&mut utf8).as_bytes());
+                    } else {
+                        // Not a recognized escape; push both literally
+                        output.push(b'\\');
+                        output.extend_from_slice(next.encode_utf8(&mut utf8).as_bytes());
+                    }
+                }
+                None => output.push(b'\\'), // Trailing backslash
+            }
+        } else {
+            output.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+        }
+    }
+
+    output.push(b'\n');
+    result.stdout = output;
+    result
+}
+
+/// Takes up to `max` digits in `radix` from `chars` and returns their
+/// value, or `None` if there are none.
+fn take_escape_digits(
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+    radix: u32,
+    max: usize,
+) -> Option<u32> {
+    let mut value = None;
+    for _ in 0..max {
+        let Some(digit) = chars.peek().and_then(|c| c.to_digit(radix)) else {
+            break;
+        };
+        value = Some(value.unwrap_or(0) * radix + digit);
+        chars.next();
+    }
+    value
+}
+
+/// Maps echo escape sequences to their corresponding characters.
+fn map_echo_escape(c: char) -> Option<char> {
+    match c {
+        'a' => Some('\x07'), // BEL
+        'b' => Some('\x08'), // Backspace
+        'e' => Some('\x1b'), // Escape
+        'f' => Some('\x0c'), // Form feed
+        'n' => Some('\n'),
+        'r' => Some('\r'),
+        't' => Some('\t'),
+        'v' => Some('\x0b'), // Vertical tab
+        '\\' => Some('\\'),
+        _ => None,
+    }
+}
+
+const PWD_SPEC: Spec = Spec {
+    name: "pwd",
+    summary: "print name of current/working directory",
+    forms: &[""],
+    flags: &[
+        Flag::new(
+            &["-L"],
+            "Print the path the directory was reached by, with any symbolic links on the \
+             way, as kept in $PWD. This is the default.",
+        ),
+        Flag::new(
+            &["-P"],
+            "Print the physical path, with every symbolic link resolved.",
+        ),
+    ],
+};
+
+/// Prints the current working directory.
+///
+/// Supports the following flags:
+/// - `-L`: Print the directory as it was reached, through symbolic links if
+///   that is how `cd` got there. This is the default.
+/// - `-P`: Print the physical path, with every symbolic link resolved.
+///
+/// The last of `-L` and `-P` wins.
+fn pwd_callback(flags: Vec<String>, _args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let physical = flags
+        .iter()
+        .rev()
+        .find(|f| *f == "-L" || *f == "-P")
+        .is_some_and(|f| f == "-P");
+    let cwd = if physical {
+        env::current_dir().and_then(fs::canonicalize)
+    } else {
+        logical_cwd(&ctx.state.vars)
+    };
+    match cwd {
+        Ok(path) => CommandResult::with_stdout(format!("{}\n", path.display())),
+        Err(e) => CommandResult::error(CommandError::io(
+            "pwd: error retrieving current directory",
+            e,
+        )),
+    }
+}
+
+/// The current directory as it was reached, kept in `$PWD`, with any
+/// symbolic links on the way left in. If `$PWD` is unset or no longer leads
+/// to the current directory, the physical path is used instead.
+pub fn logical_cwd(vars: &Variables) -> io::Result<std::path::PathBuf> {
+    let physical = env::current_dir()?;
+    match vars.get("PWD").map(std::path::PathBuf::from) {
+        Some(pwd) if pwd.is_absolute() && is_same_dir(&pwd, &physical) => Ok(pwd),
+        _ => Ok(physical),
+    }
+}
+
+/// Whether `a` and `b` lead to the same directory.
+fn is_same_dir(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Resolves `.` and `..` in `path` by the names alone, so that `..` goes
+/// back up through a symbolic link rather than to the link target's parent.
+fn normalize_logical(path: &Path) -> std::path::PathBuf {
+    use std::path::Component;
+
+    let mut out = std::path::PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                out.pop();
+            }
+            other => out.push(other),
+        }
+    }
+    out
+}
+
+/// Changes the current directory to `path` and updates `$PWD`: with the
+/// path as given, `..` resolved by name, or if `physical`, with every
+/// symbolic link resolved.
+fn change_dir(path: &Path, physical: bool, vars: &mut Variables) -> io::Result<()> {
+    if physical {
+        env::set_current_dir(path)?;
+        let resolved = fs::canonicalize(env::current_dir()?)?;
+        vars.set("PWD", &resolved.to_string_lossy());
+        return Ok(());
+    }
+    let target = normalize_logical(&logical_cwd(vars)?.join(path));
+    env::set_current_dir(&target)?;
+    vars.set("PWD", &target.to_string_lossy());
+    Ok(())
+}
+
+const CD_SPEC: Spec = Spec {
+    name: "cd",
+    summary: "change the working directory",
+    forms: &["[DIRECTORY]"],
+    flags: &[
+        Flag::new(
+            &["-L"],
+            "Keep symbolic links in the path, and resolve .. by removing the last name, so \
+             that cd .. leaves a linked directory the way it was entered. This is the default.",
+        ),
+        Flag::new(
+            &["-P"],
+            "Resolve symbolic links, so the directory is known by its real path.",
+        ),
+    ],
+};
+
+/// Changes the current working directory.
+///
+/// If no arguments are provided, it defaults to the `HOME` environment variable,
+/// or `/` if `HOME` is not set.
+///
+/// Supports the following flags:
+/// - `-L`: Follow symbolic links logically, the default. `$PWD`, the prompt
+///   and `pwd` keep the path as typed, and `..` leaves a linked directory
+///   the way it was entered.
+/// - `-P`: Resolve symbolic links, so the new directory is known by its
+///   physical path.
+///
+/// The last of `-L` and `-P` wins.
+fn cd_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let vars = &mut ctx.state.vars;
+    let physical = flags
+        .iter()
+        .rev()
+        .find(|f| *f == "-L" || *f == "-P")
+        .is_some_and(|f| f == "-P");
+    let destination = if args.is_empty() {
+        vars.get("HOME").unwrap_or_else(|| "/".to_string())
+    } else {
+        args[0].clone()
+    };
+
+    match change_dir(Path::new(&destination), physical, vars) {
+        Ok(_) => {
+            record_visit(ctx.state);
+            CommandResult::new()
+        }
+        Err(e) => CommandResult::error(CommandError::io(&format!("cd: {}", destination), e)),
+    }
+}
+
+/// Records the current directory in the session's database for `j`, if it
+/// has one.
+fn record_visit(state: &ShellState) {
+    if let Some(file) = &state.dirs_file
+        && let Ok(dir) = logical_cwd(&state.vars)
+    {
+        // Failing to remember a directory should not fail the `cd`
+        let _ = crate::jump::visit(file, &dir);
+    }
+}
+
+/// Changes to the directory that best matches the patterns, like `z` or
+/// `autojump`.
+///
+/// Directories are learned as they are visited with `cd` or `j`, and
+/// ranked by how often and how recently they were used. The patterns must
+/// appear in the path in order, ignoring case, and the last must be in its
+/// final component. Directories that no longer exist are skipped, as is the
+/// current one.
+///
+/// Supports the following options:
+/// - `-l`: List the matching directories and their scores instead. This is
+///   also what `j` does without patterns.
+fn j_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let dirs = match ctx.state.dirs_file.as_deref().map(crate::jump::load) {
+        None => Vec::new(),
+        Some(Ok(dirs)) => dirs,
+        Some(Err(e)) => return CommandResult::with_stderr(format!("j: {}", e)),
+    };
+    let now = Local::now().timestamp();
+    let found = crate::jump::matches(&dirs, &args, now);
+
+    if args.is_empty() || flags.iter().any(|f| f == "-l") {
+        let listing: String = found
+            .iter()
+            .map(|(score, dir)| format!("{:>8.1}  {}\n", score, dir.path))
+            .collect();
+        return CommandResult::with_stdout(listing);
+    }
+
+    let cwd = logical_cwd(&ctx.state.vars).unwrap_or_default();
+    let target = found
+        .iter()
+        .map(|(_, dir)| Path::new(&dir.path))
+        .find(|path| *path != cwd && path.is_dir());
+    let Some(target) = target else {
+        return CommandResult::error(CommandError::Failed(format!(
+            "j: no match for '{}'",
+            args.join(" ")
+        )));
+    };
+    match change_dir(target, false, &mut ctx.state.vars) {
+        Ok(_) => {
+            record_visit(ctx.state);
+            CommandResult::new()
+        }
+        Err(e) => CommandResult::with_stderr(format!("j: {}: {}", target.display(), e)),
+    }
+}
+
+const MKDIR_SPEC: Spec = Spec {
+    name: "mkdir",
+    summary: "create directories",
+    forms: &["DIRECTORY..."],
+    flags: &[
+        Flag::new(
+            &["-p", "--parents"],
+            "Create missing parents too, and skip directories that already exist.",
+        ),
+        Flag::new(
+            &["-v", "--verbose"],
+            "Print each directory as it is created.",
+        ),
+        Flag::with_value(
+            &["-m", "--mode"],
+            "MODE",
+            "Give new directories MODE, in octal or symbolic form, instead of 0777 less the \
+             umask. Parents made by -p keep the usual mode.",
+        ),
+    ],
+};
+
+/// Creates one or more directories.
+///
+/// The parent of each directory must already exist, and so must not the
+/// directory itself, unless `-p` is given.
+///
+/// Supports the following flags:
+/// - `-p`, `--parents`: Create missing parents as well, and skip
+///   directories that already exist.
+/// - `-m MODE`, `--mode=MODE`: Give each new directory MODE (octal or
+///   symbolic, relative to `a=rwx`) instead of `0777` reduced by the umask.
+///   Parents made by `-p` keep the default.
+/// - `-v`, `--verbose`: Print each directory as it is created.
+fn mkdir_callback(args: ParsedArgs) -> CommandResult {
+    let mode = match args.value("--mode") {
+        None => None,
+        Some(spec) => match parse_mode(spec, 0o777, true) {
+            Some(mode) => Some(mode),
+            None => {
+                return CommandResult::error(CommandError::Usage(format!(
+                    "mkdir: invalid mode '{}'",
+                    spec
+                )));
+            }
+        },
+    };
+    let parents = args.has("--parents");
+    let verbose = args.has("--verbose");
+
+    let mut result = CommandResult::new();
+    for path in &args.operands {
+        let created = match make_dirs(Path::new(path), parents) {
+            Ok(created) => created,
+            Err(e) => {
+                let context = format!("mkdir: cannot create directory '{}'", path);
+                result.push_error(CommandError::io(&context, e));
+                continue;
+            }
+        };
+        #[cfg(unix)]
+        if let Some(mode) = mode
+            && created.last().is_some_and(|dir| dir == Path::new(path))
+            && let Err(e) = fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        {
+            let context = format!("mkdir: cannot set permissions of '{}'", path);
+            result.push_error(CommandError::io(&context, e));
+        }
+        #[cfg(not(unix))]
+        let _ = mode;
+        if verbose {
+            for dir in created {
+                result.stdout.extend_from_slice(
+                    format!("mkdir: created directory '{}'\n", dir.display()).as_bytes(),
+                );
+            }
+        }
+    }
+    result
+}
+
+/// Creates the directory `path`, and with `parents` any missing directories
+/// above it, returning those it created, outermost first.
+fn make_dirs(path: &Path, parents: bool) -> io::Result<Vec<std::path::PathBuf>> {
+    if !parents {
+        fs::create_dir(path)?;
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut missing: Vec<&Path> = path
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && fs::symlink_metadata(dir).is_err())
+        .collect();
+    missing.reverse();
+    let mut created = Vec::new();
+    for dir in missing {
+        match fs::create_dir(dir) {
+            Ok(()) => created.push(dir.to_path_buf()),
+            // Made by someone else in the meantime
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dir.is_dir() => {}
+            Err(e) => return Err(e),
+        }
+    }
+    if !path.is_dir() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, "File exists"));
+    }
+    Ok(created)
+}
+
+const CAT_SPEC: Spec = Spec {
+    name: "cat",
+    summary: "concatenate files and print on the standard output",
+    forms: &["[FILE...]"],
+    flags: &[
+        Flag::new(&["-n"], "Number all output lines."),
+        Flag::new(&["-b"], "Number non-blank output lines. Overrides -n."),
+        Flag::new(&["-s"], "Squeeze each run of blank lines into one."),
+        Flag::new(&["-E"], "Show the end of each line as $."),
+        Flag::new(&["-T"], "Show tabs as ^I."),
+        Flag::new(
+            &["-v"],
+            "Show control characters as ^X and bytes above 127 as M- followed by the \
+             character for the byte less 128.",
+        ),
+        Flag::new(&["-A"], "The same as -vET."),
+        Flag::new(&["-e"], "The same as -vE."),
+        Flag::new(&["-t"], "The same as -vT."),
+    ],
+};
+
+/// Concatenates and prints files to standard output.
+///
+/// A file named `-` is standard input, read until EOF, and if no files are
+/// provided that is all it reads.
+///
+/// Supports the following flags:
+/// - `-n`: Number all output lines.
+/// - `-b`: Number non-blank output lines. Overrides `-n`.
+/// - `-E`: Show the end of each line as `$`.
+/// - `-T`: Show tabs as `^I`.
+/// - `-v`: Show control characters as `^X` and bytes above 127 as `M-x`,
+///   leaving tabs and line ends alone.
+/// - `-A`: Show everything, the same as `-vET`.
+/// - `-e`, `-t`: The same as `-vE` and `-vT`.
+/// - `-s`: Squeeze runs of blank lines into one.
+///
+/// Numbering and squeezing run on across files, as if they were one.
+fn cat_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let mut filter = CatFilter::from_flags(&flags);
+    let mut result = CommandResult::new();
+    let args = if args.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        args
+    };
+    cat_files(&mut filter, &args, ctx.stdin, ctx.out.stdout, &mut result);
+    result
+}
+
+/// How much of a file `cat` reads at a time.
+const CAT_CHUNK: usize = 64 * 1024;
+
+/// Writes the files at `paths` through `filter` to `out`, a chunk at a time,
+/// so that a file need not fit in memory and output starts at once. `-` is
+/// `stdin`, and as a terminal gives a line at a time, typed lines are
+/// echoed as soon as they are entered.
+fn cat_files(
+    filter: &mut CatFilter,
+    paths: &[String],
+    stdin: &mut dyn Read,
+    out: &mut dyn Write,
+    result: &mut CommandResult,
+) {
+    let mut chunk = Vec::with_capacity(CAT_CHUNK);
+    for file_path in paths {
+        let mut input: Box<dyn BufRead> = if file_path == "-" {
+            Box::new(BufReader::with_capacity(CAT_CHUNK, &mut *stdin))
+        } else {
+            match File::open(file_path) {
+                Ok(file) => Box::new(BufReader::with_capacity(CAT_CHUNK, file)),
+                Err(e) => {
+                    result.push_error(CommandError::io(&format!("cat: {}", file_path), e));
+                    continue;
+                }
+            }
+        };
+        loop {
+            let data = match input.fill_buf() {
+                Ok([]) => break,
+                Ok(data) => data,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    result.push_error(CommandError::io(&format!("cat: {}", file_path), e));
+                    break;
+                }
+            };
+            chunk.clear();
+            filter.feed(data, &mut chunk);
+            let n = data.len();
+            input.consume(n);
+            // A closed or full output stops everything, not just this file
+            if let Err(e) = out.write_all(&chunk).and_then(|_| out.flush()) {
+                result.push_error(CommandError::io("cat", e));
+                return;
+            }
+        }
+    }
+}
+
+/// Which lines `cat` numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CatNumber {
+    None,
+    All,
+    NonBlank,
+}
+
+/// Rewrites `cat` output as its flags ask. The input may be split anywhere,
+/// even in the middle of a line, and lines carry on across files.
+struct CatFilter {
+    number: CatNumber,
+    show_ends: bool,
+    show_tabs: bool,
+    show_nonprinting: bool,
+    squeeze_blank: bool,
+    /// The number of the last line numbered.
+    line: u64,
+    /// Whether the next byte starts a line.
+    at_line_start: bool,
+    /// Whether the last line written was blank.
+    after_blank: bool,
+}
+
+impl CatFilter {
+    fn from_flags(flags: &[String]) -> Self {
+        let has = |names: &[&str]| flags.iter().any(|f| names.contains(&f.as_str()));
+        Self {
+            number: if has(&["-b"]) {
+                CatNumber::NonBlank
+            } else if has(&["-n"]) {
+                CatNumber::All
+            } else {
+                CatNumber::None
+            },
+            show_ends: has(&["-E", "-A", "-e"]),
+            show_tabs: has(&["-T", "-A", "-t"]),
+            show_nonprinting: has(&["-v", "-A", "-e", "-t"]),
+            squeeze_blank: has(&["-s"]),
+            line: 0,
+            at_line_start: true,
+            after_blank: false,
+        }
+    }
+
+    /// Whether the output is the input, unchanged.
+    fn is_plain(&self) -> bool {
+        self.number == CatNumber::None
+            && !self.show_ends
+            && !self.show_tabs
+            && !self.show_nonprinting
+            && !self.squeeze_blank
+    }
+
+    /// Appends `data`, rewritten, to `out`.
+    fn feed(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        if self.is_plain() {
+            out.extend_from_slice(data);
+            return;
+        }
+        for &b in data {
+            if self.at_line_start {
+                let blank = b == b'\n';
+                if blank && self.after_blank && self.squeeze_blank {
+                    continue;
+                }
+                self.after_blank = blank;
+            }
+            let numbered = match self.number {
+                CatNumber::None => false,
+                CatNumber::All => true,
+                CatNumber::NonBlank => b != b'\n',
+            };
+            if self.at_line_start && numbered {
+                self.line += 1;
+                out.extend_from_slice(format!("{:>6}\t", self.line).as_bytes());
+            }
+            self.at_line_start = b == b'\n';
+            match b {
+                b'\n' if self.show_ends => out.extend_from_slice(b"$\n"),
+                b'\t' if self.show_tabs => out.extend_from_slice(b"^I"),
+                b'\n' | b'\t' => out.push(b),
+                _ if self.show_nonprinting => push_nonprinting(b, out),
+                _ => out.push(b),
+            }
+        }
+    }
+}
+
+/// Appends `b` in the `^X` and `M-x` notation of `cat -v`.
+fn push_nonprinting(b: u8, out: &mut Vec<u8>) {
+    let b = if b >= 0x80 {
+        out.extend_from_slice(b"M-");
+        b - 0x80
+    } else {
+        b
+    };
+    match b {
+        0x00..=0x1f => out.extend_from_slice(&[b'^', b + 0x40]),
+        0x7f => out.extend_from_slice(b"^?"),
+        _ => out.push(b),
+    }
+}
+
+/// Resolves the final destination path for copy/move operations.
+///
+/// If the `dest_path` is a directory, the source's file name is appended to it.
+fn resolve_destination(src_path: &Path, dest_path: &Path) -> Result<std::path::PathBuf, String> {
+    if dest_path.is_dir() {
+        let file_name = src_path
+            .file_name()
+            .ok_or_else(|| format!("invalid source path: {}", src_path.display()))?;
+        Ok(dest_path.join(file_name))
+    } else {
+        Ok(dest_path.to_path_buf())
+    }
+}
+
+const CP_SPEC: Spec = Spec {
+    name: "cp",
+    summary: "copy files and directories",
+    forms: &["SOURCE DEST", "SOURCE... DIRECTORY"],
+    flags: &[
+        Flag::new(
+            &["-r", "-R", "--recursive"],
+            "Copy directories and everything in them.",
+        ),
+        Flag::new(
+            &["-p"],
+            "Preserve permissions and access and modification times, and owners when run \
+             as root.",
+        ),
+        Flag::new(&["-i"], "Ask before overwriting a file."),
+        Flag::new(&["-n"], "Never overwrite a file. Overrides -i."),
+        Flag::new(
+            &["-u"],
+            "Only copy over files that are older than their source.",
+        ),
+        Flag::new(&["-v"], "Print each file as it is copied."),
+        Flag::new(&["-L"], "Follow symbolic links in the sources."),
+        Flag::new(&["-P", "-d"], "Copy symbolic links as links."),
+        Flag::with_value(
+            &["--reflink"],
+            "[WHEN]",
+            "Share data blocks with the source where the filesystem allows: auto falls back \
+             to copying, always (the default for a bare --reflink) fails instead, never \
+             always copies.",
+        ),
+        Flag::with_value(
+            &["--sparse"],
+            "WHEN",
+            "Keep holes in sparse files (auto), also make holes of runs of zeros (always), \
+             or write every byte (never).",
+        ),
+        Flag::with_value(
+            &["--progress"],
+            "[WHEN]",
+            "Show a progress bar for large files on a terminal (auto), always, or never.",
+        ),
+    ],
+};
+
+/// Copies files and directories.
+///
+/// Supports multiple sources if the destination is a directory.
+///
+/// Supports the following flags:
+/// - `-r`, `-R`: Copy directories recursively. Symbolic links inside them,
+///   and those named as sources, are copied as links.
+/// - `-p`: Preserve permissions, timestamps, and, as root, ownership.
+/// - `-i`: Ask on the terminal before overwriting a file.
+/// - `-n`: Never overwrite existing files. Overrides `-i`.
+/// - `-v`: Print `'SOURCE' -> 'DEST'` for each file copied.
+/// - `-u`: Only replace files older than their source.
+/// - `-L`: Always follow symbolic links, copying the files they point to.
+/// - `-P`, `-d`: Never follow symbolic links, copying them as links.
+///
+/// - `--reflink[=WHEN]`: Share data blocks with the source on filesystems
+///   that allow it: `auto` (the default) where possible, `always` (the
+///   default for a bare `--reflink`) or fail, or `never`.
+/// - `--sparse=WHEN`: Keep the holes of sparse files (`auto`, the default),
+///   also make holes of blocks of zeros (`always`), or fill them (`never`).
+/// - `--progress[=WHEN]`: Show a progress bar with the rate and time left
+///   while each file is copied: `always` (the default for a bare
+///   `--progress`), `never`, or `auto`, the default, which shows it for
+///   files of [`CP_PROGRESS_MIN_SIZE`] or more when stderr is a terminal.
+///
+/// Without `-L` or `-P`, links named as sources are followed unless copying
+/// recursively. Of `-L` and `-P`, the last given wins.
+fn cp_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    use crate::copy::{Progress, Reflink, Sparse};
+    use std::io::IsTerminal;
+
+    let mut reflink = Reflink::Auto;
+    let mut sparse = Sparse::Auto;
+    // `None` is `auto`
+    let mut progress = None;
+    for flag in &flags {
+        match flag.as_str() {
+            "--progress" | "--progress=always" => progress = Some(true),
+            "--progress=never" => progress = Some(false),
+            "--progress=auto" => progress = None,
+            "--reflink" | "--reflink=always" => reflink = Reflink::Always,
+            "--reflink=auto" => reflink = Reflink::Auto,
+            "--reflink=never" => reflink = Reflink::Never,
+            "--sparse=always" => sparse = Sparse::Always,
+            "--sparse=auto" => sparse = Sparse::Auto,
+            "--sparse=never" => sparse = Sparse::Never,
+            f if f.starts_with("--reflink=")
+                || f.starts_with("--sparse")
+                || f.starts_with("--progress=") =>
+            {
+                let (name, value) = f.split_once('=').unwrap_or((f, ""));
+                return CommandResult::error(CommandError::Usage(format!(
+                    "cp: invalid argument '{}' for '{}'",
+                    value, name
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    if args.len() < 2 {
+        return CommandResult::error(CommandError::Usage(
+            "cp: missing destination file operand after source".to_string(),
+        ));
+    }
+
+    let mut result = CommandResult::new();
+    let (sources, destination) = args.split_at(args.len() - 1);
+    let dest_path = Path::new(&destination[0]);
+
+    if sources.len() > 1 && !dest_path.is_dir() {
+        return CommandResult::error(CommandError::Usage(format!(
+            "cp: target '{}' is not a directory",
+            destination[0]
+        )));
+    }
+
+    let opts = crate::copy::Options {
+        recursive: flags
+            .iter()
+            .any(|f| f == "-r" || f == "-R" || f == "--recursive"),
+        symlinks: flags
+            .iter()
+            .rev()
+            .find_map(|f| match f.as_str() {
+                "-L" => Some(crate::copy::Symlinks::Follow),
+                "-P" | "-d" => Some(crate::copy::Symlinks::Preserve),
+                _ => None,
+            })
+            .unwrap_or_default(),
+        preserve: flags.iter().any(|f| f == "-p"),
+        no_clobber: flags.iter().any(|f| f == "-n"),
+        update: flags.iter().any(|f| f == "-u"),
+        reflink,
+        sparse,
+        progress: match progress {
+            Some(true) => Some(0),
+            Some(false) => None,
+            None => io::stderr().is_terminal().then_some(CP_PROGRESS_MIN_SIZE),
+        }
+        .map(|min_size| Progress {
+            min_size,
+            line: cp_progress_line,
+        }),
+        confirm: flags.iter().any(|f| f == "-i").then_some(|dest: &Path| {
+            crate::terminal::confirm(&format!("cp: overwrite '{}'?", dest.display()))
+        }),
+    };
+    let mut report = crate::copy::Report::default();
+    for source_str in sources {
+        let src_path = Path::new(source_str);
+        match resolve_destination(src_path, dest_path) {
+            Ok(final_dest) => {
+                crate::copy::copy(src_path, &final_dest, &opts, ctx.out.stderr, &mut report)
+            }
+            Err(e) => report.errors.push(e),
+        }
+    }
+    if flags.iter().any(|f| f == "-v") {
+        for (src, dest) in &report.copied {
+            result.stdout.extend_from_slice(
+                format!("'{}' -> '{}'\n", src.display(), dest.display()).as_bytes(),
+            );
+        }
+    }
+    for error in report.errors {
+        result.append_stderr(&format!("cp: {}", error));
+    }
+
+    result
+}
+
+/// Files at least this large get a progress bar when `cp` writes to a
+/// terminal.
+const CP_PROGRESS_MIN_SIZE: u64 = 64 << 20;
+
+/// Formats the progress bar for `cp`: that of `fetch`, followed by the time
+/// left at the rate so far.
+fn cp_progress_line(copied: u64, total: u64, elapsed: std::time::Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let eta = if copied >= total {
+        "0:00".to_string()
+    } else if copied > 0 && secs > 0.0 {
+        let left = ((total - copied) as f64 * secs / copied as f64).ceil() as u64;
+        format!("{}:{:02}", left / 60, left % 60)
+    } else {
+        "-:--".to_string()
+    };
+    format!(
+        "{}ETA {}",
+        fetch_progress_line(copied, copied, Some(total), elapsed),
+        eta
+    )
+}
+
+const MV_SPEC: Spec = Spec {
+    name: "mv",
+    summary: "move (rename) files",
+    forms: &["SOURCE DEST", "SOURCE... DIRECTORY"],
+    flags: &[
+        Flag::new(&["-i"], "Ask before overwriting a file."),
+        Flag::new(&["-n"], "Never overwrite a file. Overrides -i."),
+        Flag::new(&["-v"], "Print each file as it is moved."),
+        Flag::new(
+            &["-b"],
+            "Back up files that would be overwritten, as $VERSION_CONTROL says.",
+        ),
+        Flag::with_value(
+            &["--backup"],
+            "[CONTROL]",
+            "Back up files that would be overwritten: simple (NAME~), numbered (NAME.~N~), \
+             or existing, which is numbered if numbered backups exist. Without CONTROL, \
+             $VERSION_CONTROL decides.",
+        ),
+    ],
+};
+
+/// Moves or renames files and directories.
+///
+/// Supports multiple sources if the destination is a directory.
+///
+/// Supports the following flags:
+/// - `-i`: Ask on the terminal before overwriting a file.
+/// - `-n`: Never overwrite existing files. Overrides `-i`.
+/// - `-v`: Print `renamed 'SOURCE' -> 'DEST'` for each file moved.
+/// - `-b`, `--backup[=CONTROL]`: Rename a file about to be overwritten
+///   rather than lose it, as described at [`MvBackup`]. `-b` and a bare
+///   `--backup` use `$VERSION_CONTROL`, or `existing` if it is unset.
+///
+/// Files that cannot be renamed because the destination is on another
+/// filesystem are copied there, keeping their metadata, and then removed.
+fn mv_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    // The last backup option wins
+    let mut backup = None;
+    for flag in &flags {
+        let control = match flag.as_str() {
+            "-b" | "--backup" => ctx.state.vars.get("VERSION_CONTROL").unwrap_or_default(),
+            f => match f.strip_prefix("--backup=") {
+                Some(control) => control.to_string(),
+                None => continue,
+            },
+        };
+        backup = match control.as_str() {
+            "" | "existing" | "nil" => Some(MvBackup::Existing),
+            "simple" | "never" => Some(MvBackup::Simple),
+            "numbered" | "t" => Some(MvBackup::Numbered),
+            "none" | "off" => None,
+            _ => {
+                return CommandResult::error(CommandError::Usage(format!(
+                    "mv: invalid argument '{}' for 'backup type'",
+                    control
+                )));
+            }
+        };
+    }
+
+    if args.len() < 2 {
+        return CommandResult::error(CommandError::Usage(
+            "mv: missing destination file operand after source".to_string(),
+        ));
+    }
+
+    let mut result = CommandResult::new();
+    let (sources, destination) = args.split_at(args.len() - 1);
+    let dest_path = Path::new(&destination[0]);
+
+    if sources.len() > 1 && !dest_path.is_dir() {
+        return CommandResult::error(CommandError::Usage(format!(
+            "mv: target '{}' is not a directory",
+            destination[0]
+        )));
+    }
+
+    let no_clobber = flags.iter().any(|f| f == "-n");
+    let interactive = flags.iter().any(|f| f == "-i");
+    let verbose = flags.iter().any(|f| f == "-v");
+    for source_str in sources {
+        let src_path = Path::new(source_str);
+        let final_dest = match resolve_destination(src_path, dest_path) {
+            Ok(final_dest) => final_dest,
+            Err(e) => {
+                result.append_stderr(&format!("mv: {}", e));
+                continue;
+            }
+        };
+        let exists = fs::symlink_metadata(&final_dest).is_ok();
+        if exists && no_clobber {
+            continue;
+        }
+        if exists
+            && interactive
+            && !crate::terminal::confirm(&format!("mv: overwrite '{}'?", final_dest.display()))
+        {
+            continue;
+        }
+        let mut backup_path = None;
+        if exists && let Some(backup) = backup {
+            let path = backup.path(&final_dest);
+            if let Err(e) = fs::rename(&final_dest, &path) {
+                result.append_stderr(&format!(
+                    "mv: cannot backup '{}': {}",
+                    final_dest.display(),
+                    e
+                ));
+                continue;
+            }
+            backup_path = Some(path);
+        }
+        let moved = match fs::rename(src_path, &final_dest) {
+            Ok(()) => true,
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                let mut report = crate::copy::Report::default();
+                crate::copy::move_by_copying(src_path, &final_dest, &mut report);
+                for error in &report.errors {
+                    result.append_stderr(&format!("mv: {}", error));
+                }
+                report.errors.is_empty()
+            }
+            Err(e) => {
+                result.append_stderr(&format!(
+                    "mv: cannot move '{}' to '{}': {}",
+                    source_str, destination[0], e
+                ));
+                false
+            }
+        };
+        if moved && verbose {
+            result.stdout.extend_from_slice(
+                format!("renamed '{}' -> '{}'", source_str, final_dest.display()).as_bytes(),
+            );
+            if let Some(path) = &backup_path {
+                result
+                    .stdout
+                    .extend_from_slice(format!(" (backup: '{}')", path.display()).as_bytes());
+            }
+            result.stdout.push(b'\n');
+        }
+    }
+
+    result
+}
+
+/// How `mv` names the backup of a file it is about to overwrite.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MvBackup {
+    /// `FILE~` (`simple`, `never`).
+    Simple,
+    /// `FILE.~N~`, numbered one past the highest so far (`numbered`, `t`).
+    Numbered,
+    /// Numbered if the file has numbered backups already, and simple
+    /// otherwise (`existing`, `nil`).
+    Existing,
+}
+
+impl MvBackup {
+    /// The path to move `path` to before it is overwritten.
+    fn path(self, path: &Path) -> std::path::PathBuf {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let prefix = format!("{}.~", name);
+        let parent = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let highest = fs::read_dir(parent)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let entry_name = entry.file_name().to_string_lossy().into_owned();
+                entry_name
+                    .strip_prefix(&prefix)?
+                    .strip_suffix('~')?
+                    .parse::<u64>()
+                    .ok()
+            })
+            .max();
+
+        match (self, highest) {
+            (Self::Simple, _) | (Self::Existing, None) => path.with_file_name(format!("{}~", name)),
+            (_, highest) => path.with_file_name(format!("{}{}~", prefix, highest.unwrap_or(0) + 1)),
+        }
+    }
+}
+
+const RM_SPEC: Spec = Spec {
+    name: "rm",
+    summary: "remove files or directories",
+    forms: &["FILE..."],
+    flags: &[
+        Flag::new(&["-r", "-R"], "Remove directories and everything in them."),
+        Flag::new(&["-d"], "Remove empty directories."),
+        Flag::new(&["-f"], "Ignore missing files and never ask."),
+        Flag::new(&["-i"], "Ask before each removal."),
+        Flag::new(
+            &["-I"],
+            "Ask once before removing more than three files, or recursively.",
+        ),
+        Flag::new(&["-v"], "Print each file as it is removed."),
+        Flag::new(
+            &["--no-preserve-root"],
+            "Allow / to be removed recursively.",
+        ),
+        Flag::new(
+            &["--trash"],
+            "Move files to the trash instead of removing them.",
+        ),
+        Flag::new(
+            &["--no-trash"],
+            "Remove files for good, even when RM_TRASH is set.",
+        ),
+    ],
+};
+
+/// Removes files or directories.
+///
+/// Supports the following flags:
+/// - `-r`, `-R`: Remove directories and their contents recursively.
+/// - `-f`: Ignore files that do not exist, so that removing them again
+///   succeeds, and never ask.
+/// - `-i`: Ask on the terminal before removing each file, and before
+///   descending into each directory.
+/// - `-I`: Ask once, before removing more than three files or removing
+///   anything recursively.
+/// - `-v`: Print `removed 'FILE'` or `removed directory 'DIR'` for each
+///   file and directory removed, including those inside directories.
+/// - `-d`: Remove empty directories without `-r`.
+/// - `--no-preserve-root`: Allow `/` to be removed recursively.
+/// - `--trash`: Move files to the trash, from which `restore` can bring them
+///   back, instead of removing them. Setting `RM_TRASH=1` in the shell
+///   makes this the default, and `--no-trash` turns it off again.
+///
+/// `.` and `..` are never removed, and removing a directory that holds the
+/// current directory prints a warning first.
+///
+/// Of `-f`, `-i`, and `-I`, the last given wins.
+fn rm_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let mut opts = RmOptions {
+        recursive: flags.iter().any(|f| f == "-r" || f == "-R"),
+        force: false,
+        ask_each: false,
+        verbose: flags.iter().any(|f| f == "-v"),
+    };
+    let remove_empty = flags.iter().any(|f| f == "-d");
+    let mut ask_once = false;
+    for flag in &flags {
+        match flag.as_str() {
+            "-f" => (opts.force, opts.ask_each, ask_once) = (true, false, false),
+            "-i" => (opts.force, opts.ask_each, ask_once) = (false, true, false),
+            "-I" => (opts.force, opts.ask_each, ask_once) = (false, false, true),
+            _ => {}
+        }
+    }
+    let mut result = CommandResult::new();
+
+    if ask_once && (args.len() > 3 || opts.recursive) {
+        let count = match args.len() {
+            1 => "1 argument".to_string(),
+            n => format!("{} arguments", n),
+        };
+        let how = if opts.recursive { " recursively" } else { "" };
+        if !crate::terminal::confirm(&format!("rm: remove {}{}?", count, how)) {
+            return result;
+        }
+    }
+
+    let preserve_root = !flags.iter().any(|f| f == "--no-preserve-root");
+    let to_trash = match flags
+        .iter()
+        .rev()
+        .find(|f| *f == "--trash" || *f == "--no-trash")
+    {
+        Some(flag) => flag == "--trash",
+        None => ctx
+            .state
+            .vars
+            .get("RM_TRASH")
+            .is_some_and(|v| !v.is_empty() && v != "0"),
+    };
+    let trash = to_trash.then(|| crate::trash::Trash::home(&ctx.state.vars));
+    for path_str in args {
+        let path = Path::new(&path_str);
+        let name = path_str.trim_end_matches('/').rsplit('/').next();
+        if matches!(name, Some("." | "..")) {
+            result.append_stderr(&format!(
+                "rm: refusing to remove '.' or '..' directory: skipping '{}'",
+                path_str
+            ));
+            continue;
+        }
+        let real = fs::canonicalize(path).ok();
+        if opts.recursive {
+            if preserve_root && real.as_deref() == Some(Path::new("/")) {
+                result.append_stderr(&format!(
+                    "rm: it is dangerous to operate recursively on '{}'\n\
+                     rm: use --no-preserve-root to override this failsafe",
+                    path_str
+                ));
+                continue;
+            }
+            if let Some(real) = &real
+                && env::current_dir().is_ok_and(|cwd| cwd.starts_with(real))
+            {
+                result.append_stderr(&format!(
+                    "rm: warning: '{}' contains the current directory",
+                    path_str
+                ));
+            }
+        }
+        // Links are removed, not what they point to
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) if opts.force => continue,
+            Err(_) => {
+                result.append_stderr(&format!(
+                    "rm: cannot remove '{}': No such file or directory",
+                    path_str
+                ));
+                continue;
+            }
+        };
+        if metadata.is_dir() && !opts.recursive {
+            if !remove_empty {
+                result.append_stderr(&format!("rm: cannot remove '{}': Is a directory", path_str));
+            } else if trash.is_none() {
+                rm_empty_dir(path, &opts, &mut result);
+            } else if fs::read_dir(path).is_ok_and(|mut entries| entries.next().is_some()) {
+                result.append_stderr(&format!(
+                    "rm: cannot remove '{}': Directory not empty",
+                    path_str
+                ));
+            } else {
+                rm_to_trash(trash.as_ref().unwrap(), path, &metadata, &opts, &mut result);
+            }
+            continue;
+        }
+        match &trash {
+            Some(trash) => rm_to_trash(trash, path, &metadata, &opts, &mut result),
+            None => {
+                rm_path(path, &metadata, &opts, &mut result);
+            }
+        }
+    }
+
+    result
+}
+
+/// Moves `path`, which `metadata` describes, to the trash whole.
+fn rm_to_trash(
+    trash: &crate::trash::Trash,
+    path: &Path,
+    metadata: &fs::Metadata,
+    opts: &RmOptions,
+    result: &mut CommandResult,
+) {
+    if opts.ask_each
+        && !crate::terminal::confirm(&format!(
+            "rm: move {} '{}' to the trash?",
+            rm_file_kind(metadata),
+            path.display()
+        ))
+    {
+        return;
+    }
+    match trash.put(path) {
+        Ok(_) if opts.verbose => result
+            .stdout
+            .extend_from_slice(format!("trashed '{}'\n", path.display()).as_bytes()),
+        Ok(_) => {}
+        Err(e) => result.append_stderr(&format!(
+            "rm: cannot move '{}' to the trash: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
+/// Lists the files in the trash, oldest first, with the time each was
+/// trashed and where it came from.
+fn trash_list_callback(
+    _flags: Vec<String>,
+    _args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    match crate::trash::Trash::home(&ctx.state.vars).list() {
+        Ok(entries) => CommandResult::with_stdout(
+            entries
+                .iter()
+                .map(|entry| {
+                    format!(
+                        "{} {}\n",
+                        entry.deleted.format("%Y-%m-%d %H:%M:%S"),
+                        entry.path.display()
+                    )
+                })
+                .collect::<String>(),
+        ),
+        Err(e) => CommandResult::with_stderr(format!("trash-list: {}", e)),
+    }
+}
+
+/// Moves files in the trash back to where they were removed from.
+///
+/// Each argument is the path a file had, or its name in the trash. Of
+/// several files trashed from the same path, the latest is restored.
+fn restore_callback(
+    _flags: Vec<String>,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    let trash = crate::trash::Trash::home(&ctx.state.vars);
+    let mut result = CommandResult::new();
+    let entries = match trash.list() {
+        Ok(entries) => entries,
+        Err(e) => return CommandResult::with_stderr(format!("restore: {}", e)),
+    };
+    let cwd = env::current_dir().unwrap_or_default();
+    for arg in &args {
+        let path = cwd.join(arg);
+        let entry = entries
+            .iter()
+            .rev()
+            .find(|entry| entry.path == path)
+            .or_else(|| entries.iter().find(|entry| entry.name == *arg));
+        match entry {
+            Some(entry) => {
+                if let Err(e) = trash.restore(entry) {
+                    result.append_stderr(&format!(
+                        "restore: cannot restore '{}': {}",
+                        entry.path.display(),
+                        e
+                    ));
+                }
+            }
+            None => result.append_stderr(&format!("restore: '{}' is not in the trash", arg)),
+        }
+    }
+    result
+}
+
+/// What `rm` was asked to do.
+struct RmOptions {
+    recursive: bool,
+    force: bool,
+    /// Ask before removing each file.
+    ask_each: bool,
+    verbose: bool,
+}
+
+/// Removes `path`, which `metadata` describes, with everything in it if it
+/// is a directory. Returns whether it is gone.
+fn rm_path(
+    path: &Path,
+    metadata: &fs::Metadata,
+    opts: &RmOptions,
+    result: &mut CommandResult,
+) -> bool {
+    let ask = |question: String| !opts.ask_each || crate::terminal::confirm(&question);
+
+    if metadata.is_dir() {
+        if !ask(format!("rm: descend into directory '{}'?", path.display())) {
+            return false;
+        }
+        let entries = match fs::read_dir(path) {
+            Ok(entries) => entries,
+            Err(e) => {
+                result.append_stderr(&format!("rm: cannot remove '{}': {}", path.display(), e));
+                return false;
+            }
+        };
+        let mut emptied = true;
+        for entry in entries {
+            // `DirEntry::metadata` does not follow symbolic links
+            match entry.and_then(|entry| Ok((entry.path(), entry.metadata()?))) {
+                Ok((child, metadata)) => emptied &= rm_path(&child, &metadata, opts, result),
+                Err(e) => {
+                    result.append_stderr(&format!("rm: cannot remove '{}': {}", path.display(), e));
+                    emptied = false;
+                }
+            }
+        }
+        // Anything kept has been reported or was kept on purpose
+        return emptied && rm_empty_dir(path, opts, result);
+    }
+
+    if !ask(format!(
+        "rm: remove {} '{}'?",
+        rm_file_kind(metadata),
+        path.display()
+    )) {
+        return false;
+    }
+    match fs::remove_file(path) {
+        Ok(()) => {
+            if opts.verbose {
+                result
+                    .stdout
+                    .extend_from_slice(format!("removed '{}'\n", path.display()).as_bytes());
+            }
+            true
+        }
+        Err(e) => {
+            result.append_stderr(&format!("rm: cannot remove '{}': {}", path.display(), e));
+            false
+        }
+    }
+}
+
+/// Removes the directory `path`, which should be empty by now. Returns
+/// whether it is gone.
+fn rm_empty_dir(path: &Path, opts: &RmOptions, result: &mut CommandResult) -> bool {
+    if opts.ask_each
+        && !crate::terminal::confirm(&format!("rm: remove directory '{}'?", path.display()))
+    {
+        return false;
+    }
+    match fs::remove_dir(path) {
+        Ok(()) => {
+            if opts.verbose {
+                result.stdout.extend_from_slice(
+                    format!("removed directory '{}'\n", path.display()).as_bytes(),
+                );
+            }
+            true
+        }
+        Err(e) => {
+            let reason = match e.kind() {
+                io::ErrorKind::DirectoryNotEmpty => "Directory not empty".to_string(),
+                _ => e.to_string(),
+            };
+            result.append_stderr(&format!(
+                "rm: cannot remove '{}': {}",
+                path.display(),
+                reason
+            ));
+            false
+        }
+    }
+}
+
+/// Describes a file for `rm -i`, as in `remove regular empty file 'a'?`.
+fn rm_file_kind(metadata: &fs::Metadata) -> &'static str {
+    match FileKind::of(metadata) {
+        FileKind::File | FileKind::Executable if metadata.len() == 0 => "regular empty file",
+        FileKind::File | FileKind::Executable => "regular file",
+        FileKind::Dir => "directory",
+        FileKind::Symlink => "symbolic link",
+        FileKind::Pipe => "fifo",
+        FileKind::Socket => "socket",
+        FileKind::BlockDevice => "block special file",
+        FileKind::CharDevice => "character special file",
+    }
+}
+
+const LS_SPEC: Spec = Spec {
+    name: "ls",
+    summary: "list directory contents",
+    forms: &["[FILE...]"],
+    flags: &[
+        Flag::new(&["-a"], "Include entries whose names start with a dot."),
+        Flag::new(
+            &["-l"],
+            "Use the long format: mode, links, owner, group, size, date, and name.",
+        ),
+        Flag::new(&["-1"], "List one entry per line."),
+        Flag::new(&["-h"], "With -l, show sizes with units, such as 4.0K."),
+        Flag::new(&["-i"], "Show inode numbers."),
+        Flag::new(&["-n"], "Like -l, with numeric owner and group ids."),
+        Flag::new(
+            &["-F"],
+            "Mark directories with /, executables with *, links with @, pipes with |, and \
+             sockets with =.",
+        ),
+        Flag::new(&["-R"], "List subdirectories recursively."),
+        Flag::new(&["-t"], "Sort by modification time, newest first."),
+        Flag::new(&["-S"], "Sort by size, largest first."),
+        Flag::new(&["-X"], "Sort by extension."),
+        Flag::new(&["-U"], "Do not sort."),
+        Flag::new(&["-r"], "Reverse the sort order."),
+        Flag::with_value(
+            &["--sort"],
+            "WORD",
+            "Sort by name, time, size, extension, or none.",
+        ),
+        Flag::new(
+            &["--group-directories-first"],
+            "List directories before other files.",
+        ),
+        Flag::with_value(
+            &["--color"],
+            "[WHEN]",
+            "Color names always, never, or on a terminal (auto). Colors come from \
+             $LS_COLORS if set.",
+        ),
+    ],
+};
+
+/// Lists directory contents.
+///
+/// Supports the following flags:
+/// - `-a`: List all entries, including those starting with `.`.
+/// - `-l`: Use a long listing format.
+/// - `-F`: Append a character to each entry indicating its type: `/` for
+///   directories, `*` for executables, `@` for symbolic links, `|` for
+///   pipes, and `=` for sockets.
+/// - `-R`: List subdirectories recursively, each under a `DIR:` header.
+/// - `-t`: Sort by modification time, newest first.
+/// - `-S`: Sort by size, largest first.
+/// - `-X`: Sort by extension, names without one first.
+/// - `-U`: Do not sort, listing entries in directory order.
+/// - `--sort=WORD`: Sort by `name`, `time`, `size`, `extension`, or `none`.
+/// - `--group-directories-first`: List directories before other files.
+/// - `-r`: Reverse the sort order.
+/// - `-h`: With `-l`, show sizes with units, such as `4.0K` and `1.2M`.
+/// - `-1`: List one entry per line, as is done when not on a terminal.
+/// - `-i`: Show each entry's inode number.
+/// - `-n`: Use the long format, with the owner and group as numeric ids
+///   instead of names.
+///
+/// - `--color[=WHEN]`: Color names by file type `always` (the default for
+///   a bare `--color`), `never`, or `auto`, which colors only on a
+///   terminal and is the default. Colors come from `LS_COLORS` if set.
+///
+/// Files named as arguments are listed as they are, before the contents of
+/// any directories named. On a terminal, short listings are laid out in
+/// columns that fit its width. Otherwise they have one entry per line.
+fn ls_callback(flags: Vec<String>, mut args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    use std::io::IsTerminal;
+
+    let out = &mut ctx.out;
+    // `None` is `auto`: color when writing to a terminal
+    let mut color = None;
+    for flag in &flags {
+        color = match flag.as_str() {
+            "--color" | "--color=always" | "--color=yes" | "--color=force" => Some(true),
+            "--color=never" | "--color=no" | "--color=none" => Some(false),
+            "--color=auto" | "--color=tty" | "--color=if-tty" => None,
+            f if f.starts_with("--color=") => {
+                return CommandResult::error(CommandError::Usage(format!(
+                    "ls: invalid argument '{}' for '--color'",
+                    &f["--color=".len()..]
+                )));
+            }
+            _ => continue,
+        };
+    }
+    let color = color.unwrap_or_else(|| io::stdout().is_terminal());
+
+    // The last sort option wins
+    let mut sort = LsSort::Name;
+    for flag in &flags {
+        sort = match flag.as_str() {
+            "-t" | "--sort=time" => LsSort::Time,
+            "-S" | "--sort=size" => LsSort::Size,
+            "-X" | "--sort=extension" => LsSort::Extension,
+            "-U" | "--sort=none" => LsSort::Unsorted,
+            "--sort=name" => LsSort::Name,
+            f if f.starts_with("--sort=") => {
+                return CommandResult::error(CommandError::Usage(format!(
+                    "ls: invalid argument '{}' for '--sort'",
+                    &f["--sort=".len()..]
+                )));
+            }
+            _ => continue,
+        };
+    }
+
+    let opts = LsOptions {
+        all: flags.iter().any(|f| f == "-a"),
+        // `-n` is a long listing with numeric ids
+        long: flags.iter().any(|f| f == "-l" || f == "-n"),
+        inode: flags.iter().any(|f| f == "-i"),
+        numeric: flags.iter().any(|f| f == "-n"),
+        classify: flags.iter().any(|f| f == "-F"),
+        recursive: flags.iter().any(|f| f == "-R"),
+        sort,
+        reverse: flags.iter().any(|f| f == "-r"),
+        dirs_first: flags.iter().any(|f| f == "--group-directories-first"),
+        human: flags.iter().any(|f| f == "-h" || f == "--human-readable"),
+        width: (io::stdout().is_terminal() && !flags.iter().any(|f| f == "-1"))
+            .then(|| crate::terminal::size(&ctx.state.vars).0),
+        palette: color.then(|| crate::ls_colors::Palette::new(ctx.state.vars.get("LS_COLORS"))),
+        owners: Default::default(),
+    };
+
+    if args.is_empty() {
+        args.push(".".to_string());
+    }
+
+    // Like GNU `ls`, files named on the command line are listed together
+    // first, then each directory. Each directory is written as soon as it
+    // has been read, so a long recursive listing shows up as it goes.
+    let mut listing = LsListing {
+        out,
+        started: false,
+        status: 0,
+        ancestors: std::collections::HashSet::new(),
+    };
+    let mut files = Vec::new();
+    let mut dirs = Vec::new();
+    for path_str in &args {
+        let path = Path::new(path_str);
+        let metadata = match fs::symlink_metadata(path) {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                let context = format!("ls: cannot access '{}'", path_str);
+                listing.error(CommandError::io(&context, e));
+                continue;
+            }
+        };
+        // Links named on the command line are followed, except to show
+        // the link itself in a long listing
+        let followed = if metadata.file_type().is_symlink() && !opts.long {
+            fs::metadata(path).ok()
+        } else {
+            None
+        };
+        let entry = match followed {
+            Some(target) => LsEntry::new(path_str.clone(), path, target),
+            None => LsEntry::new(path_str.clone(), path, metadata),
+        };
+        if entry.kind == FileKind::Dir {
+            dirs.push(entry);
+        } else {
+            files.push(entry);
+        }
+    }
+    files.sort_by(|a, b| opts.compare(a, b));
+    dirs.sort_by(|a, b| opts.compare(a, b));
+
+    let written = listing
+        .write(&format_ls_entries(&files, &opts))
+        .and_then(|()| {
+            let headers = args.len() > 1 || opts.recursive;
+            dirs.iter()
+                .try_for_each(|dir| ls_directory(&dir.name, &opts, headers, &mut listing))
+        });
+    match written {
+        Ok(()) => CommandResult {
+            status: listing.status,
+            ..CommandResult::new()
+        },
+        Err(e) => CommandResult::error(CommandError::io("ls: write error", e)),
+    }
+}
+
+/// Where `ls` is writing its listing to.
+struct LsListing<'a, 'b> {
+    out: &'a mut Output<'b>,
+    /// Whether anything has been listed yet.
+    started: bool,
+    /// The exit status decided by the first error reported.
+    status: i32,
+    /// The directories being listed, from the one named on the command
+    /// line down to the current one.
+    ancestors: std::collections::HashSet<std::path::PathBuf>,
+}
+
+impl LsListing<'_, '_> {
+    fn write(&mut self, text: &str) -> io::Result<()> {
+        if !text.is_empty() {
+            self.started = true;
+            self.out.stdout.write_all(text.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reports an error with one file without stopping the listing.
+    fn error(&mut self, error: CommandError) {
+        let _ = self.out.error(&error.to_string());
+        if self.status == 0 {
+            self.status = error.status();
+        }
+    }
+}
+
+/// The `ls` flags that shape each directory's listing.
+struct LsOptions {
+    all: bool,
+    long: bool,
+    inode: bool,
+    numeric: bool,
+    classify: bool,
+    recursive: bool,
+    sort: LsSort,
+    reverse: bool,
+    /// Whether to list directories, and links to them, before other files.
+    dirs_first: bool,
+    human: bool,
+    /// The terminal width to fit short listings to, or `None` to list one
+    /// entry per line.
+    width: Option<usize>,
+    /// The colors to paint names with, if coloring.
+    palette: Option<crate::ls_colors::Palette>,
+    /// Owner and group names looked up so far.
+    owners: std::cell::RefCell<OwnerNames>,
+}
+
+/// User and group names by id, each looked up once. Ids without a name
+/// are shown as numbers.
+#[derive(Default)]
+struct OwnerNames {
+    users: HashMap<u32, String>,
+    groups: HashMap<u32, String>,
+}
+
+impl OwnerNames {
+    fn user(&mut self, uid: u32) -> String {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| {
+                #[cfg(unix)]
+                if let Some(name) = crate::process::user_name(uid) {
+                    return name;
+                }
+                uid.to_string()
+            })
+            .clone()
+    }
+
+    fn group(&mut self, gid: u32) -> String {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| {
+                #[cfg(unix)]
+                if let Some(name) = crate::process::group_name(gid) {
+                    return name;
+                }
+                gid.to_string()
+            })
+            .clone()
+    }
+}
+
+/// The order `ls` lists entries in.
+#[derive(Clone, Copy, PartialEq)]
+enum LsSort {
+    Name,
+    /// Newest first (`-t`).
+    Time,
+    /// Largest first (`-S`).
+    Size,
+    /// By the part of the name after the last `.`, names without one
+    /// first (`-X`).
+    Extension,
+    /// In the order the directory lists them (`-U`).
+    Unsorted,
+}
+
+/// A directory entry being listed by `ls`.
+struct LsEntry {
+    name: String,
+    /// What the entry itself is. A link to a directory is a link.
+    kind: FileKind,
+    /// For a symbolic link, what it points to, or `None` if it is broken.
+    target: Option<FileKind>,
+    /// For a symbolic link, the path it holds.
+    link: Option<String>,
+    /// The entry's own metadata, not that of any file it links to.
+    metadata: fs::Metadata,
+}
+
+impl LsEntry {
+    /// Describes the file at `path`, to be listed as `name`. `metadata`
+    /// must not have followed a link at `path`.
+    fn new(name: String, path: &Path, metadata: fs::Metadata) -> Self {
+        let kind = FileKind::of(&metadata);
+        let (target, link) = if kind == FileKind::Symlink {
+            (
+                fs::metadata(path).ok().map(|target| FileKind::of(&target)),
+                fs::read_link(path)
+                    .ok()
+                    .map(|link| link.to_string_lossy().into_owned()),
+            )
+        } else {
+            (None, None)
+        };
+        Self {
+            name,
+            kind,
+            target,
+            link,
+            metadata,
+        }
+    }
+}
+
+impl LsOptions {
+    /// Orders two entries for listing. Entries that tie on time, size, or
+    /// extension are ordered by name, and `-r` reverses the order. With
+    /// `--group-directories-first`, directories come first either way.
+    fn compare(&self, a: &LsEntry, b: &LsEntry) -> std::cmp::Ordering {
+        use std::cmp::Ordering;
+
+        let is_dir =
+            |entry: &LsEntry| entry.kind == FileKind::Dir || entry.target == Some(FileKind::Dir);
+        let group = if self.dirs_first {
+            is_dir(b).cmp(&is_dir(a))
+        } else {
+            Ordering::Equal
+        };
+        let extension = |entry: &LsEntry| {
+            let name = &entry.name;
+            name.rfind('.')
+                .map_or("", |dot| &name[dot + 1..])
+                .to_string()
+        };
+        let by_key = match self.sort {
+            LsSort::Name => Ordering::Equal,
+            LsSort::Time => b.metadata.modified().ok().cmp(&a.metadata.modified().ok()),
+            LsSort::Size => b.metadata.len().cmp(&a.metadata.len()),
+            LsSort::Extension => extension(a).cmp(&extension(b)),
+            LsSort::Unsorted => return group,
+        };
+        let order = by_key.then_with(|| a.name.cmp(&b.name));
+        group.then(if self.reverse { order.reverse() } else { order })
+    }
+}
+
+/// Lists one directory for `ls`, under a `path:` header if `header` is
+/// set, then its subdirectories if listing recursively.
+///
+/// Symbolic links to directories are listed but not descended into, and
+/// the listing's `ancestors` hold the directories being listed further up,
+/// so that a loop made some other way (such as a bind mount) ends instead
+/// of recursing forever. Errors are reported per directory and do not stop
+/// the rest; only failing to write the listing does.
+fn ls_directory(
+    path_str: &str,
+    opts: &LsOptions,
+    header: bool,
+    listing: &mut LsListing,
+) -> io::Result<()> {
+    if header {
+        let blank = if listing.started { "\n" } else { "" };
+        listing.write(&format!("{}{}:\n", blank, path_str))?;
+    }
+
+    let entries = match fs::read_dir(path_str) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let context = format!("ls: cannot access '{}'", path_str);
+            listing.error(CommandError::io(&context, e));
+            return Ok(());
+        }
+        Err(e) => {
+            let context = format!("ls: cannot open directory '{}'", path_str);
+            listing.error(CommandError::io(&context, e));
+            return Ok(());
+        }
+    };
+
+    let real = fs::canonicalize(path_str).ok();
+    if let Some(real) = &real
+        && !listing.ancestors.insert(real.clone())
+    {
+        listing.error(CommandError::Failed(format!(
+            "ls: {}: not listing already-listed directory",
+            path_str
+        )));
+        return Ok(());
+    }
+
+    let mut entry_list = Vec::new();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                listing.error(CommandError::io("ls", e));
+                continue;
+            }
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if !opts.all && name.starts_with('.') {
+            continue;
+        }
+        // `DirEntry::metadata` does not follow symbolic links
+        match entry.metadata() {
+            Ok(metadata) => entry_list.push(LsEntry::new(name, &entry.path(), metadata)),
+            Err(e) => listing.error(CommandError::io("ls", e)),
+        }
+    }
+
+    entry_list.sort_by(|a, b| opts.compare(a, b));
+
+    let subdirs: Vec<String> = entry_list
+        .iter()
+        .filter(|entry| opts.recursive && entry.kind == FileKind::Dir)
+        .map(|entry| entry.name.clone())
+        .collect();
+    if opts.long {
+        // Space used in 1K blocks, like GNU `ls`
+        let kib: u64 = entry_list
+            .iter()
+            .map(|entry| disk_blocks(&entry.metadata).div_ceil(2))
+            .sum();
+        let total = if opts.human {
+            format_human_size(kib * 1024)
+        } else {
+            kib.to_string()
+        };
+        listing.write(&format!("total {}\n", total))?;
+    }
+    listing.write(&format_ls_entries(&entry_list, opts))?;
+
+    for name in subdirs {
+        let sub = if path_str.ends_with('/') {
+            format!("{}{}", path_str, name)
+        } else {
+            format!("{}/{}", path_str, name)
+        };
+        ls_directory(&sub, opts, true, listing)?;
+    }
+    if let Some(real) = &real {
+        listing.ancestors.remove(real);
+    }
+    Ok(())
+}
+
+/// Formats entries for `ls` in the order given: in columns or one per
+/// line, or as a long listing with its columns aligned.
+fn format_ls_entries(entries: &[LsEntry], opts: &LsOptions) -> String {
+    let inodes: Vec<String> = entries
+        .iter()
+        .map(|entry| inode(&entry.metadata).to_string())
+        .collect();
+    let inode_width = inodes.iter().map(String::len).max().unwrap_or(0);
+
+    let mut names = Vec::new();
+    let mut widths = Vec::new();
+    for (entry, inode) in entries.iter().zip(&inodes) {
+        let mut width = crate::unicode::str_width(&entry.name);
+        let mut name = match &opts.palette {
+            Some(palette) => palette.paint(&entry.name, entry.kind, entry.target),
+            None => entry.name.clone(),
+        };
+        if opts.long
+            && let Some(link) = &entry.link
+        {
+            // A link shows its target, classified in place of the link
+            let target = match (&opts.palette, entry.target) {
+                (Some(palette), Some(kind)) => palette.paint(link, kind, None),
+                (Some(palette), None) => palette.paint(link, FileKind::Symlink, None),
+                (None, _) => link.clone(),
+            };
+            name = format!("{} -> {}", name, target);
+            if opts.classify
+                && let Some(indicator) = entry.target.and_then(type_indicator)
+            {
+                name.push(indicator);
+            }
+        } else if opts.classify
+            && let Some(indicator) = type_indicator(entry.kind)
+        {
+            name.push(indicator);
+            width += 1;
+        }
+        if opts.inode && !opts.long {
+            name = format!("{:>w$} {}", inode, name, w = inode_width);
+            width += inode_width + 1;
+        }
+        names.push(name);
+        widths.push(width);
+    }
+
+    if !opts.long {
+        return match opts.width {
+            Some(width) => format_columns(&names, &widths, width),
+            None => names.iter().map(|name| format!("{}\n", name)).collect(),
+        };
+    }
+
+    // Each row's cells, and whether each column is aligned to the left
+    let mut left_aligned = Vec::new();
+    let rows: Vec<Vec<String>> = entries
+        .iter()
+        .zip(inodes)
+        .map(|(entry, inode)| {
+            let metadata = &entry.metadata;
+            let mut cells = Vec::new();
+            let mut cell = |text: String, left: bool| {
+                if left_aligned.len() <= cells.len() {
+                    left_aligned.push(left);
+                }
+                cells.push(text);
+            };
+            if opts.inode {
+                cell(inode, false);
+            }
+            cell(parse_permissions(metadata), true);
+            cell(link_count(metadata).to_string(), false);
+            let (uid, gid) = owner_ids(metadata);
+            if opts.numeric {
+                cell(uid.to_string(), true);
+                cell(gid.to_string(), true);
+            } else {
+                let mut owners = opts.owners.borrow_mut();
+                cell(owners.user(uid), true);
+                cell(owners.group(gid), true);
+            }
+            cell(
+                if opts.human {
+                    format_human_size(metadata.len())
+                } else {
+                    metadata.len().to_string()
+                },
+                false,
+            );
+            let modified: DateTime<Local> = metadata.modified().unwrap().into();
+            cell(modified.format("%b %d %H:%M").to_string(), true);
+            cells
+        })
+        .collect();
+
+    let mut col_widths = vec![0; left_aligned.len()];
+    for row in &rows {
+        for (width, text) in col_widths.iter_mut().zip(row) {
+            *width = (*width).max(text.len());
+        }
+    }
+    let mut out = String::new();
+    for (row, name) in rows.iter().zip(names) {
+        for ((text, &width), &left) in row.iter().zip(&col_widths).zip(&left_aligned) {
+            if left {
+                out.push_str(&format!("{:<w$} ", text, w = width));
+            } else {
+                out.push_str(&format!("{:>w$} ", text, w = width));
+            }
+        }
+        out.push_str(&name);
+        out.push('\n');
+    }
+    out
+}
+
+/// Lays names out in columns that fit in `width`, filled top to bottom and
+/// then left to right like GNU `ls`, with as many columns as fit.
+///
+/// `widths` holds the width of each name on screen, which leaves out any
+/// color escapes in it.
+fn format_columns(names: &[String], widths: &[usize], width: usize) -> String {
+    if names.is_empty() {
+        return String::new();
+    }
+
+    // Each column takes at least one character and a two-space gap
+    let most = names.len().min(width / 3 + 1).max(1);
+    let mut layout = (names.len(), vec![widths.iter().copied().max().unwrap_or(0)]);
+    for cols in (2..=most).rev() {
+        let rows = names.len().div_ceil(cols);
+        let col_widths: Vec<usize> = widths
+            .chunks(rows)
+            .map(|col| col.iter().copied().max().unwrap_or(0))
+            .collect();
+        if col_widths.iter().sum::<usize>() + 2 * (col_widths.len() - 1) <= width {
+            layout = (rows, col_widths);
+            break;
+        }
+    }
+
+    let (rows, col_widths) = layout;
+    let mut out = String::new();
+    for row in 0..rows {
+        let mut col = 0;
+        while let Some(name) = names.get(col * rows + row) {
+            out.push_str(name);
+            if names.get((col + 1) * rows + row).is_some() {
+                out.push_str(&" ".repeat(col_widths[col] - widths[col * rows + row] + 2));
+            }
+            col += 1;
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// The character `ls -F` appends to a name of the given kind, if any.
+fn type_indicator(kind: FileKind) -> Option<char> {
+    match kind {
+        FileKind::Dir => Some('/'),
+        FileKind::Executable => Some('*'),
+        FileKind::Symlink => Some('@'),
+        FileKind::Pipe => Some('|'),
+        FileKind::Socket => Some('='),
+        FileKind::File | FileKind::BlockDevice | FileKind::CharDevice => None,
+    }
+}
+
+/// The inode number of a file, or 0 on platforms without them.
+fn inode(metadata: &std::fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::MetadataExt::ino(metadata)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        0
+    }
+}
+
+/// The number of hard links to a file, or 1 on platforms without them.
+fn link_count(metadata: &std::fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::MetadataExt::nlink(metadata)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        1
+    }
+}
+
+/// The number of 512-byte blocks a file takes up on disk. Where that is
+/// not known, its size rounded up to whole blocks.
+fn disk_blocks(metadata: &std::fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::MetadataExt::blocks(metadata)
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len().div_ceil(512)
+    }
+}
+
+/// The ids of a file's owner and group, or 0 on platforms without them.
+fn owner_ids(metadata: &std::fs::Metadata) -> (u32, u32) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        (metadata.uid(), metadata.gid())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        (0, 0)
+    }
+}
+
+/// Generates a human-readable permissions string (e.g., `drwxr-xr-x`).
+///
+/// The first character is the file type, so give this the metadata from
+/// `symlink_metadata` to show links as `l`.
+fn parse_permissions(metadata: &std::fs::Metadata) -> String {
+    let mut s = String::with_capacity(10);
+    s.push(match FileKind::of(metadata) {
+        FileKind::Dir => 'd',
+        FileKind::Symlink => 'l',
+        FileKind::Pipe => 'p',
+        FileKind::Socket => 's',
+        FileKind::BlockDevice => 'b',
+        FileKind::CharDevice => 'c',
+        FileKind::File | FileKind::Executable => '-',
+    });
+
+    #[cfg(unix)]
+    {
+        let mode = metadata.permissions().mode();
+        let rwx = ["---", "--x", "-w-", "-wx", "r--", "r-x", "rw-", "rwx"];
+        s.push_str(rwx[((mode >> 6) & 7) as usize]);
+        s.push_str(rwx[((mode >> 3) & 7) as usize]);
+        s.push_str(rwx[(mode & 7) as usize]);
+    }
+
+    #[cfg(not(unix))]
+    {
+        s.push_str("rw-rw-rw-");
+    }
+
+    s
+}
+
+/// Compresses files in the gzip format, or expands them with `-d`.
+///
+/// Supports the following flags:
+/// - `-d`: Decompress instead of compressing.
+/// - `-k`: Keep the input files instead of deleting them.
+/// - `-c`: Write to standard output, keeping the input files.
+/// - `-f`: Overwrite existing output files and write to a terminal.
+/// - `-1` to `-9`: Compression level, from fastest to best (default 6).
+///
+/// With no files, or a file named `-`, reads standard input and writes
+/// standard output.
+fn gzip_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let decompress = flags.iter().any(|f| f == "-d" || f == "--decompress");
+    gzip_run("gzip", &flags, args, decompress, ctx)
+}
+
+/// Expands gzip compressed files. Equivalent to `gzip -d`.
+fn gunzip_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    gzip_run("gunzip", &flags, args, true, ctx)
+}
+
+/// Shared implementation of `gzip` and `gunzip`.
+fn gzip_run(
+    name: &str,
+    flags: &[String],
+    mut args: Vec<String>,
+    decompress: bool,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    let keep = flags.iter().any(|f| f == "-k" || f == "--keep");
+    let to_stdout = flags.iter().any(|f| f == "-c" || f == "--stdout");
+    let force = flags.iter().any(|f| f == "-f" || f == "--force");
+    let level = flags
+        .iter()
+        .filter_map(|f| f.strip_prefix('-').and_then(|d| d.parse::<u32>().ok()))
+        .rfind(|d| (1..=9).contains(d))
+        .unwrap_or(6);
+
+    if args.is_empty() {
+        args.push("-".to_string());
+    }
+
+    let mut result = CommandResult::new();
+    for path_str in args {
+        if path_str == "-" {
+            if let Err(e) = gzip_stream(decompress, level, force, ctx) {
+                result.append_stderr(&format!("{}: stdin: {}", name, e));
+            }
+            continue;
+        }
+
+        let path = Path::new(&path_str);
+        if path.is_dir() {
+            result.append_stderr(&format!(
+                "{}: {}: is a directory -- ignored",
+                name, path_str
+            ));
+            continue;
+        }
+
+        if !decompress {
+            gzip_file(&path_str, level, to_stdout, force, keep, ctx, &mut result);
+            continue;
+        }
+
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) => {
+                result.append_stderr(&format!("{}: {}: {}", name, path_str, e));
+                continue;
+            }
+        };
+
+        let out_path = match gzip_output_name(&path_str) {
+            Some(p) => p,
+            None => {
+                result.append_stderr(&format!(
+                    "{}: {}: unknown suffix -- ignored",
+                    name, path_str
+                ));
+                continue;
+            }
+        };
+        let output = match crate::compress::gzip_decode(&data) {
+            Ok(output) => output,
+            Err(e) => {
+                result.append_stderr(&format!("{}: {}: {}", name, path_str, e));
+                continue;
+            }
+        };
+
+        if to_stdout {
+            let stdout = &mut ctx.out.stdout;
+            if let Err(e) = stdout.write_all(&output).and_then(|_| stdout.flush()) {
+                result.append_stderr(&format!("{}: {}", name, e));
+            }
+            continue;
+        }
+
+        if !force && Path::new(&out_path).exists() {
+            result.append_stderr(&format!("{}: {} already exists", name, out_path));
+            continue;
+        }
+
+        if let Err(e) = fs::write(&out_path, &output) {
+            result.append_stderr(&format!("{}: {}: {}", name, out_path, e));
+            continue;
+        }
+
+        if !keep && let Err(e) = fs::remove_file(path) {
+            result.append_stderr(&format!("{}: {}: {}", name, path_str, e));
+        }
+    }
+
+    result
+}
+
+/// Compresses `path_str` into `path_str.gz`, or onto standard output with
+/// `to_stdout`, reading it a chunk at a time.
+fn gzip_file(
+    path_str: &str,
+    level: u32,
+    to_stdout: bool,
+    force: bool,
+    keep: bool,
+    ctx: &mut ShellContext,
+    result: &mut CommandResult,
+) {
+    let path = Path::new(path_str);
+    if path_str.ends_with(".gz") {
+        result.append_stderr(&format!(
+            "gzip: {} already has .gz suffix -- unchanged",
+            path_str
+        ));
+        return;
+    }
+
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            result.append_stderr(&format!("gzip: {}: {}", path_str, e));
+            return;
+        }
+    };
+    let mtime = file
+        .metadata()
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+        .map_or(0, |d| d.as_secs() as u32);
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+    let encoder = crate::compress::GzipEncoder::new(level, file_name.as_deref(), mtime);
+
+    if to_stdout {
+        if let Err(e) = gzip_compress(&mut file, ctx.out.stdout, encoder) {
+            result.append_stderr(&format!("gzip: {}: {}", path_str, e));
+        }
+        return;
+    }
+
+    let out_path = format!("{}.gz", path_str);
+    if !force && Path::new(&out_path).exists() {
+        result.append_stderr(&format!("gzip: {} already exists", out_path));
+        return;
+    }
+    let mut output = match File::create(&out_path) {
+        Ok(output) => io::BufWriter::new(output),
+        Err(e) => {
+            result.append_stderr(&format!("gzip: {}: {}", out_path, e));
+            return;
+        }
+    };
+    if let Err(e) = gzip_compress(&mut file, &mut output, encoder) {
+        drop(output);
+        let _ = fs::remove_file(&out_path);
+        result.append_stderr(&format!("gzip: {}: {}", path_str, e));
+        return;
+    }
+
+    if !keep && let Err(e) = fs::remove_file(path) {
+        result.append_stderr(&format!("gzip: {}: {}", path_str, e));
+    }
+}
+
+/// Compresses `input` onto `output` a chunk at a time, so memory use does
+/// not grow with the input.
+fn gzip_compress(
+    input: &mut dyn Read,
+    output: &mut dyn Write,
+    mut encoder: crate::compress::GzipEncoder,
+) -> io::Result<()> {
+    let mut chunk = vec![0; CAT_CHUNK];
+    let mut packed = Vec::new();
+    loop {
+        let n = match input.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        encoder.update(&chunk[..n], &mut packed);
+        output.write_all(&packed)?;
+        packed.clear();
+    }
+    encoder.finish(&mut packed);
+    output.write_all(&packed)?;
+    output.flush()
+}
+
+/// Compresses standard input onto standard output a chunk at a time, or
+/// expands it.
+fn gzip_stream(
+    decompress: bool,
+    level: u32,
+    force: bool,
+    ctx: &mut ShellContext,
+) -> Result<(), String> {
+    use std::io::IsTerminal;
+
+    if decompress && !force && io::stdin().is_terminal() {
+        return Err("compressed data not read from a terminal. Use -f to force".to_string());
+    }
+    if !decompress && !force && io::stdout().is_terminal() {
+        return Err("compressed data not written to a terminal. Use -f to force".to_string());
+    }
+
+    if !decompress {
+        let encoder = crate::compress::GzipEncoder::new(level, None, 0);
+        return gzip_compress(ctx.stdin, ctx.out.stdout, encoder).map_err(|e| e.to_string());
+    }
+
+    let mut input = Vec::new();
+    ctx.stdin
+        .read_to_end(&mut input)
+        .map_err(|e| e.to_string())?;
+    let output = crate::compress::gzip_decode(&input).map_err(|e| e.to_string())?;
+
+    let stdout = &mut ctx.out.stdout;
+    stdout
+        .write_all(&output)
+        .and_then(|_| stdout.flush())
+        .map_err(|e| e.to_string())
+}
+
+/// Derives the decompressed file name from a gzip file name.
+fn gzip_output_name(path: &str) -> Option<String> {
+    if let Some(stem) = path.strip_suffix(".tgz") {
+        return Some(format!("{}.tar", stem));
+    }
+    path.strip_suffix(".gz")
+        .or_else(|| path.strip_suffix(".z"))
+        .filter(|stem| !stem.is_empty() && !stem.ends_with('/'))
+        .map(str::to_string)
+}
+
+const ZIP_SPEC: Spec = Spec {
+    name: "zip",
+    summary: "create a ZIP archive",
+    forms: &["ARCHIVE FILE..."],
+    flags: &[
+        Flag::new(
+            &["-r", "--recurse-paths"],
+            "Add directories with everything in them.",
+        ),
+        Flag::new(
+            &["-q", "--quiet"],
+            "Do not list the members as they are added.",
+        ),
+        Flag::new(
+            &["-0..-9"],
+            "Compress at level 0 to 9: -0 stores members as they are, and -9 makes them \
+             smallest.",
+        )
+        .or_default("6"),
+        Flag::with_value(
+            &["-x", "--exclude"],
+            "PATTERN",
+            "Leave out members matching PATTERN. May be repeated.",
+        ),
+    ],
+};
+
+/// Creates a ZIP archive from files and directories.
+///
+/// Supports the following flags:
+/// - `-r`: Recurse into directories.
+/// - `-0` to `-9`: Compression level; `-0` stores members uncompressed
+///   (default 6).
+/// - `-x PATTERN`: Leave out members matching PATTERN (repeatable).
+/// - `-q`: Do not list the members as they are added.
+///
+/// `.zip` is appended to the archive name if it has no extension. An
+/// existing archive is replaced.
+fn zip_callback(parsed: ParsedArgs) -> CommandResult {
+    let excludes = parsed.values("-x");
+    let recurse = parsed.has("-r");
+    let quiet = parsed.has("-q");
+    let level = parsed.get::<u32>("-0..-9").ok().flatten().unwrap_or(6);
+    let mut args = parsed.operands.clone();
+    if args.len() < 2 {
+        return CommandResult::with_stderr("zip: missing file operand".to_string());
+    }
+    let mut archive_path = args.remove(0);
+    if Path::new(&archive_path).extension().is_none() {
+        archive_path.push_str(".zip");
+    }
+    let archive_id = fs::canonicalize(&archive_path).ok();
+
+    let mut result = CommandResult::new();
+    let mut members = Vec::new();
+    for arg in &args {
+        if let Err(e) = zip_collect(Path::new(arg), recurse, &mut members) {
+            result.append_stderr(&format!("zip: {}", e));
+        }
+    }
+
+    let mut writer = crate::zip::Writer::new(Vec::new());
+    let mut added = 0;
+    for (path, name) in members {
+        if excludes
+            .iter()
+            .any(|p| crate::zip::wildcard_match(p, &name))
+            || (archive_id.is_some() && fs::canonicalize(&path).ok() == archive_id)
+        {
+            continue;
+        }
+        let meta = match fs::metadata(&path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                result.append_stderr(&format!("zip: {}: {}", path.display(), e));
+                continue;
+            }
+        };
+        let modified = meta
+            .modified()
+            .map(|t| DateTime::<Local>::from(t).naive_local())
+            .unwrap_or_default();
+        #[cfg(unix)]
+        let mode = meta.permissions().mode() & 0o7777;
+        #[cfg(not(unix))]
+        let mode = if meta.is_dir() {
+            0o755
+        } else if meta.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        };
+
+        let added_line =
+            if meta.is_dir() {
+                writer
+                    .add_dir(&name, modified, mode)
+                    .map(|_| format!("  adding: {}/ (stored 0%)\n", name))
+            } else {
+                match fs::read(&path) {
+                    Ok(data) => writer.add_file(&name, &data, modified, mode, level).map(
+                        |(method, stored)| {
+                            if method == crate::zip::METHOD_DEFLATE {
+                                let saved = 100 - stored * 100 / data.len().max(1);
+                                format!("  adding: {} (deflated {}%)\n", name, saved)
+                            } else {
+                                format!("  adding: {} (stored 0%)\n", name)
+                            }
+                        },
+                    ),
+                    Err(e) => Err(e),
+                }
+            };
+        match added_line {
+            Ok(line) => {
+                added += 1;
+                if !quiet {
+                    result.stdout.extend_from_slice(line.as_bytes());
+                }
+            }
+            Err(e) => result.append_stderr(&format!("zip: {}: {}", path.display(), e)),
+        }
+    }
+
+    if added == 0 {
+        result.append_stderr("zip: nothing to do");
+        return result;
+    }
+    if let Err(e) = writer
+        .finish()
+        .and_then(|data| fs::write(&archive_path, data))
+    {
+        result.append_stderr(&format!("zip: {}: {}", archive_path, e));
+    }
+    result
+}
+
+/// Adds `path` and, with `recurse`, everything below it to `members` as
+/// (path, member name) pairs. Names drop any root, `.`, and `..`
+/// components. Symbolic links to directories are followed for the link
+/// itself but not descended into, so link cycles cannot recurse forever.
+fn zip_collect(
+    path: &Path,
+    recurse: bool,
+    members: &mut Vec<(std::path::PathBuf, String)>,
+) -> Result<(), String> {
+    use std::path::Component;
+
+    let meta = fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let name = path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/");
+    if name.is_empty() {
+        if !meta.is_dir() || !recurse {
+            return Err(format!(
+                "{}: cannot be stored under this name",
+                path.display()
+            ));
+        }
+    } else {
+        members.push((path.to_path_buf(), name));
+    }
+
+    let is_link = fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_symlink());
+    if meta.is_dir() && recurse && !is_link {
+        let mut children: Vec<_> = fs::read_dir(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect();
+        children.sort();
+        for child in children {
+            zip_collect(&child, recurse, members)?;
+        }
+    }
+    Ok(())
+}
+
+const UNZIP_SPEC: Spec = Spec {
+    name: "unzip",
+    summary: "list or extract a ZIP archive",
+    forms: &["ARCHIVE [PATTERN]..."],
+    flags: &[
+        Flag::new(
+            &["-l", "--list"],
+            "List the members instead of extracting them.",
+        ),
+        Flag::new(
+            &["-t", "--test"],
+            "Check that every member decompresses to what was stored.",
+        ),
+        Flag::new(
+            &["-p", "--pipe"],
+            "Write the members to standard output instead of files.",
+        ),
+        Flag::new(
+            &["-o", "--overwrite"],
+            "Replace files that exist. By default they are skipped.",
+        ),
+        Flag::new(
+            &["-q", "--quiet"],
+            "Do not list the members as they are extracted.",
+        ),
+        Flag::with_value(
+            &["-d"],
+            "DIR",
+            "Extract into DIR instead of the current directory.",
+        ),
+        Flag::with_value(
+            &["-x", "--exclude"],
+            "PATTERN",
+            "Leave out members matching PATTERN. May be repeated.",
+        ),
+    ],
+};
+
+/// Lists, tests, or extracts the members of a ZIP archive.
+///
+/// Supports the following flags:
+/// - `-l`: List the members instead of extracting them.
+/// - `-t`: Test that every member decompresses with the right CRC.
+/// - `-p`: Write the members to standard output instead of files.
+/// - `-d DIR`: Extract into DIR instead of the current directory.
+/// - `-o`: Overwrite existing files (by default they are skipped).
+/// - `-x PATTERN`: Leave out members matching PATTERN (repeatable).
+/// - `-q`: Do not list the members as they are extracted.
+///
+/// Further arguments select members by wildcard pattern. Members whose
+/// names are absolute or contain `..` are never extracted.
+fn unzip_callback(parsed: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    use chrono::TimeZone;
+
+    let dest = parsed.value("-d").map(Path::new);
+    let excludes = parsed.values("-x");
+    let list = parsed.has("-l");
+    let test = parsed.has("-t");
+    let pipe = parsed.has("-p");
+    let overwrite = parsed.has("-o");
+    let quiet = parsed.has("-q") || pipe;
+    let mut args = parsed.operands.clone();
+    if args.is_empty() {
+        return CommandResult::with_stderr("unzip: missing archive operand".to_string());
+    }
+    let archive_path = args.remove(0);
+
+    let data = match fs::read(&archive_path) {
+        Ok(data) => data,
+        Err(e) => return CommandResult::with_stderr(format!("unzip: {}: {}", archive_path, e)),
+    };
+    let archive = match crate::zip::Archive::parse(&data) {
+        Ok(archive) => archive,
+        Err(e) => return CommandResult::with_stderr(format!("unzip: {}: {}", archive_path, e)),
+    };
+    let selected: Vec<&crate::zip::Entry> = archive
+        .entries
+        .iter()
+        .filter(|e| {
+            (args.is_empty() || args.iter().any(|p| crate::zip::wildcard_match(p, &e.name)))
+                && !excludes
+                    .iter()
+                    .any(|p| crate::zip::wildcard_match(p, &e.name))
+        })
+        .collect();
+
+    let mut result = CommandResult::new();
+    if list {
+        result.stdout = format!(
+            "Archive:  {}\n{}",
+            archive_path,
+            format_zip_listing(&selected)
+        )
+        .into_bytes();
+        return result;
+    }
+    if !quiet {
+        result
+            .stdout
+            .extend_from_slice(format!("Archive:  {}\n", archive_path).as_bytes());
+    }
+
+    let mut errors = 0;
+    for entry in selected {
+        if test {
+            match archive.read(entry) {
+                Ok(_) => result
+                    .stdout
+                    .extend_from_slice(format!("    testing: {:<24} OK\n", entry.name).as_bytes()),
+                Err(e) => {
+                    errors += 1;
+                    result.append_stderr(&format!("unzip: {}", e));
+                }
+            }
+            continue;
+        }
+        if pipe {
+            if entry.is_dir() {
+                continue;
+            }
+            let stdout = &mut ctx.out.stdout;
+            if let Err(e) = archive
+                .read(entry)
+                .map_err(|e| e.to_string())
+                .and_then(|contents| {
+                    stdout
+                        .write_all(&contents)
+                        .and_then(|_| stdout.flush())
+                        .map_err(|e| e.to_string())
+                })
+            {
+                result.append_stderr(&format!("unzip: {}", e));
+            }
+            continue;
+        }
+
+        let Some(relative) = crate::zip::safe_path(&entry.name) else {
+            result.append_stderr(&format!("unzip: {}: unsafe path -- skipped", entry.name));
+            continue;
+        };
+        let target = match dest {
+            Some(dest) => dest.join(relative),
+            None => relative,
+        };
+        if entry.is_dir() {
+            match fs::create_dir_all(&target) {
+                Ok(()) if !quiet => result
+                    .stdout
+                    .extend_from_slice(format!("   creating: {}/\n", target.display()).as_bytes()),
+                Ok(()) => {}
+                Err(e) => result.append_stderr(&format!("unzip: {}: {}", target.display(), e)),
+            }
+            continue;
+        }
+        if !overwrite && target.exists() {
+            result.append_stderr(&format!(
+                "unzip: {}: already exists -- skipped",
+                target.display()
+            ));
+            continue;
+        }
+
+        let written = archive
+            .read(entry)
+            .map_err(|e| e.to_string())
+            .and_then(|contents| {
+                let io_err = |e: io::Error| format!("{}: {}", target.display(), e);
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent).map_err(io_err)?;
+                }
+                let file = File::create(&target).map_err(io_err)?;
+                (&file).write_all(&contents).map_err(io_err)?;
+                if let Some(modified) = entry
+                    .modified
+                    .and_then(|t| Local.from_local_datetime(&t).earliest())
+                {
+                    let _ = file.set_modified(modified.into());
+                }
+                #[cfg(unix)]
+                if let Some(mode) = entry.mode {
+                    let _ = file.set_permissions(fs::Permissions::from_mode(mode & 0o777));
+                }
+                Ok(())
+            });
+        match written {
+            Ok(()) if !quiet => {
+                let verb = if entry.method == crate::zip::METHOD_STORE {
+                    " extracting"
+                } else {
+                    "  inflating"
+                };
+                result
+                    .stdout
+                    .extend_from_slice(format!("{}: {}\n", verb, target.display()).as_bytes());
+            }
+            Ok(()) => {}
+            Err(e) => result.append_stderr(&format!("unzip: {}", e)),
+        }
+    }
+
+    if test && errors == 0 {
+        result.stdout.extend_from_slice(
+            format!(
+                "No errors detected in compressed data of {}.\n",
+                archive_path
+            )
+            .as_bytes(),
+        );
+    }
+    result
+}
+
+/// Formats the `unzip -l` table of members with their sizes and times.
+fn format_zip_listing(entries: &[&crate::zip::Entry]) -> String {
+    let mut out =
+        String::from("  Length      Date    Time    Name\n---------  ---------- -----   ----\n");
+    let mut total: u64 = 0;
+    for entry in entries {
+        let time = entry.modified.map_or("                ".to_string(), |t| {
+            t.format("%Y-%m-%d %H:%M").to_string()
+        });
+        out.push_str(&format!("{:>9}  {}   {}\n", entry.size, time, entry.name));
+        total += entry.size as u64;
+    }
+    let count = entries.len();
+    out.push_str(&format!(
+        "---------                     -------\n{:>9}                     {} file{}\n",
+        total,
+        count,
+        if count == 1 { "" } else { "s" }
+    ));
+    out
+}
+
+const ENCODE_SPEC: Spec = Spec {
+    name: "encode",
+    summary: "convert text between character encodings",
+    forms: &["[FILE]..."],
+    flags: &[
+        Flag::with_value(&["-f", "--from"], "FROM", "Read the input as FROM.").or_default("UTF-8"),
+        Flag::with_value(&["-t", "--to"], "TO", "Write the output as TO.").or_default("UTF-8"),
+        Flag::with_value(
+            &["-e", "--errors"],
+            "POLICY",
+            "What to do with input that cannot be decoded or characters that cannot be \
+             encoded: strict stops, replace substitutes them, and ignore drops them.",
+        )
+        .or_default("strict"),
+        Flag::with_value(
+            &["-o", "--output"],
+            "OUTPUT",
+            "Write to OUTPUT instead of standard output.",
+        ),
+        Flag::new(&["-l", "--list"], "List the supported encodings."),
+    ],
+};
+
+/// Converts text between character encodings, like `iconv`.
+///
+/// Supports the following flags:
+/// - `-f FROM`: Encoding of the input (default UTF-8).
+/// - `-t TO`: Encoding of the output (default UTF-8).
+/// - `-e POLICY`: How to handle input that cannot be decoded or characters
+///   that cannot be encoded: `strict` (stop, the default), `replace`, or
+///   `ignore`.
+/// - `-o OUTPUT`: Write to OUTPUT instead of standard output.
+/// - `-l`: List the supported encodings.
+///
+/// With no files, or a file named `-`, reads standard input.
+fn encode_callback(parsed: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    use crate::encoding::{Encoding, ErrorPolicy, decode, encode};
+
+    if parsed.has("-l") {
+        let names: Vec<&str> = Encoding::ALL.iter().map(|e| e.name()).collect();
+        return CommandResult::with_stdout(names.join("\n") + "\n");
+    }
+    let encoding = |flag: &str| {
+        let name = parsed.value(flag).unwrap_or_default();
+        Encoding::from_name(name).ok_or_else(|| format!("encode: unsupported encoding '{}'", name))
+    };
+    let (from, to) = match (encoding("-f"), encoding("-t")) {
+        (Ok(from), Ok(to)) => (from, to),
+        (Err(e), _) | (_, Err(e)) => return CommandResult::with_stderr(e),
+    };
+    let policy_name = parsed.value("-e").unwrap_or_default();
+    let Some(policy) = ErrorPolicy::from_name(policy_name) else {
+        return CommandResult::with_stderr(format!(
+            "encode: invalid error policy '{}' (expected strict, replace, or ignore)",
+            policy_name
+        ));
+    };
+
+    let mut args = parsed.operands.clone();
+    if args.is_empty() {
+        args.push("-".to_string());
+    }
+    let mut result = CommandResult::new();
+    let mut output = Vec::new();
+    for path in &args {
+        let converted = read_input(path, ctx.stdin)
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| decode(&bytes, from, policy))
+            .and_then(|text| encode(&text, to, policy));
+        match converted {
+            Ok(bytes) => output.extend_from_slice(&bytes),
+            Err(e) => {
+                let name = if path == "-" { "stdin" } else { path };
+                result.append_stderr(&format!("encode: {}: {}", name, e));
+            }
+        }
+    }
+
+    if output.is_empty() && !result.stderr.is_empty() {
+        return result;
+    }
+    let written = match parsed.value("-o") {
+        Some(out_path) => fs::write(out_path, &output).map_err(|e| format!("{}: {}", out_path, e)),
+        None => {
+            let stdout = &mut ctx.out.stdout;
+            stdout
+                .write_all(&output)
+                .and_then(|_| stdout.flush())
+                .map_err(|e| e.to_string())
+        }
+    };
+    if let Err(e) = written {
+        result.append_stderr(&format!("encode: {}", e));
+    }
+    result
+}
+
+/// Converts DOS (CRLF) line endings to Unix (LF), rewriting files in place.
+///
+/// Supports the following flags:
+/// - `-c`: Write to standard output instead of changing the files.
+/// - `-b`: Keep a UTF-8 byte order mark (by default it is removed).
+/// - `-f`: Convert binary files too (by default they are skipped).
+/// - `--check`: Report the line endings each file uses without converting.
+///
+/// With no files, or a file named `-`, reads standard input and writes
+/// standard output. Lone CRs are left alone.
+fn dos2unix_callback(
+    flags: Vec<String>,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    line_endings_run("dos2unix", &flags, args, false, ctx)
+}
+
+/// Converts Unix (LF) line endings to DOS (CRLF), rewriting files in place.
+///
+/// Takes the same flags as `dos2unix`, except that `-m` adds a UTF-8 byte
+/// order mark instead of `-b` keeping one. Lines already ending in CRLF are
+/// left as they are, so mixed files come out consistent.
+fn unix2dos_callback(
+    flags: Vec<String>,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    line_endings_run("unix2dos", &flags, args, true, ctx)
+}
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// Shared implementation of `dos2unix` and `unix2dos`.
+fn line_endings_run(
+    name: &str,
+    flags: &[String],
+    mut args: Vec<String>,
+    to_dos: bool,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    let mut to_stdout = false;
+    let mut keep_bom = false;
+    let mut add_bom = false;
+    let mut force = false;
+    let mut check = false;
+    for flag in flags {
+        match flag.as_str() {
+            "-c" | "--stdout" => to_stdout = true,
+            "-b" | "--keep-bom" if !to_dos => keep_bom = true,
+            "-m" | "--add-bom" if to_dos => add_bom = true,
+            "-f" | "--force" => force = true,
+            "--check" => check = true,
+            _ => return CommandResult::with_stderr(format!("{}: invalid option '{}'", name, flag)),
+        }
+    }
+    if args.is_empty() {
+        args.push("-".to_string());
+    }
+
+    let mut result = CommandResult::new();
+    for path in &args {
+        let display = if path == "-" { "stdin" } else { path.as_str() };
+        let data = match read_input(path, ctx.stdin) {
+            Ok(data) => data,
+            Err(e) => {
+                result.append_stderr(&format!("{}: {}: {}", name, display, e));
+                continue;
+            }
+        };
+
+        if check {
+            result.stdout.extend_from_slice(
+                format!("{}: {}\n", display, describe_line_endings(&data)).as_bytes(),
+            );
+            continue;
+        }
+        if !force && data.contains(&0) {
+            result.append_stderr(&format!("{}: {}: binary file -- skipped", name, display));
+            continue;
+        }
+
+        let (had_bom, body) = match data.strip_prefix(UTF8_BOM) {
+            Some(body) => (true, body),
+            None => (false, &data[..]),
+        };
+        let mut output = Vec::with_capacity(data.len() + data.len() / 32);
+        if (had_bom && (to_dos || keep_bom)) || add_bom {
+            output.extend_from_slice(UTF8_BOM);
+        }
+        output.extend_from_slice(&convert_line_endings(body, to_dos));
+
+        let written = if to_stdout || path == "-" {
+            let stdout = &mut ctx.out.stdout;
+            stdout.write_all(&output).and_then(|_| stdout.flush())
+        } else if output == data {
+            Ok(())
+        } else {
+            fs::write(path, &output)
+        };
+        if let Err(e) = written {
+            result.append_stderr(&format!("{}: {}: {}", name, display, e));
+        }
+    }
+    result
+}
+
+/// Rewrites line endings as CRLF (`to_dos`) or LF. Lone CRs are kept.
+fn convert_line_endings(data: &[u8], to_dos: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for (i, &byte) in data.iter().enumerate() {
+        let after_cr = i > 0 && data[i - 1] == b'\r';
+        match byte {
+            b'\r' if !to_dos && data.get(i + 1) == Some(&b'\n') => {}
+            b'\n' if to_dos && !after_cr => out.extend_from_slice(b"\r\n"),
+            _ => out.push(byte),
+        }
+    }
+    out
+}
+
+/// Summarizes the line endings in `data`, as in `dos (12 CRLF, BOM)`.
+fn describe_line_endings(data: &[u8]) -> String {
+    let (mut crlf, mut lf, mut cr) = (0, 0, 0);
+    for (i, &byte) in data.iter().enumerate() {
+        match byte {
+            b'\n' if i > 0 && data[i - 1] == b'\r' => crlf += 1,
+            b'\n' => lf += 1,
+            b'\r' if data.get(i + 1) != Some(&b'\n') => cr += 1,
+            _ => {}
+        }
+    }
+    let kind = match (crlf > 0, lf > 0, cr > 0) {
+        (false, false, false) => "no line endings",
+        (true, false, false) => "dos",
+        (false, true, false) => "unix",
+        (false, false, true) => "mac",
+        _ => "mixed",
+    };
+    let mut details: Vec<String> = [(crlf, "CRLF"), (lf, "LF"), (cr, "CR")]
+        .iter()
+        .filter(|(count, _)| *count > 0)
+        .map(|(count, label)| format!("{} {}", count, label))
+        .collect();
+    if data.starts_with(UTF8_BOM) {
+        details.push("BOM".to_string());
+    }
+    if details.is_empty() {
+        kind.to_string()
+    } else {
+        format!("{} ({})", kind, details.join(", "))
+    }
+}
+
+/// Views files (or standard input) one screen at a time.
+///
+/// Keys: Space/b page down/up, j/k scroll a line, g/G jump to top/bottom,
+/// `/pattern` searches, n/N repeat the search, and q quits.
+fn less_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    pager_run("less", args, false, ctx)
+}
+
+/// Like `less`, but exits once the end of the text is reached.
+fn more_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    pager_run("more", args, true, ctx)
+}
+
+/// Shared implementation of `less` and `more`.
+fn pager_run(
+    name: &str,
+    args: Vec<String>,
+    more_style: bool,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    use std::io::IsTerminal;
+
+    let mut result = CommandResult::new();
+    if args.is_empty() && io::stdin().is_terminal() {
+        return CommandResult::with_stderr(format!("{}: missing filename", name));
+    }
+
+    // The files are opened now and read as the pager needs them
+    let mut input: Box<dyn Read + '_> = if args.is_empty() {
+        Box::new(&mut *ctx.stdin)
+    } else {
+        Box::new(io::empty())
+    };
+    for path in &args {
+        match File::open(path) {
+            Ok(file) if file.metadata().is_ok_and(|m| m.is_dir()) => {
+                result.append_stderr(&format!("{}: {}: Is a directory", name, path));
+            }
+            Ok(file) => input = Box::new(input.chain(file)),
+            Err(e) => result.append_stderr(&format!("{}: {}: {}", name, path, e)),
+        }
+    }
+
+    let title = if args.len() == 1 {
+        args[0].as_str()
+    } else {
+        ""
+    };
+    if let Err(e) = crate::pager::page_reader(input, title, more_style, &ctx.state.vars) {
+        result.append_stderr(&format!("{}: {}", name, e));
+    }
+
+    result
+}
+
+/// Options controlling how `xargs` groups its input into command lines.
+#[derive(Debug, Default)]
+struct XargsOptions {
+    max_args: Option<usize>,
+    replace: Option<String>,
+    null: bool,
+    procs: usize,
+    command: Vec<String>,
+}
+
+/// Builds and runs command lines from items read on standard input.
+///
+/// Items are whitespace separated (honoring quotes and backslashes), or
+/// NUL separated with `-0`. Supports the following options:
+/// - `-n MAX`: Use at most MAX items per command line.
+/// - `-I REPLACE`: Run once per input line, replacing REPLACE in the arguments.
+/// - `-0`: Items are terminated by NUL characters.
+/// - `-P PROCS`: Run up to PROCS command lines at a time.
+///
+/// Built-in commands and aliases are run by the shell; any other command
+/// is run as a program found on `PATH`. The default command is `echo`.
+fn xargs_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let opts = match parse_xargs_options(args) {
+        Ok(opts) => opts,
+        Err(e) => return CommandResult::with_stderr(format!("xargs: {}", e)),
+    };
+
+    let mut input = Vec::new();
+    if let Err(e) = ctx.stdin.read_to_end(&mut input) {
+        return CommandResult::with_stderr(format!("xargs: {}", e));
+    }
+    let input = String::from_utf8_lossy(&input);
+
+    let lines = match xargs_command_lines(&opts, &input, ctx.state) {
+        Ok(lines) => lines,
+        Err(e) => return CommandResult::error(e),
+    };
+    let (cmds, state) = (ctx.commands, &*ctx.state);
+    let results = run_in_parallel(&lines, opts.procs, |tokens| match CommandCall::from_tokens(
+        tokens.clone(),
+    ) {
+        Some(call) if cmds.contains(&call.name) || state.aliases.contains_key(&call.name) => {
+            cmds.run_detached(state, call)
+        }
+        Some(_) => xargs_run_program(tokens, &state.vars),
+        None => CommandResult::new(),
+    });
+
+    // As in GNU xargs, the status is 123 if a command failed, or 127 if
+    // one was not found
+    let mut result = CommandResult::new();
+    for res in results {
+        result.stdout.extend_from_slice(&res.stdout);
+        if !res.stderr.is_empty() {
+            result.append_notice(&res.stderr);
+        }
+        if res.status == 127 {
+            result.status = 127;
+        } else if !res.success() && result.status == 0 {
+            result.status = 123;
+        }
+    }
+    result
+}
+
+/// Runs `tokens` as a program that is not a built-in command, with `vars`
+/// as its environment and no input.
+fn xargs_run_program(tokens: &[String], vars: &Variables) -> CommandResult {
+    let output = vars
+        .apply(&mut std::process::Command::new(&tokens[0]))
+        .args(&tokens[1..])
+        .stdin(std::process::Stdio::null())
+        .output();
+    match output {
+        Ok(output) => {
+            let mut result = CommandResult::new();
+            result.stdout = output.stdout;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.trim().is_empty() {
+                result.append_notice(stderr.trim_end());
+            }
+            if !output.status.success() {
+                result.status = crate::plugins::exit_status(&output.status);
+            }
+            result
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => CommandResult::error(
+            CommandError::CommandNotFound(format!("xargs: {}: command not found", tokens[0])),
+        ),
+        Err(e) => CommandResult {
+            status: 126,
+            ..CommandResult::with_stderr(format!("xargs: {}: {}", tokens[0], e))
+        },
+    }
+}
+
+/// Parses the options that precede the command in an `xargs` invocation.
+fn parse_xargs_options(args: Vec<String>) -> Result<XargsOptions, String> {
+    let mut opts = XargsOptions {
+        procs: 1,
+        ..Default::default()
+    };
+    let mut iter = args.into_iter();
+
+    while let Some(arg) = iter.next() {
+        let mut value_for = |flag: &str, inline: &str| -> Result<String, String> {
+            if !inline.is_empty() {
+                return Ok(inline.to_string());
+            }
+            iter.next()
+                .ok_or_else(|| format!("option requires an argument -- '{}'", flag))
+        };
+
+        match arg.as_str() {
+            "--" => {
+                opts.command.extend(iter);
+                break;
+            }
+            "-0" | "--null" => opts.null = true,
+            a if a.starts_with("-n") => {
+                let value = value_for("n", &a[2..])?;
+                let n = value
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n > 0)
+                    .ok_or_else(|| format!("invalid number for -n option: '{}'", value))?;
+                opts.max_args = Some(n);
+            }
+            a if a.starts_with("-P") => {
+                let value = value_for("P", &a[2..])?;
+                opts.procs = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid number for -P option: '{}'", value))?
+                    .max(1);
+            }
+            a if a.starts_with("-I") => opts.replace = Some(value_for("I", &a[2..])?),
+            a if a.starts_with('-') && a.len() > 1 => {
+                return Err(format!("invalid option -- '{}'", &a[1..]));
+            }
+            _ => {
+                opts.command.push(arg);
+                opts.command.extend(iter);
+                break;
+            }
+        }
+    }
+
+    if opts.command.is_empty() {
+        opts.command.push("echo".to_string());
+    }
+    Ok(opts)
+}
+
+/// Splits the input into items and groups them into full command lines.
+/// `$?` in the items expands to `last_status`.
+fn xargs_command_lines(
+    opts: &XargsOptions,
+    input: &str,
+    state: &ShellState,
+) -> Result<Vec<Vec<String>>, CommandError> {
+    if let Some(replace) = &opts.replace {
+        // One command per non-empty input line, substituting the whole line
+        // minus its leading blanks
+        return Ok(input
+            .lines()
+            .map(str::trim_start)
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                opts.command
+                    .iter()
+                    .map(|arg| arg.replace(replace.as_str(), line))
+                    .collect()
+            })
+            .collect());
+    }
+
+    let items: Vec<String> = if opts.null {
+        input
+            .split('\0')
+            .filter(|item| !item.is_empty())
+            .map(str::to_string)
+            .collect()
+    } else {
+        tokenize(input, state)?
+    };
+
+    if items.is_empty() {
+        return Ok(vec![opts.command.clone()]);
+    }
+
+    let group = opts.max_args.unwrap_or(items.len());
+    Ok(items
+        .chunks(group)
+        .map(|chunk| {
+            let mut line = opts.command.clone();
+            line.extend_from_slice(chunk);
+            line
+        })
+        .collect())
+}
+
+/// Runs `job` over every item using up to `procs` worker threads,
+/// returning the results in input order.
+fn run_in_parallel<T: Sync, F>(items: &[T], procs: usize, job: F) -> Vec<CommandResult>
+where
+    F: Fn(&T) -> CommandResult + Sync,
+{
+    if procs <= 1 {
+        return items.iter().map(&job).collect();
+    }
+
+    let next = std::sync::atomic::AtomicUsize::new(0);
+    let slots: Vec<std::sync::Mutex<Option<CommandResult>>> =
+        items.iter().map(|_| std::sync::Mutex::new(None)).collect();
+
+    std::thread::scope(|scope| {
+        for _ in 0..procs.min(items.len()) {
+            scope.spawn(|| {
+                loop {
+                    let i = next.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                    if i >= items.len() {
+                        break;
+                    }
+                    let res = job(&items[i]);
+                    *slots[i].lock().unwrap() = Some(res);
+                }
+            });
+        }
+    });
+
+    slots
+        .into_iter()
+        .filter_map(|slot| slot.into_inner().unwrap())
+        .collect()
+}
+
+/// Runs a command repeatedly, showing its output full-screen.
+///
+/// Supports the following options:
+/// - `-n SECONDS`: Interval between runs (default 2, fractions allowed).
+/// - `-t`: Hide the header line.
+///
+/// Runs until interrupted with Ctrl+C.
+fn watch_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let (interval, show_title, command) = match parse_watch_options(args) {
+        Ok(opts) => opts,
+        Err(e) => return CommandResult::with_stderr(format!("watch: {}", e)),
+    };
+    let Some(call) = CommandCall::from_tokens(command.clone()) else {
+        return CommandResult::with_stderr("watch: missing command".to_string());
+    };
+
+    let _guard = crate::signals::InterruptGuard::install();
+
+    loop {
+        let mut screen = String::from(crate::terminal::CLEAR_SCREEN);
+        if show_title {
+            let (width, _) = crate::terminal::size(&ctx.state.vars);
+            screen.push_str(&watch_header(
+                interval.as_secs_f64(),
+                &command.join(" "),
+                width,
+                Local::now(),
+            ));
+            screen.push_str("\n\n");
+        }
+
+        let res = ctx.capture(call.clone());
+        screen.push_str(&res.stdout_text());
+        if !res.stderr.is_empty() {
+            screen.push_str(&res.stderr);
+            screen.push('\n');
+        }
+
+        let stdout = &mut ctx.out.stdout;
+        if stdout
+            .write_all(screen.as_bytes())
+            .and_then(|_| stdout.flush())
+            .is_err()
+            || res.should_exit
+        {
+            break;
+        }
+
+        if crate::signals::sleep_interruptible(interval) {
+            break;
+        }
+    }
+
+    CommandResult::with_stdout("\n".to_string())
+}
+
+/// Parses `watch` options, returning the interval, whether to show the
+/// header, and the command to run.
+fn parse_watch_options(
+    args: Vec<String>,
+) -> Result<(std::time::Duration, bool, Vec<String>), String> {
+    let mut interval = std::time::Duration::from_secs(2);
+    let mut show_title = true;
+    let mut iter = args.into_iter();
+    let mut command = Vec::new();
+
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-t" | "--no-title" => show_title = false,
+            "--" => {
+                command.extend(iter.by_ref());
+                break;
+            }
+            a if a.starts_with("-n") || a.starts_with("--interval") => {
+                let inline = a
+                    .strip_prefix("--interval")
+                    .map(|v| v.trim_start_matches('='))
+                    .unwrap_or(&a[2..]);
+                let value = if inline.is_empty() {
+                    iter.next()
+                        .ok_or_else(|| "option requires an argument -- 'n'".to_string())?
+                } else {
+                    inline.to_string()
+                };
+                interval = value
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(|v| std::time::Duration::try_from_secs_f64(v).ok())
+                    .ok_or_else(|| format!("failed to parse argument: '{}'", value))?
+                    .max(std::time::Duration::from_millis(100));
+            }
+            _ => {
+                command.push(arg);
+                command.extend(iter.by_ref());
+                break;
+            }
+        }
+    }
+
+    if command.is_empty() {
+        return Err("missing command".to_string());
+    }
+    Ok((interval, show_title, command))
+}
+
+/// Formats the `watch` header: the interval and command on the left and the
+/// current time on the right, padded to `width`.
+fn watch_header(interval: f64, command: &str, width: usize, now: DateTime<Local>) -> String {
+    let left = format!("Every {:.1}s: {}", interval, command);
+    let right = now.format("%a %b %e %H:%M:%S %Y").to_string();
+    let pad = width
+        .saturating_sub(left.chars().count() + right.len())
+        .max(1);
+    format!("{}{}{}", left, " ".repeat(pad), right)
+}
+
+/// Reports the current time, system uptime, logged-in users, and load.
+///
+/// Supports the following flags:
+/// - `-p`: Show only the uptime, in a pretty format.
+/// - `-s`: Show the time the system booted.
+///
+/// Uptime comes from `/proc/uptime` and load from `/proc/loadavg`. Missing
+/// fields are left out on platforms without them.
+fn uptime_callback(flags: Vec<String>, _args: Vec<String>) -> CommandResult {
+    let pretty = flags.iter().any(|f| f == "-p" || f == "--pretty");
+    let since = flags.iter().any(|f| f == "-s" || f == "--since");
+    let now = Local::now();
+    let uptime = read_uptime_secs();
+
+    if pretty || since {
+        let Some(secs) = uptime else {
+            return CommandResult::with_stderr(
+                "uptime: cannot determine system uptime".to_string(),
+            );
+        };
+        if since {
+            let boot = now - chrono::Duration::seconds(secs as i64);
+            return CommandResult::with_stdout(format!("{}\n", boot.format("%Y-%m-%d %H:%M:%S")));
+        }
+        return CommandResult::with_stdout(format!("up {}\n", format_uptime_pretty(secs)));
+    }
+
+    let mut line = format!(" {}", now.format("%H:%M:%S"));
+    if let Some(secs) = uptime {
+        line.push_str(&format!(" up {}", format_uptime(secs)));
+    }
+    if let Some(users) = count_logged_in_users() {
+        line.push_str(&format!(
+            ",  {} user{}",
+            users,
+            if users == 1 { "" } else { "s" }
+        ));
+    }
+    if let Some([one, five, fifteen]) = read_load_average() {
+        line.push_str(&format!(
+            ",  load average: {:.2}, {:.2}, {:.2}",
+            one, five, fifteen
+        ));
+    }
+    line.push('\n');
+    CommandResult::with_stdout(line)
+}
+
+/// Reads the number of seconds since boot from `/proc/uptime`.
+fn read_uptime_secs() -> Option<u64> {
+    let contents = fs::read_to_string("/proc/uptime").ok()?;
+    let secs: f64 = contents.split_whitespace().next()?.parse().ok()?;
+    Some(secs as u64)
+}
+
+/// Reads the 1, 5, and 15 minute load averages, preferring `/proc/loadavg`
+/// and falling back to `getloadavg` on other Unix systems.
+fn read_load_average() -> Option<[f64; 3]> {
+    if let Ok(contents) = fs::read_to_string("/proc/loadavg") {
+        let loads: Vec<f64> = contents
+            .split_whitespace()
+            .take(3)
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        if let [one, five, fifteen] = loads[..] {
+            return Some([one, five, fifteen]);
+        }
+    }
+
+    #[cfg(unix)]
+    {
+        let mut loads = [0f64; 3];
+        if unsafe { libc::getloadavg(loads.as_mut_ptr(), 3) } == 3 {
+            return Some(loads);
+        }
+    }
+    None
+}
+
+/// Counts user login sessions recorded in the utmp database.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn count_logged_in_users() -> Option<usize> {
+    let mut count = 0;
+    // SAFETY: the utmpx iteration functions are used single-threaded and the
+    // returned pointer is only read before the next call.
+    unsafe {
+        libc::setutxent();
+        loop {
+            let entry = libc::getutxent();
+            if entry.is_null() {
+                break;
+            }
+            if (*entry).ut_type == libc::USER_PROCESS {
+                count += 1;
+            }
+        }
+        libc::endutxent();
+    }
+    Some(count)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn count_logged_in_users() -> Option<usize> {
+    None
+}
+
+/// Formats uptime the way classic `uptime` does: `3 days,  4:12`, `4:12`,
+/// or `12 min`.
+fn format_uptime(secs: u64) -> String {
+    let days = secs / 86_400;
+    let hours = secs % 86_400 / 3600;
+    let minutes = secs % 3600 / 60;
+
+    let clock = if hours > 0 {
+        format!("{:2}:{:02}", hours, minutes)
+    } else {
+        format!("{} min", minutes)
+    };
+    match days {
+        0 => clock,
+        1 => format!("1 day, {}", clock),
+        _ => format!("{} days, {}", days, clock),
+    }
+}
+
+/// Formats uptime for `uptime -p`, e.g. `2 days, 3 hours, 1 minute`.
+fn format_uptime_pretty(secs: u64) -> String {
+    let parts = [
+        (secs / 604_800, "week"),
+        (secs % 604_800 / 86_400, "day"),
+        (secs % 86_400 / 3600, "hour"),
+        (secs % 3600 / 60, "minute"),
+    ];
+    let text: Vec<String> = parts
+        .iter()
+        .filter(|(n, _)| *n > 0)
+        .map(|(n, unit)| format!("{} {}{}", n, unit, if *n == 1 { "" } else { "s" }))
+        .collect();
+    if text.is_empty() {
+        "0 minutes".to_string()
+    } else {
+        text.join(", ")
+    }
+}
+
+const FREE_SPEC: Spec = Spec {
+    name: "free",
+    summary: "display amount of free and used memory",
+    forms: &[""],
+    flags: &[
+        Flag::new(&["-b", "--bytes"], "Show sizes in bytes."),
+        Flag::new(&["-k", "--kibi"], "Show sizes in KiB. This is the default."),
+        Flag::new(&["-m", "--mebi"], "Show sizes in MiB."),
+        Flag::new(&["-g", "--gibi"], "Show sizes in GiB."),
+        Flag::new(
+            &["-h", "--human"],
+            "Show sizes with units, such as 1.5Gi, instead of help.",
+        ),
+        Flag::new(
+            &["-t", "--total"],
+            "Add a line with the totals of memory and swap.",
+        ),
+    ],
+};
+
+/// Displays total, used, and free memory and swap from `/proc/meminfo`.
+/// The last of `-b`, `-k`, `-m`, and `-g` sets the unit.
+fn free_callback(args: ParsedArgs) -> CommandResult {
+    let human = args.has("-h");
+    let total = args.has("-t");
+    let shift = match args.last(&["-b", "-k", "-m", "-g"]) {
+        Some("-b") => 0,
+        Some("-m") => 20,
+        Some("-g") => 30,
+        _ => 10,
+    };
+    if let Some(extra) = args.operands.first() {
+        return CommandResult::with_stderr(format!("free: extra operand '{}'", extra));
+    }
+
+    match fs::read_to_string("/proc/meminfo") {
+        Ok(meminfo) => CommandResult::with_stdout(format_free(&meminfo, shift, human, total)),
+        Err(e) => CommandResult::with_stderr(format!("free: /proc/meminfo: {}", e)),
+    }
+}
+
+/// Renders the `free` table from the contents of `/proc/meminfo`.
+///
+/// Sizes are divided by `2^shift` bytes, or formatted for humans.
+fn format_free(meminfo: &str, shift: u32, human: bool, total: bool) -> String {
+    let mut fields = HashMap::new();
+    for line in meminfo.lines() {
+        if let Some((key, value)) = line.split_once(':') {
+            let kib: u64 = value
+                .split_whitespace()
+                .next()
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(0);
+            fields.insert(key.trim().to_string(), kib * 1024);
+        }
+    }
+    let get = |key: &str| fields.get(key).copied().unwrap_or(0);
+
+    let mem_total = get("MemTotal");
+    let mem_free = get("MemFree");
+    let buff_cache = get("Buffers") + get("Cached") + get("SReclaimable");
+    let available = fields
+        .get("MemAvailable")
+        .copied()
+        .unwrap_or(mem_free + buff_cache);
+    let mem_used = mem_total.saturating_sub(available);
+    let swap_total = get("SwapTotal");
+    let swap_free = get("SwapFree");
+    let swap_used = swap_total.saturating_sub(swap_free);
+
+    let fmt = |bytes: u64| -> String {
+        if !human {
+            (bytes >> shift).to_string()
+        } else if bytes < 1024 {
+            format!("{}B", bytes)
+        } else {
+            format!("{}i", format_human_size(bytes))
+        }
+    };
+
+    let mut out = format!(
+        "{:>7} {:>11} {:>11} {:>11} {:>11} {:>11} {:>11}\n",
+        "", "total", "used", "free", "shared", "buff/cache", "available"
+    );
+    out.push_str(&format!(
+        "{:<7} {:>11} {:>11} {:>11} {:>11} {:>11} {:>11}\n",
+        "Mem:",
+        fmt(mem_total),
+        fmt(mem_used),
+        fmt(mem_free),
+        fmt(get("Shmem")),
+        fmt(buff_cache),
+        fmt(available)
+    ));
+    out.push_str(&format!(
+        "{:<7} {:>11} {:>11} {:>11}\n",
+        "Swap:",
+        fmt(swap_total),
+        fmt(swap_used),
+        fmt(swap_free)
+    ));
+    if total {
+        out.push_str(&format!(
+            "{:<7} {:>11} {:>11} {:>11}\n",
+            "Total:",
+            fmt(mem_total + swap_total),
+            fmt(mem_used + swap_used),
+            fmt(mem_free + swap_free)
+        ));
+    }
+    out
+}
+
+/// Formats a byte count with a single-letter binary unit, rounding up like
+/// GNU tools: `512`, `4.0K`, `1.2M`, `15G`.
+fn format_human_size(bytes: u64) -> String {
+    const UNITS: [&str; 6] = ["K", "M", "G", "T", "P", "E"];
+
+    if bytes < 1024 {
+        return bytes.to_string();
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if value < 10.0 {
+        let rounded = (value * 10.0).ceil() / 10.0;
+        if rounded < 10.0 {
+            return format!("{:.1}{}", rounded, UNITS[unit]);
+        }
+        value = rounded;
+    }
+    let rounded = value.ceil();
+    if rounded >= 1024.0 && unit < UNITS.len() - 1 {
+        return format!("1.0{}", UNITS[unit + 1]);
+    }
+    format!("{:.0}{}", rounded, UNITS[unit])
+}
+
+/// Operands controlling a `dd` copy.
+#[derive(Debug)]
+struct DdOptions {
+    input: Option<String>,
+    output: Option<String>,
+    ibs: usize,
+    obs: usize,
+    count: Option<u64>,
+    skip: u64,
+    seek: u64,
+    status: String,
+    notrunc: bool,
+}
+
+/// Copies raw bytes between files, block by block.
+///
+/// Supports the following operands:
+/// - `if=FILE` / `of=FILE`: Input and output (default stdin/stdout).
+/// - `bs=BYTES`: Block size for both reading and writing (default 512);
+///   `ibs=` and `obs=` set them separately.
+/// - `count=N`: Copy only N input blocks.
+/// - `skip=N` / `seek=N`: Skip N input blocks / N output blocks first.
+/// - `status=none|noxfer|progress`: How much to report on stderr.
+/// - `conv=notrunc`: Do not truncate the output file.
+fn dd_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let opts = match parse_dd_options(&args) {
+        Ok(opts) => opts,
+        Err(e) => return CommandResult::with_stderr(format!("dd: {}", e)),
+    };
+    match dd_copy(&opts, ctx) {
+        Ok(report) => CommandResult::with_notice(report),
+        Err(e) => CommandResult::with_stderr(format!("dd: {}", e)),
+    }
+}
+
+/// Parses `dd`'s `key=value` operands.
+fn parse_dd_options(args: &[String]) -> Result<DdOptions, String> {
+    let mut opts = DdOptions {
+        input: None,
+        output: None,
+        ibs: 512,
+        obs: 512,
+        count: None,
+        skip: 0,
+        seek: 0,
+        status: String::new(),
+        notrunc: false,
+    };
+
+    for arg in args {
+        let (key, value) = arg
+            .split_once('=')
+            .ok_or_else(|| format!("unrecognized operand '{}'", arg))?;
+        let number = || -> Result<u64, String> {
+            parse_dd_size(value).ok_or_else(|| format!("invalid number: '{}'", value))
+        };
+        let block = || -> Result<usize, String> {
+            number()?
+                .try_into()
+                .ok()
+                .filter(|&n: &usize| n > 0)
+                .ok_or_else(|| format!("invalid number: '{}'", value))
+        };
+
+        match key {
+            "if" => opts.input = Some(value.to_string()),
+            "of" => opts.output = Some(value.to_string()),
+            "bs" => {
+                opts.ibs = block()?;
+                opts.obs = opts.ibs;
+            }
+            "ibs" => opts.ibs = block()?,
+            "obs" => opts.obs = block()?,
+            "count" => opts.count = Some(number()?),
+            "skip" => opts.skip = number()?,
+            "seek" => opts.seek = number()?,
+            "status" => match value {
+                "none" | "noxfer" | "progress" => opts.status = value.to_string(),
+                _ => return Err(format!("invalid status level: '{}'", value)),
+            },
+            "conv" => {
+                for conv in value.split(',') {
+                    match conv {
+                        "notrunc" => opts.notrunc = true,
+                        _ => return Err(format!("invalid conversion: '{}'", conv)),
+                    }
+                }
+            }
+            _ => return Err(format!("unrecognized operand '{}'", arg)),
+        }
+    }
+
+    Ok(opts)
+}
+
+/// Parses a size with an optional suffix: `K`, `M`, `G`, ... (powers of
+/// 1024, also written `KiB`), `KB`, `MB`, ... (powers of 1000).
+fn parse_size(value: &str) -> Option<u64> {
+    let digits_end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, suffix) = value.split_at(digits_end);
+    let number: u64 = digits.parse().ok()?;
+
+    let exponent = match suffix.chars().next() {
+        None => return Some(number),
+        Some(c) => "KMGTPE".find(c.to_ascii_uppercase())? as u32 + 1,
+    };
+    let base: u64 = match &suffix[1..] {
+        "" | "iB" => 1024,
+        "B" => 1000,
+        _ => return None,
+    };
+    number.checked_mul(base.checked_pow(exponent)?)
+}
+
+/// Parses a `dd` number, which additionally accepts the `c` (1), `w` (2),
+/// and `b` (512) suffixes.
+fn parse_dd_size(value: &str) -> Option<u64> {
+    let (digits, multiplier) = match value.chars().last()? {
+        'c' => (&value[..value.len() - 1], 1),
+        'w' => (&value[..value.len() - 1], 2),
+        'b' => (&value[..value.len() - 1], 512),
+        _ => return parse_size(value),
+    };
+    digits.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Performs the copy described by `opts`, returning the transfer report.
+fn dd_copy(opts: &DdOptions, ctx: &mut ShellContext) -> Result<String, String> {
+    use std::io::{Seek, SeekFrom};
+
+    let skip = opts
+        .skip
+        .checked_mul(opts.ibs as u64)
+        .ok_or_else(|| format!("invalid number: '{}'", opts.skip))?;
+    let seek = opts
+        .seek
+        .checked_mul(opts.obs as u64)
+        .ok_or_else(|| format!("invalid number: '{}'", opts.seek))?;
+
+    let mut input: Box<dyn Read> = match &opts.input {
+        Some(path) => {
+            let mut file =
+                File::open(path).map_err(|e| format!("failed to open '{}': {}", path, e))?;
+            file.seek(SeekFrom::Start(skip))
+                .map_err(|e| format!("'{}': cannot skip: {}", path, e))?;
+            Box::new(file)
+        }
+        None => {
+            io::copy(&mut (&mut *ctx.stdin).take(skip), &mut io::sink())
+                .map_err(|e| format!("standard input: cannot skip: {}", e))?;
+            Box::new(&mut *ctx.stdin)
+        }
+    };
+
+    let mut output: Box<dyn Write> = match &opts.output {
+        Some(path) => {
+            let mut file = fs::OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(|e| format!("failed to open '{}': {}", path, e))?;
+            if !opts.notrunc {
+                file.set_len(seek)
+                    .map_err(|e| format!("'{}': cannot truncate: {}", path, e))?;
+            }
+            file.seek(SeekFrom::Start(seek))
+                .map_err(|e| format!("'{}': cannot seek: {}", path, e))?;
+            Box::new(file)
+        }
+        None => Box::new(&mut *ctx.out.stdout),
+    };
+
+    let start = std::time::Instant::now();
+    let mut last_progress = start;
+    let mut buf = vec![0u8; opts.ibs];
+    let mut pending: Vec<u8> = Vec::with_capacity(opts.obs);
+    let (mut full_in, mut partial_in) = (0u64, 0u64);
+    let (mut full_out, mut partial_out) = (0u64, 0u64);
+    let mut bytes = 0u64;
+
+    while opts.count.is_none_or(|count| full_in + partial_in < count) {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("error reading: {}", e)),
+        };
+        if n == opts.ibs {
+            full_in += 1;
+        } else {
+            partial_in += 1;
+        }
+
+        pending.extend_from_slice(&buf[..n]);
+        while pending.len() >= opts.obs {
+            output
+                .write_all(&pending[..opts.obs])
+                .map_err(|e| format!("error writing: {}", e))?;
+            pending.drain(..opts.obs);
+            full_out += 1;
+        }
+        bytes += n as u64;
+
+        if opts.status == "progress" && last_progress.elapsed().as_secs() >= 1 {
+            last_progress = std::time::Instant::now();
+            let stderr = &mut ctx.out.stderr;
+            let _ = write!(stderr, "\r{}", dd_transfer_line(bytes, start.elapsed()));
+            let _ = stderr.flush();
+        }
+    }
+
+    if !pending.is_empty() {
+        output
+            .write_all(&pending)
+            .map_err(|e| format!("error writing: {}", e))?;
+        partial_out += 1;
+    }
+    output
+        .flush()
+        .map_err(|e| format!("error writing: {}", e))?;
+
+    if opts.status == "progress" && start.elapsed().as_secs() >= 1 {
+        let _ = writeln!(ctx.out.stderr);
+    }
+    let mut report = String::new();
+    if opts.status != "none" {
+        report = format!(
+            "{}+{} records in\n{}+{} records out",
+            full_in, partial_in, full_out, partial_out
+        );
+        if opts.status != "noxfer" {
+            report.push('\n');
+            report.push_str(&dd_transfer_line(bytes, start.elapsed()));
+        }
+    }
+    Ok(report)
+}
+
+/// Formats the bytes-copied, elapsed time, and throughput summary.
+fn dd_transfer_line(bytes: u64, elapsed: std::time::Duration) -> String {
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 {
+        format_human_size((bytes as f64 / secs) as u64)
+    } else {
+        "-".to_string()
+    };
+    format!("{} bytes copied, {:.6} s, {}B/s", bytes, secs, rate)
+}
+
+const SPLIT_SPEC: Spec = Spec {
+    name: "split",
+    summary: "split a file into pieces",
+    forms: &["[FILE [PREFIX]]"],
+    flags: &[
+        Flag::with_value(
+            &["-l", "--lines"],
+            "LINES",
+            "Put LINES lines in each piece.",
+        )
+        .or_default("1000"),
+        Flag::with_value(
+            &["-b", "--bytes"],
+            "SIZE",
+            "Put SIZE bytes in each piece. SIZE may end in K, M, or G.",
+        ),
+        Flag::new(
+            &["-d", "--numeric-suffixes"],
+            "Use numeric suffixes (00, 01, ...) instead of letters.",
+        ),
+        Flag::with_value(
+            &["-a", "--suffix-length"],
+            "LENGTH",
+            "Use suffixes of LENGTH characters.",
+        )
+        .or_default("2"),
+    ],
+};
+
+/// Splits a file into pieces named PREFIX followed by a suffix (`xaa`,
+/// `xab`, ...).
+///
+/// Supports the following flags:
+/// - `-l LINES`: Put LINES lines in each piece (default 1000).
+/// - `-b SIZE`: Put SIZE bytes in each piece (suffixes like `K`, `M` allowed).
+/// - `-d`: Use numeric suffixes (`00`, `01`, ...) instead of letters.
+/// - `-a LENGTH`: Use suffixes of LENGTH characters (default 2).
+///
+/// Reads standard input if FILE is missing or `-`. PREFIX defaults to `x`.
+fn split_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let numeric = args.has("-d");
+    let suffix_len = match args.get::<usize>("-a") {
+        Ok(Some(n)) if n > 0 => n,
+        _ => {
+            return CommandResult::with_stderr(format!(
+                "split: invalid suffix length: '{}'",
+                args.value("-a").unwrap_or_default()
+            ));
+        }
+    };
+    let lines = match args.get::<usize>("-l") {
+        Ok(Some(n)) if n > 0 => n,
+        _ => {
+            return CommandResult::with_stderr(format!(
+                "split: invalid number of lines: '{}'",
+                args.value("-l").unwrap_or_default()
+            ));
+        }
+    };
+    let bytes = match args.value("-b").map(parse_size) {
+        None => None,
+        Some(Some(n)) if n > 0 => Some(n as usize),
+        Some(_) => {
+            return CommandResult::with_stderr(format!(
+                "split: invalid number of bytes: '{}'",
+                args.value("-b").unwrap_or_default()
+            ));
+        }
+    };
+    if args.has("-l") && bytes.is_some() {
+        return CommandResult::with_stderr("split: cannot split in more than one way".to_string());
+    }
+    let operands = args.operands;
+    if operands.len() > 2 {
+        return CommandResult::with_stderr(format!("split: extra operand '{}'", operands[2]));
+    }
+
+    let input_name = operands.first().map_or("-", String::as_str);
+    let prefix = operands.get(1).map_or("x", String::as_str);
+    let data = match read_input(input_name, ctx.stdin) {
+        Ok(data) => data,
+        Err(e) => return CommandResult::with_stderr(format!("split: {}: {}", input_name, e)),
+    };
+
+    let pieces = match bytes {
+        Some(size) => data.chunks(size).collect::<Vec<_>>(),
+        None => split_by_lines(&data, lines),
+    };
+
+    for (i, piece) in pieces.into_iter().enumerate() {
+        let Some(suffix) = split_suffix(i, suffix_len, numeric) else {
+            return CommandResult::with_stderr("split: output file suffixes exhausted".to_string());
+        };
+        let name = format!("{}{}", prefix, suffix);
+        if let Err(e) = fs::write(&name, piece) {
+            return CommandResult::with_stderr(format!("split: {}: {}", name, e));
+        }
+    }
+
+    CommandResult::new()
+}
+
+/// Groups `data` into slices of `lines` newline-terminated lines each.
+fn split_by_lines(data: &[u8], lines: usize) -> Vec<&[u8]> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    let mut count = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        if byte == b'\n' {
+            count += 1;
+            if count == lines {
+                pieces.push(&data[start..=i]);
+                start = i + 1;
+                count = 0;
+            }
+        }
+    }
+    if start < data.len() {
+        pieces.push(&data[start..]);
+    }
+    pieces
+}
+
+/// Returns the `index`-th output suffix of `len` characters, or `None` once
+/// every suffix of that length has been used.
+fn split_suffix(index: usize, len: usize, numeric: bool) -> Option<String> {
+    let (base, first) = if numeric { (10, b'0') } else { (26, b'a') };
+    let mut rest = index;
+    let mut suffix = vec![first; len];
+    for slot in suffix.iter_mut().rev() {
+        *slot = first + (rest % base) as u8;
+        rest /= base;
+    }
+    if rest > 0 {
+        return None;
+    }
+    String::from_utf8(suffix).ok()
+}
+
+/// How `truncate` derives a file's new size from its current size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SizeAdjust {
+    Set(u64),
+    Extend(u64),
+    Reduce(u64),
+    AtMost(u64),
+    AtLeast(u64),
+    RoundDown(u64),
+    RoundUp(u64),
+}
+
+impl SizeAdjust {
+    /// Parses a SIZE operand such as `10M`, `+1K`, `-5`, `<100`, or `%4K`.
+    fn parse(value: &str) -> Option<Self> {
+        let (kind, rest): (fn(u64) -> Self, &str) = match value.chars().next()? {
+            '+' => (Self::Extend, &value[1..]),
+            '-' => (Self::Reduce, &value[1..]),
+            '<' => (Self::AtMost, &value[1..]),
+            '>' => (Self::AtLeast, &value[1..]),
+            '/' => (Self::RoundDown, &value[1..]),
+            '%' => (Self::RoundUp, &value[1..]),
+            _ => (Self::Set, value),
+        };
+        let size = parse_size(rest)?;
+        let adjust = kind(size);
+        match adjust {
+            Self::RoundDown(0) | Self::RoundUp(0) => None,
+            _ => Some(adjust),
+        }
+    }
+
+    /// Returns the new size, or `None` if rounding up overflows.
+    fn apply(self, current: u64) -> Option<u64> {
+        match self {
+            Self::Set(n) => Some(n),
+            Self::Extend(n) => Some(current.saturating_add(n)),
+            Self::Reduce(n) => Some(current.saturating_sub(n)),
+            Self::AtMost(n) => Some(current.min(n)),
+            Self::AtLeast(n) => Some(current.max(n)),
+            Self::RoundDown(n) => Some(current / n * n),
+            Self::RoundUp(n) => current.div_ceil(n).checked_mul(n),
+        }
+    }
+}
+
+/// Shrinks or extends files to a given size, creating them if missing.
+///
+/// Supports the following options:
+/// - `-s SIZE`: Set or adjust the size. A leading `+`/`-` extends/reduces
+///   by SIZE, `<`/`>` caps/raises to SIZE, and `/`/`%` round down/up to a
+///   multiple of SIZE. Suffixes like `K`, `M`, `G` are allowed.
+/// - `-r RFILE`: Use the size of RFILE.
+/// - `-c`: Do not create missing files.
+///
+/// Extending a file leaves a hole that reads back as zero bytes.
+fn truncate_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    let mut no_create = false;
+    let mut size_arg = None;
+    let mut reference = None;
+    let mut files = Vec::new();
+
+    let mut iter = args.into_iter();
+    while let Some(arg) = iter.next() {
+        let mut value_for = |inline: &str, flag: &str| -> Result<String, String> {
+            if !inline.is_empty() {
+                return Ok(inline.trim_start_matches('=').to_string());
+            }
+            iter.next()
+                .ok_or_else(|| format!("option requires an argument -- '{}'", flag))
+        };
+        let parsed = match arg.as_str() {
+            "-c" | "--no-create" => {
+                no_create = true;
+                Ok(())
+            }
+            "--" => {
+                files.extend(iter.by_ref());
+                break;
+            }
+            a if a.starts_with("--size") => value_for(&a[6..], "s").map(|v| size_arg = Some(v)),
+            a if a.starts_with("-s") => value_for(&a[2..], "s").map(|v| size_arg = Some(v)),
+            a if a.starts_with("--reference") => {
+                value_for(&a[11..], "r").map(|v| reference = Some(v))
+            }
+            a if a.starts_with("-r") => value_for(&a[2..], "r").map(|v| reference = Some(v)),
+            a if a.starts_with('-') && a.len() > 1 => {
+                Err(format!("invalid option -- '{}'", &a[1..]))
+            }
+            _ => {
+                files.push(arg);
+                Ok(())
+            }
+        };
+        if let Err(e) = parsed {
+            return CommandResult::with_stderr(format!("truncate: {}", e));
+        }
+    }
+
+    let adjust = match (size_arg, reference) {
+        (Some(_), Some(_)) => {
+            return CommandResult::with_stderr(
+                "truncate: you must specify either a size or a reference file, not both"
+                    .to_string(),
+            );
+        }
+        (None, None) => {
+            return CommandResult::with_stderr(
+                "truncate: you must specify either '-s SIZE' or '-r RFILE'".to_string(),
+            );
+        }
+        (Some(size), None) => match SizeAdjust::parse(&size) {
+            Some(adjust) => adjust,
+            None => {
+                return CommandResult::with_stderr(format!("truncate: invalid number: '{}'", size));
+            }
+        },
+        (None, Some(rfile)) => match fs::metadata(&rfile) {
+            Ok(meta) => SizeAdjust::Set(meta.len()),
+            Err(e) => {
+                return CommandResult::with_stderr(format!(
+                    "truncate: cannot stat '{}': {}",
+                    rfile, e
+                ));
+            }
+        },
+    };
+
+    if files.is_empty() {
+        return CommandResult::with_stderr("truncate: missing file operand".to_string());
+    }
+
+    let mut result = CommandResult::new();
+    for path in files {
+        if no_create && !Path::new(&path).exists() {
+            continue;
+        }
+        let outcome = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .and_then(|file| {
+                let current = file.metadata()?.len();
+                match adjust.apply(current) {
+                    Some(size) => file.set_len(size).map(|()| true),
+                    None => Ok(false),
+                }
+            });
+        match outcome {
+            Ok(true) => {}
+            Ok(false) => result.append_stderr(&format!(
+                "truncate: overflow rounding up size of file '{}'",
+                path
+            )),
+            Err(e) => result.append_stderr(&format!(
+                "truncate: cannot open '{}' for writing: {}",
+                path, e
+            )),
+        }
+    }
+    result
+}
+
+const SHRED_SPEC: Spec = Spec {
+    name: "shred",
+    summary: "overwrite a file to hide its contents",
+    forms: &["FILE..."],
+    flags: &[
+        Flag::with_value(
+            &["-n", "--iterations"],
+            "PASSES",
+            "Overwrite PASSES times with random data.",
+        )
+        .or_default("3"),
+        Flag::new(&["-u", "--remove"], "Remove the file afterwards."),
+        Flag::new(
+            &["-z", "--zero"],
+            "Finish with a pass of zeros to hide the shredding.",
+        ),
+        Flag::new(&["-v", "--verbose"], "Report each pass as it starts."),
+    ],
+};
+
+/// Overwrites files with random data so their contents are hard to recover.
+///
+/// Supports the following flags:
+/// - `-n PASSES`: Overwrite PASSES times (default 3).
+/// - `-z`: Finish with a pass of zeros to hide the shredding.
+/// - `-u`: Remove the file afterwards.
+/// - `-v`: Report each pass on stderr as it starts.
+///
+/// This is best effort: journaling and copy-on-write filesystems, and SSD
+/// wear levelling, may keep old copies of the data elsewhere.
+fn shred_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let Ok(Some(passes)) = args.get::<usize>("-n") else {
+        return CommandResult::with_stderr(format!(
+            "shred: invalid number of passes: '{}'",
+            args.value("-n").unwrap_or_default()
+        ));
+    };
+    let zero = args.has("-z");
+    let remove = args.has("-u");
+    let verbose = args.has("-v");
+
+    let mut rng = crate::random::Rng::from_entropy();
+    let mut result = CommandResult::new();
+    let total = passes + zero as usize;
+
+    for path in args.operands {
+        let outcome = (|| -> io::Result<()> {
+            let mut file = fs::OpenOptions::new().write(true).open(&path)?;
+            let size = file.metadata()?.len();
+
+            for pass in 1..=total {
+                let random = pass <= passes;
+                if verbose {
+                    let kind = if random { "random" } else { "000000" };
+                    let _ = ctx.out.error(&format!(
+                        "shred: {}: pass {}/{} ({})...",
+                        path, pass, total, kind
+                    ));
+                }
+                shred_pass(&mut file, size, random.then_some(&mut rng))?;
+            }
+            Ok(())
+        })();
+
+        if let Err(e) = outcome {
+            result.append_stderr(&format!("shred: {}: {}", path, e));
+            continue;
+        }
+
+        if remove {
+            if verbose {
+                let _ = ctx.out.error(&format!("shred: {}: removing", path));
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                result.append_stderr(&format!("shred: {}: failed to remove: {}", path, e));
+            }
+        }
+    }
+
+    result
+}
+
+/// Overwrites the first `size` bytes of `file` with random bytes (or zeros
+/// when `rng` is `None`) and syncs the data to disk.
+fn shred_pass(
+    file: &mut File,
+    size: u64,
+    mut rng: Option<&mut crate::random::Rng>,
+) -> io::Result<()> {
+    use std::io::{Seek, SeekFrom};
+
+    let mut buf = vec![0u8; 64 * 1024];
+    file.seek(SeekFrom::Start(0))?;
+    let mut remaining = size;
+    while remaining > 0 {
+        let n = remaining.min(buf.len() as u64) as usize;
+        match rng.as_deref_mut() {
+            Some(rng) => rng.fill_bytes(&mut buf[..n]),
+            None => buf[..n].fill(0),
+        }
+        file.write_all(&buf[..n])?;
+        remaining -= n as u64;
+    }
+    file.sync_data()
+}
+
+/// Runs a command with hangup signals ignored, so it finishes even if the
+/// terminal is closed.
+///
+/// If stdout is a terminal, output is appended to `nohup.out` in the current
+/// directory (or `$HOME/nohup.out`), and stderr follows it if it is a
+/// terminal too. Terminal input is replaced with `/dev/null`.
+fn nohup_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    use std::io::IsTerminal;
+
+    let Some(call) = CommandCall::from_tokens(args) else {
+        return CommandResult::with_stderr("nohup: missing operand".to_string());
+    };
+
+    #[cfg(unix)]
+    {
+        let _hup = crate::signals::IgnoreGuard::install(libc::SIGHUP);
+        let mut redirects = Vec::new();
+        let mut notice = String::new();
+        // Where the command's output goes, and whether its errors go too
+        let mut log = None;
+
+        if io::stdin().is_terminal()
+            && let Ok(null) = File::open("/dev/null")
+            && let Ok(redirect) = crate::process::FdRedirect::new(libc::STDIN_FILENO, &null)
+        {
+            redirects.push(redirect);
+            notice.push_str("ignoring input and ");
+        }
+
+        if io::stdout().is_terminal() {
+            let (name, file) = match open_nohup_out(&ctx.state.vars) {
+                Ok(opened) => opened,
+                Err(e) => {
+                    return CommandResult::with_stderr(format!(
+                        "nohup: failed to open 'nohup.out': {}",
+                        e
+                    ));
+                }
+            };
+            let _ = ctx
+                .out
+                .error(&format!("nohup: {}appending output to '{}'", notice, name));
+            let _ = ctx.out.stdout.flush();
+            match crate::process::FdRedirect::new(libc::STDOUT_FILENO, &file) {
+                Ok(redirect) => redirects.push(redirect),
+                Err(e) => return CommandResult::with_stderr(format!("nohup: {}", e)),
+            }
+            let mut errors_too = false;
+            if io::stderr().is_terminal()
+                && let Ok(redirect) = crate::process::FdRedirect::new(libc::STDERR_FILENO, &file)
+            {
+                redirects.push(redirect);
+                errors_too = true;
+            }
+            log = Some((file, errors_too));
+        } else if !notice.is_empty() {
+            let _ = ctx.out.error("nohup: ignoring input");
+        }
+
+        let result = ctx.capture(call);
+        let Some((mut file, errors_too)) = log else {
+            return result;
+        };
+
+        // Programs the command started wrote to the redirected descriptors;
+        // what it collected itself goes to the same file.
+        let _ = file.write_all(&result.stdout);
+        if !result.stderr.is_empty() {
+            let _ = if errors_too {
+                writeln!(file, "{}", result.stderr)
+            } else {
+                ctx.out.error(&result.stderr)
+            };
+        }
+        drop(redirects);
+        CommandResult {
+            status: result.status,
+            should_exit: result.should_exit,
+            ..CommandResult::new()
+        }
+    }
+
+    #[cfg(not(unix))]
+    ctx.capture(call)
+}
+
+/// Opens `nohup.out` for appending in the current directory, falling back to
+/// the home directory. Returns the name used along with the file.
+#[cfg(unix)]
+fn open_nohup_out(vars: &Variables) -> io::Result<(String, File)> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let open = |path: &str| {
+        fs::OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)
+    };
+    match open("nohup.out") {
+        Ok(file) => Ok(("nohup.out".to_string(), file)),
+        Err(e) => match vars.get("HOME") {
+            Some(home) => {
+                let path = format!("{}/nohup.out", home);
+                open(&path).map(|file| (path, file))
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Options for a `timeout` invocation.
+#[derive(Debug)]
+struct TimeoutOptions {
+    signal: i32,
+    kill_after: Option<std::time::Duration>,
+    verbose: bool,
+    /// Whether to end with the command's own status even if it timed out.
+    preserve_status: bool,
+    duration: std::time::Duration,
+    command: Vec<String>,
+}
+
+/// Runs a command, signalling it if it is still running after DURATION.
+///
+/// Supports the following options:
+/// - `-s SIGNAL`: Signal to send on timeout (default TERM).
+/// - `-k DURATION`: Send KILL if the command is still running this long
+///   after the first signal.
+/// - `-v`: Report on stderr when a signal is sent.
+/// - `--preserve-status`: End with the command's status even if it timed out.
+///
+/// Durations are numbers with an optional `s`, `m`, `h`, or `d` suffix.
+/// The command runs in a forked child of the shell so it can be signalled.
+///
+/// As in GNU `timeout`, the status is the command's own, or 124 if it
+/// timed out, 137 if it had to be killed, and 125 if `timeout` itself failed.
+fn timeout_callback(
+    _flags: Vec<String>,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    let failed = |msg: String| CommandResult {
+        status: 125,
+        ..CommandResult::with_stderr(msg)
+    };
+    let opts = match parse_timeout_options(args) {
+        Ok(opts) => opts,
+        Err(e) => return failed(format!("timeout: {}", e)),
+    };
+    let Some(call) = CommandCall::from_tokens(opts.command.clone()) else {
+        return failed("timeout: missing command".to_string());
+    };
+
+    #[cfg(unix)]
+    {
+        use crate::process::{WaitStatus, fork_child, send_signal, signal_name, wait_child};
+
+        let pid = match fork_child(|| run_call_in_child(ctx, call)) {
+            Ok(pid) => pid,
+            Err(e) => return failed(format!("timeout: fork failed: {}", e)),
+        };
+
+        let mut result = CommandResult::new();
+        let mut timed_out = false;
+        let wait = |timeout| wait_child(pid, timeout).map_err(|e| format!("timeout: {}", e));
+        let send = |signal: i32, result: &mut CommandResult| {
+            if opts.verbose {
+                result.append_notice(&format!(
+                    "timeout: sending signal {} to command '{}'",
+                    signal_name(signal),
+                    opts.command[0]
+                ));
+            }
+            let _ = send_signal(pid, signal);
+        };
+
+        let status = match wait(Some(opts.duration)) {
+            Ok(Some(status)) => Ok(status),
+            Ok(None) => {
+                timed_out = true;
+                send(opts.signal, &mut result);
+                match opts.kill_after {
+                    Some(grace) => match wait(Some(grace)) {
+                        Ok(Some(status)) => Ok(status),
+                        Ok(None) => {
+                            send(libc::SIGKILL, &mut result);
+                            wait(None).map(|s| s.unwrap_or(WaitStatus::Signaled(libc::SIGKILL)))
+                        }
+                        Err(e) => Err(e),
+                    },
+                    None => wait(None).map(|s| s.unwrap_or(WaitStatus::Signaled(opts.signal))),
+                }
+            }
+            Err(e) => Err(e),
+        };
+
+        result.status = match status {
+            Ok(WaitStatus::Signaled(libc::SIGKILL)) if timed_out => 128 + libc::SIGKILL,
+            _ if timed_out && !opts.preserve_status => 124,
+            Ok(WaitStatus::Exited(code)) => code,
+            Ok(WaitStatus::Signaled(signal)) => 128 + signal,
+            Err(e) => {
+                result.append_stderr(&e);
+                125
+            }
+        };
+        result
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (opts, call, ctx);
+        failed("timeout: not supported on this platform".to_string())
+    }
+}
+
+/// Runs a command line inside a forked child, writing its output to the
+/// context's writers. Returns the child's exit status.
+#[cfg(unix)]
+fn run_call_in_child(ctx: &mut ShellContext, call: CommandCall) -> i32 {
+    let commands = ctx.commands;
+    let res = commands.run_call(ctx, call);
+    let _ = ctx.out.write_result(&res);
+    let _ = ctx.out.stdout.flush();
+    let _ = ctx.out.stderr.flush();
+    res.status
+}
+
+/// Parses `timeout` options, the duration, and the command.
+fn parse_timeout_options(args: Vec<String>) -> Result<TimeoutOptions, String> {
+    let mut signal = None;
+    let mut kill_after = None;
+    let mut verbose = false;
+    let mut preserve_status = false;
+    let mut iter = args.into_iter();
+
+    let duration = loop {
+        let Some(arg) = iter.next() else {
+            return Err("missing operand".to_string());
+        };
+        let mut value_for = |inline: &str, flag: &str| -> Result<String, String> {
+            if !inline.is_empty() {
+                return Ok(inline.trim_start_matches('=').to_string());
+            }
+            iter.next()
+                .ok_or_else(|| format!("option requires an argument -- '{}'", flag))
+        };
+        match arg.as_str() {
+            "-v" | "--verbose" => verbose = true,
+            "--preserve-status" => preserve_status = true,
+            "--foreground" => {}
+            a if a.starts_with("--signal") || a.starts_with("-s") => {
+                let inline = a.strip_prefix("--signal").unwrap_or(&a[2..]);
+                let value = value_for(inline, "s")?;
+                signal = Some(value);
+            }
+            a if a.starts_with("--kill-after") || a.starts_with("-k") => {
+                let inline = a.strip_prefix("--kill-after").unwrap_or(&a[2..]);
+                let value = value_for(inline, "k")?;
+                kill_after = Some(
+                    parse_duration(&value)
+                        .ok_or_else(|| format!("invalid time interval '{}'", value))?,
+                );
+            }
+            a if a.starts_with('-') && a.len() > 1 => {
+                return Err(format!("invalid option -- '{}'", &a[1..]));
+            }
+            _ => {
+                break parse_duration(&arg)
+                    .ok_or_else(|| format!("invalid time interval '{}'", arg))?;
+            }
+        }
+    };
+
+    let command: Vec<String> = iter.collect();
+    if command.is_empty() {
+        return Err("missing command".to_string());
+    }
+
+    #[cfg(unix)]
+    let signal = match signal {
+        None => libc::SIGTERM,
+        Some(name) => crate::process::parse_signal(&name)
+            .ok_or_else(|| format!("{}: invalid signal", name))?,
+    };
+    #[cfg(not(unix))]
+    let signal = signal.map_or(15, |_| 15);
+
+    Ok(TimeoutOptions {
+        signal,
+        kill_after,
+        verbose,
+        preserve_status,
+        duration,
+        command,
+    })
+}
+
+/// Parses a duration such as `10`, `1.5s`, `2m`, `1h`, or `1d`.
+fn parse_duration(value: &str) -> Option<std::time::Duration> {
+    let (number, multiplier) = match value.chars().last()? {
+        's' => (&value[..value.len() - 1], 1.0),
+        'm' => (&value[..value.len() - 1], 60.0),
+        'h' => (&value[..value.len() - 1], 3600.0),
+        'd' => (&value[..value.len() - 1], 86_400.0),
+        _ => (value, 1.0),
+    };
+    let secs: f64 = number.parse().ok()?;
+    std::time::Duration::try_from_secs_f64(secs * multiplier).ok()
+}
+
+/// Runs a command line later in the current session.
+///
+/// WHEN is a delay in the form `timeout` accepts (`90`, `10m`, `1.5h`) or a
+/// clock time `HH:MM[:SS]`, which means tomorrow if it has already passed
+/// today. Jobs end with the shell.
+///
+/// The command runs between prompts once it is due: straight away if the
+/// shell is waiting for input, otherwise when the line being run finishes.
+/// It runs with the variables and aliases the shell had when it was
+/// scheduled, and in the directory it was scheduled from, so it cannot
+/// change those of the shell.
+///
+/// `-l` lists the pending jobs and `-c ID...` cancels them.
+fn schedule_callback(
+    _flags: Vec<String>,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    let jobs = &ctx.state.jobs;
+    match args.first().map(String::as_str) {
+        Some("-l" | "--list") => {
+            let listing: String = jobs
+                .list()
+                .iter()
+                .map(|job| {
+                    format!(
+                        "{:>3}  {}  {}\n",
+                        job.id,
+                        job.due.format("%Y-%m-%d %H:%M:%S"),
+                        job.label
+                    )
+                })
+                .collect();
+            return CommandResult::with_stdout(listing);
+        }
+        Some("-c" | "--cancel") => {
+            if args.len() < 2 {
+                return CommandResult::with_stderr("schedule: missing job id".to_string());
+            }
+            let mut result = CommandResult::new();
+            for id in &args[1..] {
+                if !id.parse().is_ok_and(|id| jobs.cancel(id)) {
+                    result.append_stderr(&format!("schedule: {}: no such job", id));
+                }
+            }
+            return result;
+        }
+        _ => {}
+    }
+
+    let Some(due) = parse_schedule_time(&args[0], Local::now()) else {
+        return CommandResult::with_stderr(format!("schedule: invalid time '{}'", args[0]));
+    };
+    if args.len() < 2 {
+        return CommandResult::with_stderr("schedule: missing command".to_string());
+    }
+    // A single argument is a quoted command line; several are its words
+    let line = if args.len() == 2 {
+        args[1].clone()
+    } else {
+        args[1..].join(" ")
+    };
+
+    let id = jobs.schedule(due, line, ctx.state.clone(), env::current_dir().ok());
+    CommandResult::with_stdout(format!(
+        "job {} at {}\n",
+        id,
+        due.format("%Y-%m-%d %H:%M:%S")
+    ))
+}
+
+/// Works out when a job given `schedule` WHEN is due: after a delay, or at
+/// the next occurrence of a clock time.
+fn parse_schedule_time(spec: &str, now: DateTime<Local>) -> Option<DateTime<Local>> {
+    use chrono::{NaiveTime, TimeZone};
+
+    if !spec.contains(':') {
+        let delay = chrono::Duration::from_std(parse_duration(spec)?).ok()?;
+        return now.checked_add_signed(delay);
+    }
+    let time = NaiveTime::parse_from_str(spec, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(spec, "%H:%M"))
+        .ok()?;
+    let mut date = now.date_naive();
+    loop {
+        // A clock time skipped by a DST change falls through to the next day
+        if let Some(due) = Local.from_local_datetime(&date.and_time(time)).earliest()
+            && due > now
+        {
+            return Some(due);
+        }
+        date = date.succ_opt()?;
+    }
+}
+
+/// Defines aliases, or shows them.
+///
+/// Each `NAME=VALUE` argument makes NAME an alias for VALUE, and each NAME
+/// on its own shows that alias. Without arguments, all aliases are shown,
+/// in a form that can be entered again. When a command line starts with an
+/// alias, the alias is replaced by its value.
+fn alias_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let aliases = &mut ctx.state.aliases;
+    let show =
+        |name: &str, value: &str| format!("alias {}='{}'\n", name, value.replace('\'', "'\\''"));
+    if args.is_empty() {
+        let listing: String = aliases
+            .iter()
+            .map(|(name, value)| show(name, value))
+            .collect();
+        return CommandResult::with_stdout(listing);
+    }
+
+    let mut result = CommandResult::new();
+    for arg in &args {
+        match arg.split_once('=') {
+            Some((name, _)) if name.is_empty() || name.contains(char::is_whitespace) => {
+                result.push_error(CommandError::Usage(format!(
+                    "alias: '{}': invalid alias name",
+                    name
+                )));
+            }
+            Some((name, value)) => {
+                aliases.insert(name.to_string(), value.to_string());
+            }
+            None => match aliases.get(arg) {
+                Some(value) => result.stdout.extend_from_slice(show(arg, value).as_bytes()),
+                None => {
+                    result.push_error(CommandError::Failed(format!("alias: {}: not found", arg)))
+                }
+            },
+        }
+    }
+    result
+}
+
+/// Removes each alias named, or with `-a`, all of them.
+fn unalias_callback(
+    flags: Vec<String>,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    let aliases = &mut ctx.state.aliases;
+    if flags.iter().any(|f| f == "-a") {
+        aliases.clear();
+        return CommandResult::new();
+    }
+    let mut result = CommandResult::new();
+    for name in &args {
+        if aliases.remove(name).is_none() {
+            result.push_error(CommandError::Failed(format!(
+                "unalias: {}: not found",
+                name
+            )));
+        }
+    }
+    result
+}
+
+const HISTORY_SPEC: Spec = Spec {
+    name: "history",
+    summary: "show or summarize the command history",
+    forms: &["[COUNT]"],
+    flags: &[
+        Flag::new(
+            &["--stats"],
+            "Summarize the history instead: the most used commands, the directories most \
+             worked in, and the number of lines entered per day.",
+        ),
+        Flag::with_value(
+            &["-n"],
+            "COUNT",
+            "Show COUNT rows in each part of the summary. The default is 10.",
+        ),
+    ],
+};
+
+/// Shows the persistent command history, numbered oldest first.
+///
+/// With COUNT, only the last COUNT entries are shown.
+///
+/// Supports the following options:
+/// - `--stats`: Summarize the history instead: the most used commands, the
+///   directories most worked in, and the number of lines entered per day.
+/// - `-n COUNT`: How many rows each part of the summary shows (default 10).
+fn history_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let count_arg = args
+        .value("-n")
+        .or(args.operands.first().map(String::as_str));
+    let count = match count_arg.map(|n| n.parse::<usize>()) {
+        None => None,
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            return CommandResult::with_stderr(format!(
+                "history: invalid count '{}'",
+                count_arg.unwrap_or_default()
+            ));
+        }
+    };
+    let entries = match crate::history::load(&ctx.state.vars) {
+        Ok(entries) => entries,
+        Err(e) => return CommandResult::with_stderr(format!("history: {}", e)),
+    };
+
+    if args.has("--stats") {
+        let stats = crate::history::stats(&entries);
+        let home = ctx.state.vars.get("HOME").unwrap_or_default();
+        return CommandResult::with_stdout(format_history_stats(
+            &stats,
+            count.unwrap_or(10),
+            &home,
+        ));
+    }
+    let skip = entries.len() - count.unwrap_or(entries.len()).min(entries.len());
+    let listing: String = entries
+        .iter()
+        .enumerate()
+        .skip(skip)
+        .map(|(i, entry)| format!("{:>5}  {}\n", i + 1, entry.line))
+        .collect();
+    CommandResult::with_stdout(listing)
+}
+
+/// Renders `history --stats`, showing `count` rows per section. Directories
+/// under `home` are shown relative to `~`, as in the prompt.
+fn format_history_stats(stats: &crate::history::Stats, count: usize, home: &str) -> String {
+    let Some((first, last)) = stats.span else {
+        return "No history yet\n".to_string();
+    };
+    let mut out = format!(
+        "{} entries from {} to {}\n",
+        stats.entries,
+        first.format("%Y-%m-%d %H:%M"),
+        last.format("%Y-%m-%d %H:%M")
+    );
+
+    out.push_str("\nMost used commands:\n");
+    for (name, n) in stats.commands.iter().take(count) {
+        out.push_str(&format!("{:>7}  {}\n", n, name));
+    }
+
+    out.push_str("\nMost visited directories:\n");
+    for (dir, n) in stats.dirs.iter().take(count) {
+        let dir = match dir.strip_prefix(home) {
+            Some(rest) if !home.is_empty() && (rest.is_empty() || rest.starts_with('/')) => {
+                format!("~{}", rest)
+            }
+            _ => dir.clone(),
+        };
+        out.push_str(&format!("{:>7}  {}\n", n, dir));
+    }
+
+    // The most recent days, with bars scaled to the busiest of them
+    out.push_str("\nCommands per day:\n");
+    let days = &stats.days[stats.days.len().saturating_sub(count)..];
+    let busiest = days.iter().map(|&(_, n)| n).max().unwrap_or(1);
+    for (day, n) in days {
+        let bar = "#".repeat((n * 40).div_ceil(busiest));
+        out.push_str(&format!("  {}  {:>5}  {}\n", day, n, bar));
+    }
+    out
+}
+
+/// Runs a command with an adjusted scheduling priority.
+///
+/// Supports `-n ADJUSTMENT` (also `-ADJUSTMENT` and `--adjustment=N`), which
+/// is added to the current nice value (default 10). Without a command,
+/// prints the current nice value. The command runs in a forked child so the
+/// shell's own priority is unchanged.
+fn nice_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let (adjustment, command) = match parse_nice_args(args) {
+        Ok(parsed) => parsed,
+        Err(e) => return CommandResult::with_stderr(format!("nice: {}", e)),
+    };
+
+    #[cfg(unix)]
+    {
+        use crate::process::{PriorityTarget, fork_child, get_priority, set_priority, wait_child};
+
+        let current = match get_priority(PriorityTarget::Process, 0) {
+            Ok(priority) => priority,
+            Err(e) => return CommandResult::with_stderr(format!("nice: {}", e)),
+        };
+        let Some(call) = CommandCall::from_tokens(command) else {
+            return CommandResult::with_stdout(format!("{}\n", current));
+        };
+
+        let priority = (current + adjustment).clamp(-20, 19);
+        let child = fork_child(|| {
+            if let Err(e) = set_priority(PriorityTarget::Process, 0, priority) {
+                let _ = ctx.out.error(&format!("nice: cannot set niceness: {}", e));
+            }
+            run_call_in_child(ctx, call)
+        });
+        match child.and_then(|pid| wait_child(pid, None)) {
+            Ok(_) => CommandResult::new(),
+            Err(e) => CommandResult::with_stderr(format!("nice: {}", e)),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (adjustment, command, ctx);
+        CommandResult::with_stderr("nice: not supported on this platform".to_string())
+    }
+}
+
+/// Splits `nice` arguments into the adjustment and the command to run.
+fn parse_nice_args(args: Vec<String>) -> Result<(i32, Vec<String>), String> {
+    let mut adjustment = 10;
+    let mut iter = args.into_iter().peekable();
+
+    while let Some(arg) = iter.peek() {
+        let value = if arg == "-n" || arg == "--adjustment" {
+            iter.next();
+            iter.next()
+                .ok_or_else(|| "option requires an argument -- 'n'".to_string())?
+        } else if let Some(value) = arg.strip_prefix("--adjustment=") {
+            let value = value.to_string();
+            iter.next();
+            value
+        } else if let Some(value) = arg.strip_prefix("-n") {
+            let value = value.to_string();
+            iter.next();
+            value
+        } else if arg.starts_with('-') && arg[1..].parse::<i32>().is_ok() {
+            // Historical `nice -5 cmd` / `nice --5 cmd` form
+            let value = arg[1..].to_string();
+            iter.next();
+            value
+        } else {
+            break;
+        };
+        adjustment = value
+            .parse()
+            .map_err(|_| format!("invalid adjustment '{}'", value))?;
+    }
+
+    Ok((adjustment, iter.collect()))
+}
+
+/// Changes the nice value of running processes, process groups, or users.
+///
+/// IDs are process ids by default; `-g` switches to process groups and `-u`
+/// to users (names or uids), `-p` back to processes. Each change is reported
+/// with the old and new priority.
+fn renice_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    let mut args = args.into_iter().peekable();
+    if args.peek().is_some_and(|a| a == "-n" || a == "--priority") {
+        args.next();
+    }
+    let Some(value) = args.next() else {
+        return CommandResult::with_stderr("renice: missing priority".to_string());
+    };
+    let Ok(priority) = value.parse::<i32>() else {
+        return CommandResult::with_stderr(format!("renice: invalid priority '{}'", value));
+    };
+
+    #[cfg(unix)]
+    {
+        use crate::process::{PriorityTarget, get_priority, set_priority, user_id};
+
+        let mut result = CommandResult::new();
+        let mut target = PriorityTarget::Process;
+        let mut any_id = false;
+        for arg in args {
+            match arg.as_str() {
+                "-p" | "--pid" => target = PriorityTarget::Process,
+                "-g" | "--pgrp" => target = PriorityTarget::Group,
+                "-u" | "--user" => target = PriorityTarget::User,
+                _ => {
+                    any_id = true;
+                    let (id, label) = match target {
+                        PriorityTarget::User => (user_id(&arg), "user ID"),
+                        PriorityTarget::Group => (arg.parse().ok(), "process group ID"),
+                        PriorityTarget::Process => (arg.parse().ok(), "process ID"),
+                    };
+                    let Some(id) = id else {
+                        result.append_stderr(&format!("renice: invalid ID '{}'", arg));
+                        continue;
+                    };
+                    let changed = get_priority(target, id).and_then(|old| {
+                        set_priority(target, id, priority)?;
+                        Ok((old, get_priority(target, id)?))
+                    });
+                    match changed {
+                        Ok((old, new)) => result.stdout.extend_from_slice(
+                            format!(
+                                "{} ({}) old priority {}, new priority {}\n",
+                                arg, label, old, new
+                            )
+                            .as_bytes(),
+                        ),
+                        Err(e) => result.append_stderr(&format!(
+                            "renice: failed to set priority for {} ({}): {}",
+                            arg, label, e
+                        )),
+                    }
+                }
+            }
+        }
+        if !any_id {
+            result.append_stderr("renice: missing ID");
+        }
+        result
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (priority, args);
+        CommandResult::with_stderr("renice: not supported on this platform".to_string())
+    }
+}
+
+/// Options shared by `pgrep` and `pkill`.
+#[derive(Debug, Default)]
+struct PgrepOptions {
+    pattern: Option<String>,
+    full: bool,
+    exact: bool,
+    ignore_case: bool,
+    invert: bool,
+    newest: bool,
+    oldest: bool,
+    users: Vec<String>,
+    // pgrep output
+    list_name: bool,
+    list_full: bool,
+    count: bool,
+    delimiter: Option<String>,
+    // pkill
+    signal: Option<String>,
+    echo: bool,
+}
+
+/// Parses `pgrep`/`pkill` arguments. Short flags may be combined (`-fl`),
+/// and `-u`/`-d` take their value from the rest of the word or the next
+/// argument. For `pkill`, a first argument such as `-9` or `-HUP` names the
+/// signal.
+fn parse_pgrep_options(args: Vec<String>, kill: bool) -> Result<PgrepOptions, String> {
+    let mut opts = PgrepOptions::default();
+    let mut iter = args.into_iter().enumerate().peekable();
+    let mut only_patterns = false;
+
+    while let Some((i, arg)) = iter.next() {
+        if only_patterns || arg == "-" || !arg.starts_with('-') {
+            if opts.pattern.replace(arg).is_some() {
+                return Err("only one pattern can be provided".to_string());
+            }
+            continue;
+        }
+        if arg == "--" {
+            only_patterns = true;
+            continue;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| iter.next().map(|(_, v)| v))
+                    .ok_or_else(|| format!("option '--{}' requires an argument", name))
+            };
+            match name {
+                "full" => opts.full = true,
+                "exact" => opts.exact = true,
+                "ignore-case" => opts.ignore_case = true,
+                "inverse" => opts.invert = true,
+                "newest" => opts.newest = true,
+                "oldest" => opts.oldest = true,
+                "euid" => opts.users.extend(value()?.split(',').map(String::from)),
+                "list-name" if !kill => opts.list_name = true,
+                "list-full" if !kill => opts.list_full = true,
+                "count" if !kill => opts.count = true,
+                "delimiter" if !kill => opts.delimiter = Some(value()?),
+                "signal" if kill => opts.signal = Some(value()?),
+                "echo" if kill => opts.echo = true,
+                _ => return Err(format!("unrecognized option '{}'", arg)),
+            }
+            continue;
+        }
+
+        let letters = &arg[1..];
+        if kill && i == 0 {
+            #[cfg(unix)]
+            if crate::process::parse_signal(letters).is_some() {
+                opts.signal = Some(letters.to_string());
+                continue;
+            }
+        }
+
+        for (pos, c) in letters.char_indices() {
+            let rest = &letters[pos + c.len_utf8()..];
+            let mut value = || {
+                if rest.is_empty() {
+                    iter.next()
+                        .map(|(_, v)| v)
+                        .ok_or_else(|| format!("option requires an argument -- '{}'", c))
+                } else {
+                    Ok(rest.to_string())
+                }
+            };
+            match c {
+                'f' => opts.full = true,
+                'x' => opts.exact = true,
+                'i' => opts.ignore_case = true,
+                'v' if !kill => opts.invert = true,
+                'n' => opts.newest = true,
+                'o' => opts.oldest = true,
+                'u' => {
+                    opts.users.extend(value()?.split(',').map(String::from));
+                    break;
+                }
+                'l' if !kill => opts.list_name = true,
+                'a' if !kill => opts.list_full = true,
+                'c' if !kill => opts.count = true,
+                'd' if !kill => {
+                    opts.delimiter = Some(value()?);
+                    break;
+                }
+                'e' if kill => opts.echo = true,
+                _ => return Err(format!("invalid option -- '{}'", c)),
+            }
+        }
+    }
+
+    if opts.pattern.is_none() && opts.users.is_empty() {
+        return Err("no matching criteria specified".to_string());
+    }
+    if opts.newest && opts.oldest {
+        return Err("-n and -o are mutually exclusive".to_string());
+    }
+    Ok(opts)
+}
+
+/// Finds the processes selected by `opts`, excluding the shell itself.
+fn find_processes(opts: &PgrepOptions) -> Result<Vec<crate::process::ProcessInfo>, String> {
+    let regex = match &opts.pattern {
+        Some(pattern) => Some(crate::regex::Regex::new(pattern, opts.ignore_case)?),
+        None => None,
+    };
+
+    #[cfg(unix)]
+    let uids = opts
+        .users
+        .iter()
+        .map(|user| {
+            crate::process::user_id(user).ok_or_else(|| format!("invalid user name: {}", user))
+        })
+        .collect::<Result<Vec<u32>, String>>()?;
+    #[cfg(not(unix))]
+    let uids: Vec<u32> = Vec::new();
+
+    let own_pid = std::process::id() as i32;
+    let processes = crate::process::list_processes().map_err(|e| format!("/proc: {}", e))?;
+    let mut matches: Vec<_> = processes
+        .into_iter()
+        .filter(|p| p.pid != own_pid)
+        .filter(|p| {
+            let subject = if opts.full && !p.cmdline.is_empty() {
+                p.cmdline.join(" ")
+            } else {
+                p.name.clone()
+            };
+            let name_ok = regex.as_ref().is_none_or(|re| {
+                if opts.exact {
+                    re.is_full_match(&subject)
+                } else {
+                    re.is_match(&subject)
+                }
+            });
+            let user_ok = uids.is_empty() || uids.contains(&p.uid);
+            (name_ok && user_ok) != opts.invert
+        })
+        .collect();
+
+    if opts.newest || opts.oldest {
+        let pick = if opts.newest {
+            matches.iter().max_by_key(|p| (p.start_time, p.pid))
+        } else {
+            matches.iter().min_by_key(|p| (p.start_time, p.pid))
+        };
+        matches = pick.cloned().into_iter().collect();
+    }
+    Ok(matches)
+}
+
+/// Lists the pids of processes whose name matches PATTERN.
+///
+/// Supports the following flags:
+/// - `-f`: Match against the full command line instead of the name.
+/// - `-x`: Require the pattern to match the whole name.
+/// - `-i`: Match case-insensitively.
+/// - `-v`: Select processes that do not match.
+/// - `-n` / `-o`: Select only the newest / oldest match.
+/// - `-u USER`: Only processes whose effective user is USER (comma list).
+/// - `-l` / `-a`: Show the name / full command line next to each pid.
+/// - `-c`: Print the number of matches instead.
+/// - `-d DELIM`: Separate pids with DELIM instead of newlines.
+fn pgrep_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    let matches = match parse_pgrep_options(args, false)
+        .and_then(|opts| find_processes(&opts).map(|found| (opts, found)))
+    {
+        Ok(found) => found,
+        Err(e) => return CommandResult::with_stderr(format!("pgrep: {}", e)),
+    };
+    let (opts, matches) = matches;
+
+    if opts.count {
+        return CommandResult::with_stdout(format!("{}\n", matches.len()));
+    }
+    if matches.is_empty() {
+        return CommandResult::new();
+    }
+    let entries: Vec<String> = matches
+        .iter()
+        .map(|p| {
+            if opts.list_full {
+                format!("{} {}", p.pid, p.command())
+            } else if opts.list_name {
+                format!("{} {}", p.pid, p.name)
+            } else {
+                p.pid.to_string()
+            }
+        })
+        .collect();
+    let delimiter = opts.delimiter.as_deref().unwrap_or("\n");
+    CommandResult::with_stdout(format!("{}\n", entries.join(delimiter)))
+}
+
+/// Sends a signal (TERM by default) to processes whose name matches PATTERN.
+///
+/// Accepts the same selection flags as `pgrep` (except `-v`), the signal as
+/// `-SIGNAL` or `--signal SIGNAL`, and `-e` to report each process signalled.
+fn pkill_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    let matches = match parse_pgrep_options(args, true)
+        .and_then(|opts| find_processes(&opts).map(|found| (opts, found)))
+    {
+        Ok(found) => found,
+        Err(e) => return CommandResult::with_stderr(format!("pkill: {}", e)),
+    };
+    let (opts, matches) = matches;
+
+    #[cfg(unix)]
+    {
+        use crate::process::{parse_signal, send_signal};
+
+        let signal = match &opts.signal {
+            None => libc::SIGTERM,
+            Some(name) => match parse_signal(name) {
+                Some(signal) => signal,
+                None => {
+                    return CommandResult::with_stderr(format!("pkill: unknown signal '{}'", name));
+                }
+            },
+        };
+
+        let mut result = CommandResult::new();
+        for p in matches {
+            match send_signal(p.pid, signal) {
+                Ok(()) if opts.echo => {
+                    result.stdout.extend_from_slice(
+                        format!("{} killed (pid {})\n", p.name, p.pid).as_bytes(),
+                    );
+                }
+                Ok(()) => {}
+                Err(e) => {
+                    result.append_stderr(&format!("pkill: killing pid {} failed: {}", p.pid, e))
+                }
+            }
+        }
+        result
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (opts, matches);
+        CommandResult::with_stderr("pkill: not supported on this platform".to_string())
+    }
+}
+
+/// Options for `killall`.
+#[derive(Debug, Default, PartialEq)]
+struct KillallOptions {
+    names: Vec<String>,
+    signal: Option<String>,
+    users: Vec<String>,
+    interactive: bool,
+    quiet: bool,
+    verbose: bool,
+    wait: bool,
+    list: bool,
+}
+
+/// Parses `killall` arguments. As with `pkill`, a first argument such as
+/// `-9` or `-HUP` names the signal; it may also be given with `-s`.
+fn parse_killall_options(args: Vec<String>) -> Result<KillallOptions, String> {
+    let mut opts = KillallOptions::default();
+    let mut iter = args.into_iter().enumerate();
+    while let Some((i, arg)) = iter.next() {
+        if arg == "--" {
+            opts.names.extend(iter.map(|(_, a)| a));
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() == 1 {
+            opts.names.push(arg);
+            continue;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            let mut value = |name: &str| {
+                iter.next()
+                    .map(|(_, v)| v)
+                    .ok_or_else(|| format!("option '--{}' requires an argument", name))
+            };
+            match long {
+                "signal" => opts.signal = Some(value(long)?),
+                "user" => opts.users.extend(value(long)?.split(',').map(String::from)),
+                "interactive" => opts.interactive = true,
+                "quiet" => opts.quiet = true,
+                "verbose" => opts.verbose = true,
+                "wait" => opts.wait = true,
+                "list" => opts.list = true,
+                _ => return Err(format!("unrecognized option '{}'", arg)),
+            }
+            continue;
+        }
+
+        let letters = &arg[1..];
+        #[cfg(unix)]
+        if i == 0 && crate::process::parse_signal(letters).is_some() {
+            opts.signal = Some(letters.to_string());
+            continue;
+        }
+        #[cfg(not(unix))]
+        let _ = i;
+
+        for (pos, c) in letters.char_indices() {
+            let rest = &letters[pos + c.len_utf8()..];
+            let mut value = || {
+                if rest.is_empty() {
+                    iter.next()
+                        .map(|(_, v)| v)
+                        .ok_or_else(|| format!("option requires an argument -- '{}'", c))
+                } else {
+                    Ok(rest.to_string())
+                }
+            };
+            match c {
+                's' => {
+                    opts.signal = Some(value()?);
+                    break;
+                }
+                'u' => {
+                    opts.users.extend(value()?.split(',').map(String::from));
+                    break;
+                }
+                'i' => opts.interactive = true,
+                'q' => opts.quiet = true,
+                'v' => opts.verbose = true,
+                'w' => opts.wait = true,
+                'l' => opts.list = true,
+                _ => return Err(format!("invalid option -- '{}'", c)),
+            }
+        }
+    }
+
+    if opts.names.is_empty() && !opts.list {
+        return Err("no process name specified".to_string());
+    }
+    Ok(opts)
+}
+
+/// Whether a process is called exactly `name`. The kernel truncates
+/// process names to 15 bytes, so the executable named in the command line
+/// is also checked.
+fn killall_matches(p: &crate::process::ProcessInfo, name: &str) -> bool {
+    p.name == name
+        || p.cmdline
+            .first()
+            .and_then(|arg0| Path::new(arg0).file_name())
+            .is_some_and(|base| base == name)
+}
+
+/// Sends a signal (TERM by default) to every process with an exact name.
+///
+/// Supports the following flags:
+/// - `-SIGNAL` or `-s SIGNAL`: The signal to send, by name or number.
+/// - `-u USER`: Only processes whose effective user is USER (comma list).
+/// - `-i`: Ask for confirmation before signalling each process.
+/// - `-v`: Report each process signalled.
+/// - `-q`: Do not complain when no process matches.
+/// - `-w`: Wait until the signalled processes have exited.
+/// - `-l`: List the signal names.
+#[cfg(unix)]
+fn killall_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    use crate::process::{SIGNALS, parse_signal, send_signal, user_id};
+
+    let opts = match parse_killall_options(args) {
+        Ok(opts) => opts,
+        Err(e) => return CommandResult::with_stderr(format!("killall: {}", e)),
+    };
+    if opts.list {
+        let names: Vec<&str> = SIGNALS.iter().map(|(name, _)| *name).collect();
+        return CommandResult::with_stdout(format!("{}\n", names.join(" ")));
+    }
+    let signal = match &opts.signal {
+        None => libc::SIGTERM,
+        Some(name) => match parse_signal(name) {
+            Some(signal) => signal,
+            None => {
+                return CommandResult::with_stderr(format!("killall: unknown signal '{}'", name));
+            }
+        },
+    };
+    let mut uids = Vec::new();
+    for user in &opts.users {
+        match user_id(user) {
+            Some(uid) => uids.push(uid),
+            None => return CommandResult::with_stderr(format!("killall: unknown user '{}'", user)),
+        }
+    }
+    let processes = match crate::process::list_processes() {
+        Ok(processes) => processes,
+        Err(e) => return CommandResult::with_stderr(format!("killall: /proc: {}", e)),
+    };
+
+    let own_pid = std::process::id() as i32;
+    let mut result = CommandResult::new();
+    let mut signalled = Vec::new();
+    for name in &opts.names {
+        let mut found = false;
+        for p in &processes {
+            if p.pid == own_pid
+                || !killall_matches(p, name)
+                || (!uids.is_empty() && !uids.contains(&p.uid))
+            {
+                continue;
+            }
+            found = true;
+            if opts.interactive
+                && !crate::terminal::confirm(&format!("Signal {}({})? (y/N)", p.name, p.pid))
+            {
+                continue;
+            }
+            match send_signal(p.pid, signal) {
+                Ok(()) => {
+                    if opts.verbose {
+                        result.stdout.extend_from_slice(
+                            format!(
+                                "Killed {}({}) with signal {}\n",
+                                p.name,
+                                p.pid,
+                                crate::process::signal_name(signal)
+                            )
+                            .as_bytes(),
+                        );
+                    }
+                    signalled.push(p.pid);
+                }
+                Err(e) => result.append_stderr(&format!("killall: {}({}): {}", p.name, p.pid, e)),
+            }
+        }
+        if !found && !opts.quiet {
+            result.append_stderr(&format!("killall: {}: no process found", name));
+        }
+    }
+
+    if opts.wait && !signalled.is_empty() {
+        let _guard = crate::signals::InterruptGuard::install();
+        // Signal 0 only checks that the process still exists
+        while signalled.iter().any(|&pid| send_signal(pid, 0).is_ok()) {
+            if crate::signals::sleep_interruptible(std::time::Duration::from_millis(100)) {
+                break;
+            }
+        }
+    }
+    result
+}
+
+#[cfg(not(unix))]
+fn killall_callback(_flags: Vec<String>, _args: Vec<String>) -> CommandResult {
+    CommandResult::with_stderr("killall: not supported on this platform".to_string())
+}
+
+const TOP_SPEC: Spec = Spec {
+    name: "top",
+    summary: "monitor processes",
+    forms: &[""],
+    flags: &[
+        Flag::new(
+            &["-b", "--batch"],
+            "Print plain snapshots instead of a full-screen display.",
+        ),
+        Flag::with_value(
+            &["-d", "--delay"],
+            "SECONDS",
+            "Wait SECONDS between refreshes. Fractions are allowed.",
+        )
+        .or_default("3"),
+        Flag::with_value(
+            &["-n", "--iterations"],
+            "COUNT",
+            "Exit after COUNT refreshes.",
+        ),
+        Flag::with_value(
+            &["-u", "--user"],
+            "USER",
+            "Only show processes whose effective user is USER.",
+        ),
+        Flag::with_value(
+            &["-o", "--sort"],
+            "FIELD",
+            "Sort by FIELD: cpu, mem, pid, or time.",
+        )
+        .or_default("cpu"),
+    ],
+};
+
+/// Shows a full-screen, periodically refreshing table of processes.
+///
+/// Supports the following options:
+/// - `-d SECONDS`: Delay between refreshes (default 3, fractions allowed).
+/// - `-n COUNT`: Exit after COUNT refreshes.
+/// - `-u USER`: Only show processes whose effective user is USER.
+/// - `-o FIELD`: Sort by `cpu` (the default), `mem`, `pid`, or `time`.
+/// - `-b`: Print plain snapshots instead of a full-screen display.
+///
+/// While running, `P`, `M`, `N`, and `T` sort by CPU, memory, pid, and CPU
+/// time, `R` reverses the order, `k` signals a process, and `q` quits.
+fn top_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    use crate::top::{Options, SortKey};
+
+    if let Some(arg) = args.operands.first() {
+        return CommandResult::with_stderr(format!("top: unexpected argument '{}'", arg));
+    }
+    let delay_arg = args.value("-d").unwrap_or_default();
+    let delay = match delay_arg
+        .parse::<f64>()
+        .ok()
+        .and_then(|d| std::time::Duration::try_from_secs_f64(d).ok())
+    {
+        Some(d) => d.max(std::time::Duration::from_millis(100)),
+        None => {
+            return CommandResult::with_stderr(format!("top: invalid delay '{}'", delay_arg));
+        }
+    };
+    let iterations = match args.get::<usize>("-n") {
+        Ok(None) => None,
+        Ok(Some(n)) if n > 0 => Some(n),
+        _ => {
+            return CommandResult::with_stderr(format!(
+                "top: invalid iteration count '{}'",
+                args.value("-n").unwrap_or_default()
+            ));
+        }
+    };
+    let sort_name = args.value("-o").unwrap_or_default();
+    let Some(sort) = SortKey::from_name(sort_name) else {
+        return CommandResult::with_stderr(format!("top: unknown sort field '{}'", sort_name));
+    };
+    let mut uids = Vec::new();
+    if let Some(user) = args.value("-u") {
+        #[cfg(unix)]
+        match crate::process::user_id(user) {
+            Some(uid) => uids.push(uid),
+            None => return CommandResult::with_stderr(format!("top: unknown user '{}'", user)),
+        }
+        #[cfg(not(unix))]
+        return CommandResult::with_stderr(format!("top: unknown user '{}'", user));
+    }
+
+    let opts = Options {
+        delay,
+        uids,
+        iterations,
+        batch: args.has("-b"),
+        sort,
+    };
+    // The first header line is the `uptime` report
+    let summary = || {
+        uptime_callback(Vec::new(), Vec::new())
+            .stdout_text()
+            .into_owned()
+    };
+    match crate::top::run(&opts, &summary, &ctx.state.vars, ctx.out.stdout) {
+        Ok(()) => CommandResult::new(),
+        Err(e) => CommandResult::with_stderr(format!("top: {}", e)),
+    }
+}
+
+const MOUNT_SPEC: Spec = Spec {
+    name: "mount",
+    summary: "list mounted filesystems",
+    forms: &[""],
+    flags: &[Flag::with_value(
+        &["-t", "--types"],
+        "TYPE[,TYPE...]",
+        "Only list filesystems of these types, or with a leading no, as in noproc,sysfs, \
+         all but these.",
+    )],
+};
+
+/// Lists mounted filesystems from `/proc/self/mounts` in aligned columns.
+///
+/// `-t` restricts the listing to a comma-separated list of filesystem types;
+/// prefixing the list with `no` (as in `-t noproc,sysfs`) excludes them
+/// instead. Mounting is not supported.
+fn mount_callback(args: ParsedArgs) -> CommandResult {
+    if !args.operands.is_empty() {
+        return CommandResult::with_stderr(
+            "mount: only listing mounted filesystems is supported".to_string(),
+        );
+    }
+
+    match fs::read_to_string("/proc/self/mounts") {
+        Ok(mounts) => CommandResult::with_stdout(format_mounts(&mounts, args.value("-t"))),
+        Err(e) => CommandResult::with_stderr(format!("mount: /proc/self/mounts: {}", e)),
+    }
+}
+
+/// Renders the contents of a mounts table, keeping only entries whose type
+/// passes the `-t` filter.
+fn format_mounts(mounts: &str, types: Option<&str>) -> String {
+    let (exclude, types): (bool, Vec<&str>) = match types {
+        Some(list) if list.starts_with("no") => (
+            true,
+            list.split(',')
+                .map(|t| t.trim_start_matches("no"))
+                .collect(),
+        ),
+        Some(list) => (false, list.split(',').collect()),
+        None => (true, Vec::new()),
+    };
+
+    let mut rows = vec![[
+        "DEVICE".to_string(),
+        "MOUNTPOINT".to_string(),
+        "TYPE".to_string(),
+        "OPTIONS".to_string(),
+    ]];
+    for line in mounts.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [device, target, fstype, options, ..] = fields[..] else {
+            continue;
+        };
+        if types.contains(&fstype) == exclude {
+            continue;
+        }
+        rows.push([device, target, fstype, options].map(unescape_mount_field));
+    }
+
+    let width = |col: usize| {
+        rows.iter()
+            .map(|r| r[col].chars().count())
+            .max()
+            .unwrap_or(0)
+    };
+    let widths = [width(0), width(1), width(2)];
+    rows.iter()
+        .map(|r| {
+            format!(
+                "{:<w0$}  {:<w1$}  {:<w2$}  {}\n",
+                r[0],
+                r[1],
+                r[2],
+                r[3],
+                w0 = widths[0],
+                w1 = widths[1],
+                w2 = widths[2]
+            )
+        })
+        .collect()
+}
+
+/// Decodes the octal escapes (`\040` for a space, etc.) the kernel uses in
+/// mount table fields.
+fn unescape_mount_field(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(code) = field
+                .get(i + 1..i + 4)
+                .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+        {
+            out.push(code);
+            i += 4;
+            continue;
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Reads the whole of `path`, or `stdin` when `path` is `-`.
+fn read_input(path: &str, stdin: &mut dyn Read) -> io::Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = Vec::new();
+        stdin.read_to_end(&mut data)?;
+        Ok(data)
+    } else {
+        fs::read(path)
+    }
+}
+
+/// Reverses the characters of each line of the files, or of standard input.
+///
+/// Lines are reversed by grapheme cluster, so accented letters and emoji
+/// sequences stay intact. Lines that are not valid UTF-8 are reversed byte
+/// by byte.
+fn rev_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let mut result = CommandResult::new();
+    let paths = if args.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        args
+    };
+
+    for path in &paths {
+        match read_input(path, ctx.stdin) {
+            Ok(data) => result.stdout.extend(reverse_lines(&data)),
+            Err(e) => result.append_stderr(&format!("rev: {}: {}", path, e)),
+        }
+    }
+    result
+}
+
+/// Reverses each line of `data`, keeping line endings in place.
+fn reverse_lines(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    for line in data.split_inclusive(|&b| b == b'\n') {
+        let (body, ending) = match line.strip_suffix(b"\n") {
+            Some(body) => (body, &b"\n"[..]),
+            None => (line, &b""[..]),
+        };
+        match std::str::from_utf8(body) {
+            Ok(text) => {
+                for grapheme in crate::unicode::graphemes(text).into_iter().rev() {
+                    out.extend_from_slice(grapheme.as_bytes());
+                }
+            }
+            Err(_) => out.extend(body.iter().rev()),
+        }
+        out.extend_from_slice(ending);
+    }
+    out
+}
+
+const FOLD_SPEC: Spec = Spec {
+    name: "fold",
+    summary: "wrap long lines to a given width",
+    forms: &["[FILE]..."],
+    flags: &[
+        Flag::new(&["-b", "--bytes"], "Count bytes rather than columns."),
+        Flag::new(
+            &["-s", "--spaces"],
+            "Break after the last blank before the limit, where there is one.",
+        ),
+        Flag::with_value(&["-w", "--width"], "WIDTH", "Wrap lines at WIDTH columns.")
+            .or_default("80"),
+    ],
+};
+
+/// Wraps lines longer than WIDTH columns.
+///
+/// Supports the following flags:
+/// - `-w WIDTH`, `--width=WIDTH`: Maximum line width (default 80).
+/// - `-s`, `--spaces`: Break after the last blank before the limit where
+///   possible.
+/// - `-b`, `--bytes`: Count bytes rather than display columns.
+///
+/// In column mode, tabs advance to the next multiple of 8, backspace moves
+/// back one column, wide characters count as two, and bytes that are not
+/// valid UTF-8 count as one each.
+fn fold_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let width = match args.get::<usize>("-w") {
+        Ok(Some(w)) if w > 0 => w,
+        _ => {
+            return CommandResult::error(CommandError::Usage(format!(
+                "fold: invalid number of columns: '{}'",
+                args.value("-w").unwrap_or_default()
+            )));
+        }
+    };
+    let bytes = args.has("--bytes");
+    let spaces = args.has("--spaces");
+
+    let mut result = CommandResult::new();
+    let paths = if args.operands.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        args.operands
+    };
+    for path in &paths {
+        match read_input(path, ctx.stdin) {
+            Ok(data) => {
+                for line in data.split_inclusive(|&b| b == b'\n') {
+                    let (body, ending) = match line.strip_suffix(b"\n") {
+                        Some(body) => (body, &b"\n"[..]),
+                        None => (line, &b""[..]),
+                    };
+                    result.stdout.extend(fold_line(body, width, bytes, spaces));
+                    result.stdout.extend_from_slice(ending);
+                }
+            }
+            Err(e) => result.append_stderr(&format!("fold: {}: {}", path, e)),
+        }
+    }
+    result
+}
+
+/// Splits `line` into the pieces `fold` may break between: single bytes
+/// with `-b`, otherwise characters, with each invalid UTF-8 byte on its own.
+fn fold_units(line: &[u8], bytes: bool) -> Vec<&[u8]> {
+    if bytes {
+        return line.chunks(1).collect();
+    }
+    let mut units = Vec::new();
+    let mut rest = line;
+    for chunk in line.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            let (unit, tail) = rest.split_at(c.len_utf8());
+            units.push(unit);
+            rest = tail;
+        }
+        for _ in chunk.invalid() {
+            let (unit, tail) = rest.split_at(1);
+            units.push(unit);
+            rest = tail;
+        }
+    }
+    units
+}
+
+/// Column position after printing `unit` at column `col`.
+fn fold_advance(col: usize, unit: &[u8], bytes: bool) -> usize {
+    if bytes {
+        return col + unit.len();
+    }
+    let c = std::str::from_utf8(unit)
+        .ok()
+        .and_then(|s| s.chars().next());
+    match c {
+        Some('\t') => col + 8 - col % 8,
+        Some('\x08') => col.saturating_sub(1),
+        Some('\r') => 0,
+        Some(c) => col + crate::unicode::char_width(c),
+        None => col + 1,
+    }
+}
+
+/// Breaks a single line (without its newline) into rows of at most `width`.
+fn fold_line(line: &[u8], width: usize, bytes: bool, spaces: bool) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut row = Vec::new();
+    let mut col = 0;
+
+    for unit in fold_units(line, bytes) {
+        if fold_advance(col, unit, bytes) > width && !row.is_empty() {
+            let blank = row
+                .iter()
+                .rposition(|&b| b == b' ' || b == b'\t')
+                .filter(|_| spaces);
+            match blank {
+                Some(pos) if pos + 1 < row.len() => {
+                    out.extend_from_slice(&row[..=pos]);
+                    row.drain(..=pos);
+                }
+                _ => out.append(&mut row),
+            }
+            out.push(b'\n');
+            col = fold_units(&row, bytes)
+                .into_iter()
+                .fold(0, |col, unit| fold_advance(col, unit, bytes));
+        }
+        row.extend_from_slice(unit);
+        col = fold_advance(col, unit, bytes);
+    }
+    out.extend_from_slice(&row);
+    out
+}
+
+/// Tab stop positions for `expand` and `unexpand`.
+#[derive(Debug, PartialEq)]
+enum TabStops {
+    /// A stop every N columns.
+    Every(usize),
+    /// Explicit, ascending stop columns.
+    List(Vec<usize>),
+}
+
+impl TabStops {
+    /// Parses `N` or a comma- or blank-separated list of columns.
+    fn parse(spec: &str) -> Result<Self, String> {
+        let stops = spec
+            .split([',', ' '])
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                s.parse::<usize>()
+                    .map_err(|_| format!("tab size contains invalid character(s): '{}'", s))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if stops.contains(&0) {
+            return Err("tab size cannot be 0".to_string());
+        }
+        if stops.windows(2).any(|w| w[0] >= w[1]) {
+            return Err("tab sizes must be ascending".to_string());
+        }
+        match stops[..] {
+            [] => Err("tab size cannot be empty".to_string()),
+            [n] => Ok(TabStops::Every(n)),
+            _ => Ok(TabStops::List(stops)),
+        }
+    }
+
+    /// The first stop after `col`, or `None` past the last explicit stop.
+    fn next(&self, col: usize) -> Option<usize> {
+        match self {
+            TabStops::Every(n) => Some(col + n - col % n),
+            TabStops::List(stops) => stops.iter().copied().find(|&s| s > col),
+        }
+    }
+
+    fn is_stop(&self, col: usize) -> bool {
+        match self {
+            TabStops::Every(n) => col > 0 && col.is_multiple_of(*n),
+            TabStops::List(stops) => stops.contains(&col),
+        }
+    }
+}
+
+/// The `-t` option shared by `expand` and `unexpand`.
+const TAB_STOPS_FLAG: Flag = Flag::with_value(
+    &["-t", "--tabs"],
+    "N|LIST",
+    "Put a tab stop every N columns, or at each column in LIST, separated by commas. The \
+     default is every 8 columns.",
+);
+
+/// Reads the `-t` option shared by `expand` and `unexpand`.
+fn parse_tab_stops(name: &str, args: &ParsedArgs) -> Result<Option<TabStops>, String> {
+    args.value("-t")
+        .map(|spec| TabStops::parse(spec).map_err(|e| format!("{}: {}", name, e)))
+        .transpose()
+}
+
+/// Applies `convert` to each input file, or standard input, writing the
+/// converted bytes to stdout.
+fn convert_inputs(
+    name: &str,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+    convert: impl Fn(&[u8]) -> Vec<u8>,
+) -> CommandResult {
+    let mut result = CommandResult::new();
+    let paths = if args.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        args
+    };
+    let stdout = &mut ctx.out.stdout;
+    for path in &paths {
+        match read_input(path, ctx.stdin) {
+            Ok(data) => {
+                if let Err(e) = stdout.write_all(&convert(&data)) {
+                    result.append_stderr(&format!("{}: {}", name, e));
+                    break;
+                }
+            }
+            Err(e) => result.append_stderr(&format!("{}: {}: {}", name, path, e)),
+        }
+    }
+    let _ = stdout.flush();
+    result
+}
+
+/// Whether `byte` starts a character (is not a UTF-8 continuation byte), so
+/// that multi-byte characters advance the column once.
+fn starts_char(byte: u8) -> bool {
+    byte & 0xC0 != 0x80
+}
+
+const EXPAND_SPEC: Spec = Spec {
+    name: "expand",
+    summary: "convert tabs to spaces",
+    forms: &["[FILE]..."],
+    flags: &[
+        Flag::new(&["-i", "--initial"], "Only convert tabs in leading blanks."),
+        TAB_STOPS_FLAG,
+    ],
+};
+
+/// Converts tabs to spaces.
+///
+/// `-t` sets the tab stops: a single number for evenly spaced stops
+/// (default 8) or a list of columns. Tabs past the last listed stop become
+/// single spaces. `-i` converts only tabs in leading whitespace.
+fn expand_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let stops = match parse_tab_stops("expand", &args) {
+        Ok(stops) => stops.unwrap_or(TabStops::Every(8)),
+        Err(e) => return CommandResult::with_stderr(e),
+    };
+    let initial = args.has("-i");
+    convert_inputs("expand", args.operands, ctx, |data| {
+        expand_tabs(data, &stops, initial)
+    })
+}
+
+fn expand_tabs(data: &[u8], stops: &TabStops, initial: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut col = 0;
+    let mut leading = true;
+
+    for &b in data {
+        match b {
+            b'\t' if leading || !initial => {
+                let next = stops.next(col).unwrap_or(col + 1);
+                out.resize(out.len() + next - col, b' ');
+                col = next;
+            }
+            b'\n' => {
+                out.push(b);
+                col = 0;
+                leading = true;
+            }
+            0x08 => {
+                out.push(b);
+                col = col.saturating_sub(1);
+            }
+            _ => {
+                out.push(b);
+                if b == b'\t' {
+                    col = stops.next(col).unwrap_or(col + 1);
+                } else if starts_char(b) {
+                    col += 1;
+                }
+                if b != b' ' && b != b'\t' {
+                    leading = false;
+                }
+            }
+        }
+    }
+    out
+}
+
+const UNEXPAND_SPEC: Spec = Spec {
+    name: "unexpand",
+    summary: "convert spaces to tabs",
+    forms: &["[FILE]..."],
+    flags: &[
+        Flag::new(
+            &["-a", "--all"],
+            "Convert all blanks, not only leading ones. Implied by -t.",
+        ),
+        TAB_STOPS_FLAG,
+    ],
+};
+
+/// Converts runs of spaces to tabs where they reach a tab stop.
+///
+/// By default only leading blanks are converted; `-a` converts all of them.
+/// Giving `-t` implies `-a`. A single space before a stop is left alone, as
+/// a tab would not save anything.
+fn unexpand_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let stops = match parse_tab_stops("unexpand", &args) {
+        Ok(stops) => stops,
+        Err(e) => return CommandResult::with_stderr(e),
+    };
+    let all = stops.is_some() || args.has("-a");
+    let stops = stops.unwrap_or(TabStops::Every(8));
+    convert_inputs("unexpand", args.operands, ctx, |data| {
+        unexpand_spaces(data, &stops, all)
+    })
+}
+
+fn unexpand_spaces(data: &[u8], stops: &TabStops, all: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut pending: Vec<u8> = Vec::new();
+    let mut col = 0;
+    let mut leading = true;
+
+    for &b in data {
+        let blank = b == b' ' || b == b'\t';
+        if blank && (leading || all) {
+            col = if b == b'\t' {
+                stops.next(col).unwrap_or(col + 1)
+            } else {
+                col + 1
+            };
+            pending.push(b);
+            if stops.is_stop(col) {
+                if pending == b" " {
+                    out.push(b' ');
+                } else {
+                    out.push(b'\t');
+                }
+                pending.clear();
+            }
+            continue;
+        }
+
+        out.append(&mut pending);
+        out.push(b);
+        match b {
+            b'\n' => {
+                col = 0;
+                leading = true;
+                continue;
+            }
+            0x08 => col = col.saturating_sub(1),
+            b'\t' => col = stops.next(col).unwrap_or(col + 1),
+            _ if starts_char(b) => col += 1,
+            _ => {}
+        }
+        if !blank {
+            leading = false;
+        }
+    }
+    out.append(&mut pending);
+    out
+}
+
+/// Compares two sorted files and prints three columns: lines only in FILE1,
+/// lines only in FILE2, and lines in both.
+///
+/// `-1`, `-2`, and `-3` suppress the corresponding column. Either file may
+/// be `-` for standard input. Lines are compared byte by byte; a warning is
+/// printed if either input is not sorted that way.
+fn comm_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    if args.len() != 2 {
+        return CommandResult::with_stderr(match args.len() {
+            0 | 1 => "comm: missing operand".to_string(),
+            _ => format!("comm: extra operand '{}'", args[2]),
+        });
+    }
+    let show = ["-1", "-2", "-3"].map(|col| !flags.iter().any(|f| f == col));
+
+    let mut inputs = Vec::new();
+    for path in &args {
+        match read_input(path, ctx.stdin) {
+            Ok(data) => inputs.push(data),
+            Err(e) => return CommandResult::with_stderr(format!("comm: {}: {}", path, e)),
+        }
+    }
+
+    let (stdout, unsorted) = compare_sorted(&inputs[0], &inputs[1], show);
+    let mut result = CommandResult::with_stdout(stdout);
+    for file in unsorted {
+        result.append_stderr(&format!("comm: file {} is not in sorted order", file));
+    }
+    result
+}
+
+/// Merges two sorted inputs into `comm` columns, returning the output and
+/// the (1-based) numbers of any inputs found to be unsorted.
+fn compare_sorted(first: &[u8], second: &[u8], show: [bool; 3]) -> (Vec<u8>, Vec<usize>) {
+    let split = |data: &'_ [u8]| -> Vec<Vec<u8>> {
+        data.split_inclusive(|&b| b == b'\n')
+            .map(|line| line.strip_suffix(b"\n").unwrap_or(line).to_vec())
+            .collect()
+    };
+    let (a, b) = (split(first), split(second));
+    let unsorted = [&a, &b]
+        .iter()
+        .enumerate()
+        .filter(|(_, lines)| lines.windows(2).any(|w| w[0] > w[1]))
+        .map(|(i, _)| i + 1)
+        .collect();
+
+    let mut out = Vec::new();
+    let mut emit = |col: usize, line: &[u8]| {
+        if !show[col] {
+            return;
+        }
+        let indent = show[..col].iter().filter(|&&shown| shown).count();
+        out.resize(out.len() + indent, b'\t');
+        out.extend_from_slice(line);
+        out.push(b'\n');
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        let order = match (a.get(i), b.get(j)) {
+            (Some(x), Some(y)) => x.cmp(y),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            _ => std::cmp::Ordering::Greater,
+        };
+        match order {
+            std::cmp::Ordering::Less => {
+                emit(0, &a[i]);
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                emit(1, &b[j]);
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                emit(2, &a[i]);
+                i += 1;
+                j += 1;
+            }
+        }
+    }
+    (out, unsorted)
+}
+
+/// Options for a `join` invocation.
+#[derive(Debug)]
+struct JoinOptions {
+    /// Zero-based join field for each file.
+    fields: [usize; 2],
+    /// Field separator; `None` splits on runs of blanks.
+    separator: Option<char>,
+    /// Print unpairable lines from each file.
+    unpaired: [bool; 2],
+    /// Print only unpairable lines, not joined ones.
+    only_unpaired: bool,
+}
+
+const JOIN_SPEC: Spec = Spec {
+    name: "join",
+    summary: "join lines of two files on a common field",
+    forms: &["FILE1 FILE2"],
+    flags: &[
+        Flag::with_value(
+            &["-t"],
+            "CHAR",
+            "Separate fields by CHAR rather than runs of blanks.",
+        ),
+        Flag::with_value(&["-1"], "FIELD", "Join on this field of FILE1."),
+        Flag::with_value(&["-2"], "FIELD", "Join on this field of FILE2."),
+        Flag::with_value(&["-j"], "FIELD", "Join on this field of both files."),
+        Flag::with_value(
+            &["-a"],
+            "FILENUM",
+            "Also print unpairable lines from file 1 or 2. May be repeated.",
+        ),
+        Flag::with_value(
+            &["-v"],
+            "FILENUM",
+            "Print only unpairable lines from file 1 or 2. May be repeated.",
+        ),
+    ],
+};
+
+/// Joins lines of two files that share the same join field value.
+///
+/// Supports the following flags:
+/// - `-1 FIELD` / `-2 FIELD`: Join on this (1-based) field of file 1 / 2.
+/// - `-j FIELD`: Join on this field of both files.
+/// - `-t CHAR`: Fields are separated by CHAR rather than runs of blanks.
+/// - `-a FILENUM`: Also print unpairable lines from file 1 or 2.
+/// - `-v FILENUM`: Print only unpairable lines from file 1 or 2.
+///
+/// Each output line holds the join field, then the remaining fields of the
+/// line from file 1, then those from file 2. Both files must be sorted on
+/// the join field.
+fn join_callback(parsed: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let opts = match join_options(&parsed) {
+        Ok(opts) => opts,
+        Err(e) => return CommandResult::with_stderr(format!("join: {}", e)),
+    };
+    let args = parsed.operands;
+    if args.len() != 2 {
+        return CommandResult::with_stderr(match args.len() {
+            0 | 1 => "join: missing operand".to_string(),
+            _ => format!("join: extra operand '{}'", args[2]),
+        });
+    }
+
+    let mut inputs = Vec::new();
+    for path in &args {
+        match read_input(path, ctx.stdin) {
+            Ok(data) => inputs.push(String::from_utf8_lossy(&data).into_owned()),
+            Err(e) => return CommandResult::with_stderr(format!("join: {}: {}", path, e)),
+        }
+    }
+    CommandResult::with_stdout(join_lines(&inputs[0], &inputs[1], &opts))
+}
+
+/// Builds [`JoinOptions`] from the parsed flags.
+fn join_options(args: &ParsedArgs) -> Result<JoinOptions, String> {
+    let field = |flag: &str| -> Result<Option<usize>, String> {
+        match args.value(flag) {
+            None => Ok(None),
+            Some(v) => match v.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(Some(n - 1)),
+                _ => Err(format!("invalid field number: '{}'", v)),
+            },
+        }
+    };
+    let both = field("-j")?.unwrap_or(0);
+    let fields = [field("-1")?.unwrap_or(both), field("-2")?.unwrap_or(both)];
+
+    let separator = match args.value("-t") {
+        None => None,
+        Some(t) => {
+            let mut chars = t.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Some(c),
+                _ => return Err(format!("multi-character tab '{}'", t)),
+            }
+        }
+    };
+
+    let mut unpaired = [false; 2];
+    let file_numbers = ["-a", "-v"].iter().flat_map(|flag| args.values(flag));
+    for number in file_numbers {
+        match number {
+            "1" => unpaired[0] = true,
+            "2" => unpaired[1] = true,
+            _ => return Err(format!("invalid file number: '{}'", number)),
+        }
+    }
+
+    Ok(JoinOptions {
+        fields,
+        separator,
+        unpaired,
+        only_unpaired: args.has("-v"),
+    })
+}
+
+/// Performs the join of two sorted inputs.
+fn join_lines(first: &str, second: &str, opts: &JoinOptions) -> String {
+    let split = |line: &str| -> Vec<String> {
+        match opts.separator {
+            Some(sep) => line.split(sep).map(String::from).collect(),
+            None => line.split_whitespace().map(String::from).collect(),
+        }
+    };
+    let a: Vec<Vec<String>> = first.lines().map(split).collect();
+    let b: Vec<Vec<String>> = second.lines().map(split).collect();
+    let key = |fields: &[String], file: usize| -> String {
+        fields.get(opts.fields[file]).cloned().unwrap_or_default()
+    };
+
+    let sep = opts.separator.map_or(" ".to_string(), String::from);
+    let mut out = String::new();
+    let mut emit = |join_key: &str, parts: &[(&[String], usize)]| {
+        let mut line = vec![join_key.to_string()];
+        for (fields, file) in parts {
+            let others = fields
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i != opts.fields[*file])
+                .map(|(_, f)| f.clone());
+            line.extend(others);
+        }
+        out.push_str(&line.join(&sep));
+        out.push('\n');
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        let order = match (a.get(i), b.get(j)) {
+            (Some(x), Some(y)) => key(x, 0).cmp(&key(y, 1)),
+            (Some(_), None) => std::cmp::Ordering::Less,
+            _ => std::cmp::Ordering::Greater,
+        };
+        match order {
+            std::cmp::Ordering::Less => {
+                if opts.unpaired[0] {
+                    emit(&key(&a[i], 0), &[(&a[i], 0)]);
+                }
+                i += 1;
+            }
+            std::cmp::Ordering::Greater => {
+                if opts.unpaired[1] {
+                    emit(&key(&b[j], 1), &[(&b[j], 1)]);
+                }
+                j += 1;
+            }
+            std::cmp::Ordering::Equal => {
+                // Pair every line of the matching group in file 1 with every
+                // line of the group in file 2
+                let k = key(&a[i], 0);
+                let end_a = i + a[i..].iter().take_while(|l| key(l, 0) == k).count();
+                let end_b = j + b[j..].iter().take_while(|l| key(l, 1) == k).count();
+                if !opts.only_unpaired {
+                    for x in &a[i..end_a] {
+                        for y in &b[j..end_b] {
+                            emit(&k, &[(x, 0), (y, 1)]);
+                        }
+                    }
+                }
+                i = end_a;
+                j = end_b;
+            }
+        }
+    }
+    out
+}
+
+/// The most numbers `shuf -i` prints.
+const SHUF_MAX_RANGE: usize = 10_000_000;
+
+const SHUF_SPEC: Spec = Spec {
+    name: "shuf",
+    summary: "randomly permute lines",
+    forms: &["[FILE]", "-e [ARG]...", "-i LO-HI"],
+    flags: &[
+        Flag::with_value(
+            &["-n", "--head-count"],
+            "COUNT",
+            "Output at most COUNT lines.",
+        ),
+        Flag::with_value(
+            &["--random-seed"],
+            "SEED",
+            "Seed the generator, for an order that can be repeated.",
+        ),
+        Flag::new(&["-e", "--echo"], "Treat each ARG as an input line."),
+        Flag::with_value(
+            &["-i", "--input-range"],
+            "LO-HI",
+            "Treat each number from LO to HI as an input line.",
+        ),
+    ],
+};
+
+/// Writes a random permutation of the input lines.
+///
+/// Supports the following flags:
+/// - `-n COUNT`: Output at most COUNT lines.
+/// - `-e`: Treat each argument as an input line.
+/// - `-i LO-HI`: Treat each number from LO to HI as an input line.
+/// - `--random-seed SEED`: Seed the generator for a reproducible order.
+///
+/// Without `-e` or `-i`, lines are read from FILE or standard input.
+fn shuf_callback(parsed: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let echo = parsed.has("-e");
+    let count = match parsed.get::<usize>("-n") {
+        Ok(count) => count.unwrap_or(usize::MAX),
+        Err(_) => {
+            return CommandResult::with_stderr(format!(
+                "shuf: invalid line count: '{}'",
+                parsed.value("-n").unwrap_or_default()
+            ));
+        }
+    };
+
+    let mut rng = match parsed.value("--random-seed") {
+        Some(seed) => crate::random::Rng::from_seed(crate::random::seed_from_text(seed)),
+        None => crate::random::Rng::from_entropy(),
+    };
+
+    let args = parsed.operands.clone();
+    let mut lines: Vec<String> = if let Some(range) = parsed.value("-i") {
+        if echo || !args.is_empty() {
+            return CommandResult::with_stderr("shuf: extra operand with -i".to_string());
+        }
+        let bounds = range
+            .split_once('-')
+            .and_then(|(lo, hi)| Some((lo.parse::<u64>().ok()?, hi.parse::<u64>().ok()?)))
+            .filter(|(lo, hi)| lo <= hi);
+        let Some((lo, hi)) = bounds else {
+            return CommandResult::with_stderr(format!("shuf: invalid input range: '{}'", range));
+        };
+        // Only the numbers printed are picked, so a sample of a huge range
+        // is cheap, but printing all of one is not
+        let size = (hi - lo) as u128 + 1;
+        let picks = size.min(count as u128);
+        if picks > SHUF_MAX_RANGE as u128 {
+            return CommandResult::with_stderr(format!(
+                "shuf: input range too large: '{}'; use -n to print fewer than {} numbers",
+                range, SHUF_MAX_RANGE
+            ));
+        }
+        let sample = rng.sample_range(lo, hi, picks as usize);
+        sample.into_iter().map(|n| n.to_string()).collect()
+    } else if echo {
+        args
+    } else {
+        if args.len() > 1 {
+            return CommandResult::with_stderr(format!("shuf: extra operand '{}'", args[1]));
+        }
+        let path = args.first().map_or("-", String::as_str);
+        match read_input(path, ctx.stdin) {
+            Ok(data) => String::from_utf8_lossy(&data)
+                .lines()
+                .map(String::from)
+                .collect(),
+            Err(e) => return CommandResult::with_stderr(format!("shuf: {}: {}", path, e)),
+        }
+    };
+
+    rng.shuffle(&mut lines, count);
+    lines.truncate(count);
+
+    let mut output = lines.join("\n");
+    if !lines.is_empty() {
+        output.push('\n');
+    }
+    CommandResult::with_stdout(output)
+}
+
+/// Prints each file, or standard input, with its lines in reverse order.
+///
+/// Regular files are read backwards in fixed-size chunks, so memory use
+/// depends on the longest line rather than the file size. Standard input
+/// cannot be read backwards and is buffered first.
+fn tac_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    let ShellContext { stdin, out, .. } = ctx;
+    let mut result = CommandResult::new();
+    let paths = if args.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        args
+    };
+
+    for path in &paths {
+        let written = if path == "-" {
+            let mut data = Vec::new();
+            stdin
+                .read_to_end(&mut data)
+                .and_then(|_| tac_reverse(&mut io::Cursor::new(data), &mut out.stdout, TAC_CHUNK))
+        } else {
+            File::open(path).and_then(|mut file| tac_reverse(&mut file, &mut out.stdout, TAC_CHUNK))
+        };
+        if let Err(e) = written {
+            result.append_stderr(&format!("tac: {}: {}", path, e));
+        }
+    }
+    let _ = out.stdout.flush();
+    result
+}
+
+/// Number of bytes `tac` reads at a time.
+const TAC_CHUNK: usize = 64 * 1024;
+
+/// Writes the lines of `input` to `out` last line first, reading backwards
+/// `chunk` bytes at a time.
+///
+/// Each line keeps its trailing newline; a final line without one is
+/// printed as is, directly followed by the line before it.
+fn tac_reverse<R: Read + io::Seek>(
+    input: &mut R,
+    out: &mut impl Write,
+    chunk: usize,
+) -> io::Result<()> {
+    let mut pos = input.seek(io::SeekFrom::End(0))?;
+    // Pieces of the current line read so far, last piece first. They are
+    // only joined once the line's start is found, so long lines cost no
+    // more than short ones.
+    let mut pending: Vec<Vec<u8>> = Vec::new();
+    let write_line = |out: &mut dyn Write, head: &[u8], pending: &mut Vec<Vec<u8>>| {
+        out.write_all(head)?;
+        for piece in pending.drain(..).rev() {
+            out.write_all(&piece)?;
+        }
+        io::Result::Ok(())
+    };
+
+    while pos > 0 {
+        let start = pos.saturating_sub(chunk as u64);
+        let mut buf = vec![0; (pos - start) as usize];
+        input.seek(io::SeekFrom::Start(start))?;
+        input.read_exact(&mut buf)?;
+        pos = start;
+
+        let mut end = buf.len();
+        for i in (0..buf.len()).rev() {
+            // A newline ends the line before it; skip the empty "line"
+            // after the file's final newline
+            if buf[i] == b'\n' && (i + 1 < end || !pending.is_empty()) {
+                write_line(out, &buf[i + 1..end], &mut pending)?;
+                end = i + 1;
+            }
+        }
+        buf.truncate(end);
+        if !buf.is_empty() {
+            pending.push(buf);
+        }
+    }
+    write_line(out, &[], &mut pending)
+}
+
+const STRINGS_SPEC: Spec = Spec {
+    name: "strings",
+    summary: "print printable character runs in files",
+    forms: &["[FILE]..."],
+    flags: &[
+        Flag::with_value(
+            &["-n", "--bytes"],
+            "MIN",
+            "Print runs of at least MIN characters.",
+        )
+        .or_default("4"),
+        Flag::with_value(
+            &["-t", "--radix"],
+            "RADIX",
+            "Put the byte offset of each run before it, in octal (o), decimal (d), or \
+             hexadecimal (x).",
+        ),
+    ],
+};
+
+/// Prints runs of at least MIN (default 4) printable characters found in
+/// each file, or in standard input.
+///
+/// Supports the following flags:
+/// - `-n MIN`: Minimum run length.
+/// - `-t o|d|x`: Prefix each string with its byte offset in octal,
+///   decimal, or hexadecimal.
+///
+/// Printable means ASCII from space to `~`, plus tab. Files are scanned in a
+/// single streaming pass.
+fn strings_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let ShellContext { stdin, out, .. } = ctx;
+    let min = match args.get::<usize>("-n") {
+        Ok(Some(n)) if n > 0 => n,
+        _ => {
+            return CommandResult::with_stderr(format!(
+                "strings: invalid minimum string length '{}'",
+                args.value("-n").unwrap_or_default()
+            ));
+        }
+    };
+    let radix = match args.value("-t") {
+        None => None,
+        Some(r @ ("o" | "d" | "x")) => r.chars().next(),
+        Some(r) => {
+            return CommandResult::with_stderr(format!("strings: invalid radix '{}'", r));
+        }
+    };
+
+    let mut result = CommandResult::new();
+    let paths = if args.operands.is_empty() {
+        vec!["-".to_string()]
+    } else {
+        args.operands
+    };
+    for path in &paths {
+        let scanned = if path == "-" {
+            scan_strings(&mut **stdin, min, radix, &mut out.stdout)
+        } else {
+            File::open(path)
+                .and_then(|file| scan_strings(BufReader::new(file), min, radix, &mut out.stdout))
+        };
+        if let Err(e) = scanned {
+            result.append_stderr(&format!("strings: {}: {}", path, e));
+        }
+    }
+    let _ = out.stdout.flush();
+    result
+}
+
+/// Writes each run of `min` or more printable bytes in `input` to `out`,
+/// one per line, optionally prefixed by its offset in the given radix.
+fn scan_strings(
+    mut input: impl Read,
+    min: usize,
+    radix: Option<char>,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut run: Vec<u8> = Vec::new();
+    let mut run_start = 0u64;
+    let mut offset = 0u64;
+    let mut buf = [0u8; 8192];
+
+    let mut flush = |run: &mut Vec<u8>, start: u64| -> io::Result<()> {
+        if run.len() >= min {
+            match radix {
+                Some('o') => write!(out, "{:7o} ", start)?,
+                Some('d') => write!(out, "{:7} ", start)?,
+                Some(_) => write!(out, "{:7x} ", start)?,
+                None => {}
+            }
+            out.write_all(run)?;
+            out.write_all(b"\n")?;
+        }
+        run.clear();
+        Ok(())
+    };
+
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for &b in &buf[..n] {
+            if b == b'\t' || (b' '..=b'~').contains(&b) {
+                if run.is_empty() {
+                    run_start = offset;
+                }
+                run.push(b);
+            } else {
+                flush(&mut run, run_start)?;
+            }
+            offset += 1;
+        }
+    }
+    flush(&mut run, run_start)
+}
+
+const BASE64_SPEC: Spec = Spec {
+    name: "base64",
+    summary: "encode or decode base64",
+    forms: &["[FILE]"],
+    flags: &[
+        Flag::new(&["-d", "--decode"], "Decode instead of encode."),
+        Flag::new(
+            &["-i", "--ignore-garbage"],
+            "When decoding, skip characters outside the base64 alphabet.",
+        ),
+        Flag::with_value(
+            &["-w", "--wrap"],
+            "COLS",
+            "Wrap encoded lines after COLS characters, or not at all if COLS is 0.",
+        )
+        .or_default("76"),
+    ],
+};
+
+/// Encodes FILE (or standard input) as base64, or decodes it with `-d`.
+///
+/// Supports the following flags:
+/// - `-d`: Decode instead of encode.
+/// - `-i`: When decoding, skip characters outside the base64 alphabet.
+/// - `-w COLS`: Wrap encoded lines after COLS characters (default 76,
+///   0 disables wrapping).
+///
+/// Input is processed in blocks and written out as it goes.
+fn base64_callback(parsed: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    use crate::base64::{Decoder, Encoder};
+
+    let Ok(Some(wrap)) = parsed.get::<usize>("-w") else {
+        return CommandResult::with_stderr(format!(
+            "base64: invalid wrap size: '{}'",
+            parsed.value("-w").unwrap_or_default()
+        ));
+    };
+    let decode = parsed.has("-d");
+    let ignore_garbage = parsed.has("-i");
+    let args = parsed.operands;
+    if args.len() > 1 {
+        return CommandResult::with_stderr(format!("base64: extra operand '{}'", args[1]));
+    }
+    let path = args.first().map_or("-", String::as_str);
+
+    let mut input: Box<dyn Read> = if path == "-" {
+        Box::new(&mut *ctx.stdin)
+    } else {
+        match File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(e) => return CommandResult::with_stderr(format!("base64: {}: {}", path, e)),
+        }
+    };
+
+    let stdout = &mut ctx.out.stdout;
+    let mut encoder = Encoder::new(wrap);
+    let mut decoder = Decoder::new(ignore_garbage);
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut out = Vec::new();
+    let outcome = (|| -> Result<(), String> {
+        loop {
+            let n = match input.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(format!("{}: {}", path, e)),
+            };
+            if decode {
+                decoder.update(&buf[..n], &mut out)?;
+            } else {
+                encoder.update(&buf[..n], &mut out);
+            }
+            stdout.write_all(&out).map_err(|e| e.to_string())?;
+            out.clear();
+        }
+        if decode {
+            decoder.finish(&mut out)?;
+        } else {
+            encoder.finish(&mut out);
+        }
+        stdout.write_all(&out).map_err(|e| e.to_string())?;
+        stdout.flush().map_err(|e| e.to_string())
+    })();
+
+    match outcome {
+        Ok(()) => CommandResult::new(),
+        Err(e) => CommandResult::with_stderr(format!("base64: {}", e)),
+    }
+}
+
+/// Prints the POSIX CRC checksum and byte count of each file, or of standard
+/// input, in the same format as POSIX `cksum`.
+fn cksum_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    use crate::compress::{cksum_finish, cksum_update};
+
+    checksum_inputs("cksum", args, ctx, |input| {
+        let (crc, len) = fold_input(input, 0, cksum_update)?;
+        Ok(format!("{} {}", cksum_finish(crc, len), len))
+    })
+}
+
+/// Prints the CRC-32 (the checksum stored by gzip and zip) of each file, or
+/// of standard input, as eight hex digits.
+fn crc32_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    checksum_inputs("crc32", args, ctx, |input| {
+        let (crc, _) = fold_input(input, 0, crate::compress::crc32_update)?;
+        Ok(format!("{:08x}", crc))
+    })
+}
+
+/// Runs `checksum` over each file (or standard input when there are none)
+/// and prints one line per input: the checksum, then the file name.
+fn checksum_inputs(
+    name: &str,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+    checksum: impl Fn(&mut dyn Read) -> io::Result<String>,
+) -> CommandResult {
+    if args.is_empty() {
+        return match checksum(ctx.stdin) {
+            Ok(sum) => CommandResult::with_stdout(format!("{}\n", sum)),
+            Err(e) => CommandResult::with_stderr(format!("{}: -: {}", name, e)),
+        };
+    }
+
+    let mut result = CommandResult::new();
+    for path in &args {
+        let sum = if path == "-" {
+            checksum(ctx.stdin)
+        } else {
+            File::open(path).and_then(|mut file| checksum(&mut file))
+        };
+        match sum {
+            Ok(sum) => result
+                .stdout
+                .extend_from_slice(format!("{} {}\n", sum, path).as_bytes()),
+            Err(e) => result.append_stderr(&format!("{}: {}: {}", name, path, e)),
+        }
+    }
+    result
+}
+
+/// Streams `input` through `update` in blocks, returning the final state and
+/// the number of bytes read.
+fn fold_input(
+    input: &mut dyn Read,
+    init: u32,
+    update: fn(u32, &[u8]) -> u32,
+) -> io::Result<(u32, u64)> {
+    let mut state = init;
+    let mut len = 0u64;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        match input.read(&mut buf) {
+            Ok(0) => return Ok((state, len)),
+            Ok(n) => {
+                state = update(state, &buf[..n]);
+                len += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Flushes cached writes to permanent storage.
+///
+/// Without arguments, all filesystems are synced. With files, only those
+/// files are synced (`fsync`), or just their data with `-d`, or the whole
+/// filesystems containing them with `-f`.
+fn sync_callback(flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    let data_only = flags.iter().any(|f| f == "-d" || f == "--data");
+    let file_system = flags.iter().any(|f| f == "-f" || f == "--file-system");
+    if data_only && file_system {
+        return CommandResult::with_stderr(
+            "sync: cannot specify both --data and --file-system".to_string(),
+        );
+    }
+    if args.is_empty() {
+        if data_only || file_system {
+            return CommandResult::with_stderr(
+                "sync: --data and --file-system need at least one file".to_string(),
+            );
+        }
+        #[cfg(unix)]
+        unsafe {
+            libc::sync();
+        }
+        return CommandResult::new();
+    }
+
+    let mut result = CommandResult::new();
+    for path in &args {
+        // Directories can be synced too, but only opened read-only
+        let synced = File::open(path).and_then(|file| {
+            if file_system {
+                sync_file_system(&file)
+            } else if data_only {
+                file.sync_data()
+            } else {
+                file.sync_all()
+            }
+        });
+        if let Err(e) = synced {
+            result.append_stderr(&format!("sync: error syncing '{}': {}", path, e));
+        }
+    }
+    result
+}
+
+/// Syncs the filesystem that contains `file`.
+fn sync_file_system(file: &File) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        if unsafe { libc::syncfs(file.as_raw_fd()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    {
+        file.sync_all()
+    }
+}
+
+/// Parses a file mode given in octal (`644`) or symbolic form (`u+x`,
+/// `go-w`, `a=rw,u+x`), applying symbolic changes to `base`.
+///
+/// Symbolic clauses are `[ugoa]*[+-=][rwxXst]*`, separated by commas, with
+/// several operations allowed per clause (`u+r-w`). An empty "who" means
+/// all. `X` adds execute only if `base` is a directory mode or already has
+/// an execute bit.
+fn parse_mode(spec: &str, base: u32, is_dir: bool) -> Option<u32> {
+    if !spec.is_empty() && spec.chars().all(|c| c.is_digit(8)) {
+        return u32::from_str_radix(spec, 8).ok().filter(|&m| m <= 0o7777);
+    }
+
+    let mut mode = base & 0o7777;
+    for clause in spec.split(',') {
+        let ops_start = clause.find(['+', '-', '='])?;
+        let (who, mut rest) = clause.split_at(ops_start);
+
+        let mut who_mask = 0;
+        for c in who.chars() {
+            who_mask |= match c {
+                'u' => 0o4700,
+                'g' => 0o2070,
+                'o' => 0o1007,
+                'a' => 0o7777,
+                _ => return None,
+            };
+        }
+        if who_mask == 0 {
+            who_mask = 0o7777;
+        }
+
+        while let Some(op) = rest.chars().next() {
+            let perms_end = rest[1..]
+                .find(['+', '-', '='])
+                .map_or(rest.len(), |i| i + 1);
+            let mut bits = 0;
+            for c in rest[1..perms_end].chars() {
+                bits |= match c {
+                    'r' => 0o444,
+                    'w' => 0o222,
+                    'x' => 0o111,
+                    'X' if is_dir || mode & 0o111 != 0 => 0o111,
+                    'X' => 0,
+                    's' => 0o6000,
+                    't' => 0o1000,
+                    _ => return None,
+                };
+            }
+            let bits = bits & who_mask;
+            match op {
+                '+' => mode |= bits,
+                '-' => mode &= !bits,
+                '=' => mode = (mode & !(who_mask & 0o777)) | bits,
+                _ => return None,
+            }
+            rest = &rest[perms_end..];
+        }
+    }
+    Some(mode)
+}
+
+const MKFIFO_SPEC: Spec = Spec {
+    name: "mkfifo",
+    summary: "create named pipes",
+    forms: &["NAME..."],
+    flags: &[Flag::with_value(
+        &["-m", "--mode"],
+        "MODE",
+        "Give new pipes MODE, in octal or symbolic form, instead of 0666 less the umask.",
+    )],
+};
+
+/// Creates named pipes (FIFOs).
+///
+/// With `-m MODE` (octal or symbolic, relative to `a=rw`), each pipe gets
+/// exactly that mode; otherwise `0666` reduced by the umask.
+fn mkfifo_callback(args: ParsedArgs) -> CommandResult {
+    let mode = match args.value("-m") {
+        None => None,
+        Some(spec) => match parse_mode(spec, 0o666, false) {
+            Some(mode) => Some(mode),
+            None => return CommandResult::with_stderr(format!("mkfifo: invalid mode '{}'", spec)),
+        },
+    };
+
+    let mut result = CommandResult::new();
+    for path in &args.operands {
+        if let Err(e) = make_fifo(path, mode) {
+            result.append_stderr(&format!("mkfifo: cannot create fifo '{}': {}", path, e));
+        }
+    }
+    result
+}
+
+#[cfg(unix)]
+fn make_fifo(path: &str, mode: Option<u32>) -> io::Result<()> {
+    let c_path = std::ffi::CString::new(path)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "path contains a NUL byte"))?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o666) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // An explicit mode is not subject to the umask
+    if let Some(mode) = mode {
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_fifo(_path: &str, _mode: Option<u32>) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "named pipes are not supported on this platform",
+    ))
+}
+
+/// The type `getrlimit` takes for a resource: an enum in glibc and uClibc,
+/// a plain `int` in other C libraries.
+#[cfg(all(target_os = "linux", any(target_env = "gnu", target_env = "uclibc")))]
+type RlimitResource = libc::__rlimit_resource_t;
+#[cfg(all(
+    unix,
+    not(all(target_os = "linux", any(target_env = "gnu", target_env = "uclibc")))
+))]
+type RlimitResource = libc::c_int;
+
+/// A resource limit known to `ulimit`: its flag letter, description, unit
+/// label, and the number of bytes (or other units) per displayed unit.
+#[cfg(unix)]
+struct UlimitResource {
+    flag: char,
+    resource: RlimitResource,
+    description: &'static str,
+    unit: &'static str,
+    scale: u64,
+}
+
+#[cfg(unix)]
+const ULIMIT_RESOURCES: &[UlimitResource] = &[
+    UlimitResource {
+        flag: 'c',
+        resource: libc::RLIMIT_CORE,
+        description: "core file size",
+        unit: "blocks",
+        scale: 1024,
+    },
+    UlimitResource {
+        flag: 'd',
+        resource: libc::RLIMIT_DATA,
+        description: "data seg size",
+        unit: "kbytes",
+        scale: 1024,
+    },
+    UlimitResource {
+        flag: 'f',
+        resource: libc::RLIMIT_FSIZE,
+        description: "file size",
+        unit: "blocks",
+        scale: 1024,
+    },
+    UlimitResource {
+        flag: 'l',
+        resource: libc::RLIMIT_MEMLOCK,
+        description: "max locked memory",
+        unit: "kbytes",
+        scale: 1024,
+    },
+    UlimitResource {
+        flag: 'm',
+        resource: libc::RLIMIT_RSS,
+        description: "max memory size",
+        unit: "kbytes",
+        scale: 1024,
+    },
+    UlimitResource {
+        flag: 'n',
+        resource: libc::RLIMIT_NOFILE,
+        description: "open files",
+        unit: "",
+        scale: 1,
+    },
+    UlimitResource {
+        flag: 's',
+        resource: libc::RLIMIT_STACK,
+        description: "stack size",
+        unit: "kbytes",
+        scale: 1024,
+    },
+    UlimitResource {
+        flag: 't',
+        resource: libc::RLIMIT_CPU,
+        description: "cpu time",
+        unit: "seconds",
+        scale: 1,
+    },
+    UlimitResource {
+        flag: 'u',
+        resource: libc::RLIMIT_NPROC,
+        description: "max user processes",
+        unit: "",
+        scale: 1,
+    },
+    UlimitResource {
+        flag: 'v',
+        resource: libc::RLIMIT_AS,
+        description: "virtual memory",
+        unit: "kbytes",
+        scale: 1024,
+    },
+];
+
+/// Shows or sets limits on the resources available to the shell and the
+/// commands it runs.
+///
+/// Each flag from `-c -d -f -l -m -n -s -t -u -v` selects a resource
+/// (default `-f`); `-a` shows them all. `-S` and `-H` select the soft or
+/// hard limit; showing uses the soft limit by default, and setting changes
+/// both unless one is given. LIMIT is a number in the resource's units,
+/// `unlimited`, `soft`, or `hard`.
+fn ulimit_callback(flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    #[cfg(unix)]
+    {
+        let soft = flags.iter().any(|f| f == "-S");
+        let hard = flags.iter().any(|f| f == "-H");
+        let all = flags.iter().any(|f| f == "-a");
+
+        let mut selected = Vec::new();
+        for flag in &flags {
+            match flag.as_str() {
+                "-S" | "-H" | "-a" => {}
+                _ => match ULIMIT_RESOURCES
+                    .iter()
+                    .find(|r| flag == &format!("-{}", r.flag))
+                {
+                    Some(resource) => selected.push(resource),
+                    None => {
+                        return CommandResult::with_stderr(format!(
+                            "ulimit: {}: invalid option",
+                            flag
+                        ));
+                    }
+                },
+            }
+        }
+        if all {
+            selected = ULIMIT_RESOURCES.iter().collect();
+        } else if selected.is_empty() {
+            selected.push(&ULIMIT_RESOURCES[2]);
+        }
+
+        if let Some(value) = args.first() {
+            if args.len() > 1 {
+                return CommandResult::with_stderr(format!(
+                    "ulimit: {}: too many arguments",
+                    args[1]
+                ));
+            }
+            if all || selected.len() > 1 {
+                return CommandResult::with_stderr(
+                    "ulimit: only one limit can be set at a time".to_string(),
+                );
+            }
+            let (set_soft, set_hard) = if soft || hard {
+                (soft, hard)
+            } else {
+                (true, true)
+            };
+            return match set_ulimit(selected[0], value, set_soft, set_hard) {
+                Ok(()) => CommandResult::new(),
+                Err(e) => CommandResult::with_stderr(format!(
+                    "ulimit: {}: cannot modify limit: {}",
+                    selected[0].description, e
+                )),
+            };
+        }
+
+        let mut result = CommandResult::new();
+        let labelled = selected.len() > 1;
+        for resource in selected {
+            let limit = match get_ulimit(resource.resource) {
+                Ok(limit) => limit,
+                Err(e) => {
+                    result.append_stderr(&format!("ulimit: {}: {}", resource.description, e));
+                    continue;
+                }
+            };
+            let value = if hard && !soft {
+                limit.rlim_max
+            } else {
+                limit.rlim_cur
+            };
+            let shown = format_rlimit(value, resource.scale);
+            if labelled {
+                let units = if resource.unit.is_empty() {
+                    format!("(-{})", resource.flag)
+                } else {
+                    format!("({}, -{})", resource.unit, resource.flag)
+                };
+                result.stdout.extend_from_slice(
+                    format!("{:<20}{:>15} {}\n", resource.description, units, shown).as_bytes(),
+                );
+            } else {
+                result
+                    .stdout
+                    .extend_from_slice(format!("{}\n", shown).as_bytes());
+            }
+        }
+        result
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = (flags, args);
+        CommandResult::with_stderr("ulimit: not supported on this platform".to_string())
+    }
+}
+
+#[cfg(unix)]
+fn get_ulimit(resource: RlimitResource) -> io::Result<libc::rlimit> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(resource, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(limit)
+}
+
+#[cfg(unix)]
+fn set_ulimit(resource: &UlimitResource, value: &str, soft: bool, hard: bool) -> io::Result<()> {
+    let mut limit = get_ulimit(resource.resource)?;
+    let new = match value {
+        "unlimited" => libc::RLIM_INFINITY,
+        "soft" => limit.rlim_cur,
+        "hard" => limit.rlim_max,
+        _ => value
+            .parse::<u64>()
+            .ok()
+            .and_then(|n| n.checked_mul(resource.scale))
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("invalid number '{}'", value),
+                )
+            })?,
+    };
+    if soft {
+        limit.rlim_cur = new;
+    }
+    if hard {
+        limit.rlim_max = new;
+    }
+    if unsafe { libc::setrlimit(resource.resource, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Formats a limit in display units, or `unlimited`.
+#[cfg(unix)]
+fn format_rlimit(value: libc::rlim_t, scale: u64) -> String {
+    if value == libc::RLIM_INFINITY {
+        "unlimited".to_string()
+    } else {
+        (value / scale).to_string()
+    }
+}
+
+/// Width of one month in `cal` output.
+const CAL_MONTH_WIDTH: usize = 20;
+
+/// Prints a calendar for a month, three months, or a whole year.
+///
+/// Supports the following flags:
+/// - `-3`: Show the previous, current, and next month.
+/// - `-y`: Show the whole current year.
+/// - `-m` / `-s`: Start weeks on Monday / Sunday (default).
+///
+/// `cal YEAR` shows a whole year and `cal MONTH YEAR` a single month. Today
+/// is highlighted when writing to a terminal.
+fn cal_callback(flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    use chrono::Datelike;
+    use std::io::IsTerminal;
+
+    let three = flags.iter().any(|f| f == "-3");
+    let mut whole_year = flags.iter().any(|f| f == "-y" || f == "--year");
+    let monday_first = flags
+        .iter()
+        .rev()
+        .find_map(|f| match f.as_str() {
+            "-m" | "--monday" => Some(true),
+            "-s" | "--sunday" => Some(false),
+            _ => None,
+        })
+        .unwrap_or(false);
+
+    let today = Local::now().date_naive();
+    let parse = |value: &str, max: u32, what: &str| -> Result<u32, String> {
+        value
+            .parse::<u32>()
+            .ok()
+            .filter(|&n| (1..=max).contains(&n))
+            .ok_or_else(|| format!("cal: invalid {}: '{}'", what, value))
+    };
+    let (month, year) = match args.as_slice() {
+        [] => (today.month(), today.year()),
+        [year] => {
+            whole_year = true;
+            match parse(year, 9999, "year") {
+                Ok(year) => (1, year as i32),
+                Err(e) => return CommandResult::with_stderr(e),
+            }
+        }
+        [month, year] => match (parse(month, 12, "month"), parse(year, 9999, "year")) {
+            (Ok(month), Ok(year)) => (month, year as i32),
+            (Err(e), _) | (_, Err(e)) => return CommandResult::with_stderr(e),
+        },
+        _ => return CommandResult::with_stderr("cal: too many arguments".to_string()),
+    };
+
+    let highlight = io::stdout().is_terminal().then_some(today);
+    let calendar = if whole_year {
+        format_cal_year(year, monday_first, highlight)
+    } else if three {
+        let (prev_year, prev) = if month == 1 {
+            (year - 1, 12)
+        } else {
+            (year, month - 1)
+        };
+        let (next_year, next) = if month == 12 {
+            (year + 1, 1)
+        } else {
+            (year, month + 1)
+        };
+        let months = [(prev_year, prev), (year, month), (next_year, next)]
+            .map(|(y, m)| cal_month_lines(y, m, true, monday_first, highlight));
+        join_cal_months(&months)
+    } else {
+        join_cal_months(&[cal_month_lines(year, month, true, monday_first, highlight)])
+    };
+    CommandResult::with_stdout(calendar)
+}
+
+/// Lays out one month as 8 lines of exactly [`CAL_MONTH_WIDTH`] visible
+/// columns: the title, weekday names, and six week rows.
+fn cal_month_lines(
+    year: i32,
+    month: u32,
+    with_year: bool,
+    monday_first: bool,
+    highlight: Option<chrono::NaiveDate>,
+) -> Vec<String> {
+    use chrono::{Datelike, NaiveDate};
+
+    const MONTHS: [&str; 12] = [
+        "January",
+        "February",
+        "March",
+        "April",
+        "May",
+        "June",
+        "July",
+        "August",
+        "September",
+        "October",
+        "November",
+        "December",
+    ];
+    let title = if with_year {
+        format!("{} {}", MONTHS[month as usize - 1], year)
+    } else {
+        MONTHS[month as usize - 1].to_string()
+    };
+    let mut lines = vec![
+        format!("{:^width$}", title, width = CAL_MONTH_WIDTH),
+        if monday_first {
+            "Mo Tu We Th Fr Sa Su ".to_string()
+        } else {
+            "Su Mo Tu We Th Fr Sa ".to_string()
+        },
+    ];
+    lines[1].truncate(CAL_MONTH_WIDTH);
+
+    let Some(first) = NaiveDate::from_ymd_opt(year, month, 1) else {
+        return lines;
+    };
+    let offset = if monday_first {
+        first.weekday().num_days_from_monday()
+    } else {
+        first.weekday().num_days_from_sunday()
+    } as usize;
+    let days = (28..=31)
+        .rev()
+        .find(|&d| NaiveDate::from_ymd_opt(year, month, d).is_some())
+        .unwrap_or(28);
+
+    let mut cells: Vec<String> = vec!["  ".to_string(); offset];
+    for day in 1..=days {
+        let cell = format!("{:>2}", day);
+        if highlight == NaiveDate::from_ymd_opt(year, month, day) {
+            cells.push(format!(
+                "{}{}{}",
+                crate::terminal::REVERSE,
+                cell,
+                crate::terminal::RESET
+            ));
+        } else {
+            cells.push(cell);
+        }
+    }
+    cells.resize(42, "  ".to_string());
+    lines.extend(cells.chunks(7).map(|week| week.join(" ")));
+    lines
+}
+
+/// Places month blocks side by side, two spaces apart, trimming trailing
+/// blanks from each line.
+fn join_cal_months(months: &[Vec<String>]) -> String {
+    let rows = months.iter().map(Vec::len).max().unwrap_or(0);
+    let mut out = String::new();
+    for row in 0..rows {
+        let line: Vec<&str> = months
+            .iter()
+            .map(|m| m.get(row).map_or("", String::as_str))
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+/// Lays out a whole year as four rows of three months under a year title.
+fn format_cal_year(year: i32, monday_first: bool, highlight: Option<chrono::NaiveDate>) -> String {
+    let width = CAL_MONTH_WIDTH * 3 + 4;
+    let mut out = format!("{:^width$}", year, width = width)
+        .trim_end()
+        .to_string();
+    out.push_str("\n\n");
+    for quarter in 0..4 {
+        let months: Vec<Vec<String>> = (1..=3)
+            .map(|i| cal_month_lines(year, quarter * 3 + i, false, monday_first, highlight))
+            .collect();
+        out.push_str(&join_cal_months(&months));
+    }
+    out
+}
+
+/// Evaluates its arguments as one arithmetic expression.
+///
+/// Arguments are joined with spaces, so `calc 3 '*' 4` and `calc "3 * 4"`
+/// are equivalent. Names refer to the shell's variables.
+fn calc_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    evaluate_expression("calc", &args, &ctx.state.vars)
+}
+
+/// Same as `calc`, under the traditional name.
+fn expr_callback(_flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    evaluate_expression("expr", &args, &ctx.state.vars)
+}
+
+fn evaluate_expression(name: &str, args: &[String], vars: &Variables) -> CommandResult {
+    match crate::expr::evaluate(&args.join(" "), &|var| vars.get(var)) {
+        Ok(value) => CommandResult::with_stdout(format!("{}\n", value)),
+        Err(e) => CommandResult::with_stderr(format!("{}: {}", name, e)),
+    }
+}
+
+const UUIDGEN_SPEC: Spec = Spec {
+    name: "uuidgen",
+    summary: "generate random (v4) or time-ordered (v7) UUIDs",
+    forms: &[""],
+    flags: &[
+        Flag::new(
+            &["-r", "--random"],
+            "Generate random (version 4) UUIDs. The default.",
+        ),
+        Flag::new(
+            &["-t", "--time"],
+            "Generate time-ordered (version 7) UUIDs, which sort in the order they were made.",
+        ),
+        Flag::with_value(&["-n", "--count"], "COUNT", "Generate COUNT UUIDs.").or_default("1"),
+    ],
+};
+
+/// Generates UUIDs: random version 4 by default, or time-ordered version 7
+/// with `-t`. `-n COUNT` prints several, one per line.
+fn uuidgen_callback(args: ParsedArgs) -> CommandResult {
+    if let Some(arg) = args.operands.first() {
+        return CommandResult::with_stderr(format!("uuidgen: extra operand '{}'", arg));
+    }
+    let time_based = args.last(&["-r", "-t"]) == Some("-t");
+    let Ok(Some(count)) = args.get::<usize>("-n") else {
+        return CommandResult::with_stderr(format!(
+            "uuidgen: invalid count '{}'",
+            args.value("-n").unwrap_or_default()
+        ));
+    };
+
+    let mut rng = crate::random::Rng::from_entropy();
+    let mut out = String::new();
+    // Version 7 UUIDs made within the same millisecond share a timestamp, so
+    // a 12-bit counter in place of the first random bits keeps them ordered
+    let mut last_millis = 0;
+    let mut sequence = 0;
+    for _ in 0..count {
+        let uuid = if time_based {
+            let now = chrono::Utc::now().timestamp_millis().max(0) as u64;
+            if now > last_millis {
+                last_millis = now;
+                // Start low enough in the range to leave room for increments
+                sequence = rng.below(0x800) as u16;
+            } else if sequence < 0xFFF {
+                sequence += 1;
+            } else {
+                last_millis += 1;
+                sequence = 0;
+            }
+            uuid_v7(&mut rng, last_millis, sequence)
+        } else {
+            uuid_v4(&mut rng)
+        };
+        out.push_str(&format_uuid(&uuid));
+        out.push('\n');
+    }
+    CommandResult::with_stdout(out)
+}
+
+/// A random UUID (RFC 9562 version 4).
+fn uuid_v4(rng: &mut crate::random::Rng) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes);
+    bytes[6] = (bytes[6] & 0x0F) | 0x40;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+    bytes
+}
+
+/// A time-ordered UUID (RFC 9562 version 7): a 48-bit Unix timestamp in
+/// milliseconds, a 12-bit sequence, then random bits.
+fn uuid_v7(rng: &mut crate::random::Rng, millis: u64, sequence: u16) -> [u8; 16] {
+    let mut bytes = [0u8; 16];
+    rng.fill_bytes(&mut bytes[8..]);
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    bytes[6] = 0x70 | (sequence >> 8) as u8 & 0x0F;
+    bytes[7] = sequence as u8;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+    bytes
+}
+
+/// Formats a UUID in the usual 8-4-4-4-12 hex form.
+fn format_uuid(bytes: &[u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Prints a random number from MIN to MAX inclusive, or from 0 to 32767
+/// like `$RANDOM`. Uses the shell's generator, so `RANDOM=SEED` makes the
+/// sequence reproducible.
+fn random_callback(
+    _flags: Vec<String>,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    let (min, max) = match args.as_slice() {
+        [] => (0, 32767),
+        [min, max] => match (min.parse::<i64>(), max.parse::<i64>()) {
+            (Ok(min), Ok(max)) if min <= max => (min, max),
+            (Ok(_), Ok(_)) => {
+                return CommandResult::with_stderr(format!(
+                    "random: MIN is greater than MAX: {} > {}",
+                    min, max
+                ));
+            }
+            (Err(_), _) => {
+                return CommandResult::with_stderr(format!("random: invalid number '{}'", min));
+            }
+            (_, Err(_)) => {
+                return CommandResult::with_stderr(format!("random: invalid number '{}'", max));
+            }
+        },
+        _ => return CommandResult::with_stderr("usage: random [MIN MAX]".to_string()),
+    };
+    let value = ctx.state.rng.with(|rng| rng.between(min, max));
+    CommandResult::with_stdout(format!("{}\n", value))
+}
+
+/// Pretty-prints JSON from files or standard input, optionally selecting
+/// parts of each document with a path query such as `.items[0].name`.
+///
+/// The first argument is taken as the query when it parses as one and is
+/// not an existing file, so `json data.json` and `json . data.json` both
+/// print the whole document.
+fn json_callback(
+    flags: Vec<String>,
+    mut args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    use std::io::IsTerminal;
+
+    let mut compact = false;
+    let mut raw = false;
+    let mut color = io::stdout().is_terminal();
+    for flag in &flags {
+        match flag.as_str() {
+            "-c" | "--compact" => compact = true,
+            "-r" | "--raw" => raw = true,
+            "-C" | "--color" => color = true,
+            "-M" | "--monochrome" => color = false,
+            _ => return CommandResult::with_stderr(format!("json: invalid option '{}'", flag)),
+        }
+    }
+
+    let query = match args.first() {
+        Some(first) if !Path::new(first).is_file() => match crate::json::Query::parse(first) {
+            Ok(query) => {
+                args.remove(0);
+                Some(query)
+            }
+            Err(e) if first.starts_with('.') || first.starts_with('[') => {
+                return CommandResult::with_stderr(format!("json: {}", e));
+            }
+            Err(_) => None,
+        },
+        _ => None,
+    };
+    if args.is_empty() {
+        args.push("-".to_string());
+    }
+
+    let indent = (!compact).then_some(2);
+    let mut result = CommandResult::new();
+    for path in &args {
+        let data = match read_input(path, ctx.stdin) {
+            Ok(data) => data,
+            Err(e) => {
+                result.append_stderr(&format!("json: {}: {}", path, e));
+                continue;
+            }
+        };
+        let values = match crate::json::parse_all(&String::from_utf8_lossy(&data)) {
+            Ok(values) => values,
+            Err(e) => {
+                result.append_stderr(&format!("json: {}: {}", path, e));
+                continue;
+            }
+        };
+        for value in &values {
+            let selected = match &query {
+                Some(query) => match query.apply(value) {
+                    Ok(selected) => selected,
+                    Err(e) => {
+                        result.append_stderr(&format!("json: {}: {}", path, e));
+                        continue;
+                    }
+                },
+                None => vec![value],
+            };
+            for item in selected {
+                match item {
+                    crate::json::Value::String(s) if raw => {
+                        result.stdout.extend_from_slice(s.as_bytes())
+                    }
+                    _ => result
+                        .stdout
+                        .extend_from_slice(crate::json::format(item, indent, color).as_bytes()),
+                }
+                result.stdout.push(b'\n');
+            }
+        }
+    }
+    result
+}
+
+const CSV_SPEC: Spec = Spec {
+    name: "csv",
+    summary: "show CSV data as aligned columns",
+    forms: &["[FILE]..."],
+    flags: &[
+        Flag::new(
+            &["-t", "--tsv"],
+            "Read tab-separated values. This is the default for .tsv files.",
+        ),
+        Flag::with_value(
+            &["-d", "--delimiter"],
+            "DELIM",
+            "Separate fields by DELIM, a single character or \\t, instead of a comma.",
+        ),
+        Flag::new(
+            &["-N", "--no-header"],
+            "Treat the first row as data rather than a header.",
+        ),
+        Flag::with_value(
+            &["--columns"],
+            "LIST",
+            "Show only these columns, in this order, by header name or number from 1, \
+             separated by commas.",
+        ),
+        Flag::with_value(&["--head"], "N", "Show only the first N data rows."),
+    ],
+};
+
+/// Shows CSV or TSV data as aligned columns, with the header row underlined.
+///
+/// The delimiter is a tab with `-t` or for `.tsv` files, otherwise a comma
+/// unless `-d` gives another. `--columns` selects and orders columns by
+/// header name or 1-based number, and `--head N` shows only the first N
+/// data rows.
+fn csv_callback(parsed: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let tabs = parsed.has("-t");
+    let no_header = parsed.has("-N");
+    let delimiter = match parsed.value("-d") {
+        None => None,
+        Some("\\t") => Some('\t'),
+        Some(d) if d.chars().count() == 1 => d.chars().next(),
+        Some(d) => {
+            return CommandResult::with_stderr(format!(
+                "csv: the delimiter must be a single character: '{}'",
+                d
+            ));
+        }
+    };
+    let head = match parsed.get::<usize>("--head") {
+        Ok(head) => head.unwrap_or(usize::MAX),
+        Err(_) => {
+            return CommandResult::with_stderr(format!(
+                "csv: invalid row count: '{}'",
+                parsed.value("--head").unwrap_or_default()
+            ));
+        }
+    };
+    let mut args = parsed.operands.clone();
+    if args.is_empty() {
+        args.push("-".to_string());
+    }
+
+    let mut result = CommandResult::new();
+    for path in &args {
+        let data = match read_input(path, ctx.stdin) {
+            Ok(data) => data,
+            Err(e) => {
+                result.append_stderr(&format!("csv: {}: {}", path, e));
+                continue;
+            }
+        };
+        let delimiter = delimiter.unwrap_or(if tabs || path.ends_with(".tsv") {
+            '\t'
+        } else {
+            ','
+        });
+        let mut rows = parse_csv(&String::from_utf8_lossy(&data), delimiter);
+        rows.truncate(head.saturating_add(!no_header as usize));
+
+        if let Some(list) = parsed.value("--columns") {
+            let header = if no_header { None } else { rows.first() };
+            let selected = match select_csv_columns(list, header) {
+                Ok(selected) => selected,
+                Err(e) => {
+                    result.append_stderr(&format!("csv: {}", e));
+                    continue;
+                }
+            };
+            rows = rows
+                .into_iter()
+                .map(|row| {
+                    selected
+                        .iter()
+                        .map(|&i| row.get(i).cloned().unwrap_or_default())
+                        .collect()
+                })
+                .collect();
+        }
+        result
+            .stdout
+            .extend_from_slice(format_table(&rows, !no_header).as_bytes());
+    }
+    result
+}
+
+/// Splits CSV text into rows of fields (RFC 4180). Quoted fields may
+/// contain the delimiter, line breaks, and doubled quotes; a quote inside
+/// an unquoted field is taken literally.
+fn parse_csv(text: &str, delimiter: char) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    // Whether the current field started with a quote
+    let mut was_quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if quoted {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => quoted = false,
+                _ => field.push(c),
+            }
+        } else if c == '"' && field.is_empty() && !was_quoted {
+            quoted = true;
+            was_quoted = true;
+        } else if c == delimiter {
+            row.push(std::mem::take(&mut field));
+            was_quoted = false;
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            row.push(std::mem::take(&mut field));
+            rows.push(std::mem::take(&mut row));
+            was_quoted = false;
+        } else {
+            field.push(c);
+        }
+    }
+    if !field.is_empty() || !row.is_empty() || was_quoted {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// Resolves a comma-separated list of column names or 1-based numbers to
+/// column indices. Names need a header row.
+fn select_csv_columns(list: &str, header: Option<&Vec<String>>) -> Result<Vec<usize>, String> {
+    list.split(',')
+        .map(|name| {
+            if let Some(i) = header.and_then(|h| h.iter().position(|f| f == name)) {
+                return Ok(i);
+            }
+            match name.parse::<usize>() {
+                Ok(n) if n > 0 => Ok(n - 1),
+                _ => Err(format!("no such column: '{}'", name)),
+            }
+        })
+        .collect()
+}
+
+/// Lays out rows as left-aligned columns two spaces apart, padding by
+/// display width. With `header`, the first row is underlined with dashes.
+/// Line breaks inside fields are shown as `\n`.
+fn format_table(rows: &[Vec<String>], header: bool) -> String {
+    let rows: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|f| f.replace("\r\n", "\\n").replace(['\n', '\r'], "\\n"))
+                .collect()
+        })
+        .collect();
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let mut widths = vec![0; columns];
+    for row in &rows {
+        for (i, field) in row.iter().enumerate() {
+            widths[i] = widths[i].max(crate::unicode::str_width(field));
+        }
+    }
+
+    let format_row = |cells: &mut dyn Iterator<Item = (&str, usize)>| {
+        let mut line = String::new();
+        for (i, (cell, width)) in cells.enumerate() {
+            if i > 0 {
+                line.push_str("  ");
+            }
+            line.push_str(cell);
+            line.push_str(&" ".repeat(width - crate::unicode::str_width(cell)));
+        }
+        line.truncate(line.trim_end().len());
+        line.push('\n');
+        line
+    };
+
+    let mut out = String::new();
+    for (n, row) in rows.iter().enumerate() {
+        out.push_str(&format_row(
+            &mut widths
+                .iter()
+                .enumerate()
+                .map(|(i, &w)| (row.get(i).map_or("", String::as_str), w)),
+        ));
+        if header && n == 0 {
+            let rules: Vec<String> = widths.iter().map(|&w| "-".repeat(w)).collect();
+            out.push_str(&format_row(
+                &mut rules.iter().zip(&widths).map(|(r, &w)| (r.as_str(), w)),
+            ));
+        }
+    }
+    out
+}
+
+const FETCH_SPEC: Spec = Spec {
+    name: "fetch",
+    summary: "download a file over HTTP or HTTPS",
+    forms: &["URL"],
+    flags: &[
+        Flag::new(
+            &["-c", "--continue"],
+            "Resume a partial download by adding to the existing file.",
+        ),
+        Flag::new(&["-q", "--quiet"], "Show no progress bar or summary."),
+        Flag::with_value(
+            &["-O", "--output"],
+            "FILE",
+            "Save to FILE, replacing it, or write to standard output if FILE is -.",
+        ),
+    ],
+};
+
+/// Downloads a URL to a file named after its last path segment, or to FILE
+/// with `-O` (`-O -` writes to standard output). Redirects are followed,
+/// `-c` resumes a partial download, and a progress bar is shown on
+/// terminals unless `-q` is given. A file named after the URL is never
+/// replaced; only `-O` may name an existing file.
+fn fetch_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let resume = args.has("-c");
+    let quiet = args.has("-q");
+    if let Some(extra) = args.operands.get(1) {
+        return CommandResult::with_stderr(format!("fetch: extra operand '{}'", extra));
+    }
+    let url = match crate::http::Url::parse(&args.operands[0]) {
+        Ok(url) => url,
+        Err(e) => return CommandResult::with_stderr(format!("fetch: {}", e)),
+    };
+    let target = match args.value("-O") {
+        Some(target) => target.to_string(),
+        None => {
+            let name = url.file_name().unwrap_or_else(|| "index.html".to_string());
+            // Only -O may replace a file; -c adds to it
+            if !resume && fs::symlink_metadata(&name).is_ok() {
+                return CommandResult::with_stderr(format!(
+                    "fetch: '{}' already exists; use -O to replace it",
+                    name
+                ));
+            }
+            name
+        }
+    };
+
+    match fetch_url(url, &target, resume, quiet, &mut ctx.out) {
+        Ok(_) if quiet => CommandResult::new(),
+        Ok(report) => CommandResult::with_notice(report),
+        Err(e) => CommandResult::with_stderr(format!("fetch: {}", e)),
+    }
+}
+
+/// Redirects followed before giving up.
+const FETCH_MAX_REDIRECTS: usize = 10;
+
+/// Downloads `url` to `target` (`-` for the stdout of `out`), returning a
+/// summary line.
+fn fetch_url(
+    mut url: crate::http::Url,
+    target: &str,
+    resume: bool,
+    quiet: bool,
+    out: &mut Output,
+) -> Result<String, String> {
+    use std::io::IsTerminal;
+
+    let offset = if resume && target != "-" {
+        fs::metadata(target).map_or(0, |m| m.len())
+    } else {
+        0
+    };
+
+    let mut redirects = 0;
+    let mut response = loop {
+        let headers = if offset > 0 {
+            vec![("Range", format!("bytes={}-", offset))]
+        } else {
+            vec![]
+        };
+        let response =
+            crate::http::get(&url, &headers).map_err(|e| format!("{}: {}", url.host, e))?;
+        match response.status {
+            301 | 302 | 303 | 307 | 308 => {
+                let location = response
+                    .header("location")
+                    .ok_or("redirect without a Location header")?;
+                url = url.join(location)?;
+                redirects += 1;
+                if redirects > FETCH_MAX_REDIRECTS {
+                    return Err("too many redirects".to_string());
+                }
+            }
+            416 if offset > 0 => return Ok(format!("'{}' is already fully retrieved", target)),
+            200..=299 => break response,
+            status => return Err(format!("server returned {} {}", status, response.reason)),
+        }
+    };
+
+    // A server that ignores the range sends the whole file again
+    let append = response.status == 206;
+    let start = if append { offset } else { 0 };
+    let total = response.content_length().map(|len| len + start);
+    let mut output: Box<dyn Write> = if target == "-" {
+        Box::new(&mut *out.stdout)
+    } else {
+        let file = if append {
+            fs::OpenOptions::new().append(true).open(target)
+        } else {
+            File::create(target)
+        };
+        Box::new(io::BufWriter::new(
+            file.map_err(|e| format!("{}: {}", target, e))?,
+        ))
+    };
+
+    let show_progress = !quiet && io::stderr().is_terminal();
+    let started = std::time::Instant::now();
+    let mut last_progress = started;
+    let mut received = start;
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = match response.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(format!("{}: {}", url.host, e)),
+        };
+        output
+            .write_all(&buf[..n])
+            .map_err(|e| format!("{}: {}", target, e))?;
+        received += n as u64;
+        if show_progress && last_progress.elapsed().as_millis() >= 100 {
+            last_progress = std::time::Instant::now();
+            let line = fetch_progress_line(received - start, received, total, started.elapsed());
+            let _ = write!(out.stderr, "\r{}", line);
+            let _ = out.stderr.flush();
+        }
+    }
+    output.flush().map_err(|e| format!("{}: {}", target, e))?;
+    if show_progress {
+        let line = fetch_progress_line(received - start, received, total, started.elapsed());
+        let _ = writeln!(out.stderr, "\r{}", line);
+    }
+
+    let name = if target == "-" { "stdout" } else { target };
+    Ok(format!("'{}' saved [{} bytes]", name, received))
+}
+
+/// Formats the progress bar: percentage, bar, size so far, and rate. Without
+/// a known total only the size and rate are shown.
+fn fetch_progress_line(
+    transferred: u64,
+    received: u64,
+    total: Option<u64>,
+    elapsed: std::time::Duration,
+) -> String {
+    const BAR_WIDTH: usize = 30;
+
+    let secs = elapsed.as_secs_f64();
+    let rate = if secs > 0.0 {
+        format_human_size((transferred as f64 / secs) as u64)
+    } else {
+        "-".to_string()
+    };
+    let size = format_human_size(received);
+    match total {
+        Some(total) if total > 0 => {
+            let fraction = (received as f64 / total as f64).min(1.0);
+            let filled = (fraction * BAR_WIDTH as f64) as usize;
+            let mut bar = "=".repeat(filled);
+            if filled < BAR_WIDTH {
+                bar.push('>');
+            }
+            format!(
+                "{:>3}% [{:<width$}] {:>6}  {}B/s   ",
+                (fraction * 100.0) as u32,
+                bar,
+                size,
+                rate,
+                width = BAR_WIDTH
+            )
+        }
+        _ => format!("{:>6}  {}B/s   ", size, rate),
+    }
+}
+
+const PING_SPEC: Spec = Spec {
+    name: "ping",
+    summary: "send echo requests to a host",
+    forms: &["HOST"],
+    flags: &[
+        Flag::with_value(
+            &["-c", "--count"],
+            "COUNT",
+            "Stop after sending COUNT requests.",
+        ),
+        Flag::with_value(
+            &["-i", "--interval"],
+            "INTERVAL",
+            "Wait INTERVAL seconds, at least 0.2, between requests.",
+        )
+        .or_default("1"),
+        Flag::with_value(
+            &["-W", "--timeout"],
+            "TIMEOUT",
+            "Wait up to TIMEOUT seconds for each reply.",
+        )
+        .or_default("2"),
+    ],
+};
+
+/// Sends echo requests to a host once per interval and reports round-trip
+/// times, until COUNT replies have been requested or Ctrl+C is pressed.
+/// A summary with packet loss and min/avg/max/mdev times is printed at the
+/// end.
+fn ping_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    let count = match args.get::<u64>("-c") {
+        Ok(None) => None,
+        Ok(Some(c)) if c > 0 => Some(c),
+        _ => {
+            return CommandResult::with_stderr(format!(
+                "ping: invalid count: '{}'",
+                args.value("-c").unwrap_or_default()
+            ));
+        }
+    };
+    let seconds = |flag: &str| {
+        let value = args.value(flag).unwrap_or_default();
+        value
+            .parse::<f64>()
+            .ok()
+            .and_then(|s| std::time::Duration::try_from_secs_f64(s).ok())
+            .filter(|s| !s.is_zero())
+            .ok_or_else(|| format!("ping: invalid value for {}: '{}'", flag, value))
+    };
+    let (interval, timeout) = match (seconds("-i"), seconds("-W")) {
+        (Ok(i), Ok(w)) => (i, w),
+        (Err(e), _) | (_, Err(e)) => return CommandResult::with_stderr(e),
+    };
+    if interval < std::time::Duration::from_millis(200) {
+        return CommandResult::with_stderr(
+            "ping: the shortest interval allowed is 0.2 seconds".to_string(),
+        );
+    }
+    let [host] = args.operands.as_slice() else {
+        return CommandResult::with_stderr(format!("ping: extra operand '{}'", args.operands[1]));
+    };
+
+    let ip = match resolve_host(host) {
+        Ok(ip) => ip,
+        Err(e) => return CommandResult::with_stderr(format!("ping: {}: {}", host, e)),
+    };
+    let pinger = crate::net::Pinger::new(ip);
+    let stdout = &mut ctx.out.stdout;
+    let via = if pinger.uses_udp() {
+        " (ICMP unavailable, using UDP probes)"
+    } else {
+        ""
+    };
+    let _ = writeln!(
+        stdout,
+        "PING {} ({}): {} data bytes{}",
+        host,
+        ip,
+        crate::net::PING_PAYLOAD,
+        via
+    );
+    let _ = stdout.flush();
+
+    let _guard = crate::signals::InterruptGuard::install();
+    let started = std::time::Instant::now();
+    let mut sent = 0u64;
+    let mut rtts = Vec::new();
+    let mut result = CommandResult::new();
+    while count.is_none_or(|c| sent < c) && !crate::signals::interrupted() {
+        let seq = (sent + 1) as u16;
+        let probe_start = std::time::Instant::now();
+        let line = match pinger.probe(seq, timeout) {
+            Ok(Some(reply)) => {
+                let ms = reply.rtt.as_secs_f64() * 1000.0;
+                rtts.push(ms);
+                match reply.bytes {
+                    Some(bytes) => {
+                        format!("{} bytes from {}: seq={} time={:.3} ms", bytes, ip, seq, ms)
+                    }
+                    None => format!(
+                        "port unreachable from {}: seq={} time={:.3} ms",
+                        ip, seq, ms
+                    ),
+                }
+            }
+            Ok(None) => format!("Request timeout for seq {}", seq),
+            Err(e) => {
+                result.append_stderr(&format!("ping: {}", e));
+                break;
+            }
+        };
+        sent += 1;
+        let _ = writeln!(stdout, "{}", line);
+        let _ = stdout.flush();
+
+        if count.is_some_and(|c| sent >= c) {
+            break;
+        }
+        let wait = interval.saturating_sub(probe_start.elapsed());
+        if crate::signals::sleep_interruptible(wait) {
+            break;
+        }
+    }
+
+    result.stdout = ping_summary(host, sent, &rtts, started.elapsed()).into_bytes();
+    result
+}
+
+/// Resolves a host name or address, preferring IPv4.
+fn resolve_host(host: &str) -> io::Result<std::net::IpAddr> {
+    use std::net::ToSocketAddrs;
+
+    let addrs: Vec<std::net::IpAddr> = (host, 0).to_socket_addrs()?.map(|a| a.ip()).collect();
+    addrs
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .or(addrs.first())
+        .copied()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses found"))
+}
+
+/// Formats the statistics printed when `ping` finishes.
+fn ping_summary(host: &str, sent: u64, rtts: &[f64], elapsed: std::time::Duration) -> String {
+    let received = rtts.len() as u64;
+    let loss = if sent == 0 {
+        0.0
+    } else {
+        (sent - received) as f64 * 100.0 / sent as f64
+    };
+    let mut out = format!(
+        "\n--- {} ping statistics ---\n{} packets transmitted, {} received, {}% packet loss, time {}ms\n",
+        host,
+        sent,
+        received,
+        format!("{:.1}", loss).trim_end_matches(".0"),
+        elapsed.as_millis()
+    );
+    if !rtts.is_empty() {
+        let n = rtts.len() as f64;
+        let min = rtts.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = rtts.iter().copied().fold(0.0, f64::max);
+        let avg = rtts.iter().sum::<f64>() / n;
+        let mdev = (rtts.iter().map(|t| t * t).sum::<f64>() / n - avg * avg)
+            .max(0.0)
+            .sqrt();
+        out.push_str(&format!(
+            "rtt min/avg/max/mdev = {:.3}/{:.3}/{:.3}/{:.3} ms\n",
+            min, avg, max, mdev
+        ));
+    }
+    out
+}
+
+const NC_SPEC: Spec = Spec {
+    name: "nc",
+    summary: "connect to or listen for a TCP connection",
+    forms: &["HOST PORT", "-l PORT"],
+    flags: &[
+        Flag::new(
+            &["-l", "--listen"],
+            "Wait for one incoming connection on PORT.",
+        ),
+        Flag::new(
+            &["-p"],
+            "Accepted for traditional netcat's -l -p PORT; the port is positional either way.",
+        ),
+        Flag::new(
+            &["-v", "--verbose"],
+            "Report connections on standard error.",
+        ),
+        Flag::with_value(
+            &["-w", "--wait"],
+            "SECONDS",
+            "Give up on connecting after SECONDS.",
+        ),
+    ],
+};
+
+/// Opens a TCP connection and relays standard input to it and its data to
+/// standard output. `nc HOST PORT` connects; `nc -l PORT` waits for one
+/// incoming connection. The command ends when the other side closes the
+/// connection or Ctrl+C is pressed.
+#[cfg(unix)]
+fn nc_callback(args: ParsedArgs, ctx: &mut ShellContext) -> CommandResult {
+    use std::io::IsTerminal;
+    use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+    use std::os::fd::AsRawFd;
+
+    let listen = args.has("-l");
+    let verbose = args.has("-v");
+    let timeout = match args.value("-w").map(|w| {
+        w.parse::<f64>()
+            .ok()
+            .and_then(|w| std::time::Duration::try_from_secs_f64(w).ok())
+            .filter(|w| !w.is_zero())
+    }) {
+        None => None,
+        Some(Some(w)) => Some(w),
+        Some(None) => {
+            return CommandResult::with_stderr(format!(
+                "nc: invalid timeout '{}'",
+                args.value("-w").unwrap_or_default()
+            ));
+        }
+    };
+    let (host, port) = match (listen, args.operands.as_slice()) {
+        (true, [port]) => ("0.0.0.0", port),
+        (false, [host, port]) => (host.as_str(), port),
+        (false, [_]) => return CommandResult::with_stderr("nc: missing port".to_string()),
+        (true, [_, extra, ..]) | (false, [_, _, extra, ..]) => {
+            return CommandResult::with_stderr(format!("nc: extra operand '{}'", extra));
+        }
+        _ => unreachable!("parse_invocation requires an operand"),
+    };
+    let Ok(port) = port.parse::<u16>() else {
+        return CommandResult::with_stderr(format!("nc: invalid port '{}'", port));
+    };
+
+    let _guard = crate::signals::InterruptGuard::install();
+    let stream = if listen {
+        let listener = match TcpListener::bind((host, port)) {
+            Ok(listener) => listener,
+            Err(e) => return CommandResult::with_stderr(format!("nc: port {}: {}", port, e)),
+        };
+        if verbose {
+            let _ = ctx.out.error(&format!(
+                "Listening on {}",
+                listener
+                    .local_addr()
+                    .map_or(port.to_string(), |a| a.to_string())
+            ));
+        }
+        // Accept in non-blocking mode so that Ctrl+C can stop the wait
+        let _ = listener.set_nonblocking(true);
+        loop {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    if verbose {
+                        let _ = ctx.out.error(&format!("Connection received from {}", peer));
+                    }
+                    let _ = stream.set_nonblocking(false);
+                    break stream;
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if crate::signals::sleep_interruptible(std::time::Duration::from_millis(100)) {
+                        return CommandResult::new();
+                    }
+                }
+                Err(e) => return CommandResult::with_stderr(format!("nc: {}", e)),
+            }
+        }
+    } else {
+        let addrs = match (host, port).to_socket_addrs() {
+            Ok(addrs) => addrs,
+            Err(e) => return CommandResult::with_stderr(format!("nc: {}: {}", host, e)),
+        };
+        let mut last_error = None;
+        let mut connected = None;
+        for addr in addrs {
+            let attempt = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+                None => TcpStream::connect(addr),
+            };
+            match attempt {
+                Ok(stream) => {
+                    connected = Some(stream);
+                    break;
+                }
+                Err(e) => last_error = Some(e),
+            }
+        }
+        match (connected, last_error) {
+            (Some(stream), _) => {
+                if verbose {
+                    let _ = ctx
+                        .out
+                        .error(&format!("Connection to {} port {} succeeded", host, port));
+                }
+                stream
+            }
+            (None, Some(e)) => {
+                return CommandResult::with_stderr(format!("nc: {} port {}: {}", host, port, e));
+            }
+            (None, None) => {
+                return CommandResult::with_stderr(format!("nc: {}: no addresses found", host));
+            }
+        }
+    };
+
+    // A terminal is polled, so that data keeps arriving while a line is
+    // being typed. Other input is read as soon as it can be.
+    let stdin = io::stdin();
+    let input_fd = stdin.is_terminal().then(|| stdin.as_raw_fd());
+    match crate::net::relay(&stream, ctx.stdin, input_fd, ctx.out.stdout) {
+        Ok(_) => CommandResult::new(),
+        Err(e) => CommandResult::with_stderr(format!("nc: {}", e)),
+    }
+}
+
+#[cfg(not(unix))]
+fn nc_callback(_args: ParsedArgs, _ctx: &mut ShellContext) -> CommandResult {
+    CommandResult::with_stderr("nc: not supported on this platform".to_string())
+}
+
+/// Looks up each NAME through the system resolver, printing every IPv4
+/// and IPv6 address. Arguments that are IP addresses, or all arguments
+/// with `-x`, are looked up in reverse to find their host name.
+#[cfg(unix)]
+fn resolve_callback(flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    let mut only_v4 = false;
+    let mut only_v6 = false;
+    let mut reverse = false;
+    for flag in &flags {
+        match flag.as_str() {
+            "-4" => only_v4 = true,
+            "-6" => only_v6 = true,
+            "-x" => reverse = true,
+            _ => return CommandResult::with_stderr(format!("resolve: invalid option '{}'", flag)),
+        }
+    }
+
+    let mut result = CommandResult::new();
+    for name in &args {
+        let ip = name.parse::<std::net::IpAddr>();
+        if reverse || ip.is_ok() {
+            let Ok(ip) = ip else {
+                result.append_stderr(&format!("resolve: {}: not an IP address", name));
+                continue;
+            };
+            match crate::net::reverse_lookup(ip) {
+                Ok(host) => result
+                    .stdout
+                    .extend_from_slice(format!("{} domain name pointer {}\n", ip, host).as_bytes()),
+                Err(e) => result.append_stderr(&format!("resolve: {}: {}", ip, e)),
+            }
+            continue;
+        }
+
+        let addrs = match crate::net::lookup(name) {
+            Ok(addrs) => addrs,
+            Err(e) => {
+                result.append_stderr(&format!("resolve: {}: {}", name, e));
+                continue;
+            }
+        };
+        let addrs: Vec<_> = addrs
+            .into_iter()
+            .filter(|ip| !(only_v4 && ip.is_ipv6() || only_v6 && ip.is_ipv4()))
+            .collect();
+        if addrs.is_empty() {
+            result.append_stderr(&format!("resolve: {}: no matching addresses", name));
+        }
+        result
+            .stdout
+            .extend_from_slice(format_addresses(name, &addrs).as_bytes());
+    }
+    result
+}
+
+#[cfg(not(unix))]
+fn resolve_callback(_flags: Vec<String>, _args: Vec<String>) -> CommandResult {
+    CommandResult::with_stderr("resolve: not supported on this platform".to_string())
+}
+
+/// One line per address, IPv4 addresses first.
+fn format_addresses(name: &str, addrs: &[std::net::IpAddr]) -> String {
+    let mut out = String::new();
+    for ip in addrs.iter().filter(|ip| ip.is_ipv4()) {
+        out.push_str(&format!("{} has address {}\n", name, ip));
+    }
+    for ip in addrs.iter().filter(|ip| ip.is_ipv6()) {
+        out.push_str(&format!("{} has IPv6 address {}\n", name, ip));
+    }
+    out
+}
+
+/// Lists network interfaces with their state, hardware address, and IPv4
+/// and IPv6 addresses. `-u` shows only interfaces that are up; naming
+/// interfaces shows just those.
+#[cfg(unix)]
+fn ifinfo_callback(flags: Vec<String>, args: Vec<String>) -> CommandResult {
+    let mut only_up = false;
+    for flag in &flags {
+        match flag.as_str() {
+            "-u" | "--up" => only_up = true,
+            _ => return CommandResult::with_stderr(format!("ifinfo: invalid option '{}'", flag)),
+        }
+    }
+    let interfaces = match crate::net::interfaces() {
+        Ok(interfaces) => interfaces,
+        Err(e) => return CommandResult::with_stderr(format!("ifinfo: {}", e)),
+    };
+
+    let mut result = CommandResult::new();
+    for name in &args {
+        if !interfaces.iter().any(|i| i.name == *name) {
+            result.append_stderr(&format!("ifinfo: {}: no such interface", name));
+        }
+    }
+    for interface in &interfaces {
+        if (!args.is_empty() && !args.contains(&interface.name)) || (only_up && !interface.up) {
+            continue;
+        }
+        result
+            .stdout
+            .extend_from_slice(format_interface(interface).as_bytes());
+    }
+    result
+}
+
+#[cfg(not(unix))]
+fn ifinfo_callback(_flags: Vec<String>, _args: Vec<String>) -> CommandResult {
+    CommandResult::with_stderr("ifinfo: not supported on this platform".to_string())
+}
+
+/// Opens each file or URL with its default application. Files must exist;
+/// anything with a URL scheme is passed through as is.
+fn open_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    if let Some(flag) = flags.first() {
+        return CommandResult::with_stderr(format!("open: invalid option '{}'", flag));
+    }
+    let mut result = CommandResult::new();
+    for target in &args {
+        let is_url = target.contains("://") || target.starts_with("mailto:");
+        if !is_url && !Path::new(target).exists() {
+            result.append_stderr(&format!("open: {}: No such file or directory", target));
+            continue;
+        }
+        if let Err(e) = crate::desktop::open(target, &ctx.state.vars) {
+            result.append_stderr(&format!("open: {}: {}", target, e));
+        }
+    }
+    result
+}
+
+/// Copies the concatenated files, or standard input without any, to the
+/// system clipboard.
+fn clip_callback(flags: Vec<String>, args: Vec<String>, ctx: &mut ShellContext) -> CommandResult {
+    if let Some(flag) = flags.first() {
+        return CommandResult::with_stderr(format!("clip: invalid option '{}'", flag));
+    }
+    let mut data = Vec::new();
+    if args.is_empty()
+        && let Err(e) = ctx.stdin.read_to_end(&mut data)
+    {
+        return CommandResult::with_stderr(format!("clip: stdin: {}", e));
+    }
+    for path in &args {
+        match fs::read(path) {
+            Ok(bytes) => data.extend_from_slice(&bytes),
+            Err(e) => return CommandResult::with_stderr(format!("clip: {}: {}", path, e)),
+        }
+    }
+    match crate::desktop::copy_to_clipboard(&data, &ctx.state.vars, ctx.out.stdout) {
+        Ok(()) => CommandResult::new(),
+        Err(e) => CommandResult::with_stderr(format!("clip: {}", e)),
+    }
+}
+
+/// Prints the clipboard contents.
+fn paste_clip_callback(
+    flags: Vec<String>,
+    args: Vec<String>,
+    ctx: &mut ShellContext,
+) -> CommandResult {
+    if let Some(flag) = flags.first() {
+        return CommandResult::with_stderr(format!("paste-clip: invalid option '{}'", flag));
+    }
+    if let Some(arg) = args.first() {
+        return CommandResult::with_stderr(format!("paste-clip: extra operand '{}'", arg));
+    }
+    match crate::desktop::paste_from_clipboard(&ctx.state.vars) {
+        Ok(bytes) => CommandResult::with_stdout(bytes),
+        Err(e) => CommandResult::with_stderr(format!("paste-clip: {}", e)),
+    }
+}
+
+/// Formats an interface as a name line with its state, then one indented
+/// line per address.
+fn format_interface(interface: &crate::net::Interface) -> String {
+    let mut state = vec![if interface.up { "UP" } else { "DOWN" }];
+    if interface.running {
+        state.push("RUNNING");
+    }
+    if interface.loopback {
+        state.push("LOOPBACK");
+    }
+    let mut out = format!("{}: <{}>\n", interface.name, state.join(","));
+    if let Some(mac) = &interface.mac {
+        let hex: Vec<String> = mac.iter().map(|b| format!("{:02x}", b)).collect();
+        out.push_str(&format!("    ether {}\n", hex.join(":")));
+    }
+    for (ip, prefix) in &interface.addrs {
+        let family = if ip.is_ipv4() { "inet" } else { "inet6" };
+        out.push_str(&format!("    {} {}/{}\n", family, ip, prefix));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    /// Runs `run` with a new shell state and no input, collecting its output
+    /// in the result.
+    fn with_context(
+        cmds: &CommandList,
+        run: impl FnOnce(&mut ShellContext) -> CommandResult,
+    ) -> CommandResult {
+        capture(cmds, &mut ShellState::default(), &mut io::empty(), run)
+    }
+
+    /// Executes a command with its output collected in the result.
+    fn execute(
+        cmds: &CommandList,
+        name: &str,
+        flags: Vec<String>,
+        args: Vec<String>,
+    ) -> CommandResult {
+        with_context(cmds, |ctx| {
+            cmds.execute(
+                ctx,
+                Invocation {
+                    name: name.to_string(),
+                    flags,
+                    args,
+                },
+            )
+        })
+    }
+
+    /// Runs `name` with `args`, in the order given on a command line.
+    fn run_command(name: &str, args: &[&str]) -> CommandResult {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        execute(&command_list(), name, vec![], args)
+    }
+
+    /// Runs `mkdir` with `args`, in the order given on a command line.
+    fn mkdir(args: &[&str]) -> CommandResult {
+        run_command("mkdir", args)
+    }
+
+    /// Runs `ls` with its output collected in the result.
+    fn run_ls(flags: Vec<String>, args: Vec<String>) -> CommandResult {
+        with_context(&command_list(), |ctx| ls_callback(flags, args, ctx))
+    }
+
+    #[test]
+    fn test_echo_basic() {
+        let res = echo_callback(vec![], vec!["hello".to_string(), "world".to_string()]);
+        assert_eq!(res.stdout_text(), "hello world\n");
+    }
+
+    #[test]
+    fn test_echo_escapes() {
+        let res = echo_callback(vec!["-e".to_string()], vec!["hello\\nworld".to_string()]);
+        assert_eq!(res.stdout_text(), "hello\nworld\n");
+    }
+
+    #[test]
+    fn test_echo_numeric_escapes() {
+        let echo = |s: &str| {
+            echo_callback(vec!["-e".to_string()], vec![s.to_string()])
+                .stdout_text()
+                .into_owned()
+        };
+        assert_eq!(echo("\\x41\\x4a2\\x7"), "AJ2\x07\n");
+        assert_eq!(echo("\\xc3\\xa9"), "\u{e9}\n");
+        assert_eq!(echo("\\0101\\0\\01018"), "A\0A8\n");
+        assert_eq!(
+            echo("\\u00e9\\U0001F600\\u263a!"),
+            "\u{e9}\u{1F600}\u{263a}!\n"
+        );
+        // Without digits the escape is left alone
+        assert_eq!(echo("\\xg \\u \\U"), "\\xg \\u \\U\n");
+    }
+
+    #[test]
+    fn test_pwd() {
+        let res = with_context(&command_list(), |ctx| pwd_callback(vec![], vec![], ctx));
+        let current = std::env::current_dir().unwrap();
+        assert_eq!(res.stdout_text(), format!("{}\n", current.display()));
+    }
+
+    #[test]
+    fn test_pwd_physical() {
+        let physical = fs::canonicalize(std::env::current_dir().unwrap()).unwrap();
+        let res = with_context(&command_list(), |ctx| {
+            pwd_callback(vec!["-P".to_string()], vec![], ctx)
+        });
+        assert_eq!(res.stdout_text(), format!("{}\n", physical.display()));
+        let res = with_context(&command_list(), |ctx| {
+            pwd_callback(vec!["-P".to_string(), "-L".to_string()], vec![], ctx)
+        });
+        assert_eq!(
+            res.stdout_text(),
+            format!(
+                "{}\n",
+                logical_cwd(&Variables::default()).unwrap().display()
+            )
+        );
+    }
+
+    #[test]
+    fn test_exit() {
+        let res = with_context(&command_list(), |ctx| exit_callback(vec![], vec![], ctx));
+        assert!(res.should_exit);
+    }
+
+    #[test]
+    fn test_mkdir_and_ls() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test_dir");
+
+        // Test mkdir
+        let res = mkdir(&[path.to_str().unwrap()]);
+        assert!(res.stderr.is_empty());
+        assert!(path.exists());
+
+        // Test ls
+        let res = run_ls(vec![], vec![dir.path().to_str().unwrap().to_string()]);
+        assert!(res.stdout_text().contains("test_dir"));
+    }
+
+    #[test]
+    fn test_cp_and_mv() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src.txt");
+        let dest = dir.path().join("dest.txt");
+        let moved = dir.path().join("moved.txt");
+
+        fs::write(&src, "hello").unwrap();
+
+        // Test cp
+        with_context(&command_list(), |ctx| {
+            cp_callback(
+                vec![],
+                vec![
+                    src.to_str().unwrap().to_string(),
+                    dest.to_str().unwrap().to_string(),
+                ],
+                ctx,
+            )
+        });
+        assert!(dest.exists());
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "hello");
+
+        // Test mv
+        with_context(&command_list(), |ctx| {
+            mv_callback(
+                vec![],
+                vec![
+                    dest.to_str().unwrap().to_string(),
+                    moved.to_str().unwrap().to_string(),
+                ],
+                ctx,
+            )
+        });
+        assert!(!dest.exists());
+        assert!(moved.exists());
+        assert_eq!(fs::read_to_string(&moved).unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_rm() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("to_remove.txt");
+        fs::write(&file, "bye").unwrap();
+
+        with_context(&command_list(), |ctx| {
+            rm_callback(vec![], vec![file.to_str().unwrap().to_string()], ctx)
+        });
+        assert!(!file.exists());
+
+        let sub_dir = dir.path().join("sub");
+        fs::create_dir(&sub_dir).unwrap();
+        let res = with_context(&command_list(), |ctx| {
+            rm_callback(vec![], vec![sub_dir.to_str().unwrap().to_string()], ctx)
+        });
+        assert!(!res.stderr.is_empty()); // Should fail without -r
+        assert!(sub_dir.exists());
+
+        with_context(&command_list(), |ctx| {
+            rm_callback(
+                vec!["-r".to_string()],
+                vec![sub_dir.to_str().unwrap().to_string()],
+                ctx,
+            )
+        });
+        assert!(!sub_dir.exists());
+    }
+
+    /// What `cat` with `flags` writes for `paths`.
+    fn cat_output(flags: &[&str], paths: &[String]) -> String {
+        let flags: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+        let mut out = Vec::new();
+        let mut result = CommandResult::new();
+        cat_files(
+            &mut CatFilter::from_flags(&flags),
+            paths,
+            &mut io::empty(),
+            &mut out,
+            &mut result,
+        );
+        assert_eq!(result.stderr, "");
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_cat() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("cat_test.txt");
+        fs::write(&file, "meow").unwrap();
+
+        assert_eq!(
+            cat_output(&[], &[file.to_str().unwrap().to_string()]),
+            "meow"
+        );
+    }
+
+    #[test]
+    fn test_command_list_execute() {
+        let cmds = command_list();
+
+        // Test help
+        let res = execute(&cmds, "help", vec![], vec![]);
+        assert!(res.stdout_text().contains("Available commands"));
+
+        // Test unrecognized
+        let res = execute(&cmds, "nope", vec![], vec![]);
+        assert!(res.stderr.contains("command not found"));
+
+        // Test command help flag
+        let res = execute(&cmds, "ls", vec!["--help".to_string()], vec![]);
+        assert!(res.stdout_text().contains(
+            "Usage: ls [-a] [-l] [-1] [-h] [-i] [-n] [-F] [-R] [-t] [-S] [-X] [-U] [-r] [--sort=WORD]"
+        ));
+
+        // Test required args
+        let res = execute(&cmds, "mkdir", vec![], vec![]);
+        assert!(res.stderr.contains("missing operand"));
+    }
+
+    #[test]
+    fn test_exit_status() {
+        let cmds = command_list();
+        let status = |name: &str, args: &[&str]| {
+            let args = args.iter().map(|a| a.to_string()).collect();
+            execute(&cmds, name, vec![], args).status
+        };
+        assert_eq!(status("echo", &["hi"]), 0);
+        assert_eq!(status("nope", &[]), 127);
+        assert_eq!(status("mkdir", &[]), 2);
+        assert_eq!(status("cat", &["/nonexistent/file"]), 1);
+        assert_eq!(status("timeout", &["--bogus"]), 125);
+
+        // The first error decides the status
+        let mut res = CommandResult::new();
+        res.push_error(CommandError::Usage("x: bad option".to_string()));
+        res.push_error(CommandError::io("x: a.txt", io::ErrorKind::NotFound.into()));
+        assert_eq!(res.status, 2);
+        assert_eq!(res.stderr, "x: bad option\nx: a.txt: entity not found");
+        // while a notice is not an error at all
+        assert!(CommandResult::with_notice("1 record in".to_string()).success());
+
+        let res = execute(&cmds, "exit", vec![], vec!["259".to_string()]);
+        assert!(res.should_exit);
+        assert_eq!(res.status, 3);
+        assert_eq!(status("exit", &["x"]), 2);
+    }
+
+    #[test]
+    fn test_gzip_and_gunzip() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.txt");
+        let packed = dir.path().join("data.txt.gz");
+        let contents = "compress me ".repeat(100);
+        fs::write(&file, &contents).unwrap();
+        let file_str = file.to_str().unwrap().to_string();
+        let packed_str = packed.to_str().unwrap().to_string();
+
+        // Compress, removing the original
+        let res = with_context(&command_list(), |ctx| {
+            gzip_callback(vec![], vec![file_str.clone()], ctx)
+        });
+        assert!(res.stderr.is_empty());
+        assert!(!file.exists());
+        assert!(fs::metadata(&packed).unwrap().len() < contents.len() as u64);
+
+        // Expand, keeping the compressed file
+        let res = with_context(&command_list(), |ctx| {
+            gunzip_callback(vec!["-k".to_string()], vec![packed_str.clone()], ctx)
+        });
+        assert!(res.stderr.is_empty());
+        assert!(packed.exists());
+        assert_eq!(fs::read_to_string(&file).unwrap(), contents);
+
+        // Refuses to clobber without -f
+        let res = with_context(&command_list(), |ctx| {
+            gzip_callback(vec![], vec![file_str], ctx)
+        });
+        assert!(res.stderr.contains("already exists"));
+
+        let res = with_context(&command_list(), |ctx| {
+            gzip_callback(
+                vec!["-d".to_string()],
+                vec![file.to_str().unwrap().to_string()],
+                ctx,
+            )
+        });
+        assert!(res.stderr.contains("unknown suffix"));
+
+        // Standard input and output are the context's
+        let cmds = command_list();
+        let mut state = ShellState::default();
+        let packed = capture(&cmds, &mut state, &mut contents.as_bytes(), |ctx| {
+            gzip_callback(vec!["-f".to_string()], vec![], ctx)
+        });
+        assert_eq!(
+            crate::compress::gzip_decode(&packed.stdout).unwrap(),
+            contents.as_bytes()
+        );
+        let res = capture(&cmds, &mut state, &mut &packed.stdout[..], |ctx| {
+            gunzip_callback(vec![], vec![], ctx)
+        });
+        assert_eq!(res.stdout_text(), contents);
+    }
+
+    #[test]
+    fn test_xargs_command_lines() {
+        let to_args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+
+        let opts = parse_xargs_options(to_args("-n 2 echo -e")).unwrap();
+        let lines = xargs_command_lines(&opts, "a b\n'c d' e\n", &ShellState::default()).unwrap();
+        assert_eq!(
+            lines,
+            vec![
+                to_args("echo -e a b"),
+                vec!["echo".into(), "-e".into(), "c d".into(), "e".into()]
+            ]
+        );
+
+        let opts = parse_xargs_options(to_args("-I {} mkdir {}/sub")).unwrap();
+        let lines = xargs_command_lines(&opts, "one\ntwo\n", &ShellState::default()).unwrap();
+        assert_eq!(
+            lines,
+            vec![to_args("mkdir one/sub"), to_args("mkdir two/sub")]
+        );
+        let lines = xargs_command_lines(&opts, "  one \n\t\n", &ShellState::default()).unwrap();
+        assert_eq!(lines, vec![vec!["mkdir".to_string(), "one /sub".into()]]);
+
+        let opts = parse_xargs_options(to_args("-0")).unwrap();
+        let lines = xargs_command_lines(&opts, "x y\0z\0", &ShellState::default()).unwrap();
+        assert_eq!(
+            lines,
+            vec![vec!["echo".to_string(), "x y".into(), "z".into()]]
+        );
+
+        assert!(parse_xargs_options(to_args("-n")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_xargs_runs_programs() {
+        let xargs = |args: &[&str], input: &[u8]| {
+            let args = args.iter().map(|arg| arg.to_string()).collect();
+            capture(
+                &command_list(),
+                &mut ShellState::default(),
+                &mut &input[..],
+                |ctx| xargs_callback(vec![], args, ctx),
+            )
+        };
+        let result = xargs(&["-n", "2", "sh", "-c", "echo $# \"$@\"", "sh"], b"a b c");
+        assert_eq!(result.stdout_text(), "2 a b\n1 c\n");
+        assert_eq!(result.status, 0);
+
+        let result = xargs(&["sh", "-c", "exit 3"], b"x");
+        assert_eq!(result.status, 123);
+        let result = xargs(&["no-such-program-0shell"], b"x");
+        assert_eq!(result.status, 127);
+        assert_eq!(
+            result.stderr,
+            "xargs: no-such-program-0shell: command not found"
+        );
+    }
+
+    #[test]
+    fn test_run_in_parallel_keeps_order() {
+        let items: Vec<usize> = (0..20).collect();
+        let results = run_in_parallel(&items, 4, |i| CommandResult::with_stdout(i.to_string()));
+        let out: Vec<String> = results
+            .into_iter()
+            .map(|r| r.stdout_text().into_owned())
+            .collect();
+        assert_eq!(out, items.iter().map(|i| i.to_string()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_watch_options_and_header() {
+        let to_args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+
+        let (interval, title, command) = parse_watch_options(to_args("-n 0.5 ls -l")).unwrap();
+        assert_eq!(interval, std::time::Duration::from_millis(500));
+        assert!(title);
+        assert_eq!(command, to_args("ls -l"));
+
+        let (interval, title, _) = parse_watch_options(to_args("-t -n3 pwd")).unwrap();
+        assert_eq!(interval, std::time::Duration::from_secs(3));
+        assert!(!title);
+
+        assert!(parse_watch_options(to_args("-n abc ls")).is_err());
+        assert!(parse_watch_options(to_args("-n 1e300 ls")).is_err());
+        assert!(parse_watch_options(to_args("-n 1")).is_err());
+
+        let header = watch_header(2.0, "ls", 60, Local::now());
+        assert!(header.starts_with("Every 2.0s: ls "));
+        assert_eq!(header.chars().count(), 60);
+    }
+
+    #[test]
+    fn test_uptime_formatting() {
+        assert_eq!(format_uptime(12 * 60), "12 min");
+        assert_eq!(format_uptime(4 * 3600 + 12 * 60), " 4:12");
+        assert_eq!(format_uptime(86_400 + 3600), "1 day,  1:00");
+        assert_eq!(format_uptime(3 * 86_400 + 60), "3 days, 1 min");
+        assert_eq!(format_uptime_pretty(59), "0 minutes");
+        assert_eq!(
+            format_uptime_pretty(9 * 86_400 + 3660),
+            "1 week, 2 days, 1 hour, 1 minute"
+        );
+
+        let res = uptime_callback(vec![], vec![]);
+        assert!(res.stdout_text().starts_with(' '));
+    }
+
+    #[test]
+    fn test_free_and_human_sizes() {
+        assert_eq!(format_human_size(512), "512");
+        assert_eq!(format_human_size(4096), "4.0K");
+        assert_eq!(format_human_size(1_258_291), "1.2M");
+        assert_eq!(format_human_size(10_239), "10K");
+        assert_eq!(format_human_size(16 * 1024 * 1024 * 1024), "16G");
+
+        let meminfo = "MemTotal: 1000 kB\nMemFree: 200 kB\nMemAvailable: 600 kB\n\
+                       Buffers: 100 kB\nCached: 200 kB\nShmem: 10 kB\n\
+                       SwapTotal: 500 kB\nSwapFree: 500 kB\n";
+        let out = format_free(meminfo, 10, false, true);
+        let lines: Vec<Vec<&str>> = out
+            .lines()
+            .map(|l| l.split_whitespace().collect())
+            .collect();
+        assert_eq!(
+            lines[1],
+            vec!["Mem:", "1000", "400", "200", "10", "300", "600"]
+        );
+        assert_eq!(lines[2], vec!["Swap:", "500", "0", "500"]);
+        assert_eq!(lines[3], vec!["Total:", "1500", "400", "700"]);
+
+        // -h is an option of free, not a request for help
+        let cmds = command_list();
+        let res = execute(&cmds, "free", vec!["-h".to_string()], vec![]);
+        assert!(!res.stdout_text().starts_with("Usage"));
+        let res = execute(&cmds, "ls", vec!["-h".to_string()], vec![]);
+        assert!(!res.stdout_text().starts_with("Usage"));
+        let res = execute(&cmds, "pwd", vec!["-h".to_string()], vec![]);
+        assert!(res.stdout_text().starts_with("Usage"));
+    }
+
+    #[test]
+    fn test_dd_copy_with_skip_seek_count() {
+        assert_eq!(parse_size("10M"), Some(10 * 1024 * 1024));
+        assert_eq!(parse_size("2KB"), Some(2000));
+        assert_eq!(parse_size("3KiB"), Some(3072));
+        assert_eq!(parse_size("1X"), None);
+        assert_eq!(parse_dd_size("2b"), Some(1024));
+
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("in.bin");
+        let dest = dir.path().join("out.bin");
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        fs::write(&src, &data).unwrap();
+        fs::write(&dest, vec![0xAAu8; 8]).unwrap();
+
+        let res = with_context(&command_list(), |ctx| {
+            dd_callback(
+                vec![],
+                vec![
+                    format!("if={}", src.display()),
+                    format!("of={}", dest.display()),
+                    "bs=100".to_string(),
+                    "skip=2".to_string(),
+                    "seek=0".to_string(),
+                    "count=3".to_string(),
+                    "conv=notrunc".to_string(),
+                ],
+                ctx,
+            )
+        });
+        assert!(res.stderr.starts_with("3+0 records in\n3+0 records out"));
+        assert_eq!(fs::read(&dest).unwrap(), data[200..500]);
+
+        // Short final block is a partial record
+        let res = with_context(&command_list(), |ctx| {
+            dd_callback(
+                vec![],
+                vec![
+                    format!("if={}", src.display()),
+                    format!("of={}", dest.display()),
+                    "bs=300".to_string(),
+                    "status=noxfer".to_string(),
+                ],
+                ctx,
+            )
+        });
+        assert_eq!(res.stderr, "3+1 records in\n3+1 records out");
+        assert_eq!(fs::read(&dest).unwrap(), data);
+
+        let res = with_context(&command_list(), |ctx| {
+            dd_callback(vec![], vec!["bogus".to_string()], ctx)
+        });
+        assert!(res.stderr.contains("unrecognized operand"));
+
+        // Block offsets that overflow are rejected rather than wrapping
+        let res = with_context(&command_list(), |ctx| {
+            dd_callback(
+                vec![],
+                vec![
+                    format!("if={}", src.display()),
+                    "skip=99999999999999999".to_string(),
+                    "bs=1M".to_string(),
+                ],
+                ctx,
+            )
+        });
+        assert_eq!(res.stderr, "dd: invalid number: '99999999999999999'");
+        let res = with_context(&command_list(), |ctx| {
+            dd_callback(
+                vec![],
+                vec![
+                    format!("if={}", src.display()),
+                    format!("of={}", dest.display()),
+                    "seek=99999999999999999".to_string(),
+                    "obs=1M".to_string(),
+                ],
+                ctx,
+            )
+        });
+        assert_eq!(res.stderr, "dd: invalid number: '99999999999999999'");
+    }
+
+    #[test]
+    fn test_split_by_lines_and_bytes() {
+        assert_eq!(split_suffix(0, 2, false).unwrap(), "aa");
+        assert_eq!(split_suffix(27, 2, false).unwrap(), "bb");
+        assert_eq!(split_suffix(7, 3, true).unwrap(), "007");
+        assert!(split_suffix(100, 2, true).is_none());
+
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("input.txt");
+        fs::write(&src, "1\n2\n3\n4\n5\n").unwrap();
+        let prefix = dir.path().join("part.").to_str().unwrap().to_string();
+
+        let split = |args: &[&str]| {
+            let mut args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            args.extend([src.to_str().unwrap().to_string(), prefix.clone()]);
+            execute(&command_list(), "split", vec![], args)
+        };
+
+        let res = split(&["-l", "2", "-d"]);
+        assert!(res.stderr.is_empty());
+        assert_eq!(
+            fs::read_to_string(format!("{}00", prefix)).unwrap(),
+            "1\n2\n"
+        );
+        assert_eq!(fs::read_to_string(format!("{}02", prefix)).unwrap(), "5\n");
+
+        // The count may be attached to the flag
+        let res = split(&["-l2"]);
+        assert!(res.stderr.is_empty());
+        assert_eq!(
+            fs::read_to_string(format!("{}ab", prefix)).unwrap(),
+            "3\n4\n"
+        );
+        fs::remove_file(format!("{}ab", prefix)).unwrap();
+
+        let res = split(&["-b", "4"]);
+        assert!(res.stderr.is_empty());
+        assert_eq!(
+            fs::read_to_string(format!("{}ab", prefix)).unwrap(),
+            "3\n4\n"
+        );
+        assert_eq!(fs::read_to_string(format!("{}ac", prefix)).unwrap(), "5\n");
+
+        let res = execute(&command_list(), "split", vec![], vec!["-l".to_string()]);
+        assert!(res.stderr.contains("requires an argument"));
+    }
+
+    #[test]
+    fn test_truncate_sizes() {
+        assert_eq!(SizeAdjust::parse("10K"), Some(SizeAdjust::Set(10 * 1024)));
+        assert_eq!(SizeAdjust::parse("%4").unwrap().apply(5), Some(8));
+        assert_eq!(SizeAdjust::parse("/4").unwrap().apply(7), Some(4));
+        assert_eq!(SizeAdjust::parse("<3").unwrap().apply(7), Some(3));
+        assert_eq!(SizeAdjust::RoundUp(3 << 62).apply(u64::MAX), None);
+        assert!(SizeAdjust::parse("/0").is_none());
+
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("fixture.bin");
+        let path = file.to_str().unwrap().to_string();
+        let run = |args: &[&str]| {
+            let args = args.iter().map(|a| a.to_string()).collect();
+            truncate_callback(vec![], args)
+        };
+
+        // -c does not create missing files
+        run(&["-c", "-s", "10", &path]);
+        assert!(!file.exists());
+
+        run(&["-s", "1K", &path]);
+        assert_eq!(fs::metadata(&file).unwrap().len(), 1024);
+        run(&["-s", "-24", &path]);
+        assert_eq!(fs::metadata(&file).unwrap().len(), 1000);
+        run(&["--size=+5", &path]);
+        assert_eq!(fs::metadata(&file).unwrap().len(), 1005);
+
+        let res = run(&[&path]);
+        assert!(res.stderr.contains("must specify"));
+    }
+
+    #[test]
+    fn test_shred() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("secret.txt");
+        let path = file.to_str().unwrap().to_string();
+        fs::write(&file, "top secret data").unwrap();
+
+        let res = run_command("shred", &["-n", "1", "-z", &path]);
+        assert!(res.stderr.is_empty());
+        assert_eq!(fs::read(&file).unwrap(), vec![0u8; 15]);
+
+        // -v reports each step on the context's stderr
+        let res = run_command("shred", &["-uv", &path]);
+        assert_eq!(
+            res.stderr,
+            format!(
+                "shred: {0}: pass 1/3 (random)...\nshred: {0}: pass 2/3 (random)...\n\
+                 shred: {0}: pass 3/3 (random)...\nshred: {0}: removing",
+                path
+            )
+        );
+        assert!(!file.exists());
+
+        let res = run_command("shred", &[&path]);
+        assert!(!res.stderr.is_empty());
+    }
+
+    #[test]
+    fn test_env_assignments_are_scoped() {
+        let cmds = command_list();
+        let mut state = ShellState::default();
+        let mut run = |line: &str| {
+            let call = CommandCall::parse(line, &state).unwrap().unwrap();
+            cmds.capture_call(&mut state, &mut io::empty(), call)
+        };
+
+        let res = run("ZS_SCOPED_VAR=3 calc ZS_SCOPED_VAR + 1");
+        assert_eq!(res.stdout_text(), "4\n");
+        run("ZS_SHELL_VAR=kept");
+
+        assert_eq!(state.vars.get("ZS_SCOPED_VAR"), None);
+        assert_eq!(state.vars.get("ZS_SHELL_VAR").as_deref(), Some("kept"));
+        // The process environment is never touched
+        assert!(env::var("ZS_SHELL_VAR").is_err());
+    }
+
+    #[test]
+    fn test_nohup_runs_command() {
+        let cmds = command_list();
+        let res = execute(
+            &cmds,
+            "nohup",
+            vec![],
+            vec!["echo".to_string(), "-e".to_string(), "a\\tb".to_string()],
+        );
+        assert_eq!(res.stdout_text(), "a\tb\n");
+
+        let res = execute(&cmds, "nohup", vec![], vec![]);
+        assert!(res.stderr.contains("missing operand"));
+    }
+
+    #[test]
+    fn test_timeout_options() {
+        use std::time::Duration;
+        let to_args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+
+        assert_eq!(parse_duration("1.5"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("-1"), None);
+        assert_eq!(parse_duration("x"), None);
+        assert_eq!(parse_duration("inf"), None);
+        assert_eq!(parse_duration("99999999999999999999d"), None);
+
+        let opts = parse_timeout_options(to_args("-s KILL -k 1s 5 ls -l")).unwrap();
+        assert_eq!(opts.duration, Duration::from_secs(5));
+        assert_eq!(opts.kill_after, Some(Duration::from_secs(1)));
+        assert_eq!(opts.command, to_args("ls -l"));
+        #[cfg(unix)]
+        assert_eq!(opts.signal, libc::SIGKILL);
+
+        assert!(parse_timeout_options(to_args("-s BOGUS 5 ls")).is_err());
+        assert!(parse_timeout_options(to_args("5")).is_err());
+    }
+
+    #[test]
+    fn test_nice_args() {
+        let to_args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+
+        assert_eq!(
+            parse_nice_args(to_args("ls -l")).unwrap(),
+            (10, to_args("ls -l"))
+        );
+        assert_eq!(
+            parse_nice_args(to_args("-n -5 ls")).unwrap(),
+            (-5, to_args("ls"))
+        );
+        assert_eq!(
+            parse_nice_args(to_args("-n3 ls")).unwrap(),
+            (3, to_args("ls"))
+        );
+        assert_eq!(
+            parse_nice_args(to_args("--7 ls")).unwrap(),
+            (-7, to_args("ls"))
+        );
+        assert!(parse_nice_args(to_args("-n x ls")).is_err());
+    }
+
+    #[test]
+    fn test_pgrep_options() {
+        let to_args = |s: &str| s.split(' ').map(String::from).collect::<Vec<_>>();
+
+        let opts = parse_pgrep_options(to_args("-fl -u root,1000 ^bash"), false).unwrap();
+        assert!(opts.full && opts.list_name);
+        assert_eq!(opts.users, vec!["root", "1000"]);
+        assert_eq!(opts.pattern.as_deref(), Some("^bash"));
+
+        let opts = parse_pgrep_options(to_args("-d, -uroot"), false).unwrap();
+        assert_eq!(opts.delimiter.as_deref(), Some(","));
+        assert_eq!(opts.users, vec!["root"]);
+
+        let opts = parse_pgrep_options(to_args("-9 -x sleep"), true).unwrap();
+        assert_eq!(opts.signal.as_deref(), Some("9"));
+        assert!(opts.exact);
+        let opts = parse_pgrep_options(to_args("--signal=HUP -e nginx"), true).unwrap();
+        assert_eq!(opts.signal.as_deref(), Some("HUP"));
+        assert!(opts.echo);
+
+        assert!(parse_pgrep_options(Vec::new(), false).is_err());
+        assert!(parse_pgrep_options(to_args("a b"), false).is_err());
+        assert!(parse_pgrep_options(to_args("-l sleep"), true).is_err());
+    }
+
+    #[test]
+    fn test_format_mounts() {
+        let mounts = "/dev/sda1 / ext4 rw,relatime 0 0\n\
+                      proc /proc proc rw,nosuid 0 0\n\
+                      /dev/sdb1 /media/my\\040disk vfat ro 0 0\n";
+
+        assert_eq!(
+            format_mounts(mounts, None),
+            "DEVICE     MOUNTPOINT      TYPE  OPTIONS\n\
+             /dev/sda1  /               ext4  rw,relatime\n\
+             proc       /proc           proc  rw,nosuid\n\
+             /dev/sdb1  /media/my disk  vfat  ro\n"
+        );
+        assert_eq!(format_mounts(mounts, Some("ext4,vfat")).lines().count(), 3);
+        let without_proc = format_mounts(mounts, Some("noproc"));
+        assert!(!without_proc.contains("/proc"));
+        assert_eq!(without_proc.lines().count(), 3);
+    }
+
+    #[test]
+    fn test_rev() {
+        assert_eq!(reverse_lines(b"abc\nhello world\n"), b"cba\ndlrow olleh\n");
+        assert_eq!(
+            reverse_lines("cafe\u{301}\nno newline".as_bytes()),
+            "e\u{301}fac\nenilwen on".as_bytes()
+        );
+        // Latin-1 is not valid UTF-8, so it is reversed byte by byte
+        assert_eq!(reverse_lines(b"caf\xe9\n"), b"\xe9fac\n");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("in.txt");
+        fs::write(&path, "12\n34\n").unwrap();
+        let result = with_context(&command_list(), |ctx| {
+            rev_callback(vec![], vec![path.to_string_lossy().into_owned()], ctx)
+        });
+        assert_eq!(result.stdout_text(), "21\n43\n");
+    }
+
+    #[test]
+    fn test_fold() {
+        let fold = |line: &str, width, bytes, spaces| {
+            String::from_utf8(fold_line(line.as_bytes(), width, bytes, spaces)).unwrap()
+        };
+        assert_eq!(fold("abcdefghij", 4, false, false), "abcd\nefgh\nij");
+        assert_eq!(
+            fold("the quick brown fox", 10, false, true),
+            "the quick \nbrown fox"
+        );
+        assert_eq!(fold("a\tb", 4, false, false), "a\n\t\nb");
+        assert_eq!(
+            fold("\u{65E5}\u{672C}\u{8A9E}", 4, false, false),
+            "\u{65E5}\u{672C}\n\u{8A9E}"
+        );
+        assert_eq!(
+            fold("\u{e9}\u{e9}\u{e9}", 4, true, false),
+            "\u{e9}\u{e9}\n\u{e9}"
+        );
+
+        // Invalid UTF-8 passes through untouched, one column per byte
+        assert_eq!(fold_line(b"caf\xe9s", 2, false, false), b"ca\nf\xe9\ns");
+        // -b breaks between raw bytes, even inside a character
+        assert_eq!(
+            fold_line("\u{e9}\u{e9}".as_bytes(), 3, true, false),
+            b"\xc3\xa9\xc3\n\xa9"
+        );
+    }
+
+    #[test]
+    fn test_expand_and_unexpand() {
+        let every = |n| TabStops::Every(n);
+
+        assert_eq!(expand_tabs(b"a\tb\n\tc", &every(4), false), b"a   b\n    c");
+        assert_eq!(expand_tabs(b"\ta\tb", &every(4), true), b"    a\tb");
+        let list = TabStops::parse("2,5").unwrap();
+        assert_eq!(expand_tabs(b"\ta\tb\tc", &list, false), b"  a  b c");
+        assert_eq!(
+            expand_tabs("\u{e9}\tx".as_bytes(), &every(4), false),
+            "\u{e9}   x".as_bytes()
+        );
+
+        assert_eq!(
+            unexpand_spaces(b"        a       b", &every(8), false),
+            b"\ta       b"
+        );
+        assert_eq!(
+            unexpand_spaces(b"        a       b", &every(8), true),
+            b"\ta\tb"
+        );
+        assert_eq!(unexpand_spaces(b"abc d   e", &every(4), true), b"abc d\te");
+        assert_eq!(unexpand_spaces(b"  x\n", &every(8), false), b"  x\n");
+
+        assert!(TabStops::parse("4,2").is_err());
+        assert!(TabStops::parse("0").is_err());
+    }
+
+    #[test]
+    fn test_comm() {
+        let a = b"apple\nbanana\ncherry\n";
+        let b = b"banana\ncherry\ndate";
+
+        let (out, unsorted) = compare_sorted(a, b, [true; 3]);
+        assert_eq!(out, b"apple\n\t\tbanana\n\t\tcherry\n\tdate\n");
+        assert!(unsorted.is_empty());
+
+        let (out, _) = compare_sorted(a, b, [false, true, false]);
+        assert_eq!(out, b"date\n");
+        let (out, _) = compare_sorted(a, b, [true, false, true]);
+        assert_eq!(out, b"apple\n\tbanana\n\tcherry\n");
+
+        let (_, unsorted) = compare_sorted(b"b\na\n", a, [true; 3]);
+        assert_eq!(unsorted, vec![1]);
+    }
+
+    #[test]
+    fn test_join() {
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            JOIN_SPEC.parse(&args).unwrap()
+        };
+        let opts = |args: &[&str]| join_options(&parse(args)).unwrap();
+        let ids = "1 alice\n2 bob\n2 bobby\n4 dave\n";
+        let ages = "1 30\n2 25\n3 41\n";
+
+        assert_eq!(
+            join_lines(ids, ages, &opts(&[])),
+            "1 alice 30\n2 bob 25\n2 bobby 25\n"
+        );
+        assert_eq!(
+            join_lines(ids, ages, &opts(&["-a", "1", "-a2"])),
+            "1 alice 30\n2 bob 25\n2 bobby 25\n3 41\n4 dave\n"
+        );
+        assert_eq!(join_lines(ids, ages, &opts(&["-v", "2"])), "3 41\n");
+
+        let csv = "alice,admin\nbob,dev\n";
+        let emails = "x@a.io,alice\ny@b.io,bob\n";
+        assert_eq!(
+            join_lines(csv, emails, &opts(&["-t,", "-2", "2"])),
+            "alice,admin,x@a.io\nbob,dev,y@b.io\n"
+        );
+
+        assert!(join_options(&parse(&["-a", "3"])).is_err());
+    }
+
+    #[test]
+    fn test_shuf() {
+        let seeded = |args: &[&str]| {
+            let args = [&["--random-seed=7"], args].concat();
+            run_command("shuf", &args).stdout_text().into_owned()
+        };
+
+        let out = seeded(&["-i", "1-10"]);
+        let mut numbers: Vec<u32> = out.lines().map(|l| l.parse().unwrap()).collect();
+        assert_eq!(out, seeded(&["-i1-10"]));
+        numbers.sort();
+        assert_eq!(numbers, (1..=10).collect::<Vec<_>>());
+
+        let sample = seeded(&["-e", "-n", "2", "a", "b", "c", "d"]);
+        assert_eq!(sample.lines().count(), 2);
+        assert!(sample.lines().all(|l| ["a", "b", "c", "d"].contains(&l)));
+
+        assert!(!run_command("shuf", &["-i", "5-1"]).stderr.is_empty());
+
+        // Samples of huge ranges are picked directly, and whole ones refused
+        let sample = seeded(&["-n", "3", "-i", "0-18446744073709551615"]);
+        let mut numbers: Vec<u64> = sample.lines().map(|l| l.parse().unwrap()).collect();
+        numbers.sort();
+        numbers.dedup();
+        assert_eq!(numbers.len(), 3);
+        let sample = seeded(&["-n9", "-i", "5-7"]);
+        let mut numbers: Vec<u64> = sample.lines().map(|l| l.parse().unwrap()).collect();
+        numbers.sort();
+        assert_eq!(numbers, [5, 6, 7]);
+        assert!(
+            run_command("shuf", &["-i", "1-1000000000"])
+                .stderr
+                .starts_with("shuf: input range too large")
+        );
+    }
+
+    #[test]
+    fn test_tac() {
+        let reverse = |text: &str, chunk: usize| {
+            let mut out = Vec::new();
+            tac_reverse(
+                &mut io::Cursor::new(text.as_bytes().to_vec()),
+                &mut out,
+                chunk,
+            )
+            .unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        for chunk in [1, 3, 1024] {
+            assert_eq!(reverse("one\ntwo\nthree\n", chunk), "three\ntwo\none\n");
+            assert_eq!(reverse("a\nb\nc", chunk), "cb\na\n");
+            assert_eq!(reverse("\n\nx\n", chunk), "x\n\n\n");
+        }
+        assert_eq!(reverse("", 4), "");
+
+        // A line spanning many chunks comes out whole
+        let long = "x".repeat(100_000);
+        assert_eq!(
+            reverse(&format!("a\n{}\nb\n", long), 7),
+            format!("b\n{}\na\n", long)
+        );
+    }
+
+    #[test]
+    fn test_strings() {
+        let data = b"\x7fELF\x02\x01hello world\x00ab\x00\x00__libc_start";
+        let scan = |min, radix| {
+            let mut out = Vec::new();
+            scan_strings(&data[..], min, radix, &mut out).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+
+        assert_eq!(scan(4, None), "hello world\n__libc_start\n");
+        assert_eq!(scan(2, None), "ELF\nhello world\nab\n__libc_start\n");
+        assert_eq!(
+            scan(4, Some('x')),
+            "      6 hello world\n     16 __libc_start\n"
+        );
+        assert_eq!(
+            scan(4, Some('d')),
+            "      6 hello world\n     22 __libc_start\n"
+        );
+    }
+
+    #[test]
+    fn test_cksum_and_crc32() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.txt");
+        fs::write(&path, "hello\n").unwrap();
+        let name = path.to_string_lossy().into_owned();
+
+        let result = with_context(&command_list(), |ctx| {
+            cksum_callback(vec![], vec![name.clone()], ctx)
+        });
+        assert_eq!(result.stdout_text(), format!("3015617425 6 {}\n", name));
+        let result = with_context(&command_list(), |ctx| {
+            crc32_callback(vec![], vec![name.clone()], ctx)
+        });
+        assert_eq!(result.stdout_text(), format!("363a3020 {}\n", name));
+
+        let result = with_context(&command_list(), |ctx| {
+            cksum_callback(vec![], vec!["missing".to_string()], ctx)
+        });
+        assert!(result.stderr.starts_with("cksum: missing:"));
+
+        let result = capture(
+            &command_list(),
+            &mut ShellState::default(),
+            &mut &b"hello\n"[..],
+            |ctx| cksum_callback(vec![], vec![], ctx),
+        );
+        assert_eq!(result.stdout_text(), "3015617425 6\n");
+    }
+
+    #[test]
+    fn test_sync() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs::write(&path, "data").unwrap();
+        let name = path.to_string_lossy().into_owned();
+
+        assert_eq!(sync_callback(vec![], vec![name.clone()]).stderr, "");
+        assert_eq!(sync_callback(vec!["-f".to_string()], vec![name]).stderr, "");
+        assert!(
+            sync_callback(vec![], vec!["missing".to_string()])
+                .stderr
+                .starts_with("sync: error syncing 'missing'")
+        );
+        assert!(
+            !sync_callback(vec!["-d".to_string()], vec![])
+                .stderr
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_parse_mode_and_mkfifo() {
+        assert_eq!(parse_mode("640", 0o666, false), Some(0o640));
+        assert_eq!(parse_mode("u+x", 0o644, false), Some(0o744));
+        assert_eq!(parse_mode("go-w,a+x", 0o666, false), Some(0o755));
+        assert_eq!(parse_mode("u=rw,go=", 0o777, false), Some(0o600));
+        assert_eq!(parse_mode("+X", 0o644, true), Some(0o755));
+        assert_eq!(parse_mode("+X", 0o644, false), Some(0o644));
+        assert_eq!(parse_mode("u+r-w", 0o200, false), Some(0o400));
+        assert_eq!(parse_mode("8", 0, false), None);
+        assert_eq!(parse_mode("q+x", 0, false), None);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::FileTypeExt;
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("pipe");
+            let name = path.to_string_lossy().into_owned();
+            let result = run_command("mkfifo", &["-m", "600", &name]);
+            assert_eq!(result.stderr, "");
+            let meta = fs::metadata(&path).unwrap();
+            assert!(meta.file_type().is_fifo());
+            assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_ulimit() {
+        let limit = get_ulimit(libc::RLIMIT_NOFILE).unwrap();
+        let shown = ulimit_callback(vec!["-n".to_string()], vec![]);
+        assert_eq!(
+            shown.stdout_text(),
+            format!("{}\n", format_rlimit(limit.rlim_cur, 1))
+        );
+
+        let all = ulimit_callback(vec!["-a".to_string()], vec![]);
+        let all = all.stdout_text();
+        assert_eq!(all.lines().count(), ULIMIT_RESOURCES.len());
+        let open_files = all.lines().find(|l| l.starts_with("open files")).unwrap();
+        assert!(open_files.contains("(-n) "));
+
+        assert_eq!(format_rlimit(libc::RLIM_INFINITY, 1024), "unlimited");
+        assert_eq!(format_rlimit(8192 * 1024, 1024), "8192");
+        assert!(
+            !ulimit_callback(vec!["-x".to_string()], vec![])
+                .stderr
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_cal_layout() {
+        let month = join_cal_months(&[cal_month_lines(2026, 2, true, false, None)]);
+        assert_eq!(
+            month,
+            "   February 2026\n\
+             Su Mo Tu We Th Fr Sa\n\
+             \x201  2  3  4  5  6  7\n\
+             \x208  9 10 11 12 13 14\n\
+             15 16 17 18 19 20 21\n\
+             22 23 24 25 26 27 28\n\n\n"
+        );
+
+        let monday = cal_month_lines(2026, 2, true, true, None);
+        assert_eq!(monday[1], "Mo Tu We Th Fr Sa Su");
+        assert_eq!(monday[2], "                   1");
+        assert!(monday.iter().all(|l| l.chars().count() == CAL_MONTH_WIDTH));
+
+        let today = chrono::NaiveDate::from_ymd_opt(2026, 2, 3);
+        let lines = cal_month_lines(2026, 2, true, false, today);
+        assert!(lines[2].contains(&format!(
+            "{} 3{}",
+            crate::terminal::REVERSE,
+            crate::terminal::RESET
+        )));
+
+        let year = format_cal_year(2026, false, None);
+        assert!(year.starts_with("                              2026\n\n"));
+        assert_eq!(year.lines().count(), 2 + 4 * 8);
+    }
+
+    #[test]
+    fn test_calc() {
+        let to_args = |s: &[&str]| s.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            with_context(&command_list(), |ctx| calc_callback(
+                vec![],
+                to_args(&["3 * (10 + 2)"]),
+                ctx
+            ))
+            .stdout_text(),
+            "36\n"
+        );
+        assert_eq!(
+            with_context(&command_list(), |ctx| expr_callback(
+                vec![],
+                to_args(&["-3", "+", "0.5"]),
+                ctx
+            ))
+            .stdout_text(),
+            "-2.5\n"
+        );
+        let result = with_context(&command_list(), |ctx| {
+            calc_callback(vec![], to_args(&["1", "/", "0"]), ctx)
+        });
+        assert_eq!(result.stderr, "calc: division by 0");
+    }
+
+    #[test]
+    fn test_uuidgen() {
+        let mut rng = crate::random::Rng::from_seed(1);
+        let v4 = format_uuid(&uuid_v4(&mut rng));
+        assert_eq!(v4.len(), 36);
+        assert_eq!(&v4[14..15], "4");
+        assert!(matches!(&v4[19..20], "8" | "9" | "a" | "b"));
+
+        let v7 = format_uuid(&uuid_v7(&mut rng, 0x0190_1234_5678, 0x123));
+        assert!(v7.starts_with("01901234-5678-7123-"));
+        assert!(format_uuid(&uuid_v7(&mut rng, 1, 0)) < format_uuid(&uuid_v7(&mut rng, 1, 1)));
+
+        let result = run_command("uuidgen", &["-r", "-tn3"]);
+        let stdout = result.stdout_text();
+        let ids: Vec<&str> = stdout.lines().collect();
+        assert_eq!(ids.len(), 3);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn test_random() {
+        let cmds = command_list();
+        let to_args = |s: &[&str]| s.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let random =
+            |args: &[&str]| with_context(&cmds, |ctx| random_callback(vec![], to_args(args), ctx));
+        for _ in 0..20 {
+            let n: i64 = random(&["-3", "3"]).stdout_text().trim().parse().unwrap();
+            assert!((-3..=3).contains(&n));
+        }
+        let n: i64 = random(&[]).stdout_text().trim().parse().unwrap();
+        assert!((0..32768).contains(&n));
+        assert_eq!(
+            random(&["5", "1"]).stderr,
+            "random: MIN is greater than MAX: 5 > 1"
+        );
+        assert!(!random(&["1"]).stderr.is_empty());
+
+        // Seeding with RANDOM=SEED repeats a sequence in that session only
+        let mut seeded = ShellState::default();
+        let mut other = ShellState::default();
+        let draw = |state: &mut ShellState, line: &str| {
+            capture(&cmds, state, &mut io::empty(), |ctx| {
+                let call = CommandCall::parse(line, ctx.state).unwrap().unwrap();
+                cmds.run_call(ctx, call)
+            })
+            .stdout_text()
+            .into_owned()
+        };
+        draw(&mut seeded, "RANDOM=42");
+        let first = draw(&mut seeded, "random 1 1000000");
+        draw(&mut other, "random 1 1000000");
+        draw(&mut seeded, "RANDOM=42");
+        assert_eq!(draw(&mut seeded, "random 1 1000000"), first);
+
+        let mut rng = crate::random::Rng::from_seed(7);
+        // The full range must not overflow
+        rng.between(i64::MIN, i64::MAX);
+        assert_eq!(rng.between(4, 4), 4);
+    }
+
+    #[test]
+    fn test_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.json");
+        fs::write(&path, r#"{"items": [{"name": "a b"}, {"name": "c"}]}"#).unwrap();
+        let path = path.to_string_lossy().to_string();
+
+        let result = with_context(&command_list(), |ctx| {
+            json_callback(vec!["-M".to_string()], vec![path.clone()], ctx)
+        });
+        assert!(
+            result
+                .stdout_text()
+                .starts_with("{\n  \"items\": [\n    {\n")
+        );
+
+        let result = with_context(&command_list(), |ctx| {
+            json_callback(
+                vec!["-r".to_string(), "-M".to_string()],
+                vec![".items[].name".to_string(), path.clone()],
+                ctx,
+            )
+        });
+        assert_eq!(result.stdout_text(), "a b\nc\n");
+
+        let result = with_context(&command_list(), |ctx| {
+            json_callback(
+                vec!["-c".to_string(), "-M".to_string()],
+                vec![".items[1]".to_string(), path.clone()],
+                ctx,
+            )
+        });
+        assert_eq!(result.stdout_text(), "{\"name\":\"c\"}\n");
+
+        let result = with_context(&command_list(), |ctx| {
+            json_callback(vec![], vec![".items.x".to_string(), path], ctx)
+        });
+        assert!(result.stderr.contains("cannot index array"));
+    }
+
+    #[test]
+    fn test_csv() {
+        let rows = parse_csv(
+            "name,note\r\n\"Lee, A\",\"said \"\"hi\"\"\"\nB,\"two\nlines\"\n",
+            ',',
+        );
+        assert_eq!(
+            rows,
+            vec![
+                vec!["name", "note"],
+                vec!["Lee, A", "said \"hi\""],
+                vec!["B", "two\nlines"],
+            ]
+        );
+        assert_eq!(parse_csv("a\t\"\"\n", '\t'), vec![vec!["a", ""]]);
+
+        assert_eq!(
+            format_table(&rows, true),
+            "name    note\n------  ----------\nLee, A  said \"hi\"\nB       two\\nlines\n"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data.tsv");
+        fs::write(&path, "id\tname\tage\n1\tAda\t36\n2\tBo\t7\n").unwrap();
+        let result = run_command(
+            "csv",
+            &["--columns", "age,2", "--head=1", &path.to_string_lossy()],
+        );
+        assert_eq!(result.stdout_text(), "age  name\n---  ----\n36   Ada\n");
+
+        assert_eq!(
+            select_csv_columns("x", None).unwrap_err(),
+            "no such column: 'x'"
+        );
+    }
+
+    #[test]
+    fn test_fetch() {
+        use std::net::TcpListener;
+
+        const BODY: &str = "0123456789abcdef";
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            for _ in 0..4 {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    request.push_str(&line);
+                }
+                let range = request
+                    .lines()
+                    .find_map(|l| l.strip_prefix("Range: bytes="))
+                    .and_then(|r| r.trim_end_matches('-').parse::<usize>().ok());
+                let response = if request.starts_with("GET /old ") {
+                    "HTTP/1.1 302 Found\r\nLocation: data.txt\r\nContent-Length: 0\r\n\r\n"
+                        .to_string()
+                } else if let Some(from) = range {
+                    format!(
+                        "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\r\n{}",
+                        BODY.len() - from,
+                        &BODY[from..]
+                    )
+                } else {
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+                        BODY.len(),
+                        BODY
+                    )
+                };
+                (&stream).write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("out.txt").to_string_lossy().to_string();
+        let fetch = |flags: &[&str], url: &str| {
+            let url = format!("http://127.0.0.1:{}{}", port, url);
+            let mut args = vec!["-O", &target];
+            args.extend(flags);
+            args.push(&url);
+            run_command("fetch", &args)
+        };
+
+        // Follows the redirect
+        let result = fetch(&[], "/old");
+        assert_eq!(result.stderr, format!("'{}' saved [16 bytes]", target));
+        assert_eq!(fs::read_to_string(&target).unwrap(), BODY);
+
+        // Resumes from the existing length
+        fs::write(&target, &BODY[..6]).unwrap();
+        assert!(fetch(&["-c", "-q"], "/data.txt").stderr.is_empty());
+        assert_eq!(fs::read_to_string(&target).unwrap(), BODY);
+
+        // Without -c the file is replaced
+        fs::write(&target, "stale contents that are longer").unwrap();
+        fetch(&["-q"], "/data.txt");
+        assert_eq!(fs::read_to_string(&target).unwrap(), BODY);
+        server.join().unwrap();
+
+        // A name taken from the URL does not replace a file, which is
+        // refused before connecting
+        let url = "http://127.0.0.1:1/x/Cargo.toml".to_string();
+        assert_eq!(
+            run_command("fetch", &["-q", &url]).stderr,
+            "fetch: 'Cargo.toml' already exists; use -O to replace it"
+        );
+
+        assert_eq!(
+            fetch_progress_line(512, 1024, Some(2048), std::time::Duration::from_secs(1)),
+            format!(" 50% [{:<30}]   1.0K  512B/s   ", "=".repeat(15) + ">")
+        );
+    }
+
+    #[test]
+    fn test_ping_summary() {
+        let elapsed = std::time::Duration::from_millis(2003);
+        assert_eq!(
+            ping_summary("localhost", 3, &[1.0, 2.0, 3.0], elapsed),
+            "\n--- localhost ping statistics ---\n\
+             3 packets transmitted, 3 received, 0% packet loss, time 2003ms\n\
+             rtt min/avg/max/mdev = 1.000/2.000/3.000/0.816 ms\n"
+        );
+        assert!(ping_summary("h", 3, &[1.0], elapsed).contains("1 received, 66.7% packet loss"));
+        assert!(!ping_summary("h", 1, &[], elapsed).contains("rtt"));
+
+        let result = run_command("ping", &["-i", "0.1", "h"]);
+        assert_eq!(
+            result.stderr,
+            "ping: the shortest interval allowed is 0.2 seconds"
+        );
+        for flag in ["-i", "-W"] {
+            let result = run_command("ping", &[flag, "1e30", "h"]);
+            assert_eq!(
+                result.stderr,
+                format!("ping: invalid value for {}: '1e30'", flag)
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_nc_timeout() {
+        assert_eq!(
+            run_command("nc", &["-w", "1e30", "127.0.0.1", "1"]).stderr,
+            "nc: invalid timeout '1e30'"
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let addrs = ["::1".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+        assert_eq!(
+            format_addresses("localhost", &addrs),
+            "localhost has address 127.0.0.1\nlocalhost has IPv6 address ::1\n"
+        );
+
+        let result = resolve_callback(vec!["-x".to_string()], vec!["localhost".to_string()]);
+        assert_eq!(result.stderr, "resolve: localhost: not an IP address");
+    }
 
-// ============================================================================
-// Command Callback Functions
-// ============================================================================
+    #[test]
+    fn test_ifinfo_format() {
+        let interface = crate::net::Interface {
+            name: "eth0".to_string(),
+            up: true,
+            running: true,
+            loopback: false,
+            mac: Some(vec![0x02, 0x42, 0xac, 0x11, 0x00, 0x02]),
+            addrs: vec![
+                ("172.17.0.2".parse().unwrap(), 16),
+                ("fe80::42:acff:fe11:2".parse().unwrap(), 64),
+            ],
+        };
+        assert_eq!(
+            format_interface(&interface),
+            "eth0: <UP,RUNNING>\n    ether 02:42:ac:11:00:02\n    inet 172.17.0.2/16\n    inet6 fe80::42:acff:fe11:2/64\n"
+        );
+        let result = ifinfo_callback(vec![], vec!["no-such-if0".to_string()]);
+        assert_eq!(result.stderr, "ifinfo: no-such-if0: no such interface");
+    }
 
-/// Causes the shell to exit.
-///
-/// Returns a special `CommandResult` that indicates the shell should terminate.
-fn exit_callback(_flags: Vec<String>, _args: Vec<String>) -> CommandResult {
-    CommandResult::exit()
-}
+    #[test]
+    fn test_open_missing_file() {
+        let cmds = command_list();
+        let result = with_context(&cmds, |ctx| {
+            open_callback(vec![], vec!["/no/such/report.pdf".to_string()], ctx)
+        });
+        assert_eq!(
+            result.stderr,
+            "open: /no/such/report.pdf: No such file or directory"
+        );
+    }
 
-/// Displays a line of text.
-///
-/// Supports the `-e` flag to interpret backslash escape sequences.
-fn echo_callback(flags: Vec<String>, args: Vec<String>) -> CommandResult {
-    let mut interpret = false;
-    let mut result = CommandResult::new();
+    #[test]
+    fn test_clip_errors() {
+        let cmds = command_list();
+        let result = with_context(&cmds, |ctx| {
+            clip_callback(vec![], vec!["/no/such/file".to_string()], ctx)
+        });
+        assert!(result.stderr.starts_with("clip: /no/such/file: "));
+        let result = with_context(&cmds, |ctx| {
+            paste_clip_callback(vec![], vec!["x".to_string()], ctx)
+        });
+        assert_eq!(result.stderr, "paste-clip: extra operand 'x'");
+    }
 
-    // Check for the -e flag in flags
-    if flags.iter().any(|f| f == "-e") {
-        interpret = true;
+    #[test]
+    fn test_zip_and_unzip() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        fs::create_dir_all(root.join("src/sub")).unwrap();
+        fs::write(root.join("src/a.txt"), "alpha\n".repeat(50)).unwrap();
+        fs::write(root.join("src/sub/b.log"), "beta\n").unwrap();
+        let archive = root.join("out.zip").to_string_lossy().into_owned();
+        let src = root.join("src").to_string_lossy().into_owned();
+
+        let result = run_command("zip", &["-rq9", "-x", "*.log", &archive, &src]);
+        assert_eq!(result.stderr, "");
+
+        let result = run_command("unzip", &["-l", &archive]);
+        assert!(result.stdout_text().contains("/src/a.txt\n"));
+        assert!(result.stdout_text().contains("/src/sub/\n"));
+        assert!(!result.stdout_text().contains("b.log"));
+        assert!(
+            result
+                .stdout_text()
+                .ends_with("      300                     3 files\n")
+        );
+
+        let dest = root.join("dest");
+        let dest_str = dest.to_string_lossy().into_owned();
+        let result = run_command("unzip", &["-q", "-d", &dest_str, &archive, "*a.txt"]);
+        assert_eq!(result.stderr, "");
+        let extracted: Vec<_> = fs::read_dir(&dest).unwrap().collect();
+        assert_eq!(extracted.len(), 1);
+
+        // Members that would escape the destination are refused
+        let mut writer = crate::zip::Writer::new(Vec::new());
+        let when = chrono::NaiveDateTime::default();
+        writer
+            .add_file("../evil.txt", b"x", when, 0o644, 0)
+            .unwrap();
+        let evil = root.join("evil.zip");
+        fs::write(&evil, writer.finish().unwrap()).unwrap();
+        let result = run_command("unzip", &["-d", &dest_str, &evil.to_string_lossy()]);
+        assert_eq!(result.stderr, "unzip: ../evil.txt: unsafe path -- skipped");
+        assert!(!root.join("evil.txt").exists());
     }
 
-    let input = args.join(" ");
+    #[test]
+    fn test_encode_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("legacy.txt");
+        let output = dir.path().join("utf16.txt");
+        fs::write(&input, b"caf\xe9 \x80\n").unwrap();
+        let (input, output_str) = (input.to_string_lossy(), output.to_string_lossy());
+        let result = run_command(
+            "encode",
+            &["-f", "cp1252", "-t", "utf-16le", "-o", &output_str, &input],
+        );
+        assert_eq!(result.stderr, "");
+        let expected: Vec<u8> = "café €\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        assert_eq!(fs::read(&output).unwrap(), expected);
 
-    if !interpret {
-        // Default Bash behavior: print literally
-        result.stdout = format!("{}\n", input);
-        return result;
+        let result = run_command("encode", &["--to=ascii", "-o", &output_str, &input]);
+        assert!(result.stderr.ends_with("invalid UTF-8 sequence at byte 3"));
+        let result = run_command("encode", &["-tebcdic"]);
+        assert_eq!(result.stderr, "encode: unsupported encoding 'ebcdic'");
     }
 
-    // -e behavior: interpret backslash sequences
-    let mut output = String::new();
-    let mut chars = input.chars().peekable();
+    #[test]
+    fn test_line_ending_conversion() {
+        let mixed = b"one\r\ntwo\nthree\rfour\r\n";
+        assert_eq!(
+            convert_line_endings(mixed, false),
+            b"one\ntwo\nthree\rfour\n"
+        );
+        assert_eq!(
+            convert_line_endings(mixed, true),
+            b"one\r\ntwo\r\nthree\rfour\r\n"
+        );
+        assert_eq!(
+            describe_line_endings(b"\xEF\xBB\xBFa\r\nb\r\n"),
+            "dos (2 CRLF, BOM)"
+        );
+        assert_eq!(describe_line_endings(mixed), "mixed (2 CRLF, 1 LF, 1 CR)");
+        assert_eq!(describe_line_endings(b"plain"), "no line endings");
 
-    while let Some(c) = chars.next() {
-        if c == '\\' {
-            match chars.next() {
-                Some('c') => {
-                    result.stdout = output;
-                    return result; // "Stop" signal: return immediately without \n
-                }
-                Some(next) => {
-                    if let Some(mapped) = map_echo_escape(next) {
-                        output.push(mapped);
-                    } else {
-                        // Not a recognized escape; push both literally
-                        output.push('\\');
-                        output.push(next);
-                    }
-                }
-                None => output.push('\\'), // Trailing backslash
-            }
-        } else {
-            output.push(c);
-        }
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("notes.txt");
+        fs::write(&file, b"\xEF\xBB\xBFa\r\nb\n").unwrap();
+        let path = file.to_string_lossy().into_owned();
+        let result = with_context(&command_list(), |ctx| {
+            dos2unix_callback(vec![], vec![path.clone()], ctx)
+        });
+        assert_eq!(result.stderr, "");
+        assert_eq!(fs::read(&file).unwrap(), b"a\nb\n");
+        with_context(&command_list(), |ctx| {
+            unix2dos_callback(vec!["-m".to_string()], vec![path], ctx)
+        });
+        assert_eq!(fs::read(&file).unwrap(), b"\xEF\xBB\xBFa\r\nb\r\n");
     }
 
-    result.stdout = format!("{}\n", output);
-    result
-}
+    #[test]
+    fn test_killall_options_and_matching() {
+        let args = |list: &[&str]| list.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let opts = parse_killall_options(args(&["-HUP", "-iv", "-u", "root", "nginx"])).unwrap();
+        assert_eq!(opts.signal.as_deref(), Some("HUP"));
+        assert!(opts.interactive && opts.verbose);
+        assert_eq!(opts.users, ["root"]);
+        assert_eq!(opts.names, ["nginx"]);
+        assert_eq!(
+            parse_killall_options(args(&["-v"])).unwrap_err(),
+            "no process name specified"
+        );
 
-/// Maps echo escape sequences to their corresponding characters.
-fn map_echo_escape(c: char) -> Option<char> {
-    match c {
-        'a' => Some('\x07'), // BEL
-        'b' => Some('\x08'), // Backspace
-        'e' => Some('\x1b'), // Escape
-        'f' => Some('\x0c'), // Form feed
-        'n' => Some('\n'),
-        'r' => Some('\r'),
-        't' => Some('\t'),
-        'v' => Some('\x0b'), // Vertical tab
-        '\\' => Some('\\'),
-        _ => None,
+        let process = crate::process::ProcessInfo {
+            pid: 42,
+            uid: 0,
+            name: "really-long-dae".to_string(),
+            cmdline: args(&["/usr/sbin/really-long-daemon", "--foreground"]),
+            start_time: 0,
+            state: 'S',
+            cpu_time: 0,
+            rss: 0,
+        };
+        assert!(killall_matches(&process, "really-long-dae"));
+        assert!(killall_matches(&process, "really-long-daemon"));
+        assert!(!killall_matches(&process, "really"));
     }
-}
 
-/// Prints the current working directory.
-fn pwd_callback(_flags: Vec<String>, _args: Vec<String>) -> CommandResult {
-    match env::current_dir() {
-        Ok(path) => CommandResult::with_stdout(format!("{}\n", path.display())),
-        Err(e) => {
-            CommandResult::with_stderr(format!("pwd: error retrieving current directory: {}", e))
-        }
+    #[test]
+    fn test_jump_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("dirs");
+        let mut state = ShellState::default();
+        record_visit(&state);
+        assert!(!file.exists());
+
+        state.dirs_file = Some(file.clone());
+        record_visit(&state);
+        let cwd = logical_cwd(&state.vars).unwrap();
+        let listing = capture(&command_list(), &mut state, &mut io::empty(), |ctx| {
+            j_callback(vec!["-l".to_string()], vec![], ctx)
+        });
+        assert!(
+            listing
+                .stdout_text()
+                .ends_with(&format!("  {}\n", cwd.display()))
+        );
     }
-}
 
-/// Changes the current working directory.
-///
-/// If no arguments are provided, it defaults to the `HOME` environment variable,
-/// or `/` if `HOME` is not set.
-fn cd_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
-    let destination = if args.is_empty() {
-        env::var("HOME").unwrap_or_else(|_| "/".to_string())
-    } else {
-        args[0].clone()
-    };
+    #[test]
+    fn test_schedule_time() {
+        use chrono::TimeZone;
 
-    let new_path = Path::new(&destination);
-    match env::set_current_dir(new_path) {
-        Ok(_) => CommandResult::new(),
-        Err(e) => CommandResult::with_stderr(format!("cd: {}: {}", destination, e)),
+        let now = Local.with_ymd_and_hms(2026, 3, 10, 14, 30, 0).unwrap();
+        let at = |spec: &str| {
+            parse_schedule_time(spec, now).map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        };
+        assert_eq!(at("10m").as_deref(), Some("2026-03-10 14:40:00"));
+        assert_eq!(at("90").as_deref(), Some("2026-03-10 14:31:30"));
+        assert_eq!(at("16:05").as_deref(), Some("2026-03-10 16:05:00"));
+        assert_eq!(at("09:00:30").as_deref(), Some("2026-03-11 09:00:30"));
+        assert_eq!(at("14:30").as_deref(), Some("2026-03-11 14:30:00"));
+        assert_eq!(at("25:00"), None);
+        assert_eq!(at("soon"), None);
+        assert_eq!(at("99999999999h"), None);
+
+        let result = with_context(&command_list(), |ctx| {
+            schedule_callback(vec![], vec!["-c".to_string(), "999".to_string()], ctx)
+        });
+        assert_eq!(result.stderr, "schedule: 999: no such job");
     }
-}
 
-/// Creates one or more directories.
-///
-/// Uses `create_dir_all` to support nested paths and skip existing directories.
-fn mkdir_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
-    let mut result = CommandResult::new();
-    for path in args {
-        if let Err(e) = std::fs::create_dir_all(&path) {
-            if !result.stderr.is_empty() {
-                result.stderr.push('\n');
-            }
-            result
-                .stderr
-                .push_str(&format!("mkdir: cannot create directory '{}': {}", path, e));
+    #[test]
+    fn test_history_stats_format() {
+        use crate::history::{Entry, stats};
+        use chrono::TimeZone;
+
+        assert_eq!(
+            format_history_stats(&stats(&[]), 10, "/home/u"),
+            "No history yet\n"
+        );
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap();
+        let entries: Vec<Entry> = [
+            (at(8, 9), "/srv", "ls"),
+            (at(9, 10), "/home/u", "ls"),
+            (at(9, 11), "/home/u", "cd src; ls -l"),
+            (at(10, 9), "/home/u/src", "cat a.txt"),
+        ]
+        .into_iter()
+        .map(|(time, dir, line)| Entry {
+            time,
+            dir: dir.to_string(),
+            line: line.to_string(),
+        })
+        .collect();
+        assert_eq!(
+            format_history_stats(&stats(&entries), 2, "/home/u"),
+            "4 entries from 2026-03-08 09:00 to 2026-03-10 09:00\n\
+             \n\
+             Most used commands:\n      \
+             3  ls\n      \
+             1  cat\n\
+             \n\
+             Most visited directories:\n      \
+             2  ~\n      \
+             1  ~/src\n\
+             \n\
+             Commands per day:\n  \
+             2026-03-09      2  ########################################\n  \
+             2026-03-10      1  ####################\n"
+        );
+    }
+
+    #[test]
+    fn test_j_no_match() {
+        let cmds = command_list();
+        let result = execute(&cmds, "j", vec![], vec!["zz-no-such-dir-7f3a".to_string()]);
+        assert_eq!(result.stderr, "j: no match for 'zz-no-such-dir-7f3a'");
+        let result = execute(
+            &cmds,
+            "j",
+            vec!["-l".to_string()],
+            vec!["zz-no-such-dir-7f3a".to_string()],
+        );
+        assert_eq!(result.stdout_text(), "");
+    }
+
+    #[test]
+    fn test_ls_recursive() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().to_str().unwrap().to_string();
+        fs::create_dir_all(dir.path().join("a/b")).unwrap();
+        fs::write(dir.path().join("a/file.txt"), "x").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("..", dir.path().join("a/up")).unwrap();
+
+        let res = run_ls(vec!["-R".to_string()], vec![root.clone()]);
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        // The layout depends on whether stdout is a terminal, the order not
+        let mut expected = vec![format!("{}:", root), "a".to_string()];
+        expected.push(format!("{}/a:", root));
+        expected.extend(["b".to_string(), "file.txt".to_string()]);
+        if cfg!(unix) {
+            expected.push("up".to_string());
         }
+        expected.push(format!("{}/a/b:", root));
+        assert_eq!(
+            res.stdout_text().split_whitespace().collect::<Vec<_>>(),
+            expected
+        );
+
+        let missing = format!("{}/missing", root);
+        let res = run_ls(vec!["-R".to_string()], vec![missing.clone(), root]);
+        assert!(
+            res.stderr
+                .starts_with(&format!("ls: cannot access '{}'", missing))
+        );
+        assert!(res.stdout_text().contains("/a/b:"));
     }
-    result
-}
 
-/// Concatenates and prints files to standard output.
-///
-/// If no files are provided, it reads from standard input until EOF.
-fn cat_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
-    let mut result = CommandResult::new();
-    if args.is_empty() {
-        let stdin = io::stdin();
-        let mut handle = stdin.lock();
-        let mut line = String::new();
-        let mut stdout = io::stdout();
+    #[test]
+    fn test_streaming_output() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().display().to_string();
+        fs::write(dir.path().join("x"), "hello\n").unwrap();
+        let cmds = command_list();
+        let call = |line: String| {
+            CommandCall::parse(&line, &ShellState::default())
+                .unwrap()
+                .unwrap()
+        };
 
-        // In interactive mode, echo lines immediately to stdout
-        while let Ok(n) = handle.read_line(&mut line) {
-            if n == 0 {
-                break;
-            }
-            if let Err(e) = stdout.write_all(line.as_bytes()) {
-                result.stderr = format!("cat: {}", e);
-                break;
+        // At the prompt, a streaming command writes its output itself
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let res = cmds.run_call(
+            &mut ShellContext::new(
+                &cmds,
+                &mut ShellState::default(),
+                &mut io::empty(),
+                Output::new(&mut stdout, &mut stderr),
+            ),
+            call(format!("ls -R {} {}/missing", root, root)),
+        );
+        assert!(res.stdout_text().is_empty() && res.stderr.is_empty());
+        assert_eq!(
+            String::from_utf8(stdout).unwrap(),
+            format!("{}:\nx\n", root)
+        );
+        let stderr = String::from_utf8(stderr).unwrap();
+        assert!(stderr.starts_with("ls: cannot access") && stderr.ends_with(")\n"));
+
+        // while a buffered one leaves it in the result
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let res = cmds.run_call(
+            &mut ShellContext::new(
+                &cmds,
+                &mut ShellState::default(),
+                &mut io::empty(),
+                Output::new(&mut stdout, &mut stderr),
+            ),
+            call("echo hi".to_string()),
+        );
+        assert!(stdout.is_empty());
+        assert_eq!(res.stdout_text(), "hi\n");
+
+        // Run by another command, its output is collected
+        let state = ShellState::default();
+        let res = cmds.run_detached(&state, call(format!("cat {}/x {}/x", root, root)));
+        assert_eq!(res.stdout_text(), "hello\nhello\n");
+        let res = cmds.run_detached(&state, call(format!("ls {}/missing {}", root, root)));
+        assert_eq!(res.stdout_text(), format!("{}:\nx\n", root));
+        assert!(res.stderr.starts_with("ls: cannot access") && !res.stderr.ends_with('\n'));
+    }
+
+    #[test]
+    fn test_stateful_command() {
+        struct Counter(std::sync::atomic::AtomicUsize);
+
+        impl ShellCommand for Counter {
+            fn run(&self, ctx: &mut ShellContext, inv: Invocation) -> CommandResult {
+                let n = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                let _ = writeln!(ctx.out.stdout, "{} run {} time(s)", inv.name, n);
+                CommandResult::with_stdout(inv.args.join(" "))
             }
-            let _ = stdout.flush();
-            line.clear();
         }
-    } else {
-        for file_path in args {
-            match File::open(&file_path) {
-                Ok(file) => {
-                    let mut reader = BufReader::new(file);
-                    let mut contents = String::new();
-                    if let Err(e) = reader.read_to_string(&mut contents) {
-                        if !result.stderr.is_empty() {
-                            result.stderr.push('\n');
-                        }
-                        result
-                            .stderr
-                            .push_str(&format!("cat: {}: {}", file_path, e));
-                    } else {
-                        result.stdout.push_str(&contents);
-                    }
-                }
-                Err(e) => {
-                    if !result.stderr.is_empty() {
-                        result.stderr.push('\n');
-                    }
-                    result
-                        .stderr
-                        .push_str(&format!("cat: {}: {}", file_path, e));
-                }
-            }
+
+        let mut cmds = command_list();
+        cmds.register(
+            "count".to_string(),
+            Command::from_command(
+                "count [ARG...] - count runs",
+                false,
+                Counter(Default::default()),
+            ),
+        );
+        execute(&cmds, "count", vec![], vec![]);
+        let res = execute(
+            &cmds,
+            "count",
+            vec![],
+            vec!["a".to_string(), "b".to_string()],
+        );
+        assert_eq!(res.stdout_text(), "count run 2 time(s)\na b");
+        let res = execute(&cmds, "count", vec!["--help".to_string()], vec![]);
+        assert_eq!(res.stdout_text(), "Usage: count [ARG...] - count runs\n");
+    }
+
+    #[test]
+    fn test_shell_context() {
+        let cmds = command_list();
+        let mut state = ShellState::default();
+        let mut run = |line: &str, stdin: &[u8]| {
+            let call = CommandCall::parse(line, &ShellState::default())
+                .unwrap()
+                .unwrap();
+            capture(&cmds, &mut state, &mut &stdin[..], |ctx| {
+                cmds.run_call(ctx, call)
+            })
+        };
+
+        // Aliases last from one command to the next
+        run("alias greet='echo hello' echo='echo -e'", b"");
+        assert_eq!(run("greet world", b"").stdout_text(), "hello world\n");
+        assert_eq!(run("echo 'a\\tb'", b"").stdout_text(), "a\tb\n");
+        assert_eq!(
+            run("alias", b"").stdout_text(),
+            "alias echo='echo -e'\nalias greet='echo hello'\n"
+        );
+        assert_eq!(run("alias nope", b"").status, 1);
+        run("unalias greet", b"");
+        assert_eq!(run("greet", b"").status, 127);
+
+        // Commands read the context's input
+        assert_eq!(run("cat", b"piped\n").stdout_text(), "piped\n");
+        assert_eq!(run("xargs echo", b"a b").stdout_text(), "a b\n");
+
+        state.last_status = 3;
+        let res = capture(&cmds, &mut state, &mut io::empty(), |ctx| {
+            exit_callback(vec![], vec![], ctx)
+        });
+        assert_eq!(res.status, 3);
+    }
+
+    #[test]
+    fn test_binary_output() {
+        let res = echo_callback(vec!["-e".to_string()], vec!["\\xff\\0\\xfe".to_string()]);
+        assert_eq!(res.stdout, b"\xff\0\xfe\n");
+
+        // Bytes that are not UTF-8 pass through unchanged
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("data.bin");
+        let data = [0x89, b'P', b'N', b'G', 0, 0xff, 0xc3, b'\n'];
+        fs::write(&file, data).unwrap();
+        let call = CommandCall::parse(&format!("cat {}", file.display()), &ShellState::default())
+            .unwrap()
+            .unwrap();
+        let res = command_list().run_detached(&ShellState::default(), call);
+        assert_eq!(res.stdout, data);
+    }
+
+    #[test]
+    fn test_ls_sort() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().to_str().unwrap().to_string();
+        let now = std::time::SystemTime::now();
+        for (name, size, age) in [("a", 30, 20), ("b", 10, 0), ("c", 20, 10)] {
+            let file = File::create(dir.path().join(name)).unwrap();
+            file.set_len(size).unwrap();
+            file.set_modified(now - std::time::Duration::from_secs(age * 60))
+                .unwrap();
         }
+        let ls = |flags: &[&str]| {
+            let flags = flags.iter().map(|f| f.to_string()).collect();
+            let res = run_ls(flags, vec![root.clone()]);
+            res.stdout_text()
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        assert_eq!(ls(&[]), "a b c");
+        assert_eq!(ls(&["-r"]), "c b a");
+        assert_eq!(ls(&["-t"]), "b c a");
+        assert_eq!(ls(&["-S"]), "a c b");
+        assert_eq!(ls(&["-S", "-r"]), "b c a");
+        assert_eq!(ls(&["-S", "-t"]), "b c a");
+
+        // `-h` is a listing option for `ls`, not a request for help
+        let res = execute(
+            &command_list(),
+            "ls",
+            vec!["-l".to_string(), "-h".to_string(), "-S".to_string()],
+            vec![root.clone()],
+        );
+        assert!(res.stdout_text().lines().nth(1).unwrap().contains(" 30 "));
+        File::options()
+            .write(true)
+            .open(dir.path().join("a"))
+            .unwrap()
+            .set_len(1_258_291)
+            .unwrap();
+        let flags = vec!["-l".to_string(), "-h".to_string(), "-S".to_string()];
+        let long = run_ls(flags, vec![root.clone()]).stdout_text().into_owned();
+        assert!(long.lines().nth(1).unwrap().contains(" 1.2M "), "{}", long);
     }
-    result
-}
 
-/// Resolves the final destination path for copy/move operations.
-///
-/// If the `dest_path` is a directory, the source's file name is appended to it.
-fn resolve_destination(src_path: &Path, dest_path: &Path) -> Result<std::path::PathBuf, String> {
-    if dest_path.is_dir() {
-        let file_name = src_path
-            .file_name()
-            .ok_or_else(|| format!("invalid source path: {}", src_path.display()))?;
-        Ok(dest_path.join(file_name))
-    } else {
-        Ok(dest_path.to_path_buf())
+    #[test]
+    fn test_format_columns() {
+        let names: Vec<String> = ["a", "bb", "ccc", "dddd", "e", "ff", "g"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let widths: Vec<usize> = names.iter().map(|name| name.len()).collect();
+        assert_eq!(
+            format_columns(&names, &widths, 80),
+            "a  bb  ccc  dddd  e  ff  g\n"
+        );
+        assert_eq!(
+            format_columns(&names, &widths, 16),
+            "a   ccc   e   g\nbb  dddd  ff\n"
+        );
+        assert_eq!(
+            format_columns(&names, &widths, 3),
+            "a\nbb\nccc\ndddd\ne\nff\ng\n"
+        );
+        assert_eq!(format_columns(&[], &[], 80), "");
+
+        // Color escapes take no room on screen
+        let painted = vec!["\x1b[01;34ma\x1b[0m".to_string(), "bb".to_string()];
+        assert_eq!(
+            format_columns(&painted, &[1, 2], 5),
+            "\x1b[01;34ma\x1b[0m  bb\n"
+        );
     }
-}
 
-/// Copies files and directories.
-///
-/// Supports multiple sources if the destination is a directory.
-fn cp_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
-    if args.len() < 2 {
-        return CommandResult::with_stderr(
-            "cp: missing destination file operand after source".to_string(),
+    #[test]
+    fn test_ls_color_option() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().to_str().unwrap().to_string();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("notes.txt"), "").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("missing", dir.path().join("gone")).unwrap();
+
+        let ls = |flag: &str| run_ls(vec![flag.to_string()], vec![root.clone()]);
+        let never = ls("--color=never");
+        assert!(!never.stdout_text().contains('\x1b'));
+        let always = ls("--color=always");
+        assert!(always.stdout_text().contains("sub\x1b[0m"));
+        assert!(always.stdout_text().contains("notes.txt"));
+        #[cfg(unix)]
+        assert!(always.stdout_text().contains("gone\x1b[0m"));
+        assert_eq!(
+            ls("--color=sometimes").stderr,
+            "ls: invalid argument 'sometimes' for '--color'"
         );
     }
 
-    let mut result = CommandResult::new();
-    let (sources, destination) = args.split_at(args.len() - 1);
-    let dest_path = Path::new(&destination[0]);
+    #[cfg(unix)]
+    #[test]
+    fn test_ls_inode_and_numeric_ids() {
+        use std::os::unix::fs::MetadataExt;
 
-    if sources.len() > 1 && !dest_path.is_dir() {
-        return CommandResult::with_stderr(format!(
-            "cp: target '{}' is not a directory",
-            destination[0]
-        ));
+        let dir = tempdir().unwrap();
+        let root = dir.path().to_str().unwrap().to_string();
+        fs::write(dir.path().join("a.txt"), "hello").unwrap();
+        let meta = fs::metadata(dir.path().join("a.txt")).unwrap();
+
+        let res = run_ls(vec!["-i".to_string()], vec![root.clone()]);
+        assert_eq!(res.stdout_text().trim(), format!("{} a.txt", meta.ino()));
+
+        let res = run_ls(vec!["-n".to_string(), "-i".to_string()], vec![root]);
+        let stdout = res.stdout_text();
+        let fields: Vec<&str> = stdout.split_whitespace().collect();
+        let ino = meta.ino().to_string();
+        let (uid, gid) = (meta.uid().to_string(), meta.gid().to_string());
+        let total = meta.blocks().div_ceil(2).to_string();
+        assert_eq!(fields[..2], ["total", &total]);
+        assert_eq!(fields[2], ino);
+        assert!(fields[3].starts_with("-rw"));
+        assert_eq!(fields[4..8], ["1", &uid, &gid, "5"]);
+        assert_eq!(fields.last(), Some(&"a.txt"));
     }
 
-    for source_str in sources {
-        let src_path = Path::new(source_str);
-        match resolve_destination(src_path, dest_path) {
-            Ok(final_dest) => {
-                if let Err(e) = fs::copy(src_path, final_dest) {
-                    if !result.stderr.is_empty() {
-                        result.stderr.push('\n');
-                    }
-                    result
-                        .stderr
-                        .push_str(&format!("cp: {}: {}", source_str, e));
-                }
-            }
-            Err(e) => {
-                if !result.stderr.is_empty() {
-                    result.stderr.push('\n');
-                }
-                result.stderr.push_str(&format!("cp: {}", e));
-            }
-        }
+    #[cfg(unix)]
+    #[test]
+    fn test_ls_owner_names() {
+        let mut owners = OwnerNames::default();
+        assert_eq!(owners.user(0), "root");
+        assert_eq!(owners.user(3_999_999), "3999999");
+        assert_eq!(owners.group(3_999_999), "3999999");
+        assert_eq!(owners.users.len(), 2);
+
+        let dir = tempdir().unwrap();
+        fs::write(dir.path().join("a.txt"), "hello").unwrap();
+        let res = run_ls(
+            vec!["-l".to_string()],
+            vec![dir.path().to_str().unwrap().to_string()],
+        );
+        let stdout = res.stdout_text();
+        let fields: Vec<&str> = stdout.split_whitespace().collect();
+        let uid = unsafe { libc::getuid() };
+        let expected = crate::process::user_name(uid).unwrap_or_else(|| uid.to_string());
+        assert_eq!(fields[4], expected);
     }
 
-    result
-}
+    #[cfg(unix)]
+    #[test]
+    fn test_ls_symlinks() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().to_str().unwrap().to_string();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        std::os::unix::fs::symlink("sub", dir.path().join("to_sub")).unwrap();
+        std::os::unix::fs::symlink("missing", dir.path().join("gone")).unwrap();
 
-/// Moves or renames files and directories.
-///
-/// Supports multiple sources if the destination is a directory.
-fn mv_callback(_flags: Vec<String>, args: Vec<String>) -> CommandResult {
-    if args.len() < 2 {
-        return CommandResult::with_stderr(
-            "mv: missing destination file operand after source".to_string(),
+        let ls = |flags: &[&str]| {
+            let flags = flags.iter().map(|f| f.to_string()).collect();
+            run_ls(flags, vec![root.clone()])
+        };
+        let short = ls(&["-F"]).stdout_text().into_owned();
+        assert_eq!(
+            short.split_whitespace().collect::<Vec<_>>(),
+            ["gone@", "sub/", "to_sub@"]
         );
+
+        let long = ls(&["-l", "-F"]).stdout_text().into_owned();
+        let lines: Vec<&str> = long.lines().collect();
+        assert!(lines[1].starts_with('l') && lines[1].ends_with(" gone -> missing"));
+        assert!(lines[2].starts_with('d') && lines[2].ends_with(" sub/"));
+        assert!(lines[3].starts_with('l') && lines[3].ends_with(" to_sub -> sub/"));
     }
 
-    let mut result = CommandResult::new();
-    let (sources, destination) = args.split_at(args.len() - 1);
-    let dest_path = Path::new(&destination[0]);
+    #[test]
+    fn test_ls_file_arguments() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("a.txt"), "hello").unwrap();
+        fs::create_dir(path("sub")).unwrap();
+        fs::write(path("sub/c"), "").unwrap();
 
-    if sources.len() > 1 && !dest_path.is_dir() {
-        return CommandResult::with_stderr(format!(
-            "mv: target '{}' is not a directory",
-            destination[0]
-        ));
+        // Files come first, then each directory under a header
+        let res = run_ls(
+            vec!["-F".to_string()],
+            vec![path("sub"), path("a.txt"), path("missing")],
+        );
+        assert!(
+            res.stderr
+                .starts_with(&format!("ls: cannot access '{}'", path("missing")))
+        );
+        assert_eq!(
+            res.stdout_text().split_whitespace().collect::<Vec<_>>(),
+            [path("a.txt"), format!("{}:", path("sub")), "c".to_string()]
+        );
+
+        let res = run_ls(vec!["-l".to_string()], vec![path("a.txt")]);
+        assert_eq!(res.stdout_text().lines().count(), 1);
+        assert!(res.stdout_text().starts_with("-rw"));
+        assert!(
+            res.stdout_text()
+                .ends_with(&format!(" {}\n", path("a.txt")))
+        );
     }
 
-    for source_str in sources {
-        let src_path = Path::new(source_str);
-        match resolve_destination(src_path, dest_path) {
-            Ok(final_dest) => {
-                if let Err(e) = fs::rename(src_path, final_dest) {
-                    if !result.stderr.is_empty() {
-                        result.stderr.push('\n');
-                    }
-                    result.stderr.push_str(&format!(
-                        "mv: cannot move '{}' to '{}': {}",
-                        source_str, destination[0], e
-                    ));
-                }
-            }
-            Err(e) => {
-                if !result.stderr.is_empty() {
-                    result.stderr.push('\n');
-                }
-                result.stderr.push_str(&format!("mv: {}", e));
-            }
+    #[test]
+    fn test_ls_one_per_line() {
+        let dir = tempdir().unwrap();
+        for name in ["a", "b", "c"] {
+            fs::write(dir.path().join(name), "").unwrap();
         }
+        let res = run_ls(
+            vec!["-1".to_string()],
+            vec![dir.path().to_str().unwrap().to_string()],
+        );
+        assert_eq!(res.stdout_text(), "a\nb\nc\n");
     }
 
-    result
-}
+    #[test]
+    fn test_ls_sort_by_extension() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().to_str().unwrap().to_string();
+        for name in ["b.txt", "a.rs", "c.rs", "Makefile", "z.md"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        fs::create_dir(dir.path().join("src")).unwrap();
+        let ls = |flags: &[&str]| {
+            let mut flags: Vec<String> = flags.iter().map(|f| f.to_string()).collect();
+            flags.push("-1".to_string());
+            run_ls(flags, vec![root.clone()])
+        };
+        assert_eq!(
+            ls(&["-X"]).stdout_text(),
+            "Makefile\nsrc\nz.md\na.rs\nc.rs\nb.txt\n"
+        );
+        assert_eq!(
+            ls(&["--sort=extension", "--group-directories-first"]).stdout_text(),
+            "src\nMakefile\nz.md\na.rs\nc.rs\nb.txt\n"
+        );
+        assert_eq!(
+            ls(&["--group-directories-first", "-r"]).stdout_text(),
+            "src\nz.md\nc.rs\nb.txt\na.rs\nMakefile\n"
+        );
+        assert_eq!(
+            ls(&["--sort=colour"]).stderr,
+            "ls: invalid argument 'colour' for '--sort'"
+        );
+    }
 
-/// Removes files or directories.
-///
-/// Supports the `-r` or `-R` flag for recursive removal of directories.
-fn rm_callback(flags: Vec<String>, args: Vec<String>) -> CommandResult {
-    let recursive = flags.iter().any(|f| f == "-r" || f == "-R");
-    let mut result = CommandResult::new();
+    #[test]
+    fn test_cp_recursive() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::create_dir_all(path("tree/sub")).unwrap();
+        fs::write(path("tree/sub/f.txt"), "data").unwrap();
+        fs::create_dir(path("into")).unwrap();
 
-    for path_str in args {
-        let path = Path::new(&path_str);
+        let res = with_context(&command_list(), |ctx| {
+            cp_callback(vec![], vec![path("tree"), path("copy")], ctx)
+        });
+        assert_eq!(
+            res.stderr,
+            format!(
+                "cp: -r not specified; omitting directory '{}'",
+                path("tree")
+            )
+        );
+        assert!(!Path::new(&path("copy")).exists());
 
-        let remove_res = if !path.exists() {
-            Err(format!(
-                "rm: cannot remove '{}': No such file or directory",
-                path_str
-            ))
-        } else if path.is_dir() {
-            if recursive {
-                fs::remove_dir_all(path).map_err(|e| format!("rm: {}: {}", path_str, e))
-            } else {
-                Err(format!("rm: cannot remove '{}': Is a directory", path_str))
-            }
-        } else {
-            fs::remove_file(path).map_err(|e| format!("rm: {}: {}", path_str, e))
-        };
+        let res = with_context(&command_list(), |ctx| {
+            cp_callback(
+                vec!["-r".to_string()],
+                vec![path("tree"), path("copy")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert_eq!(fs::read_to_string(path("copy/sub/f.txt")).unwrap(), "data");
 
-        if let Err(e) = remove_res {
-            if !result.stderr.is_empty() {
-                result.stderr.push('\n');
-            }
-            result.stderr.push_str(&e);
-        }
+        // An existing directory receives the copy inside it
+        let res = with_context(&command_list(), |ctx| {
+            cp_callback(
+                vec!["-R".to_string()],
+                vec![path("tree"), path("into")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert_eq!(
+            fs::read_to_string(path("into/tree/sub/f.txt")).unwrap(),
+            "data"
+        );
     }
 
-    result
-}
+    #[test]
+    #[cfg(unix)]
+    fn test_cp_preserve() {
+        use std::os::unix::fs::PermissionsExt;
+        use std::time::{Duration, SystemTime};
 
-/// Lists directory contents.
-///
-/// Supports the following flags:
-/// - `-a`: List all entries, including those starting with `.`.
-/// - `-l`: Use a long listing format.
-/// - `-F`: Append a character to each entry indicating its type.
-fn ls_callback(flags: Vec<String>, mut args: Vec<String>) -> CommandResult {
-    let all = flags.iter().any(|f| f == "-a");
-    let long = flags.iter().any(|f| f == "-l");
-    let classify = flags.iter().any(|f| f == "-F");
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::create_dir(path("tree")).unwrap();
+        fs::write(path("tree/f.txt"), "data").unwrap();
+        let old = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let times = fs::FileTimes::new().set_accessed(old).set_modified(old);
+        fs::File::options()
+            .write(true)
+            .open(path("tree/f.txt"))
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+        fs::File::open(path("tree"))
+            .unwrap()
+            .set_times(times)
+            .unwrap();
+        fs::set_permissions(path("tree/f.txt"), fs::Permissions::from_mode(0o640)).unwrap();
 
-    if args.is_empty() {
-        args.push(".".to_string());
+        let res = with_context(&command_list(), |ctx| {
+            cp_callback(
+                vec!["-r".to_string(), "-p".to_string()],
+                vec![path("tree"), path("copy")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        let file = fs::metadata(path("copy/f.txt")).unwrap();
+        assert_eq!(file.modified().unwrap(), old);
+        assert_eq!(file.permissions().mode() & 0o777, 0o640);
+        assert_eq!(fs::metadata(path("copy")).unwrap().modified().unwrap(), old);
+
+        // Without -p the copy is new
+        let res = with_context(&command_list(), |ctx| {
+            cp_callback(vec![], vec![path("tree/f.txt"), path("plain.txt")], ctx)
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert_ne!(
+            fs::metadata(path("plain.txt")).unwrap().modified().unwrap(),
+            old
+        );
     }
 
-    let mut result = CommandResult::new();
-    let multi_path = args.len() > 1;
+    #[test]
+    fn test_cp_verbose() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::create_dir(path("tree")).unwrap();
+        fs::write(path("tree/f.txt"), "data").unwrap();
 
-    for (i, path_str) in args.iter().enumerate() {
-        if multi_path {
-            if i > 0 {
-                result.stdout.push('\n');
-            }
-            result.stdout.push_str(&format!("{}:\n", path_str));
-        }
+        let res = with_context(&command_list(), |ctx| {
+            cp_callback(
+                vec!["-r".to_string(), "-v".to_string()],
+                vec![path("tree"), path("copy")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert_eq!(
+            res.stdout_text(),
+            format!(
+                "'{}' -> '{}'\n'{}' -> '{}'\n",
+                path("tree"),
+                path("copy"),
+                path("tree/f.txt"),
+                path("copy/f.txt")
+            )
+        );
 
-        match fs::read_dir(path_str) {
-            Ok(entries) => {
-                let mut entry_list = Vec::new();
-                for entry in entries {
-                    match entry {
-                        Ok(e) => {
-                            let name = e.file_name().to_string_lossy().into_owned();
-                            if all || !name.starts_with('.') {
-                                entry_list.push(e);
-                            }
-                        }
-                        Err(e) => {
-                            if !result.stderr.is_empty() {
-                                result.stderr.push('\n');
-                            }
-                            result.stderr.push_str(&format!("ls: {}", e));
-                        }
-                    }
-                }
+        // Skipped files are not listed
+        let res = with_context(&command_list(), |ctx| {
+            cp_callback(
+                vec!["-n".to_string(), "-v".to_string()],
+                vec![path("tree/f.txt"), path("copy/f.txt")],
+                ctx,
+            )
+        });
+        assert_eq!(res.stdout_text(), "");
+    }
 
-                entry_list.sort_by_key(|e| e.file_name());
+    #[test]
+    fn test_cp_update() {
+        use std::time::{Duration, SystemTime};
 
-                for entry in entry_list {
-                    match entry.metadata() {
-                        Ok(metadata) => {
-                            let mut name = entry.file_name().to_string_lossy().into_owned();
-                            if classify {
-                                if metadata.is_dir() {
-                                    name.push('/');
-                                } else if is_executable(&metadata) {
-                                    name.push('*');
-                                }
-                            }
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let write = |name: &str, text: &str, secs: u64| {
+            fs::write(path(name), text).unwrap();
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+            fs::File::options()
+                .write(true)
+                .open(path(name))
+                .unwrap()
+                .set_modified(time)
+                .unwrap();
+        };
+        fs::create_dir(path("src")).unwrap();
+        fs::create_dir_all(path("out/src")).unwrap();
+        write("src/newer.txt", "new", 2_000_000_000);
+        write("out/src/newer.txt", "old", 1_000_000_000);
+        write("src/older.txt", "old", 1_000_000_000);
+        write("out/src/older.txt", "kept", 2_000_000_000);
+        write("src/missing.txt", "new", 1_000_000_000);
 
-                            if long {
-                                let mode = parse_permissions(&metadata);
-                                let size = metadata.len();
-                                let modified: DateTime<Local> = metadata.modified().unwrap().into();
-                                let time_str = modified.format("%b %d %H:%M").to_string();
-                                result.stdout.push_str(&format!(
-                                    "{} {:>8} {} {}\n",
-                                    mode, size, time_str, name
-                                ));
-                            } else {
-                                result.stdout.push_str(&format!("{}  ", name));
-                            }
-                        }
-                        Err(e) => {
-                            if !result.stderr.is_empty() {
-                                result.stderr.push('\n');
-                            }
-                            result.stderr.push_str(&format!("ls: {}", e));
-                        }
-                    }
-                }
-                if !long {
-                    result.stdout.push('\n');
-                }
-            }
-            Err(e) => {
-                if !result.stderr.is_empty() {
-                    result.stderr.push('\n');
-                }
-                result
-                    .stderr
-                    .push_str(&format!("ls: cannot access '{}': {}", path_str, e));
-            }
-        }
+        let res = with_context(&command_list(), |ctx| {
+            cp_callback(
+                vec!["-r".to_string(), "-u".to_string(), "-v".to_string()],
+                vec![path("src"), path("out")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert_eq!(
+            res.stdout_text().lines().count(),
+            2,
+            "{}",
+            res.stdout_text()
+        );
+        assert_eq!(
+            fs::read_to_string(path("out/src/newer.txt")).unwrap(),
+            "new"
+        );
+        assert_eq!(
+            fs::read_to_string(path("out/src/older.txt")).unwrap(),
+            "kept"
+        );
+        assert_eq!(
+            fs::read_to_string(path("out/src/missing.txt")).unwrap(),
+            "new"
+        );
     }
 
-    result
-}
-
-/// Checks if a file is executable.
-///
-/// On Unix, checks the permission bits. On Windows, currently returns false.
-fn is_executable(metadata: &std::fs::Metadata) -> bool {
-    #[cfg(unix)]
-    {
-        metadata.permissions().mode() & 0o111 != 0
-    }
-    #[cfg(not(unix))]
-    {
-        let _ = metadata;
-        false
+    #[test]
+    fn test_cp_progress_line() {
+        let secs = std::time::Duration::from_secs;
+        let line = cp_progress_line(1 << 20, 4 << 20, secs(2));
+        assert!(line.starts_with(" 25% [=======>"), "{}", line);
+        assert!(line.ends_with("ETA 0:06"), "{}", line);
+        assert!(cp_progress_line(0, 1024, secs(0)).ends_with("ETA -:--"));
+        assert!(cp_progress_line(1024, 1024, secs(1)).ends_with("ETA 0:00"));
     }
-}
 
-/// Generates a human-readable permissions string (e.g., `drwxr-xr-x`).
-fn parse_permissions(metadata: &std::fs::Metadata) -> String {
-    let mut s = String::with_capacity(10);
+    #[test]
+    fn test_mv_no_clobber() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("a.txt"), "a").unwrap();
+        fs::write(path("b.txt"), "b").unwrap();
 
-    #[cfg(unix)]
-    {
-        let mode = metadata.permissions().mode();
-        s.push(if mode & 0o40000 != 0 { 'd' } else { '-' });
-        let rwx = ["---", "--x", "-w-", "-wx", "r--", "r-x", "rw-", "rwx"];
-        s.push_str(rwx[((mode >> 6) & 7) as usize]);
-        s.push_str(rwx[((mode >> 3) & 7) as usize]);
-        s.push_str(rwx[(mode & 7) as usize]);
-    }
+        let res = with_context(&command_list(), |ctx| {
+            mv_callback(
+                vec!["-n".to_string()],
+                vec![path("a.txt"), path("b.txt")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert_eq!(fs::read_to_string(path("a.txt")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(path("b.txt")).unwrap(), "b");
 
-    #[cfg(not(unix))]
-    {
-        s.push(if metadata.is_dir() { 'd' } else { '-' });
-        s.push_str("rw-rw-rw-");
+        let res = with_context(&command_list(), |ctx| {
+            mv_callback(
+                vec!["-n".to_string()],
+                vec![path("a.txt"), path("c.txt")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert!(!Path::new(&path("a.txt")).exists());
+        assert_eq!(fs::read_to_string(path("c.txt")).unwrap(), "a");
     }
 
-    s
-}
+    #[test]
+    fn test_mv_verbose() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("a.txt"), "a").unwrap();
+        fs::write(path("b.txt"), "b").unwrap();
+        fs::create_dir(path("into")).unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::fs;
-    use tempfile::tempdir;
+        let res = with_context(&command_list(), |ctx| {
+            mv_callback(
+                vec!["-v".to_string()],
+                vec![path("a.txt"), path("b.txt"), path("into")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert_eq!(
+            res.stdout_text(),
+            format!(
+                "renamed '{}' -> '{}'\nrenamed '{}' -> '{}'\n",
+                path("a.txt"),
+                path("into/a.txt"),
+                path("b.txt"),
+                path("into/b.txt")
+            )
+        );
+    }
 
     #[test]
-    fn test_echo_basic() {
-        let res = echo_callback(vec![], vec!["hello".to_string(), "world".to_string()]);
-        assert_eq!(res.stdout, "hello world\n");
+    fn test_mv_backup() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        let read = |name: &str| fs::read_to_string(path(name)).unwrap();
+        let mv = |flag: &str, text: &str| {
+            fs::write(path("new.txt"), text).unwrap();
+            with_context(&command_list(), |ctx| {
+                mv_callback(
+                    vec![flag.to_string()],
+                    vec![path("new.txt"), path("dest.txt")],
+                    ctx,
+                )
+            })
+        };
+        fs::write(path("dest.txt"), "0").unwrap();
+
+        // Without numbered backups, -b makes a simple one
+        assert!(mv("-b", "1").stderr.is_empty());
+        assert_eq!(
+            (read("dest.txt"), read("dest.txt~")),
+            ("1".into(), "0".into())
+        );
+
+        assert!(mv("--backup=numbered", "2").stderr.is_empty());
+        assert!(mv("--backup=numbered", "3").stderr.is_empty());
+        assert_eq!(read("dest.txt.~1~"), "1");
+        assert_eq!(read("dest.txt.~2~"), "2");
+
+        // ...and with them, a numbered one
+        assert!(mv("--backup=existing", "4").stderr.is_empty());
+        assert_eq!(read("dest.txt.~3~"), "3");
+        assert_eq!(read("dest.txt~"), "0");
+        assert_eq!(read("dest.txt"), "4");
+
+        let res = mv("--backup=sometimes", "5");
+        assert_eq!(
+            res.stderr,
+            "mv: invalid argument 'sometimes' for 'backup type'"
+        );
     }
 
     #[test]
-    fn test_echo_escapes() {
-        let res = echo_callback(vec!["-e".to_string()], vec!["hello\\nworld".to_string()]);
-        assert_eq!(res.stdout, "hello\nworld\n");
+    fn test_rm_force() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("a.txt"), "a").unwrap();
+
+        let res = with_context(&command_list(), |ctx| {
+            rm_callback(vec![], vec![path("missing")], ctx)
+        });
+        assert_eq!(
+            res.stderr,
+            format!(
+                "rm: cannot remove '{}': No such file or directory",
+                path("missing")
+            )
+        );
+
+        let res = with_context(&command_list(), |ctx| {
+            rm_callback(
+                vec!["-f".to_string()],
+                vec![path("missing"), path("a.txt")],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert!(!Path::new(&path("a.txt")).exists());
     }
 
     #[test]
-    fn test_pwd() {
-        let res = pwd_callback(vec![], vec![]);
-        let current = std::env::current_dir().unwrap();
-        assert_eq!(res.stdout, format!("{}\n", current.display()));
+    fn test_rm_recursive_tree() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::create_dir_all(path("tree/sub")).unwrap();
+        fs::write(path("tree/sub/a.txt"), "a").unwrap();
+        fs::create_dir(path("kept")).unwrap();
+        fs::write(path("kept/b.txt"), "b").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(path("kept"), path("tree/link")).unwrap();
+
+        // Links to directories are removed, not followed
+        let res = with_context(&command_list(), |ctx| {
+            rm_callback(vec!["-r".to_string()], vec![path("tree")], ctx)
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert!(!Path::new(&path("tree")).exists());
+        assert_eq!(fs::read_to_string(path("kept/b.txt")).unwrap(), "b");
+
+        let meta = fs::metadata(path("kept/b.txt")).unwrap();
+        assert_eq!(rm_file_kind(&meta), "regular file");
     }
 
     #[test]
-    fn test_exit() {
-        let res = exit_callback(vec![], vec![]);
-        assert!(res.should_exit);
+    fn test_rm_verbose() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::create_dir_all(path("tree/sub")).unwrap();
+        fs::write(path("tree/sub/a.txt"), "a").unwrap();
+
+        let res = with_context(&command_list(), |ctx| {
+            rm_callback(
+                vec!["-r".to_string(), "-v".to_string()],
+                vec![format!("{}/", path("tree"))],
+                ctx,
+            )
+        });
+        assert!(res.stderr.is_empty(), "{}", res.stderr);
+        assert_eq!(
+            res.stdout_text(),
+            format!(
+                "removed '{0}/sub/a.txt'\nremoved directory '{0}/sub'\nremoved directory '{0}/'\n",
+                path("tree")
+            )
+        );
     }
 
     #[test]
-    fn test_mkdir_and_ls() {
+    fn test_rm_empty_dirs() {
         let dir = tempdir().unwrap();
-        let path = dir.path().join("test_dir");
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::create_dir(path("empty")).unwrap();
+        fs::create_dir(path("full")).unwrap();
+        fs::write(path("full/a.txt"), "a").unwrap();
 
-        // Test mkdir
-        let res = mkdir_callback(vec![], vec![path.to_str().unwrap().to_string()]);
-        assert!(res.stderr.is_empty());
-        assert!(path.exists());
+        let res = with_context(&command_list(), |ctx| {
+            rm_callback(vec![], vec![path("empty")], ctx)
+        });
+        assert_eq!(
+            res.stderr,
+            format!("rm: cannot remove '{}': Is a directory", path("empty"))
+        );
 
-        // Test ls
-        let res = ls_callback(vec![], vec![dir.path().to_str().unwrap().to_string()]);
-        assert!(res.stdout.contains("test_dir"));
+        let res = with_context(&command_list(), |ctx| {
+            rm_callback(
+                vec!["-d".to_string()],
+                vec![path("empty"), path("full")],
+                ctx,
+            )
+        });
+        assert_eq!(
+            res.stderr,
+            format!("rm: cannot remove '{}': Directory not empty", path("full"))
+        );
+        assert!(!Path::new(&path("empty")).exists());
+        assert!(Path::new(&path("full/a.txt")).exists());
     }
 
     #[test]
-    fn test_cp_and_mv() {
+    fn test_rm_guards() {
         let dir = tempdir().unwrap();
-        let src = dir.path().join("src.txt");
-        let dest = dir.path().join("dest.txt");
-        let moved = dir.path().join("moved.txt");
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::create_dir(path("sub")).unwrap();
 
-        fs::write(&src, "hello").unwrap();
+        let res = with_context(&command_list(), |ctx| {
+            rm_callback(
+                vec!["-r".to_string()],
+                vec![path("sub/.."), ".".to_string()],
+                ctx,
+            )
+        });
+        assert_eq!(
+            res.stderr,
+            format!(
+                "rm: refusing to remove '.' or '..' directory: skipping '{}'\n\
+                 rm: refusing to remove '.' or '..' directory: skipping '.'",
+                path("sub/..")
+            )
+        );
+        assert!(Path::new(&path("sub")).exists());
+    }
 
-        // Test cp
-        cp_callback(
-            vec![],
-            vec![
-                src.to_str().unwrap().to_string(),
-                dest.to_str().unwrap().to_string(),
-            ],
+    #[test]
+    fn test_cat_numbering() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("a.txt"), "one\n\ntw").unwrap();
+        fs::write(path("b.txt"), "o\nthree\n").unwrap();
+
+        let files = [path("a.txt"), path("b.txt")];
+        assert_eq!(
+            cat_output(&["-n"], &files),
+            "     1\tone\n     2\t\n     3\ttwo\n     4\tthree\n"
         );
-        assert!(dest.exists());
-        assert_eq!(fs::read_to_string(&dest).unwrap(), "hello");
+        assert_eq!(
+            cat_output(&["-b"], &files),
+            "     1\tone\n\n     2\ttwo\n     3\tthree\n"
+        );
+    }
 
-        // Test mv
-        mv_callback(
-            vec![],
-            vec![
-                dest.to_str().unwrap().to_string(),
-                moved.to_str().unwrap().to_string(),
-            ],
+    #[test]
+    fn test_cat_show_nonprinting() {
+        let dir = tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("a.txt"), b"a\tb \r\n\x1b[0m\x7f\xc3\xa9\n").unwrap();
+
+        let cat = |flags: &[&str]| cat_output(flags, &[path("a.txt")]);
+        assert_eq!(cat(&["-E"]), "a\tb \r$\n\x1b[0m\x7f\u{e9}$\n");
+        assert_eq!(cat(&["-T"]), "a^Ib \r\n\x1b[0m\x7f\u{e9}\n");
+        assert_eq!(cat(&["-v"]), "a\tb ^M\n^[[0m^?M-CM-)\n");
+        assert_eq!(cat(&["-A"]), "a^Ib ^M$\n^[[0m^?M-CM-)$\n");
+        assert_eq!(
+            cat(&["-n", "-E"]),
+            "     1\ta\tb \r$\n     2\t\x1b[0m\x7f\u{e9}$\n"
         );
-        assert!(!dest.exists());
-        assert!(moved.exists());
-        assert_eq!(fs::read_to_string(&moved).unwrap(), "hello");
     }
 
     #[test]
-    fn test_rm() {
+    fn test_cat_squeeze_blank() {
         let dir = tempdir().unwrap();
-        let file = dir.path().join("to_remove.txt");
-        fs::write(&file, "bye").unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+        fs::write(path("a.txt"), "\n\none\n\n\n").unwrap();
+        fs::write(path("b.txt"), "\ntwo\n").unwrap();
 
-        rm_callback(vec![], vec![file.to_str().unwrap().to_string()]);
-        assert!(!file.exists());
+        let cat = |flags: &[&str]| cat_output(flags, &[path("a.txt"), path("b.txt")]);
+        // The blank lines at the end of one file and the start of the next
+        // are one run
+        assert_eq!(cat(&["-s"]), "\none\n\ntwo\n");
+        assert_eq!(
+            cat(&["-s", "-n", "-E"]),
+            "     1\t$\n     2\tone$\n     3\t$\n     4\ttwo$\n"
+        );
+    }
 
-        let sub_dir = dir.path().join("sub");
-        fs::create_dir(&sub_dir).unwrap();
-        let res = rm_callback(vec![], vec![sub_dir.to_str().unwrap().to_string()]);
-        assert!(!res.stderr.is_empty()); // Should fail without -r
-        assert!(sub_dir.exists());
+    #[test]
+    fn test_cat_streams_chunks() {
+        let dir = tempdir().unwrap();
+        let file = dir.path().join("big.log");
+        // Lines straddle the chunk boundaries
+        let line = "0123456789abcdef\tline\n";
+        let text = line.repeat(3 * CAT_CHUNK / line.len() + 7);
+        fs::write(&file, &text).unwrap();
+        let path = file.to_str().unwrap().to_string();
 
-        rm_callback(
-            vec!["-r".to_string()],
-            vec![sub_dir.to_str().unwrap().to_string()],
+        assert_eq!(cat_output(&[], std::slice::from_ref(&path)), text);
+        let numbered = cat_output(&["-n", "-T"], &[path]);
+        let last = numbered.lines().last().unwrap();
+        assert_eq!(
+            last,
+            format!("{:>6}\t0123456789abcdef^Iline", text.lines().count())
         );
-        assert!(!sub_dir.exists());
     }
 
     #[test]
-    fn test_cat() {
+    fn test_mkdir_parents_mode_verbose() {
         let dir = tempdir().unwrap();
-        let file = dir.path().join("cat_test.txt");
-        fs::write(&file, "meow").unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+
+        let res = mkdir(&[&path("a/b/c")]);
+        assert!(
+            res.stderr.contains("No such file or directory"),
+            "{}",
+            res.stderr
+        );
+        assert!(!Path::new(&path("a")).exists());
 
-        let res = cat_callback(vec![], vec![file.to_str().unwrap().to_string()]);
-        assert_eq!(res.stdout, "meow");
+        let res = mkdir(&["-pv", &path("a/b")]);
+        assert_eq!(
+            res.stdout_text(),
+            format!(
+                "mkdir: created directory '{}'\nmkdir: created directory '{}'\n",
+                path("a"),
+                path("a/b")
+            )
+        );
+        // Existing directories are fine with -p, but not without
+        assert_eq!(mkdir(&["-p", &path("a/b")]).stderr, "");
+        let res = mkdir(&[&path("a/b")]);
+        assert!(res.stderr.contains("exists"), "{}", res.stderr);
+        fs::write(path("file"), "").unwrap();
+        let res = mkdir(&["--parents", &path("file")]);
+        assert!(res.stderr.contains("File exists"), "{}", res.stderr);
+
+        #[cfg(unix)]
+        {
+            let mode = |name: &str| fs::metadata(path(name)).unwrap().permissions().mode() & 0o7777;
+            let res = mkdir(&["-p", &path("x/y"), "-m", "go-rwx"]);
+            assert_eq!(res.stderr, "");
+            assert_eq!(mode("x/y"), 0o700);
+            assert_ne!(mode("x"), 0o700);
+            let res = mkdir(&["--mode=751", &path("z")]);
+            assert_eq!(res.stderr, "");
+            assert_eq!(mode("z"), 0o751);
+        }
+        let res = mkdir(&["-mrwx", &path("bad")]);
+        assert_eq!(res.stderr, "mkdir: invalid mode 'rwx'");
+        let res = mkdir(&[&path("bad"), "-m"]);
+        assert_eq!(
+            res.stderr,
+            "mkdir: option requires an argument -- 'm'\n\
+             Try 'help' or 'mkdir --help' for more information."
+        );
+        assert_eq!(res.status, 2);
     }
 
     #[test]
-    fn test_command_list_execute() {
-        let cmds = command_list();
+    fn test_cd_logical_paths() {
+        assert_eq!(
+            normalize_logical(Path::new("/home/u/link/./sub/../..")),
+            Path::new("/home/u")
+        );
+        assert_eq!(normalize_logical(Path::new("/../a/..")), Path::new("/"));
 
-        // Test help
-        let res = cmds.execute("help".to_string(), vec![], vec![]);
-        assert!(res.stdout.contains("Available commands"));
+        #[cfg(unix)]
+        {
+            let dir = tempdir().unwrap();
+            fs::create_dir_all(dir.path().join("real/sub")).unwrap();
+            std::os::unix::fs::symlink(dir.path().join("real/sub"), dir.path().join("link"))
+                .unwrap();
+            // `$PWD` may name the directory through the link, but not by
+            // where `..` would lead physically
+            assert!(is_same_dir(
+                &dir.path().join("link"),
+                &dir.path().join("real/sub")
+            ));
+            let up = normalize_logical(&dir.path().join("link/.."));
+            assert!(is_same_dir(&up, dir.path()));
+            assert!(!is_same_dir(&dir.path().join("link/.."), dir.path()));
+        }
+    }
 
-        // Test unrecognized
-        let res = cmds.execute("nope".to_string(), vec![], vec![]);
-        assert!(res.stderr.contains("command not found"));
+    #[test]
+    fn test_help_pages() {
+        let cmds = command_list();
+        let page = cmds.manual_page("cat").unwrap();
+        assert!(
+            page.starts_with("NAME\n    cat - concatenate files"),
+            "{}",
+            page
+        );
+        assert!(page.contains("\nOPTIONS\n    -n\n        Number all output lines.\n"));
+        // Commands without a manual still get their synopsis
+        let page = cmds.manual_page("tac").unwrap();
+        assert_eq!(
+            page,
+            "NAME\n    tac - print files with lines in reverse order\n\nSYNOPSIS\n    tac [FILE...]\n"
+        );
+        assert_eq!(cmds.manual_page("nope"), None);
 
-        // Test command help flag
-        let res = cmds.execute("ls".to_string(), vec!["-h".to_string()], vec![]);
-        assert!(res.stdout.contains("Usage: ls [-a] [-l] [-F] [FILE...]"));
+        let res = execute(&cmds, "help", vec![], vec!["nope".to_string()]);
+        assert_eq!(res.stderr, "help: no help topics match 'nope'");
+    }
 
-        // Test required args
-        let res = cmds.execute("mkdir".to_string(), vec![], vec![]);
-        assert!(res.stderr.contains("missing operand"));
+    #[test]
+    fn test_generated_usage_and_version() {
+        let cmds = command_list();
+        let res = execute(&cmds, "mkdir", vec!["--help".to_string()], vec![]);
+        assert!(
+            res.stdout_text().starts_with(
+                "Usage: mkdir [-p] [-v] [-m MODE] DIRECTORY...\nCreate directories.\n\nOptions:\n  -p, --parents "
+            ),
+            "{}",
+            res.stdout_text()
+        );
+        // Commands without a spec keep their free-form usage line
+        let res = execute(&cmds, "tac", vec!["--help".to_string()], vec![]);
+        assert_eq!(
+            res.stdout_text(),
+            "Usage: tac [FILE...] - print files with lines in reverse order\n"
+        );
+
+        let res = execute(&cmds, "cat", vec!["--version".to_string()], vec![]);
+        assert_eq!(
+            res.stdout_text(),
+            format!("cat (0-shell) {}\n", env!("CARGO_PKG_VERSION"))
+        );
+        let call = CommandCall::parse("xargs --version", &ShellState::default())
+            .unwrap()
+            .unwrap();
+        assert!(
+            cmds.run_detached(&ShellState::default(), call)
+                .stdout_text()
+                .starts_with("xargs (0-shell) ")
+        );
     }
 }
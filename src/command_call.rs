@@ -1,3 +1,5 @@
+use crate::command::{CommandError, ShellState};
+
 /// Represents a parsed command call with its name, flags, and arguments.
 ///
 /// A command call is generated from a single command segment (e.g., between semicolons).
@@ -11,44 +13,101 @@ pub struct CommandCall {
     pub flags: Vec<String>,
     /// Positional arguments for the command (e.g., file paths, text).
     pub args: Vec<String>,
+    /// Every token after the command name, in its original order.
+    /// Used by commands that run other commands (e.g. `xargs`) and must
+    /// leave the inner command's flags untouched.
+    pub raw_args: Vec<String>,
+    /// `NAME=value` assignments that preceded the command name. They apply
+    /// only while this command runs, or to the shell itself if `name` is empty.
+    pub env: Vec<(String, String)>,
 }
 
-impl CommandCall {}
+impl CommandCall {
+    /// Parses one command of a line, as [`split_line`] gives them: it is
+    /// tokenized, with support for quotes, escapes, and `$(( ))`, and its
+    /// flags are separated from its arguments. Expansions use the
+    /// variables and last exit status in `state`. Returns `None` if the
+    /// command is empty, and the error if an expansion fails.
+    pub fn parse(segment: &str, state: &ShellState) -> Result<Option<Self>, CommandError> {
+        Ok(Self::from_tokens(tokenize(segment, state)?))
+    }
 
-/// Parses a line of input into a sequence of command calls.
+    /// Builds a call from already tokenized words, the first being the
+    /// command name. Returns `None` if there are no tokens.
+    pub fn from_tokens(mut tokens: Vec<String>) -> Option<Self> {
+        if tokens.is_empty() {
+            return None;
+        }
+
+        // Leading NAME=value words are environment assignments
+        let assignments = tokens
+            .iter()
+            .take_while(|t| parse_assignment(t).is_some())
+            .count();
+        let env = tokens
+            .drain(..assignments)
+            .filter_map(|t| parse_assignment(&t))
+            .collect();
+        if tokens.is_empty() {
+            return Some(CommandCall {
+                name: String::new(),
+                flags: Vec::new(),
+                args: Vec::new(),
+                raw_args: Vec::new(),
+                env,
+            });
+        }
+
+        // The first remaining token is always the command name
+        let name = tokens.remove(0).to_lowercase();
+        let raw_args = tokens.clone();
+
+        // Separate remaining tokens into flags and positional arguments
+        let (flags, args) = separate_flags_from_args(tokens);
+
+        Some(CommandCall {
+            name,
+            flags,
+            args,
+            raw_args,
+            env,
+        })
+    }
+}
+
+/// Splits a `NAME=value` word, where NAME is a valid variable name
+/// (letters, digits, and underscores, not starting with a digit).
+fn parse_assignment(token: &str) -> Option<(String, String)> {
+    let (name, value) = token.split_once('=')?;
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    valid.then(|| (name.to_string(), value.to_string()))
+}
+
+/// Splits a line of input into the text of its commands, chained with
+/// semicolons (`;`), leaving out empty ones.
 ///
-/// This function handles:
-/// 1. Command chaining with semicolons (`;`).
-/// 2. Tokenization with support for quotes and escapes.
-/// 3. Separation of flags from positional arguments.
+/// Each is then parsed with [`CommandCall::parse`] just before it runs, so
+/// that expansions in a later command see variables set by an earlier one
+/// (`x=2; echo $((x * 3))`), and `$?` the status of the one before.
 ///
 /// # Example
 /// ```
-/// let calls = parse_line("ls -la; echo \"hello world\"");
+/// # use zero_shell::ShellState;
+/// # use zero_shell::command_call::{CommandCall, split_line};
+/// let state = ShellState::default();
+/// let calls: Vec<_> = split_line("ls -la; echo \"hello world\"")
+///     .filter_map(|segment| CommandCall::parse(segment, &state).unwrap())
+///     .collect();
 /// ```
-pub fn parse_line(input: &str) -> Vec<CommandCall> {
+pub fn split_line(input: &str) -> impl Iterator<Item = &str> {
     input
         .split(';') // Split by semicolon to support command chaining
-        .filter_map(|chunk| {
-            let chunk = chunk.trim();
-            if chunk.is_empty() {
-                return None;
-            }
-
-            let mut tokens = tokenize(chunk);
-            if tokens.is_empty() {
-                return None;
-            }
-
-            // The first token is always the command name
-            let name = tokens.remove(0).to_lowercase();
-
-            // Separate remaining tokens into flags and positional arguments
-            let (flags, args) = separate_flags_from_args(tokens);
-
-            Some(CommandCall { name, flags, args })
-        })
-        .collect()
+        .map(str::trim)
+        .filter(|segment| !segment.is_empty())
 }
 
 /// Separates command tokens into flags and positional arguments.
@@ -87,16 +146,29 @@ fn separate_flags_from_args(tokens: Vec<String>) -> (Vec<String>, Vec<String>) {
 /// - Single quotes (`'`): Everything inside is treated literally.
 /// - Double quotes (`"`): Supports backslash escaping for `"`, `\`, and `$`.
 /// - Backslash escapes (`\`): Outside of quotes, escapes any following character.
+/// - Arithmetic expansion (`$(( ))`): Outside single quotes, replaced by the
+///   value of the expression.
+/// - `$RANDOM` (or `${RANDOM}`): Outside single quotes, replaced by a random
+///   number from 0 to 32767.
+/// - `$?`: Outside single quotes, replaced by the exit status of the last
+///   command run at the prompt.
+///
+/// Variables in expansions come from `state`. An expression that cannot be
+/// evaluated is an error, and no tokens are returned.
 /// - Whitespace: Separates tokens unless escaped or quoted.
-pub fn tokenize(input: &str) -> Vec<String> {
+pub fn tokenize(input: &str, state: &ShellState) -> Result<Vec<String>, CommandError> {
     let mut tokens = Vec::new();
     let mut current = String::new();
     let mut in_single_quote = false;
     let mut in_double_quote = false;
     let mut escaped = false;
-    let mut chars = input.chars().peekable();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
 
-    while let Some(c) = chars.next() {
         if escaped {
             current.push_str(&handle_escape(c, in_double_quote));
             escaped = false;
@@ -104,6 +176,25 @@ pub fn tokenize(input: &str) -> Vec<String> {
         }
 
         match c {
+            // Arithmetic expansion; without a closing `))` the text stays literal
+            '$' if !in_single_quote && chars[i..].starts_with(&['(', '(']) => {
+                match arithmetic_end(&chars, i + 2) {
+                    Some(end) => {
+                        let expr: String = chars[i + 2..end].iter().collect();
+                        current.push_str(&expand_arithmetic(&expr, state)?);
+                        i = end + 2;
+                    }
+                    None => current.push(c),
+                }
+            }
+            // Variables the shell generates itself; other `$` text is literal
+            '$' if !in_single_quote => match special_variable(&chars[i..], state) {
+                Some((value, len)) => {
+                    current.push_str(&value);
+                    i += len;
+                }
+                None => current.push(c),
+            },
             // Enter/Exit escaping state (only outside single quotes)
             '\\' if !in_single_quote => {
                 escaped = true;
@@ -133,7 +224,62 @@ pub fn tokenize(input: &str) -> Vec<String> {
         tokens.push(current);
     }
 
-    tokens
+    Ok(tokens)
+}
+
+/// Finds the `))` closing an arithmetic expansion whose expression starts at
+/// `start`, returning the index of its first `)`.
+fn arithmetic_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (i, &c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '(' => depth += 1,
+            ')' if depth == 0 => return (chars.get(i + 1) == Some(&')')).then_some(i),
+            ')' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Reads the variable name after a `$`, as `NAME` or `{NAME}`, and returns
+/// its value and the number of characters used if the shell provides it.
+fn special_variable(chars: &[char], state: &ShellState) -> Option<(String, usize)> {
+    if chars.first() == Some(&'?') {
+        return Some((state.last_status.to_string(), 1));
+    }
+    let braced = chars.first() == Some(&'{');
+    let start = braced as usize;
+    let len = chars[start..]
+        .iter()
+        .take_while(|c| c.is_ascii_alphanumeric() || **c == '_')
+        .count();
+    let name: String = chars[start..start + len].iter().collect();
+    if braced && chars.get(start + len) != Some(&'}') {
+        return None;
+    }
+    let value = match name.as_str() {
+        "RANDOM" => state.rng.random().to_string(),
+        _ => return None,
+    };
+    Some((value, start + len + braced as usize))
+}
+
+/// Looks up a variable for arithmetic: `RANDOM` is generated by the shell,
+/// other names are the shell's variables.
+fn shell_variable(name: &str, state: &ShellState) -> Option<String> {
+    match name {
+        "RANDOM" => Some(state.rng.random().to_string()),
+        _ => state.vars.get(name),
+    }
+}
+
+/// Evaluates the body of `$(( ))` against the shell's variables. An error
+/// fails the command the expansion is in.
+fn expand_arithmetic(expr: &str, state: &ShellState) -> Result<String, CommandError> {
+    crate::expr::evaluate(expr, &|name| shell_variable(name, state))
+        .map(|value| value.to_string())
+        .map_err(|e| CommandError::Failed(format!("0-shell: {}: {}", expr.trim(), e)))
 }
 
 /// Logic for handling backslash escape sequences.
@@ -158,27 +304,40 @@ fn handle_escape(c: char, in_double_quote: bool) -> String {
 mod tests {
     use super::*;
 
+    /// Parses every command of a line, with `$?` as 0.
+    fn parse_line(input: &str) -> impl Iterator<Item = CommandCall> + '_ {
+        split_line(input).filter_map(|segment| CommandCall::parse(segment, &state(0)).unwrap())
+    }
+
+    /// A fresh shell state with `last_status` as given.
+    fn state(last_status: i32) -> ShellState {
+        ShellState {
+            last_status,
+            ..ShellState::default()
+        }
+    }
+
     #[test]
     fn test_tokenize_simple() {
-        let tokens = tokenize("ls -la /home");
+        let tokens = tokenize("ls -la /home", &state(0)).unwrap();
         assert_eq!(tokens, vec!["ls", "-la", "/home"]);
     }
 
     #[test]
     fn test_tokenize_quotes() {
-        let tokens = tokenize("echo \"hello world\" 'single quote'");
+        let tokens = tokenize("echo \"hello world\" 'single quote'", &state(0)).unwrap();
         assert_eq!(tokens, vec!["echo", "hello world", "single quote"]);
     }
 
     #[test]
     fn test_tokenize_escapes() {
-        let tokens = tokenize("echo \\\"hello\\ world\\\"");
+        let tokens = tokenize("echo \\\"hello\\ world\\\"", &state(0)).unwrap();
         assert_eq!(tokens, vec!["echo", "\"hello world\""]);
     }
 
     #[test]
     fn test_parse_line_chaining() {
-        let calls = parse_line("ls -l; echo hi");
+        let calls: Vec<_> = parse_line("ls -l; echo hi").collect();
         assert_eq!(calls.len(), 2);
         assert_eq!(calls[0].name, "ls");
         assert_eq!(calls[0].flags, vec!["-l"]);
@@ -188,14 +347,108 @@ mod tests {
 
     #[test]
     fn test_parse_line_flags_expansion() {
-        let calls = parse_line("ls -la /tmp");
+        let calls: Vec<_> = parse_line("ls -la /tmp").collect();
         assert_eq!(calls[0].flags, vec!["-l", "-a"]);
         assert_eq!(calls[0].args, vec!["/tmp"]);
     }
 
+    #[test]
+    fn test_parse_line_lone_dash_is_arg() {
+        let calls: Vec<_> = parse_line("cat -n header.txt - footer.txt").collect();
+        assert_eq!(calls[0].flags, vec!["-n"]);
+        assert_eq!(calls[0].args, vec!["header.txt", "-", "footer.txt"]);
+    }
+
     #[test]
     fn test_parse_line_long_flags() {
-        let calls = parse_line("ls --all /tmp");
+        let calls: Vec<_> = parse_line("ls --all /tmp").collect();
         assert_eq!(calls[0].flags, vec!["--all"]);
     }
+
+    #[test]
+    fn test_raw_args_keep_order() {
+        let calls: Vec<_> = parse_line("xargs -n 2 ls -l").collect();
+        assert_eq!(calls[0].flags, vec!["-n", "-l"]);
+        assert_eq!(calls[0].raw_args, vec!["-n", "2", "ls", "-l"]);
+    }
+
+    #[test]
+    fn test_parse_line_env_assignments() {
+        let calls: Vec<_> = parse_line("LANG=C TZ='Europe/Paris' ls -l a=b").collect();
+        assert_eq!(
+            calls[0].env,
+            vec![
+                ("LANG".to_string(), "C".to_string()),
+                ("TZ".to_string(), "Europe/Paris".to_string())
+            ]
+        );
+        assert_eq!(calls[0].name, "ls");
+        assert_eq!(calls[0].args, vec!["a=b"]);
+
+        let calls: Vec<_> = parse_line("FOO=1").collect();
+        assert_eq!(calls[0].name, "");
+        assert_eq!(calls[0].env.len(), 1);
+
+        let calls: Vec<_> = parse_line("1X=2 echo").collect();
+        assert_eq!(calls[0].name, "1x=2");
+    }
+
+    #[test]
+    fn test_tokenize_arithmetic() {
+        assert_eq!(
+            tokenize("echo $((1 + 2 * 3))", &state(0)).unwrap(),
+            vec!["echo", "7"]
+        );
+        assert_eq!(
+            tokenize("echo x$(( (1 + 1) * 2 ))y", &state(0)).unwrap(),
+            vec!["echo", "x4y"]
+        );
+        assert_eq!(
+            tokenize("echo \"$((10 / 4.0))\"", &state(0)).unwrap(),
+            vec!["echo", "2.5"]
+        );
+        assert_eq!(
+            tokenize("echo '$((1 + 2))'", &state(0)).unwrap(),
+            vec!["echo", "$((1 + 2))"]
+        );
+        assert_eq!(
+            tokenize("echo $((1 + 2)", &state(0)).unwrap(),
+            vec!["echo", "$((1", "+", "2)"]
+        );
+
+        let mut with_x = state(0);
+        with_x.vars.set("x", "2");
+        assert_eq!(
+            tokenize("echo $((x * 3))", &with_x).unwrap(),
+            vec!["echo", "6"]
+        );
+
+        let error = tokenize("echo $((1/0)) after", &state(0)).unwrap_err();
+        assert_eq!(error.to_string(), "0-shell: 1/0: division by 0");
+        assert_eq!(error.status(), 1);
+    }
+
+    #[test]
+    fn test_tokenize_random() {
+        let tokens = tokenize(
+            "echo $RANDOM ${RANDOM}x '$RANDOM' $RANDOMX $((RANDOM < 32768))",
+            &state(0),
+        )
+        .unwrap();
+        assert!(tokens[1].parse::<u16>().is_ok_and(|n| n < 32768));
+        assert!(tokens[2].ends_with('x') && tokens[2].len() > 1);
+        assert_eq!(tokens[3..], ["$RANDOM", "$RANDOMX", "1"]);
+    }
+
+    #[test]
+    fn test_tokenize_last_status() {
+        assert_eq!(
+            tokenize("echo $? '$?' \"$?\"", &state(3)).unwrap(),
+            ["echo", "3", "$?", "3"]
+        );
+        let calls: Vec<_> = split_line("false; echo $?")
+            .filter_map(|segment| CommandCall::parse(segment, &state(1)).unwrap())
+            .collect();
+        assert_eq!(calls[1].args, ["1"]);
+    }
 }
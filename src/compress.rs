@@ -0,0 +1,1115 @@
+//! DEFLATE (RFC 1951) compression and decompression, plus the gzip (RFC 1952)
+//! container format built on top of it.
+//!
+//! Everything here is implemented from scratch so that `gzip`/`gunzip` do not
+//! depend on any external library or binary. The CRC routines are also used
+//! by the `cksum` and `crc32` commands.
+
+use std::fmt;
+
+// ============================================================================
+// CRC-32
+// ============================================================================
+
+/// Lookup table for the reflected CRC-32 polynomial used by gzip and zip.
+const CRC32_TABLE: [u32; 256] = build_crc32_table();
+
+const fn build_crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xEDB8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Computes the CRC-32 checksum of `data`.
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Continues a CRC-32 computation started with a previous checksum.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// Lookup table for the non-reflected CRC-32 polynomial used by POSIX
+/// `cksum`.
+const CKSUM_TABLE: [u32; 256] = build_cksum_table();
+
+const fn build_cksum_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = (i as u32) << 24;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Feeds `data` into a POSIX `cksum` computation. Start from 0 and call
+/// [`cksum_finish`] with the total length once all data has been seen.
+pub fn cksum_update(mut crc: u32, data: &[u8]) -> u32 {
+    for &byte in data {
+        crc = (crc << 8) ^ CKSUM_TABLE[((crc >> 24) ^ byte as u32) as usize];
+    }
+    crc
+}
+
+/// Completes a POSIX `cksum` checksum by mixing in the input length.
+pub fn cksum_finish(mut crc: u32, len: u64) -> u32 {
+    let mut len = len;
+    while len > 0 {
+        crc = (crc << 8) ^ CKSUM_TABLE[((crc >> 24) ^ (len & 0xFF) as u32) as usize];
+        len >>= 8;
+    }
+    !crc
+}
+
+// ============================================================================
+// Errors
+// ============================================================================
+
+/// An error raised while decoding compressed data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError(pub String);
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+fn corrupt(msg: &str) -> DecodeError {
+    DecodeError(msg.to_string())
+}
+
+// ============================================================================
+// Shared tables
+// ============================================================================
+
+/// Base match lengths for length codes 257..=285.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+
+/// Extra bits for length codes 257..=285.
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distances for distance codes 0..=29.
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// Extra bits for distance codes 0..=29.
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which code length code lengths are transmitted.
+const CLEN_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const MAX_BITS: usize = 15;
+const WINDOW_SIZE: usize = 32 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+
+fn fixed_litlen_lengths() -> Vec<u8> {
+    let mut lengths = vec![0u8; 288];
+    for (i, len) in lengths.iter_mut().enumerate() {
+        *len = match i {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    lengths
+}
+
+fn fixed_dist_lengths() -> Vec<u8> {
+    vec![5u8; 30]
+}
+
+// ============================================================================
+// Inflate
+// ============================================================================
+
+/// Reads bits least-significant first, as required by DEFLATE.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    bit_buf: u32,
+    bit_count: u32,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn bits(&mut self, need: u32) -> Result<u32, DecodeError> {
+        while self.bit_count < need {
+            let byte = *self
+                .data
+                .get(self.pos)
+                .ok_or_else(|| corrupt("unexpected end of compressed data"))?;
+            self.pos += 1;
+            self.bit_buf |= (byte as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buf & ((1u32 << need) - 1);
+        self.bit_buf = self.bit_buf.checked_shr(need).unwrap_or(0);
+        self.bit_count -= need;
+        Ok(value)
+    }
+
+    /// Discards any bits left in the current byte.
+    fn align(&mut self) {
+        self.bit_buf = 0;
+        self.bit_count = 0;
+    }
+
+    /// Number of whole bytes consumed so far.
+    fn byte_pos(&self) -> usize {
+        self.pos
+    }
+}
+
+/// A canonical Huffman decoding table (symbol counts per length plus
+/// symbols sorted by code).
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Self, DecodeError> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        // Reject over-subscribed code sets
+        let mut left: i32 = 1;
+        for &count in counts.iter().skip(1) {
+            left <<= 1;
+            left -= count as i32;
+            if left < 0 {
+                return Err(corrupt("invalid huffman code lengths"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, reader: &mut BitReader) -> Result<u16, DecodeError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for len in 1..=MAX_BITS {
+            code |= reader.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - count < first {
+                return Ok(self.symbols[(index + (code - first)) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(corrupt("invalid huffman code"))
+    }
+}
+
+/// Decompresses a raw DEFLATE stream, also returning the number of input
+/// bytes the stream occupied.
+pub fn inflate_with_len(data: &[u8]) -> Result<(Vec<u8>, usize), DecodeError> {
+    let mut reader = BitReader::new(data);
+    let mut out = Vec::new();
+
+    loop {
+        let last = reader.bits(1)? == 1;
+        match reader.bits(2)? {
+            0 => inflate_stored(&mut reader, &mut out)?,
+            1 => {
+                let lit = Huffman::new(&fixed_litlen_lengths())?;
+                let dist = Huffman::new(&fixed_dist_lengths())?;
+                inflate_codes(&mut reader, &mut out, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = read_dynamic_tables(&mut reader)?;
+                inflate_codes(&mut reader, &mut out, &lit, &dist)?;
+            }
+            _ => return Err(corrupt("invalid block type")),
+        }
+        if last {
+            break;
+        }
+    }
+
+    Ok((out, reader.byte_pos()))
+}
+
+fn inflate_stored(reader: &mut BitReader, out: &mut Vec<u8>) -> Result<(), DecodeError> {
+    reader.align();
+    let start = reader.pos;
+    let header = reader
+        .data
+        .get(start..start + 4)
+        .ok_or_else(|| corrupt("unexpected end of compressed data"))?;
+    let len = u16::from_le_bytes([header[0], header[1]]);
+    let nlen = u16::from_le_bytes([header[2], header[3]]);
+    if len != !nlen {
+        return Err(corrupt("stored block length mismatch"));
+    }
+    let body_start = start + 4;
+    let body = reader
+        .data
+        .get(body_start..body_start + len as usize)
+        .ok_or_else(|| corrupt("unexpected end of compressed data"))?;
+    out.extend_from_slice(body);
+    reader.pos = body_start + len as usize;
+    Ok(())
+}
+
+fn read_dynamic_tables(reader: &mut BitReader) -> Result<(Huffman, Huffman), DecodeError> {
+    let nlen = reader.bits(5)? as usize + 257;
+    let ndist = reader.bits(5)? as usize + 1;
+    let ncode = reader.bits(4)? as usize + 4;
+    if nlen > 286 || ndist > 30 {
+        return Err(corrupt("too many length or distance codes"));
+    }
+
+    let mut clen_lengths = [0u8; 19];
+    for &slot in CLEN_ORDER.iter().take(ncode) {
+        clen_lengths[slot] = reader.bits(3)? as u8;
+    }
+    let clen = Huffman::new(&clen_lengths)?;
+
+    let mut lengths = vec![0u8; nlen + ndist];
+    let mut i = 0;
+    while i < nlen + ndist {
+        let symbol = clen.decode(reader)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => {
+                if i == 0 {
+                    return Err(corrupt("repeat with no previous length"));
+                }
+                (lengths[i - 1], 3 + reader.bits(2)? as usize)
+            }
+            17 => (0, 3 + reader.bits(3)? as usize),
+            _ => (0, 11 + reader.bits(7)? as usize),
+        };
+        if i + repeat > nlen + ndist {
+            return Err(corrupt("too many code lengths"));
+        }
+        for slot in &mut lengths[i..i + repeat] {
+            *slot = value;
+        }
+        i += repeat;
+    }
+
+    if lengths[256] == 0 {
+        return Err(corrupt("missing end-of-block code"));
+    }
+
+    let lit = Huffman::new(&lengths[..nlen])?;
+    let dist = Huffman::new(&lengths[nlen..])?;
+    Ok((lit, dist))
+}
+
+fn inflate_codes(
+    reader: &mut BitReader,
+    out: &mut Vec<u8>,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> Result<(), DecodeError> {
+    loop {
+        let symbol = lit.decode(reader)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                if code >= LENGTH_BASE.len() {
+                    return Err(corrupt("invalid length code"));
+                }
+                let length =
+                    LENGTH_BASE[code] as usize + reader.bits(LENGTH_EXTRA[code] as u32)? as usize;
+
+                let dcode = dist.decode(reader)? as usize;
+                if dcode >= DIST_BASE.len() {
+                    return Err(corrupt("invalid distance code"));
+                }
+                let distance =
+                    DIST_BASE[dcode] as usize + reader.bits(DIST_EXTRA[dcode] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(corrupt("distance too far back"));
+                }
+
+                let start = out.len() - distance;
+                for k in 0..length {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+// ============================================================================
+// Deflate
+// ============================================================================
+
+/// Writes bits least-significant first.
+struct BitWriter {
+    out: Vec<u8>,
+    bit_buf: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    fn new() -> Self {
+        Self {
+            out: Vec::new(),
+            bit_buf: 0,
+            bit_count: 0,
+        }
+    }
+
+    fn write(&mut self, value: u32, count: u32) {
+        self.bit_buf |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Writes a Huffman code, which must be emitted most-significant bit first.
+    fn write_code(&mut self, code: u16, len: u8) {
+        let reversed = (code.reverse_bits() >> (16 - len as u32)) as u32;
+        self.write(reversed, len as u32);
+    }
+
+    fn align(&mut self) {
+        if self.bit_count > 0 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf = 0;
+            self.bit_count = 0;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.out
+    }
+}
+
+/// A single LZ77 output symbol.
+#[derive(Clone, Copy)]
+enum Token {
+    Literal(u8),
+    Match { length: u16, distance: u16 },
+}
+
+fn length_code(length: usize) -> usize {
+    LENGTH_BASE
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap_or(0)
+}
+
+fn dist_code(distance: usize) -> usize {
+    DIST_BASE
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap_or(0)
+}
+
+/// Finds LZ77 matches using hash chains over a sliding 32K window.
+fn lz77(data: &[u8], max_chain: usize) -> Vec<Token> {
+    lz77_from(data, 0, max_chain)
+}
+
+/// Finds LZ77 matches for `data[start..]`, which may refer back to the
+/// history in `data[..start]`.
+fn lz77_from(data: &[u8], start: usize, max_chain: usize) -> Vec<Token> {
+    const HASH_BITS: usize = 15;
+    const HASH_SIZE: usize = 1 << HASH_BITS;
+
+    let mut tokens = Vec::with_capacity((data.len() - start) / 2);
+    if max_chain == 0 {
+        tokens.extend(data[start..].iter().map(|&b| Token::Literal(b)));
+        return tokens;
+    }
+
+    let hash = |pos: usize| -> usize {
+        let value =
+            (data[pos] as usize) << 16 | (data[pos + 1] as usize) << 8 | data[pos + 2] as usize;
+        (value.wrapping_mul(2_654_435_761) >> 8) & (HASH_SIZE - 1)
+    };
+
+    let mut head = vec![usize::MAX; HASH_SIZE];
+    let mut prev = vec![usize::MAX; data.len()];
+    let insert = |pos: usize, head: &mut Vec<usize>, prev: &mut Vec<usize>| {
+        if pos + MIN_MATCH <= data.len() {
+            let h = hash(pos);
+            prev[pos] = head[h];
+            head[h] = pos;
+        }
+    };
+
+    for pos in 0..start {
+        insert(pos, &mut head, &mut prev);
+    }
+    let mut pos = start;
+    while pos < data.len() {
+        let mut best_len = 0;
+        let mut best_dist = 0;
+
+        if pos + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(pos)];
+            let max_len = MAX_MATCH.min(data.len() - pos);
+            let mut chain = max_chain;
+            while candidate != usize::MAX && pos - candidate <= WINDOW_SIZE && chain > 0 {
+                let len = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_len])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - candidate;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                candidate = prev[candidate];
+                chain -= 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            tokens.push(Token::Match {
+                length: best_len as u16,
+                distance: best_dist as u16,
+            });
+            for p in pos..pos + best_len {
+                insert(p, &mut head, &mut prev);
+            }
+            pos += best_len;
+        } else {
+            tokens.push(Token::Literal(data[pos]));
+            insert(pos, &mut head, &mut prev);
+            pos += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Builds Huffman code lengths for the given symbol frequencies, limited to
+/// `max_len` bits. Frequencies are flattened and the tree rebuilt until the
+/// limit is satisfied.
+fn build_lengths(freqs: &[u32], max_len: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    loop {
+        let lengths = huffman_lengths(&freqs);
+        if lengths.iter().all(|&len| len <= max_len) {
+            return lengths;
+        }
+        for f in freqs.iter_mut().filter(|f| **f > 0) {
+            *f = (*f >> 1) | 1;
+        }
+    }
+}
+
+/// Computes unrestricted Huffman code lengths using a simple two-queue merge.
+fn huffman_lengths(freqs: &[u32]) -> Vec<u8> {
+    let mut lengths = vec![0u8; freqs.len()];
+    let mut symbols: Vec<usize> = (0..freqs.len()).filter(|&i| freqs[i] > 0).collect();
+
+    match symbols.len() {
+        0 => return lengths,
+        1 => {
+            lengths[symbols[0]] = 1;
+            return lengths;
+        }
+        _ => {}
+    }
+
+    symbols.sort_by_key(|&i| freqs[i]);
+
+    // Nodes: leaves first, then internal nodes appended as they are merged
+    let mut weight: Vec<u64> = symbols.iter().map(|&i| freqs[i] as u64).collect();
+    let mut parent: Vec<usize> = vec![usize::MAX; symbols.len()];
+    let mut leaf_next = 0;
+    let mut node_next = symbols.len();
+
+    let take = |weight: &Vec<u64>, leaf_next: &mut usize, node_next: &mut usize| -> usize {
+        let leaf_ok = *leaf_next < symbols.len();
+        let node_ok = *node_next < weight.len();
+        if leaf_ok && (!node_ok || weight[*leaf_next] <= weight[*node_next]) {
+            *leaf_next += 1;
+            *leaf_next - 1
+        } else {
+            *node_next += 1;
+            *node_next - 1
+        }
+    };
+
+    for _ in 0..symbols.len() - 1 {
+        let a = take(&weight, &mut leaf_next, &mut node_next);
+        let b = take(&weight, &mut leaf_next, &mut node_next);
+        let id = weight.len();
+        weight.push(weight[a] + weight[b]);
+        parent.push(usize::MAX);
+        parent[a] = id;
+        parent[b] = id;
+    }
+
+    let mut depth = vec![0u32; weight.len()];
+    for node in (0..weight.len() - 1).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+    for (leaf, &symbol) in symbols.iter().enumerate() {
+        lengths[symbol] = depth[leaf].min(u8::MAX as u32) as u8;
+    }
+    lengths
+}
+
+/// Assigns canonical codes to a set of code lengths.
+fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let mut count = [0u16; MAX_BITS + 1];
+    for &len in lengths {
+        count[len as usize] += 1;
+    }
+    count[0] = 0;
+
+    let mut next = [0u16; MAX_BITS + 2];
+    let mut code = 0u16;
+    for bits in 1..=MAX_BITS {
+        code = (code + count[bits - 1]) << 1;
+        next[bits] = code;
+    }
+
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                0
+            } else {
+                let c = next[len as usize];
+                next[len as usize] += 1;
+                c
+            }
+        })
+        .collect()
+}
+
+/// Run-length encodes the concatenated literal/distance code lengths using
+/// the code length alphabet (symbols 0..=18).
+fn rle_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let mut run = 1;
+        while i + run < lengths.len() && lengths[i + run] == value {
+            run += 1;
+        }
+        i += run;
+
+        if value == 0 {
+            while run >= 11 {
+                let n = run.min(138);
+                out.push((18, (n - 11) as u8));
+                run -= n;
+            }
+            if run >= 3 {
+                out.push((17, (run - 3) as u8));
+                run = 0;
+            }
+        } else {
+            out.push((value, 0));
+            run -= 1;
+            while run >= 3 {
+                let n = run.min(6);
+                out.push((16, (n - 3) as u8));
+                run -= n;
+            }
+        }
+        for _ in 0..run {
+            out.push((value, 0));
+        }
+    }
+    out
+}
+
+/// Writes a block of tokens with the given literal and distance tables.
+fn write_tokens(writer: &mut BitWriter, tokens: &[Token], lit_lengths: &[u8], dist_lengths: &[u8]) {
+    let lit_codes = canonical_codes(lit_lengths);
+    let dist_codes = canonical_codes(dist_lengths);
+
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => {
+                writer.write_code(lit_codes[byte as usize], lit_lengths[byte as usize]);
+            }
+            Token::Match { length, distance } => {
+                let lc = length_code(length as usize);
+                writer.write_code(lit_codes[257 + lc], lit_lengths[257 + lc]);
+                writer.write((length - LENGTH_BASE[lc]) as u32, LENGTH_EXTRA[lc] as u32);
+                let dc = dist_code(distance as usize);
+                writer.write_code(dist_codes[dc], dist_lengths[dc]);
+                writer.write((distance - DIST_BASE[dc]) as u32, DIST_EXTRA[dc] as u32);
+            }
+        }
+    }
+    writer.write_code(lit_codes[256], lit_lengths[256]);
+}
+
+/// Estimates the size in bits of encoding `tokens` with the given tables.
+fn token_cost(tokens: &[Token], lit_lengths: &[u8], dist_lengths: &[u8]) -> u64 {
+    let mut bits = lit_lengths[256] as u64;
+    for token in tokens {
+        bits += match *token {
+            Token::Literal(byte) => lit_lengths[byte as usize] as u64,
+            Token::Match { length, distance } => {
+                let lc = length_code(length as usize);
+                let dc = dist_code(distance as usize);
+                (lit_lengths[257 + lc] + LENGTH_EXTRA[lc] + dist_lengths[dc] + DIST_EXTRA[dc])
+                    as u64
+            }
+        };
+    }
+    bits
+}
+
+/// Emits one block, choosing whichever of stored, fixed, or dynamic Huffman
+/// encoding is smallest.
+fn write_block(writer: &mut BitWriter, raw: &[u8], tokens: &[Token], last: bool) {
+    let mut lit_freq = vec![0u32; 286];
+    let mut dist_freq = vec![0u32; 30];
+    lit_freq[256] = 1;
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => lit_freq[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                lit_freq[257 + length_code(length as usize)] += 1;
+                dist_freq[dist_code(distance as usize)] += 1;
+            }
+        }
+    }
+
+    let lit_lengths = build_lengths(&lit_freq, MAX_BITS as u8);
+    let mut dist_lengths = build_lengths(&dist_freq, MAX_BITS as u8);
+    if dist_lengths.iter().all(|&len| len == 0) {
+        // At least one distance code must be present
+        dist_lengths[0] = 1;
+    }
+
+    let nlit = lit_lengths
+        .iter()
+        .rposition(|&l| l != 0)
+        .map_or(257, |p| p + 1)
+        .max(257);
+    let ndist = dist_lengths
+        .iter()
+        .rposition(|&l| l != 0)
+        .map_or(1, |p| p + 1);
+
+    let mut combined = lit_lengths[..nlit].to_vec();
+    combined.extend_from_slice(&dist_lengths[..ndist]);
+    let rle = rle_lengths(&combined);
+
+    let mut clen_freq = vec![0u32; 19];
+    for &(symbol, _) in &rle {
+        clen_freq[symbol as usize] += 1;
+    }
+    let clen_lengths = build_lengths(&clen_freq, 7);
+    let nclen = CLEN_ORDER
+        .iter()
+        .rposition(|&slot| clen_lengths[slot] != 0)
+        .map_or(4, |p| p + 1)
+        .max(4);
+
+    let mut dynamic_bits = 5 + 5 + 4 + 3 * nclen as u64;
+    for &(symbol, _) in &rle {
+        dynamic_bits += clen_lengths[symbol as usize] as u64
+            + match symbol {
+                16 => 2,
+                17 => 3,
+                18 => 7,
+                _ => 0,
+            };
+    }
+    dynamic_bits += token_cost(tokens, &lit_lengths, &dist_lengths);
+
+    let fixed_lit = fixed_litlen_lengths();
+    let fixed_dist = fixed_dist_lengths();
+    let fixed_bits = token_cost(tokens, &fixed_lit, &fixed_dist);
+
+    // Stored blocks hold at most 65535 bytes each and are byte aligned
+    let stored_bits = (raw.len() as u64 + 5 * raw.len().div_ceil(0xFFFF).max(1) as u64) * 8 + 7;
+
+    if stored_bits <= dynamic_bits && stored_bits <= fixed_bits {
+        let chunks: Vec<&[u8]> = if raw.is_empty() {
+            vec![&[]]
+        } else {
+            raw.chunks(0xFFFF).collect()
+        };
+        let count = chunks.len();
+        for (i, chunk) in chunks.into_iter().enumerate() {
+            writer.write((last && i + 1 == count) as u32, 1);
+            writer.write(0, 2);
+            writer.align();
+            let len = chunk.len() as u16;
+            writer.out.extend_from_slice(&len.to_le_bytes());
+            writer.out.extend_from_slice(&(!len).to_le_bytes());
+            writer.out.extend_from_slice(chunk);
+        }
+    } else if fixed_bits <= dynamic_bits {
+        writer.write(last as u32, 1);
+        writer.write(1, 2);
+        write_tokens(writer, tokens, &fixed_lit, &fixed_dist);
+    } else {
+        writer.write(last as u32, 1);
+        writer.write(2, 2);
+        writer.write((nlit - 257) as u32, 5);
+        writer.write((ndist - 1) as u32, 5);
+        writer.write((nclen - 4) as u32, 4);
+        for &slot in CLEN_ORDER.iter().take(nclen) {
+            writer.write(clen_lengths[slot] as u32, 3);
+        }
+        let clen_codes = canonical_codes(&clen_lengths);
+        for &(symbol, extra) in &rle {
+            writer.write_code(clen_codes[symbol as usize], clen_lengths[symbol as usize]);
+            match symbol {
+                16 => writer.write(extra as u32, 2),
+                17 => writer.write(extra as u32, 3),
+                18 => writer.write(extra as u32, 7),
+                _ => {}
+            }
+        }
+        write_tokens(writer, tokens, &lit_lengths, &dist_lengths);
+    }
+}
+
+/// Compresses `data` into a raw DEFLATE stream.
+///
+/// `level` ranges from 0 (store only) to 9 (slowest, best compression) and
+/// controls how far the match finder searches.
+pub fn deflate(data: &[u8], level: u32) -> Vec<u8> {
+    let tokens = lz77(data, max_chain(level));
+    let mut writer = BitWriter::new();
+
+    if tokens.is_empty() {
+        write_block(&mut writer, &[], &[], true);
+        return writer.finish();
+    }
+    write_blocks(&mut writer, data, &tokens, true);
+    writer.finish()
+}
+
+/// How far the match finder searches at compression `level`.
+fn max_chain(level: u32) -> usize {
+    match level {
+        0 => 0,
+        1..=3 => 8 << level,
+        4..=6 => 32 << (level - 3),
+        _ => 4096,
+    }
+}
+
+/// Emits `tokens`, which encode `data`, as blocks of a bounded size. With
+/// `last`, the final block ends the stream.
+fn write_blocks(writer: &mut BitWriter, data: &[u8], tokens: &[Token], last: bool) {
+    const BLOCK_TOKENS: usize = 1 << 16;
+
+    let mut raw_pos = 0;
+    let chunks: Vec<&[Token]> = tokens.chunks(BLOCK_TOKENS).collect();
+    let count = chunks.len();
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        let raw_len: usize = chunk
+            .iter()
+            .map(|t| match *t {
+                Token::Literal(_) => 1,
+                Token::Match { length, .. } => length as usize,
+            })
+            .sum();
+        write_block(
+            writer,
+            &data[raw_pos..raw_pos + raw_len],
+            chunk,
+            last && i + 1 == count,
+        );
+        raw_pos += raw_len;
+    }
+}
+
+// ============================================================================
+// gzip container
+// ============================================================================
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const FLAG_HCRC: u8 = 0x02;
+const FLAG_EXTRA: u8 = 0x04;
+const FLAG_NAME: u8 = 0x08;
+const FLAG_COMMENT: u8 = 0x10;
+
+/// The header of a gzip member.
+fn gzip_header(level: u32, name: Option<&str>, mtime: u32) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(&GZIP_MAGIC);
+    out.push(8); // CM = deflate
+    out.push(if name.is_some() { FLAG_NAME } else { 0 });
+    out.extend_from_slice(&mtime.to_le_bytes());
+    out.push(match level {
+        9 => 2,
+        1 => 4,
+        _ => 0,
+    });
+    out.push(3); // OS = Unix
+    if let Some(name) = name {
+        out.extend_from_slice(name.as_bytes());
+        out.push(0);
+    }
+    out
+}
+
+/// Compresses data into a single gzip member a piece at a time, so input of
+/// any size is compressed in bounded memory. Each piece becomes blocks of
+/// its own, with matches reaching back into the pieces before.
+pub struct GzipEncoder {
+    writer: BitWriter,
+    max_chain: usize,
+    /// The last 32K of input, which matches may refer back to.
+    window: Vec<u8>,
+    crc: u32,
+    size: u32,
+}
+
+impl GzipEncoder {
+    /// Starts a member. `name` is stored as the original file name and
+    /// `mtime` as the modification time in seconds since the epoch (0 when
+    /// unknown).
+    pub fn new(level: u32, name: Option<&str>, mtime: u32) -> Self {
+        let mut writer = BitWriter::new();
+        writer.out = gzip_header(level, name, mtime);
+        Self {
+            writer,
+            max_chain: max_chain(level),
+            window: Vec::new(),
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    /// Compresses `data`, appending the bytes finished so far to `out`.
+    pub fn update(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        if !data.is_empty() {
+            let start = self.window.len();
+            self.window.extend_from_slice(data);
+            let tokens = lz77_from(&self.window, start, self.max_chain);
+            write_blocks(&mut self.writer, data, &tokens, false);
+            let keep = self.window.len().saturating_sub(WINDOW_SIZE);
+            self.window.drain(..keep);
+            self.crc = crc32_update(self.crc, data);
+            // ISIZE is the input size modulo 2^32
+            self.size = self.size.wrapping_add(data.len() as u32);
+        }
+        out.append(&mut self.writer.out);
+    }
+
+    /// Ends the member, appending the rest of it to `out`.
+    pub fn finish(mut self, out: &mut Vec<u8>) {
+        write_block(&mut self.writer, &[], &[], true);
+        out.extend_from_slice(&self.writer.finish());
+        out.extend_from_slice(&self.crc.to_le_bytes());
+        out.extend_from_slice(&self.size.to_le_bytes());
+    }
+}
+
+/// Decodes every gzip member in `data`, verifying checksums and sizes.
+pub fn gzip_decode(data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+
+    loop {
+        let rest = &data[pos..];
+        if rest.len() < 18 || rest[..2] != GZIP_MAGIC {
+            if pos == 0 {
+                return Err(corrupt("not in gzip format"));
+            }
+            // Trailing garbage after a valid member is ignored, as in gzip
+            break;
+        }
+        if rest[2] != 8 {
+            return Err(corrupt("unknown compression method"));
+        }
+
+        let flags = rest[3];
+        let mut offset = 10;
+        let header_err = || corrupt("unexpected end of file");
+        if flags & FLAG_EXTRA != 0 {
+            let xlen = rest.get(offset..offset + 2).ok_or_else(header_err)?;
+            offset += 2 + u16::from_le_bytes([xlen[0], xlen[1]]) as usize;
+        }
+        for flag in [FLAG_NAME, FLAG_COMMENT] {
+            if flags & flag != 0 {
+                let end = rest
+                    .get(offset..)
+                    .and_then(|r| r.iter().position(|&b| b == 0))
+                    .ok_or_else(header_err)?;
+                offset += end + 1;
+            }
+        }
+        if flags & FLAG_HCRC != 0 {
+            offset += 2;
+        }
+
+        let body = rest.get(offset..).ok_or_else(header_err)?;
+        let (member, used) = inflate_with_len(body)?;
+        let trailer = body
+            .get(used..used + 8)
+            .ok_or_else(|| corrupt("unexpected end of file"))?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        if crc != crc32(&member) {
+            return Err(corrupt("invalid compressed data--crc error"));
+        }
+        if size != member.len() as u32 {
+            return Err(corrupt("invalid compressed data--length error"));
+        }
+
+        out.extend_from_slice(&member);
+        pos += offset + used + 8;
+        if pos >= data.len() {
+            break;
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_known_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn test_deflate_roundtrip() {
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(200);
+        let mut binary: Vec<u8> = (0..70_000u32).map(|i| (i * 7919 % 251) as u8).collect();
+        binary.extend_from_slice(text.as_bytes());
+
+        for data in [&b""[..], b"a", text.as_bytes(), &binary] {
+            for level in [0, 1, 6, 9] {
+                let packed = deflate(data, level);
+                assert_eq!(inflate_with_len(&packed).unwrap().0, data);
+            }
+        }
+
+        let packed = deflate(text.as_bytes(), 6);
+        assert!(packed.len() < text.len() / 10);
+    }
+
+    #[test]
+    fn test_gzip_roundtrip_and_corruption() {
+        let data = b"hello gzip hello gzip hello gzip\n";
+        let mut encoder = GzipEncoder::new(6, Some("hello.txt"), 0);
+        let mut packed = Vec::new();
+        encoder.update(data, &mut packed);
+        encoder.finish(&mut packed);
+        assert_eq!(gzip_decode(&packed).unwrap(), data);
+
+        // Concatenated members decode to concatenated data
+        let mut twice = packed.clone();
+        twice.extend_from_slice(&packed);
+        assert_eq!(
+            gzip_decode(&twice).unwrap(),
+            [&data[..], &data[..]].concat()
+        );
+
+        // Compressed a piece at a time, matches still reach across pieces
+        let text = "the quick brown fox jumps over the lazy dog. ".repeat(2000);
+        for level in [0, 6] {
+            let mut encoder = GzipEncoder::new(level, None, 0);
+            let mut streamed = Vec::new();
+            for piece in text.as_bytes().chunks(1000) {
+                encoder.update(piece, &mut streamed);
+            }
+            encoder.finish(&mut streamed);
+            assert_eq!(gzip_decode(&streamed).unwrap(), text.as_bytes());
+            if level > 0 {
+                assert!(streamed.len() < text.len() / 10);
+            }
+        }
+        let mut empty = Vec::new();
+        GzipEncoder::new(6, None, 0).finish(&mut empty);
+        assert_eq!(gzip_decode(&empty).unwrap(), b"");
+
+        let crc_pos = packed.len() - 8;
+        packed[crc_pos] ^= 0xFF;
+        assert!(gzip_decode(&packed).is_err());
+        assert!(gzip_decode(b"plain text").is_err());
+    }
+}
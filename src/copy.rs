@@ -0,0 +1,963 @@
+//! File copying behind `cp`, and behind `mv` when a file must move between
+//! filesystems.
+//!
+//! A copy walks the source tree, recreating directories, copying regular
+//! files, and either recreating symbolic links as links or copying what they
+//! point to, as [`Symlinks`] says. Errors are collected in a [`Report`] so
+//! that one unreadable file does not stop the rest.
+
+use std::fs::{self, Metadata};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Whether symbolic links are followed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Symlinks {
+    /// Follow links named as sources unless copying recursively, and copy
+    /// links inside directories as links, like GNU `cp`.
+    #[default]
+    Auto,
+    /// Always copy the file a link points to (`-L`).
+    Follow,
+    /// Always copy links as links (`-P`, `-d`).
+    Preserve,
+}
+
+/// Whether a file's copy shares its data blocks with it, on filesystems
+/// such as Btrfs and XFS that can do so.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Reflink {
+    /// Share blocks where possible and copy the data otherwise.
+    #[default]
+    Auto,
+    /// Share blocks or fail.
+    Always,
+    /// Always copy the data.
+    Never,
+}
+
+/// Which runs of zeros in a copy are left as holes that take no space.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Sparse {
+    /// Keep the holes the source file has.
+    #[default]
+    Auto,
+    /// Also make a hole of every block of zeros.
+    Always,
+    /// Write every byte out.
+    Never,
+}
+
+/// How much is copied between redraws of the progress bar.
+const COPY_CHUNK: usize = 8 << 20;
+
+/// When and how to show a progress bar while a file is copied.
+#[derive(Debug, Clone, Copy)]
+pub struct Progress {
+    /// Files smaller than this are copied without one.
+    pub min_size: u64,
+    /// Formats the bar from the bytes copied so far, the size of the file,
+    /// and the time taken so far.
+    pub line: fn(u64, u64, Duration) -> String,
+}
+
+/// How to copy.
+#[derive(Debug, Default, Clone)]
+pub struct Options {
+    /// Copy directories and everything in them.
+    pub recursive: bool,
+    pub symlinks: Symlinks,
+    /// Give each copy the permissions and timestamps of its source, and its
+    /// owner too when running as root.
+    pub preserve: bool,
+    /// Leave files that already exist at the destination alone.
+    pub no_clobber: bool,
+    /// Only replace files at the destination that are older than their
+    /// source.
+    pub update: bool,
+    /// Asked before a file at the destination is replaced, with its path;
+    /// it is kept unless this returns true.
+    pub confirm: Option<fn(&Path) -> bool>,
+    pub reflink: Reflink,
+    pub sparse: Sparse,
+    /// Shown on stderr while large files are copied.
+    pub progress: Option<Progress>,
+}
+
+/// What a copy did, and what went wrong.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Each file, link, and directory created or replaced, with its copy,
+    /// in the order they were made.
+    pub copied: Vec<(PathBuf, PathBuf)>,
+    /// One message per failure, naming the file involved.
+    pub errors: Vec<String>,
+}
+
+impl Report {
+    fn error(&mut self, message: String) {
+        self.errors.push(message);
+    }
+}
+
+/// Copies `src` to `dest`, the path the copy is to have. A progress bar,
+/// if any, is drawn on `stderr`.
+pub fn copy(src: &Path, dest: &Path, opts: &Options, stderr: &mut dyn Write, report: &mut Report) {
+    let follow = match opts.symlinks {
+        Symlinks::Auto => !opts.recursive,
+        Symlinks::Follow => true,
+        Symlinks::Preserve => false,
+    };
+    let metadata = if follow {
+        fs::metadata(src)
+    } else {
+        fs::symlink_metadata(src)
+    };
+    let metadata = match metadata {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            report.error(format!("cannot stat '{}': {}", src.display(), e));
+            return;
+        }
+    };
+
+    if metadata.is_dir() {
+        if !opts.recursive {
+            report.error(format!(
+                "-r not specified; omitting directory '{}'",
+                src.display()
+            ));
+            return;
+        }
+        if is_inside(dest, src) {
+            report.error(format!(
+                "cannot copy a directory, '{}', into itself, '{}'",
+                src.display(),
+                dest.display()
+            ));
+            return;
+        }
+    }
+    copy_entry(src, dest, &metadata, opts, stderr, report);
+}
+
+/// Moves `src` to `dest` by copying it, links as links and with its
+/// metadata, then removing it. This is how `mv` moves files between
+/// filesystems, where they cannot be renamed. The source is only removed if
+/// all of it was copied.
+pub fn move_by_copying(src: &Path, dest: &Path, report: &mut Report) {
+    let opts = Options {
+        recursive: true,
+        symlinks: Symlinks::Preserve,
+        preserve: true,
+        ..Options::default()
+    };
+    let errors = report.errors.len();
+    copy(src, dest, &opts, &mut io::sink(), report);
+    if report.errors.len() > errors {
+        return;
+    }
+
+    let removed = match fs::symlink_metadata(src) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(src),
+        Ok(_) => fs::remove_file(src),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = removed {
+        report.error(format!("cannot remove '{}': {}", src.display(), e));
+    }
+}
+
+/// Copies one file, link, or directory tree, described by `metadata`.
+fn copy_entry(
+    src: &Path,
+    dest: &Path,
+    metadata: &Metadata,
+    opts: &Options,
+    stderr: &mut dyn Write,
+    report: &mut Report,
+) {
+    let file_type = metadata.file_type();
+    // Directories are merged rather than replaced, so they are never asked
+    // about
+    if !file_type.is_dir()
+        && let Ok(existing) = fs::symlink_metadata(dest)
+    {
+        if opts.no_clobber || (opts.update && !is_newer(metadata, &existing)) {
+            return;
+        }
+        if let Some(confirm) = opts.confirm
+            && !confirm(dest)
+        {
+            return;
+        }
+    }
+    let copied = if file_type.is_dir() {
+        copy_dir(src, dest, opts, stderr, report)
+    } else if file_type.is_symlink() {
+        copy_symlink(src, dest)
+            .map_err(|e| {
+                report.error(format!(
+                    "cannot create symbolic link '{}': {}",
+                    dest.display(),
+                    e
+                ))
+            })
+            .is_ok()
+    } else if file_type.is_file() {
+        copy_file(src, dest, metadata, opts, stderr)
+            .map_err(|e| {
+                report.error(format!(
+                    "cannot copy '{}' to '{}': {}",
+                    src.display(),
+                    dest.display(),
+                    e
+                ))
+            })
+            .is_ok()
+    } else {
+        // Reading a pipe or device could block forever or never end
+        report.error(format!("cannot copy special file '{}'", src.display()));
+        false
+    };
+    // A directory's times are set last, as copying into it changes them
+    if !copied {
+        return;
+    }
+    if !file_type.is_dir() {
+        report.copied.push((src.to_path_buf(), dest.to_path_buf()));
+    }
+    if opts.preserve {
+        preserve_metadata(metadata, dest, report);
+    }
+}
+
+/// Copies a directory and its contents, merging into `dest` if it is
+/// already a directory. Returns whether `dest` is a directory afterwards.
+fn copy_dir(
+    src: &Path,
+    dest: &Path,
+    opts: &Options,
+    stderr: &mut dyn Write,
+    report: &mut Report,
+) -> bool {
+    // Following links, a link to a directory above would copy forever
+    if opts.symlinks == Symlinks::Follow && is_loop(src) {
+        report.error(format!("skipping '{}': symbolic link loop", src.display()));
+        return false;
+    }
+    match fs::create_dir(dest) {
+        // Listed before its contents, which are copied next
+        Ok(()) => report.copied.push((src.to_path_buf(), dest.to_path_buf())),
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists && dest.is_dir() => {}
+        Err(e) => {
+            report.error(format!(
+                "cannot create directory '{}': {}",
+                dest.display(),
+                e
+            ));
+            return false;
+        }
+    }
+
+    let entries = match fs::read_dir(src) {
+        Ok(entries) => entries,
+        Err(e) => {
+            report.error(format!("cannot open directory '{}': {}", src.display(), e));
+            return true;
+        }
+    };
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                report.error(format!("cannot read '{}': {}", src.display(), e));
+                continue;
+            }
+        };
+        // `DirEntry::metadata` does not follow symbolic links
+        let metadata = if opts.symlinks == Symlinks::Follow {
+            fs::metadata(entry.path())
+        } else {
+            entry.metadata()
+        };
+        match metadata {
+            Ok(metadata) => copy_entry(
+                &entry.path(),
+                &dest.join(entry.file_name()),
+                &metadata,
+                opts,
+                stderr,
+                report,
+            ),
+            Err(e) => report.error(format!("cannot stat '{}': {}", entry.path().display(), e)),
+        }
+    }
+    true
+}
+
+/// A progress bar for one file, redrawn in place on stderr as the copy
+/// goes on. It is only drawn for files of at least [`Progress::min_size`].
+struct Meter<'a> {
+    progress: Option<&'a Progress>,
+    stderr: &'a mut dyn Write,
+    name: String,
+    total: u64,
+    done: u64,
+    started: Instant,
+    drawn: Option<Instant>,
+}
+
+impl<'a> Meter<'a> {
+    fn new(opts: &'a Options, stderr: &'a mut dyn Write, dest: &Path, total: u64) -> Self {
+        Self {
+            progress: opts.progress.as_ref().filter(|p| total >= p.min_size),
+            stderr,
+            name: dest.file_name().map_or_else(
+                || dest.display().to_string(),
+                |name| name.to_string_lossy().into_owned(),
+            ),
+            total,
+            done: 0,
+            started: Instant::now(),
+            drawn: None,
+        }
+    }
+
+    /// Whether the bar is shown, so the copy must go in steps.
+    fn is_shown(&self) -> bool {
+        self.progress.is_some()
+    }
+
+    /// Counts `n` more bytes as copied, redrawing at most ten times a second.
+    fn add(&mut self, n: u64) {
+        self.done += n;
+        if self
+            .drawn
+            .is_none_or(|drawn| drawn.elapsed() >= Duration::from_millis(100))
+        {
+            self.draw("");
+            self.drawn = Some(Instant::now());
+        }
+    }
+
+    /// Draws the bar a last time, ending its line.
+    fn finish(&mut self) {
+        self.draw("\n");
+    }
+
+    fn draw(&mut self, end: &str) {
+        if let Some(progress) = self.progress {
+            let line = (progress.line)(self.done, self.total, self.started.elapsed());
+            let _ = write!(self.stderr, "\r{} {}{}", self.name, line, end);
+            let _ = self.stderr.flush();
+        }
+    }
+}
+
+/// Copies the contents and permissions of the regular file `src`.
+fn copy_file(
+    src: &Path,
+    dest: &Path,
+    metadata: &Metadata,
+    opts: &Options,
+    stderr: &mut dyn Write,
+) -> io::Result<()> {
+    let mut meter = Meter::new(opts, stderr, dest, metadata.len());
+    let result = copy_contents(src, dest, metadata, opts, &mut meter);
+    if result.is_ok() {
+        meter.done = meter.total;
+    }
+    meter.finish();
+    result
+}
+
+#[cfg(target_os = "linux")]
+fn copy_contents(
+    src: &Path,
+    dest: &Path,
+    metadata: &Metadata,
+    opts: &Options,
+    meter: &mut Meter,
+) -> io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+    use std::os::unix::io::AsRawFd;
+
+    let input = fs::File::open(src)?;
+    let output = fs::File::create(dest)?;
+    let len = metadata.len();
+    output.set_permissions(metadata.permissions())?;
+
+    if opts.reflink != Reflink::Never {
+        // SAFETY: both descriptors stay open for the duration of the call.
+        let status = unsafe { libc::ioctl(output.as_raw_fd(), libc::FICLONE, input.as_raw_fd()) };
+        if status == 0 {
+            return Ok(());
+        }
+        if opts.reflink == Reflink::Always {
+            return Err(io::Error::last_os_error());
+        }
+    }
+
+    match opts.sparse {
+        Sparse::Always => copy_skipping_zeros(&input, &output, len, meter)?,
+        // Fewer blocks than bytes means the source has holes to keep
+        Sparse::Auto if metadata.blocks() * 512 < len => {
+            copy_data_runs(&input, &output, len, meter)?
+        }
+        _ => copy_range(&input, &output, 0, len, meter)?,
+    }
+    // Any trailing hole is made by setting the length
+    output.set_len(len)
+}
+
+/// Copies a regular file, which here cannot share blocks or keep holes.
+#[cfg(not(target_os = "linux"))]
+fn copy_contents(
+    src: &Path,
+    dest: &Path,
+    metadata: &Metadata,
+    opts: &Options,
+    meter: &mut Meter,
+) -> io::Result<()> {
+    use std::io::Read;
+
+    if opts.reflink == Reflink::Always {
+        return Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "reflinks are not supported on this platform",
+        ));
+    }
+    if !meter.is_shown() {
+        return fs::copy(src, dest).map(drop);
+    }
+    let mut input = fs::File::open(src)?;
+    let mut output = fs::File::create(dest)?;
+    let mut buf = vec![0u8; COPY_CHUNK];
+    loop {
+        let n = match input.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        output.write_all(&buf[..n])?;
+        meter.add(n as u64);
+    }
+    output.set_permissions(metadata.permissions())
+}
+
+/// Copies only the parts of `input` that hold data, found with
+/// `SEEK_DATA` and `SEEK_HOLE`, leaving the rest of `output` as holes.
+#[cfg(target_os = "linux")]
+fn copy_data_runs(
+    input: &fs::File,
+    output: &fs::File,
+    len: u64,
+    meter: &mut Meter,
+) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let fd = input.as_raw_fd();
+    let mut pos = 0;
+    while pos < len {
+        // SAFETY: `lseek` only moves the offset of a descriptor we own.
+        let data = unsafe { libc::lseek(fd, pos as libc::off_t, libc::SEEK_DATA) };
+        if data < 0 {
+            let e = io::Error::last_os_error();
+            return match e.raw_os_error() {
+                // No data after `pos`: the rest is a hole
+                Some(libc::ENXIO) => Ok(()),
+                // The filesystem cannot say where its holes are
+                Some(libc::EINVAL) if pos == 0 => copy_range(input, output, 0, len, meter),
+                _ => Err(e),
+            };
+        }
+        // SAFETY: as above.
+        let hole = unsafe { libc::lseek(fd, data, libc::SEEK_HOLE) };
+        if hole < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let end = (hole as u64).min(len);
+        // Holes count as copied, as they are done when the length is set
+        meter.add(data as u64 - pos);
+        copy_range(input, output, data as u64, end, meter)?;
+        pos = end;
+    }
+    Ok(())
+}
+
+/// Copies `input` block by block, seeking over blocks of zeros instead of
+/// writing them.
+#[cfg(target_os = "linux")]
+fn copy_skipping_zeros(
+    input: &fs::File,
+    output: &fs::File,
+    len: u64,
+    meter: &mut Meter,
+) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    let mut buf = vec![0u8; 64 * 1024];
+    let mut pos = 0;
+    while pos < len {
+        let n = input.read_at(&mut buf, pos)?;
+        if n == 0 {
+            break;
+        }
+        if buf[..n].iter().any(|&b| b != 0) {
+            output.write_all_at(&buf[..n], pos)?;
+        }
+        pos += n as u64;
+        meter.add(n as u64);
+    }
+    Ok(())
+}
+
+/// Copies bytes `start..end` of `input` to the same place in `output`,
+/// in the kernel with `copy_file_range` where it can.
+#[cfg(target_os = "linux")]
+fn copy_range(
+    input: &fs::File,
+    output: &fs::File,
+    start: u64,
+    end: u64,
+    meter: &mut Meter,
+) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    use std::os::unix::io::AsRawFd;
+
+    let mut off_in = start as libc::off64_t;
+    let mut off_out = start as libc::off64_t;
+    while (off_in as u64) < end {
+        // In steps, so that the bar moves
+        let step = if meter.is_shown() {
+            COPY_CHUNK
+        } else {
+            1 << 30
+        };
+        let want = (end - off_in as u64).min(step as u64) as usize;
+        // SAFETY: both descriptors are open and the offsets are ours.
+        let n = unsafe {
+            libc::copy_file_range(
+                input.as_raw_fd(),
+                &mut off_in,
+                output.as_raw_fd(),
+                &mut off_out,
+                want,
+                0,
+            )
+        };
+        if n == 0 {
+            // The source shrank while being copied
+            return Ok(());
+        }
+        if n < 0 {
+            let e = io::Error::last_os_error();
+            if !matches!(
+                e.raw_os_error(),
+                Some(libc::ENOSYS | libc::EXDEV | libc::EINVAL | libc::EOPNOTSUPP)
+            ) {
+                return Err(e);
+            }
+            // Not possible between these files: copy through a buffer
+            let mut buf = vec![0u8; 64 * 1024];
+            let mut pos = off_in as u64;
+            while pos < end {
+                let want = (end - pos).min(buf.len() as u64) as usize;
+                let n = input.read_at(&mut buf[..want], pos)?;
+                if n == 0 {
+                    break;
+                }
+                output.write_all_at(&buf[..n], pos)?;
+                pos += n as u64;
+                meter.add(n as u64);
+            }
+            return Ok(());
+        }
+        meter.add(n as u64);
+    }
+    Ok(())
+}
+
+/// Whether the directory `dir` is the same as one of the directories its
+/// path goes through.
+fn is_loop(dir: &Path) -> bool {
+    let Ok(real) = fs::canonicalize(dir) else {
+        return false;
+    };
+    dir.ancestors()
+        .skip(1)
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .any(|parent| fs::canonicalize(parent).is_ok_and(|parent| parent == real))
+}
+
+/// Whether the file `metadata` describes was modified after the one
+/// `other` describes. Files whose times cannot be read count as newer, so
+/// they are copied.
+fn is_newer(metadata: &Metadata, other: &Metadata) -> bool {
+    match (metadata.modified(), other.modified()) {
+        (Ok(time), Ok(other)) => time > other,
+        _ => true,
+    }
+}
+
+/// Gives `dest` the timestamps, owner, and permissions in `metadata`.
+///
+/// Ownership is only changed when running as root, since nobody else may
+/// give files away. The permissions of symbolic links are not changed, as
+/// they have none of their own.
+#[cfg(unix)]
+fn preserve_metadata(metadata: &Metadata, dest: &Path, report: &mut Report) {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(c_path) = CString::new(dest.as_os_str().as_bytes()) else {
+        return;
+    };
+    let times = [
+        libc::timespec {
+            tv_sec: metadata.atime() as libc::time_t,
+            tv_nsec: metadata.atime_nsec() as _,
+        },
+        libc::timespec {
+            tv_sec: metadata.mtime() as libc::time_t,
+            tv_nsec: metadata.mtime_nsec() as _,
+        },
+    ];
+    // SAFETY: the path is NUL-terminated and `times` holds the two entries
+    // `utimensat` reads.
+    let status = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            c_path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if status != 0 {
+        report.error(format!(
+            "preserving times for '{}': {}",
+            dest.display(),
+            io::Error::last_os_error()
+        ));
+    }
+
+    // SAFETY: `geteuid` cannot fail and touches no memory.
+    if unsafe { libc::geteuid() } == 0
+        && let Err(e) = std::os::unix::fs::lchown(dest, Some(metadata.uid()), Some(metadata.gid()))
+    {
+        report.error(format!(
+            "failed to preserve ownership for '{}': {}",
+            dest.display(),
+            e
+        ));
+    }
+
+    // Changing the owner clears the set-user-ID bits, so the mode goes last
+    if !metadata.file_type().is_symlink()
+        && let Err(e) = fs::set_permissions(dest, metadata.permissions())
+    {
+        report.error(format!(
+            "preserving permissions for '{}': {}",
+            dest.display(),
+            e
+        ));
+    }
+}
+
+/// Gives `dest` the modification time and read-only flag in `metadata`.
+/// Directories and links keep their own times, which cannot be set here.
+#[cfg(not(unix))]
+fn preserve_metadata(metadata: &Metadata, dest: &Path, report: &mut Report) {
+    if metadata.is_file()
+        && let Ok(modified) = metadata.modified()
+    {
+        let times = fs::FileTimes::new().set_modified(modified);
+        let set = fs::File::options()
+            .write(true)
+            .open(dest)
+            .and_then(|file| file.set_times(times));
+        if let Err(e) = set {
+            report.error(format!("preserving times for '{}': {}", dest.display(), e));
+        }
+    }
+    if !metadata.file_type().is_symlink()
+        && let Err(e) = fs::set_permissions(dest, metadata.permissions())
+    {
+        report.error(format!(
+            "preserving permissions for '{}': {}",
+            dest.display(),
+            e
+        ));
+    }
+}
+
+/// Recreates the symbolic link `src` at `dest`, replacing any file there.
+fn copy_symlink(src: &Path, dest: &Path) -> io::Result<()> {
+    let target = fs::read_link(src)?;
+    if fs::symlink_metadata(dest).is_ok_and(|m| !m.is_dir()) {
+        fs::remove_file(dest)?;
+    }
+    #[cfg(unix)]
+    {
+        std::os::unix::fs::symlink(target, dest)
+    }
+    #[cfg(windows)]
+    {
+        if src.is_dir() {
+            std::os::windows::fs::symlink_dir(target, dest)
+        } else {
+            std::os::windows::fs::symlink_file(target, dest)
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    {
+        let _ = target;
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "symbolic links are not supported",
+        ))
+    }
+}
+
+/// Whether `path`, which need not exist yet, is `dir` or inside it.
+fn is_inside(path: &Path, dir: &Path) -> bool {
+    let Ok(dir) = fs::canonicalize(dir) else {
+        return false;
+    };
+    // Resolve the deepest part of `path` that exists
+    let mut existing = path.to_path_buf();
+    let mut rest = Vec::new();
+    let resolved: PathBuf = loop {
+        if let Ok(real) = fs::canonicalize(&existing) {
+            break rest
+                .iter()
+                .rev()
+                .fold(real, |acc: PathBuf, part| acc.join(part));
+        }
+        match (existing.parent(), existing.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name.to_os_string());
+                existing = if parent.as_os_str().is_empty() {
+                    PathBuf::from(".")
+                } else {
+                    parent.to_path_buf()
+                };
+            }
+            _ => return false,
+        }
+    };
+    resolved.starts_with(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_tree() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("sub/deeper")).unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        fs::write(src.join("sub/deeper/b.txt"), "b").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.txt", src.join("link")).unwrap();
+
+        let opts = Options {
+            recursive: true,
+            ..Options::default()
+        };
+        let mut report = Report::default();
+        let dest = dir.path().join("dest");
+        copy(&src, &dest, &opts, &mut io::sink(), &mut report);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(fs::read_to_string(dest.join("a.txt")).unwrap(), "a");
+        assert_eq!(
+            fs::read_to_string(dest.join("sub/deeper/b.txt")).unwrap(),
+            "b"
+        );
+        #[cfg(unix)]
+        assert_eq!(
+            fs::read_link(dest.join("link")).unwrap(),
+            Path::new("a.txt")
+        );
+
+        // Copying again merges into the existing tree
+        copy(&src, &dest, &opts, &mut io::sink(), &mut report);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+
+        copy(
+            &src,
+            &src.join("sub/copy"),
+            &opts,
+            &mut io::sink(),
+            &mut report,
+        );
+        assert_eq!(report.errors.len(), 1);
+        assert!(report.errors[0].contains("into itself"));
+        assert!(!src.join("sub/copy").exists());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_copy_symlink_modes() {
+        use std::os::unix::fs::symlink;
+
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        symlink("a.txt", src.join("link")).unwrap();
+        symlink("..", src.join("sub/up")).unwrap();
+        let is_link = |path: PathBuf| fs::symlink_metadata(path).unwrap().is_symlink();
+
+        // Named sources are followed by default, but not with -P
+        let mut report = Report::default();
+        let mut opts = Options::default();
+        copy(
+            &src.join("link"),
+            &dir.path().join("followed"),
+            &opts,
+            &mut io::sink(),
+            &mut report,
+        );
+        assert!(!is_link(dir.path().join("followed")));
+        opts.symlinks = Symlinks::Preserve;
+        copy(
+            &src.join("link"),
+            &dir.path().join("kept"),
+            &opts,
+            &mut io::sink(),
+            &mut report,
+        );
+        assert!(is_link(dir.path().join("kept")));
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+
+        // -L copies the files links point to, and stops at loops
+        opts.recursive = true;
+        opts.symlinks = Symlinks::Follow;
+        let dest = dir.path().join("dest");
+        copy(&src, &dest, &opts, &mut io::sink(), &mut report);
+        assert!(!is_link(dest.join("link")));
+        assert_eq!(fs::read_to_string(dest.join("link")).unwrap(), "a");
+        assert_eq!(report.errors.len(), 1, "{:?}", report.errors);
+        assert!(report.errors[0].contains("symbolic link loop"));
+        assert!(!dest.join("sub/up").exists());
+    }
+
+    #[test]
+    fn test_move_by_copying() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("sub/a.txt"), "a").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("sub/a.txt", src.join("link")).unwrap();
+
+        let mut report = Report::default();
+        let dest = dir.path().join("dest");
+        move_by_copying(&src, &dest, &mut report);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert!(!src.exists());
+        assert_eq!(fs::read_to_string(dest.join("sub/a.txt")).unwrap(), "a");
+        #[cfg(unix)]
+        assert!(
+            fs::symlink_metadata(dest.join("link"))
+                .unwrap()
+                .is_symlink()
+        );
+
+        // A failed copy leaves the source in place
+        let file = dir.path().join("file.txt");
+        fs::write(&file, "f").unwrap();
+        move_by_copying(&file, &dir.path().join("missing/file.txt"), &mut report);
+        assert_eq!(report.errors.len(), 1);
+        assert!(file.exists());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_copy_sparse() {
+        use std::os::unix::fs::{FileExt, MetadataExt};
+
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("sparse");
+        let file = fs::File::create(&src).unwrap();
+        file.write_all_at(b"start", 0).unwrap();
+        file.write_all_at(b"middle", 4 << 20).unwrap();
+        file.set_len(8 << 20).unwrap();
+        drop(file);
+        let zeros = dir.path().join("zeros");
+        fs::write(&zeros, vec![0u8; 1 << 20]).unwrap();
+        let is_sparse = |path: &Path| {
+            let meta = fs::metadata(path).unwrap();
+            meta.blocks() * 512 < meta.len()
+        };
+
+        let mut report = Report::default();
+        for sparse in [Sparse::Auto, Sparse::Always, Sparse::Never] {
+            let opts = Options {
+                reflink: Reflink::Never,
+                sparse,
+                ..Options::default()
+            };
+            let dest = dir.path().join(format!("{:?}", sparse));
+            copy(&src, &dest, &opts, &mut io::sink(), &mut report);
+            assert!(report.errors.is_empty(), "{:?}", report.errors);
+            assert_eq!(fs::read(&dest).unwrap(), fs::read(&src).unwrap());
+            // Holes are only possible where the filesystem has them
+            if is_sparse(&src) {
+                assert_eq!(is_sparse(&dest), sparse != Sparse::Never, "{:?}", sparse);
+            }
+
+            let dest = dir.path().join(format!("zeros-{:?}", sparse));
+            copy(&zeros, &dest, &opts, &mut io::sink(), &mut report);
+            assert_eq!(fs::read(&dest).unwrap(), fs::read(&zeros).unwrap());
+            if is_sparse(&src) {
+                assert_eq!(is_sparse(&dest), sparse == Sparse::Always, "{:?}", sparse);
+            }
+        }
+    }
+
+    #[test]
+    fn test_copy_overwrite_control() {
+        let dir = tempdir().unwrap();
+        let src = dir.path().join("src");
+        let dest = dir.path().join("dest");
+        fs::create_dir_all(src.join("sub")).unwrap();
+        fs::write(src.join("a.txt"), "new a").unwrap();
+        fs::write(src.join("sub/b.txt"), "new b").unwrap();
+        fs::create_dir_all(dest.join("sub")).unwrap();
+        fs::write(dest.join("a.txt"), "old a").unwrap();
+        fs::write(dest.join("sub/b.txt"), "old b").unwrap();
+        let read = |name: &str| fs::read_to_string(dest.join(name)).unwrap();
+
+        let mut report = Report::default();
+        let mut opts = Options {
+            recursive: true,
+            no_clobber: true,
+            ..Options::default()
+        };
+        copy(&src, &dest, &opts, &mut io::sink(), &mut report);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(
+            (read("a.txt"), read("sub/b.txt")),
+            ("old a".into(), "old b".into())
+        );
+
+        // Only files the answer allows are replaced
+        opts.no_clobber = false;
+        opts.confirm = Some(|path| path.ends_with("sub/b.txt"));
+        copy(&src, &dest, &opts, &mut io::sink(), &mut report);
+        assert!(report.errors.is_empty(), "{:?}", report.errors);
+        assert_eq!(
+            (read("a.txt"), read("sub/b.txt")),
+            ("old a".into(), "new b".into())
+        );
+    }
+}
@@ -0,0 +1,766 @@
+//! Desktop integration: opening files and URLs with their default
+//! applications, and copying to and pasting from the system clipboard.
+//!
+//! On Linux and other freedesktop systems this follows the XDG
+//! specifications directly rather than calling `xdg-open`: the MIME type
+//! comes from the shared MIME database's glob list, the default application
+//! from `mimeapps.list` or `mimeinfo.cache`, and the command line from the
+//! application's `.desktop` entry. macOS hands the target to `open`, and
+//! Windows calls `ShellExecuteW`.
+//!
+//! The clipboard goes through `wl-copy`/`wl-paste` on Wayland, `xclip` or
+//! `xsel` on X11, and `pbcopy`/`pbpaste` on macOS, since a selection owner
+//! has to keep running to serve pastes after the shell moves on. Windows
+//! uses the clipboard API directly.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::command::Variables;
+
+/// Opens `target`, a file path or URL, with its default application. The
+/// application is started in the background; this returns once it has
+/// been launched. The XDG directories searched are those in `vars`.
+pub fn open(target: &str, vars: &Variables) -> io::Result<()> {
+    platform_open(target, vars)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn platform_open(target: &str, vars: &Variables) -> io::Result<()> {
+    let dirs = XdgDirs::from_vars(vars);
+    let mime = mime_type(target, &dirs);
+    let not_found = || {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no application found for {}", mime),
+        )
+    };
+    let app = default_app(&mime, &dirs).ok_or_else(not_found)?;
+    let entry = fs::read_to_string(&app)?;
+    let exec = desktop_exec(&entry).ok_or_else(not_found)?;
+    let argv = expand_exec(&exec, target)
+        .filter(|argv| !argv.is_empty())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: invalid Exec line", app.display()),
+            )
+        })?;
+    spawn_detached(&argv[0], &argv[1..])
+}
+
+#[cfg(target_os = "macos")]
+fn platform_open(target: &str, _vars: &Variables) -> io::Result<()> {
+    spawn_detached("/usr/bin/open", &[target.to_string()])
+}
+
+#[cfg(windows)]
+fn platform_open(target: &str, _vars: &Variables) -> io::Result<()> {
+    use std::os::windows::ffi::OsStrExt;
+
+    #[link(name = "shell32")]
+    unsafe extern "system" {
+        fn ShellExecuteW(
+            hwnd: *mut std::ffi::c_void,
+            operation: *const u16,
+            file: *const u16,
+            parameters: *const u16,
+            directory: *const u16,
+            show: i32,
+        ) -> isize;
+    }
+    const SW_SHOWNORMAL: i32 = 1;
+
+    let wide = |s: &str| -> Vec<u16> {
+        std::ffi::OsStr::new(s)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect()
+    };
+    let operation = wide("open");
+    let file = wide(target);
+    // SAFETY: all strings are NUL-terminated and outlive the call.
+    let code = unsafe {
+        ShellExecuteW(
+            std::ptr::null_mut(),
+            operation.as_ptr(),
+            file.as_ptr(),
+            std::ptr::null(),
+            std::ptr::null(),
+            SW_SHOWNORMAL,
+        )
+    };
+    // Values above 32 mean success
+    if code > 32 {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("ShellExecute failed ({})", code)))
+    }
+}
+
+/// Starts a program in its own process group with no standard streams, so
+/// that it outlives Ctrl+C at the prompt and cannot draw over it. A thread
+/// reaps it when it exits.
+#[cfg(unix)]
+fn spawn_detached(program: &str, args: &[String]) -> io::Result<()> {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .process_group(0)
+        .spawn()?;
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+/// The base directories of the XDG Base Directory specification.
+#[derive(Debug, Clone)]
+pub struct XdgDirs {
+    /// `$XDG_CONFIG_HOME` followed by `$XDG_CONFIG_DIRS`.
+    pub config: Vec<PathBuf>,
+    /// `$XDG_DATA_HOME` followed by `$XDG_DATA_DIRS`.
+    pub data: Vec<PathBuf>,
+}
+
+impl XdgDirs {
+    /// The directories named by the shell's variables, with the defaults
+    /// of the specification for those that are unset or empty.
+    pub fn from_vars(vars: &Variables) -> Self {
+        let home = vars.get("HOME").unwrap_or_default();
+        let var = |name: &str, default: String| {
+            vars.get(name).filter(|v| !v.is_empty()).unwrap_or(default)
+        };
+        let split = |list: String| -> Vec<PathBuf> {
+            list.split(':')
+                .filter(|p| !p.is_empty())
+                .map(PathBuf::from)
+                .collect()
+        };
+
+        let mut config = vec![PathBuf::from(var(
+            "XDG_CONFIG_HOME",
+            format!("{}/.config", home),
+        ))];
+        config.extend(split(var("XDG_CONFIG_DIRS", "/etc/xdg".to_string())));
+        let mut data = vec![PathBuf::from(var(
+            "XDG_DATA_HOME",
+            format!("{}/.local/share", home),
+        ))];
+        data.extend(split(var(
+            "XDG_DATA_DIRS",
+            "/usr/local/share:/usr/share".to_string(),
+        )));
+        Self { config, data }
+    }
+}
+
+/// Guesses the MIME type of a URL or file. URLs map to
+/// `x-scheme-handler/<scheme>`, directories to `inode/directory`, and files
+/// are matched by name against the shared MIME database, falling back to
+/// `text/plain` for UTF-8 content and `application/octet-stream` otherwise.
+/// The MIME database is looked for in the data directories of `dirs`.
+pub fn mime_type(target: &str, dirs: &XdgDirs) -> String {
+    if let Some(scheme) = url_scheme(target)
+        && scheme != "file"
+    {
+        return format!("x-scheme-handler/{}", scheme);
+    }
+    let path = target.strip_prefix("file://").unwrap_or(target);
+    if Path::new(path).is_dir() {
+        return "inode/directory".to_string();
+    }
+
+    let name = Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    for dir in &dirs.data {
+        if let Ok(globs) = fs::read_to_string(dir.join("mime/globs2"))
+            && let Some(mime) = match_globs(&globs, &name)
+        {
+            return mime;
+        }
+    }
+
+    let mut head = [0u8; 512];
+    let len = fs::File::open(path)
+        .and_then(|mut f| io::Read::read(&mut f, &mut head))
+        .unwrap_or(0);
+    let text = std::str::from_utf8(&head[..len])
+        // The sample may end inside a multi-byte character
+        .or_else(|e| std::str::from_utf8(&head[..e.valid_up_to()]).map_err(|_| e))
+        .is_ok_and(|s| !s.contains('\0'));
+    if len > 0 && text {
+        "text/plain".to_string()
+    } else {
+        "application/octet-stream".to_string()
+    }
+}
+
+/// The scheme of `target` if it looks like a URL (`scheme:` followed by
+/// anything, where the scheme is at least two characters so that Windows
+/// drive letters are not mistaken for one).
+fn url_scheme(target: &str) -> Option<String> {
+    let (scheme, _) = target.split_once(':')?;
+    let mut chars = scheme.chars();
+    let valid = scheme.len() > 1
+        && chars.next()?.is_ascii_alphabetic()
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    valid.then(|| scheme.to_ascii_lowercase())
+}
+
+/// Finds the MIME type for a lowercase file name in `globs2` content
+/// (`weight:type:pattern` lines). The highest weight wins, then the longest
+/// pattern.
+fn match_globs(globs: &str, name: &str) -> Option<String> {
+    let mut best: Option<(u32, usize, &str)> = None;
+    for line in globs.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(':');
+        let (Some(weight), Some(mime), Some(pattern)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let weight: u32 = weight.parse().unwrap_or(50);
+        let pattern = pattern.to_lowercase();
+        let matches = match pattern.strip_prefix('*') {
+            Some(suffix) if !suffix.contains(['*', '?', '[']) => name.ends_with(suffix),
+            _ => pattern == name,
+        };
+        if matches && best.is_none_or(|(w, len, _)| (weight, pattern.len()) > (w, len)) {
+            best = Some((weight, pattern.len(), mime));
+        }
+    }
+    best.map(|(_, _, mime)| mime.to_string())
+}
+
+/// Finds the `.desktop` file of the default application for `mime`.
+///
+/// Follows the MIME applications associations specification: the
+/// `[Default Applications]` of each `mimeapps.list`, in order of
+/// precedence, then `[Added Associations]`, then the `mimeinfo.cache`
+/// files written by `update-desktop-database`.
+pub fn default_app(mime: &str, dirs: &XdgDirs) -> Option<PathBuf> {
+    let app_dirs: Vec<PathBuf> = dirs.data.iter().map(|d| d.join("applications")).collect();
+    let lists: Vec<PathBuf> = dirs
+        .config
+        .iter()
+        .chain(&app_dirs)
+        .map(|d| d.join("mimeapps.list"))
+        .collect();
+
+    let mut candidates = Vec::new();
+    for section in ["Default Applications", "Added Associations"] {
+        for list in &lists {
+            if let Ok(text) = fs::read_to_string(list) {
+                candidates.extend(ini_list(&text, section, mime));
+            }
+        }
+    }
+    for dir in &app_dirs {
+        if let Ok(text) = fs::read_to_string(dir.join("mimeinfo.cache")) {
+            candidates.extend(ini_list(&text, "MIME Cache", mime));
+        }
+    }
+
+    candidates
+        .iter()
+        .find_map(|id| find_desktop_file(id, &app_dirs))
+}
+
+/// The `;`-separated values of `key` in `[section]` of an INI-style file.
+fn ini_list(text: &str, section: &str, key: &str) -> Vec<String> {
+    let mut current = "";
+    for line in text.lines() {
+        let line = line.trim();
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = name;
+        } else if current == section
+            && let Some((k, v)) = line.split_once('=')
+            && k.trim() == key
+        {
+            return v
+                .split(';')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .map(String::from)
+                .collect();
+        }
+    }
+    Vec::new()
+}
+
+/// Locates a desktop file ID such as `org.gnome.Evince.desktop`. An ID may
+/// also name a file in a subdirectory, with `-` standing for `/`.
+fn find_desktop_file(id: &str, app_dirs: &[PathBuf]) -> Option<PathBuf> {
+    app_dirs.iter().find_map(|dir| {
+        let direct = dir.join(id);
+        if direct.is_file() {
+            return Some(direct);
+        }
+        let nested = dir.join(id.replacen('-', "/", 1));
+        nested.is_file().then_some(nested)
+    })
+}
+
+/// The `Exec` line of the `[Desktop Entry]` group.
+fn desktop_exec(entry: &str) -> Option<String> {
+    let mut in_entry = false;
+    for line in entry.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if in_entry && let Some(exec) = line.strip_prefix("Exec=") {
+            return Some(exec.to_string());
+        }
+    }
+    None
+}
+
+/// Splits an `Exec` line into arguments and substitutes `target` for the
+/// file or URL field code. Other field codes are dropped; if there is no
+/// file or URL code, the target is appended. Returns `None` for an
+/// unterminated quote.
+pub fn expand_exec(exec: &str, target: &str) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut chars = exec.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                in_word = true;
+            }
+            '\\' if quoted => word.push(chars.next()?),
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if quoted {
+        return None;
+    }
+    if in_word {
+        words.push(word);
+    }
+
+    let path = target.strip_prefix("file://").unwrap_or(target);
+    let mut substituted = false;
+    let mut argv = Vec::new();
+    for word in words {
+        match word.as_str() {
+            // A code standing alone may expand to nothing
+            "%f" | "%F" | "%u" | "%U" => {
+                let value = if word.ends_with(['f', 'F']) {
+                    path
+                } else {
+                    target
+                };
+                argv.push(value.to_string());
+                substituted = true;
+            }
+            "%i" | "%c" | "%k" => {}
+            _ => {
+                let mut out = String::new();
+                let mut chars = word.chars();
+                while let Some(c) = chars.next() {
+                    if c != '%' {
+                        out.push(c);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some('%') => out.push('%'),
+                        Some('f' | 'F') => {
+                            out.push_str(path);
+                            substituted = true;
+                        }
+                        Some('u' | 'U') => {
+                            out.push_str(target);
+                            substituted = true;
+                        }
+                        // Other and deprecated field codes are removed
+                        _ => {}
+                    }
+                }
+                argv.push(out);
+            }
+        }
+    }
+    if !substituted {
+        argv.push(target.to_string());
+    }
+    Some(argv)
+}
+
+/// Copies `data` to the system clipboard. On Unix without a clipboard tool,
+/// a terminal is asked to set it with an OSC 52 escape sequence written to
+/// `stdout`, which also works over SSH in terminals that support it.
+#[cfg(unix)]
+pub fn copy_to_clipboard(
+    data: &[u8],
+    vars: &Variables,
+    stdout: &mut dyn io::Write,
+) -> io::Result<()> {
+    use std::io::{IsTerminal, Write};
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+
+    for (program, args) in clipboard_tools(true, vars) {
+        let spawned = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            // Keep the selection owner alive through Ctrl+C at the prompt
+            .process_group(0)
+            .spawn();
+        let mut child = match spawned {
+            Ok(child) => child,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let written = child
+            .stdin
+            .take()
+            .map_or(Ok(()), |mut stdin| stdin.write_all(data));
+        let status = child.wait()?;
+        written?;
+        return if status.success() {
+            Ok(())
+        } else {
+            Err(io::Error::other(format!("{} failed ({})", program, status)))
+        };
+    }
+
+    if io::stdout().is_terminal() {
+        stdout.write_all(&osc52(data))?;
+        return stdout.flush();
+    }
+    Err(no_clipboard())
+}
+
+/// Returns the contents of the system clipboard.
+#[cfg(unix)]
+pub fn paste_from_clipboard(vars: &Variables) -> io::Result<Vec<u8>> {
+    use std::process::{Command, Stdio};
+
+    for (program, args) in clipboard_tools(false, vars) {
+        let output = match Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .output()
+        {
+            Ok(output) => output,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(io::Error::other(if message.is_empty() {
+                format!("{} failed ({})", program, output.status)
+            } else {
+                message
+            }));
+        }
+        return Ok(output.stdout);
+    }
+    Err(no_clipboard())
+}
+
+#[cfg(unix)]
+fn no_clipboard() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        "no clipboard available (install wl-clipboard, xclip, or xsel)",
+    )
+}
+
+/// The clipboard programs to try, in order, for copying or pasting.
+#[cfg(unix)]
+fn clipboard_tools(copy: bool, vars: &Variables) -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        return vec![if copy {
+            ("pbcopy", &[])
+        } else {
+            ("pbpaste", &[])
+        }];
+    }
+    let set = |name: &str| vars.get(name).is_some_and(|v| !v.is_empty());
+    linux_clipboard_tools(set("WAYLAND_DISPLAY"), set("DISPLAY"), copy)
+}
+
+/// The clipboard programs for the display servers that are running.
+/// Wayland comes first, since X11 programs under XWayland only see the X
+/// side of the clipboard.
+#[cfg(unix)]
+fn linux_clipboard_tools(
+    wayland: bool,
+    x11: bool,
+    copy: bool,
+) -> Vec<(&'static str, &'static [&'static str])> {
+    let mut tools: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+    if wayland {
+        tools.push(if copy {
+            ("wl-copy", &[])
+        } else {
+            ("wl-paste", &["--no-newline"])
+        });
+    }
+    if x11 {
+        if copy {
+            tools.push(("xclip", &["-selection", "clipboard", "-in"]));
+            tools.push(("xsel", &["--clipboard", "--input"]));
+        } else {
+            tools.push(("xclip", &["-selection", "clipboard", "-out"]));
+            tools.push(("xsel", &["--clipboard", "--output"]));
+        }
+    }
+    tools
+}
+
+/// The OSC 52 escape sequence that sets the clipboard to `data`.
+#[cfg(unix)]
+fn osc52(data: &[u8]) -> Vec<u8> {
+    let mut out = b"\x1b]52;c;".to_vec();
+    let mut encoder = crate::base64::Encoder::new(0);
+    encoder.update(data, &mut out);
+    encoder.finish(&mut out);
+    out.push(0x07);
+    out
+}
+
+#[cfg(windows)]
+mod win32 {
+    use std::ffi::c_void;
+
+    pub const CF_UNICODETEXT: u32 = 13;
+    pub const GMEM_MOVEABLE: u32 = 0x0002;
+
+    #[link(name = "user32")]
+    unsafe extern "system" {
+        pub fn OpenClipboard(owner: *mut c_void) -> i32;
+        pub fn CloseClipboard() -> i32;
+        pub fn EmptyClipboard() -> i32;
+        pub fn GetClipboardData(format: u32) -> *mut c_void;
+        pub fn SetClipboardData(format: u32, mem: *mut c_void) -> *mut c_void;
+    }
+
+    #[link(name = "kernel32")]
+    unsafe extern "system" {
+        pub fn GlobalAlloc(flags: u32, bytes: usize) -> *mut c_void;
+        pub fn GlobalFree(mem: *mut c_void) -> *mut c_void;
+        pub fn GlobalLock(mem: *mut c_void) -> *mut c_void;
+        pub fn GlobalUnlock(mem: *mut c_void) -> i32;
+    }
+
+    /// Holds the clipboard open, closing it when dropped.
+    pub struct Clipboard;
+
+    impl Clipboard {
+        pub fn open() -> std::io::Result<Self> {
+            // SAFETY: a null owner associates the clipboard with this task.
+            if unsafe { OpenClipboard(std::ptr::null_mut()) } == 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(Clipboard)
+        }
+    }
+
+    impl Drop for Clipboard {
+        fn drop(&mut self) {
+            // SAFETY: the clipboard was opened by `Clipboard::open`.
+            unsafe { CloseClipboard() };
+        }
+    }
+}
+
+/// Copies `data`, decoded as UTF-8, to the clipboard as Unicode text.
+#[cfg(windows)]
+pub fn copy_to_clipboard(
+    data: &[u8],
+    _vars: &Variables,
+    _stdout: &mut dyn io::Write,
+) -> io::Result<()> {
+    use win32::*;
+
+    let wide: Vec<u16> = String::from_utf8_lossy(data)
+        .encode_utf16()
+        .chain(std::iter::once(0))
+        .collect();
+    let _clipboard = Clipboard::open()?;
+    // SAFETY: the allocation is sized for `wide` and locked while it is
+    // written. Once `SetClipboardData` succeeds the system owns it.
+    unsafe {
+        if EmptyClipboard() == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mem = GlobalAlloc(GMEM_MOVEABLE, wide.len() * 2);
+        if mem.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let ptr = GlobalLock(mem) as *mut u16;
+        if ptr.is_null() {
+            GlobalFree(mem);
+            return Err(io::Error::last_os_error());
+        }
+        std::ptr::copy_nonoverlapping(wide.as_ptr(), ptr, wide.len());
+        GlobalUnlock(mem);
+        if SetClipboardData(CF_UNICODETEXT, mem).is_null() {
+            let err = io::Error::last_os_error();
+            GlobalFree(mem);
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the clipboard's Unicode text as UTF-8.
+#[cfg(windows)]
+pub fn paste_from_clipboard(_vars: &Variables) -> io::Result<Vec<u8>> {
+    use win32::*;
+
+    let _clipboard = Clipboard::open()?;
+    // SAFETY: the handle belongs to the open clipboard and is read only
+    // while locked, up to its NUL terminator.
+    let text = unsafe {
+        let mem = GetClipboardData(CF_UNICODETEXT);
+        if mem.is_null() {
+            return Ok(Vec::new());
+        }
+        let ptr = GlobalLock(mem) as *const u16;
+        if ptr.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        let mut len = 0;
+        while *ptr.add(len) != 0 {
+            len += 1;
+        }
+        let text = String::from_utf16_lossy(std::slice::from_raw_parts(ptr, len));
+        GlobalUnlock(mem);
+        text
+    };
+    Ok(text.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mime_lookup() {
+        let globs = "# comment\n50:application/pdf:*.pdf\n50:application/gzip:*.gz\n55:application/x-compressed-tar:*.tar.gz\n50:text/x-makefile:makefile\n";
+        assert_eq!(
+            match_globs(globs, "report.pdf").as_deref(),
+            Some("application/pdf")
+        );
+        assert_eq!(
+            match_globs(globs, "a.tar.gz").as_deref(),
+            Some("application/x-compressed-tar")
+        );
+        assert_eq!(
+            match_globs(globs, "makefile").as_deref(),
+            Some("text/x-makefile")
+        );
+        assert_eq!(match_globs(globs, "notes.txt"), None);
+
+        assert_eq!(url_scheme("https://example.com").as_deref(), Some("https"));
+        assert_eq!(url_scheme("mailto:a@b.c").as_deref(), Some("mailto"));
+        assert_eq!(url_scheme("C:\\file.txt"), None);
+        assert_eq!(url_scheme("report.pdf"), None);
+        let dirs = XdgDirs::from_vars(&Variables::default());
+        assert_eq!(
+            mime_type("https://example.com", &dirs),
+            "x-scheme-handler/https"
+        );
+        assert_eq!(mime_type("/", &dirs), "inode/directory");
+    }
+
+    #[test]
+    fn test_default_app() {
+        let root = tempfile::tempdir().unwrap();
+        let config = root.path().join("config");
+        let data = root.path().join("data");
+        fs::create_dir_all(&config).unwrap();
+        fs::create_dir_all(data.join("applications/org")).unwrap();
+        fs::write(
+            config.join("mimeapps.list"),
+            "[Added Associations]\napplication/pdf=other.desktop;\n[Default Applications]\napplication/pdf=missing.desktop;org-viewer.desktop;\n",
+        )
+        .unwrap();
+        fs::write(
+            data.join("applications/org/viewer.desktop"),
+            "[Desktop Entry]\nName=Viewer\nExec=viewer --page 1 %U\n[Desktop Action New]\nExec=viewer --new\n",
+        )
+        .unwrap();
+        fs::write(
+            data.join("applications/mimeinfo.cache"),
+            "[MIME Cache]\ntext/plain=org-viewer.desktop;\n",
+        )
+        .unwrap();
+
+        let dirs = XdgDirs {
+            config: vec![config],
+            data: vec![data.clone()],
+        };
+        let app = default_app("application/pdf", &dirs).unwrap();
+        assert_eq!(app, data.join("applications/org/viewer.desktop"));
+        assert!(default_app("text/plain", &dirs).is_some());
+        assert_eq!(default_app("image/png", &dirs), None);
+
+        let exec = desktop_exec(&fs::read_to_string(app).unwrap()).unwrap();
+        assert_eq!(exec, "viewer --page 1 %U");
+    }
+
+    #[test]
+    fn test_expand_exec() {
+        let argv = |exec: &str, target: &str| expand_exec(exec, target).unwrap();
+        assert_eq!(
+            argv("viewer %U", "file:///tmp/a b.pdf"),
+            ["viewer", "file:///tmp/a b.pdf"]
+        );
+        assert_eq!(
+            argv("viewer %f", "file:///tmp/a.pdf"),
+            ["viewer", "/tmp/a.pdf"]
+        );
+        assert_eq!(
+            argv("editor -i %i --name=%c", "x.txt"),
+            ["editor", "-i", "--name=", "x.txt"]
+        );
+        assert_eq!(
+            argv("\"/opt/My App/run\" --url=%u 100%%", "https://a.b"),
+            ["/opt/My App/run", "--url=https://a.b", "100%"]
+        );
+        assert_eq!(expand_exec("\"unterminated %f", "x"), None);
+    }
+
+    #[test]
+    fn test_clipboard_tools() {
+        let names = |wayland, x11, copy| -> Vec<&str> {
+            linux_clipboard_tools(wayland, x11, copy)
+                .into_iter()
+                .map(|(program, _)| program)
+                .collect()
+        };
+        assert_eq!(names(true, true, true), ["wl-copy", "xclip", "xsel"]);
+        assert_eq!(names(false, true, false), ["xclip", "xsel"]);
+        assert!(names(false, false, true).is_empty());
+        assert_eq!(osc52(b"hi"), b"\x1b]52;c;aGk=\x07");
+    }
+}
@@ -0,0 +1,390 @@
+//! Character encoding conversion between Unicode encodings and the common
+//! single-byte legacy encodings, for the `encode` command.
+//!
+//! Text is decoded to a Rust string and then encoded again, so any pair of
+//! supported encodings can be converted. How malformed input and characters
+//! the target cannot represent are handled is chosen by an [`ErrorPolicy`].
+
+/// A supported character encoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// UTF-16 with a byte order mark. Input without one is big-endian;
+    /// output is little-endian.
+    Utf16,
+    Utf16Le,
+    Utf16Be,
+    /// UTF-32 with a byte order mark, as for [`Encoding::Utf16`].
+    Utf32,
+    Utf32Le,
+    Utf32Be,
+    /// ISO-8859-1, whose bytes are the first 256 code points.
+    Latin1,
+    /// Windows-1252, Latin-1 with printable characters in 0x80-0x9F.
+    Windows1252,
+    Ascii,
+}
+
+impl Encoding {
+    pub const ALL: [Encoding; 10] = [
+        Encoding::Utf8,
+        Encoding::Utf16,
+        Encoding::Utf16Le,
+        Encoding::Utf16Be,
+        Encoding::Utf32,
+        Encoding::Utf32Le,
+        Encoding::Utf32Be,
+        Encoding::Latin1,
+        Encoding::Windows1252,
+        Encoding::Ascii,
+    ];
+
+    /// Looks up an encoding by name, ignoring case, `-`, and `_`, so that
+    /// `utf8`, `UTF-8`, and `utf_8` are all accepted. Common aliases such
+    /// as `latin1` and `cp1252` are recognized.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let key: String = name
+            .chars()
+            .filter(|c| !matches!(c, '-' | '_'))
+            .collect::<String>()
+            .to_ascii_uppercase();
+        Some(match key.as_str() {
+            "UTF8" => Encoding::Utf8,
+            "UTF16" | "UCS2" => Encoding::Utf16,
+            "UTF16LE" | "UCS2LE" => Encoding::Utf16Le,
+            "UTF16BE" | "UCS2BE" => Encoding::Utf16Be,
+            "UTF32" | "UCS4" => Encoding::Utf32,
+            "UTF32LE" | "UCS4LE" => Encoding::Utf32Le,
+            "UTF32BE" | "UCS4BE" => Encoding::Utf32Be,
+            "LATIN1" | "ISO88591" | "L1" => Encoding::Latin1,
+            "WINDOWS1252" | "CP1252" => Encoding::Windows1252,
+            "ASCII" | "USASCII" => Encoding::Ascii,
+            _ => return None,
+        })
+    }
+
+    /// The canonical name of the encoding.
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Utf16 => "UTF-16",
+            Encoding::Utf16Le => "UTF-16LE",
+            Encoding::Utf16Be => "UTF-16BE",
+            Encoding::Utf32 => "UTF-32",
+            Encoding::Utf32Le => "UTF-32LE",
+            Encoding::Utf32Be => "UTF-32BE",
+            Encoding::Latin1 => "ISO-8859-1",
+            Encoding::Windows1252 => "WINDOWS-1252",
+            Encoding::Ascii => "ASCII",
+        }
+    }
+}
+
+/// What to do with input that cannot be decoded or characters the target
+/// encoding cannot represent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop with an error.
+    Strict,
+    /// Decode as U+FFFD, and encode as `?` where U+FFFD does not exist.
+    Replace,
+    /// Leave it out.
+    Ignore,
+}
+
+impl ErrorPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "strict" => Some(ErrorPolicy::Strict),
+            "replace" => Some(ErrorPolicy::Replace),
+            "ignore" => Some(ErrorPolicy::Ignore),
+            _ => None,
+        }
+    }
+}
+
+/// Windows-1252 characters for bytes 0x80-0x9F. The five undefined bytes
+/// map to the C1 control characters, as browsers do, so every byte
+/// round-trips.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{81}', '\u{201A}', '\u{192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{2C6}', '\u{2030}', '\u{160}', '\u{2039}', '\u{152}', '\u{8D}', '\u{17D}', '\u{8F}',
+    '\u{90}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{2DC}', '\u{2122}', '\u{161}', '\u{203A}', '\u{153}', '\u{9D}', '\u{17E}', '\u{178}',
+];
+
+/// Decodes `bytes`. Under [`ErrorPolicy::Strict`] the error names the byte
+/// offset of the first malformed sequence.
+pub fn decode(bytes: &[u8], encoding: Encoding, policy: ErrorPolicy) -> Result<String, String> {
+    let mut out = String::with_capacity(bytes.len());
+    let invalid = |offset: usize, what: &str, out: &mut String| match policy {
+        ErrorPolicy::Strict => Err(format!("{} at byte {}", what, offset)),
+        ErrorPolicy::Replace => {
+            out.push(char::REPLACEMENT_CHARACTER);
+            Ok(())
+        }
+        ErrorPolicy::Ignore => Ok(()),
+    };
+
+    match encoding {
+        Encoding::Utf8 => {
+            let mut pos = 0;
+            while pos < bytes.len() {
+                match std::str::from_utf8(&bytes[pos..]) {
+                    Ok(text) => {
+                        out.push_str(text);
+                        break;
+                    }
+                    Err(e) => {
+                        let valid = e.valid_up_to();
+                        // The prefix was just validated
+                        out.push_str(std::str::from_utf8(&bytes[pos..pos + valid]).unwrap_or(""));
+                        invalid(pos + valid, "invalid UTF-8 sequence", &mut out)?;
+                        pos += valid + e.error_len().unwrap_or(bytes.len() - pos - valid);
+                    }
+                }
+            }
+        }
+        Encoding::Utf16 | Encoding::Utf16Le | Encoding::Utf16Be => {
+            let (little, mut pos) = match encoding {
+                Encoding::Utf16Le => (true, 0),
+                Encoding::Utf16Be => (false, 0),
+                _ => match bytes {
+                    [0xFF, 0xFE, ..] => (true, 2),
+                    [0xFE, 0xFF, ..] => (false, 2),
+                    _ => (false, 0),
+                },
+            };
+            let unit = |pos: usize, little: bool| {
+                let pair = [bytes[pos], bytes[pos + 1]];
+                if little {
+                    u16::from_le_bytes(pair)
+                } else {
+                    u16::from_be_bytes(pair)
+                }
+            };
+            while pos + 1 < bytes.len() {
+                let first = unit(pos, little);
+                if (0xD800..0xDC00).contains(&first) {
+                    let second = (pos + 3 < bytes.len()).then(|| unit(pos + 2, little));
+                    if let Some(second @ 0xDC00..0xE000) = second {
+                        let code =
+                            0x10000 + ((first as u32 - 0xD800) << 10) + (second as u32 - 0xDC00);
+                        out.extend(char::from_u32(code));
+                        pos += 4;
+                        continue;
+                    }
+                    invalid(pos, "unpaired surrogate", &mut out)?;
+                } else if let Some(c) = char::from_u32(first as u32) {
+                    out.push(c);
+                } else {
+                    invalid(pos, "unpaired surrogate", &mut out)?;
+                }
+                pos += 2;
+            }
+            if pos < bytes.len() {
+                invalid(pos, "incomplete character", &mut out)?;
+            }
+        }
+        Encoding::Utf32 | Encoding::Utf32Le | Encoding::Utf32Be => {
+            let (little, start) = match encoding {
+                Encoding::Utf32Le => (true, 0),
+                Encoding::Utf32Be => (false, 0),
+                _ => match bytes {
+                    [0xFF, 0xFE, 0, 0, ..] => (true, 4),
+                    [0, 0, 0xFE, 0xFF, ..] => (false, 4),
+                    _ => (false, 0),
+                },
+            };
+            let mut chunks = bytes[start..].chunks_exact(4);
+            for (i, chunk) in chunks.by_ref().enumerate() {
+                let quad = [chunk[0], chunk[1], chunk[2], chunk[3]];
+                let code = if little {
+                    u32::from_le_bytes(quad)
+                } else {
+                    u32::from_be_bytes(quad)
+                };
+                match char::from_u32(code) {
+                    Some(c) => out.push(c),
+                    None => invalid(start + i * 4, "invalid code point", &mut out)?,
+                }
+            }
+            if !chunks.remainder().is_empty() {
+                let offset = bytes.len() - chunks.remainder().len();
+                invalid(offset, "incomplete character", &mut out)?;
+            }
+        }
+        Encoding::Latin1 => out.extend(bytes.iter().map(|&b| b as char)),
+        Encoding::Windows1252 => out.extend(bytes.iter().map(|&b| match b {
+            0x80..=0x9F => WINDOWS_1252_HIGH[b as usize - 0x80],
+            _ => b as char,
+        })),
+        Encoding::Ascii => {
+            for (i, &b) in bytes.iter().enumerate() {
+                if b.is_ascii() {
+                    out.push(b as char);
+                } else {
+                    invalid(i, "non-ASCII byte", &mut out)?;
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Encodes `text`. Under [`ErrorPolicy::Strict`] the error names the first
+/// character the encoding cannot represent and its character offset.
+pub fn encode(text: &str, encoding: Encoding, policy: ErrorPolicy) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(text.len());
+    match encoding {
+        Encoding::Utf8 => out.extend_from_slice(text.as_bytes()),
+        Encoding::Utf16 | Encoding::Utf16Le | Encoding::Utf16Be => {
+            let little = encoding != Encoding::Utf16Be;
+            let units = (encoding == Encoding::Utf16)
+                .then_some(0xFEFF)
+                .into_iter()
+                .chain(text.encode_utf16());
+            for unit in units {
+                if little {
+                    out.extend_from_slice(&unit.to_le_bytes());
+                } else {
+                    out.extend_from_slice(&unit.to_be_bytes());
+                }
+            }
+        }
+        Encoding::Utf32 | Encoding::Utf32Le | Encoding::Utf32Be => {
+            let little = encoding != Encoding::Utf32Be;
+            let codes = (encoding == Encoding::Utf32)
+                .then_some(0xFEFF)
+                .into_iter()
+                .chain(text.chars().map(u32::from));
+            for code in codes {
+                if little {
+                    out.extend_from_slice(&code.to_le_bytes());
+                } else {
+                    out.extend_from_slice(&code.to_be_bytes());
+                }
+            }
+        }
+        Encoding::Latin1 | Encoding::Windows1252 | Encoding::Ascii => {
+            for (i, c) in text.chars().enumerate() {
+                let byte = match encoding {
+                    Encoding::Ascii => c.is_ascii().then_some(c as u8),
+                    Encoding::Latin1 => u8::try_from(u32::from(c)).ok(),
+                    _ => match WINDOWS_1252_HIGH.iter().position(|&h| h == c) {
+                        Some(index) => Some(0x80 + index as u8),
+                        None => u8::try_from(u32::from(c))
+                            .ok()
+                            .filter(|b| !(0x80..=0x9F).contains(b)),
+                    },
+                };
+                match (byte, policy) {
+                    (Some(byte), _) => out.push(byte),
+                    (None, ErrorPolicy::Strict) => {
+                        return Err(format!(
+                            "cannot represent '{}' (U+{:04X}) in {} at character {}",
+                            c,
+                            u32::from(c),
+                            encoding.name(),
+                            i
+                        ));
+                    }
+                    (None, ErrorPolicy::Replace) => out.push(b'?'),
+                    (None, ErrorPolicy::Ignore) => {}
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encoding_roundtrips() {
+        let text = "naïve café €5 𝄞";
+        for encoding in [
+            Encoding::Utf8,
+            Encoding::Utf16,
+            Encoding::Utf16Le,
+            Encoding::Utf16Be,
+            Encoding::Utf32,
+            Encoding::Utf32Be,
+        ] {
+            let bytes = encode(text, encoding, ErrorPolicy::Strict).unwrap();
+            assert_eq!(decode(&bytes, encoding, ErrorPolicy::Strict).unwrap(), text);
+        }
+        assert_eq!(
+            encode("é€", Encoding::Utf16, ErrorPolicy::Strict).unwrap(),
+            [0xFF, 0xFE, 0xE9, 0x00, 0xAC, 0x20]
+        );
+        assert_eq!(
+            decode(
+                &[0x00, 0x41, 0xD8, 0x34, 0xDD, 0x1E],
+                Encoding::Utf16,
+                ErrorPolicy::Strict
+            )
+            .unwrap(),
+            "A𝄞"
+        );
+
+        let legacy = decode(
+            &[0x63, 0x61, 0x66, 0xE9, 0x80],
+            Encoding::Windows1252,
+            ErrorPolicy::Strict,
+        );
+        assert_eq!(legacy.unwrap(), "café€");
+        assert_eq!(
+            encode("café€", Encoding::Windows1252, ErrorPolicy::Strict).unwrap(),
+            [0x63, 0x61, 0x66, 0xE9, 0x80]
+        );
+        assert_eq!(
+            decode(&[0xE9, 0x80], Encoding::Latin1, ErrorPolicy::Strict).unwrap(),
+            "é\u{80}"
+        );
+        assert_eq!(Encoding::from_name("utf_16le"), Some(Encoding::Utf16Le));
+        assert_eq!(Encoding::from_name("Latin-1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::from_name("klingon"), None);
+    }
+
+    #[test]
+    fn test_encoding_error_policies() {
+        let bad = b"ok\xFFfine\xE2\x82";
+        assert_eq!(
+            decode(bad, Encoding::Utf8, ErrorPolicy::Strict).unwrap_err(),
+            "invalid UTF-8 sequence at byte 2"
+        );
+        assert_eq!(
+            decode(bad, Encoding::Utf8, ErrorPolicy::Replace).unwrap(),
+            "ok\u{FFFD}fine\u{FFFD}"
+        );
+        assert_eq!(
+            decode(bad, Encoding::Utf8, ErrorPolicy::Ignore).unwrap(),
+            "okfine"
+        );
+        assert_eq!(
+            decode(
+                &[0x41, 0x00, 0x00, 0xD8],
+                Encoding::Utf16Le,
+                ErrorPolicy::Replace
+            )
+            .unwrap(),
+            "A\u{FFFD}"
+        );
+
+        assert_eq!(
+            encode("a€b", Encoding::Latin1, ErrorPolicy::Strict).unwrap_err(),
+            "cannot represent '€' (U+20AC) in ISO-8859-1 at character 1"
+        );
+        assert_eq!(
+            encode("a€b", Encoding::Ascii, ErrorPolicy::Replace).unwrap(),
+            b"a?b"
+        );
+        assert_eq!(
+            encode("a€b", Encoding::Ascii, ErrorPolicy::Ignore).unwrap(),
+            b"ab"
+        );
+    }
+}
@@ -0,0 +1,434 @@
+//! Arithmetic expression evaluation for `calc`, `expr`, and `$(( ))`.
+//!
+//! Expressions use C-style operators with the usual precedence, from lowest
+//! to highest: `?:`, `||`, `&&`, `|`, `^`, `&`, `== !=`, `< <= > >=`,
+//! `<< >>`, `+ -`, `* / %`, `**` (right-associative), and the unary
+//! operators `- + ! ~`. Integers are 64-bit, and a result too large for
+//! them is an error; any floating-point operand makes the result
+//! floating-point. Comparisons and
+//! logical operators produce 1 or 0. Names refer to variables, looked up by
+//! the caller; unset or empty variables count as 0.
+
+use std::fmt;
+
+/// The result of an expression.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Int(i64),
+    Float(f64),
+}
+
+impl Number {
+    fn as_f64(self) -> f64 {
+        match self {
+            Number::Int(n) => n as f64,
+            Number::Float(f) => f,
+        }
+    }
+
+    fn is_true(self) -> bool {
+        match self {
+            Number::Int(n) => n != 0,
+            Number::Float(f) => f != 0.0,
+        }
+    }
+
+    fn from_bool(b: bool) -> Self {
+        Number::Int(b as i64)
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Number::Int(n) => write!(f, "{}", n),
+            Number::Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(Number),
+    Name(String),
+    Op(&'static str),
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => f.write_str(name),
+            Token::Op(op) => f.write_str(op),
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+        }
+    }
+}
+
+/// The error for an integer result too large for 64 bits.
+const OVERFLOW: &str = "integer overflow";
+
+/// Operators, longest first so that `**` is not read as two `*`.
+const OPERATORS: &[&str] = &[
+    "**", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "<", ">", "!",
+    "~", "&", "|", "^", "?", ":",
+];
+
+fn lex(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit()
+            || (c == '.' && chars.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let start = i;
+            if c == '0' && matches!(chars.get(i + 1), Some('x' | 'X')) {
+                i += 2;
+                while i < chars.len() && chars[i].is_ascii_hexdigit() {
+                    i += 1;
+                }
+                let digits: String = chars[start + 2..i].iter().collect();
+                let n = i64::from_str_radix(&digits, 16)
+                    .map_err(|_| format!("invalid number '0x{}'", digits))?;
+                tokens.push(Token::Number(Number::Int(n)));
+                continue;
+            }
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            // Exponent, as in 1.5e3
+            if i < chars.len()
+                && matches!(chars[i], 'e' | 'E')
+                && chars.get(i + 1).is_some_and(|&d| {
+                    d.is_ascii_digit()
+                        || ((d == '-' || d == '+')
+                            && chars.get(i + 2).is_some_and(char::is_ascii_digit))
+                })
+            {
+                i += 2;
+                while i < chars.len() && chars[i].is_ascii_digit() {
+                    i += 1;
+                }
+            }
+            let text: String = chars[start..i].iter().collect();
+            tokens.push(Token::Number(parse_number(&text)?));
+        } else if c.is_ascii_alphabetic() || c == '_' || c == '$' {
+            // `$name` is accepted as a plain name
+            let start = if c == '$' { i + 1 } else { i };
+            i = start;
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            if i == start {
+                return Err("syntax error: '$' without a name".to_string());
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else if c == '(' {
+            tokens.push(Token::Open);
+            i += 1;
+        } else if c == ')' {
+            tokens.push(Token::Close);
+            i += 1;
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("syntax error: unexpected '{}'", c))?;
+            tokens.push(Token::Op(op));
+            i += op.len();
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses an integer or decimal literal.
+pub fn parse_number(text: &str) -> Result<Number, String> {
+    if let Ok(n) = text.parse::<i64>() {
+        return Ok(Number::Int(n));
+    }
+    text.parse::<f64>()
+        .map(Number::Float)
+        .map_err(|_| format!("invalid number '{}'", text))
+}
+
+#[derive(Debug)]
+enum Node {
+    Number(Number),
+    Name(String),
+    Unary(&'static str, Box<Node>),
+    Binary(&'static str, Box<Node>, Box<Node>),
+    Ternary(Box<Node>, Box<Node>, Box<Node>),
+}
+
+/// Binding power of binary operators; higher binds tighter.
+fn precedence(op: &str) -> Option<u8> {
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | "<=" | ">" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 12,
+        _ => return None,
+    })
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn ternary(&mut self) -> Result<Node, String> {
+        let cond = self.binary(0)?;
+        if self.peek() != Some(&Token::Op("?")) {
+            return Ok(cond);
+        }
+        self.pos += 1;
+        let then = self.ternary()?;
+        if self.next() != Some(Token::Op(":")) {
+            return Err("syntax error: expected ':'".to_string());
+        }
+        let otherwise = self.ternary()?;
+        Ok(Node::Ternary(
+            Box::new(cond),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn binary(&mut self, min: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            let Some(prec) = precedence(op) else {
+                break;
+            };
+            if prec <= min && !(op == "**" && prec == min) {
+                break;
+            }
+            self.pos += 1;
+            // `**` is right-associative, so its right side may contain another `**`
+            let next_min = if op == "**" { prec - 1 } else { prec };
+            let right = self.binary(next_min)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Op(op @ ("-" | "+" | "!" | "~"))) => {
+                // Unary minus binds looser than `**`: -2**2 is -4
+                let operand = self.binary(11)?;
+                Ok(Node::Unary(op, Box::new(operand)))
+            }
+            Some(Token::Number(n)) => Ok(Node::Number(n)),
+            Some(Token::Name(name)) => Ok(Node::Name(name)),
+            Some(Token::Open) => {
+                let inner = self.ternary()?;
+                if self.next() != Some(Token::Close) {
+                    return Err("syntax error: missing ')'".to_string());
+                }
+                Ok(inner)
+            }
+            Some(Token::Close) => Err("syntax error: unexpected ')'".to_string()),
+            Some(Token::Op(op)) => Err(format!("syntax error: unexpected '{}'", op)),
+            None => Err("syntax error: expression expected".to_string()),
+        }
+    }
+}
+
+/// Evaluates `input`, resolving names through `lookup`.
+pub fn evaluate(input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Number, String> {
+    let mut parser = Parser {
+        tokens: lex(input)?,
+        pos: 0,
+    };
+    if parser.tokens.is_empty() {
+        return Err("syntax error: expression expected".to_string());
+    }
+    let tree = parser.ternary()?;
+    if let Some(token) = parser.peek() {
+        return Err(format!("syntax error: unexpected '{}'", token));
+    }
+    eval(&tree, lookup)
+}
+
+fn eval(node: &Node, lookup: &dyn Fn(&str) -> Option<String>) -> Result<Number, String> {
+    match node {
+        Node::Number(n) => Ok(*n),
+        Node::Name(name) => match lookup(name) {
+            None => Ok(Number::Int(0)),
+            Some(value) if value.trim().is_empty() => Ok(Number::Int(0)),
+            Some(value) => parse_number(value.trim())
+                .map_err(|_| format!("{}: value is not a number: '{}'", name, value)),
+        },
+        Node::Unary(op, operand) => {
+            let value = eval(operand, lookup)?;
+            match (*op, value) {
+                ("-", Number::Int(n)) => {
+                    n.checked_neg().map(Number::Int).ok_or(OVERFLOW.to_string())
+                }
+                ("-", Number::Float(f)) => Ok(Number::Float(-f)),
+                ("+", v) => Ok(v),
+                ("!", v) => Ok(Number::from_bool(!v.is_true())),
+                ("~", Number::Int(n)) => Ok(Number::Int(!n)),
+                _ => Err(format!("'{}' needs an integer operand", op)),
+            }
+        }
+        Node::Ternary(cond, then, otherwise) => {
+            if eval(cond, lookup)?.is_true() {
+                eval(then, lookup)
+            } else {
+                eval(otherwise, lookup)
+            }
+        }
+        Node::Binary("&&", left, right) => Ok(Number::from_bool(
+            eval(left, lookup)?.is_true() && eval(right, lookup)?.is_true(),
+        )),
+        Node::Binary("||", left, right) => Ok(Number::from_bool(
+            eval(left, lookup)?.is_true() || eval(right, lookup)?.is_true(),
+        )),
+        Node::Binary(op, left, right) => apply(op, eval(left, lookup)?, eval(right, lookup)?),
+    }
+}
+
+fn apply(op: &str, left: Number, right: Number) -> Result<Number, String> {
+    use Number::{Float, Int};
+
+    match op {
+        "==" => return Ok(Number::from_bool(left.as_f64() == right.as_f64())),
+        "!=" => return Ok(Number::from_bool(left.as_f64() != right.as_f64())),
+        "<" => return Ok(Number::from_bool(left.as_f64() < right.as_f64())),
+        "<=" => return Ok(Number::from_bool(left.as_f64() <= right.as_f64())),
+        ">" => return Ok(Number::from_bool(left.as_f64() > right.as_f64())),
+        ">=" => return Ok(Number::from_bool(left.as_f64() >= right.as_f64())),
+        _ => {}
+    }
+
+    if let (Int(a), Int(b)) = (left, right) {
+        let checked = match op {
+            "+" => a.checked_add(b),
+            "-" => a.checked_sub(b),
+            "*" => a.checked_mul(b),
+            "/" | "%" if b == 0 => return Err("division by 0".to_string()),
+            "/" => a.checked_div(b),
+            "%" => a.checked_rem(b),
+            "**" if b < 0 => return Err("exponent less than 0".to_string()),
+            // Only 0, 1, and -1 have powers this large that fit
+            "**" if b > u32::MAX as i64 => match a {
+                0 | 1 => Some(a),
+                -1 => Some(if b % 2 == 0 { 1 } else { -1 }),
+                _ => None,
+            },
+            "**" => a.checked_pow(b as u32),
+            "&" => Some(a & b),
+            "|" => Some(a | b),
+            "^" => Some(a ^ b),
+            "<<" => Some(a.wrapping_shl(b as u32)),
+            ">>" => Some(a.wrapping_shr(b as u32)),
+            _ => return Err(format!("unknown operator '{}'", op)),
+        };
+        return checked.map(Int).ok_or(OVERFLOW.to_string());
+    }
+
+    let (a, b) = (left.as_f64(), right.as_f64());
+    match op {
+        "+" => Ok(Float(a + b)),
+        "-" => Ok(Float(a - b)),
+        "*" => Ok(Float(a * b)),
+        "/" | "%" if b == 0.0 => Err("division by 0".to_string()),
+        "/" => Ok(Float(a / b)),
+        "%" => Ok(Float(a % b)),
+        "**" => Ok(Float(a.powf(b))),
+        _ => Err(format!("'{}' needs integer operands", op)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calc(input: &str) -> Result<String, String> {
+        let lookup = |name: &str| match name {
+            "x" => Some("5".to_string()),
+            "empty" => Some(String::new()),
+            "word" => Some("abc".to_string()),
+            _ => None,
+        };
+        evaluate(input, &lookup).map(|n| n.to_string())
+    }
+
+    #[test]
+    fn test_evaluate() {
+        assert_eq!(calc("3 * (10 + 2)").unwrap(), "36");
+        assert_eq!(calc("1 + 2 * 3 - 4 / 2").unwrap(), "5");
+        assert_eq!(calc("7 / 2").unwrap(), "3");
+        assert_eq!(calc("7 / 2.0").unwrap(), "3.5");
+        assert_eq!(calc("-7 % 3").unwrap(), "-1");
+        assert_eq!(calc("2 ** 3 ** 2").unwrap(), "512");
+        assert_eq!(calc("-2 ** 2").unwrap(), "-4");
+        assert_eq!(calc("1.5e3 + .5").unwrap(), "1500.5");
+        assert_eq!(calc("0x1F | 1 << 8").unwrap(), "287");
+        assert_eq!(calc("3 > 2 && 2 >= 3").unwrap(), "0");
+        assert_eq!(calc("!0 + ~0").unwrap(), "0");
+        assert_eq!(calc("x > 3 ? x * 2 : 0").unwrap(), "10");
+        assert_eq!(calc("$x + unset + empty").unwrap(), "5");
+        assert_eq!(calc("0 && 1 / 0").unwrap(), "0");
+
+        assert_eq!(calc("1 / 0").unwrap_err(), "division by 0");
+        for overflow in [
+            "9223372036854775807 + 1",
+            "-9223372036854775807 - 2",
+            "4294967296 * 4294967296",
+            "-(-9223372036854775807 - 1)",
+            "(-9223372036854775807 - 1) / -1",
+            "2 ** 63",
+            "2 ** 5000000000",
+        ] {
+            assert_eq!(
+                calc(overflow).unwrap_err(),
+                "integer overflow",
+                "{}",
+                overflow
+            );
+        }
+        assert_eq!(calc("-1 ** 5000000001").unwrap(), "-1");
+        assert_eq!(
+            calc("1 ? 2 : 3 : 4").unwrap_err(),
+            "syntax error: unexpected ':'"
+        );
+        assert!(calc("2 ** -1").is_err());
+        assert!(calc("1.5 & 1").is_err());
+        assert!(calc("(1 + 2").is_err());
+        assert!(calc("1 +").is_err());
+        assert!(calc("1 2").is_err());
+        assert!(calc("word + 1").is_err());
+        assert!(calc("").is_err());
+    }
+}
@@ -0,0 +1,245 @@
+//! The persistent command history behind `history`.
+//!
+//! Every line entered at the prompt is appended to the history file, which
+//! is `$HISTFILE` if set and `~/.0shell_history` otherwise. Each entry is a
+//! single line of three tab-separated fields: the Unix time the line was
+//! entered, the working directory it was entered in, and the line itself.
+//! Tabs, newlines, and backslashes inside the last two fields are written as
+//! `\t`, `\n`, and `\\`.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+
+use crate::command::Variables;
+
+/// One line entered at the prompt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub time: DateTime<Local>,
+    pub dir: String,
+    pub line: String,
+}
+
+impl Entry {
+    /// Formats the entry as a history file line, without the newline.
+    pub fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}",
+            self.time.timestamp(),
+            escape(&self.dir),
+            escape(&self.line)
+        )
+    }
+
+    /// Parses a history file line, returning `None` if it is malformed.
+    pub fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(3, '\t');
+        let secs = fields.next()?.parse().ok()?;
+        let time = Local.timestamp_opt(secs, 0).single()?;
+        let dir = unescape(fields.next()?);
+        let line = unescape(fields.next()?);
+        Some(Self { time, dir, line })
+    }
+}
+
+/// Where the history is kept by `vars`, or `None` if there is nowhere to
+/// keep it.
+pub fn file_path(vars: &Variables) -> Option<PathBuf> {
+    match vars.get("HISTFILE") {
+        Some(path) if !path.is_empty() => Some(PathBuf::from(path)),
+        _ => {
+            let home = vars.get("HOME").filter(|home| !home.is_empty())?;
+            Some(PathBuf::from(home).join(".0shell_history"))
+        }
+    }
+}
+
+/// Appends `line` to the history, entered now in the current directory.
+/// Blank lines are not recorded.
+pub fn record(vars: &Variables, line: &str) -> io::Result<()> {
+    if line.trim().is_empty() {
+        return Ok(());
+    }
+    let Some(path) = file_path(vars) else {
+        return Ok(());
+    };
+    let entry = Entry {
+        time: Local::now(),
+        dir: env::current_dir()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        line: line.to_string(),
+    };
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    // One write per entry keeps lines from concurrent shells whole
+    file.write_all(format!("{}\n", entry.to_line()).as_bytes())
+}
+
+/// Reads the whole history, oldest first. A missing history file is an
+/// empty history, and malformed lines are skipped.
+pub fn load(vars: &Variables) -> io::Result<Vec<Entry>> {
+    let Some(path) = file_path(vars) else {
+        return Ok(Vec::new());
+    };
+    match fs::read(&path) {
+        Ok(data) => Ok(String::from_utf8_lossy(&data)
+            .lines()
+            .filter_map(Entry::parse)
+            .collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A summary of the history for `history --stats`.
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    pub entries: usize,
+    /// When the oldest and newest entries were entered.
+    pub span: Option<(DateTime<Local>, DateTime<Local>)>,
+    /// How often each command was run, most used first.
+    pub commands: Vec<(String, usize)>,
+    /// How many lines were entered in each directory, busiest first.
+    pub dirs: Vec<(String, usize)>,
+    /// How many lines were entered on each day, oldest first.
+    pub days: Vec<(NaiveDate, usize)>,
+}
+
+/// Summarizes `entries`.
+///
+/// Every command on a line counts, so `cd src; ls` counts once for `cd`
+/// and once for `ls`. Variable assignments before a command are skipped.
+pub fn stats(entries: &[Entry]) -> Stats {
+    let mut commands: HashMap<&str, usize> = HashMap::new();
+    let mut dirs: HashMap<&str, usize> = HashMap::new();
+    let mut days: HashMap<NaiveDate, usize> = HashMap::new();
+    for entry in entries {
+        for chunk in entry.line.split(';') {
+            let name = chunk.split_whitespace().find(|word| !is_assignment(word));
+            if let Some(name) = name {
+                *commands.entry(name).or_default() += 1;
+            }
+        }
+        *dirs.entry(&entry.dir).or_default() += 1;
+        *days.entry(entry.time.date_naive()).or_default() += 1;
+    }
+
+    let by_count = |counts: HashMap<&str, usize>| {
+        let mut counts: Vec<(String, usize)> = counts
+            .into_iter()
+            .map(|(key, count)| (key.to_string(), count))
+            .collect();
+        counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        counts
+    };
+    let mut days: Vec<(NaiveDate, usize)> = days.into_iter().collect();
+    days.sort();
+    let span = entries
+        .iter()
+        .map(|entry| entry.time)
+        .min()
+        .zip(entries.iter().map(|entry| entry.time).max());
+
+    Stats {
+        entries: entries.len(),
+        span,
+        commands: by_count(commands),
+        dirs: by_count(dirs),
+        days,
+    }
+}
+
+/// Whether `word` is a `NAME=value` assignment rather than a command.
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c == '_' || c.is_ascii_alphanumeric())
+    })
+}
+
+fn escape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+fn unescape(field: &str) -> String {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_history_entries_and_stats() {
+        let at = |day: u32, hour: u32| Local.with_ymd_and_hms(2026, 3, day, hour, 0, 0).unwrap();
+        let entry = Entry {
+            time: at(9, 10),
+            dir: "/tmp/odd\tdir".to_string(),
+            line: "echo 'a\\tb'".to_string(),
+        };
+        let line = entry.to_line();
+        assert_eq!(line.matches('\t').count(), 2);
+        assert_eq!(Entry::parse(&line), Some(entry));
+        assert_eq!(Entry::parse("ls -l"), None);
+
+        let entries: Vec<Entry> = [
+            (at(9, 10), "/home/u", "ls"),
+            (at(9, 11), "/home/u", "cd src; ls -l"),
+            (at(10, 9), "/home/u/src", "X=1 cat a.txt"),
+            (at(10, 12), "/home/u/src", "ls"),
+        ]
+        .into_iter()
+        .map(|(time, dir, line)| Entry {
+            time,
+            dir: dir.to_string(),
+            line: line.to_string(),
+        })
+        .collect();
+        let stats = stats(&entries);
+        assert_eq!(stats.entries, 4);
+        assert_eq!(stats.span, Some((at(9, 10), at(10, 12))));
+        assert_eq!(
+            stats.commands,
+            [
+                ("ls".to_string(), 3),
+                ("cat".to_string(), 1),
+                ("cd".to_string(), 1)
+            ]
+        );
+        assert_eq!(
+            stats.dirs,
+            [("/home/u".to_string(), 2), ("/home/u/src".to_string(), 2)]
+        );
+        let days: Vec<usize> = stats.days.iter().map(|&(_, count)| count).collect();
+        assert_eq!(days, [2, 2]);
+    }
+}
@@ -0,0 +1,432 @@
+//! A minimal HTTP/1.1 client for `fetch`.
+//!
+//! Supports plain HTTP and HTTPS (through rustls with the Mozilla root
+//! certificates), `Content-Length`, chunked, and read-until-close bodies.
+//! Each request uses its own connection; redirects are left to the caller.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    pub https: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path and query, always starting with `/`.
+    pub path: String,
+}
+
+impl Url {
+    /// Parses an `http://` or `https://` URL. A URL without a scheme is
+    /// taken to be HTTP.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let (https, rest) = if let Some(rest) = text.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = text.strip_prefix("http://") {
+            (false, rest)
+        } else if text.contains("://") {
+            return Err(format!("unsupported scheme in '{}'", text));
+        } else {
+            (false, text)
+        };
+
+        // The fragment is never sent to the server
+        let rest = rest.split('#').next().unwrap_or_default();
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(i) if rest[i..].starts_with('?') => (&rest[..i], format!("/{}", &rest[i..])),
+            Some(i) => (&rest[..i], rest[i..].to_string()),
+            None => (rest, "/".to_string()),
+        };
+        // Credentials are not supported, but should not end up in the host
+        let authority = authority.rsplit('@').next().unwrap_or_default();
+
+        let default_port = if https { 443 } else { 80 };
+        let (host, port) = if let Some(v6) = authority.strip_prefix('[') {
+            let (host, after) = v6
+                .split_once(']')
+                .ok_or_else(|| format!("invalid host in '{}'", text))?;
+            match after.strip_prefix(':') {
+                Some(port) => (host, port),
+                None => (host, ""),
+            }
+        } else {
+            match authority.rsplit_once(':') {
+                Some((host, port)) => (host, port),
+                None => (authority, ""),
+            }
+        };
+        let port = if port.is_empty() {
+            default_port
+        } else {
+            port.parse()
+                .map_err(|_| format!("invalid port in '{}'", text))?
+        };
+        if host.is_empty() {
+            return Err(format!("missing host in '{}'", text));
+        }
+
+        Ok(Self {
+            https,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// Resolves a `Location` header against this URL.
+    pub fn join(&self, location: &str) -> Result<Self, String> {
+        if location.contains("://") {
+            return Self::parse(location);
+        }
+        let scheme = if self.https { "https" } else { "http" };
+        if let Some(rest) = location.strip_prefix("//") {
+            return Self::parse(&format!("{}://{}", scheme, rest));
+        }
+        let path = if location.starts_with('/') {
+            location.to_string()
+        } else {
+            let base = self.path.split('?').next().unwrap_or("/");
+            let dir = &base[..=base.rfind('/').unwrap_or(0)];
+            format!("{}{}", dir, location)
+        };
+        Ok(Self {
+            path,
+            ..self.clone()
+        })
+    }
+
+    /// The last path segment, percent-decoded, if there is one and it is a
+    /// plain file name. A segment that decodes to a path, such as
+    /// `..%2Fnotes`, is not one.
+    pub fn file_name(&self) -> Option<String> {
+        let path = self.path.split('?').next().unwrap_or_default();
+        let name = percent_decode(path.rsplit('/').next()?);
+        let plain = !name.contains(['/', '\\', '\0']) && !std::path::Path::new(&name).is_absolute();
+        (plain && !name.is_empty() && name != "." && name != "..").then_some(name)
+    }
+
+    /// The `Host` header value, which includes the port if it is not the
+    /// default.
+    fn host_header(&self) -> String {
+        let host = if self.host.contains(':') {
+            format!("[{}]", self.host)
+        } else {
+            self.host.clone()
+        };
+        if self.port == if self.https { 443 } else { 80 } {
+            host
+        } else {
+            format!("{}:{}", host, self.port)
+        }
+    }
+}
+
+/// Decodes `%XX` escapes, replacing any invalid UTF-8 that results.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// A connection to the server, plain or encrypted.
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+fn connect(url: &Url) -> io::Result<Box<dyn Stream>> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "no addresses found");
+    let mut tcp = None;
+    for addr in (url.host.as_str(), url.port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                tcp = Some(stream);
+                break;
+            }
+            Err(e) => last_error = e,
+        }
+    }
+    let tcp = tcp.ok_or(last_error)?;
+    tcp.set_read_timeout(Some(READ_TIMEOUT))?;
+
+    if !url.https {
+        return Ok(Box::new(tcp));
+    }
+    let roots = rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder_with_provider(Arc::new(
+        rustls::crypto::ring::default_provider(),
+    ))
+    .with_safe_default_protocol_versions()
+    .map_err(io::Error::other)?
+    .with_root_certificates(roots)
+    .with_no_client_auth();
+    let name = rustls::pki_types::ServerName::try_from(url.host.clone())
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let conn = rustls::ClientConnection::new(Arc::new(config), name).map_err(io::Error::other)?;
+    Ok(Box::new(rustls::StreamOwned::new(conn, tcp)))
+}
+
+/// Sends a GET request with the given extra headers and reads the
+/// response headers. The body is read through the returned `Response`.
+pub fn get(url: &Url, headers: &[(&str, String)]) -> io::Result<Response> {
+    let mut stream = connect(url)?;
+    let mut request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: 0-shell-fetch\r\nAccept: */*\r\nConnection: close\r\n",
+        url.path,
+        url.host_header()
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+    stream.flush()?;
+    Response::read_from(Box::new(BufReader::new(stream)))
+}
+
+/// How the end of the body is found.
+enum Framing {
+    /// Bytes left to read.
+    Length(u64),
+    /// Bytes left in the current chunk, 0 when a chunk header is due;
+    /// `done` is set once the last chunk has been read.
+    Chunked {
+        left: u64,
+        done: bool,
+    },
+    UntilClose,
+}
+
+pub struct Response {
+    pub status: u16,
+    pub reason: String,
+    headers: Vec<(String, String)>,
+    reader: Box<dyn BufRead + Send>,
+    framing: Framing,
+}
+
+impl Response {
+    /// Parses the status line and headers from `reader`.
+    fn read_from(mut reader: Box<dyn BufRead + Send>) -> io::Result<Self> {
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        let status_line = read_line(&mut reader)?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = parts.next().unwrap_or_default();
+        if !version.starts_with("HTTP/") {
+            return Err(invalid("not an HTTP response"));
+        }
+        let status = parts
+            .next()
+            .and_then(|s| s.parse().ok())
+            .ok_or_else(|| invalid("invalid status line"))?;
+        let reason = parts.next().unwrap_or_default().to_string();
+
+        let mut headers = Vec::new();
+        loop {
+            let line = read_line(&mut reader)?;
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let mut response = Self {
+            status,
+            reason,
+            headers,
+            reader,
+            framing: Framing::UntilClose,
+        };
+        response.framing = if status == 204 || status == 304 {
+            Framing::Length(0)
+        } else if response
+            .header("transfer-encoding")
+            .is_some_and(|te| te.to_ascii_lowercase().contains("chunked"))
+        {
+            Framing::Chunked {
+                left: 0,
+                done: false,
+            }
+        } else if let Some(len) = response.content_length() {
+            Framing::Length(len)
+        } else {
+            Framing::UntilClose
+        };
+        Ok(response)
+    }
+
+    /// The value of the first header called `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&str> {
+        let name = name.to_ascii_lowercase();
+        self.headers
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// The body length announced by the server, if any.
+    pub fn content_length(&self) -> Option<u64> {
+        self.header("content-length")?.parse().ok()
+    }
+}
+
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.framing {
+            Framing::Length(0) => Ok(0),
+            Framing::Length(left) => {
+                let max = buf.len().min(*left as usize);
+                let n = self.reader.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before the end of the body",
+                    ));
+                }
+                *left -= n as u64;
+                Ok(n)
+            }
+            Framing::Chunked { done: true, .. } => Ok(0),
+            Framing::Chunked { left, done } => {
+                if *left == 0 {
+                    let line = read_line(&mut self.reader)?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    *left = u64::from_str_radix(size, 16).map_err(|_| {
+                        io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size")
+                    })?;
+                    if *left == 0 {
+                        // Skip any trailer headers
+                        while !read_line(&mut self.reader)?.is_empty() {}
+                        *done = true;
+                        return Ok(0);
+                    }
+                }
+                let max = buf.len().min(*left as usize);
+                let n = self.reader.read(&mut buf[..max])?;
+                if n == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed inside a chunk",
+                    ));
+                }
+                *left -= n as u64;
+                if *left == 0 {
+                    read_line(&mut self.reader)?; // CRLF after the chunk data
+                }
+                Ok(n)
+            }
+            Framing::UntilClose => match self.reader.read(buf) {
+                // Many servers close TLS connections without a close_notify
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+                result => result,
+            },
+        }
+    }
+}
+
+/// Reads one CRLF- or LF-terminated line, without the line ending.
+fn read_line(reader: &mut dyn BufRead) -> io::Result<String> {
+    let mut line = Vec::new();
+    if reader.read_until(b'\n', &mut line)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "connection closed unexpectedly",
+        ));
+    }
+    while line.last().is_some_and(|&b| b == b'\n' || b == b'\r') {
+        line.pop();
+    }
+    Ok(String::from_utf8_lossy(&line).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        let url = Url::parse("https://example.com/a/b%20c.txt?x=1#top").unwrap();
+        assert!(url.https);
+        assert_eq!((url.host.as_str(), url.port), ("example.com", 443));
+        assert_eq!(url.path, "/a/b%20c.txt?x=1");
+        assert_eq!(url.file_name().as_deref(), Some("b c.txt"));
+        assert_eq!(url.join("d").unwrap().path, "/a/d");
+        assert_eq!(url.join("/e").unwrap().path, "/e");
+        assert_eq!(url.join("//other.org/f").unwrap().host, "other.org");
+
+        let url = Url::parse("[::1]:8080?q").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("::1", 8080));
+        assert_eq!(url.path, "/?q");
+        assert_eq!(url.host_header(), "[::1]:8080");
+        assert_eq!(url.file_name(), None);
+
+        for path in [
+            "/..%2F..%2Fescaped.txt",
+            "/%2Fetc%2Fpasswd",
+            "/a%5Cb",
+            "/%2E%2E",
+        ] {
+            let url = Url::parse(&format!("http://example.com{}", path)).unwrap();
+            assert_eq!(url.file_name(), None, "{}", path);
+        }
+
+        assert!(Url::parse("ftp://example.com").is_err());
+        assert!(Url::parse("http://host:port/").is_err());
+    }
+
+    #[test]
+    fn test_response_body() {
+        let read = |raw: &'static str| {
+            let mut response = Response::read_from(Box::new(raw.as_bytes())).unwrap();
+            let mut body = String::new();
+            response.read_to_string(&mut body).map(|_| body)
+        };
+
+        assert_eq!(
+            read("HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello, extra").unwrap(),
+            "hello"
+        );
+        assert_eq!(
+            read("HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nWiki\r\n5;x=y\r\npedia\r\n0\r\nT: 1\r\n\r\n").unwrap(),
+            "Wikipedia"
+        );
+        assert_eq!(
+            read("HTTP/1.0 200 OK\n\nuntil close").unwrap(),
+            "until close"
+        );
+        assert!(read("HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nshort").is_err());
+
+        let response = Response::read_from(Box::new(
+            &b"HTTP/1.1 404 Not Found\r\nX-Test: a\r\n\r\n"[..],
+        ))
+        .unwrap();
+        assert_eq!(
+            (response.status, response.reason.as_str()),
+            (404, "Not Found")
+        );
+        assert_eq!(response.header("x-TEST"), Some("a"));
+    }
+}
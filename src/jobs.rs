@@ -0,0 +1,131 @@
+//! Deferred jobs for `schedule`: command lines that run once, when their
+//! time comes.
+//!
+//! Jobs live only as long as the shell session, in the [`JobTable`] of its
+//! state. Nothing runs them in the background: the shell's main loop takes
+//! the jobs that are due and runs them between prompts, so they never race
+//! the prompt for the current directory, the variables, or the terminal.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use chrono::{DateTime, Local};
+
+use crate::command::ShellState;
+
+/// A job waiting to run.
+#[derive(Debug, Clone)]
+pub struct Job {
+    pub id: usize,
+    pub due: DateTime<Local>,
+    /// The command line the job runs.
+    pub label: String,
+    /// The session's state when the job was scheduled, which it runs with.
+    pub state: ShellState,
+    /// The directory the job was scheduled in, which it runs in.
+    pub dir: Option<PathBuf>,
+}
+
+/// A shell's pending jobs. Clones share the same table, so jobs scheduled
+/// by a command run on another thread, or by another job, still reach the
+/// shell.
+#[derive(Debug, Clone, Default)]
+pub struct JobTable {
+    /// The jobs in the order they were scheduled.
+    jobs: Arc<Mutex<Vec<Job>>>,
+    /// The id given to the most recently scheduled job.
+    last_id: Arc<AtomicUsize>,
+}
+
+impl JobTable {
+    fn lock(&self) -> MutexGuard<'_, Vec<Job>> {
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Schedules `line` to run at `due`, with `state` and in `dir`, and
+    /// returns the job's id. Ids count up from 1 for the session.
+    pub fn schedule(
+        &self,
+        due: DateTime<Local>,
+        line: String,
+        state: ShellState,
+        dir: Option<PathBuf>,
+    ) -> usize {
+        let id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        self.lock().push(Job {
+            id,
+            due,
+            label: line,
+            state,
+            dir,
+        });
+        id
+    }
+
+    /// Lists the pending jobs, soonest first.
+    pub fn list(&self) -> Vec<Job> {
+        let mut jobs = self.lock().clone();
+        jobs.sort_by_key(|job| (job.due, job.id));
+        jobs
+    }
+
+    /// Cancels a pending job. Returns `false` if there is no such job, for
+    /// example because it has already run.
+    pub fn cancel(&self, id: usize) -> bool {
+        let mut jobs = self.lock();
+        let Some(index) = jobs.iter().position(|job| job.id == id) else {
+            return false;
+        };
+        jobs.remove(index);
+        true
+    }
+
+    /// When the soonest pending job is due.
+    pub fn next_due(&self) -> Option<DateTime<Local>> {
+        self.lock().iter().map(|job| job.due).min()
+    }
+
+    /// Removes the jobs due by `now` and returns them, soonest first, for
+    /// the caller to run.
+    pub fn take_due(&self, now: DateTime<Local>) -> Vec<Job> {
+        let mut jobs = self.lock();
+        let (mut due, pending) = jobs.drain(..).partition(|job: &Job| job.due <= now);
+        *jobs = pending;
+        due.sort_by_key(|job: &Job| (job.due, job.id));
+        due
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jobs_fall_due_and_cancel() {
+        let now = Local::now();
+        let soon = now + chrono::Duration::seconds(1);
+        let later = now + chrono::Duration::hours(1);
+
+        let table = JobTable::default();
+        let schedule =
+            |due, line: &str| table.schedule(due, line.to_string(), ShellState::default(), None);
+        let second = schedule(later, "second");
+        let first = schedule(soon, "first");
+        let labels: Vec<String> = table.list().into_iter().map(|job| job.label).collect();
+        assert_eq!(labels, ["first", "second"]);
+        assert_eq!(table.next_due(), Some(soon));
+
+        assert!(table.take_due(now).is_empty());
+        let due = table.take_due(soon);
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].id, first);
+        // A job that has been taken to run can no longer be cancelled
+        assert!(!table.cancel(first));
+
+        assert!(table.cancel(second));
+        assert!(!table.cancel(second));
+        assert!(table.list().is_empty());
+        assert_eq!(table.next_due(), None);
+    }
+}
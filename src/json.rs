@@ -0,0 +1,559 @@
+//! JSON parsing, printing, and simple path queries for the `json` command.
+//!
+//! Object keys keep their original order and numbers keep their original
+//! text, so pretty-printing never changes a document's content. Queries are
+//! jq-style paths: `.`, `.name`, `."odd key"`, `["key"]`, `[N]` (negative
+//! counts from the end), and `[]` to iterate over every element.
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    /// The number exactly as written.
+    Number(String),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        }
+    }
+}
+
+/// Parses every JSON value in `text`. Values may follow one another
+/// directly or be separated by whitespace, as in JSON Lines.
+pub fn parse_all(text: &str) -> Result<Vec<Value>, String> {
+    let mut parser = Parser {
+        text: text.as_bytes(),
+        pos: 0,
+    };
+    let mut values = Vec::new();
+    parser.skip_whitespace();
+    while parser.pos < parser.text.len() {
+        values.push(parser.value(0).map_err(|e| parser.locate(&e))?);
+        parser.skip_whitespace();
+    }
+    Ok(values)
+}
+
+/// Deep nesting is rejected rather than overflowing the stack.
+const MAX_DEPTH: usize = 512;
+
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    /// Adds the line and column of the current position to `message`.
+    fn locate(&self, message: &str) -> String {
+        let before = &self.text[..self.pos.min(self.text.len())];
+        let line = before.iter().filter(|&&b| b == b'\n').count() + 1;
+        let column = before.len()
+            - before
+                .iter()
+                .rposition(|&b| b == b'\n')
+                .map_or(0, |i| i + 1)
+            + 1;
+        format!("{} at line {}, column {}", message, line, column)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .text
+            .get(self.pos)
+            .is_some_and(|b| matches!(b, b' ' | b'\t' | b'\n' | b'\r'))
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, literal: &str) -> Result<(), String> {
+        if self.text[self.pos..].starts_with(literal.as_bytes()) {
+            self.pos += literal.len();
+            Ok(())
+        } else {
+            Err(format!("expected '{}'", literal))
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Value, String> {
+        if depth > MAX_DEPTH {
+            return Err("too deeply nested".to_string());
+        }
+        self.skip_whitespace();
+        match self.text.get(self.pos) {
+            None => Err("unexpected end of input".to_string()),
+            Some(b'n') => self.expect("null").map(|_| Value::Null),
+            Some(b't') => self.expect("true").map(|_| Value::Bool(true)),
+            Some(b'f') => self.expect("false").map(|_| Value::Bool(false)),
+            Some(b'"') => self.string().map(Value::String),
+            Some(b'[') => {
+                self.pos += 1;
+                let mut items = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.pos) == Some(&b']') {
+                    self.pos += 1;
+                    return Ok(Value::Array(items));
+                }
+                loop {
+                    items.push(self.value(depth + 1)?);
+                    self.skip_whitespace();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b']') => {
+                            self.pos += 1;
+                            return Ok(Value::Array(items));
+                        }
+                        _ => return Err("expected ',' or ']'".to_string()),
+                    }
+                }
+            }
+            Some(b'{') => {
+                self.pos += 1;
+                let mut fields = Vec::new();
+                self.skip_whitespace();
+                if self.text.get(self.pos) == Some(&b'}') {
+                    self.pos += 1;
+                    return Ok(Value::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    if self.text.get(self.pos) != Some(&b'"') {
+                        return Err("expected a string key".to_string());
+                    }
+                    let key = self.string()?;
+                    self.skip_whitespace();
+                    self.expect(":")?;
+                    fields.push((key, self.value(depth + 1)?));
+                    self.skip_whitespace();
+                    match self.text.get(self.pos) {
+                        Some(b',') => self.pos += 1,
+                        Some(b'}') => {
+                            self.pos += 1;
+                            return Ok(Value::Object(fields));
+                        }
+                        _ => return Err("expected ',' or '}'".to_string()),
+                    }
+                }
+            }
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err("unexpected character".to_string()),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        let digits = |p: &mut Self| {
+            let from = p.pos;
+            while p.text.get(p.pos).is_some_and(u8::is_ascii_digit) {
+                p.pos += 1;
+            }
+            p.pos > from
+        };
+
+        if self.text[self.pos] == b'-' {
+            self.pos += 1;
+        }
+        if self.text.get(self.pos) == Some(&b'0') {
+            self.pos += 1;
+        } else if !digits(self) {
+            return Err("invalid number".to_string());
+        }
+        if self.text.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            if !digits(self) {
+                return Err("invalid number".to_string());
+            }
+        }
+        if matches!(self.text.get(self.pos), Some(b'e' | b'E')) {
+            self.pos += 1;
+            if matches!(self.text.get(self.pos), Some(b'+' | b'-')) {
+                self.pos += 1;
+            }
+            if !digits(self) {
+                return Err("invalid number".to_string());
+            }
+        }
+        let text = String::from_utf8_lossy(&self.text[start..self.pos]).into_owned();
+        Ok(Value::Number(text))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.pos += 1; // opening quote
+        let mut bytes = Vec::new();
+        loop {
+            let Some(&b) = self.text.get(self.pos) else {
+                return Err("unterminated string".to_string());
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let escape = self.text.get(self.pos).copied();
+                    self.pos += 1;
+                    let c = match escape {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => self.unicode_escape()?,
+                        _ => return Err("invalid escape".to_string()),
+                    };
+                    let mut buf = [0; 4];
+                    bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                }
+                0..=0x1F => return Err("control character in string".to_string()),
+                _ => bytes.push(b),
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| "invalid UTF-8 in string".to_string())
+    }
+
+    /// Decodes `\uXXXX`, combining a surrogate pair into one character.
+    fn unicode_escape(&mut self) -> Result<char, String> {
+        let high = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&high) {
+            if !self.text[self.pos..].starts_with(b"\\u") {
+                return Err("unpaired surrogate".to_string());
+            }
+            self.pos += 2;
+            let low = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&low) {
+                return Err("unpaired surrogate".to_string());
+            }
+            0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+        } else {
+            high
+        };
+        char::from_u32(code).ok_or_else(|| "invalid \\u escape".to_string())
+    }
+
+    fn hex4(&mut self) -> Result<u32, String> {
+        let hex = self
+            .text
+            .get(self.pos..self.pos + 4)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u32::from_str_radix(h, 16).ok())
+            .ok_or("invalid \\u escape")?;
+        self.pos += 4;
+        Ok(hex)
+    }
+}
+
+/// Colors used when printing to a terminal, similar to jq's defaults.
+const KEY_COLOR: &str = "\x1b[34;1m";
+const STRING_COLOR: &str = "\x1b[32m";
+const LITERAL_COLOR: &str = "\x1b[90m";
+
+/// Formats `value` with `indent` spaces per level, or on one line when
+/// `indent` is `None`. With `color`, keys, strings, and literals are
+/// highlighted with ANSI escapes.
+pub fn format(value: &Value, indent: Option<usize>, color: bool) -> String {
+    let mut out = String::new();
+    write_value(&mut out, value, indent, color, 0);
+    out
+}
+
+fn write_value(out: &mut String, value: &Value, indent: Option<usize>, color: bool, level: usize) {
+    let paint = |out: &mut String, code: &str, text: &str| {
+        if color {
+            let _ = write!(out, "{}{}{}", code, text, crate::terminal::RESET);
+        } else {
+            out.push_str(text);
+        }
+    };
+
+    match value {
+        Value::Null => paint(out, LITERAL_COLOR, "null"),
+        Value::Bool(b) => paint(out, LITERAL_COLOR, if *b { "true" } else { "false" }),
+        Value::Number(n) => out.push_str(n),
+        Value::String(s) => paint(out, STRING_COLOR, &quote(s)),
+        Value::Array(items) if items.is_empty() => out.push_str("[]"),
+        Value::Object(fields) if fields.is_empty() => out.push_str("{}"),
+        Value::Array(items) => {
+            out.push('[');
+            for (i, item) in items.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, indent, level + 1);
+                write_value(out, item, indent, color, level + 1);
+            }
+            newline(out, indent, level);
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            out.push('{');
+            for (i, (key, item)) in fields.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                newline(out, indent, level + 1);
+                paint(out, KEY_COLOR, &quote(key));
+                out.push(':');
+                if indent.is_some() {
+                    out.push(' ');
+                }
+                write_value(out, item, indent, color, level + 1);
+            }
+            newline(out, indent, level);
+            out.push('}');
+        }
+    }
+}
+
+fn newline(out: &mut String, indent: Option<usize>, level: usize) {
+    if let Some(width) = indent {
+        out.push('\n');
+        out.push_str(&" ".repeat(width * level));
+    }
+}
+
+/// Quotes `s` as a JSON string.
+pub fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 || c == '\u{7F}' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Key(String),
+    Index(i64),
+    Iterate,
+}
+
+/// A parsed path query such as `.items[0].name`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    segments: Vec<Segment>,
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let chars: Vec<char> = text.trim().chars().collect();
+        if chars.first() != Some(&'.') && chars.first() != Some(&'[') {
+            return Err(format!("invalid query '{}': must start with '.'", text));
+        }
+
+        let mut segments = Vec::new();
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '.' => {
+                    i += 1;
+                    match chars.get(i) {
+                        Some('"') => {
+                            let (key, next) = quoted_key(&chars, i)
+                                .ok_or_else(|| format!("invalid query '{}'", text))?;
+                            segments.push(Segment::Key(key));
+                            i = next;
+                        }
+                        Some(c) if c.is_alphanumeric() || *c == '_' => {
+                            let start = i;
+                            while chars
+                                .get(i)
+                                .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+                            {
+                                i += 1;
+                            }
+                            segments.push(Segment::Key(chars[start..i].iter().collect()));
+                        }
+                        // `.` alone, or `.[`
+                        None | Some('[') => {}
+                        Some(_) => return Err(format!("invalid query '{}'", text)),
+                    }
+                }
+                '[' => {
+                    let close = chars[i..]
+                        .iter()
+                        .position(|&c| c == ']')
+                        .map(|p| p + i)
+                        .ok_or_else(|| format!("invalid query '{}': missing ']'", text))?;
+                    let inner: String = chars[i + 1..close].iter().collect();
+                    let inner = inner.trim();
+                    let segment = if inner.is_empty() {
+                        Segment::Iterate
+                    } else if inner.starts_with('"') {
+                        let inner: Vec<char> = inner.chars().collect();
+                        match quoted_key(&inner, 0) {
+                            Some((key, end)) if end == inner.len() => Segment::Key(key),
+                            _ => return Err(format!("invalid query '{}'", text)),
+                        }
+                    } else {
+                        Segment::Index(
+                            inner
+                                .parse()
+                                .map_err(|_| format!("invalid index '{}'", inner))?,
+                        )
+                    };
+                    segments.push(segment);
+                    i = close + 1;
+                }
+                _ => return Err(format!("invalid query '{}'", text)),
+            }
+        }
+        Ok(Self { segments })
+    }
+
+    /// Returns every value the query selects from `root`. Missing keys and
+    /// out-of-range indices select `null`, as in jq.
+    pub fn apply<'a>(&self, root: &'a Value) -> Result<Vec<&'a Value>, String> {
+        const NULL: &Value = &Value::Null;
+        let mut current = vec![root];
+        for segment in &self.segments {
+            let mut next = Vec::new();
+            for value in current {
+                match (segment, value) {
+                    (_, Value::Null) if *segment != Segment::Iterate => next.push(NULL),
+                    (Segment::Key(key), Value::Object(fields)) => next.push(
+                        fields
+                            .iter()
+                            .rev()
+                            .find(|(k, _)| k == key)
+                            .map_or(NULL, |(_, v)| v),
+                    ),
+                    (Segment::Index(index), Value::Array(items)) => {
+                        let i = if *index < 0 {
+                            items.len() as i64 + index
+                        } else {
+                            *index
+                        };
+                        next.push(
+                            usize::try_from(i)
+                                .ok()
+                                .and_then(|i| items.get(i))
+                                .unwrap_or(NULL),
+                        );
+                    }
+                    (Segment::Iterate, Value::Array(items)) => next.extend(items),
+                    (Segment::Iterate, Value::Object(fields)) => {
+                        next.extend(fields.iter().map(|(_, v)| v))
+                    }
+                    (Segment::Key(key), other) => {
+                        return Err(format!(
+                            "cannot index {} with \"{}\"",
+                            other.type_name(),
+                            key
+                        ));
+                    }
+                    (Segment::Index(index), other) => {
+                        return Err(format!("cannot index {} with {}", other.type_name(), index));
+                    }
+                    (Segment::Iterate, other) => {
+                        return Err(format!("cannot iterate over {}", other.type_name()));
+                    }
+                }
+            }
+            current = next;
+        }
+        Ok(current)
+    }
+}
+
+/// Reads a double-quoted key starting at `chars[start]`, returning it and
+/// the index after the closing quote.
+fn quoted_key(chars: &[char], start: usize) -> Option<(String, usize)> {
+    let mut key = String::new();
+    let mut i = start + 1;
+    loop {
+        match chars.get(i)? {
+            '"' => return Some((key, i + 1)),
+            '\\' => {
+                i += 1;
+                key.push(*chars.get(i)?);
+            }
+            c => key.push(*c),
+        }
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_format() {
+        let text = r#"{"name": "zero", "tags": ["a", "é😀"], "n": -1.5e3, "ok": true, "none": null, "empty": {}}"#;
+        let values = parse_all(text).unwrap();
+        assert_eq!(values.len(), 1);
+        assert_eq!(
+            format(&values[0], None, false),
+            r#"{"name":"zero","tags":["a","é😀"],"n":-1.5e3,"ok":true,"none":null,"empty":{}}"#
+        );
+        assert_eq!(
+            format(&parse_all("[1, {\"a\": [] }]").unwrap()[0], Some(2), false),
+            "[\n  1,\n  {\n    \"a\": []\n  }\n]"
+        );
+        assert_eq!(quote("a\"b\\\n\u{1}"), r#""a\"b\\\n\u0001""#);
+
+        assert_eq!(parse_all("1 2\n{}").unwrap().len(), 3);
+        assert_eq!(parse_all("  ").unwrap().len(), 0);
+        assert_eq!(
+            parse_all("{\"a\": 1,\n  \"b\" 2}").unwrap_err(),
+            "expected ':' at line 2, column 7"
+        );
+        assert!(parse_all("[1,]").is_err());
+        assert!(parse_all("\"abc").is_err());
+        assert!(parse_all("[1.]").is_err());
+    }
+
+    #[test]
+    fn test_query() {
+        let doc =
+            &parse_all(r#"{"items": [{"name": "a"}, {"name": "b"}], "odd key": 3}"#).unwrap()[0];
+        let run = |q: &str| -> Result<Vec<String>, String> {
+            Ok(Query::parse(q)?
+                .apply(doc)?
+                .iter()
+                .map(|v| format(v, None, false))
+                .collect())
+        };
+
+        assert_eq!(run(".items[0].name").unwrap(), vec!["\"a\""]);
+        assert_eq!(run(".items[-1].name").unwrap(), vec!["\"b\""]);
+        assert_eq!(run(".items[].name").unwrap(), vec!["\"a\"", "\"b\""]);
+        assert_eq!(run(".\"odd key\"").unwrap(), vec!["3"]);
+        assert_eq!(run(".[\"odd key\"]").unwrap(), vec!["3"]);
+        assert_eq!(run(".missing.deeper").unwrap(), vec!["null"]);
+        assert_eq!(run(".items[5]").unwrap(), vec!["null"]);
+        assert_eq!(run(".").unwrap().len(), 1);
+        assert_eq!(
+            run(".items.name").unwrap_err(),
+            "cannot index array with \"name\""
+        );
+        assert!(run("items").is_err());
+        assert!(run(".items[x]").is_err());
+    }
+}
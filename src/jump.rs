@@ -0,0 +1,196 @@
+//! The directory database behind `j`.
+//!
+//! Every directory changed into with `cd` or `j` is recorded in the file of
+//! the session's [`ShellState::dirs_file`], `~/.0shell_dirs` for the shell
+//! itself, one `path|rank|time` line per directory. The rank grows
+//! by one with each visit, and the time is the Unix time of the latest one.
+//! Ranks are scaled down whenever they add up to more than [`MAX_TOTAL`], so
+//! directories that are no longer used drop out.
+//!
+//! Matches are ordered by frecency: the rank, weighted by how recently the
+//! directory was visited.
+//!
+//! [`ShellState::dirs_file`]: crate::command::ShellState::dirs_file
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+
+use crate::command::Variables;
+
+/// The total rank above which all ranks are aged.
+pub const MAX_TOTAL: f64 = 9000.0;
+
+/// A directory in the database.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dir {
+    pub path: String,
+    pub rank: f64,
+    /// Unix time of the most recent visit.
+    pub last: i64,
+}
+
+impl Dir {
+    /// The directory's score at Unix time `now`.
+    pub fn frecency(&self, now: i64) -> f64 {
+        let age = now - self.last;
+        let weight = if age < 3600 {
+            4.0
+        } else if age < 86400 {
+            2.0
+        } else if age < 7 * 86400 {
+            0.5
+        } else {
+            0.25
+        };
+        self.rank * weight
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        // The path comes first and may itself contain `|`
+        let mut fields = line.rsplitn(3, '|');
+        let last = fields.next()?.parse().ok()?;
+        let rank = fields.next()?.parse().ok()?;
+        let path = fields.next()?.to_string();
+        Some(Self { path, rank, last })
+    }
+}
+
+/// Where the shell keeps the database, `~/.0shell_dirs` by the `HOME` in
+/// `vars`, or `None` without a home directory.
+pub fn file_path(vars: &Variables) -> Option<PathBuf> {
+    let home = vars.get("HOME").filter(|home| !home.is_empty())?;
+    Some(PathBuf::from(home).join(".0shell_dirs"))
+}
+
+/// Reads the database in `path`. A missing file is an empty database.
+pub fn load(path: &Path) -> io::Result<Vec<Dir>> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text.lines().filter_map(Dir::parse).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Records a visit to `dir`, now, in the database in `path`.
+pub fn visit(path: &Path, dir: &Path) -> io::Result<()> {
+    let dir = dir.to_string_lossy();
+    // Such a path would break the one-line-per-directory format
+    if dir.contains('\n') {
+        return Ok(());
+    }
+    let mut dirs = load(path)?;
+    add(&mut dirs, &dir, Local::now().timestamp());
+
+    let text: String = dirs
+        .iter()
+        .map(|d| format!("{}|{}|{}\n", d.path, d.rank, d.last))
+        .collect();
+    // Replace the file whole so that a reader never sees half of it
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, text)?;
+    fs::rename(&tmp, path)
+}
+
+/// Adds a visit to `path` at Unix time `now`, ageing the ranks if they
+/// have grown too large.
+fn add(dirs: &mut Vec<Dir>, path: &str, now: i64) {
+    match dirs.iter_mut().find(|d| d.path == path) {
+        Some(dir) => {
+            dir.rank += 1.0;
+            dir.last = now;
+        }
+        None => dirs.push(Dir {
+            path: path.to_string(),
+            rank: 1.0,
+            last: now,
+        }),
+    }
+
+    if dirs.iter().map(|d| d.rank).sum::<f64>() > MAX_TOTAL {
+        for dir in dirs.iter_mut() {
+            dir.rank *= 0.99;
+        }
+        dirs.retain(|d| d.rank >= 1.0);
+    }
+}
+
+/// The directories matching `terms`, best first, with their scores.
+///
+/// The terms must appear in the path in the order given, ignoring case,
+/// and the last must be in the final component: `j pro src` matches
+/// `~/projects/shell/src` but not `~/src/projects`. Without terms every
+/// directory matches.
+pub fn matches<'a>(dirs: &'a [Dir], terms: &[String], now: i64) -> Vec<(f64, &'a Dir)> {
+    let terms: Vec<String> = terms.iter().map(|t| t.to_lowercase()).collect();
+    let mut found: Vec<(f64, &Dir)> = dirs
+        .iter()
+        .filter(|dir| is_match(&dir.path.to_lowercase(), &terms))
+        .map(|dir| (dir.frecency(now), dir))
+        .collect();
+    found.sort_by(|a, b| b.0.total_cmp(&a.0).then_with(|| a.1.path.cmp(&b.1.path)));
+    found
+}
+
+fn is_match(path: &str, terms: &[String]) -> bool {
+    let Some((last, rest)) = terms.split_last() else {
+        return true;
+    };
+    let mut pos = 0;
+    for term in rest {
+        match path[pos..].find(term.as_str()) {
+            Some(i) => pos += i + term.len(),
+            None => return false,
+        }
+    }
+    let name_start = path.trim_end_matches('/').rfind('/').map_or(0, |i| i + 1);
+    path[pos.max(name_start)..].contains(last.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_jump_ranking() {
+        let now = 1_000_000;
+        let mut dirs = Vec::new();
+        add(&mut dirs, "/home/u/projects/shell/src", now - 100);
+        add(&mut dirs, "/home/u/src/projects", now - 10 * 86400);
+        add(&mut dirs, "/home/u/src/projects", now - 10 * 86400);
+        add(&mut dirs, "/tmp/a|b", now);
+        assert_eq!(dirs[1].rank, 2.0);
+        assert_eq!(
+            Dir::parse("/tmp/a|b|1.5|42"),
+            Some(Dir {
+                path: "/tmp/a|b".to_string(),
+                rank: 1.5,
+                last: 42
+            })
+        );
+
+        let paths = |terms: &[&str]| -> Vec<&str> {
+            let terms: Vec<String> = terms.iter().map(|t| t.to_string()).collect();
+            matches(&dirs, &terms, now)
+                .into_iter()
+                .map(|(_, d)| d.path.as_str())
+                .collect()
+        };
+        assert_eq!(paths(&["pro", "src"]), ["/home/u/projects/shell/src"]);
+        assert_eq!(paths(&["PROJ"]), ["/home/u/src/projects"]);
+        // A recent visit outweighs two old ones
+        assert_eq!(
+            paths(&["home", "s"]),
+            ["/home/u/projects/shell/src", "/home/u/src/projects"]
+        );
+        assert_eq!(paths(&["nothing"]), Vec::<&str>::new());
+
+        // Ageing drops directories whose rank falls below one
+        dirs[0].rank = MAX_TOTAL;
+        add(&mut dirs, "/new", now);
+        assert!(dirs.iter().all(|d| d.path != "/tmp/a|b"));
+        assert!(dirs[0].rank < MAX_TOTAL);
+    }
+}
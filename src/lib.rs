@@ -0,0 +1,55 @@
+//! The interpreter behind 0-shell, for embedding in other programs.
+//!
+//! [`Shell`] runs command lines and keeps a session's state between them:
+//!
+//! ```
+//! let mut shell = zero_shell::Shell::new();
+//! let results = shell.run_line("cd /; pwd");
+//! assert_eq!(results[1].stdout_text(), "/\n");
+//! ```
+//!
+//! Commands are registered in a [`CommandList`]. New ones can be added by
+//! implementing [`ShellCommand`], and lines parsed with [`split_line`] and
+//! [`CommandCall::parse`] can be run one at a time through
+//! [`CommandList::run_call`].
+
+pub mod command;
+pub mod command_call;
+pub mod history;
+pub mod jump;
+pub mod manual;
+pub mod shell;
+pub mod spec;
+
+mod base64;
+mod compress;
+mod copy;
+mod desktop;
+mod encoding;
+mod expr;
+mod http;
+mod jobs;
+mod json;
+mod ls_colors;
+mod net;
+mod pager;
+pub mod plugins;
+mod process;
+mod random;
+mod regex;
+mod signals;
+mod terminal;
+mod top;
+mod trash;
+mod unicode;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+mod zip;
+
+pub use command::{
+    Command, CommandError, CommandList, CommandResult, Invocation, Output, ShellCommand,
+    ShellContext, ShellState, Variables, command_list,
+};
+pub use command_call::{CommandCall, split_line};
+pub use jobs::{Job, JobTable};
+pub use shell::Shell;
@@ -0,0 +1,192 @@
+//! File-type colors for `ls`, in the format of the `LS_COLORS` variable.
+//!
+//! `LS_COLORS` is a colon-separated list of `KEY=SGR` pairs, such as
+//! `di=01;34:ln=01;36:*.tar=01;31`. Two-letter keys name file types and
+//! `*SUFFIX` keys match regular files by name. The values are the
+//! parameters of an SGR escape sequence (`\x1b[...m`).
+
+use std::collections::HashMap;
+use std::fs::Metadata;
+
+/// The colors used when `LS_COLORS` is unset, a subset of the GNU
+/// `dircolors` defaults.
+const DEFAULT_COLORS: &str = "di=01;34:ln=01;36:or=40;31;01:pi=40;33:so=01;35:\
+     bd=40;33;01:cd=40;33;01:ex=01;32:\
+     *.tar=01;31:*.tgz=01;31:*.gz=01;31:*.zip=01;31:*.bz2=01;31:*.xz=01;31:\
+     *.jpg=01;35:*.jpeg=01;35:*.png=01;35:*.gif=01;35:*.svg=01;35:\
+     *.mp3=00;36:*.flac=00;36:*.wav=00;36";
+
+/// What kind of file a directory entry is, for choosing its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Executable,
+    Dir,
+    Symlink,
+    Pipe,
+    Socket,
+    BlockDevice,
+    CharDevice,
+}
+
+impl FileKind {
+    /// The kind of file `metadata` describes. Pass the metadata from
+    /// `symlink_metadata` to see links themselves.
+    pub fn of(metadata: &Metadata) -> Self {
+        let file_type = metadata.file_type();
+        if file_type.is_symlink() {
+            return Self::Symlink;
+        }
+        if file_type.is_dir() {
+            return Self::Dir;
+        }
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+            if file_type.is_fifo() {
+                return Self::Pipe;
+            }
+            if file_type.is_socket() {
+                return Self::Socket;
+            }
+            if file_type.is_block_device() {
+                return Self::BlockDevice;
+            }
+            if file_type.is_char_device() {
+                return Self::CharDevice;
+            }
+            if metadata.permissions().mode() & 0o111 != 0 {
+                return Self::Executable;
+            }
+        }
+        Self::File
+    }
+
+    /// The `LS_COLORS` key for this kind.
+    fn key(self) -> &'static str {
+        match self {
+            Self::File => "fi",
+            Self::Executable => "ex",
+            Self::Dir => "di",
+            Self::Symlink => "ln",
+            Self::Pipe => "pi",
+            Self::Socket => "so",
+            Self::BlockDevice => "bd",
+            Self::CharDevice => "cd",
+        }
+    }
+}
+
+/// A set of colors to paint file names with.
+#[derive(Debug, Clone, Default)]
+pub struct Palette {
+    kinds: HashMap<String, String>,
+    /// Lowercased name suffixes and their colors, in the order given.
+    suffixes: Vec<(String, String)>,
+}
+
+impl Palette {
+    /// The palette from an `LS_COLORS` value, or the built-in one if it is
+    /// unset or empty.
+    pub fn new(spec: Option<String>) -> Self {
+        match spec {
+            Some(spec) if !spec.is_empty() => Self::parse(&spec),
+            _ => Self::parse(DEFAULT_COLORS),
+        }
+    }
+
+    /// Parses an `LS_COLORS` value. Entries that are not `KEY=VALUE` are
+    /// ignored.
+    pub fn parse(spec: &str) -> Self {
+        let mut palette = Self::default();
+        for (key, value) in spec.split(':').filter_map(|item| item.split_once('=')) {
+            match key.strip_prefix('*') {
+                Some(suffix) => palette
+                    .suffixes
+                    .push((suffix.to_lowercase(), value.to_string())),
+                None => {
+                    palette.kinds.insert(key.to_string(), value.to_string());
+                }
+            }
+        }
+        palette
+    }
+
+    /// Wraps `name` in the color for its kind. For a symbolic link,
+    /// `target` is the kind of file it points to, or `None` if it is
+    /// broken; it is used when `ln=target`.
+    pub fn paint(&self, name: &str, kind: FileKind, target: Option<FileKind>) -> String {
+        match self.color(name, kind, target) {
+            Some(code) => format!("\x1b[{}m{}\x1b[0m", code, name),
+            None => name.to_string(),
+        }
+    }
+
+    fn color(&self, name: &str, kind: FileKind, target: Option<FileKind>) -> Option<&str> {
+        let code = match (kind, target) {
+            (FileKind::Symlink, None) => self.kinds.get("or").or_else(|| self.kinds.get("ln")),
+            (FileKind::Symlink, Some(target)) => match self.kinds.get("ln") {
+                Some(code) if code == "target" => return self.color(name, target, None),
+                code => code,
+            },
+            (FileKind::File, _) => {
+                let lower = name.to_lowercase();
+                self.suffixes
+                    .iter()
+                    .find(|(suffix, _)| lower.ends_with(suffix.as_str()))
+                    .map(|(_, code)| code)
+                    .or_else(|| self.kinds.get("fi"))
+            }
+            _ => self.kinds.get(kind.key()),
+        };
+        // An empty value, `0`, or `00` means the terminal's normal colors
+        code.map(String::as_str)
+            .filter(|code| !code.trim_start_matches('0').is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ls_colors() {
+        let palette = Palette::parse("di=01;34:ln=target:or=31:fi=0:*.TAR=01;31:bogus");
+        assert_eq!(
+            palette.paint("src", FileKind::Dir, None),
+            "\x1b[01;34msrc\x1b[0m"
+        );
+        assert_eq!(
+            palette.paint("a.tar", FileKind::File, None),
+            "\x1b[01;31ma.tar\x1b[0m"
+        );
+        assert_eq!(
+            palette.paint("notes.txt", FileKind::File, None),
+            "notes.txt"
+        );
+        assert_eq!(
+            palette.paint("run.sh", FileKind::Executable, None),
+            "run.sh"
+        );
+        // `ln=target` colors a link like the file it points to
+        assert_eq!(
+            palette.paint("lib", FileKind::Symlink, Some(FileKind::Dir)),
+            "\x1b[01;34mlib\x1b[0m"
+        );
+        assert_eq!(
+            palette.paint("gone", FileKind::Symlink, None),
+            "\x1b[31mgone\x1b[0m"
+        );
+
+        let defaults = Palette::parse(DEFAULT_COLORS);
+        assert_eq!(
+            defaults.paint("run.sh", FileKind::Executable, None),
+            "\x1b[01;32mrun.sh\x1b[0m"
+        );
+        assert_eq!(
+            defaults.paint("lib", FileKind::Symlink, Some(FileKind::Dir)),
+            "\x1b[01;36mlib\x1b[0m"
+        );
+    }
+}
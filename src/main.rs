@@ -1,26 +1,46 @@
-mod command;
-mod command_call;
-
-use command::command_list;
-use command_call::parse_line;
-use std::env;
 use std::io::{self, Write};
 
+use zero_shell::command::logical_cwd;
+use zero_shell::shell::wait_for_input;
+use zero_shell::{Output, Shell, Variables, history, jump, plugins};
+
 /// Main entry point for the 0-shell
-/// Implements a read-eval-print loop (REPL) for command execution
+/// Implements a read-eval-print loop (REPL) for command execution. The shell
+/// exits with the status `exit` gives, or at EOF that of the last command.
+/// Jobs from `schedule` run between lines, or while waiting at the prompt.
 fn main() -> io::Result<()> {
     let mut stdout = io::stdout();
     let mut stderr = io::stderr();
-    let cmds = command_list();
+    let mut stdin = io::stdin();
+    let mut shell = Shell::new();
+    if let Some(dir) = plugins::dir(&shell.state().vars) {
+        shell.load_plugins(&dir);
+    }
+    // Directories visited are remembered for `j`
+    shell.state_mut().dirs_file = jump::file_path(&shell.state().vars);
 
     loop {
-        let prompt = get_prompt();
+        shell.run_due_jobs(Output::new(&mut stdout, &mut stderr))?;
+        let prompt = get_prompt(&shell.state().vars);
         stdout.write_all(prompt.as_bytes())?;
         stdout.flush()?;
 
+        // Jobs that fall due before a line is entered run on a line of
+        // their own, followed by a fresh prompt
+        while let Some(wait) = shell.next_job_in() {
+            if wait_for_input(wait) {
+                break;
+            }
+            writeln!(stdout)?;
+            shell.run_due_jobs(Output::new(&mut stdout, &mut stderr))?;
+            let prompt = get_prompt(&shell.state().vars);
+            stdout.write_all(prompt.as_bytes())?;
+            stdout.flush()?;
+        }
+
         let mut line = String::new();
         // Lock stdin only long enough to read the command line
-        let bytes_read = io::stdin().read_line(&mut line)?;
+        let bytes_read = stdin.read_line(&mut line)?;
 
         if bytes_read == 0 {
             break; // EOF (Ctrl+D)
@@ -28,38 +48,25 @@ fn main() -> io::Result<()> {
 
         // Remove trailing newline
         let raw_input = line.trim_end();
+        // A history that cannot be written should not stop the shell
+        let _ = history::record(&shell.state().vars, raw_input);
 
-        // Layer 1: Parse the line into individual calls (with flags separated)
-        let calls = parse_line(raw_input);
-
-        // Layer 2: Dispatch calls one by one
-        for call in calls {
-            let result = cmds.execute(call.name, call.flags, call.args);
-
-            if result.should_exit {
-                return Ok(());
-            }
-
-            if !result.stdout.is_empty() {
-                stdout.write_all(result.stdout.as_bytes())?;
-                stdout.flush()?;
-            }
-
-            if !result.stderr.is_empty() {
-                stderr.write_all(format!("{}\n", result.stderr).as_bytes())?;
-                stderr.flush()?;
-            }
+        // Each command is parsed just before it runs, and streaming commands
+        // write straight to the terminal as they go
+        let out = Output::new(&mut stdout, &mut stderr);
+        if let Some(status) = shell.run_line_with(raw_input, &mut stdin, out)? {
+            std::process::exit(status);
         }
     }
 
-    Ok(())
+    std::process::exit(shell.last_status());
 }
 
-/// Generates the shell prompt, showing the current directory
+/// Generates the shell prompt, showing the current directory as `cd` reached it
 /// Replaces the home directory path with ~ for brevity
-fn get_prompt() -> String {
-    let cwd = env::current_dir().unwrap_or_default();
-    let home = env::var("HOME").unwrap_or_default();
+fn get_prompt(vars: &Variables) -> String {
+    let cwd = logical_cwd(vars).unwrap_or_default();
+    let home = vars.get("HOME").unwrap_or_default();
 
     let path_str = cwd.to_string_lossy();
 
@@ -0,0 +1,191 @@
+//! Long help pages, shown by `help COMMAND`.
+//!
+//! A page is laid out like a manual page. NAME, SYNOPSIS, and OPTIONS come
+//! from the command's [`Spec`], or for commands without one, the first two
+//! from its one-line help. Commands with a [`Manual`] have DESCRIPTION and
+//! EXAMPLES sections as well.
+
+use crate::spec::{Spec, WIDTH, wrap};
+
+/// The long help of a command, beyond what its [`Spec`] says.
+#[derive(Debug)]
+pub struct Manual {
+    /// Paragraphs separated by blank lines. They are wrapped when shown.
+    pub description: &'static str,
+    /// Command lines, each followed by the output it gives, if any.
+    pub examples: &'static [&'static str],
+}
+
+/// Renders the page for `name`, whose one-line help is `help`, in the form
+/// `USAGE - SUMMARY`.
+pub fn render(name: &str, help: &str, spec: Option<&Spec>, manual: Option<&Manual>) -> String {
+    let (usage, summary) = help.split_once(" - ").unwrap_or((help, ""));
+    let mut page = format!("NAME\n    {} - {}\n\nSYNOPSIS\n", name, summary);
+    let synopsis = match spec {
+        Some(spec) => spec.synopsis(),
+        // Several forms are written `FORM or NAME FORM`
+        None => usage
+            .split(&format!(" or {} ", name))
+            .map(
+                |form| match form.strip_prefix(name).unwrap_or(form).trim_start() {
+                    "" => name.to_string(),
+                    form => format!("{} {}", name, form),
+                },
+            )
+            .collect(),
+    };
+    for form in synopsis {
+        page.push_str(&format!("    {}\n", form));
+    }
+
+    if let Some(manual) = manual {
+        page.push_str("\nDESCRIPTION\n");
+        for (i, paragraph) in manual.description.split("\n\n").enumerate() {
+            if i > 0 {
+                page.push('\n');
+            }
+            wrap_into(&mut page, paragraph, 4);
+        }
+    }
+    if let Some(spec) = spec.filter(|spec| !spec.flags.is_empty()) {
+        page.push_str("\nOPTIONS\n");
+        for flag in spec.flags {
+            page.push_str(&format!("    {}\n", flag.label()));
+            wrap_into(&mut page, &flag.description(), 8);
+        }
+    }
+    if let Some(manual) = manual.filter(|manual| !manual.examples.is_empty()) {
+        page.push_str("\nEXAMPLES\n");
+        for (i, example) in manual.examples.iter().enumerate() {
+            if i > 0 {
+                page.push('\n');
+            }
+            for line in example.lines() {
+                match line {
+                    "" => page.push('\n'),
+                    line => page.push_str(&format!("    {}\n", line)),
+                }
+            }
+        }
+    }
+    page
+}
+
+/// Appends `text`, wrapped to [`WIDTH`] and indented by `indent` spaces.
+fn wrap_into(page: &mut String, text: &str, indent: usize) {
+    for line in wrap(text, WIDTH - indent) {
+        page.push_str(&format!("{:indent$}{}\n", "", line));
+    }
+}
+
+pub const EXIT: Manual = Manual {
+    description: "Leaves the shell with exit status N, or without it the status of the last \
+        command. Pressing Ctrl+D at an empty prompt does the same.",
+    examples: &[],
+};
+
+pub const ECHO: Manual = Manual {
+    description: "Prints its arguments, separated by single spaces and followed by a \
+        newline.",
+    examples: &[
+        "$ echo -e 'one\\ttwo'\none     two",
+        "$ echo -e 'caf\\u00e9'\ncafé",
+    ],
+};
+
+pub const PWD: Manual = Manual {
+    description: "Prints the absolute path of the current directory.",
+    examples: &["$ pwd\n/home/user/projects"],
+};
+
+pub const CD: Manual = Manual {
+    description: "Changes the current directory to DIRECTORY, or to $HOME without one. \
+        Each directory changed into is remembered for j.",
+    examples: &["$ cd /tmp", "$ cd ../src"],
+};
+
+pub const MKDIR: Manual = Manual {
+    description: "Creates each DIRECTORY. Its parent must already exist and the directory \
+        itself must not, unless -p is given.",
+    examples: &["$ mkdir -pv a/b\nmkdir: created directory 'a'\nmkdir: created directory 'a/b'"],
+};
+
+pub const CAT: Manual = Manual {
+    description: "Writes each FILE to standard output, one after the other. A FILE of - \
+        is standard input, and with no FILE only standard input is read.\n\n\
+        Files are read a piece at a time, so even very large files start printing \
+        at once. Numbering and squeezing carry on from one file to the next.",
+    examples: &[
+        "$ cat -n notes.txt\n     1\tfirst line\n     2\tsecond line",
+        "$ cat header.txt - footer.txt",
+    ],
+};
+
+pub const CP: Manual = Manual {
+    description: "Copies SOURCE to DEST, or each SOURCE into DIRECTORY. Directories are only \
+        copied with -r, and never into themselves. Errors are reported and the \
+        remaining files still copied.",
+    examples: &["$ cp -rv src backup\n'src' -> 'backup'\n'src/main.rs' -> 'backup/main.rs'"],
+};
+
+pub const MV: Manual = Manual {
+    description: "Renames SOURCE to DEST, or moves each SOURCE into DIRECTORY. Files moved \
+        to another filesystem are copied, with their metadata, and then removed.",
+    examples: &["$ mv -v old.txt new.txt\nrenamed 'old.txt' -> 'new.txt'"],
+};
+
+pub const RM: Manual = Manual {
+    description: "Removes each FILE. Directories are only removed with -r or, when empty, \
+        -d. Symbolic links are removed, never followed. . and .. are refused, as is / \
+        with -r.\n\n\
+        With --trash, or when RM_TRASH=1 is set, files are moved to the trash instead, \
+        where trash-list shows them and restore brings them back.",
+    examples: &["$ rm -rv build\nremoved 'build/out.o'\nremoved directory 'build'"],
+};
+
+pub const LS: Manual = Manual {
+    description: "Lists each FILE, and the contents of each directory, or of the current \
+        directory without any. On a terminal, short listings are laid out in \
+        columns and colored by file type.",
+    examples: &["$ ls -lh", "$ ls -a --sort=size ~/Downloads"],
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::spec::Flag;
+
+    #[test]
+    fn test_render_manual() {
+        const SPEC: Spec = Spec {
+            name: "demo",
+            summary: "show a demo",
+            forms: &["FILE", "-l"],
+            flags: &[Flag::new(&["-a", "--all"], "Everything.")],
+        };
+        let manual = Manual {
+            description: "First paragraph, long enough that it has to be wrapped onto a \
+                second line of its own.\n\nSecond.",
+            examples: &["$ demo -a\nout\n\nmore"],
+        };
+        assert_eq!(
+            render("demo", &SPEC.help_line(), Some(&SPEC), Some(&manual)),
+            "NAME\n    demo - show a demo\n\n\
+             SYNOPSIS\n    demo [-a] FILE\n    demo [OPTION]... -l\n\n\
+             DESCRIPTION\n    First paragraph, long enough that it has to be wrapped onto a second line\n\
+             \x20   of its own.\n\n    Second.\n\n\
+             OPTIONS\n    -a, --all\n        Everything.\n\n\
+             EXAMPLES\n    $ demo -a\n    out\n\n    more\n"
+        );
+        // Without a spec, the synopsis comes from the one-line help
+        assert_eq!(
+            render(
+                "zip",
+                "zip [-r] ARCHIVE FILE... or zip -l - do zip things",
+                None,
+                None
+            ),
+            "NAME\n    zip - do zip things\n\nSYNOPSIS\n    zip [-r] ARCHIVE FILE...\n    zip -l\n"
+        );
+    }
+}
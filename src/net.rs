@@ -0,0 +1,644 @@
+//! Low-level networking helpers: ICMP echo for `ping`, stream relaying for
+//! `nc`, name lookups for `resolve`, and interface listing for `ifinfo`.
+//!
+//! Echo requests go through an unprivileged ICMP datagram socket where the
+//! system allows one (see `net.ipv4.ping_group_range` on Linux), then a raw
+//! socket, which needs root. When neither is available, [`Pinger`] falls
+//! back to UDP probes to an unused port and times the "port unreachable"
+//! error the host answers with.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Bytes of payload after the 8-byte ICMP header, as in most pings.
+pub const PING_PAYLOAD: usize = 56;
+
+/// First port probed by the UDP fallback; traceroute uses the same range
+/// because nothing normally listens there.
+const UDP_PROBE_PORT: u16 = 33434;
+
+/// Internet checksum (RFC 1071) of `data`.
+pub fn icmp_checksum(data: &[u8]) -> u16 {
+    let mut sum: u32 = data
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair.get(1).copied().unwrap_or(0)]) as u32)
+        .sum();
+    while sum > 0xFFFF {
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// Builds an echo request with the given identifier and sequence number.
+pub fn echo_request(v6: bool, id: u16, seq: u16) -> Vec<u8> {
+    let mut packet = vec![0u8; 8 + PING_PAYLOAD];
+    packet[0] = if v6 {
+        ICMPV6_ECHO_REQUEST
+    } else {
+        ICMP_ECHO_REQUEST
+    };
+    packet[4..6].copy_from_slice(&id.to_be_bytes());
+    packet[6..8].copy_from_slice(&seq.to_be_bytes());
+    for (i, byte) in packet[8..].iter_mut().enumerate() {
+        *byte = i as u8;
+    }
+    // The kernel fills in the ICMPv6 checksum, which covers a pseudo-header
+    if !v6 {
+        let checksum = icmp_checksum(&packet);
+        packet[2..4].copy_from_slice(&checksum.to_be_bytes());
+    }
+    packet
+}
+
+/// Checks whether `packet` is the echo reply to `seq`, returning the size
+/// of its ICMP message. Raw IPv4 sockets deliver the IP header too, and
+/// datagram sockets rewrite the identifier, so it is only checked when
+/// `id` is given.
+pub fn parse_echo_reply(
+    packet: &[u8],
+    v6: bool,
+    ip_header: bool,
+    id: Option<u16>,
+    seq: u16,
+) -> Option<usize> {
+    let icmp = if ip_header {
+        let header_len = (*packet.first()? & 0x0F) as usize * 4;
+        packet.get(header_len..)?
+    } else {
+        packet
+    };
+    if icmp.len() < 8 {
+        return None;
+    }
+    let reply_type = if v6 {
+        ICMPV6_ECHO_REPLY
+    } else {
+        ICMP_ECHO_REPLY
+    };
+    let reply_id = u16::from_be_bytes([icmp[4], icmp[5]]);
+    let reply_seq = u16::from_be_bytes([icmp[6], icmp[7]]);
+    (icmp[0] == reply_type && id.is_none_or(|id| id == reply_id) && reply_seq == seq)
+        .then_some(icmp.len())
+}
+
+/// How echo requests are sent.
+enum Probe {
+    #[cfg(unix)]
+    Icmp {
+        socket: OwnedFd,
+        raw: bool,
+    },
+    Udp,
+}
+
+/// Sends echo requests to one address and waits for the replies.
+pub struct Pinger {
+    addr: SocketAddr,
+    probe: Probe,
+    id: u16,
+}
+
+/// The answer to one probe.
+pub struct Reply {
+    /// Size of the reply, or `None` for a UDP probe's port unreachable error.
+    pub bytes: Option<usize>,
+    pub rtt: Duration,
+}
+
+impl Pinger {
+    pub fn new(ip: IpAddr) -> Self {
+        let addr = SocketAddr::new(ip, 0);
+        Self {
+            addr,
+            probe: open_icmp_socket(ip.is_ipv6()).unwrap_or(Probe::Udp),
+            id: std::process::id() as u16,
+        }
+    }
+
+    /// Whether ICMP is unavailable and UDP probes are used instead.
+    pub fn uses_udp(&self) -> bool {
+        matches!(self.probe, Probe::Udp)
+    }
+
+    /// Sends probe `seq` and waits up to `timeout` for its answer.
+    pub fn probe(&self, seq: u16, timeout: Duration) -> io::Result<Option<Reply>> {
+        match &self.probe {
+            #[cfg(unix)]
+            Probe::Icmp { socket, raw } => self.probe_icmp(socket, *raw, seq, timeout),
+            Probe::Udp => self.probe_udp(seq, timeout),
+        }
+    }
+
+    #[cfg(unix)]
+    fn probe_icmp(
+        &self,
+        socket: &OwnedFd,
+        raw: bool,
+        seq: u16,
+        timeout: Duration,
+    ) -> io::Result<Option<Reply>> {
+        let v6 = self.addr.is_ipv6();
+        let packet = echo_request(v6, self.id, seq);
+        let start = Instant::now();
+        send_to(socket, &packet, self.addr)?;
+
+        let mut buf = [0u8; 1500];
+        loop {
+            let Some(left) = timeout.checked_sub(start.elapsed()) else {
+                return Ok(None);
+            };
+            if !wait_readable(socket, left)? {
+                return Ok(None);
+            }
+            let n = unsafe {
+                libc::recv(
+                    socket.as_raw_fd(),
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                    0,
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+            // Raw sockets see every ICMP message, including other pings
+            let id = raw.then_some(self.id);
+            let ip_header = raw && !v6;
+            if let Some(bytes) = parse_echo_reply(&buf[..n as usize], v6, ip_header, id, seq) {
+                return Ok(Some(Reply {
+                    bytes: Some(bytes),
+                    rtt: start.elapsed(),
+                }));
+            }
+        }
+    }
+
+    fn probe_udp(&self, seq: u16, timeout: Duration) -> io::Result<Option<Reply>> {
+        let local: SocketAddr = if self.addr.is_ipv6() {
+            "[::]:0".parse().unwrap()
+        } else {
+            "0.0.0.0:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(local)?;
+        let port = UDP_PROBE_PORT.wrapping_add(seq % 64);
+        socket.connect(SocketAddr::new(self.addr.ip(), port))?;
+        socket.set_read_timeout(Some(timeout))?;
+
+        let start = Instant::now();
+        socket.send(&[0u8; PING_PAYLOAD])?;
+        let mut buf = [0u8; 1500];
+        match socket.recv(&mut buf) {
+            Ok(n) => Ok(Some(Reply {
+                bytes: Some(n),
+                rtt: start.elapsed(),
+            })),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => Ok(Some(Reply {
+                bytes: None,
+                rtt: start.elapsed(),
+            })),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open_icmp_socket(v6: bool) -> Option<Probe> {
+    let (domain, protocol) = if v6 {
+        (libc::AF_INET6, libc::IPPROTO_ICMPV6)
+    } else {
+        (libc::AF_INET, libc::IPPROTO_ICMP)
+    };
+    for (kind, raw) in [(libc::SOCK_DGRAM, false), (libc::SOCK_RAW, true)] {
+        let fd = unsafe { libc::socket(domain, kind | libc::SOCK_CLOEXEC, protocol) };
+        if fd >= 0 {
+            // SAFETY: `fd` is a freshly created socket owned by nobody else.
+            let socket = unsafe { OwnedFd::from_raw_fd(fd) };
+            return Some(Probe::Icmp { socket, raw });
+        }
+    }
+    None
+}
+
+#[cfg(not(unix))]
+fn open_icmp_socket(_v6: bool) -> Option<Probe> {
+    None
+}
+
+#[cfg(unix)]
+fn send_to(socket: &OwnedFd, packet: &[u8], addr: SocketAddr) -> io::Result<()> {
+    // SAFETY: `sockaddr_storage` is plain data; zeroed is a valid value.
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = sockaddr_from(addr, &mut storage);
+    let sent = unsafe {
+        libc::sendto(
+            socket.as_raw_fd(),
+            packet.as_ptr() as *const libc::c_void,
+            packet.len(),
+            0,
+            &storage as *const _ as *const libc::sockaddr,
+            len,
+        )
+    };
+    if sent < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Fills `storage` with the C form of `addr`, returning its length.
+#[cfg(unix)]
+fn sockaddr_from(addr: SocketAddr, storage: &mut libc::sockaddr_storage) -> libc::socklen_t {
+    let len = match addr {
+        SocketAddr::V4(v4) => {
+            let sin = storage as *mut _ as *mut libc::sockaddr_in;
+            // SAFETY: `sockaddr_storage` is large and aligned enough for any
+            // socket address type.
+            unsafe {
+                (*sin).sin_family = libc::AF_INET as libc::sa_family_t;
+                (*sin).sin_port = v4.port().to_be();
+                (*sin).sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+            }
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = storage as *mut _ as *mut libc::sockaddr_in6;
+            // SAFETY: as above.
+            unsafe {
+                (*sin6).sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                (*sin6).sin6_port = v6.port().to_be();
+                (*sin6).sin6_addr.s6_addr = v6.ip().octets();
+                (*sin6).sin6_scope_id = v6.scope_id();
+            }
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+    len as libc::socklen_t
+}
+
+/// Waits until `socket` has data, returning `false` on timeout.
+#[cfg(unix)]
+fn wait_readable(socket: &OwnedFd, timeout: Duration) -> io::Result<bool> {
+    let mut pfd = libc::pollfd {
+        fd: socket.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let millis = timeout.as_millis().clamp(1, i32::MAX as u128) as libc::c_int;
+    match unsafe { libc::poll(&mut pfd, 1, millis) } {
+        n if n < 0 => {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                Ok(false)
+            } else {
+                Err(e)
+            }
+        }
+        n => Ok(n > 0),
+    }
+}
+
+/// Copies `input` to `stream` and `stream` to `output` until the peer
+/// closes the connection or SIGINT arrives. When `input` ends, the sending
+/// half is shut down so the peer sees end-of-file, and data is received
+/// until the peer closes too. `input_fd`, if given, is polled to know when
+/// `input` can be read without blocking; without one, `input` is read
+/// whenever the stream has nothing to receive. Returns the bytes sent and
+/// received.
+#[cfg(unix)]
+pub fn relay(
+    stream: &TcpStream,
+    input: &mut dyn Read,
+    input_fd: Option<RawFd>,
+    output: &mut dyn Write,
+) -> io::Result<(u64, u64)> {
+    let ready =
+        |revents: libc::c_short| revents & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0;
+    let mut input_open = true;
+    let (mut sent, mut received) = (0, 0);
+    let mut buf = [0u8; 16 * 1024];
+
+    while !crate::signals::interrupted() {
+        let mut fds = [
+            libc::pollfd {
+                fd: stream.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: input_fd.unwrap_or(-1),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        let polled = input_open && input_fd.is_some();
+        let count = if polled { 2 } else { 1 };
+        // Wake up regularly to notice Ctrl+C, or at once to read unpolled input
+        let timeout = if input_open && !polled { 0 } else { 200 };
+        if unsafe { libc::poll(fds.as_mut_ptr(), count, timeout) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(e);
+        }
+
+        if ready(fds[0].revents) {
+            let n = (&*stream).read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            output.write_all(&buf[..n])?;
+            output.flush()?;
+            received += n as u64;
+        }
+        if input_open && (!polled || ready(fds[1].revents)) {
+            let n = match input.read(&mut buf) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                result => result?,
+            };
+            if n == 0 {
+                input_open = false;
+                stream.shutdown(Shutdown::Write)?;
+            } else {
+                (&*stream).write_all(&buf[..n])?;
+                sent += n as u64;
+            }
+        }
+    }
+    Ok((sent, received))
+}
+
+/// Every address `host` resolves to through the system resolver, without
+/// duplicates and in the resolver's order.
+pub fn lookup(host: &str) -> io::Result<Vec<IpAddr>> {
+    let mut addrs: Vec<IpAddr> = Vec::new();
+    for addr in (host, 0).to_socket_addrs()? {
+        if !addrs.contains(&addr.ip()) {
+            addrs.push(addr.ip());
+        }
+    }
+    Ok(addrs)
+}
+
+/// The host name registered for `ip` (a PTR lookup through the system
+/// resolver).
+#[cfg(unix)]
+pub fn reverse_lookup(ip: IpAddr) -> io::Result<String> {
+    // SAFETY: the sockaddr structs are plain data, fully initialised below.
+    let mut storage: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = sockaddr_from(SocketAddr::new(ip, 0), &mut storage);
+    let mut host = [0 as libc::c_char; libc::NI_MAXHOST as usize];
+    let status = unsafe {
+        libc::getnameinfo(
+            &storage as *const _ as *const libc::sockaddr,
+            len,
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if status != 0 {
+        let message = unsafe { std::ffi::CStr::from_ptr(libc::gai_strerror(status)) };
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            message.to_string_lossy().into_owned(),
+        ));
+    }
+    let name = unsafe { std::ffi::CStr::from_ptr(host.as_ptr()) };
+    Ok(name.to_string_lossy().into_owned())
+}
+
+/// A network interface and its addresses.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Interface {
+    pub name: String,
+    pub up: bool,
+    pub running: bool,
+    pub loopback: bool,
+    /// Hardware address, where the system reports one.
+    pub mac: Option<Vec<u8>>,
+    /// Addresses with their prefix lengths.
+    pub addrs: Vec<(IpAddr, u8)>,
+}
+
+/// Lists the system's network interfaces (via `getifaddrs(3)`), in the
+/// order the system reports them.
+#[cfg(unix)]
+pub fn interfaces() -> io::Result<Vec<Interface>> {
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut interfaces: Vec<Interface> = Vec::new();
+    let mut entry = list;
+    while !entry.is_null() {
+        // SAFETY: getifaddrs returns a valid linked list, freed below.
+        let ifa = unsafe { &*entry };
+        entry = ifa.ifa_next;
+
+        let name = unsafe { std::ffi::CStr::from_ptr(ifa.ifa_name) }
+            .to_string_lossy()
+            .into_owned();
+        let index = match interfaces.iter().position(|i| i.name == name) {
+            Some(index) => index,
+            None => {
+                let flags = ifa.ifa_flags as libc::c_int;
+                interfaces.push(Interface {
+                    name,
+                    up: flags & libc::IFF_UP != 0,
+                    running: flags & libc::IFF_RUNNING != 0,
+                    loopback: flags & libc::IFF_LOOPBACK != 0,
+                    mac: None,
+                    addrs: Vec::new(),
+                });
+                interfaces.len() - 1
+            }
+        };
+        let interface = &mut interfaces[index];
+
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let family = unsafe { (*ifa.ifa_addr).sa_family } as libc::c_int;
+        match family {
+            libc::AF_INET | libc::AF_INET6 => {
+                if let Some(ip) = unsafe { ip_from_sockaddr(ifa.ifa_addr) } {
+                    let prefix = unsafe { ip_from_sockaddr(ifa.ifa_netmask) }.map_or(0, prefix_len);
+                    interface.addrs.push((ip, prefix));
+                }
+            }
+            #[cfg(target_os = "linux")]
+            libc::AF_PACKET => {
+                // SAFETY: AF_PACKET entries carry a `sockaddr_ll`.
+                let ll = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_ll) };
+                let len = (ll.sll_halen as usize).min(ll.sll_addr.len());
+                if len > 0 && ll.sll_addr[..len].iter().any(|&b| b != 0) {
+                    interface.mac = Some(ll.sll_addr[..len].to_vec());
+                }
+            }
+            _ => {}
+        }
+    }
+    unsafe { libc::freeifaddrs(list) };
+    Ok(interfaces)
+}
+
+/// Reads an IPv4 or IPv6 address out of a C socket address.
+///
+/// # Safety
+/// `addr` must be null or point to a valid socket address.
+#[cfg(unix)]
+unsafe fn ip_from_sockaddr(addr: *const libc::sockaddr) -> Option<IpAddr> {
+    if addr.is_null() {
+        return None;
+    }
+    unsafe {
+        match (*addr).sa_family as libc::c_int {
+            libc::AF_INET => {
+                let sin = &*(addr as *const libc::sockaddr_in);
+                Some(IpAddr::from(
+                    u32::from_be(sin.sin_addr.s_addr).to_be_bytes(),
+                ))
+            }
+            libc::AF_INET6 => {
+                let sin6 = &*(addr as *const libc::sockaddr_in6);
+                Some(IpAddr::from(sin6.sin6_addr.s6_addr))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Number of leading one bits in a netmask.
+pub fn prefix_len(mask: IpAddr) -> u8 {
+    match mask {
+        IpAddr::V4(v4) => u32::from(v4).leading_ones() as u8,
+        IpAddr::V6(v6) => u128::from(v6).leading_ones() as u8,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_echo_packets() {
+        // Example from RFC 1071
+        assert_eq!(
+            icmp_checksum(&[0x00, 0x01, 0xf2, 0x03, 0xf4, 0xf5, 0xf6, 0xf7]),
+            !0xddf2
+        );
+
+        let request = echo_request(false, 0x1234, 7);
+        assert_eq!(request.len(), 64);
+        assert_eq!(request[0], ICMP_ECHO_REQUEST);
+        assert_eq!(icmp_checksum(&request), 0);
+
+        let mut reply = request.clone();
+        reply[0] = ICMP_ECHO_REPLY;
+        assert_eq!(parse_echo_reply(&reply, false, false, None, 7), Some(64));
+        assert_eq!(parse_echo_reply(&reply, false, false, Some(1), 7), None);
+        assert_eq!(parse_echo_reply(&reply, false, false, None, 8), None);
+        assert_eq!(parse_echo_reply(&request, false, false, None, 7), None);
+
+        // With a 20-byte IPv4 header in front
+        let mut with_header = vec![0x45];
+        with_header.resize(20, 0);
+        with_header.extend_from_slice(&reply);
+        assert_eq!(
+            parse_echo_reply(&with_header, false, true, Some(0x1234), 7),
+            Some(64)
+        );
+    }
+
+    #[test]
+    fn test_relay() {
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut peer, _) = listener.accept().unwrap();
+            peer.write_all(b"hello\n").unwrap();
+            let mut request = String::new();
+            peer.read_to_string(&mut request).unwrap();
+            peer.write_all(format!("got {}", request).as_bytes())
+                .unwrap();
+        });
+
+        let (mut reader, mut writer) = io::pipe().unwrap();
+        writer.write_all(b"ping").unwrap();
+        drop(writer);
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut output = Vec::new();
+        let fd = reader.as_raw_fd();
+        let (sent, received) = relay(&stream, &mut reader, Some(fd), &mut output).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(output, b"hello\ngot ping");
+        assert_eq!((sent, received), (4, 14));
+
+        // Input without a descriptor to poll is read as it comes
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut peer, _) = listener.accept().unwrap();
+            let mut request = String::new();
+            peer.read_to_string(&mut request).unwrap();
+            peer.write_all(request.to_uppercase().as_bytes()).unwrap();
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        let mut output = Vec::new();
+        relay(&stream, &mut &b"pong"[..], None, &mut output).unwrap();
+        server.join().unwrap();
+        assert_eq!(output, b"PONG");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let addrs = lookup("127.0.0.1").unwrap();
+        assert_eq!(addrs, vec![IpAddr::from([127, 0, 0, 1])]);
+        assert!(
+            lookup("localhost")
+                .unwrap()
+                .iter()
+                .all(|ip| ip.is_loopback())
+        );
+        assert!(lookup("no-such-host.invalid").is_err());
+    }
+
+    #[test]
+    fn test_interfaces() {
+        assert_eq!(prefix_len("255.255.240.0".parse().unwrap()), 20);
+        assert_eq!(prefix_len("ffff:ffff:ffff:ffff::".parse().unwrap()), 64);
+
+        let interfaces = interfaces().unwrap();
+        let lo = interfaces.iter().find(|i| i.loopback).unwrap();
+        assert!(lo.up);
+        assert!(lo.addrs.contains(&(IpAddr::from([127, 0, 0, 1]), 8)));
+    }
+}
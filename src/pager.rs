@@ -0,0 +1,437 @@
+//! A `less`-style pager for viewing text one screen at a time.
+//!
+//! The paging state is kept separate from terminal handling so that the
+//! scrolling and search logic can be tested without a real terminal. Input
+//! is read only as far as the screen needs, so paging a large file or an
+//! endless pipe starts at once and holds only what has been looked at.
+
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+
+use crate::command::Variables;
+use crate::terminal::{self, Key, RawMode};
+
+/// Scroll position, search state, and wrapped content of a pager session.
+pub struct Pager<'a> {
+    rows: Vec<String>,
+    width: usize,
+    /// The rest of the input, until it has all been read.
+    input: Option<Box<dyn BufRead + 'a>>,
+    top: usize,
+    height: usize,
+    pattern: Option<String>,
+    message: Option<String>,
+}
+
+impl<'a> Pager<'a> {
+    /// Creates a pager that reads its text from `input` as it is needed,
+    /// wrapping lines to `width` columns and showing `height` rows of
+    /// content at a time.
+    pub fn from_reader(input: impl Read + 'a, width: usize, height: usize) -> Self {
+        let mut pager = Self {
+            rows: Vec::new(),
+            width: width.max(1),
+            input: Some(Box::new(BufReader::new(input))),
+            top: 0,
+            height: height.max(1),
+            pattern: None,
+            message: None,
+        };
+        pager.fill(pager.height + 1);
+        pager
+    }
+
+    /// Reads input until there are at least `rows` rows or it runs out. A
+    /// read error ends the input, and is shown on the status line.
+    fn fill(&mut self, rows: usize) {
+        let mut line = Vec::new();
+        while self.rows.len() < rows {
+            let Some(input) = &mut self.input else {
+                return;
+            };
+            line.clear();
+            match input.read_until(b'\n', &mut line) {
+                Ok(0) => self.input = None,
+                Ok(_) => {
+                    let text = String::from_utf8_lossy(&line);
+                    let text = text.strip_suffix('\n').unwrap_or(&text);
+                    wrap_line(text, self.width, &mut self.rows);
+                }
+                Err(e) => {
+                    self.input = None;
+                    self.message = Some(format!("read error: {}", e));
+                }
+            }
+        }
+    }
+
+    /// Reads ahead so that the rows on screen, and whether there are more,
+    /// are known.
+    fn fill_screen(&mut self) {
+        self.fill(self.top + self.height + 1);
+    }
+
+    /// The highest row that may be shown at the top of the screen.
+    fn max_top(&self) -> usize {
+        self.rows.len().saturating_sub(self.height)
+    }
+
+    /// Whether the last row of content is currently visible.
+    pub fn at_end(&self) -> bool {
+        self.top >= self.max_top()
+    }
+
+    pub fn scroll_down(&mut self, n: usize) {
+        self.fill(self.top + n + self.height);
+        self.top = (self.top + n).min(self.max_top());
+        self.fill_screen();
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        self.top = self.top.saturating_sub(n);
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.height);
+    }
+
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.height);
+    }
+
+    pub fn go_top(&mut self) {
+        self.top = 0;
+    }
+
+    pub fn go_bottom(&mut self) {
+        self.fill(usize::MAX);
+        self.top = self.max_top();
+    }
+
+    /// Searches for `pattern` starting after the current top row and scrolls
+    /// the first match to the top of the screen.
+    pub fn search(&mut self, pattern: &str) -> bool {
+        self.pattern = if pattern.is_empty() {
+            None
+        } else {
+            Some(pattern.to_string())
+        };
+        self.find(true)
+    }
+
+    /// Repeats the last search forwards or backwards.
+    pub fn find(&mut self, forward: bool) -> bool {
+        let Some(pattern) = self.pattern.clone() else {
+            self.message = Some("No previous search pattern".to_string());
+            return false;
+        };
+
+        let found = if forward {
+            (self.top + 1..)
+                .find(|&i| {
+                    self.fill(i + 1);
+                    self.rows.get(i).is_none_or(|row| row.contains(&pattern))
+                })
+                .filter(|&i| i < self.rows.len())
+        } else {
+            (0..self.top)
+                .rev()
+                .find(|&i| self.rows[i].contains(&pattern))
+        };
+
+        match found {
+            Some(row) => {
+                self.fill(row + self.height);
+                self.top = row.min(self.max_top());
+                self.fill_screen();
+                self.message = None;
+                true
+            }
+            None => {
+                self.message = Some("Pattern not found".to_string());
+                false
+            }
+        }
+    }
+
+    /// Renders the visible rows followed by a status line.
+    fn render(&self, status: &str) -> String {
+        let mut frame = String::from(terminal::CLEAR_SCREEN);
+        for i in self.top..self.top + self.height {
+            match self.rows.get(i) {
+                Some(row) => frame.push_str(&self.highlight(row)),
+                None => frame.push('~'),
+            }
+            frame.push_str("\r\n");
+        }
+        frame.push_str(terminal::REVERSE);
+        frame.push_str(status);
+        frame.push_str(terminal::RESET);
+        frame
+    }
+
+    /// Wraps matches of the current search pattern in reverse video.
+    fn highlight(&self, row: &str) -> String {
+        match &self.pattern {
+            Some(pattern) => row.replace(
+                pattern.as_str(),
+                &format!("{}{}{}", terminal::REVERSE, pattern, terminal::RESET),
+            ),
+            None => row.to_string(),
+        }
+    }
+
+    /// Builds the status line text for the current position.
+    fn status(&self, name: &str, more_style: bool) -> String {
+        if let Some(message) = &self.message {
+            return message.clone();
+        }
+        // How far through the text is only known once it has all been read
+        let shown = (self.top + self.height).min(self.rows.len());
+        let percent = match self.input {
+            Some(_) => String::new(),
+            None if self.rows.is_empty() => "100%".to_string(),
+            None => format!("{}%", shown * 100 / self.rows.len()),
+        };
+        if more_style && percent.is_empty() {
+            "--More--".to_string()
+        } else if more_style {
+            format!("--More--({})", percent)
+        } else if self.at_end() {
+            "(END)".to_string()
+        } else if name.is_empty() {
+            format!(":{}", percent)
+        } else {
+            format!("{} {}", name, percent).trim_end().to_string()
+        }
+    }
+}
+
+/// Adds the display rows of one line, without its newline, to `rows`: no
+/// wider than `width` characters, with tabs expanded to 8-column stops.
+fn wrap_line(line: &str, width: usize, rows: &mut Vec<String>) {
+    let mut row = String::new();
+    let mut col = 0;
+    for c in line.chars().filter(|&c| c != '\r') {
+        if c == '\t' {
+            let spaces = 8 - col % 8;
+            for _ in 0..spaces {
+                if col == width {
+                    rows.push(std::mem::take(&mut row));
+                    col = 0;
+                }
+                row.push(' ');
+                col += 1;
+            }
+            continue;
+        }
+        if col == width {
+            rows.push(std::mem::take(&mut row));
+            col = 0;
+        }
+        row.push(c);
+        col += 1;
+    }
+    rows.push(row);
+}
+
+/// Shows `text` in the pager, or writes it straight to stdout when stdout is
+/// not a terminal.
+///
+/// `name` appears in the status line. In `more_style`, the pager exits as soon
+/// as the end is reached and content that fits on one screen is printed as is.
+/// `vars` gives the screen size when the terminal cannot be asked.
+pub fn page(text: &str, name: &str, more_style: bool, vars: &Variables) -> io::Result<()> {
+    page_reader(text.as_bytes(), name, more_style, vars)
+}
+
+/// Like [`page`], but reads the text from `input`, only as far as the
+/// screen needs. Nothing is shown for empty input.
+pub fn page_reader(
+    mut input: impl Read,
+    name: &str,
+    more_style: bool,
+    vars: &Variables,
+) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    if !stdout.is_terminal() {
+        io::copy(&mut input, &mut stdout)?;
+        return stdout.flush();
+    }
+
+    // Read the first screen as it is, to tell whether it fits
+    let (width, height) = terminal::size(vars);
+    let mut input = BufReader::new(input);
+    let mut start = Vec::new();
+    let mut lines = 0;
+    while lines < height && input.read_until(b'\n', &mut start)? > 0 {
+        lines += 1;
+    }
+    let fits = lines < height;
+    if start.is_empty() || (more_style && fits) {
+        stdout.write_all(&start)?;
+        return stdout.flush();
+    }
+
+    let mut tty = terminal::open_tty()?;
+    let _raw = RawMode::enable(&tty)?;
+    let mut pager = Pager::from_reader(io::Cursor::new(start).chain(input), width, height - 1);
+
+    stdout.write_all(terminal::ENTER_ALT_SCREEN.as_bytes())?;
+    let outcome = run(&mut pager, &mut tty, &mut stdout, name, more_style);
+    stdout.write_all(terminal::LEAVE_ALT_SCREEN.as_bytes())?;
+    stdout.flush()?;
+    outcome
+}
+
+/// The interactive key loop. The terminal still turns Ctrl+C into SIGINT,
+/// which quits the pager rather than the shell.
+fn run(
+    pager: &mut Pager,
+    tty: &mut std::fs::File,
+    stdout: &mut io::Stdout,
+    name: &str,
+    more_style: bool,
+) -> io::Result<()> {
+    let _guard = crate::signals::InterruptGuard::install();
+    loop {
+        let status = pager.status(name, more_style);
+        stdout.write_all(pager.render(&status).as_bytes())?;
+        stdout.flush()?;
+
+        let was_at_end = pager.at_end();
+        pager.message = None;
+        let Some(key) = next_key(tty)? else {
+            return Ok(());
+        };
+        match key {
+            Key::Char('q') | Key::Char('Q') | Key::Ctrl('c') => return Ok(()),
+            Key::Char(' ') | Key::Char('f') | Key::PageDown | Key::Ctrl('f') => {
+                if more_style && was_at_end {
+                    return Ok(());
+                }
+                pager.page_down();
+            }
+            Key::Char('b') | Key::PageUp | Key::Ctrl('b') => pager.page_up(),
+            Key::Char('j') | Key::Enter | Key::Down => {
+                if more_style && was_at_end {
+                    return Ok(());
+                }
+                pager.scroll_down(1);
+            }
+            Key::Char('k') | Key::Up => pager.scroll_up(1),
+            Key::Char('g') | Key::Char('<') | Key::Home => pager.go_top(),
+            Key::Char('G') | Key::Char('>') | Key::End => pager.go_bottom(),
+            Key::Char('n') => {
+                pager.find(true);
+            }
+            Key::Char('N') => {
+                pager.find(false);
+            }
+            Key::Char('/') => {
+                if let Some(pattern) = read_prompt(pager, tty, stdout)? {
+                    pager.search(&pattern);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Waits for a keystroke, or returns `None` if interrupted first.
+fn next_key(tty: &mut std::fs::File) -> io::Result<Option<Key>> {
+    loop {
+        if crate::signals::interrupted() {
+            return Ok(None);
+        }
+        // Poll briefly, so SIGINT is noticed even if it lands before the wait
+        if terminal::poll_readable(tty, 100) {
+            return terminal::read_key(tty).map(Some);
+        }
+    }
+}
+
+/// Reads a search pattern on the status line. Returns `None` if cancelled
+/// or interrupted.
+fn read_prompt(
+    pager: &Pager,
+    tty: &mut std::fs::File,
+    stdout: &mut io::Stdout,
+) -> io::Result<Option<String>> {
+    let mut input = String::new();
+    loop {
+        let frame = pager.render(&format!("/{}", input));
+        stdout.write_all(frame.as_bytes())?;
+        stdout.flush()?;
+
+        let Some(key) = next_key(tty)? else {
+            return Ok(None);
+        };
+        match key {
+            Key::Enter => return Ok(Some(input)),
+            Key::Escape | Key::Ctrl('c') => return Ok(None),
+            Key::Backspace if input.is_empty() => return Ok(None),
+            Key::Backspace => {
+                input.pop();
+            }
+            Key::Char(c) => input.push(c),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_line() {
+        let mut rows = Vec::new();
+        for line in ["abcdefgh", "\tx", "short"] {
+            wrap_line(line, 5, &mut rows);
+        }
+        assert_eq!(rows, vec!["abcde", "fgh", "     ", "   x", "short"]);
+    }
+
+    #[test]
+    fn test_pager_scrolling_and_search() {
+        let text: String = (1..=30).map(|i| format!("line {}\n", i)).collect();
+        let mut pager = Pager::from_reader(text.as_bytes(), 80, 10);
+
+        pager.page_down();
+        assert_eq!(pager.top, 10);
+        pager.go_bottom();
+        assert_eq!(pager.top, 20);
+        assert!(pager.at_end());
+        pager.page_up();
+        assert_eq!(pager.top, 10);
+
+        pager.go_top();
+        assert!(pager.search("line 15"));
+        assert_eq!(pager.top, 14);
+        assert!(!pager.find(true));
+        assert!(pager.search("line 2"));
+        assert_eq!(pager.top, 19);
+        assert!(pager.find(false));
+        assert_eq!(pager.top, 1);
+    }
+
+    #[test]
+    fn test_pager_reads_as_needed() {
+        // Endless input is read only as far as the screen goes
+        let mut pager = Pager::from_reader(io::repeat(b'\n'), 80, 10);
+        assert_eq!(pager.rows.len(), 11);
+        assert_eq!(pager.status("yes", false), "yes");
+        pager.page_down();
+        assert_eq!((pager.top, pager.rows.len()), (10, 21));
+
+        let mut pager = Pager::from_reader(&b"a\nb\nmatch\nc"[..], 80, 1);
+        assert!(pager.search("match"));
+        assert_eq!(pager.top, 2);
+        assert_eq!(pager.status("", false), ":");
+        pager.go_bottom();
+        pager.scroll_up(1);
+        assert_eq!(pager.status("", false), ":75%");
+        pager.go_bottom();
+        assert_eq!(pager.status("", false), "(END)");
+    }
+}
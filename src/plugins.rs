@@ -0,0 +1,487 @@
+//! Commands provided by other programs, found in `~/.0shell/plugins`.
+//!
+//! Every executable file in the directory is a plugin, and talks to the
+//! shell in JSON. When the shell starts, each is run with the single
+//! argument `--describe` and prints what command it provides, within
+//! [`DESCRIBE_TIMEOUT`]:
+//!
+//! ```text
+//! {"name": "greet", "help": "greet [NAME]... - say hello", "require_args": false}
+//! ```
+//!
+//! `name` defaults to the file's name, without any extension, and
+//! `require_args` to false. To run the command, the plugin is run without
+//! arguments and sent one request on its standard input, which it answers
+//! on its standard output:
+//!
+//! ```text
+//! {"name": "greet", "flags": ["-l"], "args": ["world"]}
+//! {"stdout": "Hello, world!\n", "stderr": "", "status": 0}
+//! ```
+//!
+//! Every field of the response may be left out. The plugin runs in the
+//! shell's current directory with its variables in the environment, and
+//! anything it writes to its standard error is shown as well. Plugins
+//! cannot read the shell's standard input. One that has not exited after
+//! [`RUN_TIMEOUT`] is killed.
+//!
+//! `.wasm` files in the directory are WebAssembly plugins, which speak the
+//! same JSON from inside a sandbox; see [`crate::wasm`].
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::command::{
+    Command, CommandError, CommandList, CommandResult, Invocation, ShellCommand, ShellContext,
+    Variables,
+};
+use crate::json::{self, Value};
+
+/// How long a plugin has to describe itself before it is killed and
+/// skipped.
+pub const DESCRIBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a plugin command may run before it is killed.
+pub const RUN_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// The file in the plugins directory that lists the WebAssembly plugins
+/// allowed to write files, one file name per line, such as `notes.wasm`.
+/// A plugin that asks for `"fs": "write"` but is not listed may only read.
+pub const WRITE_GRANTS: &str = "allow-write";
+
+/// The directory plugins are loaded from, `~/.0shell/plugins` by the `HOME`
+/// of `vars`.
+pub fn dir(vars: &Variables) -> Option<PathBuf> {
+    let home = vars.get("HOME").filter(|home| !home.is_empty())?;
+    Some(PathBuf::from(home).join(".0shell").join("plugins"))
+}
+
+/// Registers the command of each plugin in `dir` with `cmds`. Plugins are
+/// loaded in order of their file names, and those that fail to describe
+/// themselves, or whose command is already registered, are skipped.
+pub fn register_all(cmds: &mut CommandList, dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    paths.sort();
+    let grants = write_grants(dir);
+    for path in paths {
+        let plugin = if path.extension().is_some_and(|ext| ext == "wasm") {
+            load_wasm(&path, &grants)
+        } else if is_executable(&path) {
+            Plugin::describe(&path)
+        } else {
+            continue;
+        };
+        if let Ok(plugin) = plugin
+            && !cmds.contains(&plugin.name)
+        {
+            let help = plugin.help.clone();
+            let require_args = plugin.require_args;
+            cmds.register(
+                plugin.name.clone(),
+                Command::from_command(&help, require_args, plugin),
+            );
+        }
+    }
+}
+
+/// The file names listed in the [`WRITE_GRANTS`] file of `dir`. Blank
+/// lines and lines starting with `#` are skipped.
+fn write_grants(dir: &Path) -> Vec<String> {
+    let text = std::fs::read_to_string(dir.join(WRITE_GRANTS)).unwrap_or_default();
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect()
+}
+
+/// Loads the WebAssembly plugin at `path`. It may write files only if the
+/// user named it in `grants`; asking is not enough.
+#[cfg(feature = "wasm")]
+fn load_wasm(path: &Path, grants: &[String]) -> Result<Plugin, String> {
+    use crate::wasm::Access;
+
+    let module = crate::wasm::WasmModule::load(path)?;
+    let fields = response(&module.describe()?)?;
+    let mut access = Access::from_field(field(&fields, "fs"))?;
+    let granted = path
+        .file_name()
+        .is_some_and(|name| grants.iter().any(|grant| name == grant.as_str()));
+    if access == Access::Write && !granted {
+        access = Access::Read;
+    }
+    Plugin::new(path, &fields, Runner::Wasm(module, access))
+}
+
+/// Without the `wasm` feature, WebAssembly plugins are skipped.
+#[cfg(not(feature = "wasm"))]
+fn load_wasm(_path: &Path, _grants: &[String]) -> Result<Plugin, String> {
+    Err("WebAssembly plugins are not supported".to_string())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path)
+        .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+/// A command provided by a plugin.
+#[derive(Debug)]
+pub struct Plugin {
+    pub path: PathBuf,
+    pub name: String,
+    pub help: String,
+    pub require_args: bool,
+    runner: Runner,
+}
+
+/// How a plugin is run.
+#[derive(Debug)]
+enum Runner {
+    /// As a program of its own.
+    Process,
+    /// In the WebAssembly sandbox, with the file access it asked for.
+    #[cfg(feature = "wasm")]
+    Wasm(crate::wasm::WasmModule, crate::wasm::Access),
+}
+
+impl Plugin {
+    /// Asks the program at `path` what command it provides.
+    pub fn describe(path: &Path) -> Result<Self, String> {
+        let child = std::process::Command::new(path)
+            .arg("--describe")
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        let output = wait_with_timeout(child, DESCRIBE_TIMEOUT)?;
+        if !output.status.success() {
+            return Err(format!("exited with {}", output.status));
+        }
+        Self::new(path, &response(&output.stdout)?, Runner::Process)
+    }
+
+    /// The plugin at `path`, from the fields of its description.
+    fn new(path: &Path, fields: &[(String, Value)], runner: Runner) -> Result<Self, String> {
+        let file_name = path
+            .file_stem()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let name = string_field(fields, "name")?.unwrap_or(file_name);
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("invalid command name '{}'", name));
+        }
+        let help =
+            string_field(fields, "help")?.unwrap_or_else(|| format!("{} - a plugin command", name));
+        let require_args = match field(fields, "require_args") {
+            None | Some(Value::Null) => false,
+            Some(Value::Bool(require)) => *require,
+            Some(_) => return Err("'require_args' is not a boolean".to_string()),
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            name,
+            help,
+            require_args,
+            runner,
+        })
+    }
+
+    /// Runs the plugin for `inv`, returning what it answered. A plugin
+    /// program gets `vars` as its environment.
+    fn call(&self, inv: &Invocation, vars: &Variables) -> Result<CommandResult, String> {
+        let list = |items: &[String]| {
+            let quoted: Vec<String> = items.iter().map(|item| json::quote(item)).collect();
+            format!("[{}]", quoted.join(", "))
+        };
+        let request = format!(
+            "{{\"name\": {}, \"flags\": {}, \"args\": {}}}\n",
+            json::quote(&inv.name),
+            list(&inv.flags),
+            list(&inv.args)
+        );
+
+        let mut result = CommandResult::new();
+        let answer = match &self.runner {
+            Runner::Process => {
+                let output = self.run_process(&request, vars)?;
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                if !stderr.trim().is_empty() {
+                    result.append_notice(stderr.trim_end());
+                }
+                if !output.status.success() {
+                    result.status = exit_status(&output.status);
+                    return Ok(result);
+                }
+                output.stdout
+            }
+            #[cfg(feature = "wasm")]
+            Runner::Wasm(module, access) => {
+                let root = std::env::current_dir().map_err(|e| e.to_string())?;
+                module.run(&request, &root, *access)?
+            }
+        };
+
+        let fields = response(&answer)?;
+        if let Some(stdout) = string_field(&fields, "stdout")? {
+            result.stdout = stdout.into_bytes();
+        }
+        if let Some(stderr) = string_field(&fields, "stderr")?
+            && !stderr.is_empty()
+        {
+            result.append_stderr(stderr.trim_end());
+        }
+        match field(&fields, "status") {
+            None | Some(Value::Null) => {}
+            Some(Value::Number(n)) => {
+                result.status = n
+                    .parse()
+                    .map_err(|_| format!("'status' is not an exit status: {}", n))?
+            }
+            Some(_) => return Err("'status' is not a number".to_string()),
+        }
+        Ok(result)
+    }
+
+    /// Runs the plugin's program with `request` on its standard input.
+    fn run_process(&self, request: &str, vars: &Variables) -> Result<std::process::Output, String> {
+        let mut child = vars
+            .apply(&mut std::process::Command::new(&self.path))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        // A plugin that exits without reading its request is not an error
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(request.as_bytes());
+        }
+        // Ctrl+C stops the plugin, not the shell
+        let _guard = crate::signals::InterruptGuard::install();
+        wait_with_timeout(child, RUN_TIMEOUT)
+    }
+}
+
+/// Waits for `child` to exit, collecting its output, and kills it if it
+/// has not exited after `timeout`.
+fn wait_with_timeout(mut child: Child, timeout: Duration) -> Result<std::process::Output, String> {
+    fn collect(pipe: Option<impl Read + Send + 'static>) -> std::thread::JoinHandle<Vec<u8>> {
+        std::thread::spawn(move || {
+            let mut buf = Vec::new();
+            if let Some(mut pipe) = pipe {
+                let _ = pipe.read_to_end(&mut buf);
+            }
+            buf
+        })
+    }
+    let stdout = collect(child.stdout.take());
+    let stderr = collect(child.stderr.take());
+
+    let started = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(|e| e.to_string())? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(format!("no answer after {:?}", timeout));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    Ok(std::process::Output {
+        status,
+        stdout: stdout.join().unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+impl ShellCommand for Plugin {
+    fn run(&self, ctx: &mut ShellContext, inv: Invocation) -> CommandResult {
+        self.call(&inv, &ctx.state.vars).unwrap_or_else(|e| {
+            CommandResult::error(CommandError::Failed(format!(
+                "{}: plugin {}: {}",
+                inv.name,
+                self.path.display(),
+                e
+            )))
+        })
+    }
+}
+
+/// The exit status of a plugin or other program that failed, 128 + N if
+/// signal N killed it.
+pub(crate) fn exit_status(status: &std::process::ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Parses a plugin's answer, a single JSON object.
+fn response(stdout: &[u8]) -> Result<Vec<(String, Value)>, String> {
+    let text = std::str::from_utf8(stdout).map_err(|_| "response is not UTF-8".to_string())?;
+    match json::parse_all(text)?.as_slice() {
+        [Value::Object(fields)] => Ok(fields.clone()),
+        _ => Err("response is not a single JSON object".to_string()),
+    }
+}
+
+fn field<'a>(fields: &'a [(String, Value)], name: &str) -> Option<&'a Value> {
+    fields
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value)
+}
+
+/// A field that must be a string if present.
+fn string_field(fields: &[(String, Value)], name: &str) -> Result<Option<String>, String> {
+    match field(fields, name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("'{}' is not a string", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+
+    fn write_plugin(dir: &Path, name: &str, script: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_plugin_commands() {
+        let dir = tempdir().unwrap();
+        let request = dir.path().join("request.json");
+        write_plugin(
+            dir.path(),
+            "greet",
+            &format!(
+                "if [ \"$1\" = --describe ]; then\n\
+                 echo '{{\"help\": \"greet NAME... - say hello\", \"require_args\": true}}'\n\
+                 else\n\
+                 cat > '{}'\n\
+                 printf '%s\\n' '{{\"stdout\": \"Hello!\\n\", \"stderr\": \"greet: shy\", \"status\": 3}}'\n\
+                 fi\n",
+                request.display()
+            ),
+        );
+        write_plugin(
+            dir.path(),
+            "echo",
+            "echo '{\"help\": \"echo - not the real one\"}'\n",
+        );
+        write_plugin(dir.path(), "broken", "echo 'not json'\n");
+        write_plugin(
+            dir.path(),
+            "crash",
+            "[ \"$1\" = --describe ] && echo '{}' && exit\necho oops >&2; exit 4\n",
+        );
+        fs::write(dir.path().join("notes.txt"), "not a plugin").unwrap();
+
+        let mut cmds = crate::command::command_list();
+        register_all(&mut cmds, dir.path());
+        assert!(cmds.contains("greet") && cmds.contains("crash"));
+        assert!(!cmds.contains("broken") && !cmds.contains("notes.txt"));
+
+        let mut state = crate::command::ShellState::default();
+        let mut run = |line: &str| {
+            let call = crate::command_call::CommandCall::parse(line, &state)
+                .unwrap()
+                .unwrap();
+            cmds.capture_call(&mut state, &mut io::empty(), call)
+        };
+        let res = run("greet -l 'big world'");
+        assert_eq!(res.stdout_text(), "Hello!\n");
+        assert_eq!(res.stderr, "greet: shy");
+        assert_eq!(res.status, 3);
+        assert_eq!(
+            fs::read_to_string(&request).unwrap(),
+            "{\"name\": \"greet\", \"flags\": [\"-l\"], \"args\": [\"big world\"]}\n"
+        );
+
+        // The shell checks arguments and answers --help from the description
+        assert_eq!(run("greet").status, 2);
+        assert!(run("greet --help").stdout_text().contains("greet NAME..."));
+        // Built-in commands are not replaced
+        assert_eq!(run("echo hi").stdout_text(), "hi\n");
+        assert!(run("help").stdout_text().contains("say hello"));
+
+        let res = run("crash");
+        assert_eq!((res.status, res.stderr.as_str()), (4, "oops"));
+    }
+
+    #[cfg(feature = "wasm")]
+    #[test]
+    fn test_wasm_write_needs_grant() {
+        use crate::wasm::Access;
+
+        let description = r#"{\"help\": \"w - write\", \"fs\": \"write\"}"#;
+        let wat = format!(
+            r#"(module
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{}")
+                (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+                (func (export "describe") (result i64) (i64.const {}))
+                (func (export "run") (param i32 i32) (result i64) (i64.const 0)))"#,
+            description,
+            description.replace('\\', "").len()
+        );
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("w.wasm");
+        fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        let access = |plugin: Plugin| match plugin.runner {
+            Runner::Wasm(_, access) => access,
+            Runner::Process => unreachable!(),
+        };
+
+        let plugin = load_wasm(&path, &write_grants(dir.path())).unwrap();
+        assert_eq!(access(plugin), Access::Read);
+        fs::write(dir.path().join(WRITE_GRANTS), "# trusted\nw.wasm\n").unwrap();
+        let plugin = load_wasm(&path, &write_grants(dir.path())).unwrap();
+        assert_eq!(access(plugin), Access::Write);
+    }
+
+    #[test]
+    fn test_plugin_timeout() {
+        let dir = tempdir().unwrap();
+        let path = write_plugin(dir.path(), "hang", "exec sleep 30\n");
+        let child = std::process::Command::new(&path)
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let started = Instant::now();
+        assert_eq!(
+            wait_with_timeout(child, Duration::from_millis(100)).unwrap_err(),
+            "no answer after 100ms"
+        );
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
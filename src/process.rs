@@ -0,0 +1,441 @@
+//! Process-level helpers for commands that change how another command runs:
+//! redirecting the shell's standard streams, running a command in a forked
+//! child, sending signals, and reading the process table.
+
+use std::fs::File;
+use std::io;
+
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+/// Points a file descriptor (e.g. stdout) at another file while alive, and
+/// restores the original on drop.
+///
+/// Unlike swapping a Rust handle, this also captures output written directly
+/// to the descriptor by any code running in the meantime.
+pub struct FdRedirect {
+    #[cfg(unix)]
+    fd: i32,
+    #[cfg(unix)]
+    saved: i32,
+}
+
+impl FdRedirect {
+    #[cfg(unix)]
+    pub fn new(fd: i32, target: &File) -> io::Result<Self> {
+        let saved = unsafe { libc::dup(fd) };
+        if saved < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::dup2(target.as_raw_fd(), fd) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(saved) };
+            return Err(err);
+        }
+        Ok(Self { fd, saved })
+    }
+
+    #[cfg(not(unix))]
+    pub fn new(_fd: i32, _target: &File) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "redirection is not supported on this platform",
+        ))
+    }
+}
+
+impl Drop for FdRedirect {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::dup2(self.saved, self.fd);
+            libc::close(self.saved);
+        }
+    }
+}
+
+/// How a child process finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    Exited(i32),
+    Signaled(i32),
+}
+
+/// Signal names understood by commands that send signals, without the `SIG`
+/// prefix, paired with their numbers.
+#[cfg(unix)]
+pub const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP),
+    ("ABRT", libc::SIGABRT),
+    ("BUS", libc::SIGBUS),
+    ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("SEGV", libc::SIGSEGV),
+    ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CHLD", libc::SIGCHLD),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+    ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN),
+    ("TTOU", libc::SIGTTOU),
+    ("URG", libc::SIGURG),
+    ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ),
+    ("VTALRM", libc::SIGVTALRM),
+    ("PROF", libc::SIGPROF),
+    ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO),
+    ("SYS", libc::SIGSYS),
+];
+
+/// Parses a signal given as a number or a name such as `TERM` or `SIGTERM`
+/// (case-insensitive).
+#[cfg(unix)]
+pub fn parse_signal(value: &str) -> Option<i32> {
+    if let Ok(number) = value.parse::<i32>() {
+        return (0..=64).contains(&number).then_some(number);
+    }
+    let upper = value.to_ascii_uppercase();
+    let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+    SIGNALS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, num)| *num)
+}
+
+/// Returns the name of a signal number (e.g. `TERM`), or the number itself.
+#[cfg(unix)]
+pub fn signal_name(signal: i32) -> String {
+    SIGNALS
+        .iter()
+        .find(|(_, num)| *num == signal)
+        .map_or_else(|| signal.to_string(), |(name, _)| name.to_string())
+}
+
+/// Sends `signal` to the process `pid`.
+#[cfg(unix)]
+pub fn send_signal(pid: i32, signal: i32) -> io::Result<()> {
+    if unsafe { libc::kill(pid, signal) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Runs `child` in a forked copy of the shell and returns the child's pid.
+///
+/// The child shares the shell's open files, so output it writes to stdout
+/// and stderr appears directly. Its return value becomes the exit status.
+/// Only one thread exists in the child, so `child` should not rely on locks
+/// held by other threads of the shell.
+#[cfg(unix)]
+pub fn fork_child<F: FnOnce() -> i32>(child: F) -> io::Result<i32> {
+    use std::io::Write;
+
+    // Flush buffered output so it is not written twice
+    let _ = io::stdout().flush();
+    let _ = io::stderr().flush();
+
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => {
+            let code = child();
+            let _ = io::stdout().flush();
+            let _ = io::stderr().flush();
+            unsafe { libc::_exit(code) }
+        }
+        pid => Ok(pid),
+    }
+}
+
+/// Waits for the child `pid` to finish, giving up after `timeout`.
+///
+/// Returns `Ok(None)` if the timeout elapsed first. A timeout too long to
+/// have an end is no timeout.
+#[cfg(unix)]
+pub fn wait_child(
+    pid: i32,
+    timeout: Option<std::time::Duration>,
+) -> io::Result<Option<WaitStatus>> {
+    let deadline = timeout.and_then(|t| std::time::Instant::now().checked_add(t));
+    loop {
+        let mut status = 0;
+        let flags = if deadline.is_some() { libc::WNOHANG } else { 0 };
+        let ret = unsafe { libc::waitpid(pid, &mut status, flags) };
+        if ret == -1 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if ret == pid {
+            return Ok(Some(if libc::WIFSIGNALED(status) {
+                WaitStatus::Signaled(libc::WTERMSIG(status))
+            } else {
+                WaitStatus::Exited(libc::WEXITSTATUS(status))
+            }));
+        }
+        if let Some(deadline) = deadline {
+            if std::time::Instant::now() >= deadline {
+                return Ok(None);
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
+
+/// What a scheduling priority applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityTarget {
+    Process,
+    Group,
+    User,
+}
+
+/// The type of `getpriority`'s first argument, which glibc and uClibc
+/// declare as an enum and other C libraries as a plain `int`.
+#[cfg(all(target_os = "linux", any(target_env = "gnu", target_env = "uclibc")))]
+type PriorityWhich = libc::__priority_which_t;
+#[cfg(all(
+    unix,
+    not(all(target_os = "linux", any(target_env = "gnu", target_env = "uclibc")))
+))]
+type PriorityWhich = libc::c_int;
+
+#[cfg(unix)]
+impl PriorityTarget {
+    fn which(self) -> PriorityWhich {
+        (match self {
+            PriorityTarget::Process => libc::PRIO_PROCESS,
+            PriorityTarget::Group => libc::PRIO_PGRP,
+            PriorityTarget::User => libc::PRIO_USER,
+        }) as PriorityWhich
+    }
+}
+
+/// Sets `errno` to 0, so that a later `io::Error::last_os_error()` only
+/// reports failures from calls made after this one.
+#[cfg(unix)]
+fn clear_errno() {
+    #[cfg(any(target_os = "linux", target_os = "dragonfly"))]
+    let errno = unsafe { libc::__errno_location() };
+    #[cfg(any(target_vendor = "apple", target_os = "freebsd"))]
+    let errno = unsafe { libc::__error() };
+    #[cfg(any(target_os = "android", target_os = "netbsd", target_os = "openbsd"))]
+    let errno = unsafe { libc::__errno() };
+    unsafe { *errno = 0 };
+}
+
+/// Returns the nice value of a process, process group, or user.
+/// An `id` of 0 means the calling process, group, or user.
+#[cfg(unix)]
+pub fn get_priority(target: PriorityTarget, id: u32) -> io::Result<i32> {
+    // -1 is a valid priority, so errors are detected through errno
+    clear_errno();
+    let priority = unsafe { libc::getpriority(target.which(), id) };
+    if priority == -1 {
+        let err = io::Error::last_os_error();
+        if err.raw_os_error() != Some(0) {
+            return Err(err);
+        }
+    }
+    Ok(priority)
+}
+
+/// Sets the nice value of a process, process group, or user.
+#[cfg(unix)]
+pub fn set_priority(target: PriorityTarget, id: u32, priority: i32) -> io::Result<()> {
+    if unsafe { libc::setpriority(target.which(), id, priority) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Looks up a user by name or numeric id and returns the uid.
+#[cfg(unix)]
+pub fn user_id(user: &str) -> Option<u32> {
+    if let Ok(uid) = user.parse() {
+        return Some(uid);
+    }
+    let name = std::ffi::CString::new(user).ok()?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return None;
+    }
+    Some(unsafe { (*entry).pw_uid })
+}
+
+/// A running process, as read from `/proc/<pid>`.
+#[derive(Debug, Clone)]
+pub struct ProcessInfo {
+    pub pid: i32,
+    /// Effective user id.
+    pub uid: u32,
+    /// Executable name, truncated by the kernel to 15 bytes.
+    pub name: String,
+    /// Full argument list; empty for kernel threads.
+    pub cmdline: Vec<String>,
+    /// Start time in clock ticks since boot.
+    pub start_time: u64,
+    /// State code from `/proc/<pid>/stat`: `R` running, `S` sleeping, `D`
+    /// in uninterruptible wait, `Z` zombie, `T` stopped, and so on.
+    pub state: char,
+    /// User plus system CPU time in clock ticks.
+    pub cpu_time: u64,
+    /// Resident set size in bytes.
+    pub rss: u64,
+}
+
+impl ProcessInfo {
+    /// The command line joined with spaces, or the name in brackets for
+    /// kernel threads, as `ps` shows it.
+    pub fn command(&self) -> String {
+        if self.cmdline.is_empty() {
+            format!("[{}]", self.name)
+        } else {
+            self.cmdline.join(" ")
+        }
+    }
+}
+
+/// Lists all processes visible in `/proc`, sorted by pid.
+///
+/// Processes that exit while being read are skipped.
+pub fn list_processes() -> io::Result<Vec<ProcessInfo>> {
+    let mut processes: Vec<ProcessInfo> = std::fs::read_dir("/proc")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter_map(read_process)
+        .collect();
+    processes.sort_by_key(|p| p.pid);
+    Ok(processes)
+}
+
+/// Reads a single process from `/proc/<pid>`.
+pub fn read_process(pid: i32) -> Option<ProcessInfo> {
+    let dir = std::path::PathBuf::from(format!("/proc/{}", pid));
+    let stat = std::fs::read_to_string(dir.join("stat")).ok()?;
+    let mut info = parse_stat(&stat)?;
+
+    let status = std::fs::read_to_string(dir.join("status")).ok()?;
+    info.uid = status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))
+        .and_then(|ids| ids.split_whitespace().nth(1)?.parse().ok())
+        .unwrap_or(0);
+
+    let cmdline = std::fs::read(dir.join("cmdline")).unwrap_or_default();
+    info.cmdline = cmdline
+        .split(|&b| b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+    Some(info)
+}
+
+/// Parses the contents of `/proc/<pid>/stat`.
+///
+/// The name is wrapped in parentheses and may itself contain spaces or
+/// parentheses, so the fields after it are located from the last `)`.
+fn parse_stat(stat: &str) -> Option<ProcessInfo> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let pid = stat[..open].trim().parse().ok()?;
+    let name = stat[open + 1..close].to_string();
+    let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+    let field = |i: usize| fields.get(i).and_then(|f| f.parse::<u64>().ok());
+
+    Some(ProcessInfo {
+        pid,
+        uid: 0,
+        name,
+        cmdline: Vec::new(),
+        start_time: field(19)?,
+        state: fields.first()?.chars().next()?,
+        cpu_time: field(11)? + field(12)?,
+        rss: field(21)? * page_size(),
+    })
+}
+
+/// The size of a memory page, which `/proc` uses as its unit for RSS.
+fn page_size() -> u64 {
+    #[cfg(unix)]
+    {
+        let size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+        if size > 0 {
+            return size as u64;
+        }
+    }
+    4096
+}
+
+/// The number of clock ticks per second, the unit of process CPU and
+/// start times.
+pub fn clock_ticks() -> u64 {
+    #[cfg(unix)]
+    {
+        let ticks = unsafe { libc::sysconf(libc::_SC_CLK_TCK) };
+        if ticks > 0 {
+            return ticks as u64;
+        }
+    }
+    100
+}
+
+/// Looks up the name of a user id.
+#[cfg(unix)]
+pub fn user_name(uid: u32) -> Option<String> {
+    let entry = unsafe { libc::getpwuid(uid) };
+    if entry.is_null() {
+        return None;
+    }
+    // SAFETY: a non-null entry has a valid NUL-terminated name.
+    let name = unsafe { std::ffi::CStr::from_ptr((*entry).pw_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// Looks up the name of a group id.
+#[cfg(unix)]
+pub fn group_name(gid: u32) -> Option<String> {
+    let entry = unsafe { libc::getgrgid(gid) };
+    if entry.is_null() {
+        return None;
+    }
+    // SAFETY: a non-null entry has a valid NUL-terminated name.
+    let name = unsafe { std::ffi::CStr::from_ptr((*entry).gr_name) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_wait_child_without_end() {
+        // Reaped by wait_child, not by Child
+        let pid = std::process::Command::new("true").spawn().unwrap().id() as i32;
+        let status = wait_child(pid, Some(std::time::Duration::MAX)).unwrap();
+        assert_eq!(status, Some(WaitStatus::Exited(0)));
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "4242 (tmux: server) S 1 4242 4242 0 -1 4194560 2046 0 0 0 \
+                    150 30 0 0 20 0 1 0 98765 12345678 900 18446744073709551615";
+        let info = parse_stat(stat).unwrap();
+        assert_eq!(info.pid, 4242);
+        assert_eq!(info.name, "tmux: server");
+        assert_eq!(info.start_time, 98765);
+        assert_eq!(info.state, 'S');
+        assert_eq!(info.cpu_time, 180);
+        assert_eq!(info.rss, 900 * page_size());
+    }
+}
@@ -0,0 +1,167 @@
+//! A small pseudo-random number generator (xoshiro256**).
+//!
+//! Good enough for shuffling, test data, and overwriting files; it is not a
+//! cryptographically secure generator.
+
+use std::collections::HashSet;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub struct Rng {
+    state: [u64; 4],
+}
+
+impl Rng {
+    /// Creates a generator with a reproducible sequence for `seed`.
+    pub fn from_seed(seed: u64) -> Self {
+        // Expand the seed with splitmix64, as recommended for xoshiro
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        Self {
+            state: [next(), next(), next(), next()],
+        }
+    }
+
+    /// Creates a generator seeded from `/dev/urandom`, or from the clock and
+    /// process id where that is unavailable.
+    pub fn from_entropy() -> Self {
+        let mut seed = [0u8; 8];
+        let from_device = std::fs::File::open("/dev/urandom")
+            .and_then(|mut f| std::io::Read::read_exact(&mut f, &mut seed))
+            .is_ok();
+        if from_device {
+            return Self::from_seed(u64::from_le_bytes(seed));
+        }
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::from_seed(nanos ^ ((std::process::id() as u64) << 32))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let result = self.state[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = self.state[1] << 17;
+        self.state[2] ^= self.state[0];
+        self.state[3] ^= self.state[1];
+        self.state[1] ^= self.state[2];
+        self.state[0] ^= self.state[3];
+        self.state[2] ^= t;
+        self.state[3] = self.state[3].rotate_left(45);
+        result
+    }
+
+    /// Returns a uniformly distributed number in `0..n`. `n` must be non-zero.
+    pub fn below(&mut self, n: u64) -> u64 {
+        // Reject the top partial range so every value is equally likely
+        let zone = u64::MAX - u64::MAX % n;
+        loop {
+            let x = self.next_u64();
+            if x < zone {
+                return x % n;
+            }
+        }
+    }
+
+    /// Shuffles `items` in place (Fisher-Yates), stopping once the first
+    /// `count` positions hold a uniform random sample.
+    pub fn shuffle<T>(&mut self, items: &mut [T], count: usize) {
+        let len = items.len();
+        for i in 0..count.min(len.saturating_sub(1)) {
+            let j = i + self.below((len - i) as u64) as usize;
+            items.swap(i, j);
+        }
+    }
+
+    /// Picks `count` distinct numbers from `lo..=hi`, in random order,
+    /// without going through the whole range (Floyd's algorithm). `count`
+    /// must not be more than the range holds.
+    pub fn sample_range(&mut self, lo: u64, hi: u64, count: usize) -> Vec<u64> {
+        let span = hi - lo;
+        let mut picked = HashSet::with_capacity(count);
+        let mut sample = Vec::with_capacity(count);
+        if count > 0 {
+            // Each step picks from one more offset, taking the newest if the
+            // pick was taken before
+            for j in span - (count as u64 - 1)..=span {
+                let t = if j == u64::MAX {
+                    self.next_u64()
+                } else {
+                    self.below(j + 1)
+                };
+                let t = if picked.insert(t) { t } else { j };
+                picked.insert(t);
+                sample.push(lo + t);
+            }
+        }
+        self.shuffle(&mut sample, count);
+        sample
+    }
+
+    /// Returns a uniformly distributed number in `min..=max`.
+    pub fn between(&mut self, min: i64, max: i64) -> i64 {
+        let span = max.wrapping_sub(min) as u64;
+        let offset = if span == u64::MAX {
+            self.next_u64()
+        } else {
+            self.below(span + 1)
+        };
+        min.wrapping_add(offset as i64)
+    }
+
+    /// Fills `buf` with random bytes.
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            let bytes = self.next_u64().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+}
+
+/// Turns seed text into a seed: numbers are used as is, any other text is
+/// hashed (FNV-1a).
+pub fn seed_from_text(seed: &str) -> u64 {
+    seed.parse().unwrap_or_else(|_| {
+        seed.bytes().fold(0xCBF2_9CE4_8422_2325, |hash, b| {
+            (hash ^ b as u64).wrapping_mul(0x0100_0000_01B3)
+        })
+    })
+}
+
+/// A shell session's own generator, behind `random` and `$RANDOM`. It is
+/// created from entropy on first use and can be reseeded by assigning to
+/// `RANDOM`. Clones share the generator, so commands run on other threads
+/// for the session draw from the same sequence.
+#[derive(Clone, Default)]
+pub struct ShellRng(Arc<Mutex<Option<Rng>>>);
+
+impl ShellRng {
+    /// Runs `f` with the generator.
+    pub fn with<T>(&self, f: impl FnOnce(&mut Rng) -> T) -> T {
+        let mut rng = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        f(rng.get_or_insert_with(Rng::from_entropy))
+    }
+
+    /// Reseeds the generator so that its sequence is reproducible.
+    pub fn seed(&self, seed: u64) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(Rng::from_seed(seed));
+    }
+
+    /// The value of `$RANDOM`: a number from 0 to 32767.
+    pub fn random(&self) -> u64 {
+        self.with(|rng| rng.below(32768))
+    }
+}
+
+impl fmt::Debug for ShellRng {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ShellRng").finish_non_exhaustive()
+    }
+}
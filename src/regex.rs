@@ -0,0 +1,524 @@
+//! A small regular expression engine.
+//!
+//! Supports the extended-regex subset commands most often need: literals,
+//! `.`, bracket expressions (`[a-z]`, `[^0-9]`), anchors (`^`, `$`), the
+//! quantifiers `*`, `+`, `?`, and `{m,n}`, groups, and `|` alternation.
+//! Backslash escapes a metacharacter; `\d`, `\w`, and `\s` match digits,
+//! word characters, and whitespace.
+//!
+//! Patterns compile to a program for a Pike VM, which runs every possible
+//! match in lock step. Matching takes time proportional to the length of
+//! the text times the size of the pattern, without backtracking, so
+//! patterns like `(a*)*b` stay fast and long inputs cannot overflow the
+//! stack.
+
+#[derive(Debug, Clone)]
+enum Node {
+    Char(char),
+    Any,
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+    Start,
+    End,
+    Group(Vec<Vec<Node>>),
+    Repeat {
+        node: Box<Node>,
+        min: usize,
+        max: usize,
+    },
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem {
+    Range(char, char),
+    Digit,
+    Word,
+    Space,
+}
+
+impl ClassItem {
+    fn matches(&self, c: char, ignore_case: bool) -> bool {
+        match self {
+            ClassItem::Range(lo, hi) => {
+                (*lo..=*hi).contains(&c)
+                    || (ignore_case
+                        && c.to_lowercase()
+                            .chain(c.to_uppercase())
+                            .any(|c| (*lo..=*hi).contains(&c)))
+            }
+            ClassItem::Digit => c.is_ascii_digit(),
+            ClassItem::Word => c.is_alphanumeric() || c == '_',
+            ClassItem::Space => c.is_whitespace(),
+        }
+    }
+}
+
+/// One instruction of a compiled expression.
+#[derive(Debug, Clone)]
+enum Inst {
+    /// Consume one character matching a `Char`, `Any`, or `Class` node.
+    Consume(Node),
+    /// Continue only at the start of the text.
+    Start,
+    /// Continue only at the end of the text.
+    End,
+    /// Continue at both targets.
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+/// Compiled programs larger than this are rejected, since counted
+/// repetition like `(a{1000}){1000}` multiplies the pattern out.
+const MAX_PROGRAM: usize = 100_000;
+
+/// A compiled regular expression.
+#[derive(Debug, Clone)]
+pub struct Regex {
+    program: Vec<Inst>,
+    ignore_case: bool,
+}
+
+impl Regex {
+    /// Compiles `pattern`, optionally matching letters case-insensitively.
+    pub fn new(pattern: &str, ignore_case: bool) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+        };
+        let alternatives = parser.parse_alternatives()?;
+        if parser.pos < parser.chars.len() {
+            return Err("unmatched ')'".to_string());
+        }
+        let mut program = Vec::new();
+        compile_alternatives(&alternatives, &mut program)?;
+        program.push(Inst::Match);
+        Ok(Self {
+            program,
+            ignore_case,
+        })
+    }
+
+    /// Whether the expression matches anywhere in `text`.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.run(text, false)
+    }
+
+    /// Whether the expression matches the whole of `text`.
+    pub fn is_full_match(&self, text: &str) -> bool {
+        self.run(text, true)
+    }
+
+    /// Runs the program over `text`, advancing every live thread one
+    /// character at a time, so the cost is linear in the length of the
+    /// text and nothing recurses on it.
+    fn run(&self, text: &str, full: bool) -> bool {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut stack = Vec::new();
+        let positions = text
+            .char_indices()
+            .map(|(pos, c)| (pos, Some(c)))
+            .chain(std::iter::once((text.len(), None)));
+
+        for (pos, c) in positions {
+            // Unanchored searches may start a match at any position
+            if !full || pos == 0 {
+                self.add_thread(&mut current, &mut stack, 0, pos, text.len());
+            }
+            if current.contains(self.program.len() - 1) && (!full || c.is_none()) {
+                return true;
+            }
+            let Some(c) = c else {
+                break;
+            };
+
+            next.clear();
+            for &pc in &current.pcs {
+                if let Inst::Consume(node) = &self.program[pc]
+                    && self.matches_char(node, c)
+                {
+                    let after = pos + c.len_utf8();
+                    self.add_thread(&mut next, &mut stack, pc + 1, after, text.len());
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            if full && current.pcs.is_empty() {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Adds `pc` to `threads`, following jumps, splits, and anchors that
+    /// hold at `pos`.
+    fn add_thread(
+        &self,
+        threads: &mut Threads,
+        stack: &mut Vec<usize>,
+        pc: usize,
+        pos: usize,
+        len: usize,
+    ) {
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if !threads.insert(pc) {
+                continue;
+            }
+            match self.program[pc] {
+                Inst::Start if pos == 0 => stack.push(pc + 1),
+                Inst::End if pos == len => stack.push(pc + 1),
+                Inst::Jump(target) => stack.push(target),
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn matches_char(&self, node: &Node, c: char) -> bool {
+        match node {
+            Node::Any => c != '\n',
+            Node::Char(expected) => {
+                *expected == c || (self.ignore_case && expected.to_lowercase().eq(c.to_lowercase()))
+            }
+            Node::Class { items, negated } => {
+                items.iter().any(|item| item.matches(c, self.ignore_case)) != *negated
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The set of instructions visited while computing one step, in order.
+struct Threads {
+    pcs: Vec<usize>,
+    seen: Vec<bool>,
+}
+
+impl Threads {
+    fn new(size: usize) -> Self {
+        Self {
+            pcs: Vec::new(),
+            seen: vec![false; size],
+        }
+    }
+
+    /// Adds `pc`, returning false if it was already present.
+    fn insert(&mut self, pc: usize) -> bool {
+        if self.seen[pc] {
+            return false;
+        }
+        self.seen[pc] = true;
+        self.pcs.push(pc);
+        true
+    }
+
+    fn contains(&self, pc: usize) -> bool {
+        self.seen[pc]
+    }
+
+    fn clear(&mut self) {
+        for &pc in &self.pcs {
+            self.seen[pc] = false;
+        }
+        self.pcs.clear();
+    }
+}
+
+/// Appends `inst` to `program`, failing once it grows too large.
+fn emit(program: &mut Vec<Inst>, inst: Inst) -> Result<usize, String> {
+    if program.len() >= MAX_PROGRAM {
+        return Err("pattern is too large".to_string());
+    }
+    program.push(inst);
+    Ok(program.len() - 1)
+}
+
+/// Compiles `a|b|c` as a chain of splits, each alternative jumping past
+/// the rest when it is done.
+fn compile_alternatives(alternatives: &[Vec<Node>], program: &mut Vec<Inst>) -> Result<(), String> {
+    let mut jumps = Vec::new();
+    for (i, alt) in alternatives.iter().enumerate() {
+        let split = if i + 1 < alternatives.len() {
+            Some(emit(program, Inst::Split(0, 0))?)
+        } else {
+            None
+        };
+        for node in alt {
+            compile_node(node, program)?;
+        }
+        if let Some(split) = split {
+            jumps.push(emit(program, Inst::Jump(0))?);
+            program[split] = Inst::Split(split + 1, program.len());
+        }
+    }
+    for jump in jumps {
+        program[jump] = Inst::Jump(program.len());
+    }
+    Ok(())
+}
+
+fn compile_node(node: &Node, program: &mut Vec<Inst>) -> Result<(), String> {
+    match node {
+        Node::Start => {
+            emit(program, Inst::Start)?;
+        }
+        Node::End => {
+            emit(program, Inst::End)?;
+        }
+        Node::Group(alternatives) => compile_alternatives(alternatives, program)?,
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile_node(node, program)?;
+            }
+            if *max == usize::MAX {
+                // loop: split(body, out); body; jump loop
+                let split = emit(program, Inst::Split(0, 0))?;
+                compile_node(node, program)?;
+                emit(program, Inst::Jump(split))?;
+                program[split] = Inst::Split(split + 1, program.len());
+            } else {
+                // Each optional copy may be skipped to the end
+                let mut splits = Vec::new();
+                for _ in *min..*max {
+                    splits.push(emit(program, Inst::Split(0, 0))?);
+                    compile_node(node, program)?;
+                }
+                for split in splits {
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+            }
+        }
+        _ => {
+            emit(program, Inst::Consume(node.clone()))?;
+        }
+    }
+    Ok(())
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    /// Parses `a|b|c` up to a closing `)` or the end of the pattern.
+    fn parse_alternatives(&mut self) -> Result<Vec<Vec<Node>>, String> {
+        let mut alternatives = vec![self.parse_sequence()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            alternatives.push(self.parse_sequence()?);
+        }
+        Ok(alternatives)
+    }
+
+    fn parse_sequence(&mut self) -> Result<Vec<Node>, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            if c == '|' || c == ')' {
+                break;
+            }
+            let atom = self.parse_atom()?;
+            nodes.push(self.parse_quantifier(atom)?);
+        }
+        Ok(nodes)
+    }
+
+    fn parse_atom(&mut self) -> Result<Node, String> {
+        let c = self.next().ok_or("unexpected end of pattern")?;
+        Ok(match c {
+            '.' => Node::Any,
+            '^' => Node::Start,
+            '$' => Node::End,
+            '(' => {
+                let alternatives = self.parse_alternatives()?;
+                if self.next() != Some(')') {
+                    return Err("unmatched '('".to_string());
+                }
+                Node::Group(alternatives)
+            }
+            '[' => self.parse_class()?,
+            '*' | '+' | '?' => return Err(format!("nothing to repeat before '{}'", c)),
+            '\\' => match self.next().ok_or("trailing backslash")? {
+                'd' => Node::Class {
+                    items: vec![ClassItem::Digit],
+                    negated: false,
+                },
+                'w' => Node::Class {
+                    items: vec![ClassItem::Word],
+                    negated: false,
+                },
+                's' => Node::Class {
+                    items: vec![ClassItem::Space],
+                    negated: false,
+                },
+                't' => Node::Char('\t'),
+                'n' => Node::Char('\n'),
+                escaped => Node::Char(escaped),
+            },
+            _ => Node::Char(c),
+        })
+    }
+
+    fn parse_quantifier(&mut self, atom: Node) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, usize::MAX),
+            Some('+') => (1, usize::MAX),
+            Some('?') => (0, 1),
+            Some('{') => match self.parse_braces() {
+                Some(bounds) => return self.wrap_repeat(atom, bounds),
+                // Not a valid interval, so `{` is a literal
+                None => return Ok(atom),
+            },
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        self.wrap_repeat(atom, (min, max))
+    }
+
+    fn wrap_repeat(&mut self, atom: Node, (min, max): (usize, usize)) -> Result<Node, String> {
+        if matches!(atom, Node::Start | Node::End) {
+            return Err("nothing to repeat".to_string());
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+        })
+    }
+
+    /// Parses `{m}`, `{m,}`, or `{m,n}`, leaving the position unchanged if
+    /// the braces do not form an interval.
+    fn parse_braces(&mut self) -> Option<(usize, usize)> {
+        let rest: String = self.chars[self.pos + 1..].iter().collect();
+        let close = rest.find('}')?;
+        let body = &rest[..close];
+        let (min, max) = match body.split_once(',') {
+            None => {
+                let n = body.parse().ok()?;
+                (n, n)
+            }
+            Some((min, "")) => (min.parse().ok()?, usize::MAX),
+            Some((min, max)) => (min.parse().ok()?, max.parse().ok()?),
+        };
+        if min > max {
+            return None;
+        }
+        self.pos += body.chars().count() + 2;
+        Some((min, max))
+    }
+
+    fn parse_class(&mut self) -> Result<Node, String> {
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+
+        let mut items = Vec::new();
+        let mut first = true;
+        loop {
+            let c = self.next().ok_or("unmatched '['")?;
+            if c == ']' && !first {
+                break;
+            }
+            first = false;
+
+            let lo = match c {
+                '\\' => match self.next().ok_or("unmatched '['")? {
+                    'd' => {
+                        items.push(ClassItem::Digit);
+                        continue;
+                    }
+                    'w' => {
+                        items.push(ClassItem::Word);
+                        continue;
+                    }
+                    's' => {
+                        items.push(ClassItem::Space);
+                        continue;
+                    }
+                    escaped => escaped,
+                },
+                _ => c,
+            };
+
+            if self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']') {
+                self.pos += 1;
+                let hi = self.next().ok_or("unmatched '['")?;
+                if hi < lo {
+                    return Err(format!("invalid range '{}-{}'", lo, hi));
+                }
+                items.push(ClassItem::Range(lo, hi));
+            } else {
+                items.push(ClassItem::Range(lo, lo));
+            }
+        }
+        Ok(Node::Class { items, negated })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_regex_matching() {
+        let re = |p: &str| Regex::new(p, false).unwrap();
+
+        assert!(re("fire").is_match("firefox"));
+        assert!(re("^fire").is_match("firefox"));
+        assert!(!re("fox$").is_match("foxfire"));
+        assert!(re("a.c").is_match("xabcx"));
+        assert!(re("ab*c").is_match("ac"));
+        assert!(re("ab+c").is_match("abbbc"));
+        assert!(!re("ab+c").is_match("ac"));
+        assert!(re("colou?r").is_match("color"));
+        assert!(re("^(cat|dog)s?$").is_match("dogs"));
+        assert!(!re("^(cat|dog)s?$").is_match("cow"));
+        assert!(re("[0-9]{2,3}").is_match("a42"));
+        assert!(!re("^[0-9]{2,3}$").is_match("4242"));
+        assert!(re("[^a-z]").is_match("abC"));
+        assert!(re(r"\d+\.\d+").is_match("v1.25"));
+        assert!(re("a{x").is_match("a{x"));
+
+        assert!(re("b.*").is_full_match("bash"));
+        assert!(!re("bas").is_full_match("bash"));
+        assert!(Regex::new("BASH", true).unwrap().is_match("/bin/bash"));
+
+        assert!(Regex::new("(ab", false).is_err());
+        assert!(Regex::new("*a", false).is_err());
+        assert!(Regex::new("[z-a]", false).is_err());
+        assert!(Regex::new("(a{1000}){1000}", false).is_err());
+    }
+
+    #[test]
+    fn test_regex_long_input() {
+        let re = |p: &str| Regex::new(p, false).unwrap();
+
+        let mut text = "x".repeat(1 << 20);
+        assert!(!re(".*q").is_match(&text));
+        text.push('q');
+        assert!(re(".*q").is_match(&text));
+        assert!(re("^x*q$").is_full_match(&text));
+
+        // Nested quantifiers that backtrack exponentially elsewhere
+        let many_a = "a".repeat(10_000);
+        assert!(!re("(a*)*b").is_match(&many_a));
+        assert!(re("^(a|aa)*$").is_full_match(&many_a));
+    }
+}
@@ -0,0 +1,194 @@
+//! A shell session, for running command lines from other programs.
+//!
+//! ```
+//! use zero_shell::Shell;
+//!
+//! let mut shell = Shell::new();
+//! let results = shell.run_line("x=6; echo $((x * 7))");
+//! assert_eq!(results[1].stdout_text(), "42\n");
+//! assert_eq!(shell.last_status(), 0);
+//! ```
+
+use std::io::{self, Read};
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::Local;
+
+use crate::command::{
+    Command, CommandList, CommandResult, Output, ShellContext, ShellState, command_list,
+};
+use crate::command_call::{CommandCall, split_line};
+
+/// The built-in commands and the state of one session: its variables,
+/// aliases, last exit status, and scheduled jobs.
+pub struct Shell {
+    commands: CommandList,
+    state: ShellState,
+}
+
+impl Shell {
+    /// A session with every built-in command.
+    pub fn new() -> Self {
+        Self::with_commands(command_list())
+    }
+
+    /// A session with only the commands in `commands`.
+    pub fn with_commands(commands: CommandList) -> Self {
+        Self {
+            commands,
+            state: ShellState::default(),
+        }
+    }
+
+    /// Adds the commands of the plugins in `dir`, such as
+    /// [`crate::plugins::dir`], running each to ask what it provides. No
+    /// plugins are loaded unless this is called.
+    pub fn load_plugins(&mut self, dir: &Path) {
+        crate::plugins::register_all(&mut self.commands, dir);
+    }
+
+    /// Adds a command, replacing any of the same name.
+    pub fn register(&mut self, name: &str, command: Command) {
+        self.commands.register(name.to_string(), command);
+    }
+
+    pub fn state(&self) -> &ShellState {
+        &self.state
+    }
+
+    pub fn state_mut(&mut self) -> &mut ShellState {
+        &mut self.state
+    }
+
+    /// The exit status of the last command run, for `$?`.
+    pub fn last_status(&self) -> i32 {
+        self.state.last_status
+    }
+
+    /// Runs each command of `line` in turn, with no input, and returns
+    /// their results, each with all of the command's output. Stops after
+    /// a command that exits the shell, such as `exit`; its result has
+    /// `should_exit` set.
+    pub fn run_line(&mut self, line: &str) -> Vec<CommandResult> {
+        let mut results = Vec::new();
+        for segment in split_line(line) {
+            let result = match CommandCall::parse(segment, &self.state) {
+                Ok(Some(call)) => {
+                    self.commands
+                        .capture_call(&mut self.state, &mut io::empty(), call)
+                }
+                Ok(None) => continue,
+                Err(e) => CommandResult::error(e),
+            };
+            self.state.last_status = result.status;
+            let should_exit = result.should_exit;
+            results.push(result);
+            if should_exit {
+                break;
+            }
+        }
+        results
+    }
+
+    /// Runs each command of `line` in turn as the prompt does: reading
+    /// `stdin`, and writing to `out`, as they go or once each finishes.
+    /// Returns the status to exit with if a command exits the shell.
+    pub fn run_line_with<'a>(
+        &'a mut self,
+        line: &str,
+        stdin: &'a mut dyn Read,
+        out: Output<'a>,
+    ) -> io::Result<Option<i32>> {
+        let mut ctx = ShellContext::new(&self.commands, &mut self.state, stdin, out);
+        for segment in split_line(line) {
+            let Some(result) = run_segment(&mut ctx, segment) else {
+                continue;
+            };
+            if result.should_exit {
+                return Ok(Some(result.status));
+            }
+            ctx.out.write_result(&result)?;
+            ctx.state.last_status = result.status;
+        }
+        Ok(None)
+    }
+
+    /// How long until the next job scheduled with `schedule` is due, or
+    /// zero if one is overdue.
+    pub fn next_job_in(&self) -> Option<Duration> {
+        let due = self.state.jobs.next_due()?;
+        Some((due - Local::now()).to_std().unwrap_or_default())
+    }
+
+    /// Runs the scheduled jobs that are due, writing their output to `out`
+    /// as they go. Each runs with the state and in the directory it was
+    /// scheduled with, and the shell's own directory is restored after.
+    ///
+    /// Jobs only run when this is called, which the prompt does between
+    /// lines.
+    pub fn run_due_jobs(&mut self, out: Output) -> io::Result<()> {
+        for job in self.state.jobs.take_due(Local::now()) {
+            let saved_dir = std::env::current_dir().ok();
+            if let Some(dir) = &job.dir {
+                // A job whose directory is gone runs where the shell is
+                let _ = std::env::set_current_dir(dir);
+            }
+
+            let mut state = job.state;
+            let mut stdin = io::empty();
+            let out = Output::new(&mut *out.stdout, &mut *out.stderr);
+            let mut ctx = ShellContext::new(&self.commands, &mut state, &mut stdin, out);
+            let mut written = Ok(());
+            for segment in split_line(&job.label) {
+                let Some(result) = run_segment(&mut ctx, segment) else {
+                    continue;
+                };
+                // `exit` ends the job, not the shell
+                if result.should_exit {
+                    break;
+                }
+                written = ctx.out.write_result(&result);
+                if written.is_err() {
+                    break;
+                }
+                ctx.state.last_status = result.status;
+            }
+
+            if let Some(dir) = saved_dir {
+                let _ = std::env::set_current_dir(dir);
+            }
+            written?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses and runs one command of a line, or returns `None` if it is empty.
+/// A failed expansion fails the command without running it.
+fn run_segment(ctx: &mut ShellContext, segment: &str) -> Option<CommandResult> {
+    match CommandCall::parse(segment, ctx.state) {
+        Ok(call) => call.map(|call| ctx.commands.run_call(ctx, call)),
+        Err(e) => Some(CommandResult::error(e)),
+    }
+}
+
+/// Waits up to `timeout` for a line on standard input, returning `false`
+/// if none came in time. When standard input is not a terminal this
+/// returns `true` straight away, as lines may already be buffered.
+pub fn wait_for_input(timeout: Duration) -> bool {
+    use std::io::IsTerminal;
+
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        return true;
+    }
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+    crate::terminal::poll_readable(&stdin, timeout_ms)
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
+}
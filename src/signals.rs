@@ -0,0 +1,98 @@
+//! Signal helpers for long-running commands.
+//!
+//! Commands such as `watch` loop until the user presses Ctrl+C. Without a
+//! handler, SIGINT would terminate the whole shell, so these commands install
+//! an [`InterruptGuard`] and poll [`interrupted`] instead. [`IgnoreGuard`]
+//! shields a command from a signal entirely, as `nohup` does for SIGHUP.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_sigint(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+/// Catches SIGINT while alive, restoring the previous disposition on drop.
+pub struct InterruptGuard {
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+impl InterruptGuard {
+    pub fn install() -> Self {
+        INTERRUPTED.store(false, Ordering::SeqCst);
+
+        #[cfg(unix)]
+        {
+            let handler = on_sigint as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            // SAFETY: the handler only stores to an atomic, which is
+            // async-signal-safe.
+            let previous = unsafe { libc::signal(libc::SIGINT, handler) };
+            Self { previous }
+        }
+
+        #[cfg(not(unix))]
+        Self {}
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(libc::SIGINT, self.previous);
+        }
+        INTERRUPTED.store(false, Ordering::SeqCst);
+    }
+}
+
+/// Whether SIGINT has arrived since the current guard was installed.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Sleeps for `duration`, waking early if interrupted.
+/// Returns `true` if the sleep was cut short by SIGINT. A duration too long
+/// to have an end sleeps until then.
+pub fn sleep_interruptible(duration: std::time::Duration) -> bool {
+    let tick = std::time::Duration::from_millis(50);
+    let deadline = std::time::Instant::now().checked_add(duration);
+    loop {
+        if interrupted() {
+            return true;
+        }
+        let now = std::time::Instant::now();
+        match deadline {
+            Some(deadline) if now >= deadline => return false,
+            Some(deadline) => std::thread::sleep((deadline - now).min(tick)),
+            None => std::thread::sleep(tick),
+        }
+    }
+}
+
+/// Ignores a signal while alive, restoring the previous disposition on drop.
+pub struct IgnoreGuard {
+    #[cfg(unix)]
+    signal: libc::c_int,
+    #[cfg(unix)]
+    previous: libc::sighandler_t,
+}
+
+impl IgnoreGuard {
+    #[cfg(unix)]
+    pub fn install(signal: libc::c_int) -> Self {
+        let previous = unsafe { libc::signal(signal, libc::SIG_IGN) };
+        Self { signal, previous }
+    }
+}
+
+impl Drop for IgnoreGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::signal(self.signal, self.previous);
+        }
+    }
+}
@@ -0,0 +1,523 @@
+//! Structured descriptions of a command's arguments.
+//!
+//! A [`Spec`] lists the forms a command takes and each of its flags with a
+//! description. The usage line shown in `help`, the output of `--help`, and
+//! the OPTIONS section of a command's manual page are all generated from
+//! it, so they cannot drift apart.
+//!
+//! Commands registered with [`Command::from_spec_parsed`] also have their
+//! arguments parsed by it, into [`ParsedArgs`]. Flags may then take values,
+//! given as in `-w 40`, `-w40`, `--width 40`, or `--width=40`, and short
+//! flags may be combined, as in `-pv`. Anything the spec does not allow is
+//! a usage error worded as other tools word it.
+//!
+//! [`Command::from_spec_parsed`]: crate::command::Command::from_spec_parsed
+
+use std::str::FromStr;
+
+/// Width that help text is wrapped to.
+pub const WIDTH: usize = 78;
+
+/// The widest flag column in `--help` output. Longer flags get their
+/// description on the next line.
+const FLAG_COLUMN: usize = 24;
+
+/// The arguments a command takes.
+#[derive(Debug)]
+pub struct Spec {
+    pub name: &'static str,
+    /// What the command does, in a few words, as in `create directories`.
+    pub summary: &'static str,
+    /// The operands of each form the command takes, as in `SOURCE DEST`.
+    /// Flags are shown before the first form; later forms get `[OPTION]...`.
+    pub forms: &'static [&'static str],
+    pub flags: &'static [Flag],
+}
+
+/// A flag and what it does.
+#[derive(Debug)]
+pub struct Flag {
+    /// The flag's names, the one shown in usage lines first. A name like
+    /// `-0..-9` stands for one flag per digit in the range, and the digit
+    /// given is the flag's value.
+    pub names: &'static [&'static str],
+    /// The value the flag takes, as in `MODE`. It is in brackets, as in
+    /// `[WHEN]`, if it may be left out.
+    pub value: Option<&'static str>,
+    /// The value the flag has when it is not given.
+    pub default: Option<&'static str>,
+    pub help: &'static str,
+}
+
+/// Flags the shell answers for every command, unless its spec uses the
+/// name for something else.
+static SHELL_FLAGS: [Flag; 2] = [
+    Flag::new(&["--help", "-h"], "Show this help."),
+    Flag::new(&["--version"], "Show the version."),
+];
+
+impl Flag {
+    pub const fn new(names: &'static [&'static str], help: &'static str) -> Self {
+        Self {
+            names,
+            value: None,
+            default: None,
+            help,
+        }
+    }
+
+    pub const fn with_value(
+        names: &'static [&'static str],
+        value: &'static str,
+        help: &'static str,
+    ) -> Self {
+        Self {
+            names,
+            value: Some(value),
+            default: None,
+            help,
+        }
+    }
+
+    /// Gives the flag a value it has when it is not given.
+    pub const fn or_default(self, default: &'static str) -> Self {
+        Self {
+            default: Some(default),
+            ..self
+        }
+    }
+
+    /// Whether `name` is one of the flag's names, or a digit in its range.
+    fn is_named(&self, name: &str) -> bool {
+        self.names.contains(&name)
+            || self.names.iter().any(|range| {
+                let Some((low, high)) = range.split_once("..") else {
+                    return false;
+                };
+                match (digit_flag(low), digit_flag(high), digit_flag(name)) {
+                    (Some(low), Some(high), Some(digit)) => (low..=high).contains(&digit),
+                    _ => false,
+                }
+            })
+    }
+
+    /// Whether the flag is a range of digits, as in `-0..-9`.
+    fn is_digits(&self) -> bool {
+        self.names.iter().any(|name| name.contains(".."))
+    }
+
+    /// Whether the flag's value may be left out, as in `--color[=WHEN]`.
+    /// Such a value can only be given as in `--color=always`.
+    fn value_optional(&self) -> bool {
+        self.value.is_some_and(|value| value.starts_with('['))
+    }
+
+    /// What the flag does, with its default if it has one.
+    pub fn description(&self) -> String {
+        match self.default {
+            Some(default) => format!("{} The default is {}.", self.help, default),
+            None => self.help.to_string(),
+        }
+    }
+
+    /// How the flag is written in a usage line, as in `-m MODE`,
+    /// `--sort=WORD`, or `--color[=WHEN]`.
+    pub fn usage(&self) -> String {
+        spell(self.names[0], self.value)
+    }
+
+    /// All the flag's names, the value given with the last, as in
+    /// `-m, --mode=MODE`.
+    pub fn label(&self) -> String {
+        let (last, rest) = self.names.split_last().expect("a flag has a name");
+        let mut label: Vec<String> = rest.iter().map(|name| name.to_string()).collect();
+        label.push(spell(last, self.value));
+        label.join(", ")
+    }
+}
+
+/// The digit of a flag such as `-9`.
+fn digit_flag(name: &str) -> Option<u8> {
+    match name.as_bytes() {
+        [b'-', digit] if digit.is_ascii_digit() => Some(*digit),
+        _ => None,
+    }
+}
+
+/// Writes `name` with `value` as a command line would give them.
+fn spell(name: &str, value: Option<&str>) -> String {
+    match value {
+        None => name.to_string(),
+        Some(value) => match value.strip_prefix('[').and_then(|v| v.strip_suffix(']')) {
+            Some(value) => format!("{}[={}]", name, value),
+            None if name.starts_with("--") => format!("{}={}", name, value),
+            None => format!("{} {}", name, value),
+        },
+    }
+}
+
+impl Spec {
+    /// Each form of the command, with its flags, as in
+    /// `cp [-r] SOURCE DEST` and `cp [OPTION]... SOURCE... DIRECTORY`.
+    pub fn synopsis(&self) -> Vec<String> {
+        let flags: String = self
+            .flags
+            .iter()
+            .map(|flag| format!(" [{}]", flag.usage()))
+            .collect();
+        let options = if self.flags.is_empty() {
+            ""
+        } else {
+            " [OPTION]..."
+        };
+        self.forms
+            .iter()
+            .enumerate()
+            .map(|(i, form)| {
+                let flags = if i == 0 { flags.as_str() } else { options };
+                format!("{}{} {}", self.name, flags, form)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    /// The one-line help listed by `help`, as in `mkdir [-p] DIRECTORY... -
+    /// create directories`.
+    pub fn help_line(&self) -> String {
+        format!("{} - {}", self.synopsis().join(" or "), self.summary)
+    }
+
+    /// The output of `--help`: the usage, the summary, and a table of the
+    /// flags.
+    pub fn help_text(&self) -> String {
+        let mut text = format!("Usage: {}\n", self.synopsis().join("\n   or: "));
+        let mut summary = self.summary.chars();
+        if let Some(first) = summary.next() {
+            text.push_str(&format!("{}{}.\n", first.to_uppercase(), summary.as_str()));
+        }
+        if self.flags.is_empty() {
+            return text;
+        }
+
+        text.push_str("\nOptions:\n");
+        let labels: Vec<String> = self.flags.iter().map(Flag::label).collect();
+        let column = labels
+            .iter()
+            .map(String::len)
+            .filter(|&len| len <= FLAG_COLUMN)
+            .max()
+            .unwrap_or(0)
+            + 4;
+        for (flag, label) in self.flags.iter().zip(&labels) {
+            let mut lines = wrap(&flag.description(), WIDTH - column).into_iter();
+            if label.len() + 4 > column {
+                text.push_str(&format!("  {}\n", label));
+            } else {
+                let first = lines.next().unwrap_or_default();
+                text.push_str(&format!(
+                    "  {:<width$}{}\n",
+                    label,
+                    first,
+                    width = column - 2
+                ));
+            }
+            for line in lines {
+                text.push_str(&format!("{:column$}{}\n", "", line));
+            }
+        }
+        text
+    }
+
+    /// Parses a command's arguments, as the shell hands them over, by the
+    /// flags of the spec. Flags and operands may come in any order, and
+    /// everything after `--` is an operand. Returns the usage error to
+    /// report if the arguments break the spec.
+    pub fn parse(&'static self, args: &[String]) -> Result<ParsedArgs, String> {
+        let mut parsed = ParsedArgs {
+            spec: self,
+            flags: Vec::new(),
+            operands: Vec::new(),
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                parsed.operands.extend(args.cloned());
+                break;
+            }
+
+            if let Some(long) = arg.strip_prefix("--") {
+                let (name, inline) = match long.split_once('=') {
+                    Some((name, value)) => (format!("--{}", name), Some(value)),
+                    None => (arg.clone(), None),
+                };
+                let flag = self
+                    .flag(&name)
+                    .ok_or_else(|| format!("unrecognized option '{}'", arg))?;
+                let value = match (flag.value, inline) {
+                    (None, Some(_)) => {
+                        return Err(format!("option '{}' doesn't allow an argument", name));
+                    }
+                    (None, None) => None,
+                    (Some(_), Some(value)) => Some(value.to_string()),
+                    (Some(_), None) if flag.value_optional() => None,
+                    (Some(_), None) => Some(
+                        args.next()
+                            .ok_or_else(|| format!("option '{}' requires an argument", name))?
+                            .clone(),
+                    ),
+                };
+                parsed.flags.push((flag, value));
+            } else if let Some(short) = arg.strip_prefix('-').filter(|s| !s.is_empty()) {
+                // Combined flags, the last of which may take the rest as its value
+                for (i, c) in short.char_indices() {
+                    let flag = self
+                        .flag(&format!("-{}", c))
+                        .ok_or_else(|| format!("invalid option -- '{}'", c))?;
+                    if flag.value.is_none() {
+                        let digit = flag.is_digits().then(|| c.to_string());
+                        parsed.flags.push((flag, digit));
+                        continue;
+                    }
+                    let rest = &short[i + c.len_utf8()..];
+                    let value = if !rest.is_empty() {
+                        Some(rest.to_string())
+                    } else if flag.value_optional() {
+                        None
+                    } else {
+                        Some(
+                            args.next()
+                                .ok_or_else(|| format!("option requires an argument -- '{}'", c))?
+                                .clone(),
+                        )
+                    };
+                    parsed.flags.push((flag, value));
+                    break;
+                }
+            } else {
+                parsed.operands.push(arg.clone());
+            }
+        }
+        Ok(parsed)
+    }
+
+    /// The flag named `name`, one of the spec's or the shell's own.
+    fn flag(&'static self, name: &str) -> Option<&'static Flag> {
+        self.flags
+            .iter()
+            .chain(&SHELL_FLAGS)
+            .find(|flag| flag.is_named(name))
+    }
+
+    /// Whether `name` asks the shell for help, as `--help` and `-h` do
+    /// unless the spec gives the name to one of its own flags.
+    pub fn is_help_flag(&'static self, name: &str) -> bool {
+        self.flag(name)
+            .is_some_and(|flag| std::ptr::eq(flag, &SHELL_FLAGS[0]))
+    }
+}
+
+/// A command's arguments, parsed by its [`Spec`]. Flags are looked up by
+/// any of their names.
+#[derive(Debug)]
+pub struct ParsedArgs {
+    spec: &'static Spec,
+    /// The flags given, in order, each with its value if it has one.
+    flags: Vec<(&'static Flag, Option<String>)>,
+    /// The arguments that are not flags or their values, in order.
+    pub operands: Vec<String>,
+}
+
+impl ParsedArgs {
+    /// The values given with each use of the flag `name`.
+    fn given(&self, name: &str) -> Vec<Option<&str>> {
+        self.flags
+            .iter()
+            .filter(|(flag, _)| flag.names.contains(&name))
+            .map(|(_, value)| value.as_deref())
+            .collect()
+    }
+
+    /// Whether the flag `name` was given.
+    pub fn has(&self, name: &str) -> bool {
+        !self.given(name).is_empty()
+    }
+
+    /// Which of the flags `names` was given last, for flags that override
+    /// each other, as in `-r` and `-t`.
+    pub fn last<'a>(&self, names: &[&'a str]) -> Option<&'a str> {
+        self.flags
+            .iter()
+            .rev()
+            .find_map(|(flag, _)| names.iter().find(|name| flag.names.contains(name)).copied())
+    }
+
+    /// The value of the flag `name`: the last one given, or its default.
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.given(name)
+            .into_iter()
+            .flatten()
+            .next_back()
+            .or_else(|| self.spec.flag(name).and_then(|flag| flag.default))
+    }
+
+    /// Every value given to the flag `name`, for flags that may be
+    /// repeated.
+    pub fn values(&self, name: &str) -> Vec<&str> {
+        self.given(name).into_iter().flatten().collect()
+    }
+
+    /// The value of the flag `name` as a `T`, with a usage error naming the
+    /// flag if it is not one.
+    pub fn get<T: FromStr>(&self, name: &str) -> Result<Option<T>, String> {
+        match self.value(name) {
+            None => Ok(None),
+            Some(value) => value
+                .parse()
+                .map(Some)
+                .map_err(|_| format!("invalid argument '{}' for '{}'", value, name)),
+        }
+    }
+}
+
+/// The output of `--version` for the command `name`.
+pub fn version(name: &str) -> String {
+    format!("{} (0-shell) {}\n", name, env!("CARGO_PKG_VERSION"))
+}
+
+/// Breaks `text` into lines of at most `width` columns, between words.
+/// A word longer than that gets a line of its own.
+pub fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.len() + 1 + word.len() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spec_usage_and_help() {
+        const SPEC: Spec = Spec {
+            name: "demo",
+            summary: "show a demo",
+            forms: &["SOURCE DEST", "SOURCE... DIRECTORY"],
+            flags: &[
+                Flag::new(&["-r", "--recursive"], "Go into directories."),
+                Flag::with_value(&["-m", "--mode"], "MODE", "Set the mode."),
+                Flag::with_value(&["--color"], "[WHEN]", "Color the output."),
+                Flag::new(
+                    &["--a-very-long-flag-indeed"],
+                    "Has a description that is long enough to be wrapped onto a second line.",
+                ),
+            ],
+        };
+        let spec = &SPEC;
+        assert_eq!(
+            spec.help_line(),
+            "demo [-r] [-m MODE] [--color[=WHEN]] [--a-very-long-flag-indeed] SOURCE DEST \
+             or demo [OPTION]... SOURCE... DIRECTORY - show a demo"
+        );
+        assert_eq!(
+            spec.help_text(),
+            "Usage: demo [-r] [-m MODE] [--color[=WHEN]] [--a-very-long-flag-indeed] SOURCE DEST\n\
+             \x20  or: demo [OPTION]... SOURCE... DIRECTORY\n\
+             Show a demo.\n\
+             \n\
+             Options:\n\
+             \x20 -r, --recursive  Go into directories.\n\
+             \x20 -m, --mode=MODE  Set the mode.\n\
+             \x20 --color[=WHEN]   Color the output.\n\
+             \x20 --a-very-long-flag-indeed\n\
+             \x20                  Has a description that is long enough to be wrapped onto a\n\
+             \x20                  second line.\n"
+        );
+
+        let bare = Spec {
+            name: "exit",
+            summary: "cause the shell to exit",
+            forms: &[""],
+            flags: &[],
+        };
+        assert_eq!(bare.help_line(), "exit - cause the shell to exit");
+        assert_eq!(bare.help_text(), "Usage: exit\nCause the shell to exit.\n");
+    }
+
+    #[test]
+    fn test_spec_parse() {
+        const SPEC: Spec = Spec {
+            name: "demo",
+            summary: "show a demo",
+            forms: &["[FILE]..."],
+            flags: &[
+                Flag::new(&["-a", "--all"], "Everything."),
+                Flag::new(&["-h"], "Human sizes."),
+                Flag::with_value(&["-n", "--lines"], "N", "Show N lines.").or_default("10"),
+                Flag::with_value(&["-d"], "DELIM", "Split at DELIM."),
+                Flag::with_value(&["--color"], "[WHEN]", "Color the output."),
+                Flag::new(&["-1..-9"], "Set the level.").or_default("6"),
+            ],
+        };
+        let parse = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            SPEC.parse(&args)
+        };
+
+        let args = parse(&["x", "-an5", "-d", ",", "--color", "-", "--", "-a"]).unwrap();
+        assert_eq!(args.operands, ["x", "-", "-a"]);
+        assert!(args.has("-a") && args.has("--all") && args.has("--color"));
+        assert_eq!(args.value("--lines"), Some("5"));
+        assert_eq!(args.value("-d"), Some(","));
+        assert_eq!(args.value("--color"), None);
+        assert_eq!(args.get::<usize>("-n"), Ok(Some(5)));
+
+        let args = parse(&["--lines", "-3", "-d", ":", "-d:", "--color=always"]).unwrap();
+        assert_eq!(args.get::<i64>("-n"), Ok(Some(-3)));
+        assert_eq!(args.values("-d"), [":", ":"]);
+        assert_eq!(args.last(&["-a", "-d"]), Some("-d"));
+        assert_eq!(args.last(&["-a", "--all"]), None);
+        assert_eq!(args.value("--color"), Some("always"));
+
+        // A range of digits, which may be combined with other flags
+        assert_eq!(parse(&[]).unwrap().get::<u32>("-1..-9"), Ok(Some(6)));
+        let args = parse(&["-3", "-a9"]).unwrap();
+        assert!(args.has("-1..-9") && args.has("-a"));
+        assert_eq!(args.value("-1..-9"), Some("9"));
+
+        // Defaults, and the shell's own flags
+        let args = parse(&["-h", "--help"]).unwrap();
+        assert_eq!(args.value("-n"), Some("10"));
+        assert!(!args.has("-a") && args.has("-h") && args.has("--help"));
+        assert!(parse(&["--version"]).unwrap().has("--version"));
+        assert_eq!(
+            parse(&["-nx"]).unwrap().get::<usize>("--lines"),
+            Err("invalid argument 'x' for '--lines'".to_string())
+        );
+
+        for (args, error) in [
+            (&["-z"][..], "invalid option -- 'z'"),
+            (&["-az"], "invalid option -- 'z'"),
+            (&["-0"], "invalid option -- '0'"),
+            (&["--zap"], "unrecognized option '--zap'"),
+            (&["--zap=1"], "unrecognized option '--zap=1'"),
+            (&["-n"], "option requires an argument -- 'n'"),
+            (&["--lines"], "option '--lines' requires an argument"),
+            (&["--all=yes"], "option '--all' doesn't allow an argument"),
+        ] {
+            assert_eq!(parse(args).unwrap_err(), error);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Low-level terminal helpers: raw mode, window size, and key decoding.
+//!
+//! Interactive commands such as the pager read keystrokes one at a time from
+//! the controlling terminal rather than line by line from stdin.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+
+use crate::command::Variables;
+
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+
+/// Escape sequence switching to the alternate screen buffer.
+pub const ENTER_ALT_SCREEN: &str = "\x1b[?1049h";
+/// Escape sequence restoring the main screen buffer.
+pub const LEAVE_ALT_SCREEN: &str = "\x1b[?1049l";
+/// Clears the screen and moves the cursor to the top-left corner.
+pub const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+/// Starts reverse-video text.
+pub const REVERSE: &str = "\x1b[7m";
+/// Resets all text attributes.
+pub const RESET: &str = "\x1b[0m";
+
+/// A decoded keystroke.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Backspace,
+    Escape,
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Home,
+    End,
+    /// Ctrl+<letter>, stored as the lowercase letter.
+    Ctrl(char),
+    Unknown,
+}
+
+/// Opens the controlling terminal for reading keystrokes.
+///
+/// Reading from `/dev/tty` rather than stdin lets interactive commands take
+/// keyboard input even when their data arrives on stdin.
+pub fn open_tty() -> io::Result<File> {
+    OpenOptions::new().read(true).write(true).open("/dev/tty")
+}
+
+/// Asks a yes-or-no question on the controlling terminal and reads the
+/// answer, a line starting with `y` or `Y` meaning yes.
+///
+/// Without a terminal to ask on, the answer is no.
+pub fn confirm(question: &str) -> bool {
+    use std::io::Write;
+
+    let Ok(mut tty) = open_tty() else {
+        return false;
+    };
+    if write!(tty, "{} ", question).is_err() {
+        return false;
+    }
+    let mut answer = Vec::new();
+    let mut byte = [0u8; 1];
+    while let Ok(1) = tty.read(&mut byte) {
+        if byte[0] == b'\n' {
+            break;
+        }
+        answer.push(byte[0]);
+    }
+    matches!(answer.first(), Some(b'y' | b'Y'))
+}
+
+/// Returns the terminal size as `(columns, rows)`.
+///
+/// Queries the terminal attached to stdout, falling back to the `COLUMNS` and
+/// `LINES` variables, and finally to 80x24.
+pub fn size(vars: &Variables) -> (usize, usize) {
+    #[cfg(unix)]
+    {
+        // SAFETY: `winsize` is plain data and TIOCGWINSZ only writes into it.
+        let mut ws: libc::winsize = unsafe { std::mem::zeroed() };
+        let ok = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0;
+        if ok && ws.ws_col > 0 && ws.ws_row > 0 {
+            return (ws.ws_col as usize, ws.ws_row as usize);
+        }
+    }
+
+    let from_env = |name: &str, default: usize| {
+        vars.get(name)
+            .and_then(|v| v.parse().ok())
+            .filter(|&n: &usize| n > 0)
+            .unwrap_or(default)
+    };
+    (from_env("COLUMNS", 80), from_env("LINES", 24))
+}
+
+/// Puts a terminal into raw mode for as long as the guard is alive.
+///
+/// Echo and line buffering are disabled so single keystrokes can be read.
+/// Signal generation is left on so Ctrl+C still interrupts.
+pub struct RawMode {
+    #[cfg(unix)]
+    fd: i32,
+    #[cfg(unix)]
+    original: libc::termios,
+}
+
+impl RawMode {
+    #[cfg(unix)]
+    pub fn enable(tty: &File) -> io::Result<Self> {
+        let fd = tty.as_raw_fd();
+        // SAFETY: `termios` is plain data filled in by tcgetattr.
+        let mut original: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(fd, &mut original) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut raw = original;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::IEXTEN);
+        raw.c_iflag &= !(libc::IXON | libc::ICRNL);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &raw) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { fd, original })
+    }
+
+    #[cfg(not(unix))]
+    pub fn enable(_tty: &File) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "raw terminal mode is not supported on this platform",
+        ))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        unsafe {
+            libc::tcsetattr(self.fd, libc::TCSAFLUSH, &self.original);
+        }
+    }
+}
+
+/// Waits up to `timeout_ms` for input to become readable on `tty`.
+#[cfg(unix)]
+pub fn poll_readable(tty: &impl AsRawFd, timeout_ms: i32) -> bool {
+    let mut pfd = libc::pollfd {
+        fd: tty.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&mut pfd, 1, timeout_ms) > 0 }
+}
+
+#[cfg(not(unix))]
+pub fn poll_readable<T>(_tty: &T, _timeout_ms: i32) -> bool {
+    true
+}
+
+/// Reads and decodes a single keystroke from a terminal in raw mode.
+pub fn read_key(tty: &mut File) -> io::Result<Key> {
+    let mut byte = [0u8; 1];
+    if tty.read(&mut byte)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "terminal closed",
+        ));
+    }
+
+    let key = match byte[0] {
+        b'\r' | b'\n' => Key::Enter,
+        0x7f | 0x08 => Key::Backspace,
+        0x1b => return read_escape(tty),
+        b @ 1..=26 => Key::Ctrl((b - 1 + b'a') as char),
+        b if b < 0x80 => Key::Char(b as char),
+        first => {
+            // Multi-byte UTF-8 character
+            let extra = match first {
+                0xC0..=0xDF => 1,
+                0xE0..=0xEF => 2,
+                _ => 3,
+            };
+            let mut buf = vec![first];
+            let mut rest = vec![0u8; extra];
+            tty.read_exact(&mut rest)?;
+            buf.extend_from_slice(&rest);
+            String::from_utf8(buf)
+                .ok()
+                .and_then(|s| s.chars().next())
+                .map_or(Key::Unknown, Key::Char)
+        }
+    };
+    Ok(key)
+}
+
+/// Decodes the remainder of an escape sequence after the initial ESC byte.
+fn read_escape(tty: &mut File) -> io::Result<Key> {
+    if !poll_readable(tty, 50) {
+        return Ok(Key::Escape);
+    }
+
+    let mut seq = [0u8; 1];
+    tty.read_exact(&mut seq)?;
+    if seq[0] != b'[' && seq[0] != b'O' {
+        return Ok(Key::Escape);
+    }
+
+    let mut params = Vec::new();
+    loop {
+        let mut b = [0u8; 1];
+        tty.read_exact(&mut b)?;
+        if b[0].is_ascii_digit() || b[0] == b';' {
+            params.push(b[0]);
+            continue;
+        }
+        return Ok(match (b[0], params.as_slice()) {
+            (b'A', _) => Key::Up,
+            (b'B', _) => Key::Down,
+            (b'H', _) | (b'~', b"1") | (b'~', b"7") => Key::Home,
+            (b'F', _) | (b'~', b"4") | (b'~', b"8") => Key::End,
+            (b'~', b"5") => Key::PageUp,
+            (b'~', b"6") => Key::PageDown,
+            _ => Key::Unknown,
+        });
+    }
+}
@@ -0,0 +1,539 @@
+//! A full-screen process monitor for `top`.
+//!
+//! Each refresh reads the process table from `/proc` and works out CPU
+//! usage from how much CPU time each process used since the previous
+//! refresh. Keys change the sort order or send a signal to a process.
+
+use std::collections::HashMap;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use crate::command::Variables;
+use crate::process::ProcessInfo;
+use crate::terminal::{self, Key, RawMode};
+
+/// The column processes are ordered by, largest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Cpu,
+    Memory,
+    Pid,
+    Time,
+}
+
+impl SortKey {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "cpu" | "%cpu" => Some(SortKey::Cpu),
+            "mem" | "%mem" | "res" => Some(SortKey::Memory),
+            "pid" => Some(SortKey::Pid),
+            "time" | "time+" => Some(SortKey::Time),
+            _ => None,
+        }
+    }
+}
+
+/// How `top` runs, from its command-line options.
+pub struct Options {
+    /// Time between refreshes.
+    pub delay: Duration,
+    /// Only show processes owned by these users, if any are given.
+    pub uids: Vec<u32>,
+    /// Stop after this many refreshes.
+    pub iterations: Option<usize>,
+    /// Print plain snapshots instead of drawing a full-screen display.
+    pub batch: bool,
+    pub sort: SortKey,
+}
+
+/// One line of the process table.
+#[derive(Debug, Clone, PartialEq)]
+struct Row {
+    pid: i32,
+    user: String,
+    state: char,
+    cpu: f64,
+    mem: f64,
+    rss: u64,
+    /// CPU time in clock ticks.
+    cpu_time: u64,
+    command: String,
+}
+
+/// Memory totals from `/proc/meminfo`, in bytes.
+#[derive(Debug, Clone, Copy, Default)]
+struct Memory {
+    total: u64,
+    available: u64,
+}
+
+/// The sampling and display state carried between refreshes.
+struct Monitor<'a> {
+    opts: &'a Options,
+    /// Produces the first header line (time, uptime, users, load).
+    summary: &'a dyn Fn() -> String,
+    /// The shell's variables, for the screen size when the terminal cannot
+    /// be asked.
+    vars: &'a Variables,
+    sort: SortKey,
+    reverse: bool,
+    /// CPU time of each process at the previous sample.
+    last_times: HashMap<i32, u64>,
+    last_at: Instant,
+    users: HashMap<u32, String>,
+    ticks_per_sec: f64,
+    rows: Vec<Row>,
+    tasks: String,
+    memory: Memory,
+    message: Option<String>,
+}
+
+impl<'a> Monitor<'a> {
+    fn new(opts: &'a Options, summary: &'a dyn Fn() -> String, vars: &'a Variables) -> Self {
+        Self {
+            opts,
+            summary,
+            vars,
+            sort: opts.sort,
+            reverse: false,
+            last_times: HashMap::new(),
+            last_at: Instant::now(),
+            users: HashMap::new(),
+            ticks_per_sec: crate::process::clock_ticks() as f64,
+            rows: Vec::new(),
+            tasks: String::new(),
+            memory: Memory::default(),
+            message: None,
+        }
+    }
+
+    /// Reads the process table and memory totals, updating the rows.
+    fn sample(&mut self) -> io::Result<()> {
+        let processes = crate::process::list_processes()?;
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_at).as_secs_f64().max(0.001);
+        self.memory = read_memory();
+        self.tasks = task_summary(&processes);
+
+        let mut times = HashMap::with_capacity(processes.len());
+        let mut rows = Vec::with_capacity(processes.len());
+        for p in processes {
+            times.insert(p.pid, p.cpu_time);
+            if !self.opts.uids.is_empty() && !self.opts.uids.contains(&p.uid) {
+                continue;
+            }
+            // Processes new since the last sample show no usage until the next
+            let used = self
+                .last_times
+                .get(&p.pid)
+                .map_or(0, |&last| p.cpu_time.saturating_sub(last));
+            let user = self
+                .users
+                .entry(p.uid)
+                .or_insert_with(|| user_label(p.uid))
+                .clone();
+            rows.push(Row {
+                pid: p.pid,
+                user,
+                state: p.state,
+                cpu: used as f64 / self.ticks_per_sec / elapsed * 100.0,
+                mem: if self.memory.total > 0 {
+                    p.rss as f64 / self.memory.total as f64 * 100.0
+                } else {
+                    0.0
+                },
+                rss: p.rss,
+                cpu_time: p.cpu_time,
+                // Arguments may contain newlines that would break the table
+                command: p.command().replace(char::is_control, " "),
+            });
+        }
+        self.last_times = times;
+        self.last_at = now;
+        sort_rows(&mut rows, self.sort, self.reverse);
+        self.rows = rows;
+        Ok(())
+    }
+
+    /// The header lines above the process table.
+    fn header(&self) -> Vec<String> {
+        let mib = |bytes: u64| bytes as f64 / (1024.0 * 1024.0);
+        vec![
+            format!("top - {}", (self.summary)().trim()),
+            self.tasks.clone(),
+            format!(
+                "MiB Mem: {:.1} total, {:.1} used, {:.1} available",
+                mib(self.memory.total),
+                mib(self.memory.total.saturating_sub(self.memory.available)),
+                mib(self.memory.available)
+            ),
+        ]
+    }
+
+    /// Draws a full screen of `width` by `height` characters.
+    fn render(&self, width: usize, height: usize, status: &str) -> String {
+        let mut frame = String::from(terminal::CLEAR_SCREEN);
+        for line in self.header() {
+            frame.push_str(&fit(&line, width));
+            frame.push_str("\r\n");
+        }
+        frame.push_str(&fit(status, width));
+        frame.push_str("\r\n");
+        frame.push_str(terminal::REVERSE);
+        frame.push_str(&format!("{:<width$}", fit(&column_header(), width)));
+        frame.push_str(terminal::RESET);
+
+        let table_rows = height.saturating_sub(5);
+        for row in self.rows.iter().take(table_rows) {
+            frame.push_str("\r\n");
+            frame.push_str(&fit(&format_row(row, self.ticks_per_sec), width));
+        }
+        frame
+    }
+
+    /// Formats a plain snapshot with every process, for batch mode.
+    fn snapshot(&self) -> String {
+        let mut out = self.header().join("\n");
+        out.push_str("\n\n");
+        out.push_str(&column_header());
+        out.push('\n');
+        for row in &self.rows {
+            out.push_str(&format_row(row, self.ticks_per_sec));
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Applies a key press. Returns `false` when the monitor should exit.
+    fn handle_key(
+        &mut self,
+        key: Key,
+        tty: &mut std::fs::File,
+        out: &mut dyn Write,
+    ) -> io::Result<bool> {
+        let sort = match key {
+            Key::Char('q') | Key::Char('Q') | Key::Ctrl('c') => return Ok(false),
+            Key::Char('P') => Some(SortKey::Cpu),
+            Key::Char('M') => Some(SortKey::Memory),
+            Key::Char('N') => Some(SortKey::Pid),
+            Key::Char('T') => Some(SortKey::Time),
+            Key::Char('R') => {
+                self.reverse = !self.reverse;
+                None
+            }
+            Key::Char('k') => {
+                self.kill_prompt(tty, out)?;
+                None
+            }
+            _ => None,
+        };
+        if let Some(sort) = sort {
+            self.sort = sort;
+        }
+        sort_rows(&mut self.rows, self.sort, self.reverse);
+        Ok(true)
+    }
+
+    /// Asks for a pid (defaulting to the top row) and a signal (defaulting
+    /// to TERM), then sends it.
+    #[cfg(unix)]
+    fn kill_prompt(&mut self, tty: &mut std::fs::File, out: &mut dyn Write) -> io::Result<()> {
+        let default_pid = self.rows.first().map(|r| r.pid);
+        let prompt = match default_pid {
+            Some(pid) => format!("PID to signal [{}]: ", pid),
+            None => "PID to signal: ".to_string(),
+        };
+        let Some(pid) = self.read_line(tty, &prompt, out)? else {
+            return Ok(());
+        };
+        let pid = match (pid.trim(), default_pid) {
+            ("", Some(pid)) => pid,
+            (text, _) => match text.parse::<i32>() {
+                Ok(pid) if pid > 0 => pid,
+                _ => {
+                    self.message = Some(format!("Invalid PID '{}'", text));
+                    return Ok(());
+                }
+            },
+        };
+        let Some(signal) =
+            self.read_line(tty, &format!("Signal to send to {} [TERM]: ", pid), out)?
+        else {
+            return Ok(());
+        };
+        let name = if signal.trim().is_empty() {
+            "TERM"
+        } else {
+            signal.trim()
+        };
+        self.message = Some(match crate::process::parse_signal(name) {
+            None => format!("Unknown signal '{}'", name),
+            Some(signal) => match crate::process::send_signal(pid, signal) {
+                Ok(()) => format!("Sent {} to {}", crate::process::signal_name(signal), pid),
+                Err(e) => format!("Failed to signal {}: {}", pid, e),
+            },
+        });
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn kill_prompt(&mut self, _tty: &mut std::fs::File, _out: &mut dyn Write) -> io::Result<()> {
+        self.message = Some("Sending signals is not supported on this platform".to_string());
+        Ok(())
+    }
+
+    /// Reads a line on the status row. Returns `None` if cancelled.
+    #[cfg(unix)]
+    fn read_line(
+        &self,
+        tty: &mut std::fs::File,
+        prompt: &str,
+        out: &mut dyn Write,
+    ) -> io::Result<Option<String>> {
+        let mut input = String::new();
+        loop {
+            let (width, height) = terminal::size(self.vars);
+            let frame = self.render(width, height, &format!("{}{}", prompt, input));
+            out.write_all(frame.as_bytes())?;
+            out.flush()?;
+            match terminal::read_key(tty)? {
+                Key::Enter => return Ok(Some(input)),
+                Key::Escape | Key::Ctrl('c') => return Ok(None),
+                Key::Backspace => {
+                    input.pop();
+                }
+                Key::Char(c) => input.push(c),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Runs the monitor until `q`, Ctrl+C, or the iteration limit, drawing on
+/// `out`. When stdout is not a terminal, or in batch mode, plain snapshots
+/// are written instead.
+pub fn run(
+    opts: &Options,
+    summary: &dyn Fn() -> String,
+    vars: &Variables,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut monitor = Monitor::new(opts, summary, vars);
+    monitor.sample()?;
+    // The first sample only sets the baseline for CPU usage
+    std::thread::sleep(Duration::from_millis(200));
+    monitor.sample()?;
+
+    let _guard = crate::signals::InterruptGuard::install();
+    if opts.batch || !io::stdout().is_terminal() {
+        let mut count = 0;
+        loop {
+            out.write_all(monitor.snapshot().as_bytes())?;
+            out.flush()?;
+            count += 1;
+            if opts.iterations.is_some_and(|limit| count >= limit)
+                || crate::signals::sleep_interruptible(opts.delay)
+            {
+                return Ok(());
+            }
+            out.write_all(b"\n")?;
+            monitor.sample()?;
+        }
+    }
+
+    let mut tty = terminal::open_tty()?;
+    let _raw = RawMode::enable(&tty)?;
+    out.write_all(terminal::ENTER_ALT_SCREEN.as_bytes())?;
+    let outcome = interactive(&mut monitor, &mut tty, out);
+    out.write_all(terminal::LEAVE_ALT_SCREEN.as_bytes())?;
+    out.flush()?;
+    outcome
+}
+
+/// The refresh and key loop of the full-screen display.
+fn interactive(
+    monitor: &mut Monitor,
+    tty: &mut std::fs::File,
+    out: &mut dyn Write,
+) -> io::Result<()> {
+    let mut count = 0;
+    // A delay too long to have an end waits for keys alone
+    let mut next_refresh = Instant::now().checked_add(monitor.opts.delay);
+    loop {
+        let (width, height) = terminal::size(monitor.vars);
+        let status = monitor.message.take().unwrap_or_else(|| {
+            "P cpu  M mem  N pid  T time  R reverse  k kill  q quit".to_string()
+        });
+        out.write_all(monitor.render(width, height, &status).as_bytes())?;
+        out.flush()?;
+
+        let now = Instant::now();
+        let wait = next_refresh.map_or(-1, |next| {
+            let wait = next.saturating_duration_since(now).as_millis();
+            wait.min(i32::MAX as u128) as i32
+        });
+        if terminal::poll_readable(tty, wait) {
+            let key = terminal::read_key(tty)?;
+            if !monitor.handle_key(key, tty, out)? {
+                return Ok(());
+            }
+            continue;
+        }
+        if crate::signals::interrupted() {
+            return Ok(());
+        }
+        count += 1;
+        if monitor.opts.iterations.is_some_and(|limit| count >= limit) {
+            return Ok(());
+        }
+        monitor.sample()?;
+        next_refresh = Instant::now().checked_add(monitor.opts.delay);
+    }
+}
+
+fn sort_rows(rows: &mut [Row], key: SortKey, reverse: bool) {
+    rows.sort_by(|a, b| {
+        let order = match key {
+            SortKey::Cpu => b.cpu.total_cmp(&a.cpu),
+            SortKey::Memory => b.rss.cmp(&a.rss),
+            SortKey::Pid => a.pid.cmp(&b.pid),
+            SortKey::Time => b.cpu_time.cmp(&a.cpu_time),
+        };
+        let order = order.then(a.pid.cmp(&b.pid));
+        if reverse { order.reverse() } else { order }
+    });
+}
+
+fn column_header() -> String {
+    format!(
+        "{:>7} {:<9} {} {:>5} {:>5} {:>9} {:>9} {}",
+        "PID", "USER", "S", "%CPU", "%MEM", "RES", "TIME+", "COMMAND"
+    )
+}
+
+/// Formats a row, with RES in KiB and TIME+ as minutes, seconds, and
+/// hundredths.
+fn format_row(row: &Row, ticks_per_sec: f64) -> String {
+    let hundredths = (row.cpu_time as f64 / ticks_per_sec * 100.0) as u64;
+    let time = format!(
+        "{}:{:02}.{:02}",
+        hundredths / 6000,
+        hundredths / 100 % 60,
+        hundredths % 100
+    );
+    let mut user = row.user.clone();
+    if user.chars().count() > 9 {
+        user = user.chars().take(8).collect::<String>() + "+";
+    }
+    format!(
+        "{:>7} {:<9} {} {:>5.1} {:>5.1} {:>9} {:>9} {}",
+        row.pid,
+        user,
+        row.state,
+        row.cpu,
+        row.mem,
+        row.rss / 1024,
+        time,
+        row.command
+    )
+}
+
+/// Counts processes by state, as in `Tasks: 120 total, 1 running, ...`.
+fn task_summary(processes: &[ProcessInfo]) -> String {
+    let count = |states: &[char]| {
+        processes
+            .iter()
+            .filter(|p| states.contains(&p.state))
+            .count()
+    };
+    format!(
+        "Tasks: {} total, {} running, {} sleeping, {} stopped, {} zombie",
+        processes.len(),
+        count(&['R']),
+        count(&['S', 'D', 'I']),
+        count(&['T', 't']),
+        count(&['Z'])
+    )
+}
+
+fn read_memory() -> Memory {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").unwrap_or_default();
+    let field = |name: &str| {
+        meminfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(':'))
+            .and_then(|value| value.split_whitespace().next()?.parse::<u64>().ok())
+            .map(|kib| kib * 1024)
+    };
+    let total = field("MemTotal").unwrap_or(0);
+    Memory {
+        total,
+        available: field("MemAvailable").or(field("MemFree")).unwrap_or(0),
+    }
+}
+
+fn user_label(uid: u32) -> String {
+    #[cfg(unix)]
+    if let Some(name) = crate::process::user_name(uid) {
+        return name;
+    }
+    uid.to_string()
+}
+
+/// Truncates `line` to `width` display columns.
+fn fit(line: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for c in line.chars() {
+        let w = crate::unicode::str_width(c.encode_utf8(&mut [0; 4]));
+        if used + w > width {
+            break;
+        }
+        used += w;
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(pid: i32, cpu: f64, rss: u64, cpu_time: u64) -> Row {
+        Row {
+            pid,
+            user: "root".to_string(),
+            state: 'S',
+            cpu,
+            mem: 0.0,
+            rss,
+            cpu_time,
+            command: format!("cmd{}", pid),
+        }
+    }
+
+    #[test]
+    fn test_top_sorting_and_rows() {
+        let mut rows = vec![
+            row(3, 1.0, 300, 5),
+            row(1, 9.5, 100, 50),
+            row(2, 1.0, 900, 1),
+        ];
+        let pids = |rows: &[Row]| rows.iter().map(|r| r.pid).collect::<Vec<_>>();
+        sort_rows(&mut rows, SortKey::Cpu, false);
+        assert_eq!(pids(&rows), [1, 2, 3]);
+        sort_rows(&mut rows, SortKey::Memory, false);
+        assert_eq!(pids(&rows), [2, 3, 1]);
+        sort_rows(&mut rows, SortKey::Pid, true);
+        assert_eq!(pids(&rows), [3, 2, 1]);
+
+        let mut long = row(4242, 12.34, 20 * 1024 * 1024, 12345);
+        long.user = "postgresql".to_string();
+        assert_eq!(
+            format_row(&long, 100.0),
+            "   4242 postgres+ S  12.3   0.0     20480   2:03.45 cmd4242"
+        );
+        assert_eq!(fit("abcdef", 4), "abcd");
+        assert_eq!(SortKey::from_name("%MEM"), Some(SortKey::Memory));
+    }
+}
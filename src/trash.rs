@@ -0,0 +1,251 @@
+//! The trash behind `rm --trash`, `trash-list`, and `restore`.
+//!
+//! Files are kept as the FreeDesktop.org trash specification describes, so
+//! desktop file managers see them too. A trashed file is moved into
+//! `files/` under the trash directory, and beside it in `info/` goes a
+//! `NAME.trashinfo` file recording where it came from and when:
+//!
+//! ```text
+//! [Trash Info]
+//! Path=/home/user/notes%20old.txt
+//! DeletionDate=2026-03-09T10:00:00
+//! ```
+//!
+//! The path is percent-encoded. Only the home trash, under
+//! `$XDG_DATA_HOME/Trash`, is used.
+
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, NaiveDateTime, Timelike};
+
+use crate::command::Variables;
+
+/// How deletion dates are written in `.trashinfo` files.
+const DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+
+/// A file in the trash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The name of the file under `files/`.
+    pub name: String,
+    /// Where the file was before it was trashed.
+    pub path: PathBuf,
+    pub deleted: NaiveDateTime,
+}
+
+/// A trash directory.
+#[derive(Debug, Clone)]
+pub struct Trash {
+    dir: PathBuf,
+}
+
+impl Trash {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// The home trash, `$XDG_DATA_HOME/Trash` by `vars`.
+    pub fn home(vars: &Variables) -> Self {
+        let data_home = crate::desktop::XdgDirs::from_vars(vars).data.remove(0);
+        Self::new(data_home.join("Trash"))
+    }
+
+    fn files(&self) -> PathBuf {
+        self.dir.join("files")
+    }
+
+    fn info(&self, name: &str) -> PathBuf {
+        self.dir.join("info").join(format!("{}.trashinfo", name))
+    }
+
+    /// Moves `path` into the trash, returning its entry.
+    pub fn put(&self, path: &Path) -> io::Result<Entry> {
+        // The original location is recorded as an absolute path, but links
+        // on the way to it are kept as they are
+        let path = env::current_dir()?.join(path);
+        let base = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?
+            .to_string_lossy()
+            .into_owned();
+        fs::create_dir_all(self.files())?;
+        fs::create_dir_all(self.dir.join("info"))?;
+
+        // Dates are written to the second
+        let deleted = Local::now().naive_local();
+        let deleted = deleted.with_nanosecond(0).unwrap_or(deleted);
+        let text = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            percent_encode(&path.to_string_lossy()),
+            deleted.format(DATE_FORMAT)
+        );
+        // Creating the info file claims its name, even against another
+        // shell trashing a file with the same name at the same time
+        let mut n = 1;
+        let (name, mut info) = loop {
+            let name = match n {
+                1 => base.clone(),
+                n => format!("{}.{}", base, n),
+            };
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.info(&name))
+            {
+                Ok(info) => break (name, info),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => n += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        info.write_all(text.as_bytes())?;
+
+        let moved = match fs::rename(&path, self.files().join(&name)) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                let mut report = crate::copy::Report::default();
+                crate::copy::move_by_copying(&path, &self.files().join(&name), &mut report);
+                match report.errors.into_iter().next() {
+                    Some(error) => Err(io::Error::other(error)),
+                    None => Ok(()),
+                }
+            }
+            moved => moved,
+        };
+        if let Err(e) = moved {
+            let _ = fs::remove_file(self.info(&name));
+            return Err(e);
+        }
+        Ok(Entry {
+            name,
+            path,
+            deleted,
+        })
+    }
+
+    /// The files in the trash, oldest first. An empty or missing trash has
+    /// none, and files without readable info are left out.
+    pub fn list(&self) -> io::Result<Vec<Entry>> {
+        let entries = match fs::read_dir(self.dir.join("info")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        let mut found = Vec::new();
+        for entry in entries {
+            let entry = entry?;
+            let file_name = entry.file_name().to_string_lossy().into_owned();
+            let Some(name) = file_name.strip_suffix(".trashinfo") else {
+                continue;
+            };
+            if let Ok(text) = fs::read_to_string(entry.path())
+                && let Some(entry) = parse_info(name, &text)
+            {
+                found.push(entry);
+            }
+        }
+        found.sort_by(|a, b| a.deleted.cmp(&b.deleted).then_with(|| a.name.cmp(&b.name)));
+        Ok(found)
+    }
+
+    /// Moves `entry` back to where it came from, creating the directories
+    /// on the way if they are gone. Nothing is overwritten.
+    pub fn restore(&self, entry: &Entry) -> io::Result<()> {
+        if fs::symlink_metadata(&entry.path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a file with that name already exists",
+            ));
+        }
+        if let Some(parent) = entry.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = self.files().join(&entry.name);
+        match fs::rename(&file, &entry.path) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                let mut report = crate::copy::Report::default();
+                crate::copy::move_by_copying(&file, &entry.path, &mut report);
+                if let Some(error) = report.errors.into_iter().next() {
+                    return Err(io::Error::other(error));
+                }
+            }
+            moved => moved?,
+        }
+        fs::remove_file(self.info(&entry.name))
+    }
+}
+
+/// Parses the contents of a `.trashinfo` file for the file `name`.
+fn parse_info(name: &str, text: &str) -> Option<Entry> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next()? != "[Trash Info]" {
+        return None;
+    }
+    let mut path = None;
+    let mut deleted = None;
+    for line in lines {
+        match line.split_once('=') {
+            Some(("Path", value)) => path = Some(PathBuf::from(crate::http::percent_decode(value))),
+            Some(("DeletionDate", value)) => {
+                deleted = NaiveDateTime::parse_from_str(value, DATE_FORMAT).ok()
+            }
+            _ => {}
+        }
+    }
+    Some(Entry {
+        name: name.to_string(),
+        path: path?,
+        deleted: deleted?,
+    })
+}
+
+/// Encodes everything in `path` but unreserved characters and `/`.
+fn percent_encode(path: &str) -> String {
+    let mut out = String::with_capacity(path.len());
+    for &b in path.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"/-._~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_trash_put_list_restore() {
+        let dir = tempdir().unwrap();
+        let trash = Trash::new(dir.path().join("Trash"));
+        let file = dir.path().join("old notes.txt");
+        fs::write(&file, "first").unwrap();
+
+        let first = trash.put(&file).unwrap();
+        assert!(!file.exists());
+        assert_eq!(first.name, "old notes.txt");
+        assert_eq!(first.path, file);
+        let info = fs::read_to_string(trash.info(&first.name)).unwrap();
+        assert!(
+            info.contains("Path=") && info.contains("old%20notes.txt"),
+            "{}",
+            info
+        );
+
+        // A second file of the same name gets a name of its own
+        fs::write(&file, "second").unwrap();
+        let second = trash.put(&file).unwrap();
+        assert_eq!(second.name, "old notes.txt.2");
+        assert_eq!(trash.list().unwrap(), [first.clone(), second.clone()]);
+
+        trash.restore(&second).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "second");
+        let err = trash.restore(&first).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(trash.list().unwrap(), [first]);
+    }
+}
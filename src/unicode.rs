@@ -0,0 +1,149 @@
+//! Approximate Unicode text segmentation.
+//!
+//! Covers the cases that matter for terminal text: combining marks stay with
+//! their base character, emoji joined with ZWJ or modified by skin tones and
+//! variation selectors form one cluster, regional indicators pair into flags,
+//! and `\r\n` is kept together. Hangul syllable composition and rarer rules
+//! of UAX #29 are not implemented.
+//!
+//! Display widths follow the same spirit: East Asian wide characters and
+//! emoji take two terminal columns, combining marks none.
+
+/// Whether `c` extends the preceding character rather than starting a new
+/// grapheme cluster.
+fn is_extender(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F     // combining diacritical marks
+        | 0x0483..=0x0489   // Cyrillic combining marks
+        | 0x0591..=0x05BD   // Hebrew points
+        | 0x0610..=0x061A   // Arabic marks
+        | 0x064B..=0x065F
+        | 0x0670
+        | 0x06D6..=0x06DC
+        | 0x06DF..=0x06E4
+        | 0x0900..=0x0903   // Devanagari and other Indic signs
+        | 0x093A..=0x094F
+        | 0x0951..=0x0957
+        | 0x0962..=0x0963
+        | 0x0981..=0x0983
+        | 0x09BC..=0x09D7
+        | 0x0E31
+        | 0x0E34..=0x0E3A   // Thai vowels and tone marks
+        | 0x0E47..=0x0E4E
+        | 0x1AB0..=0x1AFF   // combining diacritical marks extended
+        | 0x1DC0..=0x1DFF   // combining diacritical marks supplement
+        | 0x200C            // zero width non-joiner
+        | 0x20D0..=0x20FF   // combining marks for symbols
+        | 0x302A..=0x302F
+        | 0x3099..=0x309A   // kana voicing marks
+        | 0xFE00..=0xFE0F   // variation selectors
+        | 0xFE20..=0xFE2F   // combining half marks
+        | 0x1F3FB..=0x1F3FF // emoji skin tone modifiers
+        | 0xE0020..=0xE007F // emoji tag sequences
+        | 0xE0100..=0xE01EF // variation selectors supplement
+    )
+}
+
+/// Number of terminal columns `c` occupies: 0 for combining marks and
+/// control characters, 2 for wide characters, 1 otherwise.
+pub fn char_width(c: char) -> usize {
+    if c.is_control() || c == '\u{200D}' || is_extender(c) {
+        return 0;
+    }
+    let wide = matches!(c as u32,
+        0x1100..=0x115F     // Hangul Jamo
+        | 0x2E80..=0x303E   // CJK radicals, punctuation
+        | 0x3041..=0x33FF   // kana, CJK compatibility
+        | 0x3400..=0x4DBF   // CJK extension A
+        | 0x4E00..=0x9FFF   // CJK unified ideographs
+        | 0xA000..=0xA4CF   // Yi
+        | 0xAC00..=0xD7A3   // Hangul syllables
+        | 0xF900..=0xFAFF   // CJK compatibility ideographs
+        | 0xFE30..=0xFE4F   // CJK compatibility forms
+        | 0xFF00..=0xFF60   // fullwidth forms
+        | 0xFFE0..=0xFFE6
+        | 0x1F1E6..=0x1F1FF // regional indicators
+        | 0x1F300..=0x1F64F // pictographs and emoticons
+        | 0x1F680..=0x1F6FF // transport and map symbols
+        | 0x1F900..=0x1F9FF // supplemental symbols and pictographs
+        | 0x1FA70..=0x1FAFF
+        | 0x20000..=0x3FFFD // CJK extensions B and beyond
+    );
+    if wide { 2 } else { 1 }
+}
+
+/// Number of terminal columns `text` occupies.
+pub fn str_width(text: &str) -> usize {
+    text.chars().map(char_width).sum()
+}
+
+fn is_regional_indicator(c: char) -> bool {
+    ('\u{1F1E6}'..='\u{1F1FF}').contains(&c)
+}
+
+/// Splits `text` into grapheme clusters.
+pub fn graphemes(text: &str) -> Vec<&str> {
+    let mut clusters = Vec::new();
+    let mut start = 0;
+    let mut prev: Option<char> = None;
+    // Regional indicators seen in the current cluster
+    let mut indicators = 0;
+
+    for (i, c) in text.char_indices() {
+        let joins = match prev {
+            None => false,
+            Some('\r') => c == '\n',
+            Some('\u{200D}') => true,
+            Some(p) if is_regional_indicator(p) && is_regional_indicator(c) => indicators % 2 == 1,
+            Some(p) => p != '\n' && p != '\r' && (is_extender(c) || c == '\u{200D}'),
+        };
+        if !joins {
+            if i > start {
+                clusters.push(&text[start..i]);
+            }
+            start = i;
+            indicators = 0;
+        }
+        if is_regional_indicator(c) {
+            indicators += 1;
+        }
+        prev = Some(c);
+    }
+    if start < text.len() {
+        clusters.push(&text[start..]);
+    }
+    clusters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_graphemes() {
+        assert_eq!(graphemes("abc"), vec!["a", "b", "c"]);
+        assert_eq!(graphemes("e\u{301}x"), vec!["e\u{301}", "x"]);
+        assert_eq!(graphemes("a\r\nb"), vec!["a", "\r\n", "b"]);
+        // Woman + ZWJ + laptop, thumbs up with skin tone
+        assert_eq!(
+            graphemes("\u{1F469}\u{200D}\u{1F4BB}\u{1F44D}\u{1F3FD}"),
+            vec!["\u{1F469}\u{200D}\u{1F4BB}", "\u{1F44D}\u{1F3FD}"]
+        );
+        // Two flags: FI and SE
+        assert_eq!(
+            graphemes("\u{1F1EB}\u{1F1EE}\u{1F1F8}\u{1F1EA}"),
+            vec!["\u{1F1EB}\u{1F1EE}", "\u{1F1F8}\u{1F1EA}"]
+        );
+        assert_eq!(graphemes(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn test_display_width() {
+        assert_eq!(char_width('a'), 1);
+        assert_eq!(char_width('\u{301}'), 0);
+        assert_eq!(char_width('\t'), 0);
+        assert_eq!(char_width('\u{65E5}'), 2);
+        assert_eq!(char_width('\u{1F44D}'), 2);
+        assert_eq!(str_width("a\u{65E5}e\u{301}"), 4);
+    }
+}
@@ -0,0 +1,408 @@
+//! WebAssembly plugins, run in a sandbox inside the shell.
+//!
+//! A `.wasm` file in the plugins directory provides a command just as a
+//! program there does, and speaks the same JSON (see [`crate::plugins`]),
+//! but it is run by the wasmi interpreter rather than as a process of its
+//! own. It can reach nothing outside its own memory but the functions the
+//! shell gives it, so an untrusted plugin cannot harm the system. Each run
+//! starts from a fresh instance of the module, with a limit on the memory
+//! it may use and on how long it may run.
+//!
+//! A module exports its `memory` and three functions:
+//!
+//! - `alloc(len: i32) -> i32` returns the address of `len` bytes of free
+//!   memory, where the shell puts the request.
+//! - `describe() -> i64` returns the plugin's description.
+//! - `run(ptr: i32, len: i32) -> i64` is given the request, at `ptr`, and
+//!   returns the answer.
+//!
+//! Both return the address of their JSON in the high 32 bits and its length
+//! in the low 32 bits. A plugin whose description has `"fs": "read"`, or
+//! `"fs": "write"`, may also use files through two functions it can import
+//! from the module `0shell`:
+//!
+//! - `read_file(path: i32, path_len: i32, buf: i32, buf_len: i32) -> i64`
+//!   returns the size of the file, and reads it into `buf` if it fits. A
+//!   file larger than a plugin's memory cannot be used.
+//! - `write_file(path: i32, path_len: i32, data: i32, data_len: i32) -> i32`
+//!   replaces the file with `data`, returning 0.
+//!
+//! Both return -1 if access is denied and -2 if the file cannot be used.
+//! Writing also needs the user's consent, in the file named by
+//! [`crate::plugins::WRITE_GRANTS`]. Paths are relative to the current directory, and access is denied to
+//! anything outside it, even through symbolic links.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use wasmi::core::TrapCode;
+use wasmi::{
+    Caller, Config, Engine, Extern, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use crate::json::Value;
+
+/// How much a plugin may run before it is stopped, roughly in
+/// instructions: a second or two of work.
+const FUEL: u64 = 1_000_000_000;
+
+/// The most memory a plugin may use.
+const MEMORY: usize = 64 << 20;
+
+/// What a plugin may do with files under the current directory.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    None,
+    Read,
+    Write,
+}
+
+impl Access {
+    /// The access asked for by the `fs` field of a description.
+    pub fn from_field(value: Option<&Value>) -> Result<Self, String> {
+        match value {
+            None | Some(Value::Null) => Ok(Access::None),
+            Some(Value::String(s)) if s == "none" => Ok(Access::None),
+            Some(Value::String(s)) if s == "read" => Ok(Access::Read),
+            Some(Value::String(s)) if s == "write" => Ok(Access::Write),
+            Some(_) => Err("'fs' is not one of \"none\", \"read\", or \"write\"".to_string()),
+        }
+    }
+}
+
+/// A compiled plugin module.
+#[derive(Debug)]
+pub struct WasmModule {
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+}
+
+/// What the functions the shell gives a plugin can reach.
+struct Host {
+    /// The directory the plugin's files are in, with links resolved.
+    root: PathBuf,
+    access: Access,
+    limits: StoreLimits,
+}
+
+impl WasmModule {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let wasm = fs::read(path).map_err(|e| e.to_string())?;
+        Self::new(&wasm)
+    }
+
+    pub fn new(wasm: &[u8]) -> Result<Self, String> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+        let module = Module::new(&engine, wasm).map_err(|e| e.to_string())?;
+        Ok(Self {
+            engine,
+            module,
+            fuel: FUEL,
+        })
+    }
+
+    /// Stops each run of the plugin after `fuel` instead.
+    pub fn with_fuel(self, fuel: u64) -> Self {
+        Self { fuel, ..self }
+    }
+
+    /// The plugin's description. It gets no access to files.
+    pub fn describe(&self) -> Result<Vec<u8>, String> {
+        let (mut store, instance) = self.instantiate(Path::new("/"), Access::None)?;
+        let describe = instance
+            .get_typed_func::<(), i64>(&store, "describe")
+            .map_err(|e| e.to_string())?;
+        let answer = describe.call(&mut store, ()).map_err(trap_message)?;
+        read_answer(&store, instance.get_memory(&store, "memory"), answer)
+    }
+
+    /// Runs the plugin for `request`, with `access` to files under `root`,
+    /// and returns its answer.
+    pub fn run(&self, request: &str, root: &Path, access: Access) -> Result<Vec<u8>, String> {
+        let root = fs::canonicalize(root).map_err(|e| e.to_string())?;
+        let (mut store, instance) = self.instantiate(&root, access)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .ok_or("the module exports no memory")?;
+        let alloc = instance
+            .get_typed_func::<i32, i32>(&store, "alloc")
+            .map_err(|e| e.to_string())?;
+        let run = instance
+            .get_typed_func::<(i32, i32), i64>(&store, "run")
+            .map_err(|e| e.to_string())?;
+
+        let len = i32::try_from(request.len()).map_err(|_| "request too long")?;
+        let ptr = alloc.call(&mut store, len).map_err(trap_message)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, request.as_bytes())
+            .map_err(|_| "alloc returned memory out of bounds")?;
+        let answer = run.call(&mut store, (ptr, len)).map_err(trap_message)?;
+        read_answer(&store, Some(memory), answer)
+    }
+
+    /// A fresh instance of the module, with its own memory and fuel.
+    fn instantiate(
+        &self,
+        root: &Path,
+        access: Access,
+    ) -> Result<(Store<Host>, wasmi::Instance), String> {
+        let host = Host {
+            root: root.to_path_buf(),
+            access,
+            limits: StoreLimitsBuilder::new().memory_size(MEMORY).build(),
+        };
+        let mut store = Store::new(&self.engine, host);
+        store.limiter(|host| &mut host.limits);
+        store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+
+        let mut linker = <Linker<Host>>::new(&self.engine);
+        linker
+            .func_wrap("0shell", "read_file", read_file)
+            .map_err(|e| e.to_string())?;
+        linker
+            .func_wrap("0shell", "write_file", write_file)
+            .map_err(|e| e.to_string())?;
+        let instance = linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(trap_message)?;
+        Ok((store, instance))
+    }
+}
+
+/// Describes why a plugin stopped, naming the limits it can run into.
+fn trap_message(e: wasmi::Error) -> String {
+    match e.as_trap_code() {
+        Some(TrapCode::OutOfFuel) => "ran for too long and was stopped".to_string(),
+        _ => e.to_string(),
+    }
+}
+
+/// Copies the JSON a plugin returned, `answer` being its address and length.
+fn read_answer(
+    store: &Store<Host>,
+    memory: Option<Memory>,
+    answer: i64,
+) -> Result<Vec<u8>, String> {
+    let memory = memory.ok_or("the module exports no memory")?;
+    let (ptr, len) = ((answer >> 32) as u32 as usize, answer as u32 as usize);
+    memory
+        .data(store)
+        .get(ptr..ptr + len)
+        .map(<[u8]>::to_vec)
+        .ok_or_else(|| "answer out of bounds".to_string())
+}
+
+/// The memory of the plugin calling a function.
+fn caller_memory(caller: &Caller<'_, Host>) -> Option<Memory> {
+    caller.get_export("memory").and_then(Extern::into_memory)
+}
+
+/// The bytes at `ptr`, `len` long, in the caller's memory.
+fn guest_bytes(caller: &Caller<'_, Host>, ptr: i32, len: i32) -> Option<Vec<u8>> {
+    let (ptr, len) = (ptr as u32 as usize, len as u32 as usize);
+    let memory = caller_memory(caller)?;
+    memory.data(caller).get(ptr..ptr + len).map(<[u8]>::to_vec)
+}
+
+/// The file a plugin named, if it may use it.
+fn guest_path(caller: &Caller<'_, Host>, ptr: i32, len: i32, write: bool) -> Option<PathBuf> {
+    let host = caller.data();
+    let allowed = match host.access {
+        Access::None => false,
+        Access::Read => !write,
+        Access::Write => true,
+    };
+    if !allowed {
+        return None;
+    }
+    let path = String::from_utf8(guest_bytes(caller, ptr, len)?).ok()?;
+    resolve(&host.root, &path)
+}
+
+fn read_file(
+    mut caller: Caller<'_, Host>,
+    path: i32,
+    path_len: i32,
+    buf: i32,
+    buf_len: i32,
+) -> i64 {
+    use std::io::Read;
+
+    let Some(path) = guest_path(&caller, path, path_len, false) else {
+        return -1;
+    };
+    // The file is looked at before it is opened, so a large file costs
+    // nothing and a FIFO cannot keep the shell waiting
+    let size = match fs::metadata(&path) {
+        Ok(meta) if meta.is_file() && meta.len() <= MEMORY as u64 => meta.len(),
+        _ => return -2,
+    };
+    if size > buf_len as u32 as u64 {
+        return size as i64;
+    }
+    let mut data = Vec::new();
+    let read = fs::File::open(path).and_then(|file| file.take(size).read_to_end(&mut data));
+    if read.is_err() {
+        return -2;
+    }
+    if let Some(memory) = caller_memory(&caller)
+        && memory
+            .write(&mut caller, buf as u32 as usize, &data)
+            .is_err()
+    {
+        return -2;
+    }
+    data.len() as i64
+}
+
+fn write_file(caller: Caller<'_, Host>, path: i32, path_len: i32, data: i32, data_len: i32) -> i32 {
+    let Some(path) = guest_path(&caller, path, path_len, true) else {
+        return -1;
+    };
+    match guest_bytes(&caller, data, data_len).map(|data| fs::write(path, data)) {
+        Some(Ok(())) => 0,
+        _ => -2,
+    }
+}
+
+/// Resolves `path` against `root`, or `None` if it leads outside it.
+fn resolve(root: &Path, path: &str) -> Option<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    for component in Path::new(path).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir if resolved != root => {
+                resolved.pop();
+            }
+            // Absolute paths, and climbing out of the root
+            _ => return None,
+        }
+    }
+    // Links on the way must not lead out either. A file that does not
+    // exist yet is checked by its directory.
+    let real = match fs::canonicalize(&resolved) {
+        Ok(real) => real,
+        Err(_) if fs::symlink_metadata(&resolved).is_err() => fs::canonicalize(resolved.parent()?)
+            .ok()?
+            .join(resolved.file_name()?),
+        // A dangling link could point anywhere
+        Err(_) => return None,
+    };
+    real.starts_with(root).then_some(real)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// A plugin that answers with the contents of the file `path`, or
+    /// with status 5 if it cannot read it.
+    fn cat_plugin(fs: &str, path: &str) -> WasmModule {
+        let description = format!("{{\"help\": \"cat1 - show a file\", \"fs\": \"{}\"}}", fs);
+        let wat = format!(
+            r#"(module
+                (import "0shell" "read_file" (func $read (param i32 i32 i32 i32) (result i64)))
+                (memory (export "memory") 1)
+                (data (i32.const 0) "{description}")
+                (data (i32.const 256) "{path}")
+                (data (i32.const 512) "{{\"stdout\": \"")
+                (data (i32.const 600) "{{\"status\": 5}}")
+                (func (export "alloc") (param i32) (result i32) (i32.const 2048))
+                (func (export "describe") (result i64) (i64.const {describe_len}))
+                (func (export "run") (param i32 i32) (result i64)
+                    (local $size i64)
+                    (local.set $size (call $read (i32.const 256) (i32.const {path_len})
+                        (i32.const 524) (i32.const 1024)))
+                    (if (i64.lt_s (local.get $size) (i64.const 0))
+                        (then (return (i64.or (i64.shl (i64.const 600) (i64.const 32))
+                            (i64.const 13)))))
+                    (i32.store16 (i32.add (i32.const 524) (i32.wrap_i64 (local.get $size)))
+                        (i32.const 0x7d22))
+                    (i64.or (i64.shl (i64.const 512) (i64.const 32))
+                        (i64.add (local.get $size) (i64.const 14)))))"#,
+            description = description.replace('"', "\\\""),
+            describe_len = description.len(),
+            path_len = path.len(),
+        );
+        WasmModule::new(&wat::parse_str(wat).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_wasm_plugin_file_access() {
+        let dir = tempdir().unwrap();
+        let root = dir.path().join("root");
+        fs::create_dir(&root).unwrap();
+        fs::write(root.join("a.txt"), "inside").unwrap();
+        fs::write(dir.path().join("secret.txt"), "outside").unwrap();
+        std::os::unix::fs::symlink(dir.path().join("secret.txt"), root.join("link")).unwrap();
+
+        let plugin = cat_plugin("read", "a.txt");
+        assert_eq!(
+            plugin.describe().unwrap(),
+            b"{\"help\": \"cat1 - show a file\", \"fs\": \"read\"}"
+        );
+        let answer = plugin.run("{}", &root, Access::Read).unwrap();
+        assert_eq!(answer, b"{\"stdout\": \"inside\"}");
+
+        // Without access, and outside the root, files cannot be read
+        let denied = b"{\"status\": 5}".to_vec();
+        assert_eq!(plugin.run("{}", &root, Access::None).unwrap(), denied);
+        for path in [
+            "../secret.txt",
+            "link",
+            "/etc/passwd",
+            "./a.txt/../../secret.txt",
+        ] {
+            let plugin = cat_plugin("read", path);
+            assert_eq!(
+                plugin.run("{}", &root, Access::Read).unwrap(),
+                denied,
+                "{}",
+                path
+            );
+        }
+        // A file too large for the plugin's memory is refused unread
+        fs::File::create(root.join("huge"))
+            .unwrap()
+            .set_len(MEMORY as u64 + 1)
+            .unwrap();
+        let plugin = cat_plugin("read", "huge");
+        assert_eq!(plugin.run("{}", &root, Access::Read).unwrap(), denied);
+
+        assert_eq!(
+            resolve(&fs::canonicalize(&root).unwrap(), "sub/../a.txt"),
+            Some(fs::canonicalize(root.join("a.txt")).unwrap())
+        );
+    }
+
+    #[test]
+    fn test_wasm_plugin_limits() {
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) (i32.const 0))
+            (func (export "describe") (result i64) (i64.const 0))
+            (func (export "run") (param i32 i32) (result i64)
+                (loop $forever (br $forever))
+                (i64.const 0)))"#;
+        let plugin = WasmModule::new(&wat::parse_str(wat).unwrap())
+            .unwrap()
+            .with_fuel(1_000_000);
+        let err = plugin.run("{}", Path::new("/"), Access::None).unwrap_err();
+        assert_eq!(err, "ran for too long and was stopped");
+
+        let wat = r#"(module
+            (memory (export "memory") 1)
+            (func (export "describe") (result i64)
+                (drop (memory.grow (i32.const 2000)))
+                (i64.const 0x7fffffff)))"#;
+        let plugin = WasmModule::new(&wat::parse_str(wat).unwrap()).unwrap();
+        assert_eq!(plugin.describe().unwrap_err(), "answer out of bounds");
+        assert!(WasmModule::new(b"not wasm").is_err());
+    }
+}
@@ -0,0 +1,488 @@
+//! ZIP archive reading and writing, with members either stored or
+//! compressed with DEFLATE (the two methods every unzip tool understands).
+//!
+//! Archives are handled in memory, like gzip files in `compress.rs`. ZIP64
+//! extensions are not supported, which limits archives and members to
+//! 4 GiB and 65535 entries. Encrypted members are rejected.
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
+
+use crate::compress::{DecodeError, crc32, deflate, inflate_with_len};
+
+const LOCAL_HEADER_SIG: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIG: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_SIG: u32 = 0x0605_4b50;
+
+pub const METHOD_STORE: u16 = 0;
+pub const METHOD_DEFLATE: u16 = 8;
+
+/// The member is encrypted.
+const FLAG_ENCRYPTED: u16 = 0x0001;
+/// Names are UTF-8.
+const FLAG_UTF8: u16 = 0x0800;
+
+/// Version 2.0, the first with DEFLATE and directories.
+const VERSION_NEEDED: u16 = 20;
+/// "Made by" Unix, so that readers use the permission bits.
+const VERSION_MADE_BY: u16 = 3 << 8 | VERSION_NEEDED;
+
+/// A member of an archive, as described by the central directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    /// The member's path, with `/` separators. Directories end in `/`.
+    pub name: String,
+    pub method: u16,
+    pub crc: u32,
+    pub compressed_size: u32,
+    pub size: u32,
+    /// The modification time, in local time. DOS timestamps have two
+    /// second resolution.
+    pub modified: Option<NaiveDateTime>,
+    /// Unix permission bits, when the archive was made on Unix.
+    pub mode: Option<u32>,
+    flags: u16,
+    header_offset: u32,
+}
+
+impl Entry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// A parsed archive borrowing its bytes.
+pub struct Archive<'a> {
+    data: &'a [u8],
+    pub entries: Vec<Entry>,
+}
+
+impl<'a> Archive<'a> {
+    /// Reads the central directory at the end of `data`.
+    pub fn parse(data: &'a [u8]) -> Result<Self, DecodeError> {
+        let not_zip = || DecodeError("not a zip archive".to_string());
+        // The end record is 22 bytes plus a comment of up to 65535 bytes
+        let search_from = data.len().saturating_sub(22 + 0xFFFF);
+        let end = (search_from..=data.len().saturating_sub(22))
+            .rev()
+            .find(|&i| u32_at(data, i) == Some(END_OF_CENTRAL_SIG))
+            .ok_or_else(not_zip)?;
+
+        let count = u16_at(data, end + 10).ok_or_else(not_zip)? as usize;
+        let dir_offset = u32_at(data, end + 16).ok_or_else(not_zip)?;
+        if count == 0xFFFF || dir_offset == u32::MAX {
+            return Err(DecodeError("ZIP64 archives are not supported".to_string()));
+        }
+
+        let truncated = || DecodeError("truncated central directory".to_string());
+        let mut entries = Vec::with_capacity(count);
+        let mut pos = dir_offset as usize;
+        for _ in 0..count {
+            if u32_at(data, pos) != Some(CENTRAL_HEADER_SIG) {
+                return Err(truncated());
+            }
+            let field = |offset: usize| u16_at(data, pos + offset).ok_or_else(truncated);
+            let field32 = |offset: usize| u32_at(data, pos + offset).ok_or_else(truncated);
+            let made_by = field(4)?;
+            let flags = field(8)?;
+            let name_len = field(28)? as usize;
+            let extra_len = field(30)? as usize;
+            let comment_len = field(32)? as usize;
+            let external = field32(38)?;
+            let name = data
+                .get(pos + 46..pos + 46 + name_len)
+                .ok_or_else(truncated)?;
+            // Without the UTF-8 flag names are nominally CP437, but in
+            // practice they are ASCII or in the creator's system encoding
+            let name = String::from_utf8_lossy(name).into_owned();
+            let mode = (made_by >> 8 == 3)
+                .then_some(external >> 16)
+                .filter(|&m| m != 0);
+
+            entries.push(Entry {
+                name,
+                method: field(10)?,
+                modified: from_dos_datetime(field(14)?, field(12)?),
+                crc: field32(16)?,
+                compressed_size: field32(20)?,
+                size: field32(24)?,
+                mode,
+                flags,
+                header_offset: field32(42)?,
+            });
+            pos += 46 + name_len + extra_len + comment_len;
+        }
+
+        Ok(Self { data, entries })
+    }
+
+    /// Returns the contents of `entry`, checking its CRC.
+    pub fn read(&self, entry: &Entry) -> Result<Vec<u8>, DecodeError> {
+        let corrupt = |msg: &str| DecodeError(format!("{}: {}", entry.name, msg));
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            return Err(corrupt("encrypted members are not supported"));
+        }
+
+        let pos = entry.header_offset as usize;
+        if u32_at(self.data, pos) != Some(LOCAL_HEADER_SIG) {
+            return Err(corrupt("bad local header"));
+        }
+        let name_len = u16_at(self.data, pos + 26).ok_or_else(|| corrupt("truncated"))?;
+        let extra_len = u16_at(self.data, pos + 28).ok_or_else(|| corrupt("truncated"))?;
+        // Sizes come from the central directory, since the local header
+        // may defer them to a data descriptor
+        let start = pos + 30 + name_len as usize + extra_len as usize;
+        let body = self
+            .data
+            .get(start..start + entry.compressed_size as usize)
+            .ok_or_else(|| corrupt("truncated"))?;
+
+        let contents = match entry.method {
+            METHOD_STORE => body.to_vec(),
+            METHOD_DEFLATE => {
+                inflate_with_len(body)
+                    .map_err(|e| corrupt(&e.to_string()))?
+                    .0
+            }
+            method => {
+                return Err(corrupt(&format!(
+                    "unsupported compression method {}",
+                    method
+                )));
+            }
+        };
+        if contents.len() != entry.size as usize {
+            return Err(corrupt("size mismatch"));
+        }
+        if crc32(&contents) != entry.crc {
+            return Err(corrupt("bad CRC"));
+        }
+        Ok(contents)
+    }
+}
+
+/// Writes an archive member by member.
+pub struct Writer<W: Write> {
+    out: W,
+    offset: u64,
+    central: Vec<u8>,
+    count: usize,
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            offset: 0,
+            central: Vec::new(),
+            count: 0,
+        }
+    }
+
+    /// Adds a file, deflated at `level` (1-9) unless that would not make it
+    /// smaller or `level` is 0. Returns the method used and the stored size.
+    pub fn add_file(
+        &mut self,
+        name: &str,
+        data: &[u8],
+        modified: NaiveDateTime,
+        mode: u32,
+        level: u32,
+    ) -> io::Result<(u16, usize)> {
+        if data.len() > u32::MAX as usize {
+            return Err(too_large());
+        }
+        let packed = (level > 0)
+            .then(|| deflate(data, level))
+            .filter(|packed| packed.len() < data.len());
+        let (method, body) = match &packed {
+            Some(packed) => (METHOD_DEFLATE, &packed[..]),
+            None => (METHOD_STORE, data),
+        };
+        let header = Header {
+            name,
+            method,
+            crc: crc32(data),
+            compressed_size: body.len() as u32,
+            size: data.len() as u32,
+            modified,
+            external: 0o100000 | (mode & 0o7777),
+        };
+        self.add(&header, body)?;
+        Ok((method, body.len()))
+    }
+
+    /// Adds a directory. A trailing `/` is appended to `name` if missing.
+    pub fn add_dir(&mut self, name: &str, modified: NaiveDateTime, mode: u32) -> io::Result<()> {
+        let name = if name.ends_with('/') {
+            name.to_string()
+        } else {
+            format!("{}/", name)
+        };
+        let header = Header {
+            name: &name,
+            method: METHOD_STORE,
+            crc: 0,
+            compressed_size: 0,
+            size: 0,
+            modified,
+            // The low byte carries the MS-DOS directory attribute
+            external: 0o040000 | (mode & 0o7777),
+        };
+        self.add(&header, &[])
+    }
+
+    /// Writes the central directory and returns the underlying writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        if self.count > 0xFFFE || self.offset > u32::MAX as u64 {
+            return Err(too_large());
+        }
+        let mut end = Vec::with_capacity(22);
+        end.extend_from_slice(&END_OF_CENTRAL_SIG.to_le_bytes());
+        end.extend_from_slice(&[0; 4]); // disk numbers
+        end.extend_from_slice(&(self.count as u16).to_le_bytes());
+        end.extend_from_slice(&(self.count as u16).to_le_bytes());
+        end.extend_from_slice(&(self.central.len() as u32).to_le_bytes());
+        end.extend_from_slice(&(self.offset as u32).to_le_bytes());
+        end.extend_from_slice(&0u16.to_le_bytes()); // comment length
+        self.out.write_all(&self.central)?;
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn add(&mut self, header: &Header, body: &[u8]) -> io::Result<()> {
+        if self.offset > u32::MAX as u64 {
+            return Err(too_large());
+        }
+        let (time, date) = to_dos_datetime(header.modified);
+        let mut common = Vec::with_capacity(26);
+        common.extend_from_slice(&VERSION_NEEDED.to_le_bytes());
+        common.extend_from_slice(&FLAG_UTF8.to_le_bytes());
+        common.extend_from_slice(&header.method.to_le_bytes());
+        common.extend_from_slice(&time.to_le_bytes());
+        common.extend_from_slice(&date.to_le_bytes());
+        common.extend_from_slice(&header.crc.to_le_bytes());
+        common.extend_from_slice(&header.compressed_size.to_le_bytes());
+        common.extend_from_slice(&header.size.to_le_bytes());
+        common.extend_from_slice(&(header.name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes()); // extra length
+
+        let mut local = Vec::with_capacity(30 + header.name.len());
+        local.extend_from_slice(&LOCAL_HEADER_SIG.to_le_bytes());
+        local.extend_from_slice(&common);
+        local.extend_from_slice(header.name.as_bytes());
+        self.out.write_all(&local)?;
+        self.out.write_all(body)?;
+
+        let dos_attr = if header.name.ends_with('/') { 0x10 } else { 0 };
+        self.central
+            .extend_from_slice(&CENTRAL_HEADER_SIG.to_le_bytes());
+        self.central
+            .extend_from_slice(&VERSION_MADE_BY.to_le_bytes());
+        self.central.extend_from_slice(&common);
+        self.central.extend_from_slice(&[0; 6]); // comment length, disk, internal attributes
+        self.central
+            .extend_from_slice(&(header.external << 16 | dos_attr).to_le_bytes());
+        self.central
+            .extend_from_slice(&(self.offset as u32).to_le_bytes());
+        self.central.extend_from_slice(header.name.as_bytes());
+
+        self.offset += (local.len() + body.len()) as u64;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+/// The fields of a member header shared by its local and central copies.
+struct Header<'a> {
+    name: &'a str,
+    method: u16,
+    crc: u32,
+    compressed_size: u32,
+    size: u32,
+    modified: NaiveDateTime,
+    /// Unix file type and permission bits.
+    external: u32,
+}
+
+fn too_large() -> io::Error {
+    io::Error::other("archive too large (ZIP64 is not supported)")
+}
+
+/// Converts a member name to a relative path for extraction, or `None` if
+/// it could write outside the destination: absolute names, drive letters,
+/// and any `..` component.
+pub fn safe_path(name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    if name.starts_with('/') {
+        return None;
+    }
+    let mut path = PathBuf::new();
+    for (i, part) in name.split('/').enumerate() {
+        match part {
+            "" | "." => {}
+            ".." => return None,
+            _ if i == 0 && part.len() == 2 && part.ends_with(':') => return None,
+            _ => path.push(part),
+        }
+    }
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// Matches a member name against a wildcard pattern, as `unzip` does:
+/// `*` matches any run of characters including `/`, `?` any single
+/// character, and `[...]` a character class (`[!...]` negated).
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where to resume after the most recent `*`
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        let step = match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+                continue;
+            }
+            Some('?') => Some(p + 1),
+            Some('[') => match_class(&pattern, p, name[n]),
+            Some(&c) => (c == name[n]).then_some(p + 1),
+            None => None,
+        };
+        match (step, star) {
+            (Some(next), _) => {
+                p = next;
+                n += 1;
+            }
+            (None, Some((star_p, star_n))) => {
+                p = star_p;
+                n = star_n + 1;
+                star = Some((star_p, star_n + 1));
+            }
+            (None, None) => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Matches `c` against the class starting at `pattern[start]` (a `[`),
+/// returning the index after the class on a match. An unterminated class
+/// matches a literal `[`.
+fn match_class(pattern: &[char], start: usize, c: char) -> Option<usize> {
+    let mut i = start + 1;
+    let negated = matches!(pattern.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while let Some(&lo) = pattern.get(i) {
+        if lo == ']' && !first {
+            return (matched != negated).then_some(i + 1);
+        }
+        first = false;
+        if pattern.get(i + 1) == Some(&'-') && pattern.get(i + 2).is_some_and(|&hi| hi != ']') {
+            matched |= (lo..=pattern[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            matched |= lo == c;
+            i += 1;
+        }
+    }
+    (c == '[').then_some(start + 1)
+}
+
+/// Packs a timestamp into MS-DOS time and date fields, clamped to the
+/// representable years 1980-2107.
+fn to_dos_datetime(t: NaiveDateTime) -> (u16, u16) {
+    if t.year() < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    let year = (t.year() - 1980).min(127) as u16;
+    let time = (t.hour() as u16) << 11 | (t.minute() as u16) << 5 | (t.second() as u16 / 2);
+    let date = year << 9 | (t.month() as u16) << 5 | t.day() as u16;
+    (time, date)
+}
+
+fn from_dos_datetime(date: u16, time: u16) -> Option<NaiveDateTime> {
+    NaiveDate::from_ymd_opt(
+        1980 + (date >> 9) as i32,
+        (date >> 5 & 0xF) as u32,
+        (date & 0x1F) as u32,
+    )?
+    .and_hms_opt(
+        (time >> 11) as u32,
+        (time >> 5 & 0x3F) as u32,
+        (time & 0x1F) as u32 * 2,
+    )
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(pos..pos + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zip_roundtrip() {
+        let when = NaiveDate::from_ymd_opt(2024, 5, 17)
+            .unwrap()
+            .and_hms_opt(13, 45, 30)
+            .unwrap();
+        let text = "zip me ".repeat(100);
+        let mut writer = Writer::new(Vec::new());
+        writer.add_dir("docs", when, 0o755).unwrap();
+        let (method, _) = writer
+            .add_file("docs/notes.txt", text.as_bytes(), when, 0o644, 6)
+            .unwrap();
+        assert_eq!(method, METHOD_DEFLATE);
+        let (method, _) = writer.add_file("tiny", b"x", when, 0o600, 6).unwrap();
+        assert_eq!(method, METHOD_STORE);
+        let data = writer.finish().unwrap();
+
+        let archive = Archive::parse(&data).unwrap();
+        let names: Vec<&str> = archive.entries.iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["docs/", "docs/notes.txt", "tiny"]);
+        assert!(archive.entries[0].is_dir());
+        assert_eq!(archive.entries[1].modified, Some(when));
+        assert_eq!(archive.entries[2].mode, Some(0o100600));
+        assert_eq!(archive.read(&archive.entries[1]).unwrap(), text.as_bytes());
+        assert_eq!(archive.read(&archive.entries[2]).unwrap(), b"x");
+
+        let mut corrupted = data.clone();
+        let body = archive.entries[2].header_offset as usize + 30 + 4;
+        corrupted[body] = b'y';
+        let archive = Archive::parse(&corrupted).unwrap();
+        assert!(archive.read(&archive.entries[2]).is_err());
+        assert!(Archive::parse(b"not a zip").is_err());
+    }
+
+    #[test]
+    fn test_safe_path_and_wildcards() {
+        assert_eq!(safe_path("a/./b.txt"), Some(PathBuf::from("a/b.txt")));
+        assert_eq!(safe_path("dir/"), Some(PathBuf::from("dir")));
+        assert_eq!(safe_path("../evil"), None);
+        assert_eq!(safe_path("a/../../evil"), None);
+        assert_eq!(safe_path("/etc/passwd"), None);
+        assert_eq!(safe_path("C:\\Windows\\x"), None);
+        assert_eq!(safe_path("a\\..\\b"), None);
+
+        assert!(wildcard_match("*.txt", "docs/notes.txt"));
+        assert!(wildcard_match("docs/*", "docs/a/b"));
+        assert!(wildcard_match("file?.[ch]", "file1.c"));
+        assert!(!wildcard_match("file?.[!ch]", "file1.c"));
+        assert!(wildcard_match("[a-c]*", "beta"));
+        assert!(!wildcard_match("*.txt", "notes.txt.bak"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+    }
+}
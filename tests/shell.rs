@@ -0,0 +1,154 @@
+use zero_shell::{Command, CommandResult, Invocation, Shell, ShellCommand, ShellContext};
+
+#[test]
+fn test_run_line_results() {
+    let mut shell = Shell::new();
+    let results = shell.run_line("echo hello; nosuchcommand; echo $?");
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].stdout_text(), "hello\n");
+    assert_eq!(results[0].status, 0);
+    assert_eq!(results[1].status, 127);
+    assert!(results[1].stderr.contains("nosuchcommand"));
+    assert_eq!(results[2].stdout_text(), "127\n");
+    assert_eq!(shell.last_status(), 0);
+}
+
+#[test]
+fn test_session_state_persists() {
+    let mut shell = Shell::new();
+    shell.run_line("alias greet='echo hi there'");
+    assert_eq!(shell.run_line("greet")[0].stdout_text(), "hi there\n");
+    assert_eq!(
+        shell.state().aliases.get("greet").map(String::as_str),
+        Some("echo hi there")
+    );
+
+    shell.run_line("cd /no/such/directory");
+    assert_eq!(shell.last_status(), 1);
+    assert_eq!(shell.run_line("echo $?")[0].stdout_text(), "1\n");
+}
+
+#[test]
+fn test_exit_stops_the_line() {
+    let mut shell = Shell::new();
+    let results = shell.run_line("echo one; exit 3; echo two");
+    assert_eq!(results.len(), 2);
+    assert!(results[1].should_exit);
+    assert_eq!(results[1].status, 3);
+    assert_eq!(shell.last_status(), 3);
+}
+
+struct Count;
+
+impl ShellCommand for Count {
+    fn run(&self, _ctx: &mut ShellContext, inv: Invocation) -> CommandResult {
+        CommandResult::with_stdout(format!("{}\n", inv.args.len()))
+    }
+}
+
+#[test]
+fn test_registered_command() {
+    let mut shell = Shell::new();
+    shell.register(
+        "count",
+        Command::from_command("count [ARG]... - count the arguments", false, Count),
+    );
+    assert_eq!(shell.run_line("count a b c")[0].stdout_text(), "3\n");
+    assert!(
+        shell.run_line("help")[0]
+            .stdout_text()
+            .contains("count [ARG]...")
+    );
+}
+
+#[test]
+fn test_run_line_with_streams_output() {
+    let mut shell = Shell::new();
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    let mut stdin: &[u8] = b"piped\n";
+    let status = shell
+        .run_line_with(
+            "cat; echo done",
+            &mut stdin,
+            zero_shell::Output::new(&mut stdout, &mut stderr),
+        )
+        .unwrap();
+    assert_eq!(status, None);
+    assert_eq!(stdout, b"piped\ndone\n");
+    assert!(stderr.is_empty());
+}
+
+#[test]
+fn test_scheduled_jobs_run_between_lines() {
+    let mut shell = Shell::new();
+    let results = shell.run_line("schedule 0 X=2; schedule 0 alias gone=true");
+    assert!(results[0].stdout_text().starts_with("job 1 at "));
+    shell.run_line("schedule 0 'echo job $((X + 1))'");
+    assert!(shell.next_job_in().is_some());
+
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    shell
+        .run_due_jobs(zero_shell::Output::new(&mut stdout, &mut stderr))
+        .unwrap();
+    assert_eq!(stdout, b"job 1\n");
+    assert!(stderr.is_empty());
+    assert_eq!(shell.next_job_in(), None);
+
+    // Each job had a state of its own
+    assert_eq!(shell.state().vars.get("X"), None);
+    assert!(!shell.state().aliases.contains_key("gone"));
+}
+
+#[test]
+fn test_arithmetic_errors_fail_the_command() {
+    let mut shell = Shell::new();
+    let results = shell.run_line("echo $((1/0)); echo $?");
+    assert!(results[0].stdout.is_empty());
+    assert_eq!(results[0].stderr, "0-shell: 1/0: division by 0");
+    assert_eq!(results[0].status, 1);
+    assert_eq!(results[1].stdout_text(), "1\n");
+
+    let results = shell.run_line("calc 9223372036854775807+1");
+    assert_eq!(results[0].stderr, "calc: integer overflow");
+    assert_eq!(results[0].status, 1);
+}
+
+#[test]
+fn test_run_line_captures_streamed_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notes.txt");
+    std::fs::write(&path, "streamed through gzip\n").unwrap();
+
+    let mut shell = Shell::new();
+    let results = shell.run_line(&format!("gzip -c '{}'", path.display()));
+    assert_eq!(results[0].status, 0, "{}", results[0].stderr);
+    assert!(results[0].stdout.starts_with(&[0x1f, 0x8b]));
+    assert!(path.exists());
+
+    // And expands again from the stdin given to the line; -f as the
+    // test itself may run on a terminal
+    let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+    shell
+        .run_line_with(
+            "gunzip -f",
+            &mut &results[0].stdout[..],
+            zero_shell::Output::new(&mut stdout, &mut stderr),
+        )
+        .unwrap();
+    assert_eq!(stdout, b"streamed through gzip\n");
+}
+
+#[test]
+fn test_run_line_gives_no_input() {
+    // Reading the test's own stdin would wait for a terminal forever
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let results = Shell::new().run_line("cksum; dd status=none");
+        let _ = tx.send(results);
+    });
+    let results = rx
+        .recv_timeout(std::time::Duration::from_secs(10))
+        .expect("commands waited for input");
+    assert_eq!(results[0].stdout_text(), "4294967295 0\n");
+    assert!(results[1].stdout.is_empty());
+}